@@ -0,0 +1,2 @@
+-- The container (or other environment) history was run in. Empty for the host itself.
+alter table history add column namespace text not null default '';
@@ -0,0 +1,3 @@
+-- How much output a command wrote, when the shell integration can measure it
+alter table history add column stdout_bytes integer;
+alter table history add column stderr_bytes integer;
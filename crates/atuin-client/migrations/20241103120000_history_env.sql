@@ -0,0 +1,2 @@
+-- The environment variables configured in history_env, as a JSON object
+alter table history add column env text;
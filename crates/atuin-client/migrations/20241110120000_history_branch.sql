@@ -0,0 +1,2 @@
+-- The git branch checked out when the command ran. Empty outside of a repo.
+alter table history add column branch text not null default '';
@@ -0,0 +1,5 @@
+-- History that has been starred. The stars themselves sync through the kv store, this is a
+-- copy to search with.
+create table if not exists starred (
+	id text primary key
+);
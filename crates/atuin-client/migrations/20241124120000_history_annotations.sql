@@ -0,0 +1,14 @@
+-- Tags and notes on history. These sync as their own records, this is a copy to search with.
+create table if not exists history_tags (
+	id text not null,
+	tag text not null,
+
+	primary key (id, tag)
+);
+
+create index if not exists idx_history_tags_tag on history_tags(tag);
+
+create table if not exists history_notes (
+	id text primary key,
+	note text not null
+);
@@ -0,0 +1,9 @@
+-- Autosuggestions look up commands by prefix on every keypress. This covers everything that
+-- lookup reads, so it never has to touch the table. -2 is a cancelled command.
+create index if not exists idx_history_prefix_suggestion on history(
+	command,
+	timestamp,
+	cwd,
+	exit,
+	deleted_at
+) where deleted_at is null and exit != -2;
@@ -0,0 +1,81 @@
+-- Totals for each command, so stats don't have to count all of history. The triggers below keep
+-- it up to date with every insert, update and delete, whether from the shell, an import or sync.
+-- Like the stats themselves, deleted and cancelled history isn't counted.
+create table if not exists command_stats (
+	command text primary key,
+	runs integer not null,
+	last_run integer not null,
+	-- of the runs with a known duration, to average them
+	total_duration integer not null,
+	timed_runs integer not null,
+	failures integer not null
+);
+
+insert into command_stats(command, runs, last_run, total_duration, timed_runs, failures)
+	select command, count(1), max(timestamp), sum(max(duration, 0)), sum(duration >= 0), sum(exit > 0)
+	from history
+	where deleted_at is null and exit != -2
+	group by command;
+
+create trigger if not exists command_stats_insert after insert on history
+when new.deleted_at is null and new.exit != -2
+begin
+	insert into command_stats(command, runs, last_run, total_duration, timed_runs, failures)
+		values(new.command, 1, new.timestamp, max(new.duration, 0), new.duration >= 0, new.exit > 0)
+		on conflict(command) do update set
+			runs = runs + 1,
+			last_run = max(last_run, excluded.last_run),
+			total_duration = total_duration + excluded.total_duration,
+			timed_runs = timed_runs + excluded.timed_runs,
+			failures = failures + excluded.failures;
+end;
+
+create trigger if not exists command_stats_delete after delete on history
+when old.deleted_at is null and old.exit != -2
+begin
+	update command_stats set
+		runs = runs - 1,
+		last_run = coalesce((
+			select max(timestamp) from history
+			where command = old.command and deleted_at is null and exit != -2
+		), 0),
+		total_duration = total_duration - max(old.duration, 0),
+		timed_runs = timed_runs - (old.duration >= 0),
+		failures = failures - (old.exit > 0)
+	where command = old.command;
+
+	delete from command_stats where command = old.command and runs <= 0;
+end;
+
+-- Take the old row away, then add the new one. This is how `history end` records the exit and
+-- duration, and how history is marked deleted.
+create trigger if not exists command_stats_update
+after update of timestamp, duration, exit, command, deleted_at on history
+begin
+	update command_stats set
+		runs = runs - 1,
+		last_run = coalesce((
+			select max(timestamp) from history
+			where command = old.command and deleted_at is null and exit != -2
+		), 0),
+		total_duration = total_duration - max(old.duration, 0),
+		timed_runs = timed_runs - (old.duration >= 0),
+		failures = failures - (old.exit > 0)
+	where command = old.command and old.deleted_at is null and old.exit != -2;
+
+	insert into command_stats(command, runs, last_run, total_duration, timed_runs, failures)
+		select new.command, 1, new.timestamp, max(new.duration, 0), new.duration >= 0, new.exit > 0
+		where new.deleted_at is null and new.exit != -2
+		on conflict(command) do update set
+			runs = runs + 1,
+			last_run = max(last_run, excluded.last_run),
+			total_duration = total_duration + excluded.total_duration,
+			timed_runs = timed_runs + excluded.timed_runs,
+			failures = failures + excluded.failures;
+
+	delete from command_stats where command = old.command and runs <= 0;
+end;
+
+-- Cancelled commands aren't in the totals, stats count them separately
+create index if not exists idx_history_cancelled on history(timestamp)
+where deleted_at is null and exit = -2;
@@ -0,0 +1,16 @@
+-- The latest value of each kv key, built from the kv records so it can be read without replaying
+-- them all
+create table if not exists kv (
+  namespace text not null,
+  key text not null,
+  value text not null,
+  timestamp integer not null, -- of the record that set it, as the latest write wins
+
+  primary key (namespace, key)
+);
+
+-- The last kv record in the index from each host
+create table if not exists kv_applied (
+  host text primary key,
+  idx integer not null
+);
@@ -0,0 +1,2 @@
+-- Deleted keys stay in the index, so an older write that syncs later can't bring them back
+alter table kv add column deleted integer not null default 0;
@@ -0,0 +1,6 @@
+-- The host that set each key, to break ties between writes with the same timestamp. The index is
+-- emptied, so it's built again with hosts the next time it's read.
+alter table kv add column host text not null default '';
+
+delete from kv;
+delete from kv_applied;
@@ -0,0 +1,2 @@
+-- When a key set with a ttl expires, in unix seconds. Null for keys that never do.
+alter table kv add column expires integer;
@@ -1,38 +1,130 @@
 use std::collections::HashMap;
 use std::env;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use eyre::{bail, Result};
 use reqwest::{
-    header::{HeaderMap, AUTHORIZATION, USER_AGENT},
-    Response, StatusCode, Url,
+    header::{
+        HeaderMap, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT,
+    },
+    RequestBuilder, Response, StatusCode, Url,
 };
 
 use atuin_common::{
     api::{
         AddHistoryRequest, ChangePasswordRequest, CountResponse, DeleteHistoryRequest,
-        ErrorResponse, LoginRequest, LoginResponse, MeResponse, RegisterResponse,
-        SendVerificationResponse, StatusResponse, SyncHistoryResponse, VerificationTokenRequest,
-        VerificationTokenResponse,
+        ErrorResponse, HostRevokedResponse, LoginRequest, LoginResponse, MeResponse,
+        QuotaExceededResponse, QuotaKind, RegisterResponse, RevokeHostRequest, RevokeHostResponse,
+        RevokeSessionsResponse, SendVerificationResponse, StatusResponse, SyncHistoryResponse,
+        TotpCodeRequest, TotpEnrollResponse, VerificationTokenRequest, VerificationTokenResponse,
     },
-    record::RecordStatus,
+    record::{RecordDigest, RecordStatus},
 };
 use atuin_common::{
-    api::{ATUIN_CARGO_VERSION, ATUIN_HEADER_VERSION, ATUIN_VERSION},
+    api::{
+        ATUIN_CARGO_VERSION, ATUIN_HEADER_HOST, ATUIN_HEADER_TOTP, ATUIN_HEADER_VERSION,
+        ATUIN_VERSION,
+    },
     record::{EncryptedData, HostId, Record, RecordIdx},
 };
 
 use semver::Version;
+use thiserror::Error;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
-use crate::{history::History, sync::hash_str, utils::get_host_user};
+#[cfg(feature = "check-update")]
+use crate::settings::UpdateCheck;
+use crate::{history::History, settings::Settings, sync::hash_str, utils::get_host_user};
+
+/// Where the time each request to the sync server takes is logged. `sync.log_timings` turns it on.
+pub const TIMING_TARGET: &str = "atuin_client::timing";
 
 static APP_USER_AGENT: &str = concat!("atuin/", env!("CARGO_PKG_VERSION"),);
 
 pub struct Client<'a> {
     sync_addr: &'a str,
     client: reqwest::Client,
+    host_id: Option<HostId>,
+    /// Whether the server has said it can take zstd compressed requests
+    zstd: AtomicBool,
+}
+
+// Record pages are mostly base64 and repeated JSON keys, so they compress well
+const ZSTD_LEVEL: i32 = 3;
+
+fn accepts_zstd(resp: &Response) -> bool {
+    resp.headers()
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim() == "zstd")
+}
+
+async fn decode_json<T: serde::de::DeserializeOwned>(resp: Response) -> Result<T> {
+    let zstd = resp
+        .headers()
+        .get(CONTENT_ENCODING)
+        .is_some_and(|v| v == "zstd");
+
+    let body = resp.bytes().await?;
+
+    if zstd {
+        Ok(serde_json::from_slice(&zstd::stream::decode_all(&*body)?)?)
+    } else {
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// The sync server has revoked this host. It should stop syncing, and delete its key if asked to.
+#[derive(Debug, Error)]
+#[error("this host has been revoked, and may no longer sync: {reason}")]
+pub struct HostRevoked {
+    pub reason: String,
+    pub wipe_key: bool,
+}
+
+/// The account has two-factor login enabled, and the login didn't include a code
+#[derive(Debug, Error)]
+#[error("this account needs a two-factor code to log in")]
+pub struct TotpRequired;
+
+/// The sync server refused an upload that would go over one of its limits
+#[derive(Debug, Error)]
+pub struct QuotaExceeded {
+    pub kind: QuotaKind,
+    pub limit: u64,
+    pub used: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { limit, used, .. } = self;
+
+        match self.kind {
+            QuotaKind::Records => write!(
+                f,
+                "the sync server keeps up to {limit} entries per account, and this would make yours {used}"
+            )?,
+            QuotaKind::Bytes => write!(
+                f,
+                "the sync server keeps up to {limit} bytes per account, and this would make yours {used}"
+            )?,
+            QuotaKind::RecordSize => {
+                return write!(
+                    f,
+                    "a record is {used} bytes, more than the {limit} the sync server accepts"
+                )
+            }
+        }
+
+        write!(
+            f,
+            ". Delete history you don't need, or ask whoever runs the server to raise the limit"
+        )
+    }
 }
 
 pub async fn register(
@@ -82,6 +174,11 @@ pub async fn login(address: &str, req: LoginRequest) -> Result<LoginResponse> {
         .json(&req)
         .send()
         .await?;
+
+    if resp.status() == StatusCode::UNAUTHORIZED && resp.headers().contains_key(ATUIN_HEADER_TOTP) {
+        return Err(TotpRequired.into());
+    }
+
     let resp = handle_resp_error(resp).await?;
 
     if !ensure_version(&resp)? {
@@ -92,15 +189,40 @@ pub async fn login(address: &str, req: LoginRequest) -> Result<LoginResponse> {
     Ok(session)
 }
 
+/// The latest release on an update channel. Stable releases are announced by the atuin api, and
+/// betas are found from GitHub's releases.
 #[cfg(feature = "check-update")]
-pub async fn latest_version() -> Result<Version> {
+pub async fn latest_version(channel: UpdateCheck) -> Result<Version> {
     use atuin_common::api::IndexResponse;
 
-    let url = "https://api.atuin.sh";
+    #[derive(serde::Deserialize)]
+    struct GithubRelease {
+        tag_name: String,
+        draft: bool,
+    }
+
     let client = reqwest::Client::new();
 
+    if channel == UpdateCheck::Beta {
+        let resp = client
+            .get("https://api.github.com/repos/atuinsh/atuin/releases?per_page=20")
+            .header(USER_AGENT, APP_USER_AGENT)
+            .send()
+            .await?;
+        let resp = handle_resp_error(resp).await?;
+
+        let releases = resp.json::<Vec<GithubRelease>>().await?;
+
+        return releases
+            .iter()
+            .filter(|release| !release.draft)
+            .filter_map(|release| Version::parse(release.tag_name.trim_start_matches('v')).ok())
+            .max()
+            .ok_or_else(|| eyre::eyre!("no releases found"));
+    }
+
     let resp = client
-        .get(url)
+        .get("https://api.atuin.sh")
         .header(USER_AGENT, APP_USER_AGENT)
         .send()
         .await?;
@@ -112,6 +234,21 @@ pub async fn latest_version() -> Result<Version> {
     Ok(version)
 }
 
+/// Download a release artifact, like a binary or its checksum
+#[cfg(feature = "self-update")]
+pub async fn download(url: &str) -> Result<Vec<u8>> {
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .get(url)
+        .header(USER_AGENT, APP_USER_AGENT)
+        .send()
+        .await?;
+    let resp = handle_resp_error(resp).await?;
+
+    Ok(resp.bytes().await?.to_vec())
+}
+
 pub fn ensure_version(response: &Response) -> Result<bool> {
     let version = response.headers().get(ATUIN_HEADER_VERSION);
 
@@ -149,6 +286,31 @@ async fn handle_resp_error(resp: Response) -> Result<Response> {
         bail!("Rate limited; please wait before doing that again");
     }
 
+    if status == StatusCode::PAYLOAD_TOO_LARGE {
+        if let Ok(quota) = resp.json::<QuotaExceededResponse>().await {
+            return Err(QuotaExceeded {
+                kind: quota.kind,
+                limit: quota.limit,
+                used: quota.used,
+            }
+            .into());
+        }
+
+        bail!("The sync server refused an upload that was too large")
+    }
+
+    if status == StatusCode::GONE {
+        if let Ok(revoked) = resp.json::<HostRevokedResponse>().await {
+            return Err(HostRevoked {
+                reason: revoked.reason,
+                wipe_key: revoked.wipe_key,
+            }
+            .into());
+        }
+
+        bail!("There was an error with the atuin sync service: Status {status:?}.\nIf the problem persists, contact the host")
+    }
+
     if !status.is_success() {
         if let Ok(error) = resp.json::<ErrorResponse>().await {
             let reason = error.reason;
@@ -187,14 +349,59 @@ impl<'a> Client<'a> {
                 .connect_timeout(Duration::new(connect_timeout, 0))
                 .timeout(Duration::new(timeout, 0))
                 .build()?,
+            host_id: Settings::host_id(),
+            zstd: AtomicBool::new(false),
         })
     }
 
+    /// Send a request, logging how long it took to [`TIMING_TARGET`]
+    async fn send(&self, req: RequestBuilder) -> Result<Response> {
+        let req = self.with_host(req).build()?;
+        let (method, path) = (req.method().clone(), req.url().path().to_string());
+
+        let start = Instant::now();
+        let resp = self.client.execute(req).await;
+        let elapsed_ms = start.elapsed().as_millis();
+
+        match &resp {
+            Ok(resp) => info!(
+                target: TIMING_TARGET,
+                "{method} {path}: {} in {elapsed_ms}ms",
+                resp.status().as_u16()
+            ),
+            Err(e) => {
+                info!(target: TIMING_TARGET, "{method} {path}: failed in {elapsed_ms}ms: {e}")
+            }
+        }
+
+        Ok(resp?)
+    }
+
+    // Every response from the server says whether it takes compressed requests. Older servers
+    // don't say, and only get uncompressed ones.
+    fn note_encoding(&self, resp: &Response) {
+        self.zstd.store(accepts_zstd(resp), Ordering::Relaxed);
+    }
+
+    /// Identify requests as coming from another host than this one. Every request says which
+    /// host it's from, so the server can turn away a host that has been revoked.
+    pub fn with_host_id(mut self, host_id: HostId) -> Self {
+        self.host_id = Some(host_id);
+        self
+    }
+
+    fn with_host(&self, req: RequestBuilder) -> RequestBuilder {
+        match self.host_id {
+            Some(host) => req.header(ATUIN_HEADER_HOST, host.0.as_simple().to_string()),
+            None => req,
+        }
+    }
+
     pub async fn count(&self) -> Result<i64> {
         let url = format!("{}/sync/count", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.get(url).send().await?;
+        let resp = self.send(self.client.get(url)).await?;
         let resp = handle_resp_error(resp).await?;
 
         if !ensure_version(&resp)? {
@@ -214,7 +421,7 @@ impl<'a> Client<'a> {
         let url = format!("{}/sync/status", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.get(url).send().await?;
+        let resp = self.send(self.client.get(url)).await?;
         let resp = handle_resp_error(resp).await?;
 
         if !ensure_version(&resp)? {
@@ -230,7 +437,7 @@ impl<'a> Client<'a> {
         let url = format!("{}/api/v0/me", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.get(url).send().await?;
+        let resp = self.send(self.client.get(url)).await?;
         let resp = handle_resp_error(resp).await?;
 
         let status = resp.json::<MeResponse>().await?;
@@ -254,7 +461,7 @@ impl<'a> Client<'a> {
             host,
         );
 
-        let resp = self.client.get(url).send().await?;
+        let resp = self.send(self.client.get(url)).await?;
         let resp = handle_resp_error(resp).await?;
 
         let history = resp.json::<SyncHistoryResponse>().await?;
@@ -265,7 +472,7 @@ impl<'a> Client<'a> {
         let url = format!("{}/history", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.post(url).json(history).send().await?;
+        let resp = self.send(self.client.post(url).json(history)).await?;
         handle_resp_error(resp).await?;
 
         Ok(())
@@ -276,12 +483,9 @@ impl<'a> Client<'a> {
         let url = Url::parse(url.as_str())?;
 
         let resp = self
-            .client
-            .delete(url)
-            .json(&DeleteHistoryRequest {
+            .send(self.client.delete(url).json(&DeleteHistoryRequest {
                 client_id: h.id.to_string(),
-            })
-            .send()
+            }))
             .await?;
 
         handle_resp_error(resp).await?;
@@ -293,20 +497,51 @@ impl<'a> Client<'a> {
         let url = format!("{}/api/v0/store", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.delete(url).send().await?;
+        let resp = self.send(self.client.delete(url)).await?;
 
         handle_resp_error(resp).await?;
 
         Ok(())
     }
 
+    /// Revoke a host, getting the session that replaces the one it shared with this one
+    pub async fn revoke_host(&self, host: HostId, wipe_key: bool) -> Result<RevokeHostResponse> {
+        let url = format!("{}/api/v0/host/revoke", self.sync_addr);
+        let url = Url::parse(url.as_str())?;
+
+        let resp = self
+            .send(
+                self.client
+                    .post(url)
+                    .json(&RevokeHostRequest { host, wipe_key }),
+            )
+            .await?;
+        let resp = handle_resp_error(resp).await?;
+
+        Ok(resp.json().await?)
+    }
+
     pub async fn post_records(&self, records: &[Record<EncryptedData>]) -> Result<()> {
         let url = format!("{}/api/v0/record", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
         debug!("uploading {} records to {url}", records.len());
 
-        let resp = self.client.post(url).json(records).send().await?;
+        let request = self.client.post(url);
+
+        let request = if self.zstd.load(Ordering::Relaxed) {
+            let body = zstd::bulk::compress(&serde_json::to_vec(records)?, ZSTD_LEVEL)?;
+
+            request
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_ENCODING, "zstd")
+                .body(body)
+        } else {
+            request.json(records)
+        };
+
+        let resp = self.send(request).await?;
+        self.note_encoding(&resp);
         handle_resp_error(resp).await?;
 
         Ok(())
@@ -333,10 +568,13 @@ impl<'a> Client<'a> {
 
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.get(url).send().await?;
+        let resp = self
+            .send(self.client.get(url).header(ACCEPT_ENCODING, "zstd"))
+            .await?;
+        self.note_encoding(&resp);
         let resp = handle_resp_error(resp).await?;
 
-        let records = resp.json::<Vec<Record<EncryptedData>>>().await?;
+        let records = decode_json::<Vec<Record<EncryptedData>>>(resp).await?;
 
         Ok(records)
     }
@@ -345,7 +583,8 @@ impl<'a> Client<'a> {
         let url = format!("{}/api/v0/record", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.get(url).send().await?;
+        let resp = self.send(self.client.get(url)).await?;
+        self.note_encoding(&resp);
         let resp = handle_resp_error(resp).await?;
 
         if !ensure_version(&resp)? {
@@ -359,11 +598,21 @@ impl<'a> Client<'a> {
         Ok(index)
     }
 
+    pub async fn record_digest(&self) -> Result<RecordDigest> {
+        let url = format!("{}/api/v0/record/digest", self.sync_addr);
+        let url = Url::parse(url.as_str())?;
+
+        let resp = self.send(self.client.get(url)).await?;
+        let resp = handle_resp_error(resp).await?;
+
+        Ok(resp.json().await?)
+    }
+
     pub async fn delete(&self) -> Result<()> {
         let url = format!("{}/account", self.sync_addr);
         let url = Url::parse(url.as_str())?;
 
-        let resp = self.client.delete(url).send().await?;
+        let resp = self.send(self.client.delete(url)).await?;
 
         if resp.status() == 403 {
             bail!("invalid login details");
@@ -383,13 +632,10 @@ impl<'a> Client<'a> {
         let url = Url::parse(url.as_str())?;
 
         let resp = self
-            .client
-            .patch(url)
-            .json(&ChangePasswordRequest {
+            .send(self.client.patch(url).json(&ChangePasswordRequest {
                 current_password,
                 new_password,
-            })
-            .send()
+            }))
             .await?;
 
         if resp.status() == 401 {
@@ -403,6 +649,53 @@ impl<'a> Client<'a> {
         }
     }
 
+    /// Revoke every session, getting a new one for this machine
+    pub async fn revoke_sessions(&self) -> Result<RevokeSessionsResponse> {
+        let url = format!("{}/api/v0/account/sessions/revoke", self.sync_addr);
+        let url = Url::parse(url.as_str())?;
+
+        let resp = self.send(self.client.post(url)).await?;
+        let resp = handle_resp_error(resp).await?;
+
+        Ok(resp.json().await?)
+    }
+
+    /// Start enrolling in two-factor login, getting the secret for an authenticator app
+    pub async fn totp_enroll(&self) -> Result<TotpEnrollResponse> {
+        let url = format!("{}/api/v0/account/totp", self.sync_addr);
+        let url = Url::parse(url.as_str())?;
+
+        let resp = self.send(self.client.post(url)).await?;
+        let resp = handle_resp_error(resp).await?;
+
+        Ok(resp.json().await?)
+    }
+
+    /// Confirm enrolment with a code from the authenticator app. Logging in needs one after this.
+    pub async fn totp_enable(&self, code: String) -> Result<()> {
+        let url = format!("{}/api/v0/account/totp/enable", self.sync_addr);
+        let url = Url::parse(url.as_str())?;
+
+        let resp = self
+            .send(self.client.post(url).json(&TotpCodeRequest { code }))
+            .await?;
+        handle_resp_error(resp).await?;
+
+        Ok(())
+    }
+
+    pub async fn totp_disable(&self, code: String) -> Result<()> {
+        let url = format!("{}/api/v0/account/totp", self.sync_addr);
+        let url = Url::parse(url.as_str())?;
+
+        let resp = self
+            .send(self.client.delete(url).json(&TotpCodeRequest { code }))
+            .await?;
+        handle_resp_error(resp).await?;
+
+        Ok(())
+    }
+
     // Either request a verification email if token is null, or validate a token
     pub async fn verify(&self, token: Option<String>) -> Result<(bool, bool)> {
         // could dedupe this a bit, but it's simple at the moment
@@ -411,10 +704,11 @@ impl<'a> Client<'a> {
             let url = Url::parse(url.as_str())?;
 
             let resp = self
-                .client
-                .post(url)
-                .json(&VerificationTokenRequest { token })
-                .send()
+                .send(
+                    self.client
+                        .post(url)
+                        .json(&VerificationTokenRequest { token }),
+                )
                 .await?;
             let resp = handle_resp_error(resp).await?;
             let resp = resp.json::<VerificationTokenResponse>().await?;
@@ -424,7 +718,7 @@ impl<'a> Client<'a> {
             let url = format!("{}/api/v0/account/send-verification", self.sync_addr);
             let url = Url::parse(url.as_str())?;
 
-            let resp = self.client.post(url).send().await?;
+            let resp = self.send(self.client.post(url)).await?;
             let resp = handle_resp_error(resp).await?;
             let resp = resp.json::<SendVerificationResponse>().await?;
 
@@ -0,0 +1,307 @@
+//! Snapshots of the local history, record store and key, under `backup.directory`
+//!
+//! Each snapshot is a directory named for when it was taken. The databases are copied with
+//! sqlite's `VACUUM INTO`, which gives a consistent copy even while shells are writing to them.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use eyre::{bail, eyre, Result};
+use fs_err as fs;
+use humantime::parse_duration;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    ConnectOptions, Connection,
+};
+use time::{format_description::FormatItem, macros::format_description, OffsetDateTime};
+
+use crate::settings::Settings;
+
+const NAME_FORMAT: &[FormatItem<'static>] =
+    format_description!("[year][month][day]T[hour][minute][second].[subsecond digits:3]Z");
+
+// the names the files have within a snapshot
+const HISTORY: &str = "history.db";
+const RECORDS: &str = "records.db";
+const KEY: &str = "key";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    pub path: PathBuf,
+    pub created: OffsetDateTime,
+}
+
+/// The files a snapshot holds, and where each lives outside of one
+fn files(settings: &Settings) -> [(&'static str, &str, bool); 3] {
+    [
+        (HISTORY, settings.db_path.as_str(), true),
+        (RECORDS, settings.record_store_path.as_str(), true),
+        (KEY, settings.key_path.as_str(), false),
+    ]
+}
+
+/// Every snapshot in the backup directory, oldest first
+pub fn list(settings: &Settings) -> Result<Vec<Snapshot>> {
+    let dir = Path::new(&settings.backup.directory);
+
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut snapshots = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // skips anything else in there, including snapshots still being written
+        let Ok(created) = time::PrimitiveDateTime::parse(&name, NAME_FORMAT) else {
+            continue;
+        };
+
+        if entry.file_type()?.is_dir() {
+            snapshots.push(Snapshot {
+                name,
+                path: entry.path(),
+                created: created.assume_utc(),
+            });
+        }
+    }
+
+    snapshots.sort_by_key(|s| s.created);
+
+    Ok(snapshots)
+}
+
+/// Find a snapshot by name. "latest" is the newest one.
+pub fn find(settings: &Settings, name: &str) -> Result<Snapshot> {
+    let snapshots = list(settings)?;
+
+    let snapshot = if name == "latest" {
+        snapshots.into_iter().last()
+    } else {
+        snapshots.into_iter().find(|s| s.name == name)
+    };
+
+    snapshot.ok_or_else(|| eyre!("no backup named {name} in {}", settings.backup.directory))
+}
+
+/// Whether the newest snapshot is older than `backup.frequency`
+pub fn due(settings: &Settings) -> Result<bool> {
+    let frequency = parse_duration(&settings.backup.frequency).map_err(|e| {
+        eyre!(
+            "invalid backup.frequency {:?}: {e}",
+            settings.backup.frequency
+        )
+    })?;
+    let frequency = time::Duration::try_from(frequency)?;
+
+    Ok(match list(settings)?.last() {
+        Some(newest) => OffsetDateTime::now_utc() - newest.created >= frequency,
+        None => true,
+    })
+}
+
+/// Take a snapshot now, then delete the oldest beyond `backup.keep`
+pub async fn create(settings: &Settings) -> Result<Snapshot> {
+    let now = OffsetDateTime::now_utc();
+    let created = now.replace_millisecond(now.millisecond())?;
+    let name = created.format(NAME_FORMAT)?;
+    let dir = PathBuf::from(&settings.backup.directory);
+    let path = dir.join(&name);
+
+    if path.exists() {
+        bail!("a backup was already taken at {path:?}");
+    }
+
+    // written under another name first, so that an interrupted backup is never listed
+    let partial = dir.join(format!("{name}.partial"));
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)?;
+
+    for (file, source, database) in files(settings) {
+        let source = Path::new(source);
+
+        if !source.exists() {
+            continue;
+        }
+
+        if database {
+            vacuum_into(source, &partial.join(file)).await?;
+        } else {
+            fs::copy(source, partial.join(file))?;
+        }
+    }
+
+    fs::rename(&partial, &path)?;
+
+    let removed = rotate(settings)?;
+    debug!("backed up to {path:?}, removed {removed} old backups");
+
+    Ok(Snapshot {
+        name,
+        path,
+        created,
+    })
+}
+
+/// Delete all but the newest `backup.keep` snapshots, returning how many went. 0 keeps them all.
+pub fn rotate(settings: &Settings) -> Result<usize> {
+    let keep = settings.backup.keep;
+
+    if keep == 0 {
+        return Ok(0);
+    }
+
+    let snapshots = list(settings)?;
+    let remove = snapshots.len().saturating_sub(keep);
+
+    for snapshot in &snapshots[..remove] {
+        fs::remove_dir_all(&snapshot.path)?;
+    }
+
+    Ok(remove)
+}
+
+/// Replace the databases and key with those from a snapshot. What's there now is backed up
+/// first, and that snapshot is returned, so a restore can be undone.
+pub async fn restore(settings: &Settings, snapshot: &Snapshot) -> Result<Snapshot> {
+    let current = create(settings).await?;
+
+    for (file, target, database) in files(settings) {
+        let source = snapshot.path.join(file);
+
+        if !source.exists() {
+            continue;
+        }
+
+        let target = PathBuf::from(target);
+        let staged = target.with_extension("restoring");
+        fs::copy(&source, &staged)?;
+
+        // the old write-ahead log would be replayed on top of the restored database
+        if database {
+            for suffix in ["-wal", "-shm"] {
+                let mut sidecar = target.clone().into_os_string();
+                sidecar.push(suffix);
+                let sidecar = PathBuf::from(sidecar);
+
+                if sidecar.exists() {
+                    fs::remove_file(sidecar)?;
+                }
+            }
+        }
+
+        fs::rename(&staged, &target)?;
+    }
+
+    Ok(current)
+}
+
+async fn vacuum_into(source: &Path, target: &Path) -> Result<()> {
+    let opts = SqliteConnectOptions::from_str(&format!("sqlite://{}", source.display()))?
+        .read_only(true)
+        .log_statements(log::LevelFilter::Trace);
+
+    let mut conn = SqliteConnection::connect_with(&opts).await?;
+
+    sqlx::query("vacuum into ?1")
+        .bind(target.to_string_lossy().as_ref())
+        .execute(&mut conn)
+        .await?;
+
+    conn.close().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{database::Database, database::Sqlite, history::History, settings::Settings};
+
+    use super::{create, find, list, restore, rotate};
+
+    fn settings(dir: &std::path::Path) -> Settings {
+        let mut settings = Settings::utc();
+        settings.db_path = dir.join("history.db").to_string_lossy().to_string();
+        settings.record_store_path = dir.join("records.db").to_string_lossy().to_string();
+        settings.key_path = dir.join("key").to_string_lossy().to_string();
+        settings.backup.directory = dir.join("backups").to_string_lossy().to_string();
+        settings.backup.keep = 2;
+        settings
+    }
+
+    async fn save(settings: &Settings, command: &str) {
+        let db = Sqlite::new(&settings.db_path, 2.0).await.unwrap();
+        let h: History = History::import()
+            .timestamp(time::OffsetDateTime::now_utc())
+            .command(command)
+            .build()
+            .into();
+        db.save(&h).await.unwrap();
+    }
+
+    async fn commands(settings: &Settings) -> Vec<String> {
+        let db = Sqlite::new(&settings.db_path, 2.0).await.unwrap();
+        let mut commands: Vec<_> = db
+            .range(
+                time::OffsetDateTime::UNIX_EPOCH,
+                time::OffsetDateTime::now_utc(),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.command)
+            .collect();
+        commands.sort();
+        commands
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn backup_and_restore() {
+        let dir: PathBuf = std::env::temp_dir().join(format!(
+            "atuin-backup-test-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let settings = settings(&dir);
+
+        save(&settings, "ls").await;
+        std::fs::write(&settings.key_path, "secret").unwrap();
+
+        let snapshot = create(&settings).await.unwrap();
+        assert!(snapshot.path.join("history.db").exists());
+        assert_eq!(
+            std::fs::read_to_string(snapshot.path.join("key")).unwrap(),
+            "secret"
+        );
+        assert_eq!(find(&settings, "latest").unwrap(), snapshot);
+
+        save(&settings, "rm -rf important").await;
+        std::fs::write(&settings.key_path, "changed").unwrap();
+
+        let undo = restore(&settings, &snapshot).await.unwrap();
+        assert_eq!(commands(&settings).await, ["ls"]);
+        assert_eq!(
+            std::fs::read_to_string(&settings.key_path).unwrap(),
+            "secret"
+        );
+
+        // the restore backed up what it replaced
+        assert_eq!(list(&settings).unwrap().len(), 2);
+        restore(&settings, &undo).await.unwrap();
+        assert_eq!(commands(&settings).await, ["ls", "rm -rf important"]);
+
+        // restoring took another backup, past the two kept
+        assert_eq!(list(&settings).unwrap().len(), 2);
+        assert_eq!(rotate(&settings).unwrap(), 0);
+        assert!(find(&settings, &snapshot.name).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
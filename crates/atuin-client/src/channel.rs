@@ -0,0 +1,204 @@
+//! Channels share history with a team. Each is a record store of its own, with its own key,
+//! synced with a blob target everyone in the channel can reach. History is only ever shared
+//! on purpose: with `atuin share`, or by tagging it with one of the channel's tags.
+//!
+//! What's been shared to every channel this host subscribes to is built into one database, kept
+//! apart from our own history, and searched with the channel filter mode.
+
+use std::path::PathBuf;
+
+use eyre::{bail, ensure, eyre, Context, Result};
+use fs_err as fs;
+
+use crate::{
+    database::{Database, Sqlite},
+    encryption::{decode_key, generate_encoded_key, Key},
+    history::{store::HistoryStore, History, HistoryId},
+    record::sqlite_store::SqliteStore,
+    settings::{Channel, Settings},
+};
+
+const CHANNELS_DIR: &str = "channels";
+
+/// Where a channel's records are kept
+pub fn dir(name: &str) -> PathBuf {
+    atuin_common::utils::data_dir()
+        .join(CHANNELS_DIR)
+        .join(name)
+}
+
+/// The channel's shared key, as written by [`new_key`]
+pub fn key_path(channel: &Channel) -> PathBuf {
+    channel
+        .key_path
+        .as_ref()
+        .map_or_else(|| dir(&channel.name).join("key"), PathBuf::from)
+}
+
+// The name is a directory in the data dir
+fn check_name(name: &str) -> Result<()> {
+    ensure!(
+        atuin_common::utils::is_valid_profile(name),
+        "channel names may only have letters, numbers, - and _, not {name:?}"
+    );
+
+    Ok(())
+}
+
+/// A channel this host subscribes to, by name
+pub fn find<'a>(settings: &'a Settings, name: &str) -> Result<&'a Channel> {
+    check_name(name)?;
+
+    settings
+        .channels
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| eyre!("no channel {name:?} in config.toml"))
+}
+
+/// Everything shared to the channels this host subscribes to, or None without any
+pub async fn open_db(settings: &Settings) -> Result<Option<Sqlite>> {
+    if settings.channels.is_empty() {
+        return Ok(None);
+    }
+
+    let path = atuin_common::utils::data_dir()
+        .join(CHANNELS_DIR)
+        .join("history.db");
+
+    Ok(Some(Sqlite::new(path, settings.local_timeout).await?))
+}
+
+pub fn load_key(channel: &Channel) -> Result<Key> {
+    let path = key_path(channel);
+
+    let encoded = fs::read_to_string(&path).with_context(|| {
+        format!(
+            "no key for channel {:?}. Create one with `atuin channel key {0} --new`, or import a teammate's with --import",
+            channel.name
+        )
+    })?;
+
+    decode_key(encoded).with_context(|| format!("{} is not a valid key", path.display()))
+}
+
+/// Save the channel's key, refusing to replace one already there, as that would lose access to
+/// everything shared with it
+pub fn save_key(channel: &Channel, encoded: &str) -> Result<()> {
+    decode_key(encoded.to_string()).context("not a valid channel key")?;
+
+    let path = key_path(channel);
+    if path.exists() {
+        bail!(
+            "channel {:?} already has a key, at {}",
+            channel.name,
+            path.display()
+        );
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(&path, encoded)?;
+
+    Ok(())
+}
+
+/// Make a key for a new channel, returning it encoded to hand to teammates
+pub fn new_key(channel: &Channel) -> Result<String> {
+    let (_, encoded) = generate_encoded_key()?;
+    save_key(channel, &encoded)?;
+
+    Ok(encoded)
+}
+
+/// A channel's records, as history
+pub struct ChannelStore {
+    pub channel: Channel,
+    pub history: HistoryStore,
+}
+
+impl ChannelStore {
+    pub async fn open(settings: &Settings, channel: &Channel) -> Result<Self> {
+        check_name(&channel.name)?;
+
+        let key: [u8; 32] = load_key(channel)?.into();
+        let host_id = Settings::host_id().expect("failed to get host_id");
+
+        let store = SqliteStore::new(
+            dir(&channel.name).join("records.db"),
+            settings.local_timeout,
+        )
+        .await?;
+
+        Ok(Self {
+            channel: channel.clone(),
+            history: HistoryStore::new(store, host_id, key).with_strip(&channel.strip),
+        })
+    }
+
+    /// Share history to the channel, and the channels database. Returns false if it already
+    /// has been.
+    pub async fn share(&self, channels_db: &dyn Database, history: History) -> Result<bool> {
+        if self.history.history_ids().await?.contains(&history.id) {
+            return Ok(false);
+        }
+
+        let (id, _) = self.history.push(history).await?;
+        self.history.incremental_build(channels_db, &[id]).await?;
+
+        Ok(true)
+    }
+
+    /// Take back history shared to the channel. Anyone who has synced it since keeps it until
+    /// they sync again.
+    pub async fn unshare(&self, channels_db: &dyn Database, id: HistoryId) -> Result<()> {
+        let (record, _) = self.history.delete(id).await?;
+        self.history.incremental_build(channels_db, &[record]).await
+    }
+
+    /// Share everything with one of the channel's tags that hasn't been yet. Returns how many
+    /// were.
+    pub async fn share_tagged(
+        &self,
+        db: &dyn Database,
+        channels_db: &dyn Database,
+    ) -> Result<usize> {
+        let mut shared = self.history.history_ids().await?;
+        let mut records = Vec::new();
+
+        for tag in &self.channel.tags {
+            for history in db.tagged(tag).await? {
+                if shared.insert(history.id.clone()) {
+                    records.push(self.history.push(history).await?.0);
+                }
+            }
+        }
+
+        self.history
+            .incremental_build(channels_db, &records)
+            .await?;
+
+        Ok(records.len())
+    }
+
+    /// Send what's been shared from here, and fetch what everyone else has. Returns how many
+    /// records went each way.
+    #[cfg(feature = "sync")]
+    pub async fn sync(&self, channels_db: &dyn Database) -> Result<(i64, usize)> {
+        use crate::record::{
+            blob::{self, BlobRemote},
+            sync::sync_with,
+        };
+
+        let remote = BlobRemote::new(blob::target(&self.channel.backend)?);
+        let (uploaded, downloaded) = sync_with(&self.history.store, &remote).await?;
+
+        self.history
+            .incremental_build(channels_db, &downloaded)
+            .await?;
+
+        Ok((uploaded, downloaded.len()))
+    }
+}
@@ -1,29 +1,32 @@
 use std::{
-    borrow::Cow,
     env,
+    ops::Range,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
     time::Duration,
 };
 
 use async_trait::async_trait;
 use atuin_common::utils;
+use clap::ValueEnum;
 use fs_err as fs;
 use itertools::Itertools;
-use rand::{distributions::Alphanumeric, Rng};
-use sql_builder::{bind::Bind, esc, quote, SqlBuilder, SqlName};
+use serde::Serialize;
+use sql_builder::{esc, quote, SqlBuilder};
 use sqlx::{
+    migrate::Migrator,
     sqlite::{
-        SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow,
-        SqliteSynchronous,
+        SqliteArguments, SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool,
+        SqlitePoolOptions, SqliteRow, SqliteSynchronous,
     },
-    Result, Row,
+    Connection, Result, Row,
 };
-use time::OffsetDateTime;
+use time::{OffsetDateTime, UtcOffset};
 
 use crate::{
-    history::{HistoryId, HistoryStats},
-    utils::get_host_user,
+    history::{HistoryId, HistoryStats, CONTAINER_ID, CONTAINER_IMAGE},
+    utils::{get_container, get_host_user, get_namespace, get_pane},
 };
 
 use super::{
@@ -32,12 +35,89 @@ use super::{
     settings::{FilterMode, SearchMode, Settings},
 };
 
+use queries::StatsQueries;
+use write_queue::{Write, WriteQueue};
+
+#[cfg(feature = "libsql")]
+pub use self::libsql::LibSql;
+
+#[cfg(feature = "libsql")]
+mod libsql;
+mod queries;
+mod write_queue;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+// Stars are on history entries, but the starred filter shows every run of a starred command
+const STARRED_CONDITION: &str =
+    "command in (select command from history where id in (select id from starred))";
+
+// Stored as a JSON object, so that it can be searched with json_extract. Null if nothing was
+// recorded.
+fn env_json(h: &History) -> Option<String> {
+    if h.env.is_empty() {
+        None
+    } else {
+        serde_json::to_string(&h.env).ok()
+    }
+}
+
+// History from the context's multiplexer pane, or none outside of one
+fn pane_condition(context: &Context) -> String {
+    match &context.pane {
+        Some((key, pane)) => format!("json_extract(env, '$.{key}') = {}", quote(pane)),
+        None => "false".to_string(),
+    }
+}
+
+// History from containers like the context's, or from any container on the host
+fn container_condition(context: &Context) -> String {
+    match &context.container {
+        Some((key, container)) => {
+            format!("json_extract(env, '$.{key}') = {}", quote(container))
+        }
+        None => format!("json_extract(env, '$.{CONTAINER_ID}') is not null"),
+    }
+}
+
 pub struct Context {
     pub session: String,
     pub cwd: String,
     pub hostname: String,
+    pub namespace: String,
     pub host_id: String,
     pub git_root: Option<PathBuf>,
+    /// The multiplexer pane, as the key it's recorded under in history's env and its id. None
+    /// outside of tmux, zellij and wezterm.
+    pub pane: Option<(&'static str, String)>,
+    /// The container, as its image if that's known, or else its id, and the key it's recorded
+    /// under in history's env. None on the host.
+    pub container: Option<(&'static str, String)>,
+}
+
+/// What [`Database::grouped_stats`] breaks history down by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StatsGroup {
+    Day,
+    /// Weeks start on a Monday
+    Week,
+    Dir,
+    Host,
+    Exit,
+}
+
+/// Counts for one day, week, directory, host or exit code
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct GroupStats {
+    /// The day, or the day a week starts, as YYYY-MM-DD. Otherwise the directory, host or exit
+    /// code.
+    pub group: String,
+    pub commands: i64,
+    pub unique_commands: i64,
+    /// Commands that exited with a non-zero code
+    pub failed: i64,
+    /// In nanoseconds, of the commands whose duration is known
+    pub average_duration: Option<i64>,
 }
 
 #[derive(Default, Clone)]
@@ -46,6 +126,12 @@ pub struct OptFilters {
     pub exclude_exit: Option<i64>,
     pub cwd: Option<String>,
     pub exclude_cwd: Option<String>,
+    pub namespace: Option<String>,
+    /// Environment variables the history was recorded with, as (name, value)
+    pub env: Vec<(String, String)>,
+    pub branch: Option<String>,
+    /// Tags the history must all have
+    pub tags: Vec<String>,
     pub before: Option<String>,
     pub after: Option<String>,
     pub limit: Option<i64>,
@@ -53,12 +139,51 @@ pub struct OptFilters {
     pub reverse: bool,
 }
 
+/// Whether a history hostname ("host:user") matches a host filter, which is either a full
+/// "host:user" or a bare "host" for any user on it
+pub fn host_matches(filter: &str, hostname: &str) -> bool {
+    if filter.contains(':') {
+        hostname.eq_ignore_ascii_case(filter)
+    } else {
+        hostname
+            .split_once(':')
+            .is_some_and(|(host, _)| host.eq_ignore_ascii_case(filter))
+    }
+}
+
+/// Take filters written into a search query, such as `branch:main`, `tag:deploy` or
+/// `env:nix_shell=atuin`, out of the query and into `filters`. Returns what's left of the query.
+pub fn take_query_filters(query: &str, filters: &mut OptFilters) -> String {
+    query
+        .split(' ')
+        .filter(|part| {
+            if let Some(branch) = part.strip_prefix("branch:").filter(|b| !b.is_empty()) {
+                filters.branch = Some(branch.to_string());
+                false
+            } else if let Some(tag) = part.strip_prefix("tag:").filter(|t| !t.is_empty()) {
+                filters.tags.push(tag.to_string());
+                false
+            } else if let Some((name, value)) = part
+                .strip_prefix("env:")
+                .and_then(|env| env.split_once('='))
+                .filter(|(name, _)| !name.is_empty())
+            {
+                filters.env.push((name.to_string(), value.to_string()));
+                false
+            } else {
+                true
+            }
+        })
+        .join(" ")
+}
+
 pub fn current_context() -> Context {
     let Ok(session) = env::var("ATUIN_SESSION") else {
         eprintln!("ERROR: Failed to find $ATUIN_SESSION in the environment. Check that you have correctly set up your shell.");
         std::process::exit(1);
     };
     let hostname = get_host_user();
+    let namespace = get_namespace();
     let cwd = utils::get_current_dir();
     let host_id = Settings::host_id().expect("failed to load host ID");
     let git_root = utils::in_git_repo(cwd.as_str());
@@ -66,16 +191,114 @@ pub fn current_context() -> Context {
     Context {
         session,
         hostname,
+        namespace,
+        cwd,
+        git_root,
+        host_id: host_id.0.as_simple().to_string(),
+        pane: get_pane(),
+        container: current_container(),
+    }
+}
+
+// What the container filter mode matches: the image when it's known, as a new container of the
+// same image is the same environment to work in, or else the container itself
+fn current_container() -> Option<(&'static str, String)> {
+    let (id, image) = get_container();
+
+    image
+        .map(|image| (CONTAINER_IMAGE, image))
+        .or_else(|| id.map(|id| (CONTAINER_ID, id)))
+}
+
+/// The context of another process's shell, such as one asking the daemon for history. Unlike
+/// [`current_context`], nothing comes from this process's environment but the host.
+pub fn shell_context(session: String, cwd: String) -> Context {
+    let host_id = Settings::host_id().expect("failed to load host ID");
+    let git_root = utils::in_git_repo(cwd.as_str());
+
+    Context {
+        session,
+        hostname: get_host_user(),
+        namespace: get_namespace(),
         cwd,
         git_root,
         host_id: host_id.0.as_simple().to_string(),
+        pane: None,
+        container: None,
+    }
+}
+
+/// Open the history database configured in `settings`. Commands open history through here
+/// rather than opening the file themselves, so there's one place that decides where it lives.
+/// That's a local SQLite file, or a libSQL database such as Turso if `db_path` is its URL.
+pub async fn open(settings: &Settings) -> eyre::Result<Arc<dyn Database>> {
+    let path = settings.db_path.as_str();
+
+    if let Some((scheme, _)) = path.split_once("://") {
+        return open_remote(settings, scheme).await;
+    }
+
+    Ok(Arc::new(Sqlite::new(path, settings.local_timeout).await?))
+}
+
+#[cfg(feature = "libsql")]
+async fn open_remote(settings: &Settings, scheme: &str) -> eyre::Result<Arc<dyn Database>> {
+    if !matches!(scheme, "libsql" | "https" | "http") {
+        eyre::bail!("db_path is a {scheme}:// URL, but history can only be kept in libSQL");
+    }
+
+    let token = settings.db_auth_token.as_deref().unwrap_or_default();
+
+    Ok(Arc::new(LibSql::new(&settings.db_path, token).await?))
+}
+
+#[cfg(not(feature = "libsql"))]
+async fn open_remote(_: &Settings, scheme: &str) -> eyre::Result<Arc<dyn Database>> {
+    eyre::bail!(
+        "db_path is a {scheme}:// URL, but this build of atuin only supports local SQLite files. Build it with the libsql feature to keep history in libSQL"
+    );
+}
+
+/// Whether this shell is incognito, and its history shouldn't be kept. Set by
+/// `atuin incognito`, or by setting `ATUIN_INCOGNITO=1`.
+pub fn incognito() -> bool {
+    env::var("ATUIN_INCOGNITO").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Save history as its command starts. This runs before every command, so rather than opening the
+/// database as a whole it's a single insert, falling back to [`open`] when the database is new or
+/// needs migrating. Incognito shells only kept history in memory, so save nothing.
+pub async fn save_start(settings: &Settings, h: &History) -> eyre::Result<()> {
+    if incognito() {
+        return Ok(());
+    }
+
+    let path = Path::new(&settings.db_path);
+
+    if !path.exists() || !Sqlite::save_direct(path, settings.local_timeout, h).await? {
+        open(settings).await?.save(h).await?;
+    }
+
+    Ok(())
+}
+
+/// Open history for recording and searching from this shell. That's the configured database,
+/// unless the shell is [incognito](incognito).
+pub async fn open_session(settings: &Settings) -> eyre::Result<Arc<dyn Database>> {
+    if incognito() {
+        Ok(Arc::new(
+            Sqlite::incognito(settings.db_path.as_str(), settings.local_timeout).await?,
+        ))
+    } else {
+        open(settings).await
     }
 }
 
 #[async_trait]
-pub trait Database: Send + Sync + 'static {
+pub trait Database: std::fmt::Debug + Send + Sync + 'static {
     async fn save(&self, h: &History) -> Result<()>;
-    async fn save_bulk(&self, h: &[History]) -> Result<()>;
+    /// Saves history that may already be there, returning how many entries were new
+    async fn save_bulk(&self, h: &[History]) -> Result<u64>;
 
     async fn load(&self, id: &str) -> Result<Option<History>>;
     async fn list(
@@ -91,13 +314,91 @@ pub trait Database: Send + Sync + 'static {
     async fn update(&self, h: &History) -> Result<()>;
     async fn history_count(&self, include_deleted: bool) -> Result<i64>;
 
+    /// Every host with history, most recently used first
+    async fn hosts(&self) -> Result<Vec<String>>;
+
+    /// The most used commands, and how many times each was run. Commands are grouped by their
+    /// first word if `by_prefix` is set. Commands cancelled at the prompt never ran, so aren't
+    /// counted.
+    async fn top_commands(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        limit: Option<usize>,
+        by_prefix: bool,
+    ) -> Result<Vec<(String, i64)>>;
+
+    /// How many commands were cancelled at the prompt
+    async fn cancelled_count(&self, range: Option<Range<OffsetDateTime>>) -> Result<i64>;
+
+    /// Counts of history for each day, week, directory, host or exit code. Days and weeks are
+    /// in order, in the given timezone, and the rest have the most used first. Like
+    /// `top_commands`, cancelled commands aren't counted.
+    async fn grouped_stats(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        group: StatsGroup,
+        offset: UtcOffset,
+    ) -> Result<Vec<GroupStats>>;
+
+    /// How many commands were run on each day with history, as ("YYYY-MM-DD", count), in order
+    /// and in the given timezone. Only runs of `command` are counted, if it's given.
+    async fn daily_counts(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        command: Option<&str>,
+        offset: UtcOffset,
+    ) -> Result<Vec<(String, i64)>>;
+
     async fn last(&self) -> Result<Option<History>>;
     async fn before(&self, timestamp: OffsetDateTime, count: i64) -> Result<Vec<History>>;
 
-    async fn delete(&self, h: History) -> Result<()>;
-    async fn delete_rows(&self, ids: &[HistoryId]) -> Result<()>;
+    /// Mark history as deleted. It's kept as a tombstone until purged, so that the deletion
+    /// wins over any copy of the history that sync brings back.
+    async fn delete(&self, id: HistoryId) -> Result<()>;
+    async fn delete_bulk(&self, ids: &[HistoryId]) -> Result<()>;
     async fn deleted(&self) -> Result<Vec<History>>;
 
+    /// Permanently remove deleted history. Anything deleted may then reappear, if sync
+    /// downloads it again.
+    async fn purge(&self) -> Result<()>;
+
+    /// History that repeats a later entry exactly - the same command, run in the same directory
+    /// during the same session. The latest run of each is not included.
+    async fn duplicates(&self) -> Result<Vec<History>>;
+
+    /// History that falls outside a retention policy: anything from before `before`, and
+    /// anything but the newest `keep` entries. Oldest first.
+    async fn expired(
+        &self,
+        before: Option<OffsetDateTime>,
+        keep: Option<usize>,
+    ) -> Result<Vec<History>>;
+
+    /// Rebuild the database file, giving back the space left over by deleted rows
+    async fn vacuum(&self) -> Result<()>;
+
+    /// Star or unstar history. These are only a local copy of the stars, see
+    /// [`HistoryStore::star`](crate::history::store::HistoryStore::star).
+    async fn star(&self, ids: &[HistoryId], starred: bool) -> Result<()>;
+    /// Replace every star with these
+    async fn replace_starred(&self, ids: &[HistoryId]) -> Result<()>;
+    async fn starred(&self) -> Result<Vec<History>>;
+
+    /// Tag or untag history, and set or remove (with an empty note) its note. Like stars, these
+    /// are a local copy, see [`AnnotationStore`](crate::history::annotation::AnnotationStore).
+    async fn tag(&self, id: &HistoryId, tag: &str, tagged: bool) -> Result<()>;
+    async fn note(&self, id: &HistoryId, note: &str) -> Result<()>;
+    /// Replace every tag and note with these
+    async fn replace_annotations(
+        &self,
+        tags: Vec<(HistoryId, String)>,
+        notes: Vec<(HistoryId, String)>,
+    ) -> Result<()>;
+    /// The tags and note on some history
+    async fn annotations(&self, id: &HistoryId) -> Result<(Vec<String>, Option<String>)>;
+    /// History with a tag, newest first
+    async fn tagged(&self, tag: &str) -> Result<Vec<History>>;
+
     // Yes I know, it's a lot.
     // Could maybe break it down to a searchparams struct or smth but that feels a little... pointless.
     // Been debating maybe a DSL for search? eg "before:time limit:1 the query"
@@ -111,6 +412,10 @@ pub trait Database: Send + Sync + 'static {
         filter_options: OptFilters,
     ) -> Result<Vec<History>>;
 
+    /// The best command that starts with, and is longer than, `prefix`, for shell
+    /// autosuggestions. Commands run often, recently and in the current directory win.
+    async fn prefix_suggestion(&self, prefix: &str, context: &Context) -> Result<Option<String>>;
+
     async fn query_history(&self, query: &str) -> Result<Vec<History>>;
 
     async fn all_with_count(&self) -> Result<Vec<(History, i32)>>;
@@ -118,11 +423,200 @@ pub trait Database: Send + Sync + 'static {
     async fn stats(&self, h: &History) -> Result<HistoryStats>;
 }
 
+// Lets `open` choose the backend at runtime, and hand it to anything wanting a Database
+#[async_trait]
+impl<T: Database + ?Sized> Database for Arc<T> {
+    async fn save(&self, h: &History) -> Result<()> {
+        (**self).save(h).await
+    }
+
+    async fn save_bulk(&self, h: &[History]) -> Result<u64> {
+        (**self).save_bulk(h).await
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<History>> {
+        (**self).load(id).await
+    }
+
+    async fn list(
+        &self,
+        filters: &[FilterMode],
+        context: &Context,
+        max: Option<usize>,
+        unique: bool,
+        include_deleted: bool,
+    ) -> Result<Vec<History>> {
+        (**self)
+            .list(filters, context, max, unique, include_deleted)
+            .await
+    }
+
+    async fn range(&self, from: OffsetDateTime, to: OffsetDateTime) -> Result<Vec<History>> {
+        (**self).range(from, to).await
+    }
+
+    async fn update(&self, h: &History) -> Result<()> {
+        (**self).update(h).await
+    }
+
+    async fn history_count(&self, include_deleted: bool) -> Result<i64> {
+        (**self).history_count(include_deleted).await
+    }
+
+    async fn hosts(&self) -> Result<Vec<String>> {
+        (**self).hosts().await
+    }
+
+    async fn top_commands(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        limit: Option<usize>,
+        by_prefix: bool,
+    ) -> Result<Vec<(String, i64)>> {
+        (**self).top_commands(range, limit, by_prefix).await
+    }
+
+    async fn cancelled_count(&self, range: Option<Range<OffsetDateTime>>) -> Result<i64> {
+        (**self).cancelled_count(range).await
+    }
+
+    async fn grouped_stats(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        group: StatsGroup,
+        offset: UtcOffset,
+    ) -> Result<Vec<GroupStats>> {
+        (**self).grouped_stats(range, group, offset).await
+    }
+
+    async fn daily_counts(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        command: Option<&str>,
+        offset: UtcOffset,
+    ) -> Result<Vec<(String, i64)>> {
+        (**self).daily_counts(range, command, offset).await
+    }
+
+    async fn last(&self) -> Result<Option<History>> {
+        (**self).last().await
+    }
+
+    async fn before(&self, timestamp: OffsetDateTime, count: i64) -> Result<Vec<History>> {
+        (**self).before(timestamp, count).await
+    }
+
+    async fn delete(&self, id: HistoryId) -> Result<()> {
+        (**self).delete(id).await
+    }
+
+    async fn delete_bulk(&self, ids: &[HistoryId]) -> Result<()> {
+        (**self).delete_bulk(ids).await
+    }
+
+    async fn deleted(&self) -> Result<Vec<History>> {
+        (**self).deleted().await
+    }
+
+    async fn purge(&self) -> Result<()> {
+        (**self).purge().await
+    }
+
+    async fn duplicates(&self) -> Result<Vec<History>> {
+        (**self).duplicates().await
+    }
+
+    async fn expired(
+        &self,
+        before: Option<OffsetDateTime>,
+        keep: Option<usize>,
+    ) -> Result<Vec<History>> {
+        (**self).expired(before, keep).await
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        (**self).vacuum().await
+    }
+
+    async fn star(&self, ids: &[HistoryId], starred: bool) -> Result<()> {
+        (**self).star(ids, starred).await
+    }
+
+    async fn replace_starred(&self, ids: &[HistoryId]) -> Result<()> {
+        (**self).replace_starred(ids).await
+    }
+
+    async fn starred(&self) -> Result<Vec<History>> {
+        (**self).starred().await
+    }
+
+    async fn tag(&self, id: &HistoryId, tag: &str, tagged: bool) -> Result<()> {
+        (**self).tag(id, tag, tagged).await
+    }
+
+    async fn note(&self, id: &HistoryId, note: &str) -> Result<()> {
+        (**self).note(id, note).await
+    }
+
+    async fn replace_annotations(
+        &self,
+        tags: Vec<(HistoryId, String)>,
+        notes: Vec<(HistoryId, String)>,
+    ) -> Result<()> {
+        (**self).replace_annotations(tags, notes).await
+    }
+
+    async fn annotations(&self, id: &HistoryId) -> Result<(Vec<String>, Option<String>)> {
+        (**self).annotations(id).await
+    }
+
+    async fn tagged(&self, tag: &str) -> Result<Vec<History>> {
+        (**self).tagged(tag).await
+    }
+
+    async fn search(
+        &self,
+        search_mode: SearchMode,
+        filter: FilterMode,
+        context: &Context,
+        query: &str,
+        filter_options: OptFilters,
+    ) -> Result<Vec<History>> {
+        (**self)
+            .search(search_mode, filter, context, query, filter_options)
+            .await
+    }
+
+    async fn prefix_suggestion(&self, prefix: &str, context: &Context) -> Result<Option<String>> {
+        (**self).prefix_suggestion(prefix, context).await
+    }
+
+    async fn query_history(&self, query: &str) -> Result<Vec<History>> {
+        (**self).query_history(query).await
+    }
+
+    async fn all_with_count(&self) -> Result<Vec<(History, i32)>> {
+        (**self).all_with_count().await
+    }
+
+    async fn stats(&self, h: &History) -> Result<HistoryStats> {
+        (**self).stats(h).await
+    }
+}
+
 // Intended for use on a developer machine and not a sync server.
 // TODO: implement IntoIterator
 #[derive(Debug, Clone)]
 pub struct Sqlite {
+    /// For reads. With the database in WAL mode these never wait on a write, whether it's ours
+    /// or another process's.
     pub pool: SqlitePool,
+
+    // A single connection, which only the write queue and vacuum use
+    write_pool: SqlitePool,
+
+    // Writes are funneled through a single task, see write_queue.rs
+    writer: WriteQueue,
 }
 
 impl Sqlite {
@@ -137,6 +631,7 @@ impl Sqlite {
             }
         }
 
+        let timeout = Duration::from_secs_f64(timeout);
         let opts = SqliteConnectOptions::from_str(path.as_os_str().to_str().unwrap())?
             .journal_mode(SqliteJournalMode::Wal)
             .optimize_on_close(true, None)
@@ -144,14 +639,72 @@ impl Sqlite {
             .with_regexp()
             .create_if_missing(true);
 
+        // Other shells write to the same file as they finish commands. Wait for them, rather
+        // than failing with SQLITE_BUSY.
+        let write_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(timeout)
+            .connect_with(opts.clone().busy_timeout(timeout))
+            .await?;
+
+        Self::setup_db(&write_pool).await?;
+
+        // An in memory database (as used by tests) only exists while the writer has it open,
+        // and can't be opened read only
+        let in_memory = opts
+            .get_filename()
+            .to_string_lossy()
+            .starts_with("file:sqlx-in-memory");
         let pool = SqlitePoolOptions::new()
-            .acquire_timeout(Duration::from_secs_f64(timeout))
-            .connect_with(opts)
+            .acquire_timeout(timeout)
+            .connect_with(opts.read_only(!in_memory))
+            .await?;
+
+        let writer = WriteQueue::new(write_pool.clone());
+
+        Ok(Self {
+            pool,
+            write_pool,
+            writer,
+        })
+    }
+
+    /// History written here is kept in memory, and gone as soon as we exit. Reads come from the
+    /// database at `path`, which is opened read only, so incognito shells can still search
+    /// everything from before.
+    pub async fn incognito(path: impl AsRef<Path>, timeout: f64) -> Result<Self> {
+        let path = path.as_ref();
+        let timeout = Duration::from_secs_f64(timeout);
+
+        let write_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(timeout)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?.with_regexp())
             .await?;
 
-        Self::setup_db(&pool).await?;
+        Self::setup_db(&write_pool).await?;
+
+        // with nothing to read yet, there's only what this shell has written
+        let pool = if path.exists() {
+            let opts = SqliteConnectOptions::from_str(path.as_os_str().to_str().unwrap())?
+                .with_regexp()
+                .read_only(true);
+
+            SqlitePoolOptions::new()
+                .acquire_timeout(timeout)
+                .connect_with(opts)
+                .await?
+        } else {
+            write_pool.clone()
+        };
+
+        let writer = WriteQueue::new(write_pool.clone());
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            write_pool,
+            writer,
+        })
     }
 
     pub async fn sqlite_version(&self) -> Result<String> {
@@ -163,91 +716,206 @@ impl Sqlite {
     async fn setup_db(pool: &SqlitePool) -> Result<()> {
         debug!("running sqlite database setup");
 
-        sqlx::migrate!("./migrations").run(pool).await?;
+        MIGRATOR.run(pool).await?;
 
         Ok(())
     }
 
-    async fn save_raw(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, h: &History) -> Result<()> {
-        sqlx::query(
-            "insert or ignore into history(id, timestamp, duration, exit, command, cwd, session, hostname, deleted_at)
-                values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-        )
-        .bind(h.id.0.as_str())
-        .bind(h.timestamp.unix_timestamp_nanos() as i64)
-        .bind(h.duration)
-        .bind(h.exit)
-        .bind(h.command.as_str())
-        .bind(h.cwd.as_str())
-        .bind(h.session.as_str())
-        .bind(h.hostname.as_str())
-        .bind(h.deleted_at.map(|t|t.unix_timestamp_nanos() as i64))
-        .execute(&mut **tx)
-        .await?;
+    /// Saves `h` over one connection, without the pools or write queue. Returns false, having
+    /// saved nothing, if there are migrations to run first.
+    async fn save_direct(path: &Path, timeout: f64, h: &History) -> Result<bool> {
+        let timeout = Duration::from_secs_f64(timeout);
+        let opts = SqliteConnectOptions::from_str(path.as_os_str().to_str().unwrap())?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(timeout);
+
+        let mut conn = SqliteConnection::connect_with(&opts).await?;
+
+        // the migrations table may not be there yet, which needs migrating too
+        let migrated: Option<i64> =
+            sqlx::query_scalar("select max(version) from _sqlx_migrations where success")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap_or_default();
+
+        if migrated != MIGRATOR.iter().map(|m| m.version).max() {
+            return Ok(false);
+        }
+
+        let mut tx = conn.begin().await?;
+        Self::save_raw(&mut tx, h).await?;
+        tx.commit().await?;
+
+        conn.close().await?;
+
+        Ok(true)
+    }
+
+    /// Returns how many rows were inserted, which is 0 if the entry was already saved
+    async fn save_raw(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, h: &History) -> Result<u64> {
+        let res = Self::bind_history(sqlx::query(queries::SAVE), h)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn update_raw(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, h: &History) -> Result<()> {
+        Self::bind_history(sqlx::query(queries::UPDATE), h)
+            .execute(&mut **tx)
+            .await?;
 
         Ok(())
     }
 
-    async fn delete_row_raw(
+    async fn delete_raw(
         tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-        id: HistoryId,
+        id: &HistoryId,
+        deleted_at: OffsetDateTime,
     ) -> Result<()> {
-        sqlx::query("delete from history where id = ?1")
+        sqlx::query(queries::DELETE)
             .bind(id.0.as_str())
+            .bind(deleted_at.unix_timestamp_nanos() as i64)
             .execute(&mut **tx)
             .await?;
 
         Ok(())
     }
 
-    fn query_history(row: SqliteRow) -> History {
-        let deleted_at: Option<i64> = row.get("deleted_at");
-
-        History::from_db()
-            .id(row.get("id"))
-            .timestamp(
-                OffsetDateTime::from_unix_timestamp_nanos(row.get::<i64, _>("timestamp") as i128)
-                    .unwrap(),
-            )
-            .duration(row.get("duration"))
-            .exit(row.get("exit"))
-            .command(row.get("command"))
-            .cwd(row.get("cwd"))
-            .session(row.get("session"))
-            .hostname(row.get("hostname"))
-            .deleted_at(
-                deleted_at.and_then(|t| OffsetDateTime::from_unix_timestamp_nanos(t as i128).ok()),
-            )
-            .build()
-            .into()
-    }
-}
-
-#[async_trait]
-impl Database for Sqlite {
-    async fn save(&self, h: &History) -> Result<()> {
-        debug!("saving history to sqlite");
-        let mut tx = self.pool.begin().await?;
-        Self::save_raw(&mut tx, h).await?;
-        tx.commit().await?;
+    async fn purge_raw(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<()> {
+        sqlx::query(queries::PURGE).execute(&mut **tx).await?;
 
         Ok(())
     }
 
-    async fn save_bulk(&self, h: &[History]) -> Result<()> {
-        debug!("saving history to sqlite");
-
-        let mut tx = self.pool.begin().await?;
+    async fn star_raw(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: &HistoryId,
+        starred: bool,
+    ) -> Result<()> {
+        let query = if starred {
+            queries::STAR
+        } else {
+            queries::UNSTAR
+        };
 
-        for i in h {
-            Self::save_raw(&mut tx, i).await?;
-        }
+        sqlx::query(query)
+            .bind(id.0.as_str())
+            .execute(&mut **tx)
+            .await?;
 
-        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn tag_raw(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: &HistoryId,
+        tag: &str,
+        tagged: bool,
+    ) -> Result<()> {
+        let query = if tagged { queries::TAG } else { queries::UNTAG };
+
+        sqlx::query(query)
+            .bind(id.0.as_str())
+            .bind(tag)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn note_raw(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: &HistoryId,
+        note: &str,
+    ) -> Result<()> {
+        if note.is_empty() {
+            sqlx::query(queries::REMOVE_NOTE)
+                .bind(id.0.as_str())
+                .execute(&mut **tx)
+                .await?;
+        } else {
+            sqlx::query(queries::NOTE)
+                .bind(id.0.as_str())
+                .bind(note)
+                .execute(&mut **tx)
+                .await?;
+        }
 
         Ok(())
     }
 
+    // The parameters of queries::SAVE and queries::UPDATE
+    fn bind_history<'q>(
+        query: sqlx::query::Query<'q, sqlx::Sqlite, SqliteArguments<'q>>,
+        h: &'q History,
+    ) -> sqlx::query::Query<'q, sqlx::Sqlite, SqliteArguments<'q>> {
+        query
+            .bind(h.id.0.as_str())
+            .bind(h.timestamp.unix_timestamp_nanos() as i64)
+            .bind(h.duration)
+            .bind(h.exit)
+            .bind(h.command.as_str())
+            .bind(h.cwd.as_str())
+            .bind(h.session.as_str())
+            .bind(h.hostname.as_str())
+            .bind(h.deleted_at.map(|t| t.unix_timestamp_nanos() as i64))
+            .bind(h.namespace.as_str())
+            .bind(h.stdout_bytes)
+            .bind(h.stderr_bytes)
+            .bind(env_json(h))
+            .bind(h.branch.as_str())
+    }
+
+    fn query_history(row: SqliteRow) -> History {
+        let deleted_at: Option<i64> = row.get("deleted_at");
+        let env: Option<String> = row.get("env");
+
+        History::from_db()
+            .id(row.get("id"))
+            .timestamp(
+                OffsetDateTime::from_unix_timestamp_nanos(row.get::<i64, _>("timestamp") as i128)
+                    .unwrap(),
+            )
+            .duration(row.get("duration"))
+            .exit(row.get("exit"))
+            .command(row.get("command"))
+            .cwd(row.get("cwd"))
+            .session(row.get("session"))
+            .hostname(row.get("hostname"))
+            .namespace(row.get("namespace"))
+            .stdout_bytes(row.get("stdout_bytes"))
+            .stderr_bytes(row.get("stderr_bytes"))
+            .env(
+                env.and_then(|env| serde_json::from_str(&env).ok())
+                    .unwrap_or_default(),
+            )
+            .branch(row.get("branch"))
+            .deleted_at(
+                deleted_at.and_then(|t| OffsetDateTime::from_unix_timestamp_nanos(t as i128).ok()),
+            )
+            .build()
+            .into()
+    }
+}
+
+#[async_trait]
+impl Database for Sqlite {
+    async fn save(&self, h: &History) -> Result<()> {
+        debug!("saving history to sqlite");
+
+        self.writer.submit(Write::Save(h.clone())).await
+    }
+
+    async fn save_bulk(&self, h: &[History]) -> Result<u64> {
+        debug!("saving history to sqlite");
+
+        self.writer
+            .submit_counted(Write::SaveBulk(h.to_vec()))
+            .await
+    }
+
     async fn load(&self, id: &str) -> Result<Option<History>> {
         debug!("loading history item {}", id);
 
@@ -263,27 +931,9 @@ impl Database for Sqlite {
     async fn update(&self, h: &History) -> Result<()> {
         debug!("updating sqlite history");
 
-        sqlx::query(
-            "update history
-                set timestamp = ?2, duration = ?3, exit = ?4, command = ?5, cwd = ?6, session = ?7, hostname = ?8, deleted_at = ?9
-                where id = ?1",
-        )
-        .bind(h.id.0.as_str())
-        .bind(h.timestamp.unix_timestamp_nanos() as i64)
-        .bind(h.duration)
-        .bind(h.exit)
-        .bind(h.command.as_str())
-        .bind(h.cwd.as_str())
-        .bind(h.session.as_str())
-        .bind(h.hostname.as_str())
-        .bind(h.deleted_at.map(|t|t.unix_timestamp_nanos() as i64))
-        .execute(&self.pool)
-        .await?;
-
-        Ok(())
+        self.writer.submit(Write::Update(h.clone())).await
     }
 
-    // make a unique list, that only shows the *newest* version of things
     async fn list(
         &self,
         filters: &[FilterMode],
@@ -294,37 +944,7 @@ impl Database for Sqlite {
     ) -> Result<Vec<History>> {
         debug!("listing history");
 
-        let mut query = SqlBuilder::select_from(SqlName::new("history").alias("h").baquoted());
-        query.field("*").order_desc("timestamp");
-        if !include_deleted {
-            query.and_where_is_null("deleted_at");
-        }
-
-        let git_root = if let Some(git_root) = context.git_root.clone() {
-            git_root.to_str().unwrap_or("/").to_string()
-        } else {
-            context.cwd.clone()
-        };
-
-        for filter in filters {
-            match filter {
-                FilterMode::Global => &mut query,
-                FilterMode::Host => query.and_where_eq("hostname", quote(&context.hostname)),
-                FilterMode::Session => query.and_where_eq("session", quote(&context.session)),
-                FilterMode::Directory => query.and_where_eq("cwd", quote(&context.cwd)),
-                FilterMode::Workspace => query.and_where_like_left("cwd", &git_root),
-            };
-        }
-
-        if unique {
-            query.group_by("command").having("max(timestamp)");
-        }
-
-        if let Some(max) = max {
-            query.limit(max);
-        }
-
-        let query = query.sql().expect("bug in list query. please report");
+        let query = queries::list(filters, context, max, unique, include_deleted);
 
         let res = sqlx::query(&query)
             .map(Self::query_history)
@@ -337,38 +957,67 @@ impl Database for Sqlite {
     async fn range(&self, from: OffsetDateTime, to: OffsetDateTime) -> Result<Vec<History>> {
         debug!("listing history from {:?} to {:?}", from, to);
 
-        let res = sqlx::query(
-            "select * from history where timestamp >= ?1 and timestamp <= ?2 order by timestamp asc",
-        )
-        .bind(from.unix_timestamp_nanos() as i64)
-        .bind(to.unix_timestamp_nanos() as i64)
+        let res = sqlx::query(queries::RANGE)
+            .bind(from.unix_timestamp_nanos() as i64)
+            .bind(to.unix_timestamp_nanos() as i64)
             .map(Self::query_history)
-        .fetch_all(&self.pool)
-        .await?;
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(res)
     }
 
     async fn last(&self) -> Result<Option<History>> {
-        let res = sqlx::query(
-            "select * from history where duration >= 0 order by timestamp desc limit 1",
-        )
-        .map(Self::query_history)
-        .fetch_optional(&self.pool)
-        .await?;
+        let res = sqlx::query(queries::LAST)
+            .map(Self::query_history)
+            .fetch_optional(&self.pool)
+            .await?;
 
         Ok(res)
     }
 
     async fn before(&self, timestamp: OffsetDateTime, count: i64) -> Result<Vec<History>> {
-        let res = sqlx::query(
-            "select * from history where timestamp < ?1 order by timestamp desc limit ?2",
-        )
-        .bind(timestamp.unix_timestamp_nanos() as i64)
-        .bind(count)
-        .map(Self::query_history)
-        .fetch_all(&self.pool)
-        .await?;
+        let res = sqlx::query(queries::BEFORE)
+            .bind(timestamp.unix_timestamp_nanos() as i64)
+            .bind(count)
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
+
+    async fn starred(&self) -> Result<Vec<History>> {
+        let res = sqlx::query(queries::STARRED)
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
+
+    async fn annotations(&self, id: &HistoryId) -> Result<(Vec<String>, Option<String>)> {
+        let tags: Vec<(String,)> =
+            sqlx::query_as("select tag from history_tags where id = ?1 order by tag")
+                .bind(id.0.as_str())
+                .fetch_all(&self.pool)
+                .await?;
+
+        let note: Option<(String,)> =
+            sqlx::query_as("select note from history_notes where id = ?1")
+                .bind(id.0.as_str())
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok((tags.into_iter().map(|t| t.0).collect(), note.map(|n| n.0)))
+    }
+
+    async fn tagged(&self, tag: &str) -> Result<Vec<History>> {
+        let res = sqlx::query(queries::TAGGED)
+            .bind(tag)
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(res)
     }
@@ -393,180 +1042,100 @@ impl Database for Sqlite {
         Ok(res.0)
     }
 
-    async fn search(
-        &self,
-        search_mode: SearchMode,
-        filter: FilterMode,
-        context: &Context,
-        query: &str,
-        filter_options: OptFilters,
-    ) -> Result<Vec<History>> {
-        let mut sql = SqlBuilder::select_from("history");
+    async fn hosts(&self) -> Result<Vec<String>> {
+        let res = sqlx::query_scalar(queries::HOSTS)
+            .fetch_all(&self.pool)
+            .await?;
 
-        sql.group_by("command").having("max(timestamp)");
+        Ok(res)
+    }
 
-        if let Some(limit) = filter_options.limit {
-            sql.limit(limit);
-        }
+    async fn top_commands(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        limit: Option<usize>,
+        by_prefix: bool,
+    ) -> Result<Vec<(String, i64)>> {
+        let query = queries::top_commands(range, limit, by_prefix);
 
-        if let Some(offset) = filter_options.offset {
-            sql.offset(offset);
-        }
+        let res = sqlx::query_as(&query).fetch_all(&self.pool).await?;
 
-        if filter_options.reverse {
-            sql.order_asc("timestamp");
-        } else {
-            sql.order_desc("timestamp");
-        }
+        Ok(res)
+    }
 
-        let git_root = if let Some(git_root) = context.git_root.clone() {
-            git_root.to_str().unwrap_or("/").to_string()
-        } else {
-            context.cwd.clone()
-        };
+    async fn grouped_stats(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        group: StatsGroup,
+        offset: UtcOffset,
+    ) -> Result<Vec<GroupStats>> {
+        let query = queries::grouped_stats(range, group, offset);
 
-        match filter {
-            FilterMode::Global => &mut sql,
-            FilterMode::Host => {
-                sql.and_where_eq("lower(hostname)", quote(context.hostname.to_lowercase()))
-            }
-            FilterMode::Session => sql.and_where_eq("session", quote(&context.session)),
-            FilterMode::Directory => sql.and_where_eq("cwd", quote(&context.cwd)),
-            FilterMode::Workspace => sql.and_where_like_left("cwd", git_root),
-        };
+        let res = sqlx::query_as(&query).fetch_all(&self.pool).await?;
 
-        let orig_query = query;
-
-        let mut regexes = Vec::new();
-        match search_mode {
-            SearchMode::Prefix => sql.and_where_like_left("command", query.replace('*', "%")),
-            _ => {
-                let mut is_or = false;
-                let mut regex = None;
-                for part in query.split_inclusive(' ') {
-                    let query_part: Cow<str> = match (&mut regex, part.starts_with("r/")) {
-                        (None, false) => {
-                            if part.trim_end().is_empty() {
-                                continue;
-                            }
-                            Cow::Owned(part.trim_end().replace('*', "%")) // allow wildcard char
-                        }
-                        (None, true) => {
-                            if part[2..].trim_end().ends_with('/') {
-                                let end_pos = part.trim_end().len() - 1;
-                                regexes.push(String::from(&part[2..end_pos]));
-                            } else {
-                                regex = Some(String::from(&part[2..]));
-                            }
-                            continue;
-                        }
-                        (Some(r), _) => {
-                            if part.trim_end().ends_with('/') {
-                                let end_pos = part.trim_end().len() - 1;
-                                r.push_str(&part.trim_end()[..end_pos]);
-                                regexes.push(regex.take().unwrap());
-                            } else {
-                                r.push_str(part);
-                            }
-                            continue;
-                        }
-                    };
-
-                    // TODO smart case mode could be made configurable like in fzf
-                    let (is_glob, glob) = if query_part.contains(char::is_uppercase) {
-                        (true, "*")
-                    } else {
-                        (false, "%")
-                    };
-
-                    let (is_inverse, query_part) = match query_part.strip_prefix('!') {
-                        Some(stripped) => (true, Cow::Borrowed(stripped)),
-                        None => (false, query_part),
-                    };
-
-                    #[allow(clippy::if_same_then_else)]
-                    let param = if query_part == "|" {
-                        if !is_or {
-                            is_or = true;
-                            continue;
-                        } else {
-                            format!("{glob}|{glob}")
-                        }
-                    } else if let Some(term) = query_part.strip_prefix('^') {
-                        format!("{term}{glob}")
-                    } else if let Some(term) = query_part.strip_suffix('$') {
-                        format!("{glob}{term}")
-                    } else if let Some(term) = query_part.strip_prefix('\'') {
-                        format!("{glob}{term}{glob}")
-                    } else if is_inverse {
-                        format!("{glob}{query_part}{glob}")
-                    } else if search_mode == SearchMode::FullText {
-                        format!("{glob}{query_part}{glob}")
-                    } else {
-                        query_part.split("").join(glob)
-                    };
-
-                    sql.fuzzy_condition("command", param, is_inverse, is_glob, is_or);
-                    is_or = false;
-                }
-                if let Some(r) = regex {
-                    regexes.push(r);
-                }
-
-                &mut sql
-            }
+        Ok(res)
+    }
+
+    async fn daily_counts(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        command: Option<&str>,
+        offset: UtcOffset,
+    ) -> Result<Vec<(String, i64)>> {
+        let query = queries::daily_counts(range, command.is_some(), offset);
+
+        let query = sqlx::query_as(&query);
+        let query = match command {
+            Some(command) => query.bind(command),
+            None => query,
         };
 
-        for regex in regexes {
-            sql.and_where("command regexp ?".bind(&regex));
-        }
+        Ok(query.fetch_all(&self.pool).await?)
+    }
 
-        filter_options
-            .exit
-            .map(|exit| sql.and_where_eq("exit", exit));
+    async fn cancelled_count(&self, range: Option<Range<OffsetDateTime>>) -> Result<i64> {
+        let query = queries::cancelled_count(range);
 
-        filter_options
-            .exclude_exit
-            .map(|exclude_exit| sql.and_where_ne("exit", exclude_exit));
+        let res: (i64,) = sqlx::query_as(&query).fetch_one(&self.pool).await?;
 
-        filter_options
-            .cwd
-            .map(|cwd| sql.and_where_eq("cwd", quote(cwd)));
+        Ok(res.0)
+    }
 
-        filter_options
-            .exclude_cwd
-            .map(|exclude_cwd| sql.and_where_ne("cwd", quote(exclude_cwd)));
+    async fn search(
+        &self,
+        search_mode: SearchMode,
+        filter: FilterMode,
+        context: &Context,
+        query: &str,
+        filter_options: OptFilters,
+    ) -> Result<Vec<History>> {
+        let (sql, query) = queries::search(search_mode, filter, context, query, filter_options);
 
-        filter_options.before.map(|before| {
-            interim::parse_date_string(
-                before.as_str(),
-                OffsetDateTime::now_utc(),
-                interim::Dialect::Uk,
-            )
-            .map(|before| {
-                sql.and_where_lt("timestamp", quote(before.unix_timestamp_nanos() as i64))
-            })
-        });
+        let res = sqlx::query(&sql)
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
 
-        filter_options.after.map(|after| {
-            interim::parse_date_string(
-                after.as_str(),
-                OffsetDateTime::now_utc(),
-                interim::Dialect::Uk,
-            )
-            .map(|after| sql.and_where_gt("timestamp", quote(after.unix_timestamp_nanos() as i64)))
-        });
+        Ok(ordering::reorder_fuzzy(search_mode, &query, res))
+    }
 
-        sql.and_where_is_null("deleted_at");
+    async fn prefix_suggestion(&self, prefix: &str, context: &Context) -> Result<Option<String>> {
+        if prefix.is_empty() {
+            return Ok(None);
+        }
 
-        let query = sql.sql().expect("bug in search query. please report");
+        let end = format!("{prefix}\u{10FFFF}");
+        let now = OffsetDateTime::now_utc().unix_timestamp_nanos() as i64;
 
-        let res = sqlx::query(&query)
-            .map(Self::query_history)
-            .fetch_all(&self.pool)
+        let res: Option<(String,)> = sqlx::query_as(queries::PREFIX_SUGGESTION)
+            .bind(prefix)
+            .bind(end)
+            .bind(context.cwd.as_str())
+            .bind(now)
+            .fetch_optional(&self.pool)
             .await?;
 
-        Ok(ordering::reorder_fuzzy(search_mode, orig_query, res))
+        Ok(res.map(|(command,)| command))
     }
 
     async fn query_history(&self, query: &str) -> Result<Vec<History>> {
@@ -581,27 +1150,7 @@ impl Database for Sqlite {
     async fn all_with_count(&self) -> Result<Vec<(History, i32)>> {
         debug!("listing history");
 
-        let mut query = SqlBuilder::select_from(SqlName::new("history").alias("h").baquoted());
-
-        query
-            .fields(&[
-                "id",
-                "max(timestamp) as timestamp",
-                "max(duration) as duration",
-                "exit",
-                "command",
-                "deleted_at",
-                "group_concat(cwd, ':') as cwd",
-                "group_concat(session) as session",
-                "group_concat(hostname, ',') as hostname",
-                "count(*) as count",
-            ])
-            .group_by("command")
-            .group_by("exit")
-            .and_where("deleted_at is null")
-            .order_desc("timestamp");
-
-        let query = query.sql().expect("bug in list query. please report");
+        let query = queries::all_with_count();
 
         let res = sqlx::query(&query)
             .map(|row: SqliteRow| {
@@ -616,129 +1165,126 @@ impl Database for Sqlite {
 
     // deleted_at doesn't mean the actual time that the user deleted it,
     // but the time that the system marks it as deleted
-    async fn delete(&self, mut h: History) -> Result<()> {
-        let now = OffsetDateTime::now_utc();
-        h.command = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect(); // overwrite with random string
-        h.deleted_at = Some(now); // delete it
+    async fn delete(&self, id: HistoryId) -> Result<()> {
+        self.delete_bulk(&[id]).await
+    }
 
-        self.update(&h).await?; // save it
+    async fn delete_bulk(&self, ids: &[HistoryId]) -> Result<()> {
+        self.writer
+            .submit(Write::Delete(ids.to_vec(), OffsetDateTime::now_utc()))
+            .await
+    }
 
-        Ok(())
+    async fn purge(&self) -> Result<()> {
+        self.writer.submit(Write::Purge).await
+    }
+
+    async fn star(&self, ids: &[HistoryId], starred: bool) -> Result<()> {
+        self.writer.submit(Write::Star(ids.to_vec(), starred)).await
+    }
+
+    async fn replace_starred(&self, ids: &[HistoryId]) -> Result<()> {
+        self.writer
+            .submit(Write::ReplaceStarred(ids.to_vec()))
+            .await
     }
 
-    async fn delete_rows(&self, ids: &[HistoryId]) -> Result<()> {
-        let mut tx = self.pool.begin().await?;
+    async fn tag(&self, id: &HistoryId, tag: &str, tagged: bool) -> Result<()> {
+        self.writer
+            .submit(Write::Tag(id.clone(), tag.to_string(), tagged))
+            .await
+    }
+
+    async fn note(&self, id: &HistoryId, note: &str) -> Result<()> {
+        self.writer
+            .submit(Write::Note(id.clone(), note.to_string()))
+            .await
+    }
+
+    async fn replace_annotations(
+        &self,
+        tags: Vec<(HistoryId, String)>,
+        notes: Vec<(HistoryId, String)>,
+    ) -> Result<()> {
+        self.writer
+            .submit(Write::ReplaceAnnotations(tags, notes))
+            .await
+    }
+
+    async fn duplicates(&self) -> Result<Vec<History>> {
+        let res = sqlx::query(queries::DUPLICATES)
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
 
-        for id in ids {
-            Self::delete_row_raw(&mut tx, id.clone()).await?;
+    async fn expired(
+        &self,
+        before: Option<OffsetDateTime>,
+        keep: Option<usize>,
+    ) -> Result<Vec<History>> {
+        if before.is_none() && keep.is_none() {
+            return Ok(Vec::new());
         }
 
-        tx.commit().await?;
+        let res = sqlx::query(queries::EXPIRED)
+            .bind(before.map_or(i64::MIN, |t| t.unix_timestamp_nanos() as i64))
+            .bind(keep.map_or(-1, |k| k as i64))
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        // vacuum can't run inside a transaction, so this goes straight to the pool rather than
+        // through the write queue
+        sqlx::query("vacuum").execute(&self.write_pool).await?;
 
         Ok(())
     }
 
     async fn stats(&self, h: &History) -> Result<HistoryStats> {
-        // We select the previous in the session by time
-        let mut prev = SqlBuilder::select_from("history");
-        prev.field("*")
-            .and_where("timestamp < ?1")
-            .and_where("session = ?2")
-            .order_by("timestamp", true)
-            .limit(1);
-
-        let mut next = SqlBuilder::select_from("history");
-        next.field("*")
-            .and_where("timestamp > ?1")
-            .and_where("session = ?2")
-            .order_by("timestamp", false)
-            .limit(1);
-
-        let mut total = SqlBuilder::select_from("history");
-        total.field("count(1)").and_where("command = ?1");
-
-        let mut average = SqlBuilder::select_from("history");
-        average.field("avg(duration)").and_where("command = ?1");
-
-        let mut exits = SqlBuilder::select_from("history");
-        exits
-            .fields(&["exit", "count(1) as count"])
-            .and_where("command = ?1")
-            .group_by("exit");
-
-        // rewrite the following with sqlbuilder
-        let mut day_of_week = SqlBuilder::select_from("history");
-        day_of_week
-            .fields(&[
-                "strftime('%w', ROUND(timestamp / 1000000000), 'unixepoch') AS day_of_week",
-                "count(1) as count",
-            ])
-            .and_where("command = ?1")
-            .group_by("day_of_week");
-
-        // Intentionally format the string with 01 hardcoded. We want the average runtime for the
-        // _entire month_, but will later parse it as a datetime for sorting
-        // Sqlite has no datetime so we cannot do it there, and otherwise sorting will just be a
-        // string sort, which won't be correct.
-        let mut duration_over_time = SqlBuilder::select_from("history");
-        duration_over_time
-            .fields(&[
-                "strftime('01-%m-%Y', ROUND(timestamp / 1000000000), 'unixepoch') AS month_year",
-                "avg(duration) as duration",
-            ])
-            .and_where("command = ?1")
-            .group_by("month_year")
-            .having("duration > 0");
-
-        let prev = prev.sql().expect("issue in stats previous query");
-        let next = next.sql().expect("issue in stats next query");
-        let total = total.sql().expect("issue in stats average query");
-        let average = average.sql().expect("issue in stats previous query");
-        let exits = exits.sql().expect("issue in stats exits query");
-        let day_of_week = day_of_week.sql().expect("issue in stats day of week query");
-        let duration_over_time = duration_over_time
-            .sql()
-            .expect("issue in stats duration over time query");
+        let queries = StatsQueries::new();
 
-        let prev = sqlx::query(&prev)
+        let prev = sqlx::query(&queries.prev)
             .bind(h.timestamp.unix_timestamp_nanos() as i64)
             .bind(&h.session)
             .map(Self::query_history)
             .fetch_optional(&self.pool)
             .await?;
 
-        let next = sqlx::query(&next)
+        let next = sqlx::query(&queries.next)
             .bind(h.timestamp.unix_timestamp_nanos() as i64)
             .bind(&h.session)
             .map(Self::query_history)
             .fetch_optional(&self.pool)
             .await?;
 
-        let total: (i64,) = sqlx::query_as(&total)
+        let total: (i64,) = sqlx::query_as(&queries.total)
             .bind(&h.command)
             .fetch_one(&self.pool)
             .await?;
 
-        let average: (f64,) = sqlx::query_as(&average)
+        let average: (f64,) = sqlx::query_as(&queries.average)
             .bind(&h.command)
             .fetch_one(&self.pool)
             .await?;
 
-        let exits: Vec<(i64, i64)> = sqlx::query_as(&exits)
+        let exits: Vec<(i64, i64)> = sqlx::query_as(&queries.exits)
             .bind(&h.command)
             .fetch_all(&self.pool)
             .await?;
 
-        let day_of_week: Vec<(String, i64)> = sqlx::query_as(&day_of_week)
+        let day_of_week: Vec<(String, i64)> = sqlx::query_as(&queries.day_of_week)
             .bind(&h.command)
             .fetch_all(&self.pool)
             .await?;
 
-        let duration_over_time: Vec<(String, f64)> = sqlx::query_as(&duration_over_time)
+        let duration_over_time: Vec<(String, f64)> = sqlx::query_as(&queries.duration_over_time)
             .bind(&h.command)
             .fetch_all(&self.pool)
             .await?;
@@ -748,6 +1294,28 @@ impl Database for Sqlite {
             .map(|f| (f.0.clone(), f.1.round() as i64))
             .collect();
 
+        let success_over_time: Vec<(String, i64, i64)> = sqlx::query_as(&queries.success_over_time)
+            .bind(&h.command)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let hour_of_day: Vec<(i64, i64)> = sqlx::query_as(&queries.hour_of_day)
+            .bind(&h.command)
+            .fetch_all(&self.pool)
+            .await?;
+
+        // the same query as `atuin stats --calendar`, for the 53 weeks it shows
+        let now = OffsetDateTime::now_utc();
+        let calendar = self
+            .daily_counts(
+                Some(now - time::Duration::weeks(53)..now + time::Duration::SECOND),
+                Some(h.command.as_str()),
+                UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC),
+            )
+            .await?;
+
+        let (tags, note) = self.annotations(&h.id).await?;
+
         Ok(HistoryStats {
             next,
             previous: prev,
@@ -756,12 +1324,18 @@ impl Database for Sqlite {
             exits,
             day_of_week,
             duration_over_time,
+            success_over_time,
+            hour_of_day,
+            calendar,
+            tags,
+            note,
         })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::history::{CANCELLED_EXIT, TMUX_PANE, WEZTERM_PANE, ZELLIJ_PANE};
     use crate::settings::test_local_timeout;
 
     use super::*;
@@ -776,10 +1350,13 @@ mod test {
     ) -> Result<Vec<History>> {
         let context = Context {
             hostname: "test:host".to_string(),
+            namespace: String::new(),
             session: "beepboopiamasession".to_string(),
             cwd: "/home/ellie".to_string(),
             host_id: "test-host".to_string(),
             git_root: None,
+            pane: None,
+            container: None,
         };
 
         let results = db
@@ -830,6 +1407,7 @@ mod test {
         captured.duration = 1;
         captured.session = "beep boop".to_string();
         captured.hostname = "booop".to_string();
+        captured.namespace = String::new();
 
         db.save(&captured).await
     }
@@ -871,6 +1449,12 @@ mod test {
         assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "hm", 0)
             .await
             .unwrap();
+        assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "elli", 1)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "ELLI", 0)
+            .await
+            .unwrap();
 
         // regex
         assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "r/^ls ", 1)
@@ -939,33 +1523,99 @@ mod test {
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn test_search_fuzzy() {
+    async fn test_search_fulltext_index() {
         let mut db = Sqlite::new("sqlite::memory:", test_local_timeout())
             .await
             .unwrap();
-        new_history_item(&mut db, "ls /home/ellie").await.unwrap();
-        new_history_item(&mut db, "ls /home/frank").await.unwrap();
-        new_history_item(&mut db, "cd /home/Ellie").await.unwrap();
-        new_history_item(&mut db, "/home/ellie/.bin/rustup")
+        new_history_item(&mut db, "echo \"it's a trap\"")
             .await
             .unwrap();
-
-        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "ls /", 3)
+        new_history_item(&mut db, "cargo build --release")
             .await
             .unwrap();
-        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "ls/", 2)
+
+        // fts query syntax and sql quoting in the term are matched literally
+        assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "\"it's", 1)
             .await
             .unwrap();
-        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "l/h/", 2)
+        assert_search_eq(
+            &db,
+            SearchMode::FullText,
+            FilterMode::Global,
+            "--release",
+            1,
+        )
+        .await
+        .unwrap();
+        assert_search_eq(
+            &db,
+            SearchMode::FullText,
+            FilterMode::Global,
+            "trap | build",
+            2,
+        )
+        .await
+        .unwrap();
+
+        // the index follows updates and deletes
+        let h = db
+            .search(
+                SearchMode::FullText,
+                FilterMode::Global,
+                &Context {
+                    hostname: "test:host".to_string(),
+                    namespace: String::new(),
+                    session: "beepboopiamasession".to_string(),
+                    cwd: "/home/ellie".to_string(),
+                    host_id: "test-host".to_string(),
+                    git_root: None,
+                    pane: None,
+                    container: None,
+                },
+                "cargo",
+                OptFilters::default(),
+            )
             .await
             .unwrap();
-        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "/h/e", 3)
+        db.delete(h[0].id.clone()).await.unwrap();
+        assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "cargo", 0)
             .await
             .unwrap();
-        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "/hmoe/", 0)
+
+        db.purge().await.unwrap();
+        assert_search_eq(&db, SearchMode::FullText, FilterMode::Global, "trap", 1)
             .await
             .unwrap();
-        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "ellie/home", 0)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_fuzzy() {
+        let mut db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+        new_history_item(&mut db, "ls /home/ellie").await.unwrap();
+        new_history_item(&mut db, "ls /home/frank").await.unwrap();
+        new_history_item(&mut db, "cd /home/Ellie").await.unwrap();
+        new_history_item(&mut db, "/home/ellie/.bin/rustup")
+            .await
+            .unwrap();
+
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "ls /", 3)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "ls/", 2)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "l/h/", 2)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "/h/e", 3)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "/hmoe/", 0)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "ellie/home", 0)
             .await
             .unwrap();
         assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "lsellie", 1)
@@ -1069,14 +1719,1103 @@ mod test {
             .unwrap();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_writes() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        // Lots of writers at once, as if we had many shells ending commands together
+        let writers = (0..64).map(|i| {
+            let mut db = db.clone();
+            tokio::spawn(async move { new_history_item(&mut db, &format!("echo {i}")).await })
+        });
+
+        for res in futures::future::join_all(writers).await {
+            res.unwrap().unwrap();
+        }
+
+        assert_eq!(db.history_count(false).await.unwrap(), 64);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_read_during_write() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-history-{}.db",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        let mut db = Sqlite::new(&path, test_local_timeout()).await.unwrap();
+        new_history_item(&mut db, "ls").await.unwrap();
+
+        // Another shell, part way through writing
+        let other = Sqlite::new(&path, test_local_timeout()).await.unwrap();
+        let mut tx = other.write_pool.begin().await.unwrap();
+        sqlx::query("delete from history")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        // reads see what was there before, without waiting for it to finish
+        let count = tokio::time::timeout(Duration::from_secs(1), db.history_count(false))
+            .await
+            .expect("read waited on a write")
+            .unwrap();
+        assert_eq!(count, 1);
+
+        tx.rollback().await.unwrap();
+
+        // the read pool can't write
+        assert!(sqlx::query("delete from history")
+            .execute(&db.pool)
+            .await
+            .is_err());
+
+        drop((db, other));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_incognito() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-history-{}.db",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        let mut db = Sqlite::new(&path, test_local_timeout()).await.unwrap();
+        new_history_item(&mut db, "ls").await.unwrap();
+
+        let mut incognito = Sqlite::incognito(&path, test_local_timeout())
+            .await
+            .unwrap();
+        new_history_item(&mut incognito, "cat secrets.txt")
+            .await
+            .unwrap();
+
+        // searches see history from before, but nothing incognito reaches the file
+        assert_search_commands(
+            &incognito,
+            SearchMode::Fuzzy,
+            FilterMode::Global,
+            "",
+            vec!["ls"],
+        )
+        .await;
+        assert_eq!(db.history_count(false).await.unwrap(), 1);
+
+        drop((db, incognito));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_save_direct() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-history-{}.db",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        let h: History = History::capture()
+            .timestamp(OffsetDateTime::now_utc())
+            .command("ls")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+
+        // an empty file has nothing migrated, so is left for the migrations
+        fs::write(&path, "").unwrap();
+        assert!(!Sqlite::save_direct(&path, test_local_timeout(), &h)
+            .await
+            .unwrap());
+
+        let db = Sqlite::new(&path, test_local_timeout()).await.unwrap();
+        assert!(Sqlite::save_direct(&path, test_local_timeout(), &h)
+            .await
+            .unwrap());
+
+        let saved = db.load(&h.id.0).await.unwrap().unwrap();
+        assert_eq!(saved.command, "ls");
+        assert_eq!(db.history_count(false).await.unwrap(), 1);
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_other_host() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for (i, hostname) in ["laptop:ellie", "laptop:root", "desktop:ellie"]
+            .into_iter()
+            .enumerate()
+        {
+            let mut h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc() + time::Duration::seconds(i as i64))
+                .command(format!("echo {hostname}"))
+                .cwd("/home/ellie")
+                .build()
+                .into();
+            h.hostname = hostname.to_string();
+
+            db.save(&h).await.unwrap();
+        }
+
+        assert_eq!(
+            db.hosts().await.unwrap(),
+            vec!["desktop:ellie", "laptop:root", "laptop:ellie"]
+        );
+
+        let context = |hostname: &str| Context {
+            hostname: hostname.to_string(),
+            namespace: String::new(),
+            session: "beepboopiamasession".to_string(),
+            cwd: "/home/ellie".to_string(),
+            host_id: "test-host".to_string(),
+            git_root: None,
+            pane: None,
+            container: None,
+        };
+
+        let search = |hostname: &'static str| {
+            let db = db.clone();
+            async move {
+                db.search(
+                    SearchMode::Fuzzy,
+                    FilterMode::Host,
+                    &context(hostname),
+                    "echo",
+                    OptFilters::default(),
+                )
+                .await
+                .unwrap()
+                .len()
+            }
+        };
+
+        // a bare host matches any user on it
+        assert_eq!(search("laptop").await, 2);
+        assert_eq!(search("Laptop:root").await, 1);
+
+        let listed = db
+            .list(&[FilterMode::Host], &context("laptop"), None, false, false)
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 2);
+
+        assert!(host_matches("laptop", "laptop:ellie"));
+        assert!(host_matches("laptop:ellie", "LAPTOP:ellie"));
+        assert!(!host_matches("laptop", "laptop2:ellie"));
+        assert!(!host_matches("laptop:root", "laptop:ellie"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_namespace() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for (i, namespace) in [
+            "",
+            "devcontainer:atuin",
+            "devcontainer:atuin",
+            "docker:3f2a",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc() + time::Duration::seconds(i as i64))
+                .command(format!("echo {i}"))
+                .cwd("/home/ellie")
+                .build()
+                .into();
+            h.namespace = namespace.to_string();
+
+            db.save(&h).await.unwrap();
+        }
+
+        let context = |namespace: &str| Context {
+            hostname: "test:host".to_string(),
+            namespace: namespace.to_string(),
+            session: "beepboopiamasession".to_string(),
+            cwd: "/home/ellie".to_string(),
+            host_id: "test-host".to_string(),
+            git_root: None,
+            pane: None,
+            container: None,
+        };
+
+        for (namespace, expected) in [("", 1), ("devcontainer:atuin", 2), ("docker:3f2a", 1)] {
+            let found = db
+                .search(
+                    SearchMode::Fuzzy,
+                    FilterMode::Namespace,
+                    &context(namespace),
+                    "echo",
+                    OptFilters::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(found.len(), expected, "namespace {namespace:?}");
+            assert!(found.iter().all(|h| h.namespace == namespace));
+        }
+
+        // and from anywhere, with --namespace
+        let found = db
+            .search(
+                SearchMode::Fuzzy,
+                FilterMode::Global,
+                &context(""),
+                "echo",
+                OptFilters {
+                    namespace: Some("docker:3f2a".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_env() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for (i, profile) in [None, Some("dev"), Some("prod")].into_iter().enumerate() {
+            let mut h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc() + time::Duration::seconds(i as i64))
+                .command(format!("aws s3 ls {i}"))
+                .cwd("/home/ellie")
+                .build()
+                .into();
+            h.env = profile
+                .map(|p| ("AWS_PROFILE".to_string(), p.to_string()))
+                .into_iter()
+                .collect();
+
+            db.save(&h).await.unwrap();
+        }
+
+        let search = |env: Vec<(&str, &str)>| {
+            let db = db.clone();
+            let env = env
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+
+            async move {
+                db.search(
+                    SearchMode::Fuzzy,
+                    FilterMode::Global,
+                    &Context {
+                        hostname: "test:host".to_string(),
+                        namespace: String::new(),
+                        session: "beepboopiamasession".to_string(),
+                        cwd: "/home/ellie".to_string(),
+                        host_id: "test-host".to_string(),
+                        git_root: None,
+                        pane: None,
+                        container: None,
+                    },
+                    "aws",
+                    OptFilters {
+                        env,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        assert_eq!(search(vec![]).await.len(), 3);
+
+        let prod = search(vec![("AWS_PROFILE", "prod")]).await;
+        assert_eq!(prod.len(), 1);
+        assert_eq!(prod[0].command, "aws s3 ls 2");
+        assert_eq!(prod[0].env["AWS_PROFILE"], "prod");
+
+        assert!(search(vec![("AWS_PROFILE", "staging")]).await.is_empty());
+        assert!(search(vec![("KUBECONFIG", "prod")]).await.is_empty());
+
+        let mut filters = OptFilters::default();
+        assert_eq!(
+            take_query_filters("aws env:AWS_PROFILE=dev", &mut filters),
+            "aws"
+        );
+        assert_eq!(
+            filters.env,
+            vec![("AWS_PROFILE".to_string(), "dev".to_string())]
+        );
+        assert_eq!(take_query_filters("env:", &mut filters), "env:");
+
+        assert_search_commands(
+            &db,
+            SearchMode::Fuzzy,
+            FilterMode::Global,
+            "aws env:AWS_PROFILE=dev",
+            vec!["aws s3 ls 1"],
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_branch() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for (cmd, branch) in [
+            ("cargo test", "main"),
+            ("cargo build", "fix/sync"),
+            ("ls", ""),
+        ] {
+            let mut h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc())
+                .command(cmd)
+                .cwd("/home/ellie")
+                .build()
+                .into();
+            h.branch = branch.to_string();
+
+            db.save(&h).await.unwrap();
+        }
+
+        let mut filters = OptFilters::default();
+        assert_eq!(
+            take_query_filters("branch:fix/sync cargo", &mut filters),
+            "cargo"
+        );
+        assert_eq!(filters.branch.as_deref(), Some("fix/sync"));
+
+        assert_search_commands(
+            &db,
+            SearchMode::FullText,
+            FilterMode::Global,
+            "cargo branch:fix/sync",
+            vec!["cargo build"],
+        )
+        .await;
+        assert_search_commands(
+            &db,
+            SearchMode::Prefix,
+            FilterMode::Global,
+            "branch:main",
+            vec!["cargo test"],
+        )
+        .await;
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Global, "branch:gone", 0)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_starred() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for cmd in ["cargo test", "cargo build", "cargo test"] {
+            let h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc())
+                .command(cmd)
+                .cwd("/home/ellie")
+                .build()
+                .into();
+
+            ids.push(h.id.clone());
+            db.save(&h).await.unwrap();
+        }
+
+        assert_search_eq(&db, SearchMode::Fuzzy, FilterMode::Starred, "", 0)
+            .await
+            .unwrap();
+
+        // starring one run of a command is enough to find it
+        db.star(&ids[0..1], true).await.unwrap();
+        assert_search_commands(
+            &db,
+            SearchMode::Prefix,
+            FilterMode::Starred,
+            "cargo",
+            vec!["cargo test"],
+        )
+        .await;
+        assert_eq!(db.starred().await.unwrap().len(), 1);
+
+        db.replace_starred(&ids[1..2]).await.unwrap();
+        assert_search_commands(
+            &db,
+            SearchMode::FullText,
+            FilterMode::Starred,
+            "cargo",
+            vec!["cargo build"],
+        )
+        .await;
+
+        db.star(&ids[1..2], false).await.unwrap();
+        assert!(db.starred().await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_pane() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for (cmd, key, pane) in [
+            ("make", TMUX_PANE, "4821:%3"),
+            ("make test", ZELLIJ_PANE, "work:3"),
+            ("make clean", TMUX_PANE, "4821:%4"),
+        ] {
+            let mut h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc())
+                .command(cmd)
+                .cwd("/home/ellie")
+                .build()
+                .into();
+            h.env.insert(key.to_string(), pane.to_string());
+
+            db.save(&h).await.unwrap();
+        }
+
+        let search = |pane: Option<(&'static str, &str)>| {
+            let db = db.clone();
+            let pane = pane.map(|(key, pane)| (key, pane.to_string()));
+
+            async move {
+                db.search(
+                    SearchMode::Prefix,
+                    FilterMode::Pane,
+                    &Context {
+                        hostname: "test:host".to_string(),
+                        namespace: String::new(),
+                        session: "beepboopiamasession".to_string(),
+                        cwd: "/home/ellie".to_string(),
+                        host_id: "test-host".to_string(),
+                        git_root: None,
+                        pane,
+                        container: None,
+                    },
+                    "make",
+                    OptFilters::default(),
+                )
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|h| h.command)
+                .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(search(Some((TMUX_PANE, "4821:%3"))).await, vec!["make"]);
+        assert_eq!(
+            search(Some((ZELLIJ_PANE, "work:3"))).await,
+            vec!["make test"]
+        );
+        // the same id from another multiplexer is another pane
+        assert!(search(Some((WEZTERM_PANE, "work:3"))).await.is_empty());
+        assert!(search(None).await.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_container() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for (cmd, env) in [
+            (
+                "gdb ./core",
+                vec![(CONTAINER_ID, "0123456789ab"), (CONTAINER_IMAGE, "debug")],
+            ),
+            (
+                "apt install gdb",
+                vec![(CONTAINER_ID, "ba9876543210"), (CONTAINER_IMAGE, "debug")],
+            ),
+            ("cargo test", vec![(CONTAINER_ID, "fedcba987654")]),
+            ("ls", vec![]),
+        ] {
+            let mut h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc())
+                .command(cmd)
+                .cwd("/home/ellie")
+                .build()
+                .into();
+            h.env = env
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+
+            db.save(&h).await.unwrap();
+        }
+
+        let search = |container: Option<(&'static str, &str)>| {
+            let db = db.clone();
+            let container = container.map(|(key, value)| (key, value.to_string()));
+
+            async move {
+                let mut commands = db
+                    .search(
+                        SearchMode::FullText,
+                        FilterMode::Container,
+                        &Context {
+                            hostname: "test:host".to_string(),
+                            namespace: String::new(),
+                            session: "beepboopiamasession".to_string(),
+                            cwd: "/home/ellie".to_string(),
+                            host_id: "test-host".to_string(),
+                            git_root: None,
+                            pane: None,
+                            container,
+                        },
+                        "",
+                        OptFilters::default(),
+                    )
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|h| h.command)
+                    .collect::<Vec<_>>();
+
+                commands.sort();
+                commands
+            }
+        };
+
+        // every container of the image, not just this one
+        assert_eq!(
+            search(Some((CONTAINER_IMAGE, "debug"))).await,
+            vec!["apt install gdb", "gdb ./core"]
+        );
+        assert_eq!(
+            search(Some((CONTAINER_ID, "fedcba987654"))).await,
+            vec!["cargo test"]
+        );
+        // from the host, any container
+        assert_eq!(
+            search(None).await,
+            vec!["apt install gdb", "cargo test", "gdb ./core"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_tags() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for cmd in ["kubectl apply", "kubectl rollout undo", "ls"] {
+            let h: History = History::capture()
+                .timestamp(OffsetDateTime::now_utc())
+                .command(cmd)
+                .cwd("/home/ellie")
+                .build()
+                .into();
+
+            ids.push(h.id.clone());
+            db.save(&h).await.unwrap();
+        }
+
+        db.tag(&ids[0], "deploy", true).await.unwrap();
+        db.tag(&ids[1], "deploy", true).await.unwrap();
+        db.tag(&ids[1], "oops", true).await.unwrap();
+
+        let mut filters = OptFilters::default();
+        assert_eq!(
+            take_query_filters("tag:deploy kubectl tag:oops", &mut filters),
+            "kubectl"
+        );
+        assert_eq!(filters.tags, vec!["deploy", "oops"]);
+
+        assert_search_commands(
+            &db,
+            SearchMode::Fuzzy,
+            FilterMode::Global,
+            "tag:deploy tag:oops",
+            vec!["kubectl rollout undo"],
+        )
+        .await;
+        assert_search_eq(
+            &db,
+            SearchMode::Prefix,
+            FilterMode::Global,
+            "tag:deploy kub",
+            2,
+        )
+        .await
+        .unwrap();
+
+        db.tag(&ids[0], "deploy", false).await.unwrap();
+        db.note(&ids[1], "when the canary fails").await.unwrap();
+
+        let (tags, note) = db.annotations(&ids[1]).await.unwrap();
+        assert_eq!(tags, vec!["deploy", "oops"]);
+        assert_eq!(note.as_deref(), Some("when the canary fails"));
+        assert_eq!(db.tagged("deploy").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_top_commands() {
+        let mut db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for cmd in [
+            "git status",
+            "git status",
+            "git push",
+            "ls",
+            "ls",
+            " ls",
+            "cargo build",
+        ] {
+            new_history_item(&mut db, cmd).await.unwrap();
+        }
+
+        let mut cancelled: History = History::capture()
+            .timestamp(OffsetDateTime::now_utc())
+            .command("git push --force")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+        cancelled.exit = CANCELLED_EXIT;
+        cancelled.duration = 0;
+        db.save(&cancelled).await.unwrap();
+
+        assert_eq!(db.cancelled_count(None).await.unwrap(), 1);
+
+        let top = db.top_commands(None, None, false).await.unwrap();
+        assert_eq!(
+            top,
+            vec![
+                ("ls".to_string(), 3),
+                ("git status".to_string(), 2),
+                ("cargo build".to_string(), 1),
+                ("git push".to_string(), 1),
+            ]
+        );
+
+        let top = db.top_commands(None, Some(2), true).await.unwrap();
+        assert_eq!(top, vec![("git".to_string(), 3), ("ls".to_string(), 3)]);
+
+        let now = OffsetDateTime::now_utc();
+        let top = db
+            .top_commands(
+                Some(now - time::Duration::days(2)..now - time::Duration::days(1)),
+                None,
+                true,
+            )
+            .await
+            .unwrap();
+        assert!(top.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_command_stats() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        // what the triggers have kept, against counting it all again
+        async fn assert_totals(db: &Sqlite) {
+            let counted = "select command, count(1), max(timestamp), sum(max(duration, 0)),
+                    sum(duration >= 0), sum(exit > 0)
+                from history where deleted_at is null and exit != -2
+                group by command order by command";
+            let kept = "select command, runs, last_run, total_duration, timed_runs, failures
+                from command_stats order by command";
+
+            type Totals = Vec<(String, i64, i64, i64, i64, i64)>;
+            let counted: Totals = sqlx::query_as(counted).fetch_all(&db.pool).await.unwrap();
+            let kept: Totals = sqlx::query_as(kept).fetch_all(&db.pool).await.unwrap();
+
+            assert_eq!(kept, counted);
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let mut history = Vec::new();
+
+        for (minute, command) in ["cargo build", "cargo build", "ls", "cargo test"]
+            .into_iter()
+            .enumerate()
+        {
+            let h: History = History::capture()
+                .timestamp(now - time::Duration::minutes(10 - minute as i64))
+                .command(command)
+                .cwd("/src")
+                .build()
+                .into();
+            db.save(&h).await.unwrap();
+            history.push(h);
+        }
+
+        // started, but not yet ended
+        assert_totals(&db).await;
+        assert_eq!(
+            db.top_commands(None, None, false).await.unwrap()[0],
+            ("cargo build".to_string(), 2)
+        );
+
+        for (h, exit) in history.iter_mut().zip([0, 101, 0, 1]) {
+            h.exit = exit;
+            h.duration = 1000;
+            db.update(h).await.unwrap();
+        }
+        assert_totals(&db).await;
+
+        history[3].exit = CANCELLED_EXIT;
+        db.update(&history[3]).await.unwrap();
+        assert_totals(&db).await;
+
+        // the latest run is deleted, so the last run goes back to the one before
+        db.delete(history[1].id.clone()).await.unwrap();
+        assert_totals(&db).await;
+        let (last_run,): (i64,) =
+            sqlx::query_as("select last_run from command_stats where command = 'cargo build'")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(last_run, history[0].timestamp.unix_timestamp_nanos() as i64);
+
+        db.delete(history[2].id.clone()).await.unwrap();
+        db.purge().await.unwrap();
+        assert_totals(&db).await;
+        assert_eq!(
+            db.top_commands(None, None, false).await.unwrap(),
+            vec![("cargo build".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_prefix_suggestion() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc();
+
+        // repeats are a minute apart, as history is unique by timestamp, cwd and command
+        for (minute, (days_ago, command, cwd)) in [
+            (30, "git push --force", "/src"),
+            (30, "git push --force", "/src"),
+            (30, "git push --force", "/src"),
+            (1, "git pull", "/home"),
+            (1, "git pull", "/home"),
+            (0, "git push", "/src"),
+            (3, "git puss", "/src"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let h: History = History::import()
+                .timestamp(
+                    now - time::Duration::days(days_ago)
+                        - time::Duration::minutes(minute as i64 + 1),
+                )
+                .command(command)
+                .cwd(cwd)
+                .build()
+                .into();
+            db.save(&h).await.unwrap();
+        }
+
+        let mut context = Context {
+            hostname: "test:host".to_string(),
+            namespace: String::new(),
+            session: "session".to_string(),
+            cwd: "/home".to_string(),
+            host_id: "test-host".to_string(),
+            git_root: None,
+            pane: None,
+            container: None,
+        };
+
+        // recent runs beat older ones, however many
+        let suggestion = db.prefix_suggestion("git pu", &context).await.unwrap();
+        assert_eq!(suggestion.as_deref(), Some("git pull"));
+
+        let suggestion = db.prefix_suggestion("git push", &context).await.unwrap();
+        assert_eq!(suggestion.as_deref(), Some("git push --force"));
+
+        for prefix in ["git push --force", "", "Git"] {
+            assert_eq!(db.prefix_suggestion(prefix, &context).await.unwrap(), None);
+        }
+
+        // and the current directory counts for more
+        context.cwd = "/src".to_string();
+        let suggestion = db.prefix_suggestion("git pu", &context).await.unwrap();
+        assert_eq!(suggestion.as_deref(), Some("git push"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grouped_stats() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        // Friday 2024-03-01 to Monday 2024-03-04, from 23:00 UTC
+        for (day, minute, command, cwd, exit, duration) in [
+            (1, 0, "ls", "/home", 0, 100),
+            (1, 1, "ls", "/home", 0, 300),
+            (2, 0, "cargo build", "/src", 101, 1000),
+            (4, 0, "ls", "/src", 0, -1),
+        ] {
+            let h: History = History::import()
+                .timestamp(
+                    time::macros::datetime!(2024-03-01 23:00 UTC)
+                        + time::Duration::days(day - 1)
+                        + time::Duration::minutes(minute),
+                )
+                .command(command)
+                .cwd(cwd)
+                .exit(exit)
+                .duration(duration)
+                .hostname("laptop:ellie")
+                .build()
+                .into();
+            db.save(&h).await.unwrap();
+        }
+
+        let utc = UtcOffset::UTC;
+
+        let days = db.grouped_stats(None, StatsGroup::Day, utc).await.unwrap();
+        assert_eq!(
+            days,
+            vec![
+                GroupStats {
+                    group: "2024-03-01".to_string(),
+                    commands: 2,
+                    unique_commands: 1,
+                    failed: 0,
+                    average_duration: Some(200),
+                },
+                GroupStats {
+                    group: "2024-03-02".to_string(),
+                    commands: 1,
+                    unique_commands: 1,
+                    failed: 1,
+                    average_duration: Some(1000),
+                },
+                GroupStats {
+                    group: "2024-03-04".to_string(),
+                    commands: 1,
+                    unique_commands: 1,
+                    failed: 0,
+                    average_duration: None,
+                },
+            ]
+        );
+
+        // an hour ahead, everything is a day later
+        let ahead = UtcOffset::from_hms(1, 0, 0).unwrap();
+        let days = db
+            .grouped_stats(None, StatsGroup::Day, ahead)
+            .await
+            .unwrap();
+        assert_eq!(days[0].group, "2024-03-02");
+
+        let weeks = db.grouped_stats(None, StatsGroup::Week, utc).await.unwrap();
+        let weeks: Vec<_> = weeks
+            .iter()
+            .map(|w| (w.group.as_str(), w.commands))
+            .collect();
+        assert_eq!(weeks, [("2024-02-26", 3), ("2024-03-04", 1)]);
+
+        let dirs = db.grouped_stats(None, StatsGroup::Dir, utc).await.unwrap();
+        let dirs: Vec<_> = dirs
+            .iter()
+            .map(|d| (d.group.as_str(), d.commands))
+            .collect();
+        assert_eq!(dirs, [("/home", 2), ("/src", 2)]);
+
+        let exits = db.grouped_stats(None, StatsGroup::Exit, utc).await.unwrap();
+        let exits: Vec<_> = exits
+            .iter()
+            .map(|e| (e.group.as_str(), e.commands))
+            .collect();
+        assert_eq!(exits, [("0", 3), ("101", 1)]);
+
+        let range = time::macros::datetime!(2024-03-02 00:00 UTC)
+            ..time::macros::datetime!(2024-03-03 00:00 UTC);
+        let hosts = db
+            .grouped_stats(Some(range), StatsGroup::Host, utc)
+            .await
+            .unwrap();
+        let hosts: Vec<_> = hosts
+            .iter()
+            .map(|h| (h.group.as_str(), h.commands))
+            .collect();
+        assert_eq!(hosts, [("laptop:ellie", 1)]);
+
+        let all = db.daily_counts(None, None, utc).await.unwrap();
+        assert_eq!(
+            all,
+            [
+                ("2024-03-01".to_string(), 2),
+                ("2024-03-02".to_string(), 1),
+                ("2024-03-04".to_string(), 1),
+            ]
+        );
+
+        let ls = db.daily_counts(None, Some("ls"), ahead).await.unwrap();
+        assert_eq!(
+            ls,
+            [("2024-03-02".to_string(), 2), ("2024-03-05".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delete_tombstone() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let h: History = History::capture()
+            .timestamp(OffsetDateTime::now_utc())
+            .command("rm -rf secrets")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+
+        // deleted before we ever saw it, such as when the delete syncs first
+        db.delete(h.id.clone()).await.unwrap();
+        db.save(&h).await.unwrap();
+
+        assert_eq!(db.history_count(false).await.unwrap(), 0);
+
+        let deleted = db.deleted().await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].id, h.id);
+        assert_ne!(deleted[0].command, h.command);
+
+        db.purge().await.unwrap();
+        assert!(db.deleted().await.unwrap().is_empty());
+        assert_eq!(db.history_count(true).await.unwrap(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_expired() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let now = OffsetDateTime::now_utc();
+
+        for days in [400, 200, 100, 10, 1] {
+            let h: History = History::capture()
+                .timestamp(now - time::Duration::days(days))
+                .command(format!("echo {days}"))
+                .cwd("/home/ellie")
+                .build()
+                .into();
+
+            db.save(&h).await.unwrap();
+        }
+
+        let commands = |h: Vec<History>| h.into_iter().map(|h| h.command).collect::<Vec<_>>();
+
+        assert!(db.expired(None, None).await.unwrap().is_empty());
+
+        let old = db
+            .expired(Some(now - time::Duration::days(365)), None)
+            .await
+            .unwrap();
+        assert_eq!(commands(old), vec!["echo 400"]);
+
+        let extra = db.expired(None, Some(2)).await.unwrap();
+        assert_eq!(commands(extra), vec!["echo 400", "echo 200", "echo 100"]);
+
+        let both = db
+            .expired(Some(now - time::Duration::days(150)), Some(4))
+            .await
+            .unwrap();
+        assert_eq!(commands(both), vec!["echo 400", "echo 200"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_duplicates() {
+        let mut db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        for cmd in ["ls", "ls", "git status", "ls"] {
+            new_history_item(&mut db, cmd).await.unwrap();
+        }
+
+        // the same command, but somewhere else
+        let mut elsewhere: History = History::capture()
+            .timestamp(OffsetDateTime::now_utc())
+            .command("ls")
+            .cwd("/tmp")
+            .build()
+            .into();
+        elsewhere.session = "beep boop".to_string();
+        db.save(&elsewhere).await.unwrap();
+
+        let dupes = db.duplicates().await.unwrap();
+        assert_eq!(dupes.len(), 2);
+        assert!(dupes
+            .iter()
+            .all(|h| h.command == "ls" && h.cwd == "/home/ellie"));
+
+        let ids: Vec<_> = dupes.into_iter().map(|h| h.id).collect();
+        db.delete_bulk(&ids).await.unwrap();
+        db.vacuum().await.unwrap();
+
+        assert!(db.duplicates().await.unwrap().is_empty());
+        assert_eq!(db.history_count(false).await.unwrap(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_import_again() {
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let imported = |command: &str, secs: i64| -> History {
+            History::import()
+                .timestamp(OffsetDateTime::from_unix_timestamp(secs).unwrap())
+                .command(command)
+                .hostname("laptop:ellie")
+                .build()
+                .into()
+        };
+
+        let first = vec![imported("ls", 1), imported("git status", 2)];
+        assert_eq!(db.save_bulk(&first).await.unwrap(), 2);
+
+        // the same file again, with more history since
+        let second = vec![
+            imported("ls", 1),
+            imported("git status", 2),
+            imported("cargo test", 3),
+        ];
+        assert_eq!(second[0].id, first[0].id);
+        assert_ne!(second[0].id, second[1].id);
+
+        assert_eq!(db.save_bulk(&second).await.unwrap(), 1);
+        assert_eq!(db.history_count(false).await.unwrap(), 3);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_search_bench_dupes() {
         let context = Context {
             hostname: "test:host".to_string(),
+            namespace: String::new(),
             session: "beepboopiamasession".to_string(),
             cwd: "/home/ellie".to_string(),
             host_id: "test-host".to_string(),
             git_root: None,
+            pane: None,
+            container: None,
         };
 
         let mut db = Sqlite::new("sqlite::memory:", test_local_timeout())
@@ -1115,6 +2854,10 @@ trait SqlBuilderExt {
         glob: bool,
         is_or: bool,
     ) -> &mut Self;
+
+    fn fts_condition<T: ToString>(&mut self, term: T, is_or: bool) -> &mut Self;
+
+    fn host_condition(&mut self, hostname: &str) -> &mut Self;
 }
 
 impl SqlBuilderExt for SqlBuilder {
@@ -1144,4 +2887,30 @@ impl SqlBuilderExt for SqlBuilder {
             self.and_where(cond)
         }
     }
+
+    /// match a term against the history_fts index
+    fn fts_condition<T: ToString>(&mut self, term: T, is_or: bool) -> &mut Self {
+        // quote the term as an fts5 string, so none of it is read as query syntax
+        let term = format!("\"{}\"", term.to_string().replace('"', "\"\""));
+        let cond = format!(
+            "rowid IN (SELECT rowid FROM history_fts WHERE history_fts MATCH '{}')",
+            esc(term)
+        );
+        if is_or {
+            self.or_where(cond)
+        } else {
+            self.and_where(cond)
+        }
+    }
+
+    /// match a full "host:user" exactly, or any user on a bare "host"
+    fn host_condition(&mut self, hostname: &str) -> &mut Self {
+        let hostname = hostname.to_lowercase();
+
+        if hostname.contains(':') {
+            self.and_where_eq("lower(hostname)", quote(hostname))
+        } else {
+            self.and_where_like_left("lower(hostname)", format!("{hostname}:"))
+        }
+    }
 }
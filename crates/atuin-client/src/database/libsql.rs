@@ -0,0 +1,751 @@
+// History kept in a libSQL database, such as one hosted by Turso, rather than a local file.
+//
+// This runs the same SQL as the SQLite backend, from queries.rs, and the same migrations. They're
+// recorded in the same table sqlx keeps them in, so a database file uploaded from a local
+// history.db carries on from where it was.
+//
+// libSQL has no regexp function, so searching with an r/.../ term is an error.
+
+use std::{ops::Range, sync::Arc};
+
+use async_trait::async_trait;
+use libsql::{params::IntoParams, Connection, Row, Transaction, Value};
+use sqlx::Result;
+use time::{OffsetDateTime, UtcOffset};
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::{
+    env_json, queries, Context, Database, GroupStats, OptFilters, StatsGroup, StatsQueries,
+    MIGRATOR,
+};
+use crate::{
+    history::{History, HistoryId, HistoryStats},
+    ordering,
+    settings::{FilterMode, SearchMode},
+};
+
+#[derive(Debug, Clone)]
+pub struct LibSql {
+    conn: Connection,
+
+    // Transactions are on the one connection, so only one may be open at a time
+    write: Arc<Mutex<()>>,
+}
+
+impl LibSql {
+    /// Connect to the libSQL database at `url`, and bring its schema up to date
+    pub async fn new(url: &str, auth_token: &str) -> Result<Self> {
+        debug!("opening libsql database at {url}");
+
+        let db = libsql::Builder::new_remote(url.to_string(), auth_token.to_string())
+            .build()
+            .await
+            .map_err(driver)?;
+
+        let db = Self {
+            conn: db.connect().map_err(driver)?,
+            write: Arc::new(Mutex::new(())),
+        };
+
+        db.migrate().await?;
+
+        Ok(db)
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        debug!("running libsql database setup");
+
+        let _write = self.write.lock().await;
+
+        self.conn
+            .execute(
+                "create table if not exists _sqlx_migrations (
+                    version bigint primary key,
+                    description text not null,
+                    installed_on timestamp not null default current_timestamp,
+                    success boolean not null,
+                    checksum blob not null,
+                    execution_time bigint not null
+                )",
+                (),
+            )
+            .await
+            .map_err(driver)?;
+
+        let applied = self
+            .rows("select version from _sqlx_migrations where success", ())
+            .await?
+            .iter()
+            .filter_map(|row| int(row, 0))
+            .collect::<Vec<_>>();
+
+        for migration in MIGRATOR.iter() {
+            if migration.migration_type.is_down_migration() || applied.contains(&migration.version)
+            {
+                continue;
+            }
+
+            debug!("applying migration {}", migration.version);
+
+            let tx = self.conn.transaction().await.map_err(driver)?;
+
+            tx.execute_batch(&migration.sql).await.map_err(driver)?;
+            tx.execute(
+                "insert into _sqlx_migrations(version, description, success, checksum, execution_time)
+                    values(?1, ?2, true, ?3, 0)",
+                (
+                    migration.version,
+                    migration.description.as_ref(),
+                    migration.checksum.to_vec(),
+                ),
+            )
+            .await
+            .map_err(driver)?;
+
+            tx.commit().await.map_err(driver)?;
+        }
+
+        Ok(())
+    }
+
+    async fn rows(&self, sql: &str, params: impl IntoParams) -> Result<Vec<Row>> {
+        let mut rows = self.conn.query(sql, params).await.map_err(driver)?;
+        let mut res = Vec::new();
+
+        while let Some(row) = rows.next().await.map_err(driver)? {
+            res.push(row);
+        }
+
+        Ok(res)
+    }
+
+    async fn history(&self, sql: &str, params: impl IntoParams) -> Result<Vec<History>> {
+        self.rows(sql, params).await?.iter().map(history).collect()
+    }
+
+    async fn history_optional(
+        &self,
+        sql: &str,
+        params: impl IntoParams,
+    ) -> Result<Option<History>> {
+        Ok(self.history(sql, params).await?.into_iter().next())
+    }
+
+    // Rows of (text, integer), as most of the stats are
+    async fn counts(&self, sql: &str, params: impl IntoParams) -> Result<Vec<(String, i64)>> {
+        Ok(self
+            .rows(sql, params)
+            .await?
+            .iter()
+            .map(|row| (text(row, 0).unwrap_or_default(), int(row, 1).unwrap_or(0)))
+            .collect())
+    }
+
+    async fn count(&self, sql: &str, params: impl IntoParams) -> Result<i64> {
+        let rows = self.rows(sql, params).await?;
+
+        Ok(rows.first().and_then(|row| int(row, 0)).unwrap_or(0))
+    }
+
+    // Writes hold on to the guard until their transaction is done
+    async fn begin(&self) -> Result<(MutexGuard<'_, ()>, Transaction)> {
+        let write = self.write.lock().await;
+        let tx = self.conn.transaction().await.map_err(driver)?;
+
+        Ok((write, tx))
+    }
+}
+
+async fn save_raw(tx: &Connection, h: &History) -> Result<u64> {
+    tx.execute(queries::SAVE, history_params(h))
+        .await
+        .map_err(driver)
+}
+
+async fn star_raw(tx: &Connection, id: &HistoryId, starred: bool) -> Result<()> {
+    let query = if starred {
+        queries::STAR
+    } else {
+        queries::UNSTAR
+    };
+
+    tx.execute(query, [id.0.as_str()]).await.map_err(driver)?;
+
+    Ok(())
+}
+
+async fn tag_raw(tx: &Connection, id: &HistoryId, tag: &str, tagged: bool) -> Result<()> {
+    let query = if tagged { queries::TAG } else { queries::UNTAG };
+
+    tx.execute(query, [id.0.as_str(), tag])
+        .await
+        .map_err(driver)?;
+
+    Ok(())
+}
+
+async fn note_raw(tx: &Connection, id: &HistoryId, note: &str) -> Result<()> {
+    if note.is_empty() {
+        tx.execute(queries::REMOVE_NOTE, [id.0.as_str()])
+            .await
+            .map_err(driver)?;
+    } else {
+        tx.execute(queries::NOTE, [id.0.as_str(), note])
+            .await
+            .map_err(driver)?;
+    }
+
+    Ok(())
+}
+
+// The parameters of queries::SAVE and queries::UPDATE
+fn history_params(h: &History) -> Vec<Value> {
+    vec![
+        h.id.0.as_str().into(),
+        (h.timestamp.unix_timestamp_nanos() as i64).into(),
+        h.duration.into(),
+        h.exit.into(),
+        h.command.as_str().into(),
+        h.cwd.as_str().into(),
+        h.session.as_str().into(),
+        h.hostname.as_str().into(),
+        h.deleted_at.map(|t| t.unix_timestamp_nanos() as i64).into(),
+        h.namespace.as_str().into(),
+        h.stdout_bytes.into(),
+        h.stderr_bytes.into(),
+        env_json(h).into(),
+        h.branch.as_str().into(),
+    ]
+}
+
+fn driver(e: libsql::Error) -> sqlx::Error {
+    sqlx::Error::AnyDriverError(Box::new(e))
+}
+
+fn index(row: &Row, name: &str) -> Option<i32> {
+    (0..row.column_count()).find(|&i| row.column_name(i) == Some(name))
+}
+
+// Columns are read by hand, as libsql panics rather than erroring on a value of the wrong type
+
+fn int(row: &Row, idx: i32) -> Option<i64> {
+    match row.get_value(idx).ok()? {
+        Value::Integer(i) => Some(i),
+        Value::Real(f) => Some(f as i64),
+        _ => None,
+    }
+}
+
+fn real(row: &Row, idx: i32) -> Option<f64> {
+    match row.get_value(idx).ok()? {
+        Value::Integer(i) => Some(i as f64),
+        Value::Real(f) => Some(f),
+        _ => None,
+    }
+}
+
+fn text(row: &Row, idx: i32) -> Option<String> {
+    match row.get_value(idx).ok()? {
+        Value::Text(s) => Some(s),
+        Value::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+fn history(row: &Row) -> Result<History> {
+    let column =
+        |name: &str| index(row, name).ok_or_else(|| sqlx::Error::ColumnNotFound(name.to_string()));
+    let get_int = |name: &str| column(name).map(|i| int(row, i));
+    let get_text = |name: &str| column(name).map(|i| text(row, i).unwrap_or_default());
+
+    let timestamp = get_int("timestamp")?.unwrap_or_default();
+    let deleted_at = get_int("deleted_at")?;
+    let env = column("env").map(|i| text(row, i))?;
+
+    Ok(History::from_db()
+        .id(get_text("id")?)
+        .timestamp(
+            OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128)
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+        )
+        .duration(get_int("duration")?.unwrap_or_default())
+        .exit(get_int("exit")?.unwrap_or_default())
+        .command(get_text("command")?)
+        .cwd(get_text("cwd")?)
+        .session(get_text("session")?)
+        .hostname(get_text("hostname")?)
+        .namespace(get_text("namespace")?)
+        .stdout_bytes(get_int("stdout_bytes")?)
+        .stderr_bytes(get_int("stderr_bytes")?)
+        .env(
+            env.and_then(|env| serde_json::from_str(&env).ok())
+                .unwrap_or_default(),
+        )
+        .branch(get_text("branch")?)
+        .deleted_at(
+            deleted_at.and_then(|t| OffsetDateTime::from_unix_timestamp_nanos(t as i128).ok()),
+        )
+        .build()
+        .into())
+}
+
+#[async_trait]
+impl Database for LibSql {
+    async fn save(&self, h: &History) -> Result<()> {
+        self.save_bulk(std::slice::from_ref(h)).await?;
+
+        Ok(())
+    }
+
+    async fn save_bulk(&self, h: &[History]) -> Result<u64> {
+        debug!("saving history to libsql");
+
+        let (_write, tx) = self.begin().await?;
+
+        let mut count = 0;
+
+        for i in h {
+            count += save_raw(&tx, i).await?;
+        }
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(count)
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<History>> {
+        debug!("loading history item {}", id);
+
+        self.history_optional("select * from history where id = ?1", [id])
+            .await
+    }
+
+    async fn update(&self, h: &History) -> Result<()> {
+        debug!("updating libsql history");
+
+        let (_write, tx) = self.begin().await?;
+
+        tx.execute(queries::UPDATE, history_params(h))
+            .await
+            .map_err(driver)?;
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        filters: &[FilterMode],
+        context: &Context,
+        max: Option<usize>,
+        unique: bool,
+        include_deleted: bool,
+    ) -> Result<Vec<History>> {
+        debug!("listing history");
+
+        let query = queries::list(filters, context, max, unique, include_deleted);
+
+        self.history(&query, ()).await
+    }
+
+    async fn range(&self, from: OffsetDateTime, to: OffsetDateTime) -> Result<Vec<History>> {
+        debug!("listing history from {:?} to {:?}", from, to);
+
+        self.history(
+            queries::RANGE,
+            [
+                from.unix_timestamp_nanos() as i64,
+                to.unix_timestamp_nanos() as i64,
+            ],
+        )
+        .await
+    }
+
+    async fn last(&self) -> Result<Option<History>> {
+        self.history_optional(queries::LAST, ()).await
+    }
+
+    async fn before(&self, timestamp: OffsetDateTime, count: i64) -> Result<Vec<History>> {
+        self.history(
+            queries::BEFORE,
+            [timestamp.unix_timestamp_nanos() as i64, count],
+        )
+        .await
+    }
+
+    async fn starred(&self) -> Result<Vec<History>> {
+        self.history(queries::STARRED, ()).await
+    }
+
+    async fn annotations(&self, id: &HistoryId) -> Result<(Vec<String>, Option<String>)> {
+        let tags = self
+            .rows(
+                "select tag from history_tags where id = ?1 order by tag",
+                [id.0.as_str()],
+            )
+            .await?;
+
+        let note = self
+            .rows(
+                "select note from history_notes where id = ?1",
+                [id.0.as_str()],
+            )
+            .await?;
+
+        Ok((
+            tags.iter().filter_map(|row| text(row, 0)).collect(),
+            note.first().and_then(|row| text(row, 0)),
+        ))
+    }
+
+    async fn tagged(&self, tag: &str) -> Result<Vec<History>> {
+        self.history(queries::TAGGED, [tag]).await
+    }
+
+    async fn deleted(&self) -> Result<Vec<History>> {
+        self.history("select * from history where deleted_at is not null", ())
+            .await
+    }
+
+    async fn history_count(&self, include_deleted: bool) -> Result<i64> {
+        let query = if include_deleted {
+            "select count(1) from history"
+        } else {
+            "select count(1) from history where deleted_at is null"
+        };
+
+        self.count(query, ()).await
+    }
+
+    async fn hosts(&self) -> Result<Vec<String>> {
+        Ok(self
+            .rows(queries::HOSTS, ())
+            .await?
+            .iter()
+            .filter_map(|row| text(row, 0))
+            .collect())
+    }
+
+    async fn top_commands(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        limit: Option<usize>,
+        by_prefix: bool,
+    ) -> Result<Vec<(String, i64)>> {
+        let query = queries::top_commands(range, limit, by_prefix);
+
+        self.counts(&query, ()).await
+    }
+
+    async fn grouped_stats(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        group: StatsGroup,
+        offset: UtcOffset,
+    ) -> Result<Vec<GroupStats>> {
+        let query = queries::grouped_stats(range, group, offset);
+
+        Ok(self
+            .rows(&query, ())
+            .await?
+            .iter()
+            .map(|row| GroupStats {
+                group: text(row, 0).unwrap_or_default(),
+                commands: int(row, 1).unwrap_or(0),
+                unique_commands: int(row, 2).unwrap_or(0),
+                failed: int(row, 3).unwrap_or(0),
+                average_duration: int(row, 4),
+            })
+            .collect())
+    }
+
+    async fn daily_counts(
+        &self,
+        range: Option<Range<OffsetDateTime>>,
+        command: Option<&str>,
+        offset: UtcOffset,
+    ) -> Result<Vec<(String, i64)>> {
+        let query = queries::daily_counts(range, command.is_some(), offset);
+
+        match command {
+            Some(command) => self.counts(&query, [command]).await,
+            None => self.counts(&query, ()).await,
+        }
+    }
+
+    async fn cancelled_count(&self, range: Option<Range<OffsetDateTime>>) -> Result<i64> {
+        let query = queries::cancelled_count(range);
+
+        self.count(&query, ()).await
+    }
+
+    async fn search(
+        &self,
+        search_mode: SearchMode,
+        filter: FilterMode,
+        context: &Context,
+        query: &str,
+        filter_options: OptFilters,
+    ) -> Result<Vec<History>> {
+        let (sql, query) = queries::search(search_mode, filter, context, query, filter_options);
+
+        let res = self.history(&sql, ()).await?;
+
+        Ok(ordering::reorder_fuzzy(search_mode, &query, res))
+    }
+
+    async fn prefix_suggestion(&self, prefix: &str, context: &Context) -> Result<Option<String>> {
+        if prefix.is_empty() {
+            return Ok(None);
+        }
+
+        let end = format!("{prefix}\u{10FFFF}");
+        let now = OffsetDateTime::now_utc().unix_timestamp_nanos() as i64;
+
+        let res = self
+            .rows(
+                queries::PREFIX_SUGGESTION,
+                (prefix, end, context.cwd.as_str(), now),
+            )
+            .await?;
+
+        Ok(res.first().and_then(|row| text(row, 0)))
+    }
+
+    async fn query_history(&self, query: &str) -> Result<Vec<History>> {
+        self.history(query, ()).await
+    }
+
+    async fn all_with_count(&self) -> Result<Vec<(History, i32)>> {
+        debug!("listing history");
+
+        let query = queries::all_with_count();
+
+        self.rows(&query, ())
+            .await?
+            .iter()
+            .map(|row| {
+                let count = index(row, "count").and_then(|i| int(row, i)).unwrap_or(0);
+
+                Ok((history(row)?, count as i32))
+            })
+            .collect()
+    }
+
+    async fn delete(&self, id: HistoryId) -> Result<()> {
+        self.delete_bulk(&[id]).await
+    }
+
+    async fn delete_bulk(&self, ids: &[HistoryId]) -> Result<()> {
+        let deleted_at = OffsetDateTime::now_utc().unix_timestamp_nanos() as i64;
+
+        let (_write, tx) = self.begin().await?;
+
+        for id in ids {
+            tx.execute(queries::DELETE, (id.0.as_str(), deleted_at))
+                .await
+                .map_err(driver)?;
+        }
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(())
+    }
+
+    async fn purge(&self) -> Result<()> {
+        let (_write, tx) = self.begin().await?;
+
+        tx.execute(queries::PURGE, ()).await.map_err(driver)?;
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(())
+    }
+
+    async fn star(&self, ids: &[HistoryId], starred: bool) -> Result<()> {
+        let (_write, tx) = self.begin().await?;
+
+        for id in ids {
+            star_raw(&tx, id, starred).await?;
+        }
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(())
+    }
+
+    async fn replace_starred(&self, ids: &[HistoryId]) -> Result<()> {
+        let (_write, tx) = self.begin().await?;
+
+        tx.execute("delete from starred", ())
+            .await
+            .map_err(driver)?;
+
+        for id in ids {
+            star_raw(&tx, id, true).await?;
+        }
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(())
+    }
+
+    async fn tag(&self, id: &HistoryId, tag: &str, tagged: bool) -> Result<()> {
+        let (_write, tx) = self.begin().await?;
+
+        tag_raw(&tx, id, tag, tagged).await?;
+
+        tx.commit().await.map_err(driver)
+    }
+
+    async fn note(&self, id: &HistoryId, note: &str) -> Result<()> {
+        let (_write, tx) = self.begin().await?;
+
+        note_raw(&tx, id, note).await?;
+
+        tx.commit().await.map_err(driver)
+    }
+
+    async fn replace_annotations(
+        &self,
+        tags: Vec<(HistoryId, String)>,
+        notes: Vec<(HistoryId, String)>,
+    ) -> Result<()> {
+        let (_write, tx) = self.begin().await?;
+
+        tx.execute("delete from history_tags", ())
+            .await
+            .map_err(driver)?;
+        tx.execute("delete from history_notes", ())
+            .await
+            .map_err(driver)?;
+
+        for (id, tag) in &tags {
+            tag_raw(&tx, id, tag, true).await?;
+        }
+
+        for (id, note) in &notes {
+            note_raw(&tx, id, note).await?;
+        }
+
+        tx.commit().await.map_err(driver)?;
+
+        Ok(())
+    }
+
+    async fn duplicates(&self) -> Result<Vec<History>> {
+        self.history(queries::DUPLICATES, ()).await
+    }
+
+    async fn expired(
+        &self,
+        before: Option<OffsetDateTime>,
+        keep: Option<usize>,
+    ) -> Result<Vec<History>> {
+        if before.is_none() && keep.is_none() {
+            return Ok(Vec::new());
+        }
+
+        self.history(
+            queries::EXPIRED,
+            [
+                before.map_or(i64::MIN, |t| t.unix_timestamp_nanos() as i64),
+                keep.map_or(-1, |k| k as i64),
+            ],
+        )
+        .await
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        // the server looks after its own storage
+        Ok(())
+    }
+
+    async fn stats(&self, h: &History) -> Result<HistoryStats> {
+        let queries = StatsQueries::new();
+        let timestamp = h.timestamp.unix_timestamp_nanos() as i64;
+        let command = [h.command.as_str()];
+
+        let prev = self
+            .history_optional(&queries.prev, (timestamp, h.session.as_str()))
+            .await?;
+
+        let next = self
+            .history_optional(&queries.next, (timestamp, h.session.as_str()))
+            .await?;
+
+        let total = self.count(&queries.total, command).await?;
+
+        let average = self.rows(&queries.average, command).await?;
+        let average = average.first().and_then(|row| real(row, 0)).unwrap_or(0.0);
+
+        let exits = self
+            .rows(&queries.exits, command)
+            .await?
+            .iter()
+            .map(|row| (int(row, 0).unwrap_or(0), int(row, 1).unwrap_or(0)))
+            .collect();
+
+        let day_of_week = self.counts(&queries.day_of_week, command).await?;
+
+        let duration_over_time = self
+            .rows(&queries.duration_over_time, command)
+            .await?
+            .iter()
+            .map(|row| {
+                (
+                    text(row, 0).unwrap_or_default(),
+                    real(row, 1).unwrap_or(0.0).round() as i64,
+                )
+            })
+            .collect();
+
+        let success_over_time = self
+            .rows(&queries.success_over_time, command)
+            .await?
+            .iter()
+            .map(|row| {
+                (
+                    text(row, 0).unwrap_or_default(),
+                    int(row, 1).unwrap_or(0),
+                    int(row, 2).unwrap_or(0),
+                )
+            })
+            .collect();
+
+        let hour_of_day = self
+            .rows(&queries.hour_of_day, command)
+            .await?
+            .iter()
+            .map(|row| (int(row, 0).unwrap_or(0), int(row, 1).unwrap_or(0)))
+            .collect();
+
+        // the same query as `atuin stats --calendar`, for the 53 weeks it shows
+        let now = OffsetDateTime::now_utc();
+        let calendar = self
+            .daily_counts(
+                Some(now - time::Duration::weeks(53)..now + time::Duration::SECOND),
+                Some(h.command.as_str()),
+                UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC),
+            )
+            .await?;
+
+        let (tags, note) = self.annotations(&h.id).await?;
+
+        Ok(HistoryStats {
+            next,
+            previous: prev,
+            total: total as u64,
+            average_duration: average as u64,
+            exits,
+            day_of_week,
+            duration_over_time,
+            success_over_time,
+            hour_of_day,
+            calendar,
+            tags,
+            note,
+        })
+    }
+}
@@ -0,0 +1,658 @@
+// The SQL that history is kept with. Both the SQLite and libSQL backends run these, so that what
+// a search or stats finds doesn't depend on where the history lives.
+
+use std::{borrow::Cow, ops::Range};
+
+use itertools::Itertools;
+use sql_builder::{bind::Bind, quote, SqlBuilder, SqlName};
+use time::{OffsetDateTime, UtcOffset};
+
+use super::{
+    container_condition, pane_condition, take_query_filters, Context, OptFilters, SqlBuilderExt,
+    StatsGroup, STARRED_CONDITION,
+};
+use crate::{
+    history::CANCELLED_EXIT,
+    settings::{FilterMode, SearchMode},
+};
+
+pub(super) const SAVE: &str =
+    "insert or ignore into history(id, timestamp, duration, exit, command, cwd, session, hostname, deleted_at, namespace, stdout_bytes, stderr_bytes, env, branch)
+        values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)";
+
+pub(super) const UPDATE: &str = "update history
+    set timestamp = ?2, duration = ?3, exit = ?4, command = ?5, cwd = ?6, session = ?7, hostname = ?8, deleted_at = ?9, namespace = ?10,
+        stdout_bytes = ?11, stderr_bytes = ?12, env = ?13, branch = ?14
+    where id = ?1";
+
+// Deleted history is kept as a tombstone, so that a copy of it downloaded later on doesn't
+// bring it back. If we've not seen the history yet, the tombstone is all there is of it.
+// The command is overwritten, so nothing of what was run is left behind.
+pub(super) const DELETE: &str =
+    "insert into history(id, timestamp, duration, exit, command, cwd, session, hostname, deleted_at)
+        values(?1, 0, 0, 0, lower(hex(randomblob(16))), '', '', '', ?2)
+        on conflict(id) do update
+        set command = excluded.command, deleted_at = excluded.deleted_at
+        where deleted_at is null";
+
+pub(super) const PURGE: &str = "delete from history where deleted_at is not null";
+
+pub(super) const STAR: &str = "insert into starred(id) values(?1) on conflict(id) do nothing";
+pub(super) const UNSTAR: &str = "delete from starred where id = ?1";
+
+pub(super) const TAG: &str =
+    "insert into history_tags(id, tag) values(?1, ?2) on conflict(id, tag) do nothing";
+pub(super) const UNTAG: &str = "delete from history_tags where id = ?1 and tag = ?2";
+
+pub(super) const NOTE: &str = "insert into history_notes(id, note) values(?1, ?2)
+    on conflict(id) do update set note = excluded.note";
+pub(super) const REMOVE_NOTE: &str = "delete from history_notes where id = ?1";
+
+pub(super) const RANGE: &str =
+    "select * from history where timestamp >= ?1 and timestamp <= ?2 order by timestamp asc";
+
+pub(super) const LAST: &str =
+    "select * from history where duration >= 0 order by timestamp desc limit 1";
+
+pub(super) const BEFORE: &str =
+    "select * from history where timestamp < ?1 order by timestamp desc limit ?2";
+
+pub(super) const STARRED: &str =
+    "select history.* from history join starred on starred.id = history.id
+        where deleted_at is null order by timestamp desc";
+
+pub(super) const TAGGED: &str =
+    "select history.* from history join history_tags on history_tags.id = history.id
+        where tag = ?1 and deleted_at is null order by timestamp desc";
+
+pub(super) const HOSTS: &str = "select hostname from history
+    where deleted_at is null
+    group by hostname
+    order by max(timestamp) desc";
+
+// This runs on every keypress, so it's a range over a covering index rather than a `like`, which
+// sqlite can't answer from one. Everything starting with the prefix sorts before the prefix
+// followed by the highest codepoint. The deleted and cancelled checks are literals to match the
+// index's, which sqlite won't use for a bound parameter.
+//
+// Each run counts for less the older it is, halving after a week, and double in the current
+// directory.
+pub(super) const PREFIX_SUGGESTION: &str = "select command from history
+    where command > ?1 and command < ?2
+        and deleted_at is null and exit != -2
+    group by command
+    order by sum(
+        (case when cwd = ?3 then 2.0 else 1.0 end)
+        / (1.0 + max(?4 - timestamp, 0) / 604800000000000.0)
+    ) desc, max(timestamp) desc
+    limit 1";
+
+pub(super) const DUPLICATES: &str = "select * from (
+        select *, row_number() over (
+            partition by command, cwd, session
+            order by timestamp desc, id desc
+        ) as run
+        from history
+        where deleted_at is null
+    )
+    where run > 1
+    order by timestamp asc";
+
+// A negative limit is no limit at all, so nothing is expired by count
+pub(super) const EXPIRED: &str = "select * from history
+    where deleted_at is null
+    and (
+        timestamp < ?1
+        or id not in (
+            select id from history
+            where deleted_at is null
+            order by timestamp desc, id desc
+            limit ?2
+        )
+    )
+    order by timestamp asc";
+
+// make a unique list, that only shows the *newest* version of things
+pub(super) fn list(
+    filters: &[FilterMode],
+    context: &Context,
+    max: Option<usize>,
+    unique: bool,
+    include_deleted: bool,
+) -> String {
+    let mut query = SqlBuilder::select_from(SqlName::new("history").alias("h").baquoted());
+    query.field("*").order_desc("timestamp");
+    if !include_deleted {
+        query.and_where_is_null("deleted_at");
+    }
+
+    let git_root = if let Some(git_root) = context.git_root.clone() {
+        git_root.to_str().unwrap_or("/").to_string()
+    } else {
+        context.cwd.clone()
+    };
+    let pane = pane_condition(context);
+    let container = container_condition(context);
+
+    for filter in filters {
+        match filter {
+            // channel history is in a database of its own, so nothing is filtered out here
+            FilterMode::Global | FilterMode::Channel => &mut query,
+            FilterMode::Host => query.host_condition(&context.hostname),
+            FilterMode::Session => query.and_where_eq("session", quote(&context.session)),
+            FilterMode::Directory => query.and_where_eq("cwd", quote(&context.cwd)),
+            FilterMode::Workspace => query.and_where_like_left("cwd", &git_root),
+            FilterMode::Namespace => query.and_where_eq("namespace", quote(&context.namespace)),
+            FilterMode::Starred => query.and_where(STARRED_CONDITION),
+            FilterMode::Pane => query.and_where(&pane),
+            FilterMode::Container => query.and_where(&container),
+        };
+    }
+
+    if unique {
+        query.group_by("command").having("max(timestamp)");
+    }
+
+    if let Some(max) = max {
+        query.limit(max);
+    }
+
+    query.sql().expect("bug in list query. please report")
+}
+
+// Selects (cmd, count)
+pub(super) fn top_commands(
+    range: Option<Range<OffsetDateTime>>,
+    limit: Option<usize>,
+    by_prefix: bool,
+) -> String {
+    let command = if by_prefix {
+        // everything up until the first space
+        "substr(trim(command), 1, instr(trim(command) || ' ', ' ') - 1)"
+    } else {
+        "trim(command)"
+    };
+
+    // All time comes from the totals the triggers keep, which already leave out deleted and
+    // cancelled history. A range has to count history itself.
+    let mut query = SqlBuilder::select_from(if range.is_some() {
+        "history"
+    } else {
+        "command_stats"
+    });
+    query.field(format!("{command} as cmd"));
+
+    if let Some(range) = range {
+        query
+            .field("count(1) as count")
+            .and_where_is_null("deleted_at")
+            .and_where_ne("exit", CANCELLED_EXIT)
+            .and_where_ge("timestamp", range.start.unix_timestamp_nanos() as i64)
+            .and_where_lt("timestamp", range.end.unix_timestamp_nanos() as i64);
+    } else {
+        query.field("sum(runs) as count");
+    }
+
+    query
+        .and_where("trim(command) != ''")
+        .group_by("cmd")
+        .order_desc("count")
+        .order_asc("cmd");
+
+    if let Some(limit) = limit {
+        query.limit(limit);
+    }
+
+    query
+        .sql()
+        .expect("bug in top commands query. please report")
+}
+
+// Selects the columns of GroupStats
+pub(super) fn grouped_stats(
+    range: Option<Range<OffsetDateTime>>,
+    group: StatsGroup,
+    offset: UtcOffset,
+) -> String {
+    // timestamps are in nanoseconds, and days end at midnight where the user is
+    let local = format!(
+        "timestamp / 1000000000 + {}, 'unixepoch'",
+        offset.whole_seconds()
+    );
+    let group_by = match group {
+        StatsGroup::Day => format!("date({local})"),
+        StatsGroup::Week => format!("date({local}, '-6 days', 'weekday 1')"),
+        StatsGroup::Dir => "cwd".to_string(),
+        StatsGroup::Host => "hostname".to_string(),
+        StatsGroup::Exit => "cast(exit as text)".to_string(),
+    };
+
+    let mut query = SqlBuilder::select_from("history");
+    query
+        .field(format!("{group_by} as \"group\""))
+        .field("count(1) as commands")
+        .field("count(distinct trim(command)) as unique_commands")
+        .field("sum(exit > 0) as failed")
+        .field(
+            "cast(avg(case when duration >= 0 then duration end) as integer) as average_duration",
+        )
+        .and_where_is_null("deleted_at")
+        .and_where("trim(command) != ''")
+        .and_where_ne("exit", CANCELLED_EXIT)
+        .group_by("\"group\"");
+
+    match group {
+        StatsGroup::Day | StatsGroup::Week => query.order_asc("\"group\""),
+        _ => query.order_desc("commands").order_asc("\"group\""),
+    };
+
+    if let Some(range) = range {
+        query
+            .and_where_ge("timestamp", range.start.unix_timestamp_nanos() as i64)
+            .and_where_lt("timestamp", range.end.unix_timestamp_nanos() as i64);
+    }
+
+    query
+        .sql()
+        .expect("bug in grouped stats query. please report")
+}
+
+// Selects (day, commands). With `command`, the command is bound to ?1.
+pub(super) fn daily_counts(
+    range: Option<Range<OffsetDateTime>>,
+    command: bool,
+    offset: UtcOffset,
+) -> String {
+    let mut query = SqlBuilder::select_from("history");
+    query
+        .field(format!(
+            "date(timestamp / 1000000000 + {}, 'unixepoch') as day",
+            offset.whole_seconds()
+        ))
+        .field("count(1) as commands")
+        .and_where_is_null("deleted_at")
+        .and_where("trim(command) != ''")
+        .and_where_ne("exit", CANCELLED_EXIT)
+        .group_by("day")
+        .order_asc("day");
+
+    if command {
+        query.and_where("command = ?1");
+    }
+
+    if let Some(range) = range {
+        query
+            .and_where_ge("timestamp", range.start.unix_timestamp_nanos() as i64)
+            .and_where_lt("timestamp", range.end.unix_timestamp_nanos() as i64);
+    }
+
+    query
+        .sql()
+        .expect("bug in daily counts query. please report")
+}
+
+pub(super) fn cancelled_count(range: Option<Range<OffsetDateTime>>) -> String {
+    let mut query = SqlBuilder::select_from("history");
+    query
+        .field("count(1)")
+        .and_where_is_null("deleted_at")
+        .and_where_eq("exit", CANCELLED_EXIT);
+
+    if let Some(range) = range {
+        query
+            .and_where_ge("timestamp", range.start.unix_timestamp_nanos() as i64)
+            .and_where_lt("timestamp", range.end.unix_timestamp_nanos() as i64);
+    }
+
+    query
+        .sql()
+        .expect("bug in cancelled count query. please report")
+}
+
+/// The search query, and what's left of the search once its filters are taken out, for
+/// [`reorder_fuzzy`](crate::ordering::reorder_fuzzy)
+pub(super) fn search(
+    search_mode: SearchMode,
+    filter: FilterMode,
+    context: &Context,
+    query: &str,
+    filter_options: OptFilters,
+) -> (String, String) {
+    let mut filter_options = filter_options;
+    let query = take_query_filters(query, &mut filter_options);
+
+    let mut sql = SqlBuilder::select_from("history");
+
+    sql.group_by("command").having("max(timestamp)");
+
+    if let Some(limit) = filter_options.limit {
+        sql.limit(limit);
+    }
+
+    if let Some(offset) = filter_options.offset {
+        sql.offset(offset);
+    }
+
+    if filter_options.reverse {
+        sql.order_asc("timestamp");
+    } else {
+        sql.order_desc("timestamp");
+    }
+
+    let git_root = if let Some(git_root) = context.git_root.clone() {
+        git_root.to_str().unwrap_or("/").to_string()
+    } else {
+        context.cwd.clone()
+    };
+    let pane = pane_condition(context);
+    let container = container_condition(context);
+
+    match filter {
+        FilterMode::Global | FilterMode::Channel => &mut sql,
+        FilterMode::Host => sql.host_condition(&context.hostname),
+        FilterMode::Session => sql.and_where_eq("session", quote(&context.session)),
+        FilterMode::Directory => sql.and_where_eq("cwd", quote(&context.cwd)),
+        FilterMode::Workspace => sql.and_where_like_left("cwd", git_root),
+        FilterMode::Namespace => sql.and_where_eq("namespace", quote(&context.namespace)),
+        FilterMode::Starred => sql.and_where(STARRED_CONDITION),
+        FilterMode::Pane => sql.and_where(&pane),
+        FilterMode::Container => sql.and_where(&container),
+    };
+
+    let mut regexes = Vec::new();
+    match search_mode {
+        SearchMode::Prefix => sql.and_where_like_left("command", query.replace('*', "%")),
+        _ => {
+            let mut is_or = false;
+            let mut regex = None;
+            for part in query.split_inclusive(' ') {
+                let query_part: Cow<str> = match (&mut regex, part.starts_with("r/")) {
+                    (None, false) => {
+                        if part.trim_end().is_empty() {
+                            continue;
+                        }
+                        Cow::Owned(part.trim_end().replace('*', "%")) // allow wildcard char
+                    }
+                    (None, true) => {
+                        if part[2..].trim_end().ends_with('/') {
+                            let end_pos = part.trim_end().len() - 1;
+                            regexes.push(String::from(&part[2..end_pos]));
+                        } else {
+                            regex = Some(String::from(&part[2..]));
+                        }
+                        continue;
+                    }
+                    (Some(r), _) => {
+                        if part.trim_end().ends_with('/') {
+                            let end_pos = part.trim_end().len() - 1;
+                            r.push_str(&part.trim_end()[..end_pos]);
+                            regexes.push(regex.take().unwrap());
+                        } else {
+                            r.push_str(part);
+                        }
+                        continue;
+                    }
+                };
+
+                // TODO smart case mode could be made configurable like in fzf
+                let (is_glob, glob) = if query_part.contains(char::is_uppercase) {
+                    (true, "*")
+                } else {
+                    (false, "%")
+                };
+
+                let (is_inverse, query_part) = match query_part.strip_prefix('!') {
+                    Some(stripped) => (true, Cow::Borrowed(stripped)),
+                    None => (false, query_part),
+                };
+
+                // Plain fulltext terms can be answered by the FTS index. Trigrams need at
+                // least three characters, and smart case needs a case sensitive GLOB, so
+                // anything else is left to the scan below.
+                if search_mode == SearchMode::FullText
+                    && !is_glob
+                    && !is_inverse
+                    && query_part.chars().count() >= 3
+                    && !query_part.contains('%')
+                    && !query_part.starts_with(['^', '\''])
+                    && !query_part.ends_with('$')
+                {
+                    sql.fts_condition(&query_part, is_or);
+                    is_or = false;
+                    continue;
+                }
+
+                #[allow(clippy::if_same_then_else)]
+                let param = if query_part == "|" {
+                    if !is_or {
+                        is_or = true;
+                        continue;
+                    } else {
+                        format!("{glob}|{glob}")
+                    }
+                } else if let Some(term) = query_part.strip_prefix('^') {
+                    format!("{term}{glob}")
+                } else if let Some(term) = query_part.strip_suffix('$') {
+                    format!("{glob}{term}")
+                } else if let Some(term) = query_part.strip_prefix('\'') {
+                    format!("{glob}{term}{glob}")
+                } else if is_inverse {
+                    format!("{glob}{query_part}{glob}")
+                } else if search_mode == SearchMode::FullText {
+                    format!("{glob}{query_part}{glob}")
+                } else {
+                    query_part.split("").join(glob)
+                };
+
+                sql.fuzzy_condition("command", param, is_inverse, is_glob, is_or);
+                is_or = false;
+            }
+            if let Some(r) = regex {
+                regexes.push(r);
+            }
+
+            &mut sql
+        }
+    };
+
+    for regex in regexes {
+        sql.and_where("command regexp ?".bind(&regex));
+    }
+
+    filter_options
+        .exit
+        .map(|exit| sql.and_where_eq("exit", exit));
+
+    filter_options
+        .exclude_exit
+        .map(|exclude_exit| sql.and_where_ne("exit", exclude_exit));
+
+    filter_options
+        .cwd
+        .map(|cwd| sql.and_where_eq("cwd", quote(cwd)));
+
+    filter_options
+        .exclude_cwd
+        .map(|exclude_cwd| sql.and_where_ne("cwd", quote(exclude_cwd)));
+
+    filter_options
+        .namespace
+        .map(|namespace| sql.and_where_eq("namespace", quote(namespace)));
+
+    filter_options
+        .branch
+        .map(|branch| sql.and_where_eq("branch", quote(branch)));
+
+    for (name, value) in &filter_options.env {
+        let path = format!("$.\"{}\"", name.replace('"', ""));
+        sql.and_where_eq(format!("json_extract(env, {})", quote(path)), quote(value));
+    }
+
+    for tag in &filter_options.tags {
+        sql.and_where(format!(
+            "id in (select id from history_tags where tag = {})",
+            quote(tag)
+        ));
+    }
+
+    filter_options.before.map(|before| {
+        interim::parse_date_string(
+            before.as_str(),
+            OffsetDateTime::now_utc(),
+            interim::Dialect::Uk,
+        )
+        .map(|before| sql.and_where_lt("timestamp", quote(before.unix_timestamp_nanos() as i64)))
+    });
+
+    filter_options.after.map(|after| {
+        interim::parse_date_string(
+            after.as_str(),
+            OffsetDateTime::now_utc(),
+            interim::Dialect::Uk,
+        )
+        .map(|after| sql.and_where_gt("timestamp", quote(after.unix_timestamp_nanos() as i64)))
+    });
+
+    sql.and_where_is_null("deleted_at");
+
+    let sql = sql.sql().expect("bug in search query. please report");
+
+    (sql, query)
+}
+
+// Selects history, with how many times it was run as count
+pub(super) fn all_with_count() -> String {
+    let mut query = SqlBuilder::select_from(SqlName::new("history").alias("h").baquoted());
+
+    query
+        .fields(&[
+            "id",
+            "max(timestamp) as timestamp",
+            "max(duration) as duration",
+            "exit",
+            "command",
+            "deleted_at",
+            "group_concat(cwd, ':') as cwd",
+            "group_concat(session) as session",
+            "group_concat(hostname, ',') as hostname",
+            "group_concat(namespace, ',') as namespace",
+            "stdout_bytes",
+            "stderr_bytes",
+            "env",
+            "group_concat(branch, ',') as branch",
+            "count(*) as count",
+        ])
+        .group_by("command")
+        .group_by("exit")
+        .and_where("deleted_at is null")
+        .order_desc("timestamp");
+
+    query.sql().expect("bug in list query. please report")
+}
+
+/// The queries behind [`Database::stats`](super::Database::stats). `prev` and `next` bind the
+/// timestamp and session, and the rest bind the command.
+pub(super) struct StatsQueries {
+    pub prev: String,
+    pub next: String,
+    pub total: String,
+    pub average: String,
+    pub exits: String,
+    pub day_of_week: String,
+    pub duration_over_time: String,
+    pub success_over_time: String,
+    pub hour_of_day: String,
+}
+
+impl StatsQueries {
+    pub fn new() -> Self {
+        // We select the previous in the session by time
+        let mut prev = SqlBuilder::select_from("history");
+        prev.field("*")
+            .and_where("timestamp < ?1")
+            .and_where("session = ?2")
+            .order_by("timestamp", true)
+            .limit(1);
+
+        let mut next = SqlBuilder::select_from("history");
+        next.field("*")
+            .and_where("timestamp > ?1")
+            .and_where("session = ?2")
+            .order_by("timestamp", false)
+            .limit(1);
+
+        let mut total = SqlBuilder::select_from("history");
+        total.field("count(1)").and_where("command = ?1");
+
+        let mut average = SqlBuilder::select_from("history");
+        average.field("avg(duration)").and_where("command = ?1");
+
+        let mut exits = SqlBuilder::select_from("history");
+        exits
+            .fields(&["exit", "count(1) as count"])
+            .and_where("command = ?1")
+            .group_by("exit");
+
+        // rewrite the following with sqlbuilder
+        let mut day_of_week = SqlBuilder::select_from("history");
+        day_of_week
+            .fields(&[
+                "strftime('%w', ROUND(timestamp / 1000000000), 'unixepoch') AS day_of_week",
+                "count(1) as count",
+            ])
+            .and_where("command = ?1")
+            .group_by("day_of_week");
+
+        // Intentionally format the string with 01 hardcoded. We want the average runtime for the
+        // _entire month_, but will later parse it as a datetime for sorting
+        // Sqlite has no datetime so we cannot do it there, and otherwise sorting will just be a
+        // string sort, which won't be correct.
+        let mut duration_over_time = SqlBuilder::select_from("history");
+        duration_over_time
+            .fields(&[
+                "strftime('01-%m-%Y', ROUND(timestamp / 1000000000), 'unixepoch') AS month_year",
+                "avg(duration) as duration",
+            ])
+            .and_where("command = ?1")
+            .group_by("month_year")
+            .having("duration > 0");
+
+        // Same month keys as the duration, so they can be sorted the same way
+        let mut success_over_time = SqlBuilder::select_from("history");
+        success_over_time
+            .fields(&[
+                "strftime('01-%m-%Y', ROUND(timestamp / 1000000000), 'unixepoch') AS month_year",
+                "sum(exit = 0) as succeeded",
+                "sum(exit > 0) as failed",
+            ])
+            .and_where("command = ?1")
+            .and_where("exit >= 0")
+            .group_by("month_year");
+
+        let mut hour_of_day = SqlBuilder::select_from("history");
+        hour_of_day
+            .fields(&[
+                "cast(strftime('%H', ROUND(timestamp / 1000000000), 'unixepoch', 'localtime') as integer) AS hour",
+                "count(1) as count",
+            ])
+            .and_where("command = ?1")
+            .group_by("hour")
+            .order_asc("hour");
+
+        Self {
+            prev: prev.sql().expect("issue in stats previous query"),
+            next: next.sql().expect("issue in stats next query"),
+            total: total.sql().expect("issue in stats average query"),
+            average: average.sql().expect("issue in stats previous query"),
+            exits: exits.sql().expect("issue in stats exits query"),
+            day_of_week: day_of_week.sql().expect("issue in stats day of week query"),
+            duration_over_time: duration_over_time
+                .sql()
+                .expect("issue in stats duration over time query"),
+            success_over_time: success_over_time
+                .sql()
+                .expect("issue in stats success over time query"),
+            hour_of_day: hour_of_day.sql().expect("issue in stats hour of day query"),
+        }
+    }
+}
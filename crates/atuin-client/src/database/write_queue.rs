@@ -0,0 +1,175 @@
+// All of one process's writes to the history database go through here.
+//
+// Many things in one process want to write at once - the TUI deleting entries, the daemon
+// finishing commands, sync building downloaded history. Rather than have them all race for the
+// sqlite write lock (and occasionally lose with SQLITE_BUSY), writers enqueue their change and a
+// single task applies whatever has queued up in one transaction.
+//
+// This does nothing for separate processes, such as the `atuin history start` and `end` each
+// shell hook runs. Those wait their turn for the lock with WAL and busy_timeout, set up where the
+// pool is opened.
+
+use sqlx::{sqlite::SqlitePool, Result};
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, oneshot};
+
+use super::Sqlite;
+use crate::history::{History, HistoryId};
+
+// How many writes may be waiting before writers start to wait for space
+const QUEUE_DEPTH: usize = 1024;
+
+// The most writes we will apply in a single transaction
+const MAX_BATCH: usize = 256;
+
+#[derive(Debug)]
+pub(crate) enum Write {
+    Save(History),
+    SaveBulk(Vec<History>),
+    Update(History),
+    Delete(Vec<HistoryId>, OffsetDateTime),
+    Purge,
+    Star(Vec<HistoryId>, bool),
+    ReplaceStarred(Vec<HistoryId>),
+    Tag(HistoryId, String, bool),
+    Note(HistoryId, String),
+    ReplaceAnnotations(Vec<(HistoryId, String)>, Vec<(HistoryId, String)>),
+}
+
+#[derive(Debug)]
+struct Job {
+    write: Write,
+    // how many history entries the write saved, for saves
+    reply: oneshot::Sender<Result<u64>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct WriteQueue {
+    tx: mpsc::Sender<Job>,
+}
+
+impl WriteQueue {
+    /// Spawn the writer task for this pool. It exits once every handle to the queue is dropped.
+    pub(crate) fn new(pool: SqlitePool) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+
+        tokio::spawn(writer(pool, rx));
+
+        Self { tx }
+    }
+
+    /// Queue a write, and wait for it to be committed
+    pub(crate) async fn submit(&self, write: Write) -> Result<()> {
+        self.submit_counted(write).await.map(|_| ())
+    }
+
+    /// Queue a write, and wait for it to be committed. Returns how many new history entries it
+    /// saved, as saves skip entries that are already there.
+    pub(crate) async fn submit_counted(&self, write: Write) -> Result<u64> {
+        let (reply, rx) = oneshot::channel();
+
+        self.tx
+            .send(Job { write, reply })
+            .await
+            .map_err(|_| sqlx::Error::PoolClosed)?;
+
+        rx.await.map_err(|_| sqlx::Error::WorkerCrashed)?
+    }
+}
+
+async fn writer(pool: SqlitePool, mut rx: mpsc::Receiver<Job>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+
+    while rx.recv_many(&mut batch, MAX_BATCH).await > 0 {
+        debug!("applying {} queued history writes", batch.len());
+
+        let counts = match apply(&pool, batch.iter().map(|job| &job.write)).await {
+            Ok(counts) => counts,
+            Err(e) if batch.len() == 1 => {
+                let job = batch.pop().unwrap();
+                let _ = job.reply.send(Err(e));
+
+                continue;
+            }
+            Err(_) => {
+                // One of the writes failed and took the whole batch down with it. Retry them one
+                // at a time, so that each error goes back to the writer it belongs to.
+                for job in batch.drain(..) {
+                    let res = apply(&pool, std::iter::once(&job.write))
+                        .await
+                        .map(|counts| counts[0]);
+                    let _ = job.reply.send(res);
+                }
+
+                continue;
+            }
+        };
+
+        for (job, count) in batch.drain(..).zip(counts) {
+            // the writer may have given up waiting, that's fine
+            let _ = job.reply.send(Ok(count));
+        }
+    }
+}
+
+// Returns how many history entries each write saved, in order
+async fn apply(pool: &SqlitePool, writes: impl Iterator<Item = &Write>) -> Result<Vec<u64>> {
+    let mut tx = pool.begin().await?;
+    let mut counts = Vec::new();
+
+    for write in writes {
+        let mut count = 0;
+
+        match write {
+            Write::Save(h) => count = Sqlite::save_raw(&mut tx, h).await?,
+            Write::SaveBulk(h) => {
+                for i in h {
+                    count += Sqlite::save_raw(&mut tx, i).await?;
+                }
+            }
+            Write::Update(h) => Sqlite::update_raw(&mut tx, h).await?,
+            Write::Delete(ids, deleted_at) => {
+                for id in ids {
+                    Sqlite::delete_raw(&mut tx, id, *deleted_at).await?;
+                }
+            }
+            Write::Purge => Sqlite::purge_raw(&mut tx).await?,
+            Write::Star(ids, starred) => {
+                for id in ids {
+                    Sqlite::star_raw(&mut tx, id, *starred).await?;
+                }
+            }
+            Write::ReplaceStarred(ids) => {
+                sqlx::query("delete from starred").execute(&mut *tx).await?;
+
+                for id in ids {
+                    Sqlite::star_raw(&mut tx, id, true).await?;
+                }
+            }
+            Write::Tag(id, tag, tagged) => Sqlite::tag_raw(&mut tx, id, tag, *tagged).await?,
+            Write::Note(id, note) => Sqlite::note_raw(&mut tx, id, note).await?,
+            Write::ReplaceAnnotations(tags, notes) => {
+                sqlx::query("delete from history_tags")
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("delete from history_notes")
+                    .execute(&mut *tx)
+                    .await?;
+
+                for (id, tag) in tags {
+                    Sqlite::tag_raw(&mut tx, id, tag, true).await?;
+                }
+
+                for (id, note) in notes {
+                    Sqlite::note_raw(&mut tx, id, note).await?;
+                }
+            }
+        }
+
+        counts.push(count);
+    }
+
+    tx.commit().await?;
+
+    Ok(counts)
+}
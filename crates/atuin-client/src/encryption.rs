@@ -22,7 +22,14 @@ use rmp::{decode::Bytes, Marker};
 use serde::{Deserialize, Serialize};
 use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime};
 
-use crate::{history::History, settings::Settings};
+use crate::{
+    history::History,
+    record::{
+        compression,
+        encryption::{parse_public_key, set_keyring},
+    },
+    settings::{KeyBackend, Settings},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EncryptedHistory {
@@ -38,35 +45,482 @@ pub fn generate_encoded_key() -> Result<(Key, String)> {
 }
 
 pub fn new_key(settings: &Settings) -> Result<Key> {
-    let path = settings.key_path.as_str();
-    let path = PathBuf::from(path);
-
-    if path.exists() {
+    if key_exists(settings)? {
         bail!("key already exists! cannot overwrite");
     }
 
     let (key, encoded) = generate_encoded_key()?;
-
-    let mut file = fs::File::create(path)?;
-    file.write_all(encoded.as_bytes())?;
+    save_key(settings, &encoded)?;
 
     Ok(key)
 }
 
+/// The key this host writes records with
+#[derive(Debug, Clone, Copy)]
+pub enum HostKey {
+    /// The master key, which can read everything as well as write
+    Master([u8; 32]),
+
+    /// Only the public key, from `atuin key --public`. Records are sealed to it, so this host
+    /// can write them and sync them up, but can't read any back, its own included.
+    Public([u8; 32]),
+}
+
+// Public keys are saved as their PASERK, so they can't be mistaken for the master key
+const PUBLIC_KEY_PREFIX: &str = "k4.public.";
+
+/// Load whichever key this host has. Hosts set up with [`save_public_key`] only get the public
+/// key; everywhere else this is [`load_key`].
+pub fn load_host_key(settings: &Settings) -> Result<HostKey> {
+    if let Some(key) = read_key(settings)? {
+        if key.trim().starts_with(PUBLIC_KEY_PREFIX) {
+            compression::set_enabled(settings.sync.compress);
+            return Ok(HostKey::Public(parse_public_key(&key)?));
+        }
+    }
+
+    Ok(HostKey::Master(load_key(settings)?.into()))
+}
+
+/// Whether this host only has the public key, so it writes records but can't read them
+pub fn is_write_only(settings: &Settings) -> Result<bool> {
+    Ok(read_key(settings)?.is_some_and(|key| key.trim().starts_with(PUBLIC_KEY_PREFIX)))
+}
+
+/// Make this a write-only host, keeping only the public key. There can't be a master key here
+/// already, as that would be thrown away.
+pub fn save_public_key(settings: &Settings, public_key: &str) -> Result<()> {
+    parse_public_key(public_key)?;
+
+    if key_exists(settings)? && !is_write_only(settings)? {
+        bail!(
+            "this host already has the encryption key. Log out first to keep only the public key"
+        );
+    }
+
+    save_key(settings, public_key.trim())
+}
+
 // Loads the secret key, will create + save if it doesn't exist
 pub fn load_key(settings: &Settings) -> Result<Key> {
-    let path = settings.key_path.as_str();
-
-    let key = if PathBuf::from(path).exists() {
-        let key = fs_err::read_to_string(path)?;
-        decode_key(key)?
-    } else {
-        new_key(settings)?
+    let key = match read_key(settings)? {
+        Some(key) if key.trim().starts_with(PUBLIC_KEY_PREFIX) => {
+            bail!("this host only has the public key, so it can write history but not read it")
+        }
+        Some(key) => decode_key(key)?,
+        None => new_key(settings)?,
     };
 
+    // so that records from before the key was last rotated can still be read
+    let previous = previous_keys(settings)?;
+    set_keyring(previous.into_iter().map(Into::into).collect());
+
+    // records are only written once there's a key to write them with
+    compression::set_enabled(settings.sync.compress);
+
     Ok(key)
 }
 
+/// Whether loading the key may ask for a passphrase or a touch every time atuin runs, as the
+/// age backend does when it can't keep the unlocked key for the session
+pub fn unlocks_every_run(settings: &Settings) -> bool {
+    settings.key_backend == KeyBackend::Age && !age::caches(settings)
+}
+
+/// Whether there's a key saved, in whichever backend is configured
+pub fn key_exists(settings: &Settings) -> Result<bool> {
+    Ok(read_key(settings)?.is_some())
+}
+
+/// Read the encoded key, if there is one
+pub fn read_key(settings: &Settings) -> Result<Option<String>> {
+    read_stored(settings, &settings.key_path)
+}
+
+/// Save an encoded key, replacing any that's already there
+pub fn save_key(settings: &Settings, encoded: &str) -> Result<()> {
+    write_stored(settings, &settings.key_path, encoded)
+}
+
+/// Forget the key, and any it replaced. There's no getting them back, unless they're saved
+/// somewhere else.
+pub fn delete_key(settings: &Settings) -> Result<()> {
+    delete_stored(settings, &settings.key_path)?;
+    delete_stored(settings, &previous_keys_path(settings))
+}
+
+/// Keys that have been replaced by a rotation, and are only kept to decrypt older records
+pub fn previous_keys(settings: &Settings) -> Result<Vec<Key>> {
+    let Some(keys) = read_stored(settings, &previous_keys_path(settings))? else {
+        return Ok(Vec::new());
+    };
+
+    keys.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| decode_key(line.to_string()))
+        .collect()
+}
+
+/// Keep a key that's about to be replaced, so records encrypted with it stay readable
+pub fn archive_key(settings: &Settings, key: &Key) -> Result<()> {
+    let mut keys = previous_keys(settings)?;
+
+    if keys.contains(key) {
+        return Ok(());
+    }
+
+    keys.push(*key);
+
+    let encoded = keys.iter().map(encode_key).collect::<Result<Vec<_>>>()?;
+
+    write_stored(settings, &previous_keys_path(settings), &encoded.join("\n"))
+}
+
+// Previous keys live next to the current one, in the same backend
+fn previous_keys_path(settings: &Settings) -> String {
+    format!("{}.previous", settings.key_path)
+}
+
+fn read_stored(settings: &Settings, path: &str) -> Result<Option<String>> {
+    match settings.key_backend {
+        KeyBackend::File => {
+            if PathBuf::from(path).exists() {
+                Ok(Some(fs::read_to_string(path)?))
+            } else {
+                Ok(None)
+            }
+        }
+        KeyBackend::Keychain => keychain::read(path),
+        KeyBackend::Age => age::read(settings, path),
+    }
+}
+
+fn write_stored(settings: &Settings, path: &str, contents: &str) -> Result<()> {
+    match settings.key_backend {
+        KeyBackend::File => {
+            // write it alongside, then move it into place, so the old key is never half replaced
+            let tmp = format!("{path}.tmp");
+
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+
+            fs::rename(tmp, path)?;
+
+            Ok(())
+        }
+        KeyBackend::Keychain => keychain::write(path, contents),
+        KeyBackend::Age => age::write(settings, path, contents),
+    }
+}
+
+fn delete_stored(settings: &Settings, path: &str) -> Result<()> {
+    match settings.key_backend {
+        KeyBackend::File => match fs_err::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        },
+        KeyBackend::Keychain => keychain::delete(path),
+        KeyBackend::Age => age::delete(path),
+    }
+}
+
+// Keys encrypted with the age command line tool, so that any of its plugins can unlock them
+mod age {
+    use std::{
+        collections::HashMap,
+        io::Write,
+        process::{Command, Stdio},
+        sync::{LazyLock, Mutex},
+    };
+
+    use eyre::{bail, ensure, Context, Result};
+
+    use crate::settings::Settings;
+
+    // Unlocking may mean a passphrase or a touch, so only do it once per process
+    static UNLOCKED: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Mutex::default);
+
+    fn expand(path: &str) -> Result<String> {
+        Ok(shellexpand::full(path)?.to_string())
+    }
+
+    // What age writes with --armor. Anything else is taken for a key from the file backend.
+    const ARMOR: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+    /// Whether the unlocked key is kept for the rest of the session, rather than unlocked again
+    /// by every process that needs it
+    pub fn caches(settings: &Settings) -> bool {
+        session::SUPPORTED && settings.age.cache
+    }
+
+    pub fn read(settings: &Settings, path: &str) -> Result<Option<String>> {
+        let path = &expand(path)?;
+
+        if let Some(key) = UNLOCKED.lock().expect("lock poisoned").get(path) {
+            return Ok(Some(key.clone()));
+        }
+
+        let contents = match fs_err::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !contents.trim_start().starts_with(ARMOR) {
+            return migrate(settings, path, &contents).map(Some);
+        }
+
+        let key = match session::get(settings, path) {
+            Some(key) => key,
+            None => {
+                let key = decrypt(settings, path)?;
+                session::set(settings, path, &key);
+                key
+            }
+        };
+
+        UNLOCKED
+            .lock()
+            .expect("lock poisoned")
+            .insert(path.to_string(), key.clone());
+
+        Ok(Some(key))
+    }
+
+    pub fn write(settings: &Settings, path: &str, contents: &str) -> Result<()> {
+        ensure!(
+            !settings.age.recipient.is_empty(),
+            "set age.recipient to save the key with age"
+        );
+
+        let path = &expand(path)?;
+
+        // as with the file backend, the old key is only replaced once the new one is written
+        let tmp = format!("{path}.tmp");
+
+        let mut child = Command::new("age")
+            .args(["--encrypt", "--armor", "--recipient"])
+            .arg(&settings.age.recipient)
+            .arg("--output")
+            .arg(&tmp)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("could not run age. Is it installed?")?;
+
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(contents.as_bytes())?;
+
+        if !child.wait()?.success() {
+            bail!("age could not encrypt the key");
+        }
+
+        fs_err::rename(tmp, path)?;
+
+        session::set(settings, path, contents);
+        UNLOCKED
+            .lock()
+            .expect("lock poisoned")
+            .insert(path.to_string(), contents.to_string());
+
+        Ok(())
+    }
+
+    pub fn delete(path: &str) -> Result<()> {
+        let path = &expand(path)?;
+
+        UNLOCKED.lock().expect("lock poisoned").remove(path);
+        session::delete(path);
+
+        match fs_err::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // Encrypt a file left over from the file backend in place, as the keychain backend moves it
+    // into the keychain, so that switching backends doesn't lose the key
+    fn migrate(settings: &Settings, path: &str, contents: &str) -> Result<String> {
+        for key in contents.lines().filter(|line| !line.trim().is_empty()) {
+            super::decode_key(key.to_string())
+                .with_context(|| format!("{path} does not contain a valid key"))?;
+        }
+
+        write(settings, path, contents.trim_end())?;
+
+        log::info!("encrypted {path} with age");
+
+        Ok(contents.to_string())
+    }
+
+    // age prompts on the terminal itself, so only its output is captured
+    fn decrypt(settings: &Settings, path: &str) -> Result<String> {
+        ensure!(
+            !settings.age.identity.is_empty(),
+            "set age.identity to unlock the key with age"
+        );
+
+        let output = Command::new("age")
+            .args(["--decrypt", "--identity"])
+            .arg(expand(&settings.age.identity)?)
+            .arg(path)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .context("could not run age. Is it installed?")?;
+
+        if !output.status.success() {
+            bail!("age could not unlock the key at {path}");
+        }
+
+        String::from_utf8(output.stdout).context("the unlocked key is not valid utf-8")
+    }
+
+    // The kernel's session keyring lasts until logout, and never touches the disk
+    #[cfg(target_os = "linux")]
+    mod session {
+        use linux_keyutils::{KeyRing, KeyRingIdentifier};
+
+        use crate::settings::Settings;
+
+        pub const SUPPORTED: bool = true;
+
+        fn description(path: &str) -> String {
+            format!("atuin:{path}")
+        }
+
+        fn keyring() -> Option<KeyRing> {
+            KeyRing::from_special_id(KeyRingIdentifier::Session, false).ok()
+        }
+
+        pub fn get(settings: &Settings, path: &str) -> Option<String> {
+            if !settings.age.cache {
+                return None;
+            }
+
+            let key = keyring()?.search(&description(path)).ok()?;
+            String::from_utf8(key.read_to_vec().ok()?).ok()
+        }
+
+        // Caching is best effort. Without it, the key is unlocked again next time.
+        pub fn set(settings: &Settings, path: &str, key: &str) {
+            if !settings.age.cache {
+                return;
+            }
+
+            if let Some(keyring) = keyring() {
+                if let Err(e) = keyring.add_key(&description(path), key) {
+                    log::debug!("could not cache the unlocked key: {e:?}");
+                }
+            }
+        }
+
+        pub fn delete(path: &str) {
+            if let Some(key) = keyring().and_then(|k| k.search(&description(path)).ok()) {
+                let _ = key.invalidate();
+            }
+        }
+    }
+
+    // Nowhere else has a store that's both gone at logout and never written to disk, so each
+    // process unlocks the key again
+    #[cfg(not(target_os = "linux"))]
+    mod session {
+        use crate::settings::Settings;
+
+        pub const SUPPORTED: bool = false;
+
+        pub fn get(_: &Settings, _: &str) -> Option<String> {
+            None
+        }
+
+        pub fn set(_: &Settings, _: &str, _: &str) {}
+
+        pub fn delete(_: &str) {}
+    }
+}
+
+#[cfg(feature = "keychain")]
+mod keychain {
+    use eyre::{Context, Result};
+    use keyring::{Entry, Error};
+
+    const SERVICE: &str = "atuin";
+
+    // Keys are stored against their path, so that setups with more than one config keep more
+    // than one key, just as they would with files
+    fn entry(path: &str) -> Result<Entry> {
+        Entry::new(SERVICE, path).context("could not open the OS keychain")
+    }
+
+    pub fn read(path: &str) -> Result<Option<String>> {
+        match entry(path)?.get_password() {
+            Ok(key) => Ok(Some(key)),
+            Err(Error::NoEntry) => migrate(path),
+            Err(e) => Err(e).context("could not read the key from the OS keychain"),
+        }
+    }
+
+    pub fn write(path: &str, contents: &str) -> Result<()> {
+        entry(path)?
+            .set_password(contents)
+            .context("could not save the key to the OS keychain")
+    }
+
+    pub fn delete(path: &str) -> Result<()> {
+        match entry(path)?.delete_credential() {
+            Ok(()) | Err(Error::NoEntry) => Ok(()),
+            Err(e) => Err(e).context("could not delete the key from the OS keychain"),
+        }
+    }
+
+    // Move a file left over from the file backend into the keychain, so that switching backends
+    // doesn't lose the key
+    fn migrate(path: &str) -> Result<Option<String>> {
+        let contents = match fs_err::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        for key in contents.lines().filter(|line| !line.trim().is_empty()) {
+            super::decode_key(key.to_string())
+                .with_context(|| format!("{path} does not contain a valid key"))?;
+        }
+
+        write(path, contents.trim_end())?;
+        fs_err::remove_file(path)?;
+
+        log::info!("moved {path} into the OS keychain");
+
+        Ok(Some(contents))
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+mod keychain {
+    use eyre::{bail, Result};
+
+    const UNSUPPORTED: &str =
+        "this build of atuin has no keychain support. Build it with --features keychain, or set \
+         key_backend = \"file\" instead";
+
+    pub fn read(_: &str) -> Result<Option<String>> {
+        bail!(UNSUPPORTED)
+    }
+
+    pub fn write(_: &str, _: &str) -> Result<()> {
+        bail!(UNSUPPORTED)
+    }
+
+    pub fn delete(_: &str) -> Result<()> {
+        bail!(UNSUPPORTED)
+    }
+}
+
 pub fn encode_key(key: &Key) -> Result<String> {
     let mut buf = vec![];
     rmp::encode::write_array_len(&mut buf, key.len() as u32)
@@ -253,6 +707,11 @@ fn decode(bytes: &[u8]) -> Result<History> {
         cwd: cwd.to_owned(),
         session: session.to_owned(),
         hostname: hostname.to_owned(),
+        namespace: String::new(),
+        stdout_bytes: None,
+        stderr_bytes: None,
+        env: Default::default(),
+        branch: String::new(),
         deleted_at: deleted_at
             .map(|t| OffsetDateTime::parse(t, &Rfc3339))
             .transpose()?,
@@ -269,9 +728,15 @@ mod test {
     use pretty_assertions::assert_eq;
     use time::{macros::datetime, OffsetDateTime};
 
-    use crate::history::History;
+    use crate::{
+        history::History,
+        settings::{KeyBackend, Settings},
+    };
 
-    use super::{decode, decrypt, encode, encrypt};
+    use super::{
+        archive_key, decode, decrypt, delete_key, encode, encrypt, is_write_only, key_exists,
+        load_host_key, load_key, new_key, previous_keys, save_public_key, HostKey,
+    };
 
     #[test]
     fn test_encrypt_decrypt() {
@@ -287,6 +752,11 @@ mod test {
             .duration(1)
             .session("beep boop".into())
             .hostname("booop".into())
+            .namespace(String::new())
+            .stdout_bytes(None)
+            .stderr_bytes(None)
+            .env(Default::default())
+            .branch(String::new())
             .deleted_at(None)
             .build()
             .into();
@@ -331,6 +801,11 @@ mod test {
             cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
             session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
             hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
             deleted_at: None,
         };
 
@@ -352,6 +827,11 @@ mod test {
             cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
             session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
             hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
             deleted_at: Some(datetime!(2023-05-28 18:35:40.633872 +00:00)),
         };
 
@@ -383,6 +863,11 @@ mod test {
             cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
             session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
             hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
             deleted_at: None,
         };
 
@@ -427,4 +912,66 @@ mod test {
             assert_eq!(decode_key(k.to_owned()).expect(k), key);
         }
     }
+
+    #[test]
+    fn key_file_backend() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-key-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        let settings = Settings {
+            key_path: path.to_string_lossy().into_owned(),
+            key_backend: KeyBackend::File,
+            ..Settings::utc()
+        };
+
+        assert!(!key_exists(&settings).unwrap());
+
+        let key = load_key(&settings).unwrap();
+        assert!(key_exists(&settings).unwrap());
+        assert_eq!(load_key(&settings).unwrap(), key);
+        assert!(new_key(&settings).is_err());
+
+        let old = key;
+        archive_key(&settings, &old).unwrap();
+        archive_key(&settings, &old).unwrap();
+        assert_eq!(previous_keys(&settings).unwrap(), vec![old]);
+
+        delete_key(&settings).unwrap();
+        assert!(!key_exists(&settings).unwrap());
+        assert!(previous_keys(&settings).unwrap().is_empty());
+    }
+
+    #[test]
+    fn public_key_only() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-key-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        let settings = Settings {
+            key_path: path.to_string_lossy().into_owned(),
+            key_backend: KeyBackend::File,
+            ..Settings::utc()
+        };
+
+        let master: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let public = crate::record::encryption::public_key(&master);
+        let expected = crate::record::encryption::parse_public_key(&public).unwrap();
+
+        save_public_key(&settings, &public).unwrap();
+        assert!(is_write_only(&settings).unwrap());
+        assert!(matches!(load_host_key(&settings).unwrap(), HostKey::Public(k) if k == expected));
+
+        // the master key is never made up to replace it
+        assert!(load_key(&settings).is_err());
+
+        delete_key(&settings).unwrap();
+
+        // and a host with the master key keeps it
+        load_key(&settings).unwrap();
+        assert!(save_public_key(&settings, &public).is_err());
+        assert!(!is_write_only(&settings).unwrap());
+
+        delete_key(&settings).unwrap();
+    }
 }
@@ -0,0 +1,249 @@
+use std::io::Write;
+
+use eyre::Result;
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{history::History, settings::ExportFormat};
+
+/// The shape of an exported history entry. Kept separate from `History`, so that exports stay
+/// stable as the database model changes.
+#[derive(Debug, Serialize)]
+struct ExportedHistory<'a> {
+    id: &'a str,
+    #[serde(with = "time::serde::rfc3339")]
+    timestamp: OffsetDateTime,
+    duration: i64,
+    exit: i64,
+    command: &'a str,
+    cwd: &'a str,
+    session: &'a str,
+    hostname: &'a str,
+}
+
+impl<'a> From<&'a History> for ExportedHistory<'a> {
+    fn from(h: &'a History) -> Self {
+        Self {
+            id: h.id.0.as_str(),
+            timestamp: h.timestamp,
+            duration: h.duration,
+            exit: h.exit,
+            command: h.command.as_str(),
+            cwd: h.cwd.as_str(),
+            session: h.session.as_str(),
+            hostname: h.hostname.as_str(),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Write history to `w` in the given format
+pub fn export(w: &mut impl Write, format: ExportFormat, history: &[History]) -> Result<()> {
+    match format {
+        ExportFormat::Json => json(w, history),
+        ExportFormat::Markdown => markdown(w, history),
+        ExportFormat::Csv => csv(w, history),
+    }
+}
+
+fn json(w: &mut impl Write, history: &[History]) -> Result<()> {
+    let entries: Vec<ExportedHistory> = history.iter().map(ExportedHistory::from).collect();
+
+    serde_json::to_writer_pretty(&mut *w, &entries)?;
+    writeln!(w)?;
+
+    Ok(())
+}
+
+fn markdown(w: &mut impl Write, history: &[History]) -> Result<()> {
+    // pipes end a table cell, and newlines end the row
+    fn cell(s: &str) -> String {
+        s.replace('|', "\\|").replace('\n', "<br>")
+    }
+
+    // a code span can't hold html, so newlines become the spaces it would render them as. The
+    // fence is a run of backticks longer than any in the command, padded if the command starts
+    // or ends with one
+    fn code(s: &str) -> String {
+        let s = s
+            .replace('|', "\\|")
+            .replace("\r\n", " ")
+            .replace('\n', " ");
+
+        let longest = s
+            .split(|c| c != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or_default();
+        let fence = "`".repeat(longest + 1);
+        let pad = if s.starts_with('`') || s.ends_with('`') {
+            " "
+        } else {
+            ""
+        };
+
+        format!("{fence}{pad}{s}{pad}{fence}")
+    }
+
+    writeln!(
+        w,
+        "| time | exit | duration (ms) | directory | host | command |"
+    )?;
+    writeln!(w, "| --- | --- | --- | --- | --- | --- |")?;
+
+    for h in history {
+        writeln!(
+            w,
+            "| {} | {} | {} | {} | {} | {} |",
+            h.timestamp.format(&Rfc3339)?,
+            h.exit,
+            h.duration.max(0) / 1_000_000,
+            cell(&h.cwd),
+            cell(&h.hostname),
+            code(&h.command),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn csv(w: &mut impl Write, history: &[History]) -> Result<()> {
+    // RFC 4180 - quote everything, and double up any quotes inside
+    fn field(s: &str) -> String {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    }
+
+    writeln!(w, "id,timestamp,duration,exit,command,cwd,session,hostname")?;
+
+    for h in history {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{}",
+            field(&h.id.0),
+            field(&h.timestamp.format(&Rfc3339)?),
+            h.duration,
+            h.exit,
+            field(&h.command),
+            field(&h.cwd),
+            field(&h.session),
+            field(&h.hostname),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use crate::{history::History, settings::ExportFormat};
+
+    use super::export;
+
+    fn history() -> Vec<History> {
+        vec![entry("echo \"a|b\"")]
+    }
+
+    fn entry(command: &str) -> History {
+        History {
+            id: "018cd4fe81757cd2aee65cd7861f9c81".to_owned().into(),
+            timestamp: datetime!(2024-01-04 00:00:00.000000 +00:00),
+            duration: 2_000_000,
+            exit: 0,
+            command: command.to_owned(),
+            cwd: "/home/ellie".to_owned(),
+            session: "018cd4fead897597852527a31c998059".to_owned(),
+            hostname: "boop:ellie".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
+            deleted_at: None,
+        }
+    }
+
+    fn exported(format: ExportFormat) -> String {
+        exported_history(format, &history())
+    }
+
+    fn exported_history(format: ExportFormat, history: &[History]) -> String {
+        let mut out = Vec::new();
+        export(&mut out, format, history).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn export_json() {
+        let out = exported(ExportFormat::Json);
+        let parsed: serde_json::Value = serde_json::from_str(&out).unwrap();
+
+        assert_eq!(parsed[0]["command"], "echo \"a|b\"");
+        assert_eq!(parsed[0]["timestamp"], "2024-01-04T00:00:00Z");
+    }
+
+    #[test]
+    fn export_markdown() {
+        let out = exported(ExportFormat::Markdown);
+
+        assert_eq!(
+            out.lines().nth(2).unwrap(),
+            "| 2024-01-04T00:00:00Z | 0 | 2 | /home/ellie | boop:ellie | `echo \"a\\|b\"` |"
+        );
+    }
+
+    #[test]
+    fn export_markdown_multiline() {
+        let out = exported_history(
+            ExportFormat::Markdown,
+            &[entry("for i in 1 2\ndo echo $i\ndone")],
+        );
+
+        assert_eq!(out.lines().count(), 3);
+        assert!(out
+            .lines()
+            .nth(2)
+            .unwrap()
+            .ends_with("| `for i in 1 2 do echo $i done` |"));
+    }
+
+    #[test]
+    fn export_markdown_backticks() {
+        let out = exported_history(
+            ExportFormat::Markdown,
+            &[entry("echo `date`"), entry("echo ``a`` `")],
+        );
+
+        assert!(out
+            .lines()
+            .nth(2)
+            .unwrap()
+            .ends_with("| `` echo `date` `` |"));
+        assert!(out
+            .lines()
+            .nth(3)
+            .unwrap()
+            .ends_with("| ``` echo ``a`` ` ``` |"));
+    }
+
+    #[test]
+    fn export_csv() {
+        let out = exported(ExportFormat::Csv);
+
+        assert_eq!(
+            out.lines().nth(1).unwrap(),
+            "\"018cd4fe81757cd2aee65cd7861f9c81\",\"2024-01-04T00:00:00Z\",2000000,0,\"echo \"\"a|b\"\"\",\"/home/ellie\",\"018cd4fead897597852527a31c998059\",\"boop:ellie\""
+        );
+    }
+}
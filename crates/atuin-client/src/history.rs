@@ -1,6 +1,7 @@
 use core::fmt::Formatter;
-use rmp::decode::ValueReadError;
+use rmp::decode::{NumValueReadError, ValueReadError};
 use rmp::{decode::Bytes, Marker};
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt::Display;
 
@@ -8,18 +9,42 @@ use atuin_common::record::DecryptedData;
 use atuin_common::utils::uuid_v7;
 
 use eyre::{bail, eyre, Result};
-use regex::RegexSet;
 
-use crate::utils::get_host_user;
-use crate::{secrets::SECRET_PATTERNS, settings::Settings};
+use crate::utils::{
+    get_container, get_host_user, get_namespace, get_nix_shell, get_pane, get_tmux_window,
+};
+use crate::{
+    secrets,
+    settings::{SecretsAction, Settings, SyncField},
+};
 use time::OffsetDateTime;
 
+pub mod annotation;
 mod builder;
+pub mod retention;
 pub mod store;
 
 const HISTORY_VERSION: &str = "v0";
 pub const HISTORY_TAG: &str = "history";
 
+/// The exit code of a command that was typed, then cancelled at the prompt rather than run.
+/// Nothing that ran exits with a negative code, so it can't be taken for a command that failed.
+pub const CANCELLED_EXIT: i64 = -2;
+
+/// Where in a terminal multiplexer history ran, kept with its environment variables
+pub const TMUX_PANE: &str = "tmux_pane";
+pub const TMUX_WINDOW: &str = "tmux_window";
+pub const ZELLIJ_PANE: &str = "zellij_pane";
+pub const WEZTERM_PANE: &str = "wezterm_pane";
+
+/// The derivation of the `nix develop` or `nix-shell` environment history ran in, kept with its
+/// environment variables
+pub const NIX_SHELL: &str = "nix_shell";
+
+/// The container history ran in, kept with its environment variables
+pub const CONTAINER_ID: &str = "container_id";
+pub const CONTAINER_IMAGE: &str = "container_image";
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct HistoryId(pub String);
 
@@ -68,6 +93,20 @@ pub struct History {
     pub session: String,
     /// The hostname of the machine the command was run on.
     pub hostname: String,
+    /// The container the command was run in, such as "devcontainer:atuin". Empty when run on
+    /// the host itself.
+    pub namespace: String,
+    /// How many bytes the command wrote to stdout, if the shell integration could tell.
+    pub stdout_bytes: Option<i64>,
+    /// How many bytes the command wrote to stderr, if the shell integration could tell.
+    pub stderr_bytes: Option<i64>,
+    /// The environment variables listed in `history_env`, as they were when the command ran.
+    /// In tmux, zellij or wezterm, also the pane it ran in, and in tmux its `tmux_window`. In a
+    /// nix shell, also its `nix_shell`, and in a container its `container_id` and
+    /// `container_image`, when they can be found.
+    pub env: BTreeMap<String, String>,
+    /// The git branch checked out when the command was run. Empty outside of a repo.
+    pub branch: String,
     /// Timestamp, which is set when the entry is deleted, allowing a soft delete.
     pub deleted_at: Option<OffsetDateTime>,
 }
@@ -90,6 +129,21 @@ pub struct HistoryStats {
     pub day_of_week: Vec<(String, i64)>,
 
     pub duration_over_time: Vec<(String, i64)>,
+
+    /// Runs that succeeded and failed in each month, as ("01-mm-yyyy", succeeded, failed). Runs
+    /// without a known exit code aren't counted.
+    pub success_over_time: Vec<(String, i64, i64)>,
+
+    /// Runs in each hour of the day, in local time, as (hour, count)
+    pub hour_of_day: Vec<(i64, i64)>,
+
+    /// Runs on each day of the last 53 weeks, in local time, as ("YYYY-MM-DD", count)
+    pub calendar: Vec<(String, i64)>,
+
+    /// Tags on this run of the command
+    pub tags: Vec<String>,
+
+    pub note: Option<String>,
 }
 
 impl History {
@@ -102,12 +156,14 @@ impl History {
         duration: i64,
         session: Option<String>,
         hostname: Option<String>,
+        namespace: Option<String>,
         deleted_at: Option<OffsetDateTime>,
     ) -> Self {
         let session = session
             .or_else(|| env::var("ATUIN_SESSION").ok())
             .unwrap_or_else(|| uuid_v7().as_simple().to_string());
         let hostname = hostname.unwrap_or_else(get_host_user);
+        let namespace = namespace.unwrap_or_else(get_namespace);
 
         Self {
             id: uuid_v7().as_simple().to_string().into(),
@@ -118,6 +174,11 @@ impl History {
             duration,
             session,
             hostname,
+            namespace,
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: BTreeMap::new(),
+            branch: String::new(),
             deleted_at,
         }
     }
@@ -133,7 +194,20 @@ impl History {
         // write the version
         encode::write_u16(&mut output, 0)?;
         // INFO: ensure this is updated when adding new fields
-        encode::write_array_len(&mut output, 9)?;
+        // Newer fields are only written when they're set, so that most history can still be read
+        // by versions of atuin from before they were added.
+        let nfields = if !self.branch.is_empty() {
+            14
+        } else if !self.env.is_empty() {
+            13
+        } else if self.stdout_bytes.is_some() || self.stderr_bytes.is_some() {
+            12
+        } else if !self.namespace.is_empty() {
+            10
+        } else {
+            9
+        };
+        encode::write_array_len(&mut output, nfields)?;
 
         encode::write_str(&mut output, &self.id.0)?;
         encode::write_u64(&mut output, self.timestamp.unix_timestamp_nanos() as u64)?;
@@ -149,6 +223,29 @@ impl History {
             None => encode::write_nil(&mut output)?,
         }
 
+        if nfields > 9 {
+            encode::write_str(&mut output, &self.namespace)?;
+        }
+
+        if nfields > 10 {
+            for bytes in [self.stdout_bytes, self.stderr_bytes] {
+                match bytes {
+                    Some(b) => {
+                        encode::write_sint(&mut output, b)?;
+                    }
+                    None => encode::write_nil(&mut output)?,
+                }
+            }
+        }
+
+        if nfields > 12 {
+            encode::write_str(&mut output, &serde_json::to_string(&self.env)?)?;
+        }
+
+        if nfields > 13 {
+            encode::write_str(&mut output, &self.branch)?;
+        }
+
         Ok(DecryptedData(output))
     }
 
@@ -169,7 +266,7 @@ impl History {
 
         let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
 
-        if nfields != 9 {
+        if !matches!(nfields, 9 | 10 | 12..=14) {
             bail!("cannot decrypt history from a different version of Atuin");
         }
 
@@ -197,6 +294,44 @@ impl History {
             Err(err) => return Err(error_report(err)),
         };
 
+        let (namespace, bytes) = if nfields > 9 {
+            decode::read_str_from_slice(bytes).map_err(error_report)?
+        } else {
+            ("", bytes)
+        };
+
+        let mut bytes = Bytes::new(bytes);
+
+        let mut read_output_bytes = || -> Result<Option<i64>> {
+            if nfields <= 10 {
+                return Ok(None);
+            }
+
+            match decode::read_int(&mut bytes) {
+                Ok(b) => Ok(Some(b)),
+                Err(NumValueReadError::TypeMismatch(Marker::Null)) => Ok(None),
+                Err(err) => Err(error_report(err)),
+            }
+        };
+
+        let stdout_bytes = read_output_bytes()?;
+        let stderr_bytes = read_output_bytes()?;
+
+        let bytes = bytes.remaining_slice();
+
+        let (env, bytes) = if nfields > 12 {
+            let (env, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+            (serde_json::from_str(env)?, bytes)
+        } else {
+            (BTreeMap::new(), bytes)
+        };
+
+        let (branch, bytes) = if nfields > 13 {
+            decode::read_str_from_slice(bytes).map_err(error_report)?
+        } else {
+            ("", bytes)
+        };
+
         if !bytes.is_empty() {
             bail!("trailing bytes in encoded history. malformed")
         }
@@ -210,6 +345,11 @@ impl History {
             cwd: cwd.to_owned(),
             session: session.to_owned(),
             hostname: hostname.to_owned(),
+            namespace: namespace.to_owned(),
+            stdout_bytes,
+            stderr_bytes,
+            env,
+            branch: branch.to_owned(),
             deleted_at: deleted_at
                 .map(|t| OffsetDateTime::from_unix_timestamp_nanos(t as i128))
                 .transpose()?,
@@ -373,14 +513,103 @@ impl History {
         self.exit == 0 || self.duration == -1
     }
 
+    /// Whether the command was cancelled at the prompt, rather than run
+    pub fn cancelled(&self) -> bool {
+        self.exit == CANCELLED_EXIT
+    }
+
+    /// This history, without the given fields
+    pub fn stripped(mut self, fields: &[SyncField]) -> History {
+        for field in fields {
+            match field {
+                SyncField::Cwd => self.cwd.clear(),
+                SyncField::Hostname => self.hostname.clear(),
+                SyncField::Session => self.session.clear(),
+            }
+        }
+
+        self
+    }
+
     pub fn should_save(&self, settings: &Settings) -> bool {
-        let secret_regex = SECRET_PATTERNS.iter().map(|f| f.1);
-        let secret_regex = RegexSet::new(secret_regex).expect("Failed to build secrets regex");
+        // history with secrets is saved, once redacted
+        let skip_secrets = settings.secrets_action == SecretsAction::Skip;
 
         !(self.command.starts_with(' ')
             || settings.history_filter.is_match(&self.command)
             || settings.cwd_filter.is_match(&self.cwd)
-            || (secret_regex.is_match(&self.command)) && settings.secrets_filter)
+            || skip_secrets && secrets::contains_secret(&self.command, settings))
+    }
+
+    /// This history, with the environment variables listed in `history_env` recorded, and the
+    /// multiplexer pane, nix shell and container it ran in
+    pub fn with_env(mut self, settings: &Settings) -> History {
+        self.env = settings
+            .history_env
+            .iter()
+            .filter_map(|name| Some((name.clone(), env::var(name).ok()?)))
+            .collect();
+
+        if let Some((key, pane)) = get_pane() {
+            if key == TMUX_PANE {
+                if let Some(window) = get_tmux_window(&pane) {
+                    self.env.insert(TMUX_WINDOW.to_string(), window);
+                }
+            }
+
+            self.env.insert(key.to_string(), pane);
+        }
+
+        if let Some(shell) = get_nix_shell() {
+            self.env.insert(NIX_SHELL.to_string(), shell);
+        }
+
+        let (id, image) = get_container();
+        if let Some(id) = id {
+            self.env.insert(CONTAINER_ID.to_string(), id);
+        }
+        if let Some(image) = image {
+            self.env.insert(CONTAINER_IMAGE.to_string(), image);
+        }
+
+        self
+    }
+
+    /// This history, with any secrets in the command redacted if configured to
+    pub fn redacted(mut self, settings: &Settings) -> History {
+        if settings.secrets_action == SecretsAction::Redact {
+            self.command = secrets::redact(&self.command, settings);
+        }
+
+        self
+    }
+
+    /// A command that's about to run, as `atuin history start` records it, or None if it isn't
+    /// saved
+    pub fn started(settings: &Settings, command: String, cwd: String) -> Option<History> {
+        let h: History = History::capture()
+            .timestamp(OffsetDateTime::now_utc())
+            .command(command)
+            .cwd(cwd)
+            .build()
+            .into();
+
+        if !h.should_save(settings) {
+            return None;
+        }
+
+        Some(h.redacted(settings).with_env(settings))
+    }
+
+    /// Whether history started here records anything of the environment it ran in: its
+    /// namespace, or what `with_env` adds. The daemon can't see a shell's environment, so only a
+    /// shell where this is false can have it start history for it.
+    pub fn records_env(settings: &Settings) -> bool {
+        !settings.history_env.is_empty()
+            || !get_namespace().is_empty()
+            || get_pane().is_some()
+            || get_nix_shell().is_some()
+            || get_container() != (None, None)
     }
 }
 
@@ -389,7 +618,10 @@ mod tests {
     use regex::RegexSet;
     use time::macros::datetime;
 
-    use crate::{history::HISTORY_VERSION, settings::Settings};
+    use crate::{
+        history::HISTORY_VERSION,
+        settings::{SecretsAction, Settings},
+    };
 
     use super::History;
 
@@ -461,6 +693,27 @@ mod tests {
         assert!(stripe_key.should_save(&settings));
     }
 
+    #[test]
+    fn redact_secrets() {
+        let settings = Settings {
+            secrets_action: SecretsAction::Redact,
+            ..Settings::utc()
+        };
+
+        let stripe_key: History = History::capture()
+            .timestamp(time::OffsetDateTime::now_utc())
+            .command("curl foo.com/bar?key=sk_test_1234567890abcdefghijklmn")
+            .cwd("/")
+            .build()
+            .into();
+
+        assert!(stripe_key.should_save(&settings));
+        assert_eq!(
+            stripe_key.redacted(&settings).command,
+            "curl foo.com/bar?key=[REDACTED]"
+        );
+    }
+
     #[test]
     fn test_serialize_deserialize() {
         let bytes = [
@@ -484,6 +737,11 @@ mod tests {
             cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
             session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
             hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
             deleted_at: None,
         };
 
@@ -511,6 +769,11 @@ mod tests {
             cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
             session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
             hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
             deleted_at: Some(datetime!(2023-11-19 20:18 +00:00)),
         };
 
@@ -522,6 +785,99 @@ mod tests {
         assert_eq!(history, deserialized);
     }
 
+    #[test]
+    fn test_serialize_deserialize_namespace() {
+        let history = History {
+            id: "66d16cbee7cd47538e5c5b8b44e9006e".to_owned().into(),
+            timestamp: datetime!(2023-05-28 18:35:40.633872 +00:00),
+            duration: 49206000,
+            exit: 0,
+            command: "git status".to_owned(),
+            cwd: "/workspaces/atuin".to_owned(),
+            session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
+            hostname: "3f2a9c1d:vscode".to_owned(),
+            namespace: "devcontainer:atuin".to_owned(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
+            deleted_at: None,
+        };
+
+        let serialized = history.serialize().expect("failed to serialize history");
+
+        let deserialized = History::deserialize(&serialized.0, HISTORY_VERSION)
+            .expect("failed to deserialize history");
+
+        assert_eq!(history, deserialized);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_output_bytes() {
+        let mut history = History {
+            id: "66d16cbee7cd47538e5c5b8b44e9006e".to_owned().into(),
+            timestamp: datetime!(2023-05-28 18:35:40.633872 +00:00),
+            duration: 49206000,
+            exit: 0,
+            command: "git status".to_owned(),
+            cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
+            session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
+            hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: Some(1234),
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
+            deleted_at: None,
+        };
+
+        for namespace in ["", "devcontainer:atuin"] {
+            history.namespace = namespace.to_owned();
+
+            let serialized = history.serialize().expect("failed to serialize history");
+
+            let deserialized = History::deserialize(&serialized.0, HISTORY_VERSION)
+                .expect("failed to deserialize history");
+
+            assert_eq!(history, deserialized);
+        }
+    }
+
+    #[test]
+    fn test_serialize_deserialize_env() {
+        let mut history = History {
+            id: "66d16cbee7cd47538e5c5b8b44e9006e".to_owned().into(),
+            timestamp: datetime!(2023-05-28 18:35:40.633872 +00:00),
+            duration: 49206000,
+            exit: 0,
+            command: "kubectl get pods".to_owned(),
+            cwd: "/Users/conrad.ludgate/Documents/code/atuin".to_owned(),
+            session: "b97d9a306f274473a203d2eba41f9457".to_owned(),
+            hostname: "fvfg936c0kpf:conrad.ludgate".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: [
+                ("AWS_PROFILE".to_owned(), "prod".to_owned()),
+                ("KUBECONFIG".to_owned(), "~/.kube/prod".to_owned()),
+            ]
+            .into(),
+            branch: String::new(),
+            deleted_at: None,
+        };
+
+        for branch in ["", "main"] {
+            history.branch = branch.to_owned();
+
+            let serialized = history.serialize().expect("failed to serialize history");
+
+            let deserialized = History::deserialize(&serialized.0, HISTORY_VERSION)
+                .expect("failed to deserialize history");
+
+            assert_eq!(history, deserialized);
+        }
+    }
+
     #[test]
     fn test_serialize_deserialize_version() {
         // v0
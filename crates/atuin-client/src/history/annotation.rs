@@ -0,0 +1,297 @@
+// Tags and notes on history. These are their own record type, so that they sync, and are built
+// into the history database to search with. Like stars, they're kept apart from the history
+// records themselves, as history is never edited once it's been written to the store.
+
+use std::collections::{HashMap, HashSet};
+
+use atuin_common::record::{DecryptedData, Host, HostId, Record};
+use eyre::{bail, ensure, eyre, Result};
+
+use crate::{
+    database::Database,
+    record::{encryption::PASETO_V4, sqlite_store::SqliteStore, store::Store},
+};
+
+use super::HistoryId;
+
+pub const ANNOTATION_TAG: &str = "history-annotation";
+const ANNOTATION_VERSION: &str = "v0";
+
+// Notes are for a line or two of context, not documentation
+const ANNOTATION_MAX_LEN: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnotationRecord {
+    Tag(HistoryId, String),
+    Untag(HistoryId, String),
+    /// Replace the note on some history. An empty note removes it.
+    Note(HistoryId, String),
+}
+
+impl AnnotationRecord {
+    /// Each record is its type (0 = tag, 1 = untag, 2 = note), followed by the history id and
+    /// the tag or note.
+    pub fn serialize(&self) -> Result<DecryptedData> {
+        use rmp::encode;
+
+        let (kind, id, value) = match self {
+            AnnotationRecord::Tag(id, tag) => (0, id, tag),
+            AnnotationRecord::Untag(id, tag) => (1, id, tag),
+            AnnotationRecord::Note(id, note) => (2, id, note),
+        };
+
+        let mut output = vec![];
+
+        encode::write_u8(&mut output, kind)?;
+        encode::write_array_len(&mut output, 2)?;
+        encode::write_str(&mut output, id.0.as_str())?;
+        encode::write_str(&mut output, value)?;
+
+        Ok(DecryptedData(output))
+    }
+
+    pub fn deserialize(data: &DecryptedData, version: &str) -> Result<Self> {
+        use rmp::decode;
+
+        fn error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
+            eyre!("{err:?}")
+        }
+
+        if version != ANNOTATION_VERSION {
+            bail!("unknown version {version:?}");
+        }
+
+        let mut bytes = decode::Bytes::new(&data.0);
+
+        let kind = decode::read_u8(&mut bytes).map_err(error_report)?;
+        let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
+        ensure!(nfields == 2, "wrong number of fields in annotation record");
+
+        let bytes = bytes.remaining_slice();
+        let (id, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+        let (value, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+        if !bytes.is_empty() {
+            bail!("trailing bytes in encoded annotation record. malformed")
+        }
+
+        let id = HistoryId(id.to_string());
+        let value = value.to_string();
+
+        match kind {
+            0 => Ok(AnnotationRecord::Tag(id, value)),
+            1 => Ok(AnnotationRecord::Untag(id, value)),
+            2 => Ok(AnnotationRecord::Note(id, value)),
+            n => bail!("unknown AnnotationRecord type {n}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnotationStore {
+    pub store: SqliteStore,
+    pub host_id: HostId,
+    pub encryption_key: [u8; 32],
+}
+
+impl AnnotationStore {
+    pub fn new(store: SqliteStore, host_id: HostId, encryption_key: [u8; 32]) -> Self {
+        AnnotationStore {
+            store,
+            host_id,
+            encryption_key,
+        }
+    }
+
+    async fn push(&self, record: &AnnotationRecord) -> Result<()> {
+        let bytes = record.serialize()?;
+
+        let idx = self
+            .store
+            .last(self.host_id, ANNOTATION_TAG)
+            .await?
+            .map_or(0, |entry| entry.idx + 1);
+
+        let record = Record::builder()
+            .host(Host::new(self.host_id))
+            .version(ANNOTATION_VERSION.to_string())
+            .tag(ANNOTATION_TAG.to_string())
+            .idx(idx)
+            .data(bytes)
+            .build();
+
+        self.store
+            .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn tag(&self, database: &dyn Database, id: HistoryId, tag: &str) -> Result<()> {
+        let tag = valid_tag(tag)?;
+
+        self.push(&AnnotationRecord::Tag(id.clone(), tag.to_string()))
+            .await?;
+        database.tag(&id, tag, true).await?;
+
+        Ok(())
+    }
+
+    pub async fn untag(&self, database: &dyn Database, id: HistoryId, tag: &str) -> Result<()> {
+        let tag = valid_tag(tag)?;
+
+        self.push(&AnnotationRecord::Untag(id.clone(), tag.to_string()))
+            .await?;
+        database.tag(&id, tag, false).await?;
+
+        Ok(())
+    }
+
+    /// Set the note on some history, or remove it with an empty one
+    pub async fn note(&self, database: &dyn Database, id: HistoryId, note: &str) -> Result<()> {
+        let note = note.trim();
+
+        if note.len() > ANNOTATION_MAX_LEN {
+            bail!("note too large: max len {ANNOTATION_MAX_LEN} bytes");
+        }
+
+        self.push(&AnnotationRecord::Note(id.clone(), note.to_string()))
+            .await?;
+        database.note(&id, note).await?;
+
+        Ok(())
+    }
+
+    pub async fn annotations(&self) -> Result<Vec<AnnotationRecord>> {
+        let mut records = self.store.all_tagged(ANNOTATION_TAG).await?;
+
+        // records from different hosts are only ordered amongst themselves. Replay them in the
+        // order they were made, so the latest change wins.
+        records.sort_by_key(|r| r.timestamp);
+
+        records
+            .into_iter()
+            .map(|record| {
+                let decrypted = match record.version.as_str() {
+                    ANNOTATION_VERSION => record.decrypt::<PASETO_V4>(&self.encryption_key)?,
+                    version => bail!("unknown version {version:?}"),
+                };
+
+                AnnotationRecord::deserialize(&decrypted.data, ANNOTATION_VERSION)
+            })
+            .collect()
+    }
+
+    /// Replace the tags and notes in the database with those in the store, after a sync may have
+    /// changed them
+    pub async fn build(&self, database: &dyn Database) -> Result<()> {
+        let mut tags = HashSet::new();
+        let mut notes = HashMap::new();
+
+        for record in self.annotations().await? {
+            match record {
+                AnnotationRecord::Tag(id, tag) => {
+                    tags.insert((id, tag));
+                }
+                AnnotationRecord::Untag(id, tag) => {
+                    tags.remove(&(id, tag));
+                }
+                AnnotationRecord::Note(id, note) if note.is_empty() => {
+                    notes.remove(&id);
+                }
+                AnnotationRecord::Note(id, note) => {
+                    notes.insert(id, note);
+                }
+            }
+        }
+
+        database
+            .replace_annotations(tags.into_iter().collect(), notes.into_iter().collect())
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Tags are searched for with `tag:NAME`, which ends at the next space
+fn valid_tag(tag: &str) -> Result<&str> {
+    let tag = tag.trim();
+
+    ensure!(!tag.is_empty(), "tags can't be empty");
+    ensure!(
+        !tag.contains(char::is_whitespace),
+        "tags can't contain spaces"
+    );
+    ensure!(
+        tag.len() <= ANNOTATION_MAX_LEN,
+        "tag too large: max len {ANNOTATION_MAX_LEN} bytes"
+    );
+
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
+    use rand::rngs::OsRng;
+
+    use crate::{
+        database::{Database, Sqlite},
+        history::HistoryId,
+        record::sqlite_store::SqliteStore,
+        settings::test_local_timeout,
+    };
+
+    use super::{AnnotationRecord, AnnotationStore, ANNOTATION_VERSION};
+
+    #[test]
+    fn encode_decode() {
+        let records = [
+            AnnotationRecord::Tag(HistoryId("1".into()), "deploy".into()),
+            AnnotationRecord::Untag(HistoryId("1".into()), "deploy".into()),
+            AnnotationRecord::Note(HistoryId("2".into()), "rotates the staging certs".into()),
+        ];
+
+        for record in records {
+            let encoded = record.serialize().unwrap();
+            let decoded = AnnotationRecord::deserialize(&encoded, ANNOTATION_VERSION).unwrap();
+
+            assert_eq!(decoded, record);
+        }
+    }
+
+    #[tokio::test]
+    async fn build_annotations() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+        let annotations = AnnotationStore::new(store, host_id, key);
+
+        let id = HistoryId("1".into());
+
+        annotations.tag(&db, id.clone(), "deploy").await.unwrap();
+        annotations.tag(&db, id.clone(), "prod").await.unwrap();
+        annotations.untag(&db, id.clone(), "deploy").await.unwrap();
+        annotations
+            .note(&db, id.clone(), "needs the vpn up")
+            .await
+            .unwrap();
+
+        assert!(annotations.tag(&db, id.clone(), "two words").await.is_err());
+
+        // a fresh database, as if the annotations had just been downloaded
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+        annotations.build(&db).await.unwrap();
+
+        let (tags, note) = db.annotations(&id).await.unwrap();
+        assert_eq!(tags, vec!["prod".to_string()]);
+        assert_eq!(note.as_deref(), Some("needs the vpn up"));
+    }
+}
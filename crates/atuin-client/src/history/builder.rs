@@ -1,6 +1,10 @@
+use std::collections::BTreeMap;
+
+use atuin_common::utils::git_branch;
+use blake2::{digest::consts::U16, Blake2b, Digest};
 use typed_builder::TypedBuilder;
 
-use super::History;
+use super::{History, HistoryId};
 
 /// Builder for a history entry that is imported from shell history.
 ///
@@ -24,7 +28,7 @@ pub struct HistoryImported {
 
 impl From<HistoryImported> for History {
     fn from(imported: HistoryImported) -> Self {
-        History::new(
+        let mut history = History::new(
             imported.timestamp,
             imported.command,
             imported.cwd,
@@ -33,10 +37,31 @@ impl From<HistoryImported> for History {
             imported.session,
             imported.hostname,
             None,
-        )
+            None,
+        );
+
+        // the same entry always gets the same id, so importing a file again skips whatever was
+        // already imported from it
+        history.id = imported_id(&history);
+
+        history
     }
 }
 
+fn imported_id(history: &History) -> HistoryId {
+    let mut hasher = Blake2b::<U16>::new();
+    hasher.update(history.timestamp.unix_timestamp_nanos().to_le_bytes());
+    hasher.update(history.hostname.as_bytes());
+    hasher.update([0]);
+    hasher.update(history.command.as_bytes());
+
+    uuid::Builder::from_custom_bytes(hasher.finalize().into())
+        .into_uuid()
+        .as_simple()
+        .to_string()
+        .into()
+}
+
 /// Builder for a history entry that is captured via hook.
 ///
 /// This builder is used only at the `start` step of the hook,
@@ -53,7 +78,9 @@ pub struct HistoryCaptured {
 
 impl From<HistoryCaptured> for History {
     fn from(captured: HistoryCaptured) -> Self {
-        History::new(
+        let branch = git_branch(&captured.cwd).unwrap_or_default();
+
+        let mut history = History::new(
             captured.timestamp,
             captured.command,
             captured.cwd,
@@ -62,7 +89,11 @@ impl From<HistoryCaptured> for History {
             None,
             None,
             None,
-        )
+            None,
+        );
+        history.branch = branch;
+
+        history
     }
 }
 
@@ -79,6 +110,11 @@ pub struct HistoryFromDb {
     duration: i64,
     session: String,
     hostname: String,
+    namespace: String,
+    stdout_bytes: Option<i64>,
+    stderr_bytes: Option<i64>,
+    env: BTreeMap<String, String>,
+    branch: String,
     deleted_at: Option<time::OffsetDateTime>,
 }
 
@@ -93,6 +129,11 @@ impl From<HistoryFromDb> for History {
             duration: from_db.duration,
             session: from_db.session,
             hostname: from_db.hostname,
+            namespace: from_db.namespace,
+            stdout_bytes: from_db.stdout_bytes,
+            stderr_bytes: from_db.stderr_bytes,
+            env: from_db.env,
+            branch: from_db.branch,
             deleted_at: from_db.deleted_at,
         }
     }
@@ -114,11 +155,18 @@ pub struct HistoryDaemonCapture {
     session: String,
     #[builder(setter(into))]
     hostname: String,
+    /// Empty when the client isn't in a container
+    #[builder(default, setter(into))]
+    namespace: String,
+    #[builder(default)]
+    env: BTreeMap<String, String>,
+    #[builder(default, setter(into))]
+    branch: String,
 }
 
 impl From<HistoryDaemonCapture> for History {
     fn from(captured: HistoryDaemonCapture) -> Self {
-        History::new(
+        let mut history = History::new(
             captured.timestamp,
             captured.command,
             captured.cwd,
@@ -126,7 +174,14 @@ impl From<HistoryDaemonCapture> for History {
             -1,
             Some(captured.session),
             Some(captured.hostname),
+            Some(captured.namespace),
             None,
-        )
+        );
+
+        // the client knows what it was asked to record, the daemon doesn't
+        history.env = captured.env;
+        history.branch = captured.branch;
+
+        history
     }
 }
@@ -0,0 +1,84 @@
+//! Pruning history that's past the `[history]` retention policy
+
+use std::{
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use eyre::Result;
+use time::OffsetDateTime;
+
+use crate::{
+    database::Database,
+    export,
+    settings::{ExportFormat, Settings},
+};
+
+use super::{store::HistoryStore, History};
+
+/// History that's older than `history.max_age`, or beyond the newest `history.max_count`
+pub async fn expired(settings: &Settings, db: &dyn Database) -> Result<Vec<History>> {
+    let cutoff = settings.retention_cutoff()?;
+
+    Ok(db.expired(cutoff, settings.history.max_count).await?)
+}
+
+/// Write history to a new file in `history.archive_dir`, if one is set
+pub fn archive(settings: &Settings, history: &[History]) -> Result<Option<PathBuf>> {
+    let Some(dir) = &settings.history.archive_dir else {
+        return Ok(None);
+    };
+
+    if history.is_empty() {
+        return Ok(None);
+    }
+
+    fs_err::create_dir_all(dir)?;
+
+    let format = ExportFormat::Json;
+    let path = PathBuf::from(dir).join(format!(
+        "atuin-archive-{}.{}",
+        OffsetDateTime::now_utc().unix_timestamp_nanos(),
+        format.extension()
+    ));
+
+    let mut file = BufWriter::new(fs_err::File::create(&path)?);
+    export::export(&mut file, format, history)?;
+    file.flush()?;
+
+    Ok(Some(path))
+}
+
+/// Archive and delete expired history, returning how much was pruned. Deletions are left as
+/// tombstones, and with record sync they go through the store so that other hosts drop the
+/// history too.
+pub async fn prune(
+    settings: &Settings,
+    db: &dyn Database,
+    history_store: &HistoryStore,
+) -> Result<usize> {
+    let history = expired(settings, db).await?;
+
+    if history.is_empty() {
+        return Ok(0);
+    }
+
+    if let Some(path) = archive(settings, &history)? {
+        debug!("archived {} history entries to {path:?}", history.len());
+    }
+
+    let count = history.len();
+    let ids: Vec<_> = history.into_iter().map(|h| h.id).collect();
+
+    // the deletes are applied here rather than built from the records, as a host with only the
+    // public key can't read them back
+    if settings.sync.records {
+        for id in &ids {
+            history_store.delete(id.clone()).await?;
+        }
+    }
+
+    db.delete_bulk(&ids).await?;
+
+    Ok(count)
+}
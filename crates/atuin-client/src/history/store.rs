@@ -1,14 +1,24 @@
-use std::{collections::HashSet, fmt::Write, time::Duration};
+use std::{collections::HashSet, fmt::Write, num::NonZeroUsize, time::Duration};
 
 use eyre::{bail, eyre, Result};
+use futures::{stream, StreamExt, TryStreamExt};
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use itertools::Itertools;
 use rmp::decode::Bytes;
 
 use crate::{
     database::{current_context, Database},
-    record::{encryption::PASETO_V4, sqlite_store::SqliteStore, store::Store},
+    kv::KvStore,
+    record::{
+        encryption::{PASETO_V4, PASETO_V4_PKE},
+        sqlite_store::SqliteStore,
+        store::Store,
+    },
+    settings::{Settings, SyncField},
+};
+use atuin_common::record::{
+    DecryptedData, EncryptedData, Host, HostId, Record, RecordId, RecordIdx,
 };
-use atuin_common::record::{DecryptedData, Host, HostId, Record, RecordId, RecordIdx};
 
 use super::{History, HistoryId, HISTORY_TAG, HISTORY_VERSION};
 
@@ -17,6 +27,13 @@ pub struct HistoryStore {
     pub store: SqliteStore,
     pub host_id: HostId,
     pub encryption_key: [u8; 32],
+
+    /// Fields left out of history before it's written to the store, and so synced
+    pub strip: Vec<SyncField>,
+
+    /// Set on a host that only has the public key. Records are sealed to it rather than
+    /// encrypted with the master key, and nothing can be read back.
+    pub public_key: Option<[u8; 32]>,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -108,17 +125,106 @@ impl HistoryRecord {
     }
 }
 
+const STAR_NAMESPACE: &str = "atuin.star";
+
+// Decrypting is CPU bound, and an initial sync may bring down hundreds of thousands of records.
+// They're decrypted in chunks on the blocking pool, with a chunk in flight per core.
+const DECRYPT_CHUNK: usize = 512;
+
+fn decrypt_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+async fn decrypt(key: [u8; 32], records: Vec<Record<EncryptedData>>) -> Result<Vec<HistoryRecord>> {
+    tokio::task::spawn_blocking(move || {
+        records
+            .into_iter()
+            .map(|record| match record.version.as_str() {
+                HISTORY_VERSION => {
+                    let decrypted = record.decrypt::<PASETO_V4>(&key)?;
+
+                    HistoryRecord::deserialize(&decrypted.data, HISTORY_VERSION)
+                }
+                version => bail!("unknown history version {version:?}"),
+            })
+            .collect()
+    })
+    .await?
+}
+
+// Read records from the store and decrypt them, skipping any that aren't history
+async fn load(store: SqliteStore, key: [u8; 32], ids: Vec<RecordId>) -> Result<Vec<HistoryRecord>> {
+    let mut records = Vec::with_capacity(ids.len());
+
+    for id in ids {
+        match store.get(id).await {
+            Ok(record) if record.tag == HISTORY_TAG => records.push(record),
+            _ => continue,
+        }
+    }
+
+    decrypt(key, records).await
+}
+
 impl HistoryStore {
     pub fn new(store: SqliteStore, host_id: HostId, encryption_key: [u8; 32]) -> Self {
         HistoryStore {
             store,
             host_id,
             encryption_key,
+            strip: Vec::new(),
+            public_key: None,
+        }
+    }
+
+    /// A store for a host with only the public key, which writes history but can't read it
+    pub fn write_only(store: SqliteStore, host_id: HostId, public_key: [u8; 32]) -> Self {
+        HistoryStore {
+            public_key: Some(public_key),
+            ..Self::new(store, host_id, [0; 32])
+        }
+    }
+
+    /// The store history is synced from, leaving out the fields `sync.strip` names. They're
+    /// left out of the records kept locally too, not just the ones uploaded.
+    pub fn from_settings(
+        store: SqliteStore,
+        host_id: HostId,
+        encryption_key: [u8; 32],
+        settings: &Settings,
+    ) -> Self {
+        Self::new(store, host_id, encryption_key).with_strip(&settings.sync.strip)
+    }
+
+    pub fn with_strip(mut self, fields: &[SyncField]) -> Self {
+        self.strip = fields.to_vec();
+        self
+    }
+
+    fn encrypt(&self, record: Record<DecryptedData>) -> Record<EncryptedData> {
+        match &self.public_key {
+            Some(public_key) => record.encrypt::<PASETO_V4_PKE>(public_key),
+            None => record.encrypt::<PASETO_V4>(&self.encryption_key),
+        }
+    }
+
+    fn ensure_readable(&self) -> Result<()> {
+        if self.public_key.is_some() {
+            bail!("this host only has the public key, so it can write history but not read it");
+        }
+
+        Ok(())
+    }
+
+    fn strip(&self, record: HistoryRecord) -> HistoryRecord {
+        match record {
+            HistoryRecord::Create(h) => HistoryRecord::Create(h.stripped(&self.strip)),
+            delete => delete,
         }
     }
 
     async fn push_record(&self, record: HistoryRecord) -> Result<(RecordId, RecordIdx)> {
-        let bytes = record.serialize()?;
+        let bytes = self.strip(record).serialize()?;
         let idx = self
             .store
             .last(self.host_id, HISTORY_TAG)
@@ -135,9 +241,7 @@ impl HistoryStore {
 
         let id = record.id;
 
-        self.store
-            .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
-            .await?;
+        self.store.push(&self.encrypt(record)).await?;
 
         Ok((id, idx))
     }
@@ -154,7 +258,7 @@ impl HistoryStore {
         // Could probably _also_ do this as an iterator, but let's see how this is for now.
         // optimizing for minimal sqlite transactions, this code can be optimised later
         for (n, record) in records.enumerate() {
-            let bytes = record.serialize()?;
+            let bytes = self.strip(record).serialize()?;
 
             let record = Record::builder()
                 .host(Host::new(self.host_id))
@@ -164,9 +268,7 @@ impl HistoryStore {
                 .data(bytes)
                 .build();
 
-            let record = record.encrypt::<PASETO_V4>(&self.encryption_key);
-
-            ret.push(record);
+            ret.push(self.encrypt(record));
         }
 
         self.store.push_batch(ret.iter()).await?;
@@ -180,6 +282,50 @@ impl HistoryStore {
         self.push_record(record).await
     }
 
+    /// Star or unstar history. Stars are kept in the kv store so that they sync, and copied into
+    /// the database to search with.
+    pub async fn star(&self, database: &dyn Database, id: HistoryId, starred: bool) -> Result<()> {
+        self.ensure_readable()?;
+
+        let value = if starred { "1" } else { "" };
+
+        KvStore::new()
+            .set(
+                &self.store,
+                &self.encryption_key,
+                self.host_id,
+                STAR_NAMESPACE,
+                id.0.as_str(),
+                value,
+            )
+            .await?;
+
+        database.star(&[id], starred).await?;
+
+        Ok(())
+    }
+
+    /// Bring the kv index up to date after a sync, and copy the stars from it into the database
+    pub async fn build_starred(&self, database: &dyn Database) -> Result<()> {
+        self.ensure_readable()?;
+
+        let kv = KvStore::new();
+        kv.update(&self.store, &self.encryption_key).await?;
+
+        // the kv store has no deletes, so unstarring stores an empty value
+        let ids: Vec<HistoryId> = kv
+            .list(&self.store, &self.encryption_key, Some(STAR_NAMESPACE))
+            .await?
+            .into_iter()
+            .filter(|kv| !kv.value.is_empty())
+            .map(|kv| kv.key.into())
+            .collect();
+
+        database.replace_starred(&ids).await?;
+
+        Ok(())
+    }
+
     pub async fn push(&self, history: History) -> Result<(RecordId, RecordIdx)> {
         // TODO(ellie): move the history store to its own file
         // it's tiny rn so fine as is
@@ -191,23 +337,23 @@ impl HistoryStore {
     pub async fn history(&self) -> Result<Vec<HistoryRecord>> {
         // Atm this loads all history into memory
         // Not ideal as that is potentially quite a lot, although history will be small.
-        let records = self.store.all_tagged(HISTORY_TAG).await?;
-        let mut ret = Vec::with_capacity(records.len());
-
-        for record in records.into_iter() {
-            let hist = match record.version.as_str() {
-                HISTORY_VERSION => {
-                    let decrypted = record.decrypt::<PASETO_V4>(&self.encryption_key)?;
-
-                    HistoryRecord::deserialize(&decrypted.data, HISTORY_VERSION)
-                }
-                version => bail!("unknown history version {version:?}"),
-            }?;
-
-            ret.push(hist);
-        }
+        self.ensure_readable()?;
 
-        Ok(ret)
+        let records = self.store.all_tagged(HISTORY_TAG).await?;
+        let key = self.encryption_key;
+
+        let chunks: Vec<Vec<_>> = records
+            .into_iter()
+            .chunks(DECRYPT_CHUNK)
+            .into_iter()
+            .map(Iterator::collect)
+            .collect();
+
+        stream::iter(chunks)
+            .map(|chunk| decrypt(key, chunk))
+            .buffered(decrypt_workers())
+            .try_concat()
+            .await
     }
 
     pub async fn build(&self, database: &dyn Database) -> Result<()> {
@@ -237,37 +383,39 @@ impl HistoryStore {
         }
 
         database.save_bulk(&creates).await?;
-        database.delete_rows(&deletes).await?;
+        database.delete_bulk(&deletes).await?;
 
-        Ok(())
+        self.build_starred(database).await
     }
 
     pub async fn incremental_build(&self, database: &dyn Database, ids: &[RecordId]) -> Result<()> {
-        for id in ids {
-            let record = self.store.get(*id).await;
+        self.ensure_readable()?;
 
-            let record = if let Ok(record) = record {
-                record
-            } else {
-                continue;
-            };
+        let key = self.encryption_key;
 
-            if record.tag != HISTORY_TAG {
-                continue;
-            }
+        // Only a few chunks are read and decrypted ahead of the one being written, so a big
+        // download doesn't all end up in memory at once
+        let chunks: Vec<Vec<RecordId>> = ids.chunks(DECRYPT_CHUNK).map(<[_]>::to_vec).collect();
 
-            let decrypted = record.decrypt::<PASETO_V4>(&self.encryption_key)?;
-            let record = HistoryRecord::deserialize(&decrypted.data, HISTORY_VERSION)?;
+        let mut chunks = stream::iter(chunks)
+            .map(|ids| load(self.store.clone(), key, ids))
+            .buffered(decrypt_workers());
 
-            match record {
-                HistoryRecord::Create(h) => {
-                    // TODO: benchmark CPU time/memory tradeoff of batch commit vs one at a time
-                    database.save(&h).await?;
-                }
-                HistoryRecord::Delete(id) => {
-                    database.delete_rows(&[id]).await?;
+        while let Some(chunk) = chunks.try_next().await? {
+            let mut creates = Vec::new();
+            let mut deletes = Vec::new();
+
+            for record in chunk {
+                match record {
+                    HistoryRecord::Create(h) => creates.push(h),
+                    HistoryRecord::Delete(id) => deletes.push(id),
                 }
             }
+
+            // Deletes leave a tombstone that a later save can't overwrite, so it doesn't matter
+            // which order these land in
+            database.save_bulk(&creates).await?;
+            database.delete_bulk(&deletes).await?;
         }
 
         Ok(())
@@ -339,12 +487,20 @@ impl HistoryStore {
 
 #[cfg(test)]
 mod tests {
-    use atuin_common::record::DecryptedData;
+    use atuin_common::{
+        record::{DecryptedData, HostId},
+        utils::uuid_v7,
+    };
     use time::macros::datetime;
 
-    use crate::history::{store::HistoryRecord, HISTORY_VERSION};
+    use crate::{
+        database::{Database, Sqlite},
+        history::{store::HistoryRecord, HISTORY_TAG, HISTORY_VERSION},
+        record::{sqlite_store::SqliteStore, store::Store},
+        settings::{test_local_timeout, SyncField},
+    };
 
-    use super::History;
+    use super::{History, HistoryStore};
 
     #[test]
     fn test_serialize_deserialize_create() {
@@ -368,6 +524,11 @@ mod tests {
             cwd: "/Users/ellie/src/github.com/atuinsh/atuin".to_owned(),
             session: "018cd4fead897597852527a31c998059".to_owned(),
             hostname: "boop:ellie".to_owned(),
+            namespace: String::new(),
+            stdout_bytes: None,
+            stderr_bytes: None,
+            env: Default::default(),
+            branch: String::new(),
             deleted_at: None,
         };
 
@@ -407,4 +568,210 @@ mod tests {
                 .expect("failed to deserialize HistoryRecord");
         assert_eq!(deserialized, record);
     }
+
+    #[tokio::test]
+    async fn test_strip_fields() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let history_store = HistoryStore::new(store, HostId(uuid_v7()), [0; 32])
+            .with_strip(&[SyncField::Cwd, SyncField::Session]);
+
+        let history: History = History::capture()
+            .timestamp(datetime!(2024-01-04 00:00:00.000000 +00:00))
+            .command("ls")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+
+        history_store.push(history.clone()).await.unwrap();
+
+        let HistoryRecord::Create(stored) = &history_store.history().await.unwrap()[0] else {
+            panic!("expected a history create");
+        };
+
+        assert_eq!(stored.command, history.command);
+        assert_eq!(stored.hostname, history.hostname);
+        assert!(stored.cwd.is_empty());
+        assert!(stored.session.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_only() {
+        use crate::record::encryption::{parse_public_key, public_key};
+
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let master = [7; 32];
+        let public = parse_public_key(&public_key(&master)).unwrap();
+
+        let host_id = HostId(uuid_v7());
+        let write_only = HistoryStore::write_only(store.clone(), host_id, public);
+
+        let history: History = History::capture()
+            .timestamp(datetime!(2024-01-04 00:00:00.000000 +00:00))
+            .command("ls")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+
+        write_only.push(history.clone()).await.unwrap();
+
+        // the host that wrote it can't read it back
+        assert!(write_only.history().await.is_err());
+
+        // anything with the master key can
+        let history_store = HistoryStore::new(store, host_id, master);
+        assert_eq!(
+            history_store.history().await.unwrap(),
+            vec![HistoryRecord::Create(history)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_init_store_strips_fields() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let history_store = HistoryStore::new(store, HostId(uuid_v7()), [0; 32])
+            .with_strip(&[SyncField::Cwd, SyncField::Session]);
+
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let history: History = History::capture()
+            .timestamp(datetime!(2024-01-04 00:00:00.000000 +00:00))
+            .command("ls")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+
+        db.save(&history).await.unwrap();
+
+        // init_store reads the context a shell would set
+        std::env::set_var("ATUIN_SESSION", uuid_v7().as_simple().to_string());
+        history_store.init_store(&db).await.unwrap();
+
+        let HistoryRecord::Create(stored) = &history_store.history().await.unwrap()[0] else {
+            panic!("expected a history create");
+        };
+
+        assert_eq!(stored.command, history.command);
+        assert!(stored.cwd.is_empty());
+        assert!(stored.session.is_empty());
+
+        // the history database keeps them
+        assert_eq!(
+            db.load(&history.id.0).await.unwrap().unwrap().cwd,
+            history.cwd
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_build_many() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let history_store = HistoryStore::new(store.clone(), HostId(uuid_v7()), [0; 32]);
+
+        // enough to span a few decryption chunks
+        let history: Vec<History> = (0..1200)
+            .map(|i| {
+                History::capture()
+                    .timestamp(datetime!(2024-01-04 00:00:00.000000 +00:00))
+                    .command(format!("echo {i}"))
+                    .cwd("/home/ellie")
+                    .build()
+                    .into()
+            })
+            .collect();
+
+        history_store
+            .push_batch(history.iter().cloned().map(HistoryRecord::Create))
+            .await
+            .unwrap();
+        history_store
+            .push_batch(
+                history
+                    .iter()
+                    .step_by(2)
+                    .map(|h| HistoryRecord::Delete(h.id.clone())),
+            )
+            .await
+            .unwrap();
+
+        let records = history_store.history().await.unwrap();
+        assert_eq!(records.len(), 1800);
+        assert_eq!(records[0], HistoryRecord::Create(history[0].clone()));
+        assert_eq!(
+            records[1799],
+            HistoryRecord::Delete(history[1198].id.clone())
+        );
+
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let ids: Vec<_> = store
+            .all_tagged(HISTORY_TAG)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+
+        history_store.incremental_build(&db, &ids).await.unwrap();
+
+        assert_eq!(db.history_count(false).await.unwrap(), 600);
+        assert_eq!(db.history_count(true).await.unwrap(), 1200);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delete_outlives_late_create() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let history_store = HistoryStore::new(store, HostId(uuid_v7()), [0; 32]);
+
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let history: History = History::capture()
+            .timestamp(datetime!(2024-01-04 00:00:00.000000 +00:00))
+            .command("rm -rf secrets")
+            .cwd("/home/ellie")
+            .build()
+            .into();
+
+        // the delete syncs first, then a host that still has the history sends it again
+        let (delete, _) = history_store
+            .push_record(HistoryRecord::Delete(history.id.clone()))
+            .await
+            .unwrap();
+        history_store
+            .incremental_build(&db, &[delete])
+            .await
+            .unwrap();
+
+        let (create, _) = history_store
+            .push_record(HistoryRecord::Create(history.clone()))
+            .await
+            .unwrap();
+        history_store
+            .incremental_build(&db, &[create])
+            .await
+            .unwrap();
+
+        assert_eq!(db.history_count(false).await.unwrap(), 0);
+
+        // and the same for a full rebuild, which sees both at once
+        let db = Sqlite::new("sqlite::memory:", test_local_timeout())
+            .await
+            .unwrap();
+        history_store.build(&db).await.unwrap();
+
+        assert_eq!(db.history_count(false).await.unwrap(), 0);
+    }
 }
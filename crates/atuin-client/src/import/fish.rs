@@ -12,6 +12,8 @@ use super::{unix_byte_lines, Importer, Loader};
 use crate::history::History;
 use crate::import::read_to_end;
 
+/// Fish only records when each command ran, and the paths it mentioned, so unlike the histdb
+/// importers there is no cwd, duration or exit code to carry over.
 #[derive(Debug)]
 pub struct Fish {
     bytes: Vec<u8>,
@@ -0,0 +1,191 @@
+// import history written by anything else!
+// reads one json object per line from stdin, so converters for other shells and tools can be
+// written in any language and piped into `atuin import json`
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use async_trait::async_trait;
+use atuin_common::utils::uuid_v7;
+use eyre::{eyre, Context, Result};
+use serde::Deserialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::{unix_byte_lines, Importer, Loader};
+use crate::history::History;
+
+/// One line of input. Only `command` and `timestamp` are required, anything missing is recorded
+/// the same way as for the other importers.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct JsonEntry {
+    pub command: String,
+    /// Seconds since the unix epoch, or an RFC 3339 string
+    pub timestamp: JsonTimestamp,
+    /// Nanoseconds
+    pub duration: Option<i64>,
+    pub exit: Option<i64>,
+    pub cwd: Option<String>,
+    /// Any string, entries sharing one are imported into the same session
+    pub session: Option<String>,
+    /// As `host:user`, like the history atuin records itself
+    pub hostname: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum JsonTimestamp {
+    Unix(i64),
+    Rfc3339(String),
+}
+
+impl JsonTimestamp {
+    fn to_datetime(&self) -> Result<OffsetDateTime> {
+        match self {
+            Self::Unix(secs) => Ok(OffsetDateTime::from_unix_timestamp(*secs)?),
+            Self::Rfc3339(s) => Ok(OffsetDateTime::parse(s, &Rfc3339)?),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Json {
+    entries: Vec<JsonEntry>,
+}
+
+/// Every line is checked before anything is imported, so a broken converter doesn't leave half
+/// of its output behind. Blank lines are ignored.
+fn parse(bytes: &[u8]) -> Result<Vec<JsonEntry>> {
+    let mut entries = Vec::new();
+
+    for (i, line) in unix_byte_lines(bytes).enumerate() {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        let entry: JsonEntry = serde_json::from_slice(line)
+            .wrap_err_with(|| format!("invalid entry on line {}", i + 1))?;
+        entry
+            .timestamp
+            .to_datetime()
+            .wrap_err_with(|| format!("invalid timestamp on line {}", i + 1))?;
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+#[async_trait]
+impl Importer for Json {
+    const NAME: &'static str = "json";
+
+    async fn new() -> Result<Self> {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| eyre!("could not read stdin: {e}"))?;
+
+        // the last line may not end in a newline
+        if !bytes.ends_with(b"\n") {
+            bytes.push(b'\n');
+        }
+
+        Ok(Self {
+            entries: parse(&bytes)?,
+        })
+    }
+
+    async fn entries(&mut self) -> Result<usize> {
+        Ok(self.entries.len())
+    }
+
+    async fn load(self, h: &mut impl Loader) -> Result<()> {
+        // atuin's session ids are uuids, so each session name gets a new one
+        let mut session_map = HashMap::new();
+
+        for entry in self.entries {
+            let timestamp = entry.timestamp.to_datetime()?;
+
+            let imported = History::import()
+                .timestamp(timestamp)
+                .command(entry.command)
+                .cwd(entry.cwd.unwrap_or_else(|| "unknown".into()))
+                .exit(entry.exit.unwrap_or(-1))
+                .duration(entry.duration.unwrap_or(-1));
+
+            let session = entry.session.map(|name| {
+                session_map
+                    .entry(name)
+                    .or_insert_with(|| uuid_v7().as_simple().to_string())
+                    .clone()
+            });
+
+            let imported = match (session, entry.hostname) {
+                (Some(session), Some(hostname)) => {
+                    imported.session(session).hostname(hostname).build()
+                }
+                (Some(session), None) => imported.session(session).build(),
+                (None, Some(hostname)) => imported.hostname(hostname).build(),
+                (None, None) => imported.build(),
+            };
+
+            h.push(imported.into()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::import::{tests::TestLoader, Importer};
+
+    use super::{parse, Json};
+
+    #[tokio::test]
+    async fn parse_entries() {
+        let bytes = br#"{"command": "ls -la", "timestamp": 1700000000}
+{"command": "cargo build", "timestamp": "2023-11-14T22:13:25Z", "duration": 2000000000, "exit": 101, "cwd": "/src/atuin", "session": "tty1", "hostname": "laptop:ellie"}
+
+{"command": "echo \"hi\"\nexit", "timestamp": 1700000030, "session": "tty1"}
+"#;
+
+        let mut json = Json {
+            entries: parse(bytes).unwrap(),
+        };
+        assert_eq!(json.entries().await.unwrap(), 3);
+
+        let mut loader = TestLoader::default();
+        json.load(&mut loader).await.unwrap();
+        let history = loader.buf;
+
+        assert_eq!(history[0].command, "ls -la");
+        assert_eq!(history[0].timestamp.unix_timestamp(), 1_700_000_000);
+        assert_eq!(history[0].exit, -1);
+        assert_eq!(history[0].duration, -1);
+        assert_eq!(history[0].cwd, "unknown");
+
+        assert_eq!(history[1].timestamp.unix_timestamp(), 1_700_000_005);
+        assert_eq!(history[1].duration, 2_000_000_000);
+        assert_eq!(history[1].exit, 101);
+        assert_eq!(history[1].cwd, "/src/atuin");
+        assert_eq!(history[1].hostname, "laptop:ellie");
+
+        assert_eq!(history[2].command, "echo \"hi\"\nexit");
+        assert_eq!(history[1].session, history[2].session);
+        assert_ne!(history[0].session, history[1].session);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let err =
+            parse(b"{\"command\": \"ls\", \"timestamp\": 1}\n{\"command\": \"ls\"}\n").unwrap_err();
+        assert_eq!(err.to_string(), "invalid entry on line 2");
+
+        let err = parse(b"{\"command\": \"ls\", \"timestamp\": \"yesterday\"}\n").unwrap_err();
+        assert_eq!(err.to_string(), "invalid timestamp on line 1");
+
+        assert!(parse(b"{\"command\": \"ls\", \"timestamp\": 1, \"exit_code\": 0}\n").is_err());
+    }
+}
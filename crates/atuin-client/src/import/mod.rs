@@ -10,8 +10,10 @@ use crate::history::History;
 
 pub mod bash;
 pub mod fish;
+pub mod json;
 pub mod nu;
 pub mod nu_histdb;
+pub mod powershell;
 pub mod replxx;
 pub mod resh;
 pub mod xonsh;
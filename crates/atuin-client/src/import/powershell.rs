@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use directories::BaseDirs;
+use eyre::{eyre, Result};
+use time::{Duration, OffsetDateTime};
+
+use super::{get_histpath, unix_byte_lines, Importer, Loader};
+use crate::history::History;
+use crate::import::read_to_end;
+
+#[derive(Debug)]
+pub struct Powershell {
+    bytes: Vec<u8>,
+}
+
+/// PSReadLine's history file, see `(Get-PSReadLineOption).HistorySavePath`
+fn default_histpath() -> Result<PathBuf> {
+    let base = BaseDirs::new().ok_or_else(|| eyre!("could not determine data directory"))?;
+
+    let dir = if cfg!(windows) {
+        base.data_dir()
+            .join("Microsoft\\Windows\\PowerShell\\PSReadLine")
+    } else {
+        let data = std::env::var("XDG_DATA_HOME").map_or_else(
+            |_| base.home_dir().join(".local").join("share"),
+            PathBuf::from,
+        );
+
+        data.join("powershell").join("PSReadLine")
+    };
+
+    Ok(dir.join("ConsoleHost_history.txt"))
+}
+
+/// The commands in a history file. PSReadLine saves each line of a multi-line command with a
+/// backtick at the end of every line but the last.
+fn commands(bytes: &[u8]) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current: Option<String> = None;
+
+    for line in unix_byte_lines(bytes) {
+        let Ok(line) = std::str::from_utf8(line) else {
+            // skip past invalid utf8, and the rest of its command
+            current = None;
+            continue;
+        };
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        let (line, continues) = match line.strip_suffix('`') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+
+        let command = match current.take() {
+            Some(mut command) => {
+                command.push('\n');
+                command.push_str(line);
+                command
+            }
+            None => line.to_string(),
+        };
+
+        if continues {
+            current = Some(command);
+        } else if !command.trim().is_empty() {
+            commands.push(command);
+        }
+    }
+
+    // a file cut off partway through a command still has the part before
+    if let Some(command) = current {
+        commands.push(command);
+    }
+
+    commands
+}
+
+#[async_trait]
+impl Importer for Powershell {
+    const NAME: &'static str = "powershell";
+
+    async fn new() -> Result<Self> {
+        let bytes = read_to_end(get_histpath(default_histpath)?)?;
+        Ok(Self { bytes })
+    }
+
+    async fn entries(&mut self) -> Result<usize> {
+        Ok(commands(&self.bytes).len())
+    }
+
+    async fn load(self, h: &mut impl Loader) -> Result<()> {
+        let commands = commands(&self.bytes);
+
+        // PSReadLine doesn't record when anything ran, so count back from now to keep the order
+        let timestamp_increment = Duration::milliseconds(1);
+        let mut timestamp = OffsetDateTime::now_utc() - timestamp_increment * commands.len() as i32;
+
+        for command in commands {
+            let imported = History::import().timestamp(timestamp).command(command);
+
+            h.push(imported.build().into()).await?;
+            timestamp += timestamp_increment;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::{assert_equal, Itertools};
+
+    use crate::import::{tests::TestLoader, Importer};
+
+    use super::Powershell;
+
+    #[tokio::test]
+    async fn parse() {
+        let bytes = b"Get-ChildItem\r
+cd ~/src\r
+\r
+foreach ($x in 1..3) {`\r
+    echo $x`\r
+}\r
+git log --format=`%h\r
+"
+        .to_vec();
+
+        let mut powershell = Powershell { bytes };
+        assert_eq!(powershell.entries().await.unwrap(), 4);
+
+        let mut loader = TestLoader::default();
+        powershell.load(&mut loader).await.unwrap();
+
+        assert_equal(
+            loader.buf.iter().map(|h| h.command.as_str()),
+            [
+                "Get-ChildItem",
+                "cd ~/src",
+                "foreach ($x in 1..3) {\n    echo $x\n}",
+                "git log --format=`%h",
+            ],
+        );
+        assert!(loader
+            .buf
+            .iter()
+            .tuple_windows()
+            .all(|(a, b)| a.timestamp < b.timestamp));
+    }
+}
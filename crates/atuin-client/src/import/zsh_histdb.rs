@@ -59,8 +59,9 @@ pub struct HistDbEntry {
     pub host: Vec<u8>,
     pub dir: Vec<u8>,
     pub argv: Vec<u8>,
-    pub duration: i64,
-    pub exit_status: i64,
+    /// Seconds, or null for a command that was still running when histdb last saw it
+    pub duration: Option<i64>,
+    pub exit_status: Option<i64>,
     pub session: i64,
 }
 
@@ -156,12 +157,13 @@ impl Importer for ZshHistDb {
             );
             let session = session_map.entry(entry.session).or_insert_with(uuid_v7);
 
+            // commands histdb never saw finish keep atuin's unknown -1
             let imported = History::import()
                 .timestamp(entry.start_time.assume_utc())
                 .command(command)
                 .cwd(cwd)
-                .duration(entry.duration * 1_000_000_000)
-                .exit(entry.exit_status)
+                .duration(entry.duration.map_or(-1, |d| d * 1_000_000_000))
+                .exit(entry.exit_status.unwrap_or(-1))
                 .session(session.as_simple().to_string())
                 .hostname(hostname)
                 .build();
@@ -175,6 +177,7 @@ impl Importer for ZshHistDb {
 mod test {
 
     use super::*;
+    use crate::import::tests::TestLoader;
     use sqlx::sqlite::SqlitePoolOptions;
     use std::env;
     #[tokio::test(flavor = "multi_thread")]
@@ -233,15 +236,34 @@ mod test {
 
         // test histdb iterator
         let histdb_vec = hist_from_db_conn(pool).await.unwrap();
-        let histdb = ZshHistDb {
+        let mut histdb = ZshHistDb {
             histdb: histdb_vec,
-            username: get_username(),
+            username: "noyez".to_string(),
         };
 
-        println!("h: {:#?}", histdb.histdb);
-        println!("counter: {:?}", histdb.histdb.len());
-        for i in histdb.histdb {
-            println!("{i:?}");
-        }
+        assert_eq!(histdb.entries().await.unwrap(), 3);
+
+        let mut loader = TestLoader::default();
+        histdb.load(&mut loader).await.unwrap();
+        let history = loader.buf;
+
+        assert_eq!(
+            history
+                .iter()
+                .map(|h| h.command.as_str())
+                .collect::<Vec<_>>(),
+            ["pwd", "curl google.com", "bash"]
+        );
+        assert!(history.iter().all(|h| h.cwd == "/home/noyez"));
+        assert!(history.iter().all(|h| h.hostname == "mbp16.local:noyez"));
+        assert!(history.iter().all(|h| h.session == history[0].session));
+
+        assert_eq!(history[0].timestamp.unix_timestamp(), 1_651_497_918);
+        assert_eq!(history[0].duration, 1_000_000_000);
+        assert_eq!(history[0].exit, 0);
+
+        // still running when histdb last saw it
+        assert_eq!(history[2].duration, -1);
+        assert_eq!(history[2].exit, -1);
     }
 }
@@ -1,35 +1,66 @@
-use std::collections::BTreeMap;
+use std::collections::{btree_map::Entry, BTreeMap};
 
 use atuin_common::record::{DecryptedData, Host, HostId};
 use eyre::{bail, ensure, eyre, Result};
 use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
 
 use crate::record::encryption::PASETO_V4;
 use crate::record::store::Store;
 
 const KV_VERSION: &str = "v0";
+// v1 can also be a tombstone, with no value, and can have a fourth field, when the key expires
+// in unix seconds. Only tombstones and expiring keys are written as v1, so that clients that only
+// know v0 can still read everything else.
+const KV_VERSION_V1: &str = "v1";
 const KV_TAG: &str = "kv";
 const KV_VAL_MAX_LEN: usize = 100 * 1024;
 
+// How many records to read and decrypt at once while updating the index
+const KV_PAGE_SIZE: u64 = 1000;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KvRecord {
     pub namespace: String,
     pub key: String,
     pub value: String,
+    /// A tombstone, left by deleting the key. Its value is empty.
+    pub deleted: bool,
+    /// When the key stops being readable, if it was set with a ttl
+    pub expires: Option<OffsetDateTime>,
 }
 
 impl KvRecord {
+    /// The record version this is serialized as
+    pub fn version(&self) -> &'static str {
+        if self.deleted || self.expires.is_some() {
+            KV_VERSION_V1
+        } else {
+            KV_VERSION
+        }
+    }
+
     pub fn serialize(&self) -> Result<DecryptedData> {
         use rmp::encode;
 
         let mut output = vec![];
 
         // INFO: ensure this is updated when adding new fields
-        encode::write_array_len(&mut output, 3)?;
+        let nfields = if self.expires.is_some() { 4 } else { 3 };
+        encode::write_array_len(&mut output, nfields)?;
 
         encode::write_str(&mut output, &self.namespace)?;
         encode::write_str(&mut output, &self.key)?;
-        encode::write_str(&mut output, &self.value)?;
+
+        if self.deleted {
+            encode::write_nil(&mut output)?;
+        } else {
+            encode::write_str(&mut output, &self.value)?;
+        }
+
+        if let Some(expires) = self.expires {
+            encode::write_sint(&mut output, expires.unix_timestamp())?;
+        }
 
         Ok(DecryptedData(output))
     }
@@ -37,23 +68,44 @@ impl KvRecord {
     pub fn deserialize(data: &DecryptedData, version: &str) -> Result<Self> {
         use rmp::decode;
 
+        const NIL: u8 = 0xc0;
+
         fn error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
             eyre!("{err:?}")
         }
 
         match version {
-            KV_VERSION => {
+            KV_VERSION | KV_VERSION_V1 => {
                 let mut bytes = decode::Bytes::new(&data.0);
 
                 let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
-                ensure!(nfields == 3, "too many entries in v0 kv record");
+                ensure!(
+                    nfields == 3 || (nfields == 4 && version == KV_VERSION_V1),
+                    "wrong number of entries in {version} kv record"
+                );
 
                 let bytes = bytes.remaining_slice();
 
                 let (namespace, bytes) =
                     decode::read_str_from_slice(bytes).map_err(error_report)?;
                 let (key, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
-                let (value, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+                let (value, deleted, bytes) = match bytes.split_first() {
+                    Some((&NIL, rest)) if version == KV_VERSION_V1 => ("", true, rest),
+                    _ => {
+                        let (value, bytes) =
+                            decode::read_str_from_slice(bytes).map_err(error_report)?;
+                        (value, false, bytes)
+                    }
+                };
+
+                let mut bytes = bytes;
+                let expires = if nfields == 4 {
+                    let secs: i64 = decode::read_int(&mut bytes).map_err(error_report)?;
+                    Some(OffsetDateTime::from_unix_timestamp(secs)?)
+                } else {
+                    None
+                };
 
                 if !bytes.is_empty() {
                     bail!("trailing bytes in encoded kvrecord. malformed")
@@ -63,6 +115,8 @@ impl KvRecord {
                     namespace: namespace.to_owned(),
                     key: key.to_owned(),
                     value: value.to_owned(),
+                    deleted,
+                    expires,
                 })
             }
             _ => {
@@ -96,19 +150,78 @@ impl KvStore {
         key: &str,
         value: &str,
     ) -> Result<()> {
-        if value.len() > KV_VAL_MAX_LEN {
-            return Err(eyre!(
-                "kv value too large: max len {} bytes",
-                KV_VAL_MAX_LEN
-            ));
-        }
+        let record = KvRecord {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            deleted: false,
+            expires: None,
+        };
+
+        self.push(store, encryption_key, host_id, &record).await
+    }
+
+    /// Set a key that can only be read for `ttl`. After that it reads as unset, and the next
+    /// [`purge_expired`](Self::purge_expired) drops its value from the index.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn set_with_ttl(
+        &self,
+        store: &(impl Store + Send + Sync),
+        encryption_key: &[u8; 32],
+        host_id: HostId,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<()> {
+        ensure!(ttl.is_positive(), "kv ttl must be positive");
 
         let record = KvRecord {
             namespace: namespace.to_string(),
             key: key.to_string(),
             value: value.to_string(),
+            deleted: false,
+            expires: Some(OffsetDateTime::now_utc() + ttl),
         };
 
+        self.push(store, encryption_key, host_id, &record).await
+    }
+
+    /// Delete a key, by leaving a tombstone. It syncs like any other write, so the key is gone
+    /// everywhere, unless it's set again later.
+    pub async fn delete(
+        &self,
+        store: &(impl Store + Send + Sync),
+        encryption_key: &[u8; 32],
+        host_id: HostId,
+        namespace: &str,
+        key: &str,
+    ) -> Result<()> {
+        let record = KvRecord {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            value: String::new(),
+            deleted: true,
+            expires: None,
+        };
+
+        self.push(store, encryption_key, host_id, &record).await
+    }
+
+    async fn push(
+        &self,
+        store: &(impl Store + Send + Sync),
+        encryption_key: &[u8; 32],
+        host_id: HostId,
+        record: &KvRecord,
+    ) -> Result<()> {
+        if record.value.len() > KV_VAL_MAX_LEN {
+            return Err(eyre!(
+                "kv value too large: max len {} bytes",
+                KV_VAL_MAX_LEN
+            ));
+        }
+
         let bytes = record.serialize()?;
 
         let idx = store
@@ -118,7 +231,7 @@ impl KvStore {
 
         let record = atuin_common::record::Record::builder()
             .host(Host::new(host_id))
-            .version(KV_VERSION.to_string())
+            .version(record.version().to_string())
             .tag(KV_TAG.to_string())
             .idx(idx)
             .data(bytes)
@@ -128,11 +241,10 @@ impl KvStore {
             .push(&record.encrypt::<PASETO_V4>(encryption_key))
             .await?;
 
-        Ok(())
+        // picks up this record, and any others that arrived since the index was last updated
+        self.update(store, encryption_key).await
     }
 
-    // TODO: setup an actual kv store, rebuild func, and do not pass the main store in here as
-    // well.
     pub async fn get(
         &self,
         store: &impl Store,
@@ -140,24 +252,93 @@ impl KvStore {
         namespace: &str,
         key: &str,
     ) -> Result<Option<KvRecord>> {
-        // TODO: don't rebuild every time...
-        let map = self.build_kv(store, encryption_key).await?;
+        self.ensure_built(store, encryption_key).await?;
+
+        store.kv_get(namespace, key).await
+    }
+
+    /// Every key, or only those in one namespace, sorted by namespace then key
+    pub async fn list(
+        &self,
+        store: &impl Store,
+        encryption_key: &[u8; 32],
+        namespace: Option<&str>,
+    ) -> Result<Vec<KvRecord>> {
+        self.ensure_built(store, encryption_key).await?;
 
-        let res = map.get(namespace);
+        store.kv_list(namespace).await
+    }
 
-        if let Some(ns) = res {
-            let value = ns.get(key);
+    /// Drop the values of expired keys from the index, returning how many there were. Reads
+    /// already skip them, so this only keeps them from sitting on disk.
+    pub async fn purge_expired(&self, store: &impl Store) -> Result<u64> {
+        store.kv_purge_expired(OffsetDateTime::now_utc()).await
+    }
 
-            Ok(value.cloned())
-        } else {
-            Ok(None)
+    /// Build the kv index from scratch
+    pub async fn build(&self, store: &impl Store, encryption_key: &[u8; 32]) -> Result<()> {
+        store.kv_clear().await?;
+
+        self.update(store, encryption_key).await
+    }
+
+    /// Add kv records to the index that it doesn't have yet, like those just downloaded by a
+    /// sync. Each host's records arrive in order, so only those after the last one it has from
+    /// each host need reading.
+    pub async fn update(&self, store: &impl Store, encryption_key: &[u8; 32]) -> Result<()> {
+        let status = store.status().await?;
+        let applied = store.kv_applied().await?;
+
+        for (host, tags) in &status.hosts {
+            let Some(&last) = tags.get(KV_TAG) else {
+                continue;
+            };
+
+            let mut start = applied.get(host).map_or(0, |idx| idx + 1);
+
+            while start <= last {
+                let page = store.next(*host, KV_TAG, start, KV_PAGE_SIZE).await?;
+
+                let Some(idx) = page.last().map(|r| r.idx) else {
+                    break;
+                };
+
+                let records = page
+                    .into_iter()
+                    .map(|record| {
+                        let timestamp = record.timestamp;
+                        let version = record.version.clone();
+                        let decrypted = match version.as_str() {
+                            KV_VERSION | KV_VERSION_V1 => {
+                                record.decrypt::<PASETO_V4>(encryption_key)?
+                            }
+                            version => bail!("unknown version {version:?}"),
+                        };
+
+                        Ok((KvRecord::deserialize(&decrypted.data, &version)?, timestamp))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                store.kv_apply(*host, idx, &records).await?;
+
+                start = idx + 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The index starts empty, for stores that had kv records before it existed
+    async fn ensure_built(&self, store: &impl Store, encryption_key: &[u8; 32]) -> Result<()> {
+        if store.kv_applied().await?.is_empty() {
+            self.update(store, encryption_key).await?;
         }
+
+        Ok(())
     }
 
-    // Build a kv map out of the linked list kv store
-    // Map is Namespace -> Key -> Value
-    // TODO(ellie): "cache" this into a real kv structure, which we can
-    // use as a write-through cache to avoid constant rebuilds.
+    /// Build a kv map by replaying every kv record, rather than reading the index.
+    /// Map is Namespace -> Key -> Value
     pub async fn build_kv(
         &self,
         store: &impl Store,
@@ -170,22 +351,42 @@ impl KvStore {
         // or, iterate/paginate
         let tagged = store.all_tagged(KV_TAG).await?;
 
-        // iterate through all tags and play each KV record at a time
-        // this is "last write wins"
-        // probably good enough for now, but revisit in future
+        // the latest write to each key, by the same rule as the index: last write wins, with
+        // ties going to the greater host id
+        let mut latest: BTreeMap<(String, String), (u64, HostId, KvRecord)> = BTreeMap::new();
+
         for record in tagged {
-            let decrypted = match record.version.as_str() {
-                KV_VERSION => record.decrypt::<PASETO_V4>(encryption_key)?,
+            let version = record.version.clone();
+            let (timestamp, host) = (record.timestamp, record.host.id);
+            let decrypted = match version.as_str() {
+                KV_VERSION | KV_VERSION_V1 => record.decrypt::<PASETO_V4>(encryption_key)?,
                 version => bail!("unknown version {version:?}"),
             };
 
-            let kv = KvRecord::deserialize(&decrypted.data, KV_VERSION)?;
+            let kv = KvRecord::deserialize(&decrypted.data, &version)?;
 
-            let ns = map
-                .entry(kv.namespace.clone())
-                .or_insert_with(BTreeMap::new);
+            match latest.entry((kv.namespace.clone(), kv.key.clone())) {
+                Entry::Occupied(mut entry) => {
+                    let (t, h, _) = entry.get();
 
-            ns.insert(kv.key.clone(), kv);
+                    if (timestamp, host) >= (*t, *h) {
+                        entry.insert((timestamp, host, kv));
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert((timestamp, host, kv));
+                }
+            }
+        }
+
+        let now = OffsetDateTime::now_utc();
+
+        for (_, _, kv) in latest.into_values() {
+            if !kv.deleted && !kv.expires.is_some_and(|expires| expires <= now) {
+                map.entry(kv.namespace.clone())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(kv.key.clone(), kv);
+            }
         }
 
         Ok(map)
@@ -197,10 +398,16 @@ mod tests {
     use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
     use rand::rngs::OsRng;
 
-    use crate::record::sqlite_store::SqliteStore;
+    use atuin_common::record::{Host, HostId, Record};
+    use time::{Duration, OffsetDateTime};
+
+    use crate::record::encryption::PASETO_V4;
+    use crate::record::{sqlite_store::SqliteStore, store::Store};
     use crate::settings::test_local_timeout;
 
-    use super::{KvRecord, KvStore, KV_VERSION};
+    use std::collections::BTreeMap;
+
+    use super::{KvRecord, KvStore, KV_TAG, KV_VERSION, KV_VERSION_V1};
 
     #[test]
     fn encode_decode() {
@@ -208,6 +415,8 @@ mod tests {
             namespace: "foo".to_owned(),
             key: "bar".to_owned(),
             value: "baz".to_owned(),
+            deleted: false,
+            expires: None,
         };
         let snapshot = [
             0x93, 0xa3, b'f', b'o', b'o', 0xa3, b'b', b'a', b'r', 0xa3, b'b', b'a', b'z',
@@ -247,7 +456,9 @@ mod tests {
             KvRecord {
                 namespace: String::from("test-kv"),
                 key: String::from("foo"),
-                value: String::from("bar")
+                value: String::from("bar"),
+                deleted: false,
+                expires: None,
             }
         );
 
@@ -259,8 +470,343 @@ mod tests {
             KvRecord {
                 namespace: String::from("test-kv"),
                 key: String::from("1"),
-                value: String::from("2")
+                value: String::from("2"),
+                deleted: false,
+                expires: None,
             }
         );
     }
+
+    #[tokio::test]
+    async fn index() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let kv = KvStore::new();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+        kv.set(&store, &key, host_id, "test-kv", "foo", "bar")
+            .await
+            .unwrap();
+        kv.set(&store, &key, host_id, "test-kv", "foo", "baz")
+            .await
+            .unwrap();
+
+        let value = kv.get(&store, &key, "test-kv", "foo").await.unwrap();
+        assert_eq!(value.map(|kv| kv.value), Some("baz".to_string()));
+
+        // records that arrive without going through the kv store, like from a sync, are picked
+        // up by the next update
+        let other = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let other_host = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+        kv.set(&other, &key, other_host, "other", "1", "2")
+            .await
+            .unwrap();
+        store
+            .push_batch(other.all_tagged(KV_TAG).await.unwrap().iter())
+            .await
+            .unwrap();
+
+        assert!(kv.get(&store, &key, "other", "1").await.unwrap().is_none());
+
+        kv.update(&store, &key).await.unwrap();
+
+        let value = kv.get(&store, &key, "other", "1").await.unwrap();
+        assert_eq!(value.map(|kv| kv.value), Some("2".to_string()));
+
+        // and the index agrees with replaying every record
+        let map = kv.build_kv(&store, &key).await.unwrap();
+        let replayed: Vec<_> = map.into_values().flat_map(BTreeMap::into_values).collect();
+
+        assert_eq!(kv.list(&store, &key, None).await.unwrap(), replayed);
+
+        kv.build(&store, &key).await.unwrap();
+        assert_eq!(kv.list(&store, &key, None).await.unwrap(), replayed);
+    }
+
+    #[test]
+    fn encode_decode_tombstone() {
+        let kv = KvRecord {
+            namespace: "foo".to_owned(),
+            key: "bar".to_owned(),
+            value: String::new(),
+            deleted: true,
+            expires: None,
+        };
+        let snapshot = [0x93, 0xa3, b'f', b'o', b'o', 0xa3, b'b', b'a', b'r', 0xc0];
+
+        assert_eq!(kv.version(), KV_VERSION_V1);
+
+        let encoded = kv.serialize().unwrap();
+        let decoded = KvRecord::deserialize(&encoded, KV_VERSION_V1).unwrap();
+
+        assert_eq!(encoded.0, &snapshot);
+        assert_eq!(decoded, kv);
+
+        // v0 records never have a nil value
+        assert!(KvRecord::deserialize(&encoded, KV_VERSION).is_err());
+    }
+
+    #[tokio::test]
+    async fn delete() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let kv = KvStore::new();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+        kv.set(&store, &key, host_id, "deploy", "region", "eu-west-1")
+            .await
+            .unwrap();
+        kv.set(&store, &key, host_id, "deploy", "stage", "prod")
+            .await
+            .unwrap();
+        kv.set(&store, &key, host_id, "default", "foo", "bar")
+            .await
+            .unwrap();
+
+        kv.delete(&store, &key, host_id, "deploy", "region")
+            .await
+            .unwrap();
+
+        assert!(kv
+            .get(&store, &key, "deploy", "region")
+            .await
+            .unwrap()
+            .is_none());
+
+        let keys: Vec<_> = kv
+            .list(&store, &key, Some("deploy"))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect();
+        assert_eq!(keys, ["stage"]);
+
+        // a deleted key can be set again
+        kv.set(&store, &key, host_id, "deploy", "region", "us-east-1")
+            .await
+            .unwrap();
+        let value = kv.get(&store, &key, "deploy", "region").await.unwrap();
+        assert_eq!(value.map(|kv| kv.value), Some("us-east-1".to_string()));
+
+        // deleting the only key in a namespace drops the namespace
+        kv.delete(&store, &key, host_id, "default", "foo")
+            .await
+            .unwrap();
+
+        let map = kv.build_kv(&store, &key).await.unwrap();
+        assert!(!map.contains_key("default"));
+
+        kv.build(&store, &key).await.unwrap();
+        let replayed: Vec<_> = map.into_values().flat_map(BTreeMap::into_values).collect();
+        assert_eq!(kv.list(&store, &key, None).await.unwrap(), replayed);
+    }
+
+    // Write a kv record with a chosen timestamp, as if it came from another machine
+    async fn push_at(
+        store: &SqliteStore,
+        key: &[u8; 32],
+        host: HostId,
+        timestamp: u64,
+        value: &str,
+    ) {
+        let kv = KvRecord {
+            namespace: "default".to_owned(),
+            key: "foo".to_owned(),
+            value: value.to_owned(),
+            deleted: false,
+            expires: None,
+        };
+
+        let idx = store
+            .last(host, KV_TAG)
+            .await
+            .unwrap()
+            .map_or(0, |entry| entry.idx + 1);
+
+        let record = Record::builder()
+            .host(Host::new(host))
+            .version(KV_VERSION.to_string())
+            .tag(KV_TAG.to_string())
+            .idx(idx)
+            .timestamp(timestamp)
+            .data(kv.serialize().unwrap())
+            .build();
+
+        store.push(&record.encrypt::<PASETO_V4>(key)).await.unwrap();
+    }
+
+    async fn sync(from: &SqliteStore, to: &SqliteStore) {
+        let status = to.status().await.unwrap();
+        let records: Vec<_> = from
+            .all_tagged(KV_TAG)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|r| status.get(r.host.id, KV_TAG.into()).is_none())
+            .collect();
+
+        to.push_batch(records.iter()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes() {
+        let kv = KvStore::new();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+
+        let desktop = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let laptop = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let desktop_host = HostId(atuin_common::utils::uuid_v7());
+        let laptop_host = HostId(atuin_common::utils::uuid_v7());
+
+        // both set the same key before either syncs. The laptop's write is newer.
+        push_at(&desktop, &key, desktop_host, 100, "desktop").await;
+        push_at(&desktop, &key, desktop_host, 200, "desktop again").await;
+        push_at(&laptop, &key, laptop_host, 150, "laptop").await;
+        push_at(&laptop, &key, laptop_host, 300, "laptop again").await;
+
+        kv.update(&desktop, &key).await.unwrap();
+        kv.update(&laptop, &key).await.unwrap();
+
+        sync(&desktop, &laptop).await;
+        sync(&laptop, &desktop).await;
+
+        kv.update(&desktop, &key).await.unwrap();
+        kv.update(&laptop, &key).await.unwrap();
+
+        for store in [&desktop, &laptop] {
+            let value = kv.get(store, &key, "default", "foo").await.unwrap();
+            assert_eq!(value.map(|kv| kv.value), Some("laptop again".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn timestamp_tie() {
+        let kv = KvStore::new();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+
+        let a = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let b = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let a_host = HostId(atuin_common::utils::uuid_v7());
+        let b_host = HostId(atuin_common::utils::uuid_v7());
+
+        push_at(&a, &key, a_host, 100, "a").await;
+        push_at(&b, &key, b_host, 100, "b").await;
+
+        // each sees its own write first, then the other's
+        kv.update(&a, &key).await.unwrap();
+        kv.update(&b, &key).await.unwrap();
+
+        sync(&a, &b).await;
+        sync(&b, &a).await;
+
+        kv.update(&a, &key).await.unwrap();
+        kv.update(&b, &key).await.unwrap();
+
+        let winner = if a_host > b_host { "a" } else { "b" };
+
+        for store in [&a, &b] {
+            let value = kv.get(store, &key, "default", "foo").await.unwrap();
+            assert_eq!(value.map(|kv| kv.value), Some(winner.to_string()));
+
+            // replaying every record agrees
+            let map = kv.build_kv(store, &key).await.unwrap();
+            assert_eq!(map["default"]["foo"].value, winner);
+        }
+    }
+
+    #[test]
+    fn encode_decode_expiring() {
+        let kv = KvRecord {
+            namespace: "foo".to_owned(),
+            key: "bar".to_owned(),
+            value: "baz".to_owned(),
+            deleted: false,
+            expires: Some(OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap()),
+        };
+
+        assert_eq!(kv.version(), KV_VERSION_V1);
+
+        let encoded = kv.serialize().unwrap();
+        let decoded = KvRecord::deserialize(&encoded, KV_VERSION_V1).unwrap();
+
+        assert_eq!(encoded.0[0], 0x94);
+        assert_eq!(decoded, kv);
+    }
+
+    #[tokio::test]
+    async fn ttl() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let kv = KvStore::new();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = HostId(atuin_common::utils::uuid_v7());
+
+        kv.set_with_ttl(
+            &store,
+            &key,
+            host_id,
+            "default",
+            "url",
+            "https://example.com/once",
+            Duration::hours(1),
+        )
+        .await
+        .unwrap();
+
+        // one that has already expired, as if it was set a while ago on another machine
+        let expired = KvRecord {
+            namespace: "default".to_owned(),
+            key: "token".to_owned(),
+            value: "abc".to_owned(),
+            deleted: false,
+            expires: Some(OffsetDateTime::now_utc() - Duration::minutes(1)),
+        };
+        kv.push(&store, &key, host_id, &expired).await.unwrap();
+
+        let value = kv.get(&store, &key, "default", "url").await.unwrap();
+        assert!(value.unwrap().expires.is_some());
+
+        assert!(kv
+            .get(&store, &key, "default", "token")
+            .await
+            .unwrap()
+            .is_none());
+
+        let keys: Vec<_> = kv
+            .list(&store, &key, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect();
+        assert_eq!(keys, ["url"]);
+
+        let map = kv.build_kv(&store, &key).await.unwrap();
+        assert_eq!(map["default"].keys().collect::<Vec<_>>(), ["url"]);
+
+        assert_eq!(kv.purge_expired(&store).await.unwrap(), 1);
+        assert_eq!(kv.purge_expired(&store).await.unwrap(), 0);
+
+        assert!(kv
+            .set_with_ttl(&store, &key, host_id, "default", "x", "y", Duration::ZERO)
+            .await
+            .is_err());
+    }
 }
@@ -8,18 +8,25 @@ pub mod api_client;
 #[cfg(feature = "sync")]
 pub mod sync;
 
+pub mod backup;
+pub mod channel;
 pub mod database;
 pub mod encryption;
+pub mod export;
 pub mod history;
 pub mod import;
 pub mod kv;
 pub mod login;
 pub mod logout;
+pub mod network;
 pub mod ordering;
 pub mod record;
 pub mod register;
 pub mod secrets;
+pub mod session;
 pub mod settings;
 pub mod theme;
+#[cfg(feature = "self-update")]
+pub mod update;
 
 mod utils;
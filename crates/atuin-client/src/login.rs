@@ -1,5 +1,3 @@
-use std::path::PathBuf;
-
 use atuin_common::api::LoginRequest;
 use eyre::{bail, Context, Result};
 use tokio::fs::File;
@@ -7,7 +5,7 @@ use tokio::io::AsyncWriteExt;
 
 use crate::{
     api_client,
-    encryption::{decode_key, encode_key, load_key, Key},
+    encryption::{decode_key, encode_key, key_exists, load_key, save_key, Key},
     record::{sqlite_store::SqliteStore, store::Store},
     settings::Settings,
 };
@@ -43,16 +41,12 @@ pub async fn login(
         }
     };
 
-    let key_path = settings.key_path.as_str();
-    let key_path = PathBuf::from(key_path);
-
-    if !key_path.exists() {
+    if !key_exists(settings)? {
         if decode_key(key.clone()).is_err() {
             bail!("the specified key was invalid");
         }
 
-        let mut file = File::create(key_path).await?;
-        file.write_all(key.as_bytes()).await?;
+        save_key(settings, &key)?;
     } else {
         // we now know that the user has logged in specifying a key, AND that the key path
         // exists
@@ -72,14 +66,17 @@ pub async fn login(
             store.re_encrypt(&current_key, &new_key).await?;
 
             println!("Writing new key");
-            let mut file = File::create(key_path).await?;
-            file.write_all(encoded.as_bytes()).await?;
+            save_key(settings, &encoded)?;
         }
     }
 
     let session = api_client::login(
         settings.sync_address.as_str(),
-        LoginRequest { username, password },
+        LoginRequest {
+            username,
+            password,
+            totp: None,
+        },
     )
     .await?;
 
@@ -0,0 +1,88 @@
+// Detecting the network we're on, so that sync can be scheduled around it.
+//
+// This is best effort. Currently we only know how to ask NetworkManager, so anywhere else we
+// can't tell, and sync carries on as if there were no network specific settings.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    /// The name of the connection, usually the SSID for wifi
+    pub name: Option<String>,
+    pub metered: bool,
+}
+
+impl Network {
+    #[cfg(target_os = "linux")]
+    pub fn current() -> Option<Network> {
+        use std::process::Command;
+
+        fn nmcli(args: &[&str]) -> Option<String> {
+            let output = Command::new("nmcli").arg("-t").args(args).output().ok()?;
+
+            if !output.status.success() {
+                return None;
+            }
+
+            String::from_utf8(output.stdout).ok()
+        }
+
+        // The primary connection is listed first
+        let active = nmcli(&["-f", "NAME,DEVICE,TYPE", "connection", "show", "--active"])?;
+        let (name, device) = active.lines().find_map(parse_active_connection)?;
+
+        let metered = nmcli(&["-g", "GENERAL.METERED", "device", "show", &device])
+            .is_some_and(|m| m.trim().starts_with("yes"));
+
+        Some(Network {
+            name: Some(name),
+            metered,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn current() -> Option<Network> {
+        None
+    }
+}
+
+// Parse a line of `nmcli -t -f NAME,DEVICE,TYPE connection show --active`, skipping loopback.
+// Terse output escapes colons within fields with a backslash.
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn parse_active_connection(line: &str) -> Option<(String, String)> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => field.extend(chars.next()),
+            ':' => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    match fields.as_slice() {
+        [_, _, kind] if kind == "loopback" => None,
+        [name, device, _] if !device.is_empty() => Some((name.clone(), device.clone())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_active_connection;
+
+    #[test]
+    fn parse_nmcli_connection() {
+        assert_eq!(
+            parse_active_connection("Home Wifi:wlp2s0:802-11-wireless"),
+            Some(("Home Wifi".to_owned(), "wlp2s0".to_owned()))
+        );
+        assert_eq!(
+            parse_active_connection(r"cafe\:free:wlp2s0:802-11-wireless"),
+            Some(("cafe:free".to_owned(), "wlp2s0".to_owned()))
+        );
+        assert_eq!(parse_active_connection("lo:lo:loopback"), None);
+        assert_eq!(parse_active_connection("garbage"), None);
+    }
+}
@@ -0,0 +1,225 @@
+//! A portable copy of the record store, for moving it between machines by hand, keeping a backup,
+//! or carrying it somewhere sync can't reach.
+//!
+//! An archive is a zstd compressed stream of JSON lines. The first is a [`Header`], saying which
+//! version of the format it is and what it holds. Every line after that is a record, exactly as
+//! it's stored, still encrypted. Importing one needs the same key as the store it came from.
+
+use std::io::{BufRead, BufReader, Read, Write};
+
+use eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use atuin_common::record::{EncryptedData, HostId, Record, RecordDigest, RecordId};
+
+use super::store::Store;
+
+const FORMAT: &str = "atuin-store";
+
+/// The newest version of the format this can read
+pub const VERSION: u32 = 1;
+
+const PAGE_SIZE: u64 = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u32,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+
+    /// The host that made it
+    pub host: HostId,
+
+    /// The count and last record of every host and tag in it
+    pub digest: RecordDigest,
+}
+
+impl Header {
+    /// How many records the archive should hold
+    pub fn count(&self) -> u64 {
+        self.digest.entries.iter().map(|e| e.count).sum()
+    }
+}
+
+/// What [`import`] found
+#[derive(Debug)]
+pub struct Imported {
+    pub header: Header,
+
+    /// Records that weren't in the store already
+    pub records: Vec<RecordId>,
+
+    /// Every record read, including those already in the store
+    pub read: u64,
+}
+
+/// Write every record in the store to `writer` as an archive
+pub async fn export(store: &impl Store, host: HostId, writer: impl Write) -> Result<Header> {
+    let header = Header {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        created: OffsetDateTime::now_utc(),
+        host,
+        digest: store.digest().await?,
+    };
+
+    let mut writer = zstd::stream::write::Encoder::new(writer, 3)?;
+
+    serde_json::to_writer(&mut writer, &header)?;
+    writer.write_all(b"\n")?;
+
+    for entry in &header.digest.entries {
+        let mut start = 0;
+
+        loop {
+            let page = store.next(entry.host, &entry.tag, start, PAGE_SIZE).await?;
+
+            let Some(last) = page.last() else {
+                break;
+            };
+            start = last.idx + 1;
+
+            for record in &page {
+                serde_json::to_writer(&mut writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+
+    writer.finish()?.flush()?;
+
+    Ok(header)
+}
+
+/// Add the records in an archive to the store. Any it has already are skipped.
+pub async fn import(store: &impl Store, reader: impl Read) -> Result<Imported> {
+    let mut lines = BufReader::new(zstd::stream::read::Decoder::new(reader)?).lines();
+
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).context("not an atuin store archive")?,
+        None => bail!("the archive is empty"),
+    };
+
+    if header.format != FORMAT {
+        bail!("not an atuin store archive");
+    }
+
+    if header.version > VERSION {
+        bail!(
+            "this archive is version {}, but this version of atuin can only read up to {VERSION}. Try upgrading atuin",
+            header.version
+        );
+    }
+
+    // Records are added to a store in order, so anything up to its last idx is already here
+    let status = store.status().await?;
+
+    let mut imported = Imported {
+        header,
+        records: Vec::new(),
+        read: 0,
+    };
+    let mut batch = Vec::new();
+
+    for line in lines {
+        let record: Record<EncryptedData> =
+            serde_json::from_str(&line?).context("the archive has a corrupt record")?;
+        imported.read += 1;
+
+        if status
+            .get(record.host.id, record.tag.clone())
+            .is_some_and(|idx| idx >= record.idx)
+        {
+            continue;
+        }
+
+        batch.push(record);
+
+        if batch.len() as u64 >= PAGE_SIZE {
+            store.push_batch(batch.iter()).await?;
+            imported.records.extend(batch.drain(..).map(|r| r.id));
+        }
+    }
+
+    store.push_batch(batch.iter()).await?;
+    imported.records.extend(batch.into_iter().map(|r| r.id));
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_common::{
+        record::{EncryptedData, Host, HostId, Record},
+        utils::uuid_v7,
+    };
+
+    use crate::{
+        record::{sqlite_store::SqliteStore, store::Store},
+        settings::test_local_timeout,
+    };
+
+    use super::{export, import};
+
+    fn records(host: HostId, tag: &str, count: u64) -> Vec<Record<EncryptedData>> {
+        (0..count)
+            .map(|idx| {
+                Record::builder()
+                    .host(Host::new(host))
+                    .version("v0".into())
+                    .tag(tag.into())
+                    .data(EncryptedData {
+                        data: format!("data {idx}"),
+                        content_encryption_key: "cek".into(),
+                    })
+                    .idx(idx)
+                    .build()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let from = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let to = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let host = HostId(uuid_v7());
+        let history = records(host, "history", 1500);
+        let kv = records(host, "kv", 3);
+
+        from.push_batch(history.iter().chain(kv.iter()))
+            .await
+            .unwrap();
+
+        // the store being imported to already has some of them
+        to.push_batch(history[..100].iter()).await.unwrap();
+
+        let mut archive = Vec::new();
+        let header = export(&from, host, &mut archive).await.unwrap();
+        assert_eq!(header.count(), 1503);
+
+        let imported = import(&to, archive.as_slice()).await.unwrap();
+        assert_eq!(imported.read, 1503);
+        assert_eq!(imported.records.len(), 1403);
+
+        assert_eq!(to.digest().await.unwrap(), from.digest().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rejects_other_files() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let archive = zstd::bulk::compress(b"{\"hello\": \"world\"}\n", 3).unwrap();
+
+        assert!(import(&store, archive.as_slice()).await.is_err());
+    }
+}
@@ -0,0 +1,392 @@
+//! Syncing the record store without a sync server, by keeping records in a dumb blob target: an
+//! S3 bucket, a WebDAV share, or a directory that something else keeps in sync, like a mounted
+//! drive or a syncthing folder.
+//!
+//! Records are kept exactly as they're uploaded to the server, still encrypted. Each host and tag
+//! has its records split into chunks of [`CHUNK_SIZE`], by idx, and a host's `stores` keeps
+//! the count and last record of each of its tags. `hosts` lists every host, as not every
+//! target can list what's in it.
+//!
+//! ```text
+//! hosts.json.zst
+//! <host>/stores.json.zst
+//! <host>/<tag>/<chunk>.json.zst
+//! ```
+//!
+//! Only one host writes to a store, so nothing is ever written by two hosts at once, other than
+//! `hosts` the first time a host syncs. Every upload makes sure its host is still listed, so
+//! one lost in a race is added back next time.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use eyre::{bail, Context, Result};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Serialize};
+
+use atuin_common::record::{
+    DigestEntry, EncryptedData, HostId, Record, RecordDigest, RecordIdx, RecordStatus,
+};
+
+use super::remote::Remote;
+
+mod s3;
+mod webdav;
+
+pub use s3::S3;
+pub use webdav::WebDav;
+
+/// Records per chunk. Adding records rewrites the last chunk, so this is kept small.
+const CHUNK_SIZE: u64 = 256;
+
+const HOSTS: &str = "hosts.json.zst";
+
+// Everything is zstd compressed JSON. Chunks are mostly base64 and repeated JSON keys, so they compress well
+const ZSTD_LEVEL: i32 = 3;
+
+#[async_trait]
+pub trait BlobTarget: Send + Sync {
+    /// Read a blob, or None if there isn't one with this key
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Write a blob, replacing any there already
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()>;
+}
+
+/// A directory on this machine
+pub struct Directory {
+    path: std::path::PathBuf,
+}
+
+impl Directory {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl BlobTarget for Directory {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("could not read {key}")),
+        }
+    }
+
+    // Written to a temporary file first, so whatever syncs the directory never sees half a blob
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.path.join(key);
+        let tmp = path.with_extension(format!(
+            "tmp-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tokio::fs::write(&tmp, data)
+            .await
+            .with_context(|| format!("could not write {key}"))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .with_context(|| format!("could not write {key}"))?;
+
+        Ok(())
+    }
+}
+
+/// Open the blob target for a `sync_backend` URL
+pub fn target(url: &str) -> Result<Box<dyn BlobTarget>> {
+    let parsed = Url::parse(url).with_context(|| format!("invalid sync_backend {url:?}"))?;
+
+    Ok(match parsed.scheme() {
+        "file" => match parsed.to_file_path() {
+            Ok(path) => Box::new(Directory::new(path)),
+            Err(_) => bail!("sync_backend {url:?} is not a local path"),
+        },
+        "s3" => Box::new(S3::from_url(&parsed)?),
+        "dav" | "davs" => Box::new(WebDav::from_url(&parsed)?),
+        scheme => bail!("unknown sync_backend {scheme:?}, expected one of s3, dav, davs, or file"),
+    })
+}
+
+/// Syncs records with a [`BlobTarget`], rather than a sync server
+pub struct BlobRemote {
+    target: Box<dyn BlobTarget>,
+}
+
+impl BlobRemote {
+    pub fn new(target: Box<dyn BlobTarget>) -> Self {
+        Self { target }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let Some(data) = self.target.get(key).await? else {
+            return Ok(None);
+        };
+
+        let json = zstd::stream::decode_all(data.as_slice())
+            .with_context(|| format!("could not decompress {key}"))?;
+
+        Ok(Some(
+            serde_json::from_slice(&json).with_context(|| format!("could not parse {key}"))?,
+        ))
+    }
+
+    async fn put_json(&self, key: &str, value: &impl Serialize) -> Result<()> {
+        let data = zstd::bulk::compress(&serde_json::to_vec(value)?, ZSTD_LEVEL)?;
+
+        self.target.put(key, data).await
+    }
+
+    async fn hosts(&self) -> Result<Vec<HostId>> {
+        Ok(self.get_json(HOSTS).await?.unwrap_or_default())
+    }
+
+    async fn stores(&self, host: HostId) -> Result<Vec<DigestEntry>> {
+        Ok(self.get_json(&stores_key(host)).await?.unwrap_or_default())
+    }
+
+    async fn chunk(
+        &self,
+        host: HostId,
+        tag: &str,
+        chunk: u64,
+    ) -> Result<Vec<Record<EncryptedData>>> {
+        Ok(self
+            .get_json(&chunk_key(host, tag, chunk))
+            .await?
+            .unwrap_or_default())
+    }
+
+    // Add records to one host and tag, returning how many weren't there already
+    async fn add(
+        &self,
+        host: HostId,
+        tag: &str,
+        records: &[&Record<EncryptedData>],
+    ) -> Result<u64> {
+        let mut chunks: BTreeMap<u64, Vec<&Record<EncryptedData>>> = BTreeMap::new();
+
+        for record in records {
+            chunks
+                .entry(record.idx / CHUNK_SIZE)
+                .or_default()
+                .push(record);
+        }
+
+        let mut added = 0;
+
+        for (chunk, records) in chunks {
+            let mut existing: BTreeMap<RecordIdx, Record<EncryptedData>> = self
+                .chunk(host, tag, chunk)
+                .await?
+                .into_iter()
+                .map(|r| (r.idx, r))
+                .collect();
+
+            let before = existing.len();
+
+            for record in records {
+                existing
+                    .entry(record.idx)
+                    .or_insert_with(|| (*record).clone());
+            }
+
+            if existing.len() > before {
+                added += (existing.len() - before) as u64;
+
+                let records: Vec<_> = existing.into_values().collect();
+                self.put_json(&chunk_key(host, tag, chunk), &records)
+                    .await?;
+            }
+        }
+
+        Ok(added)
+    }
+}
+
+// Keys are made of path segments, each encoded to be safe in a URL
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(urlencoding::encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn stores_key(host: HostId) -> String {
+    format!("{}/stores.json.zst", host.0.as_simple())
+}
+
+fn chunk_key(host: HostId, tag: &str, chunk: u64) -> String {
+    format!(
+        "{}/{}/{chunk}.json.zst",
+        host.0.as_simple(),
+        urlencoding::encode(tag)
+    )
+}
+
+#[async_trait]
+impl Remote for BlobRemote {
+    async fn status(&self) -> Result<RecordStatus> {
+        let mut status = RecordStatus::new();
+
+        for entry in self.digest().await?.entries {
+            status.set_raw(entry.host, entry.tag, entry.idx);
+        }
+
+        Ok(status)
+    }
+
+    async fn digest(&self) -> Result<RecordDigest> {
+        let mut entries = Vec::new();
+
+        for host in self.hosts().await? {
+            entries.extend(self.stores(host).await?);
+        }
+
+        Ok(RecordDigest::new(entries))
+    }
+
+    async fn next_records(
+        &self,
+        host: HostId,
+        tag: String,
+        start: RecordIdx,
+        count: u64,
+    ) -> Result<Vec<Record<EncryptedData>>> {
+        let end = start + count;
+        let mut records = Vec::new();
+
+        for chunk in start / CHUNK_SIZE..end.div_ceil(CHUNK_SIZE) {
+            let page = self.chunk(host, &tag, chunk).await?;
+
+            if page.is_empty() {
+                break;
+            }
+
+            records.extend(page.into_iter().filter(|r| (start..end).contains(&r.idx)));
+        }
+
+        Ok(records)
+    }
+
+    async fn post_records(&self, records: &[Record<EncryptedData>]) -> Result<()> {
+        let mut by_store: BTreeMap<(HostId, &str), Vec<&Record<EncryptedData>>> = BTreeMap::new();
+
+        for record in records {
+            by_store
+                .entry((record.host.id, record.tag.as_str()))
+                .or_default()
+                .push(record);
+        }
+
+        let mut hosts = self.hosts().await?;
+
+        for ((host, tag), records) in by_store {
+            let added = self.add(host, tag, &records).await?;
+            let last = records
+                .iter()
+                .max_by_key(|r| r.idx)
+                .expect("stores always have records");
+
+            let mut stores = self.stores(host).await?;
+
+            match stores.iter_mut().find(|e| e.tag == tag) {
+                Some(entry) => {
+                    entry.count += added;
+
+                    if last.idx > entry.idx {
+                        entry.idx = last.idx;
+                        entry.last = last.id;
+                    }
+                }
+                None => stores.push(DigestEntry {
+                    host,
+                    tag: tag.to_string(),
+                    count: added,
+                    idx: last.idx,
+                    last: last.id,
+                }),
+            }
+
+            if added > 0 {
+                self.put_json(&stores_key(host), &stores).await?;
+            }
+
+            if !hosts.contains(&host) {
+                hosts.push(host);
+                self.put_json(HOSTS, &hosts).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_common::{
+        record::{EncryptedData, Host, HostId, Record},
+        utils::uuid_v7,
+    };
+
+    use super::{BlobRemote, Directory, Remote};
+
+    fn records(host: HostId, tag: &str, count: u64) -> Vec<Record<EncryptedData>> {
+        (0..count)
+            .map(|idx| {
+                Record::builder()
+                    .host(Host::new(host))
+                    .version("v0".into())
+                    .tag(tag.into())
+                    .data(EncryptedData {
+                        data: format!("data {idx}"),
+                        content_encryption_key: "cek".into(),
+                    })
+                    .idx(idx)
+                    .build()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("atuin-blob-{}", uuid_v7().as_simple()));
+        let remote = BlobRemote::new(Box::new(Directory::new(&dir)));
+
+        let host = HostId(uuid_v7());
+        let history = records(host, "history", 600);
+
+        // across a few chunks, and with some overlap, as sync sends the last record again
+        remote.post_records(&history[..300]).await.unwrap();
+        remote.post_records(&history[299..]).await.unwrap();
+        remote.post_records(&records(host, "kv", 3)).await.unwrap();
+
+        let status = remote.status().await.unwrap();
+        assert_eq!(status.get(host, "history".into()), Some(599));
+        assert_eq!(status.get(host, "kv".into()), Some(2));
+
+        let digest = remote.digest().await.unwrap();
+        let entry = digest.get(host, "history").unwrap();
+        assert_eq!(entry.count, 600);
+        assert_eq!(entry.last, history[599].id);
+
+        let page = remote
+            .next_records(host, "history".into(), 250, 100)
+            .await
+            .unwrap();
+        assert_eq!(page, history[250..350]);
+
+        // past the end only returns what's there
+        let page = remote
+            .next_records(host, "history".into(), 590, 100)
+            .await
+            .unwrap();
+        assert_eq!(page, history[590..]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use std::env;
+
+use async_trait::async_trait;
+use eyre::{bail, Result};
+use reqwest::{Method, Response, StatusCode, Url};
+
+use super::{encode_key, BlobTarget};
+use crate::record::sigv4::{self, Credentials};
+
+/// An S3 bucket, or anything else that speaks its API. Credentials come from the standard AWS
+/// environment variables, and `AWS_ENDPOINT_URL` points it somewhere other than AWS.
+pub struct S3 {
+    /// Where the prefix is, ending in a slash
+    base: Url,
+    credentials: Credentials,
+    client: reqwest::Client,
+}
+
+impl S3 {
+    /// From a URL like s3://bucket/prefix
+    pub fn from_url(url: &Url) -> Result<Self> {
+        let Some(bucket) = url.host_str() else {
+            bail!("the s3 sync_backend needs a bucket, like s3://bucket/prefix");
+        };
+
+        let credentials = Credentials::from_env()?;
+
+        // Other endpoints take the bucket in the path, as they may not have DNS for each one
+        let base = match env::var("AWS_ENDPOINT_URL_S3").or_else(|_| env::var("AWS_ENDPOINT_URL")) {
+            Ok(endpoint) => format!("{}/{bucket}/", endpoint.trim_end_matches('/')),
+            Err(_) => format!("https://{bucket}.s3.{}.amazonaws.com/", credentials.region),
+        };
+
+        let mut base = Url::parse(&base)?;
+        let prefix = url.path().trim_matches('/');
+
+        if !prefix.is_empty() {
+            base = base.join(&format!("{prefix}/"))?;
+        }
+
+        Ok(Self {
+            base,
+            credentials,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    async fn request(&self, method: Method, key: &str, body: Vec<u8>) -> Result<Response> {
+        let url = self.base.join(&encode_key(key))?;
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => bail!("s3 endpoint {url} has no host"),
+        };
+
+        let payload_hash = sigv4::payload_hash(&body);
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", sigv4::amz_date()?),
+        ];
+
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let authorization = self.credentials.authorization(
+            "s3",
+            method.as_str(),
+            url.path(),
+            &headers,
+            &payload_hash,
+        );
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("authorization", authorization)
+            .body(body);
+
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+
+        Ok(request.send().await?)
+    }
+}
+
+async fn check(resp: Response, action: &str, key: &str) -> Result<Response> {
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        bail!("S3 {action} of {key} failed with {status}: {text}");
+    }
+
+    Ok(resp)
+}
+
+#[async_trait]
+impl BlobTarget for S3 {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let resp = self.request(Method::GET, key, Vec::new()).await?;
+
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let resp = check(resp, "GET", key).await?;
+
+        Ok(Some(resp.bytes().await?.to_vec()))
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let resp = self.request(Method::PUT, key, data).await?;
+        check(resp, "PUT", key).await?;
+
+        Ok(())
+    }
+}
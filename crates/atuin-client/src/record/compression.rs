@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// Small payloads barely compress, and pay zstd's frame overhead. Most history is a short command,
+// so only bother past this size.
+const MIN_SIZE: usize = 256;
+
+const ZSTD_LEVEL: i32 = 3;
+
+// Versions of atuin from before compression fail to sync records they can't read, so it's opt in.
+// It's set once, with the key, rather than threaded through every store.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Compress records written from now on, as `sync.compress` says
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// How a record payload was compressed before it was encrypted. This is stored in the
+/// encrypted payload, so it's authenticated along with the data it describes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+}
+
+impl Compression {
+    /// Compress `data`, if it's big enough to be worth it and actually gets smaller. Returns the
+    /// compression used, if any, alongside the bytes to encrypt.
+    pub fn compress(data: Vec<u8>) -> (Option<Self>, Vec<u8>) {
+        if data.len() < MIN_SIZE {
+            return (None, data);
+        }
+
+        match zstd::bulk::compress(&data, ZSTD_LEVEL) {
+            Ok(compressed) if compressed.len() < data.len() => (Some(Self::Zstd), compressed),
+            _ => (None, data),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Zstd => zstd::stream::decode_all(data).context("could not decompress entry"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+
+    #[test]
+    fn round_trip() {
+        let data = "for f in *.log; do gzip \"$f\"; done\n"
+            .repeat(64)
+            .into_bytes();
+
+        let (compression, compressed) = Compression::compress(data.clone());
+        assert_eq!(compression, Some(Compression::Zstd));
+        assert!(compressed.len() < data.len());
+
+        let decompressed = Compression::Zstd.decompress(&compressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn small_payloads_are_left_alone() {
+        let data = b"ls -la".to_vec();
+
+        assert_eq!(Compression::compress(data.clone()), (None, data));
+    }
+}
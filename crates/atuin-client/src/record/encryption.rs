@@ -1,18 +1,36 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+};
+
 use atuin_common::record::{
     AdditionalData, DecryptedData, EncryptedData, Encryption, HostId, RecordId, RecordIdx,
 };
 use base64::{engine::general_purpose, Engine};
-use eyre::{ensure, Context, Result};
-use rusty_paserk::{Key, KeyId, Local, PieWrappedKey};
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use eyre::{bail, Context, Result};
+use rusty_paserk::{Key, KeyId, Local, PieWrappedKey, PlaintextKey, Public, SealedKey, Secret};
 use rusty_paseto::core::{
     ImplicitAssertion, Key as DataKey, Local as LocalPurpose, Paseto, PasetoNonce, Payload, V4,
 };
 use serde::{Deserialize, Serialize};
 
+use super::{
+    compression::{self, Compression},
+    kms::{KeyWrapper, KmsFooter},
+};
+
 /// Use PASETO V4 Local encryption using the additional data as an implicit assertion.
 #[allow(non_camel_case_types)]
 pub struct PASETO_V4;
 
+/// The same as [`PASETO_V4`], except the content-encryption key is sealed to a public key rather
+/// than wrapped with the master key. A host with only the [public key](public_key) can write
+/// records, but can't read any, including its own. Anything with the master key can read them,
+/// as the secret half is derived from it.
+#[allow(non_camel_case_types)]
+pub struct PASETO_V4_PKE;
+
 /*
 Why do we use a random content-encryption key?
 Originally I was planning on using a derived key for encryption based on additional data.
@@ -58,8 +76,38 @@ impl Encryption for PASETO_V4 {
         old_key: &[u8; 32],
         new_key: &[u8; 32],
     ) -> Result<EncryptedData> {
-        let cek = Self::decrypt_cek(data.content_encryption_key, old_key)?;
-        data.content_encryption_key = Self::encrypt_cek(cek, new_key);
+        // sealed records stay sealed, to the new key's key pair, so they're still written the
+        // same way as the hosts that can only write them
+        let sealed = matches!(
+            serde_json::from_str(&data.content_encryption_key),
+            Ok(Footer::Sealed(_))
+        );
+        let new_kid = if sealed {
+            secret_key(new_key).public_key().to_id().to_string()
+        } else {
+            key_id(new_key)
+        };
+
+        let cek = match Self::decrypt_cek(data.content_encryption_key.clone(), old_key) {
+            Ok(cek) => cek,
+
+            // already using the new key, such as a record downloaded from a host that has it
+            Err(e)
+                if e.downcast_ref::<KeyMismatch>()
+                    .is_some_and(|m| m.expected == new_kid) =>
+            {
+                return Ok(data)
+            }
+
+            Err(e) => return Err(e),
+        };
+
+        data.content_encryption_key = if sealed {
+            PASETO_V4_PKE::seal_cek(cek, &secret_key(new_key).public_key())
+        } else {
+            Self::encrypt_cek(cek, new_key)
+        };
+
         Ok(data)
     }
 
@@ -68,39 +116,126 @@ impl Encryption for PASETO_V4 {
         // aka content-encryption-key (CEK)
         let random_key = Key::<V4, Local>::new_os_random();
 
-        // encode the implicit assertions
+        EncryptedData {
+            data: Self::encrypt_data(data, ad, random_key),
+            content_encryption_key: Self::encrypt_cek(random_key, key),
+        }
+    }
+
+    fn decrypt(data: EncryptedData, ad: AdditionalData, key: &[u8; 32]) -> Result<DecryptedData> {
+        let cek = Self::decrypt_cek(data.content_encryption_key, key)?;
+
+        Self::decrypt_data(&data.data, ad, cek)
+    }
+}
+
+impl Encryption for PASETO_V4_PKE {
+    // Rotating needs both master keys, and PASETO_V4 keeps sealed records sealed
+    fn re_encrypt(
+        data: EncryptedData,
+        ad: AdditionalData,
+        old_key: &[u8; 32],
+        new_key: &[u8; 32],
+    ) -> Result<EncryptedData> {
+        PASETO_V4::re_encrypt(data, ad, old_key, new_key)
+    }
+
+    fn encrypt(data: DecryptedData, ad: AdditionalData, key: &[u8; 32]) -> EncryptedData {
+        let public_key = Key::<V4, Public>::from_public_key(key)
+            .expect("public keys are checked when they're parsed");
+
+        let cek = Key::<V4, Local>::new_os_random();
+
+        EncryptedData {
+            data: PASETO_V4::encrypt_data(data, ad, cek),
+            content_encryption_key: Self::seal_cek(cek, &public_key),
+        }
+    }
+
+    // reading needs the master key, which PASETO_V4 already knows what to do with
+    fn decrypt(data: EncryptedData, ad: AdditionalData, key: &[u8; 32]) -> Result<DecryptedData> {
+        PASETO_V4::decrypt(data, ad, key)
+    }
+}
+
+impl PASETO_V4_PKE {
+    fn seal_cek(cek: Key<V4, Local>, public_key: &Key<V4, Public>) -> String {
+        let sealed = SealedFooter {
+            seal: cek.seal(public_key),
+            kid: public_key.to_id(),
+        };
+
+        serde_json::to_string(&sealed).expect("could not serialize sealed cek")
+    }
+}
+
+/// The public key that hosts can encrypt records to with [`PASETO_V4_PKE`], as a PASERK
+/// (`k4.public.…`)
+pub fn public_key(key: &[u8; 32]) -> String {
+    PlaintextKey(secret_key(key).public_key()).to_string()
+}
+
+/// Parse a public key from [`public_key`], for use with [`PASETO_V4_PKE`]
+pub fn parse_public_key(key: &str) -> Result<[u8; 32]> {
+    let PlaintextKey(key) = key
+        .trim()
+        .parse::<PlaintextKey<V4, Public>>()
+        .map_err(|e| eyre::eyre!("not a valid public key: {e:?}"))?;
+
+    let key: [u8; 32] = key
+        .as_ref()
+        .try_into()
+        .map_err(|_| eyre::eyre!("not a valid public key: wrong length"))?;
+
+    Key::<V4, Public>::from_public_key(&key)
+        .map_err(|e| eyre::eyre!("not a valid public key: {e:?}"))?;
+
+    Ok(key)
+}
+
+// The secret key for sealed records. It's derived from the master key, so there's nothing else to
+// keep safe, or to share between the hosts that can read everything.
+fn secret_key(key: &[u8; 32]) -> Key<V4, Secret> {
+    let seed = Blake2b::<U32>::new()
+        .chain_update(b"atuin-pke-v4")
+        .chain_update(key)
+        .finalize();
+
+    Key::<V4, Secret>::from_secret_key(seed.into())
+}
+
+impl PASETO_V4 {
+    // encode the implicit assertions, then encrypt the content with the CEK
+    fn encrypt_data(data: DecryptedData, ad: AdditionalData, cek: Key<V4, Local>) -> String {
         let assertions = Assertions::from(ad).encode();
 
-        // build the payload and encrypt the token
+        // compress (if enabled and worthwhile), then build the payload and encrypt the token
+        let (compression, data) = if compression::enabled() {
+            Compression::compress(data.0)
+        } else {
+            (None, data.0)
+        };
         let payload = serde_json::to_string(&AtuinPayload {
-            data: general_purpose::URL_SAFE_NO_PAD.encode(data.0),
+            data: general_purpose::URL_SAFE_NO_PAD.encode(data),
+            compression,
         })
         .expect("json encoding can't fail");
         let nonce = DataKey::<32>::try_new_random().expect("could not source from random");
         let nonce = PasetoNonce::<V4, LocalPurpose>::from(&nonce);
 
-        let token = Paseto::<V4, LocalPurpose>::builder()
+        Paseto::<V4, LocalPurpose>::builder()
             .set_payload(Payload::from(payload.as_str()))
             .set_implicit_assertion(ImplicitAssertion::from(assertions.as_str()))
-            .try_encrypt(&random_key.into(), &nonce)
-            .expect("error encrypting atuin data");
-
-        EncryptedData {
-            data: token,
-            content_encryption_key: Self::encrypt_cek(random_key, key),
-        }
+            .try_encrypt(&cek.into(), &nonce)
+            .expect("error encrypting atuin data")
     }
 
-    fn decrypt(data: EncryptedData, ad: AdditionalData, key: &[u8; 32]) -> Result<DecryptedData> {
-        let token = data.data;
-        let cek = Self::decrypt_cek(data.content_encryption_key, key)?;
-
-        // encode the implicit assertions
+    // decrypt the payload with the implicit assertions
+    fn decrypt_data(token: &str, ad: AdditionalData, cek: Key<V4, Local>) -> Result<DecryptedData> {
         let assertions = Assertions::from(ad).encode();
 
-        // decrypt the payload with the footer and implicit assertions
         let payload = Paseto::<V4, LocalPurpose>::try_decrypt(
-            &token,
+            token,
             &cek.into(),
             None,
             ImplicitAssertion::from(&*assertions),
@@ -109,36 +244,110 @@ impl Encryption for PASETO_V4 {
 
         let payload: AtuinPayload = serde_json::from_str(&payload)?;
         let data = general_purpose::URL_SAFE_NO_PAD.decode(payload.data)?;
+
+        let data = match payload.compression {
+            Some(compression) => compression.decompress(&data)?,
+            None => data,
+        };
+
         Ok(DecryptedData(data))
     }
-}
 
-impl PASETO_V4 {
-    fn decrypt_cek(wrapped_cek: String, key: &[u8; 32]) -> Result<Key<V4, Local>> {
+    /// Encrypt with a content-encryption key wrapped by `wrapper`, rather than the master key
+    pub async fn encrypt_with(
+        data: DecryptedData,
+        ad: AdditionalData<'_>,
+        wrapper: &(impl KeyWrapper + ?Sized),
+    ) -> Result<EncryptedData> {
+        let cek = Key::<V4, Local>::new_os_random();
+
+        Ok(EncryptedData {
+            data: Self::encrypt_data(data, ad, cek),
+            content_encryption_key: wrapper.wrap(&cek.to_bytes()).await?,
+        })
+    }
+
+    /// Decrypt a record whose content-encryption key was wrapped by `wrapper`
+    pub async fn decrypt_with(
+        data: EncryptedData,
+        ad: AdditionalData<'_>,
+        wrapper: &(impl KeyWrapper + ?Sized),
+    ) -> Result<DecryptedData> {
+        let cek = wrapper.unwrap(&data.content_encryption_key).await?;
+
+        Self::decrypt_data(&data.data, ad, Key::<V4, Local>::from_bytes(cek))
+    }
+
+    pub(crate) fn decrypt_cek(wrapped_cek: String, key: &[u8; 32]) -> Result<Key<V4, Local>> {
         let wrapping_key = Key::<V4, Local>::from_bytes(*key);
 
         // let wrapping_key = PasetoSymmetricKey::from(Key::from(key));
 
-        let AtuinFooter { kid, wpk } = serde_json::from_str(&wrapped_cek)
+        let footer: Footer = serde_json::from_str(&wrapped_cek)
             .context("wrapped cek did not contain the correct contents")?;
 
-        // check that the wrapping key matches the required key to decrypt.
-        // In future, we could support multiple keys and use this key to
-        // look up the key rather than only allow one key.
-        // For now though we will only support the one key and key rotation will
-        // have to be a hard reset
+        let AtuinFooter { kid, wpk } = match footer {
+            Footer::Wrapped(footer) => footer,
+            Footer::Kms(KmsFooter { kms, .. }) => {
+                bail!("this record's key is held by {kms}, and can only be decrypted through it")
+            }
+            Footer::Sealed(SealedFooter { seal, kid }) => {
+                // sealed for the key pair of this key, or one it replaced
+                let previous: Vec<[u8; 32]> = KEYRING
+                    .read()
+                    .expect("keyring lock poisoned")
+                    .keys
+                    .values()
+                    .copied()
+                    .collect();
+
+                let Some(secret_key) = std::iter::once(*key)
+                    .chain(previous)
+                    .map(|key| secret_key(&key))
+                    .find(|secret_key| secret_key.public_key().to_id() == kid)
+                else {
+                    return Err(KeyMismatch {
+                        current: secret_key(key).public_key().to_id().to_string(),
+                        expected: kid.to_string(),
+                    }
+                    .into());
+                };
+
+                return Ok(seal.unseal(&secret_key)?);
+            }
+        };
+
+        // check that the wrapping key matches the required key to decrypt. If it doesn't, the
+        // record may be from before the key was rotated, so look for the key it needs among the
+        // ones we used before.
         let current_kid = wrapping_key.to_id();
 
-        ensure!(
-            current_kid == kid,
-            "attempting to decrypt with incorrect key. currently using {current_kid}, expecting {kid}"
-        );
+        let wrapping_key = if current_kid == kid {
+            wrapping_key
+        } else {
+            let previous = KEYRING
+                .read()
+                .expect("keyring lock poisoned")
+                .get(&kid.to_string())
+                .copied();
+
+            match previous {
+                Some(key) => Key::<V4, Local>::from_bytes(key),
+                None => {
+                    return Err(KeyMismatch {
+                        current: current_kid.to_string(),
+                        expected: kid.to_string(),
+                    }
+                    .into())
+                }
+            }
+        };
 
         // decrypt the random key
         Ok(wpk.unwrap_key(&wrapping_key)?)
     }
 
-    fn encrypt_cek(cek: Key<V4, Local>, key: &[u8; 32]) -> String {
+    pub(crate) fn encrypt_cek(cek: Key<V4, Local>, key: &[u8; 32]) -> String {
         // aka key-encryption-key (KEK)
         let wrapping_key = Key::<V4, Local>::from_bytes(*key);
 
@@ -151,9 +360,83 @@ impl PASETO_V4 {
     }
 }
 
+/// A record was encrypted with a different key than the one we have. Usually this means the key
+/// on this machine isn't the one used by the rest of the account.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "attempting to decrypt with incorrect key. currently using {current}, expecting {expected}"
+)]
+pub struct KeyMismatch {
+    /// ID of the key we tried to decrypt with
+    pub current: String,
+
+    /// ID of the key the record was encrypted with
+    pub expected: String,
+}
+
+/// Keys that were replaced by a rotation, by their ID. Records are always encrypted with the
+/// current key, but ones wrapped with any of these can still be decrypted.
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    keys: HashMap<String, [u8; 32]>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: [u8; 32]) {
+        self.keys.insert(key_id(&key), key);
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&[u8; 32]> {
+        self.keys.get(kid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl FromIterator<[u8; 32]> for Keyring {
+    fn from_iter<I: IntoIterator<Item = [u8; 32]>>(iter: I) -> Self {
+        let mut keyring = Self::new();
+
+        for key in iter {
+            keyring.insert(key);
+        }
+
+        keyring
+    }
+}
+
+// Every record is decrypted through PASETO_V4, but only ever given the current key. Previous keys
+// are set once, when the current one is loaded, rather than threaded through every store.
+static KEYRING: LazyLock<RwLock<Keyring>> = LazyLock::new(RwLock::default);
+
+/// Set the previous keys to fall back on when a record wasn't encrypted with the current one
+pub fn set_keyring(keyring: Keyring) {
+    *KEYRING.write().expect("keyring lock poisoned") = keyring;
+}
+
+/// The ID of a key, as recorded alongside everything encrypted with it
+pub fn key_id(key: &[u8; 32]) -> String {
+    Key::<V4, Local>::from_bytes(*key).to_id().to_string()
+}
+
 #[derive(Serialize, Deserialize)]
 struct AtuinPayload {
     data: String,
+
+    /// Left out for uncompressed data, so those payloads are still readable by versions of atuin
+    /// from before compression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<Compression>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -166,6 +449,23 @@ struct AtuinFooter {
     kid: KeyId<V4, Local>,
 }
 
+/// The sealed equivalent of [`AtuinFooter`], for records written with [`PASETO_V4_PKE`]
+#[derive(Serialize, Deserialize)]
+struct SealedFooter {
+    /// Sealed key
+    seal: SealedKey<V4>,
+    /// ID of the public key it was sealed to
+    kid: KeyId<V4, Public>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Footer {
+    Wrapped(AtuinFooter),
+    Sealed(SealedFooter),
+    Kms(KmsFooter),
+}
+
 /// Used in the implicit assertions. This is not encrypted and not stored in the data blob.
 // This cannot be changed, otherwise it breaks the authenticated encryption.
 #[derive(Debug, Copy, Clone, Serialize)]
@@ -223,6 +523,28 @@ mod tests {
         assert_eq!(decrypted, data);
     }
 
+    #[test]
+    fn round_trip_compressed() {
+        let key = Key::<V4, Local>::new_os_random();
+
+        let ad = AdditionalData {
+            id: &RecordId(uuid_v7()),
+            version: "v0",
+            tag: "history",
+            host: &HostId(uuid_v7()),
+            idx: &0,
+        };
+
+        let data = DecryptedData("echo hello world && ".repeat(100).into_bytes());
+
+        compression::set_enabled(true);
+        let encrypted = PASETO_V4::encrypt(data.clone(), ad, &key.to_bytes());
+        assert!(encrypted.data.len() < data.0.len());
+
+        let decrypted = PASETO_V4::decrypt(encrypted, ad, &key.to_bytes()).unwrap();
+        assert_eq!(decrypted, data);
+    }
+
     #[test]
     fn same_entry_different_output() {
         let key = Key::<V4, Local>::new_os_random();
@@ -246,6 +568,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decrypt_with_previous_key() {
+        let old_key = Key::<V4, Local>::new_os_random();
+        let new_key = Key::<V4, Local>::new_os_random();
+
+        let ad = AdditionalData {
+            id: &RecordId(uuid_v7()),
+            version: "v0",
+            tag: "kv",
+            host: &HostId(uuid_v7()),
+            idx: &0,
+        };
+
+        let data = DecryptedData(vec![1, 2, 3, 4]);
+        let encrypted = PASETO_V4::encrypt(data.clone(), ad, &old_key.to_bytes());
+
+        set_keyring([old_key.to_bytes()].into_iter().collect());
+        let decrypted = PASETO_V4::decrypt(encrypted, ad, &new_key.to_bytes()).unwrap();
+        set_keyring(Keyring::new());
+
+        assert_eq!(decrypted, data);
+    }
+
+    #[test]
+    fn round_trip_sealed() {
+        let key = Key::<V4, Local>::new_os_random().to_bytes();
+        let public = parse_public_key(&public_key(&key)).unwrap();
+
+        let ad = AdditionalData {
+            id: &RecordId(uuid_v7()),
+            version: "v0",
+            tag: "history",
+            host: &HostId(uuid_v7()),
+            idx: &0,
+        };
+
+        let data = DecryptedData(vec![1, 2, 3, 4]);
+        let encrypted = PASETO_V4_PKE::encrypt(data.clone(), ad, &public);
+
+        // the public key is no use for reading, but the master key is
+        assert!(PASETO_V4_PKE::decrypt(encrypted.clone(), ad, &public).is_err());
+        let decrypted = PASETO_V4::decrypt(encrypted.clone(), ad, &key).unwrap();
+        assert_eq!(decrypted, data);
+
+        // re-encrypting leaves a normally wrapped record
+        let new_key = Key::<V4, Local>::new_os_random().to_bytes();
+        let encrypted = PASETO_V4::re_encrypt(encrypted, ad, &key, &new_key).unwrap();
+        let decrypted = PASETO_V4::decrypt(encrypted, ad, &new_key).unwrap();
+        assert_eq!(decrypted, data);
+    }
+
     #[test]
     fn cannot_decrypt_different_key() {
         let key = Key::<V4, Local>::new_os_random();
@@ -262,7 +635,75 @@ mod tests {
         let data = DecryptedData(vec![1, 2, 3, 4]);
 
         let encrypted = PASETO_V4::encrypt(data, ad, &key.to_bytes());
-        let _ = PASETO_V4::decrypt(encrypted, ad, &fake_key.to_bytes()).unwrap_err();
+        let err = PASETO_V4::decrypt(encrypted, ad, &fake_key.to_bytes()).unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<KeyMismatch>(),
+            Some(&KeyMismatch {
+                current: key_id(&fake_key.to_bytes()),
+                expected: key_id(&key.to_bytes()),
+            })
+        );
+    }
+
+    #[test]
+    fn re_encrypt_skips_records_with_new_key() {
+        let old_key = Key::<V4, Local>::new_os_random().to_bytes();
+        let new_key = Key::<V4, Local>::new_os_random().to_bytes();
+
+        let ad = AdditionalData {
+            id: &RecordId(uuid_v7()),
+            version: "v0",
+            tag: "kv",
+            host: &HostId(uuid_v7()),
+            idx: &0,
+        };
+
+        let data = DecryptedData(vec![1, 2, 3, 4]);
+
+        let old = PASETO_V4::encrypt(data.clone(), ad, &old_key);
+        let new = PASETO_V4::encrypt(data.clone(), ad, &new_key);
+
+        let old = PASETO_V4::re_encrypt(old, ad, &old_key, &new_key).unwrap();
+        let new = PASETO_V4::re_encrypt(new, ad, &old_key, &new_key).unwrap();
+
+        assert_eq!(PASETO_V4::decrypt(old, ad, &new_key).unwrap(), data);
+        assert_eq!(PASETO_V4::decrypt(new, ad, &new_key).unwrap(), data);
+    }
+
+    #[test]
+    fn re_encrypt_keeps_sealed_records_sealed() {
+        let old_key = Key::<V4, Local>::new_os_random().to_bytes();
+        let new_key = Key::<V4, Local>::new_os_random().to_bytes();
+
+        let ad = AdditionalData {
+            id: &RecordId(uuid_v7()),
+            version: "v0",
+            tag: "kv",
+            host: &HostId(uuid_v7()),
+            idx: &0,
+        };
+
+        let data = DecryptedData(vec![1, 2, 3, 4]);
+        let public = parse_public_key(&public_key(&old_key)).unwrap();
+
+        let sealed = PASETO_V4_PKE::encrypt(data.clone(), ad, &public);
+        let sealed = PASETO_V4_PKE::re_encrypt(sealed, ad, &old_key, &new_key).unwrap();
+
+        let footer: Footer = serde_json::from_str(&sealed.content_encryption_key).unwrap();
+        let Footer::Sealed(footer) = footer else {
+            panic!("re-encrypting unsealed the record");
+        };
+        assert_eq!(footer.kid, secret_key(&new_key).public_key().to_id());
+
+        assert_eq!(
+            PASETO_V4::decrypt(sealed.clone(), ad, &new_key).unwrap(),
+            data
+        );
+
+        // and a second rotation to the same key leaves it be
+        let again = PASETO_V4::re_encrypt(sealed.clone(), ad, &old_key, &new_key).unwrap();
+        assert_eq!(again.content_encryption_key, sealed.content_encryption_key);
     }
 
     #[test]
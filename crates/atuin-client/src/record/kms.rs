@@ -0,0 +1,144 @@
+//! Key wrapping, for keeping the key-encryption key somewhere atuin can't read it.
+//!
+//! Every record is encrypted with its own random content-encryption key (CEK), which is then
+//! wrapped and stored alongside it. Usually the master key wraps it, but a [`KeyWrapper`] can hand
+//! that job to a KMS or HSM instead, so the key-encryption key never leaves it. See the notes in
+//! [`encryption`](super::encryption) for why.
+
+use async_trait::async_trait;
+use eyre::Result;
+use rusty_paserk::{Key, Local, V4};
+use serde::{Deserialize, Serialize};
+
+use super::encryption::{key_id, PASETO_V4};
+
+#[cfg(feature = "kms")]
+mod aws;
+#[cfg(feature = "kms")]
+mod gcp;
+
+#[cfg(feature = "kms")]
+pub use aws::AwsKms;
+#[cfg(feature = "kms")]
+pub use gcp::GcpKms;
+
+#[async_trait]
+pub trait KeyWrapper: Send + Sync {
+    /// Which key-encryption key this is. Records wrapped by it say so, so they can be matched up
+    /// again.
+    fn key_id(&self) -> String;
+
+    /// Wrap a CEK, returning what's stored with the record
+    async fn wrap(&self, cek: &[u8; 32]) -> Result<String>;
+
+    /// Get the CEK back out of what [`wrap`](KeyWrapper::wrap) returned
+    async fn unwrap(&self, wrapped: &str) -> Result<[u8; 32]>;
+}
+
+/// Wraps CEKs with the master key, exactly as records are usually encrypted
+pub struct LocalKeyWrapper {
+    key: [u8; 32],
+}
+
+impl LocalKeyWrapper {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+}
+
+#[async_trait]
+impl KeyWrapper for LocalKeyWrapper {
+    fn key_id(&self) -> String {
+        key_id(&self.key)
+    }
+
+    async fn wrap(&self, cek: &[u8; 32]) -> Result<String> {
+        Ok(PASETO_V4::encrypt_cek(
+            Key::<V4, Local>::from_bytes(*cek),
+            &self.key,
+        ))
+    }
+
+    async fn unwrap(&self, wrapped: &str) -> Result<[u8; 32]> {
+        Ok(PASETO_V4::decrypt_cek(wrapped.to_string(), &self.key)?.to_bytes())
+    }
+}
+
+/// What's stored with a record whose CEK was wrapped by a KMS
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct KmsFooter {
+    /// [`KeyWrapper::key_id`] of the KMS key that wrapped it
+    pub kms: String,
+    /// The wrapped CEK, as the KMS returned it
+    pub wck: String,
+}
+
+// only the KMS wrappers write them. Reading just needs to know which key it was.
+#[cfg(feature = "kms")]
+impl KmsFooter {
+    pub(crate) fn encode(kms: String, wck: String) -> String {
+        serde_json::to_string(&KmsFooter { kms, wck }).expect("could not serialize wrapped cek")
+    }
+
+    // Parse a footer, checking that it was wrapped by the expected key
+    pub(crate) fn decode(wrapped: &str, expected: &str) -> Result<Self> {
+        let footer: KmsFooter = serde_json::from_str(wrapped)?;
+
+        eyre::ensure!(
+            footer.kms == expected,
+            "this record's key was wrapped by {}, not {expected}",
+            footer.kms
+        );
+
+        Ok(footer)
+    }
+}
+
+#[cfg(feature = "kms")]
+fn decode_cek(cek: &[u8]) -> Result<[u8; 32]> {
+    cek.try_into()
+        .map_err(|_| eyre::eyre!("the KMS returned a key of the wrong length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_common::{
+        record::{AdditionalData, DecryptedData, Encryption, HostId, RecordId},
+        utils::uuid_v7,
+    };
+    use rusty_paserk::{Key, Local, V4};
+
+    use crate::record::encryption::PASETO_V4;
+
+    use super::LocalKeyWrapper;
+
+    #[tokio::test]
+    async fn local_wrapper_matches_paseto_v4() {
+        let key = Key::<V4, Local>::new_os_random().to_bytes();
+        let wrapper = LocalKeyWrapper::new(key);
+
+        let ad = AdditionalData {
+            id: &RecordId(uuid_v7()),
+            version: "v0",
+            tag: "kv",
+            host: &HostId(uuid_v7()),
+            idx: &0,
+        };
+
+        let data = DecryptedData(vec![1, 2, 3, 4]);
+
+        // either way of encrypting can be read by the other
+        let encrypted = PASETO_V4::encrypt_with(data.clone(), ad, &wrapper)
+            .await
+            .unwrap();
+        assert_eq!(PASETO_V4::decrypt(encrypted, ad, &key).unwrap(), data);
+
+        let encrypted = PASETO_V4::encrypt(data.clone(), ad, &key);
+        assert_eq!(
+            PASETO_V4::decrypt_with(encrypted, ad, &wrapper)
+                .await
+                .unwrap(),
+            data
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use eyre::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{decode_cek, KeyWrapper, KmsFooter};
+use crate::record::sigv4::{self, Credentials};
+
+/// Wraps CEKs with a key in AWS KMS, through its Encrypt and Decrypt APIs
+pub struct AwsKms {
+    key_id: String,
+    credentials: Credentials,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EncryptRequest<'a> {
+    key_id: &'a str,
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EncryptResponse {
+    ciphertext_blob: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DecryptRequest<'a> {
+    key_id: &'a str,
+    ciphertext_blob: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DecryptResponse {
+    plaintext: String,
+}
+
+impl AwsKms {
+    pub fn new(
+        key_id: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+    ) -> Self {
+        Self {
+            key_id,
+            credentials: Credentials {
+                region,
+                access_key_id,
+                secret_access_key,
+                session_token,
+            },
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Use the key with this ID or ARN, and credentials from the standard AWS environment
+    /// variables
+    pub fn from_env(key_id: String) -> Result<Self> {
+        Ok(Self {
+            key_id,
+            credentials: Credentials::from_env()?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        action: &str,
+        body: &impl Serialize,
+    ) -> Result<T> {
+        let host = format!("kms.{}.amazonaws.com", self.credentials.region);
+        let body = serde_json::to_vec(body)?;
+        let target = format!("TrentService.{action}");
+
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", host.clone()),
+            ("x-amz-date", sigv4::amz_date()?),
+            ("x-amz-target", target),
+        ];
+
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let authorization = self.credentials.authorization(
+            "kms",
+            "POST",
+            "/",
+            &headers,
+            &sigv4::payload_hash(&body),
+        );
+
+        let mut request = self
+            .client
+            .post(format!("https://{host}/"))
+            .header("authorization", authorization)
+            .body(body);
+
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+
+        let resp = request.send().await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            bail!("AWS KMS {action} failed with {status}: {text}");
+        }
+
+        Ok(resp.json().await?)
+    }
+}
+
+#[async_trait]
+impl KeyWrapper for AwsKms {
+    fn key_id(&self) -> String {
+        format!("aws:{}", self.key_id)
+    }
+
+    async fn wrap(&self, cek: &[u8; 32]) -> Result<String> {
+        let resp: EncryptResponse = self
+            .call(
+                "Encrypt",
+                &EncryptRequest {
+                    key_id: &self.key_id,
+                    plaintext: STANDARD.encode(cek),
+                },
+            )
+            .await?;
+
+        Ok(KmsFooter::encode(self.key_id(), resp.ciphertext_blob))
+    }
+
+    async fn unwrap(&self, wrapped: &str) -> Result<[u8; 32]> {
+        let footer = KmsFooter::decode(wrapped, &self.key_id())?;
+
+        let resp: DecryptResponse = self
+            .call(
+                "Decrypt",
+                &DecryptRequest {
+                    key_id: &self.key_id,
+                    ciphertext_blob: &footer.wck,
+                },
+            )
+            .await?;
+
+        decode_cek(&STANDARD.decode(resp.plaintext)?)
+    }
+}
@@ -0,0 +1,116 @@
+use std::env;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{decode_cek, KeyWrapper, KmsFooter};
+
+/// Wraps CEKs with a key in Google Cloud KMS, through its encrypt and decrypt APIs
+pub struct GcpKms {
+    /// projects/{project}/locations/{location}/keyRings/{ring}/cryptoKeys/{key}
+    name: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct EncryptRequest {
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+struct EncryptResponse {
+    ciphertext: String,
+}
+
+#[derive(Serialize)]
+struct DecryptRequest<'a> {
+    ciphertext: &'a str,
+}
+
+#[derive(Deserialize)]
+struct DecryptResponse {
+    plaintext: String,
+}
+
+impl GcpKms {
+    /// Use the key with this resource name, authenticating with an OAuth access token
+    pub fn new(name: String, token: String) -> Self {
+        Self {
+            name,
+            token,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Use the key with this resource name, and the access token in
+    /// `$GOOGLE_OAUTH_ACCESS_TOKEN`, such as from `gcloud auth print-access-token`
+    pub fn from_env(name: String) -> Result<Self> {
+        let token = env::var("GOOGLE_OAUTH_ACCESS_TOKEN")
+            .context("$GOOGLE_OAUTH_ACCESS_TOKEN is not set")?;
+
+        Ok(Self::new(name, token))
+    }
+
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: &str,
+        body: &impl Serialize,
+    ) -> Result<T> {
+        let resp = self
+            .client
+            .post(format!(
+                "https://cloudkms.googleapis.com/v1/{}:{method}",
+                self.name
+            ))
+            .bearer_auth(&self.token)
+            .json(body)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            bail!("Cloud KMS {method} failed with {status}: {text}");
+        }
+
+        Ok(resp.json().await?)
+    }
+}
+
+#[async_trait]
+impl KeyWrapper for GcpKms {
+    fn key_id(&self) -> String {
+        format!("gcp:{}", self.name)
+    }
+
+    async fn wrap(&self, cek: &[u8; 32]) -> Result<String> {
+        let resp: EncryptResponse = self
+            .call(
+                "encrypt",
+                &EncryptRequest {
+                    plaintext: STANDARD.encode(cek),
+                },
+            )
+            .await?;
+
+        Ok(KmsFooter::encode(self.key_id(), resp.ciphertext))
+    }
+
+    async fn unwrap(&self, wrapped: &str) -> Result<[u8; 32]> {
+        let footer = KmsFooter::decode(wrapped, &self.key_id())?;
+
+        let resp: DecryptResponse = self
+            .call(
+                "decrypt",
+                &DecryptRequest {
+                    ciphertext: &footer.wck,
+                },
+            )
+            .await?;
+
+        decode_cek(&STANDARD.decode(resp.plaintext)?)
+    }
+}
@@ -1,6 +1,16 @@
+pub mod archive;
+pub mod compression;
 pub mod encryption;
+pub mod kms;
 pub mod sqlite_store;
 pub mod store;
 
+#[cfg(any(feature = "sync", feature = "kms"))]
+mod sigv4;
+
+#[cfg(feature = "sync")]
+pub mod blob;
+#[cfg(feature = "sync")]
+pub mod remote;
 #[cfg(feature = "sync")]
 pub mod sync;
@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+use eyre::Result;
+
+use atuin_common::record::{EncryptedData, HostId, Record, RecordDigest, RecordIdx, RecordStatus};
+
+use crate::api_client::Client;
+
+/// Somewhere the record store syncs to. Usually the sync server, but records can also be kept in
+/// a [`BlobRemote`](super::blob::BlobRemote) by those who'd rather not run one.
+#[async_trait]
+pub trait Remote: Send + Sync {
+    /// The last idx of each host and tag
+    async fn status(&self) -> Result<RecordStatus>;
+
+    /// The count and last record of each host and tag
+    async fn digest(&self) -> Result<RecordDigest>;
+
+    /// Up to `count` records of a host and tag, starting from idx `start`
+    async fn next_records(
+        &self,
+        host: HostId,
+        tag: String,
+        start: RecordIdx,
+        count: u64,
+    ) -> Result<Vec<Record<EncryptedData>>>;
+
+    /// Add records. Any that are already there are ignored.
+    async fn post_records(&self, records: &[Record<EncryptedData>]) -> Result<()>;
+}
+
+#[async_trait]
+impl Remote for Client<'_> {
+    async fn status(&self) -> Result<RecordStatus> {
+        self.record_status().await
+    }
+
+    async fn digest(&self) -> Result<RecordDigest> {
+        self.record_digest().await
+    }
+
+    async fn next_records(
+        &self,
+        host: HostId,
+        tag: String,
+        start: RecordIdx,
+        count: u64,
+    ) -> Result<Vec<Record<EncryptedData>>> {
+        Client::next_records(self, host, tag, start, count).await
+    }
+
+    async fn post_records(&self, records: &[Record<EncryptedData>]) -> Result<()> {
+        Client::post_records(self, records).await
+    }
+}
@@ -3,24 +3,28 @@
 // by tag/host
 
 use std::str::FromStr;
-use std::{path::Path, time::Duration};
+use std::{collections::HashMap, path::Path, time::Duration};
 
 use async_trait::async_trait;
 use eyre::{eyre, Result};
 use fs_err as fs;
 
+use time::OffsetDateTime;
+
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow},
     Row,
 };
 
 use atuin_common::record::{
-    EncryptedData, Host, HostId, Record, RecordId, RecordIdx, RecordStatus,
+    DigestEntry, EncryptedData, Host, HostId, Record, RecordDigest, RecordId, RecordIdx,
+    RecordStatus,
 };
 use uuid::Uuid;
 
 use super::encryption::PASETO_V4;
 use super::store::Store;
+use crate::kv::KvRecord;
 
 #[derive(Debug, Clone)]
 pub struct SqliteStore {
@@ -156,6 +160,7 @@ impl Store for SqliteStore {
 
     async fn delete_all(&self) -> Result<()> {
         sqlx::query("delete from store").execute(&self.pool).await?;
+        self.kv_clear().await?;
 
         Ok(())
     }
@@ -278,6 +283,31 @@ impl Store for SqliteStore {
         Ok(status)
     }
 
+    async fn digest(&self) -> Result<RecordDigest> {
+        // sqlite takes the bare id column from the row with the max idx
+        let res: Vec<(String, String, i64, i64, String)> = sqlx::query_as(
+            "select host, tag, count(1), max(idx), id from store group by host, tag",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| eyre!("failed to fetch local store digest: {}", e))?;
+
+        let entries = res
+            .into_iter()
+            .map(|(host, tag, count, idx, last)| {
+                Ok(DigestEntry {
+                    host: HostId(Uuid::from_str(&host)?),
+                    tag,
+                    count: count as u64,
+                    idx: idx as u64,
+                    last: RecordId(Uuid::from_str(&last)?),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(RecordDigest::new(entries))
+    }
+
     async fn all_tagged(&self, tag: &str) -> Result<Vec<Record<EncryptedData>>> {
         let res = sqlx::query("select * from store where tag = ?1 order by timestamp asc")
             .bind(tag)
@@ -288,6 +318,139 @@ impl Store for SqliteStore {
         Ok(res)
     }
 
+    async fn kv_get(&self, namespace: &str, key: &str) -> Result<Option<KvRecord>> {
+        let res: Option<(String, Option<i64>)> = sqlx::query_as(
+            "select value, expires from kv where namespace = ?1 and key = ?2 and not deleted
+            and (expires is null or expires > ?3)",
+        )
+        .bind(namespace)
+        .bind(key)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        res.map(|(value, expires)| {
+            Ok(KvRecord {
+                namespace: namespace.to_string(),
+                key: key.to_string(),
+                value,
+                deleted: false,
+                expires: expires
+                    .map(OffsetDateTime::from_unix_timestamp)
+                    .transpose()?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn kv_list(&self, namespace: Option<&str>) -> Result<Vec<KvRecord>> {
+        let res: Vec<(String, String, String, Option<i64>)> = sqlx::query_as(
+            "select namespace, key, value, expires from kv
+            where (?1 is null or namespace = ?1) and not deleted
+            and (expires is null or expires > ?2)
+            order by namespace, key",
+        )
+        .bind(namespace)
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .fetch_all(&self.pool)
+        .await?;
+
+        res.into_iter()
+            .map(|(namespace, key, value, expires)| {
+                Ok(KvRecord {
+                    namespace,
+                    key,
+                    value,
+                    deleted: false,
+                    expires: expires
+                        .map(OffsetDateTime::from_unix_timestamp)
+                        .transpose()?,
+                })
+            })
+            .collect()
+    }
+
+    async fn kv_apply(
+        &self,
+        host: HostId,
+        idx: RecordIdx,
+        records: &[(KvRecord, u64)],
+    ) -> Result<()> {
+        let host = host.0.as_hyphenated().to_string();
+        let mut tx = self.pool.begin().await?;
+
+        for (kv, timestamp) in records {
+            sqlx::query(
+                "insert into kv(namespace, key, value, timestamp, deleted, host, expires)
+                values(?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                on conflict(namespace, key) do update
+                set value = excluded.value, timestamp = excluded.timestamp, deleted = excluded.deleted,
+                host = excluded.host, expires = excluded.expires
+                where excluded.timestamp > kv.timestamp
+                or (excluded.timestamp = kv.timestamp and excluded.host >= kv.host)",
+            )
+            .bind(kv.namespace.as_str())
+            .bind(kv.key.as_str())
+            .bind(kv.value.as_str())
+            .bind(*timestamp as i64)
+            .bind(kv.deleted)
+            .bind(host.as_str())
+            .bind(kv.expires.map(OffsetDateTime::unix_timestamp))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            "insert into kv_applied(host, idx) values(?1, ?2)
+            on conflict(host) do update set idx = excluded.idx",
+        )
+        .bind(host.as_str())
+        .bind(idx as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn kv_applied(&self) -> Result<HashMap<HostId, RecordIdx>> {
+        let res: Vec<(String, i64)> = sqlx::query_as("select host, idx from kv_applied")
+            .fetch_all(&self.pool)
+            .await?;
+
+        res.into_iter()
+            .map(|(host, idx)| Ok((HostId(Uuid::from_str(&host)?), idx as u64)))
+            .collect()
+    }
+
+    async fn kv_purge_expired(&self, now: OffsetDateTime) -> Result<u64> {
+        // they stay in the index as tombstones, so an older write that syncs later can't bring
+        // the key back
+        let res = sqlx::query(
+            "update kv set value = '', deleted = 1, expires = null
+            where not deleted and expires <= ?1",
+        )
+        .bind(now.unix_timestamp())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn kv_clear(&self) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("delete from kv").execute(&mut *tx).await?;
+        sqlx::query("delete from kv_applied")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     /// Reencrypt every single item in this store with a new key
     /// Be careful - this may mess with sync.
     async fn re_encrypt(&self, old_key: &[u8; 32], new_key: &[u8; 32]) -> Result<()> {
@@ -545,6 +708,32 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn digest() {
+        let db = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let mut tail = test_record();
+        db.push(&tail).await.unwrap();
+
+        for _ in 1..10 {
+            tail = tail.append(vec![1, 2, 3, 4]).encrypt::<PASETO_V4>(&[0; 32]);
+            db.push(&tail).await.unwrap();
+        }
+
+        let other = test_record();
+        db.push(&other).await.unwrap();
+
+        let digest = db.digest().await.unwrap();
+        assert_eq!(digest.entries.len(), 2);
+
+        let entry = digest.get(tail.host.id, &tail.tag).unwrap();
+        assert_eq!(entry.count, 10);
+        assert_eq!(entry.idx, 9);
+        assert_eq!(entry.last, tail.id);
+    }
+
     #[tokio::test]
     async fn append_a_big_bunch() {
         let db = SqliteStore::new(":memory:", test_local_timeout())
@@ -1,7 +1,15 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
 use eyre::Result;
 
-use atuin_common::record::{EncryptedData, HostId, Record, RecordId, RecordIdx, RecordStatus};
+use atuin_common::record::{
+    EncryptedData, HostId, Record, RecordDigest, RecordId, RecordIdx, RecordStatus,
+};
+
+use time::OffsetDateTime;
+
+use crate::kv::KvRecord;
 
 /// A record store stores records
 /// In more detail - we tend to need to process this into _another_ format to actually query it.
@@ -55,6 +63,36 @@ pub trait Store {
 
     async fn status(&self) -> Result<RecordStatus>;
 
+    /// The count and last record of each host and tag, to check against the server's
+    async fn digest(&self) -> Result<RecordDigest>;
+
     /// Get all records for a given tag
     async fn all_tagged(&self, tag: &str) -> Result<Vec<Record<EncryptedData>>>;
+
+    /// The latest value of a key, from the index kept by [`KvStore`](crate::kv::KvStore), unless
+    /// it was deleted or has expired
+    async fn kv_get(&self, namespace: &str, key: &str) -> Result<Option<KvRecord>>;
+
+    /// Every key in the kv index, or only those in one namespace. Deleted and expired keys are
+    /// left out.
+    async fn kv_list(&self, namespace: Option<&str>) -> Result<Vec<KvRecord>>;
+
+    /// Add one host's kv records, with their timestamps, to the index, and note that it's up to
+    /// `idx`. The newest write to a key wins. Two with the same timestamp are settled by host id,
+    /// so every machine agrees on the winner whatever order they arrive in.
+    async fn kv_apply(
+        &self,
+        host: HostId,
+        idx: RecordIdx,
+        records: &[(KvRecord, u64)],
+    ) -> Result<()>;
+
+    /// The last kv record in the index from each host
+    async fn kv_applied(&self) -> Result<HashMap<HostId, RecordIdx>>;
+
+    /// Turn keys that expired by `now` into tombstones in the index, returning how many
+    async fn kv_purge_expired(&self, now: OffsetDateTime) -> Result<u64>;
+
+    /// Empty the kv index, so it can be built again
+    async fn kv_clear(&self) -> Result<()>;
 }
@@ -1,13 +1,33 @@
 // do a sync :O
-use std::{cmp::Ordering, fmt::Write};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt::Write,
+    path::PathBuf,
+    sync::atomic::{self, AtomicU64},
+};
 
 use eyre::Result;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-
-use super::store::Store;
-use crate::{api_client::Client, settings::Settings};
-
-use atuin_common::record::{Diff, HostId, RecordId, RecordIdx, RecordStatus};
+use time::OffsetDateTime;
+
+use super::{
+    blob::{self, BlobRemote},
+    remote::Remote,
+    store::Store,
+};
+use crate::{
+    api_client::{Client, HostRevoked, QuotaExceeded},
+    settings::Settings,
+};
+
+use atuin_common::{
+    detail, output,
+    record::{Diff, DigestEntry, EncryptedData, HostId, Record, RecordId, RecordIdx, RecordStatus},
+    status,
+};
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 
 #[derive(Error, Debug)]
@@ -26,6 +46,30 @@ pub enum SyncError {
 
     #[error("a request to the sync server failed: {msg:?}")]
     RemoteRequestError { msg: String },
+
+    #[error("this host has been revoked by the sync server, and has been logged out")]
+    HostRevoked { wipe_key: bool },
+
+    #[error("the sync server refused the upload: {msg}")]
+    QuotaExceeded { msg: String },
+}
+
+// Keep a revoked host and a full quota distinct from any other failed request, so that sync
+// can act on them
+fn remote_error(e: eyre::Report) -> SyncError {
+    if let Some(revoked) = e.downcast_ref::<HostRevoked>() {
+        return SyncError::HostRevoked {
+            wipe_key: revoked.wipe_key,
+        };
+    }
+
+    if let Some(quota) = e.downcast_ref::<QuotaExceeded>() {
+        return SyncError::QuotaExceeded {
+            msg: quota.to_string(),
+        };
+    }
+
+    SyncError::RemoteRequestError { msg: e.to_string() }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -49,10 +93,15 @@ pub enum Operation {
     },
 }
 
-pub async fn diff(
-    settings: &Settings,
-    store: &impl Store,
-) -> Result<(Vec<Diff>, RecordStatus), SyncError> {
+// Where to sync: the configured sync backend, or the sync server
+fn remote(settings: &Settings) -> Result<Box<dyn Remote + '_>, SyncError> {
+    if let Some(backend) = &settings.sync_backend {
+        let target = blob::target(backend)
+            .map_err(|e| SyncError::OperationalError { msg: e.to_string() })?;
+
+        return Ok(Box::new(BlobRemote::new(target)));
+    }
+
     let client = Client::new(
         &settings.sync_address,
         settings
@@ -64,21 +113,54 @@ pub async fn diff(
     )
     .map_err(|e| SyncError::OperationalError { msg: e.to_string() })?;
 
+    Ok(Box::new(client))
+}
+
+pub async fn diff(
+    settings: &Settings,
+    store: &impl Store,
+) -> Result<(Vec<Diff>, RecordStatus), SyncError> {
+    let remote = remote(settings)?;
+
     let local_index = store
         .status()
         .await
         .map_err(|e| SyncError::LocalStoreError { msg: e.to_string() })?;
 
-    let remote_index = client
-        .record_status()
-        .await
-        .map_err(|e| SyncError::RemoteRequestError { msg: e.to_string() })?;
+    let remote_index = remote.status().await.map_err(remote_error)?;
+
+    // a host with only the public key couldn't read anything another host wrote, so it only
+    // syncs its own records
+    let write_only = crate::encryption::is_write_only(settings)
+        .map_err(|e| SyncError::OperationalError { msg: e.to_string() })?;
 
-    let diff = local_index.diff(&remote_index);
+    let own_host = Settings::host_id();
+    let diff = local_index
+        .diff(&remote_index)
+        .into_iter()
+        .filter(|diff| selected(settings, own_host, diff))
+        .filter(|diff| !write_only || Some(diff.host) == own_host)
+        .collect();
 
     Ok((diff, remote_index))
 }
 
+// Whether to sync this host's records for this tag. Only downloads can be left out, as this
+// host's own records are always uploaded.
+fn selected(settings: &Settings, own_host: Option<HostId>, diff: &Diff) -> bool {
+    if Some(diff.host) == own_host {
+        return true;
+    }
+
+    let host_excluded = settings
+        .sync
+        .exclude_hosts
+        .iter()
+        .any(|h| uuid::Uuid::parse_str(h).is_ok_and(|h| h == diff.host.0));
+
+    !host_excluded && !settings.sync.exclude_tags.contains(&diff.tag)
+}
+
 // Take a diff, along with a local store, and resolve it into a set of operations.
 // With the store as context, we can determine if a tail exists locally or not and therefore if it needs uploading or download.
 // In theory this could be done as a part of the diffing stage, but it's easier to reason
@@ -156,9 +238,63 @@ pub async fn operations(
     Ok(operations)
 }
 
+// How many pages to download at once. Mostly helps over high latency links.
+const DOWNLOAD_CONCURRENCY: usize = 4;
+
+// Pages start at this many records, then are sized to come to about PAGE_BYTES each. Big
+// records, like dotfiles, get smaller pages, and short commands get fewer requests.
+const PAGE_SIZE: u64 = 100;
+const PAGE_BYTES: usize = 512 * 1024;
+const PAGE_SIZE_RANGE: (u64, u64) = (10, 1000);
+
+/// How many records like these fit in a page
+fn page_size(page: &[Record<EncryptedData>]) -> u64 {
+    let bytes: usize = page
+        .iter()
+        .map(|r| r.data.data.len() + r.data.content_encryption_key.len())
+        .sum();
+
+    if bytes == 0 {
+        return PAGE_SIZE;
+    }
+
+    let average = bytes.div_ceil(page.len());
+
+    ((PAGE_BYTES / average) as u64).clamp(PAGE_SIZE_RANGE.0, PAGE_SIZE_RANGE.1)
+}
+
+fn progress_bar(len: u64) -> ProgressBar {
+    if output::is_quiet() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new(len);
+    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} ({eta})")
+        .unwrap()
+        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+        .progress_chars("#>-"));
+
+    pb
+}
+
+fn log_page(action: &str, page: &[Record<EncryptedData>]) {
+    let (Some(first), Some(last)) = (page.first(), page.last()) else {
+        return;
+    };
+
+    detail!(
+        "{action} {} records, {}..={} of {}/{}",
+        page.len(),
+        first.idx,
+        last.idx,
+        first.host.id.0.as_simple(),
+        first.tag
+    );
+}
+
 async fn sync_upload(
     store: &impl Store,
-    client: &Client<'_>,
+    client: &dyn Remote,
     host: HostId,
     tag: String,
     local: RecordIdx,
@@ -166,45 +302,62 @@ async fn sync_upload(
 ) -> Result<i64, SyncError> {
     let remote = remote.unwrap_or(0);
     let expected = local - remote;
-    let upload_page_size = 100;
     let mut progress = 0;
 
-    let pb = ProgressBar::new(expected);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} ({eta})")
-        .unwrap()
-        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-"));
+    let pb = progress_bar(expected);
 
-    println!(
+    status!(
         "Uploading {} records to {}/{}",
         expected,
         host.0.as_simple(),
         tag
     );
 
+    let tag_ref = tag.as_str();
+    let read = move |start: RecordIdx, count: u64| async move {
+        store.next(host, tag_ref, start, count).await.map_err(|e| {
+            error!("failed to read upload page: {e:?}");
+
+            SyncError::LocalStoreError { msg: e.to_string() }
+        })
+    };
+
     // preload with the first entry if remote does not know of this store
-    loop {
-        let page = store
-            .next(host, tag.as_str(), remote + progress, upload_page_size)
-            .await
-            .map_err(|e| {
-                error!("failed to read upload page: {e:?}");
+    let mut page = read(remote, PAGE_SIZE).await?;
 
-                SyncError::LocalStoreError { msg: e.to_string() }
-            })?;
+    loop {
+        let uploaded = progress + page.len() as u64;
+
+        // pages start at the last record the remote has, so this is done once it's past ours
+        let done = page.is_empty() || remote + uploaded > local;
+
+        // Read the next page while this one uploads. Pages are posted one at a time, in order, as
+        // the server takes the last record it has as the head of the store. If a later page
+        // landed before an earlier one failed, the earlier one would never be retried.
+        let (posted, next) = tokio::join!(client.post_records(&page), async {
+            if done {
+                Ok(Vec::new())
+            } else {
+                read(remote + uploaded, page_size(&page)).await
+            }
+        });
 
-        client.post_records(&page).await.map_err(|e| {
+        posted.map_err(|e| {
             error!("failed to post records: {e:?}");
 
-            SyncError::RemoteRequestError { msg: e.to_string() }
+            remote_error(e)
         })?;
 
+        pb.suspend(|| log_page("uploaded", &page));
+
         pb.set_position(progress);
-        progress += page.len() as u64;
+        progress = uploaded;
 
-        if progress >= expected {
+        if done {
             break;
         }
+
+        page = next?;
     }
 
     pb.finish_with_message("Uploaded records");
@@ -214,7 +367,7 @@ async fn sync_upload(
 
 async fn sync_download(
     store: &impl Store,
-    client: &Client<'_>,
+    client: &dyn Remote,
     host: HostId,
     tag: String,
     local: Option<RecordIdx>,
@@ -222,43 +375,57 @@ async fn sync_download(
 ) -> Result<Vec<RecordId>, SyncError> {
     let local = local.unwrap_or(0);
     let expected = remote - local;
-    let download_page_size = 100;
     let mut progress = 0;
     let mut ret = Vec::new();
 
-    println!(
+    status!(
         "Downloading {} records from {}/{}",
         expected,
         host.0.as_simple(),
         tag
     );
 
-    let pb = ProgressBar::new(expected);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {human_pos}/{human_len} ({eta})")
-        .unwrap()
-        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-"));
+    let pb = progress_bar(expected);
 
-    // preload with the first entry if remote does not know of this store
-    loop {
-        let page = client
-            .next_records(host, tag.clone(), local + progress, download_page_size)
-            .await
-            .map_err(|e| SyncError::RemoteRequestError { msg: e.to_string() })?;
+    // Pages are fetched several at a time, but saved in order, so an interrupted download
+    // leaves no gaps locally. If the server is missing records, pages overlap rather than
+    // leave any out, and the overlap is skipped. Each page is sized by the last one to arrive.
+    let download_page_size = AtomicU64::new(PAGE_SIZE);
+    let download_page_size = &download_page_size;
+
+    let starts = stream::unfold(local, move |start| {
+        let count = download_page_size.load(atomic::Ordering::Relaxed);
+        std::future::ready((start <= remote).then_some(((start, count), start + count)))
+    });
+
+    let mut pages = starts
+        .map(|(start, count)| client.next_records(host, tag.clone(), start, count))
+        .buffered(DOWNLOAD_CONCURRENCY);
+
+    let mut next_idx = local;
+
+    while let Some(page) = pages.next().await {
+        let mut page = page.map_err(remote_error)?;
+
+        download_page_size.store(page_size(&page), atomic::Ordering::Relaxed);
+        page.retain(|r| r.idx >= next_idx);
+
+        let Some(last) = page.last() else {
+            continue;
+        };
+        next_idx = last.idx + 1;
 
         store
             .push_batch(page.iter())
             .await
             .map_err(|e| SyncError::LocalStoreError { msg: e.to_string() })?;
 
+        pb.suspend(|| log_page("downloaded", &page));
+
         ret.extend(page.iter().map(|f| f.id));
 
         pb.set_position(progress);
         progress += page.len() as u64;
-
-        if progress >= expected {
-            break;
-        }
     }
 
     pb.finish_with_message("Downloaded records");
@@ -271,20 +438,18 @@ pub async fn sync_remote(
     local_store: &impl Store,
     settings: &Settings,
 ) -> Result<(i64, Vec<RecordId>), SyncError> {
-    let client = Client::new(
-        &settings.sync_address,
-        settings
-            .session_token()
-            .map_err(|e| SyncError::RemoteRequestError { msg: e.to_string() })?
-            .as_str(),
-        settings.network_connect_timeout,
-        settings.network_timeout,
-    )
-    .expect("failed to create client");
+    let client = remote(settings)?;
 
     let mut uploaded = 0;
     let mut downloaded = Vec::new();
 
+    let local_status = local_store
+        .status()
+        .await
+        .map_err(|e| SyncError::LocalStoreError { msg: e.to_string() })?;
+    let own_host = Settings::host_id();
+    let mut state = SyncState::load();
+
     // this can totally run in parallel, but lets get it working first
     for i in operations {
         match i {
@@ -293,7 +458,14 @@ pub async fn sync_remote(
                 tag,
                 local,
                 remote,
-            } => uploaded += sync_upload(local_store, &client, host, tag, local, remote).await?,
+            } => {
+                uploaded +=
+                    sync_upload(local_store, &*client, host, tag.clone(), local, remote).await?;
+
+                // saved as we go, so a sync that fails part way still counts what it uploaded
+                state.synced(tag, local);
+                state.save();
+            }
 
             Operation::Download {
                 host,
@@ -301,14 +473,22 @@ pub async fn sync_remote(
                 local,
                 remote,
             } => {
-                let mut d = sync_download(local_store, &client, host, tag, local, remote).await?;
+                let mut d = sync_download(local_store, &*client, host, tag, local, remote).await?;
                 downloaded.append(&mut d)
             }
 
-            Operation::Noop { .. } => continue,
+            Operation::Noop { host, tag } => {
+                if Some(host) == own_host {
+                    if let Some(idx) = local_status.get(host, tag.clone()) {
+                        state.synced(tag, idx);
+                    }
+                }
+            }
         }
     }
 
+    state.save();
+
     Ok((uploaded, downloaded))
 }
 
@@ -316,13 +496,365 @@ pub async fn sync(
     settings: &Settings,
     store: &impl Store,
 ) -> Result<(i64, Vec<RecordId>), SyncError> {
-    let (diff, _) = diff(settings, store).await?;
-    let operations = operations(diff, store).await?;
-    let (uploaded, downloaded) = sync_remote(operations, store, settings).await?;
+    let res = async {
+        let (diff, _) = diff(settings, store).await?;
+        let operations = operations(diff, store).await?;
+        sync_remote(operations, store, settings).await
+    }
+    .await;
+
+    match res {
+        Ok(_) => {
+            SyncState::succeeded();
+
+            // Older servers can't say, so this never fails the sync
+            if let Err(e) = check_digest(settings, store).await {
+                debug!("could not compare the local store with the server: {e}");
+            }
+        }
+        Err(SyncError::RemoteRequestError { .. } | SyncError::QuotaExceeded { .. }) => {
+            SyncState::failed()
+        }
+        Err(SyncError::HostRevoked { wipe_key }) => forget_host(settings, wipe_key)?,
+        Err(_) => {}
+    }
+
+    res
+}
+
+/// Sync a store with a remote of its own, rather than the one in settings, such as a shared
+/// channel's. Every host and tag is synced, and the sync state is left alone, as that's only
+/// kept for the main store.
+pub async fn sync_with(
+    store: &impl Store,
+    remote: &dyn Remote,
+) -> Result<(i64, Vec<RecordId>), SyncError> {
+    let local_index = store
+        .status()
+        .await
+        .map_err(|e| SyncError::LocalStoreError { msg: e.to_string() })?;
+    let remote_index = remote.status().await.map_err(remote_error)?;
+
+    let operations = operations(local_index.diff(&remote_index), store).await?;
+
+    let mut uploaded = 0;
+    let mut downloaded = Vec::new();
+
+    for op in operations {
+        match op {
+            Operation::Upload {
+                host,
+                tag,
+                local,
+                remote: idx,
+            } => uploaded += sync_upload(store, remote, host, tag, local, idx).await?,
+
+            Operation::Download {
+                host,
+                tag,
+                local,
+                remote: idx,
+            } => downloaded.append(&mut sync_download(store, remote, host, tag, local, idx).await?),
+
+            Operation::Noop { .. } => {}
+        }
+    }
+
+    Ok((uploaded, downloaded))
+}
+
+/// Where the local store and the server disagree on a host and tag: (local, remote)
+pub type Divergence = (DigestEntry, DigestEntry);
+
+/// Compare digests of the local store and the server's
+pub async fn diverged(
+    settings: &Settings,
+    store: &impl Store,
+) -> Result<Vec<Divergence>, SyncError> {
+    let remote = remote(settings)?;
+
+    let local = store
+        .digest()
+        .await
+        .map_err(|e| SyncError::LocalStoreError { msg: e.to_string() })?;
+    let remote = remote.digest().await.map_err(remote_error)?;
+
+    Ok(local
+        .diverged(&remote)
+        .into_iter()
+        .map(|(local, remote)| (local.clone(), remote.clone()))
+        .collect())
+}
+
+/// After a sync, both sides should have the same records for every host and tag they share. If
+/// they don't, something has gone wrong, and [`reconcile`] can fix it. What's found is noted in
+/// the [`SyncState`].
+pub async fn check_digest(
+    settings: &Settings,
+    store: &impl Store,
+) -> Result<Vec<Divergence>, SyncError> {
+    let diverged = diverged(settings, store).await?;
+
+    for (local, remote) in &diverged {
+        warn!(
+            "local store and server disagree on {}/{}: {} records to {}, {} records to {}",
+            local.host.0.as_simple(),
+            local.tag,
+            local.count,
+            local.idx,
+            remote.count,
+            remote.idx
+        );
+    }
+
+    let mut state = SyncState::load();
+    state.diverged = diverged
+        .iter()
+        .map(|(local, _)| format!("{}/{}", local.host.0.as_simple(), local.tag))
+        .collect();
+    state.save();
+
+    Ok(diverged)
+}
+
+/// Sync every record for these hosts and tags, both ways, filling any gaps on either side
+pub async fn reconcile(
+    settings: &Settings,
+    store: &impl Store,
+    diverged: &[Divergence],
+) -> Result<(i64, Vec<RecordId>), SyncError> {
+    let client = remote(settings)?;
+
+    let mut uploaded = 0;
+    let mut downloaded = Vec::new();
+
+    // Both sides ignore records they already have, so it's safe to send everything
+    for (local, remote) in diverged {
+        let mut d = sync_download(
+            store,
+            &*client,
+            remote.host,
+            remote.tag.clone(),
+            None,
+            remote.idx,
+        )
+        .await?;
+        downloaded.append(&mut d);
+
+        uploaded += sync_upload(
+            store,
+            &*client,
+            local.host,
+            local.tag.clone(),
+            local.idx,
+            None,
+        )
+        .await?;
+    }
 
     Ok((uploaded, downloaded))
 }
 
+const SYNC_STATE_FILENAME: &str = "sync_state.json";
+
+/// What this host knows of the server as of its last sync, so that what's left to upload can be
+/// worked out without asking it, and failed syncs can back off. Records that haven't been
+/// uploaded yet just wait in the local store until the next sync that can reach the server.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SyncState {
+    /// This host's tags, and what the server had of each
+    #[serde(default)]
+    pub tags: BTreeMap<String, TagState>,
+
+    /// How many syncs in a row couldn't reach the server
+    #[serde(default)]
+    pub failures: u32,
+
+    /// Don't try again until this time
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub retry_after: Option<OffsetDateTime>,
+
+    /// Hosts and tags the server disagreed with us on after the last sync, as host/tag
+    #[serde(default)]
+    pub diverged: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagState {
+    /// The last record the server had
+    pub remote: RecordIdx,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_sync: OffsetDateTime,
+}
+
+impl SyncState {
+    /// Backoff after the first failure. It doubles with each one after that.
+    const BACKOFF: time::Duration = time::Duration::seconds(30);
+    const MAX_BACKOFF: time::Duration = time::Duration::hours(1);
+
+    pub fn load() -> Self {
+        let path = atuin_common::utils::data_dir().join(SYNC_STATE_FILENAME);
+
+        fs_err::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    // Losing this only means a little less detail in `atuin sync status`, so it's never worth
+    // failing a sync over
+    fn save(&self) {
+        let path = atuin_common::utils::data_dir().join(SYNC_STATE_FILENAME);
+
+        let res = serde_json::to_string(self)
+            .map_err(eyre::Report::from)
+            .and_then(|s| Ok(fs_err::write(path, s)?));
+
+        if let Err(e) = res {
+            debug!("failed to save sync state: {e:?}");
+        }
+    }
+
+    fn synced(&mut self, tag: String, remote: RecordIdx) {
+        self.tags.insert(
+            tag,
+            TagState {
+                remote,
+                last_sync: OffsetDateTime::now_utc(),
+            },
+        );
+    }
+
+    /// Whether to try syncing now, or keep backing off after failing to reach the server
+    pub fn retry_due(&self) -> bool {
+        self.retry_after
+            .map_or(true, |after| OffsetDateTime::now_utc() >= after)
+    }
+
+    /// Note a sync that couldn't reach the server
+    pub fn failed() {
+        let mut state = Self::load();
+
+        state.failures += 1;
+        state.retry_after = Some(OffsetDateTime::now_utc() + state.backoff());
+        state.save();
+    }
+
+    /// Note a sync that worked, to stop backing off
+    pub fn succeeded() {
+        let mut state = Self::load();
+
+        if state.failures > 0 {
+            state.failures = 0;
+            state.retry_after = None;
+            state.save();
+        }
+    }
+
+    fn backoff(&self) -> time::Duration {
+        let exp = self.failures.saturating_sub(1).min(16);
+
+        (Self::BACKOFF * 2_i32.pow(exp)).min(Self::MAX_BACKOFF)
+    }
+
+    /// How many of each tag's records haven't been uploaded yet, as far as we know
+    pub fn pending(&self, local: &RecordStatus, host: HostId) -> BTreeMap<String, u64> {
+        local
+            .hosts
+            .get(&host)
+            .into_iter()
+            .flatten()
+            .map(|(tag, &idx)| {
+                let pending = match self.tags.get(tag) {
+                    Some(state) => idx.saturating_sub(state.remote),
+                    None => idx + 1,
+                };
+
+                (tag.clone(), pending)
+            })
+            .collect()
+    }
+}
+
+const SYNC_LOCK_FILENAME: &str = "sync.lock";
+
+/// Held while syncing automatically, so that shells finishing commands at the same time - as
+/// they all do after waking from sleep - don't each start a sync. Dropping it unlocks.
+#[derive(Debug)]
+pub struct SyncLock {
+    path: PathBuf,
+}
+
+impl SyncLock {
+    /// A lock this old was left by a sync that died without unlocking
+    const STALE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+    /// Take the lock, or None if another sync has it
+    pub fn acquire() -> Option<Self> {
+        Self::acquire_at(atuin_common::utils::data_dir().join(SYNC_LOCK_FILENAME))
+    }
+
+    fn acquire_at(path: PathBuf) -> Option<Self> {
+        // the second try is after removing a stale lock
+        for _ in 0..2 {
+            match fs_err::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Some(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .is_ok_and(|t| t.elapsed().is_ok_and(|age| age > Self::STALE));
+
+                    if !stale {
+                        return None;
+                    }
+
+                    debug!("removing a stale sync lock");
+                    let _ = fs_err::remove_file(&path);
+                }
+                Err(e) => {
+                    debug!("failed to take the sync lock: {e}");
+                    return None;
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for SyncLock {
+    fn drop(&mut self) {
+        let _ = fs_err::remove_file(&self.path);
+    }
+}
+
+// A revoked host stops syncing by logging out. If the server asked for it, it also deletes its
+// key, so that whoever has the machine can't decrypt anything they may later get hold of.
+fn forget_host(settings: &Settings, wipe_key: bool) -> Result<(), SyncError> {
+    let remove = |path: &str| match fs_err::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(SyncError::OperationalError { msg: e.to_string() })
+        }
+        _ => Ok(()),
+    };
+
+    error!("this host has been revoked by the sync server, logging out");
+    remove(settings.session_path.as_str())?;
+
+    if wipe_key {
+        error!("the sync server asked for this host's key to be deleted");
+        crate::encryption::delete_key(settings)
+            .map_err(|e| SyncError::OperationalError { msg: e.to_string() })?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use atuin_common::record::{Diff, EncryptedData, HostId, Record};
@@ -613,4 +1145,155 @@ mod tests {
 
         assert_eq!(result_ops, operations);
     }
+
+    #[test]
+    fn sync_state_backoff() {
+        let mut state = sync::SyncState::default();
+
+        let backoffs: Vec<_> = (1..=9)
+            .map(|failures| {
+                state.failures = failures;
+                state.backoff().whole_seconds()
+            })
+            .collect();
+
+        assert_eq!(backoffs, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+
+        state.failures = u32::MAX;
+        assert_eq!(state.backoff().whole_seconds(), 3600);
+    }
+
+    #[test]
+    fn sync_state_pending() {
+        let host = HostId(atuin_common::utils::uuid_v7());
+        let other = HostId(atuin_common::utils::uuid_v7());
+
+        let mut local = atuin_common::record::RecordStatus::new();
+        local.set_raw(host, "history".into(), 9);
+        local.set_raw(host, "kv".into(), 2);
+        local.set_raw(other, "history".into(), 100);
+
+        let mut state = sync::SyncState::default();
+        state.synced("history".into(), 4);
+
+        let pending = state.pending(&local, host);
+
+        // other hosts' records are never ours to upload
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending["history"], 5);
+        assert_eq!(pending["kv"], 3);
+    }
+
+    #[test]
+    fn selective_sync() {
+        let own = HostId(atuin_common::utils::uuid_v7());
+        let work = HostId(atuin_common::utils::uuid_v7());
+        let other = HostId(atuin_common::utils::uuid_v7());
+
+        let mut settings = crate::settings::Settings::utc();
+        settings.sync.exclude_hosts = vec![work.0.as_hyphenated().to_string()];
+        settings.sync.exclude_tags = vec!["kv".to_string()];
+
+        let diff = |host, tag: &str| Diff {
+            host,
+            tag: tag.to_string(),
+            local: None,
+            remote: Some(1),
+        };
+
+        assert!(!sync::selected(
+            &settings,
+            Some(own),
+            &diff(work, "history")
+        ));
+        assert!(!sync::selected(&settings, Some(own), &diff(other, "kv")));
+        assert!(sync::selected(
+            &settings,
+            Some(own),
+            &diff(other, "history")
+        ));
+
+        // our own records are always uploaded
+        assert!(sync::selected(&settings, Some(own), &diff(own, "kv")));
+    }
+
+    #[test]
+    fn adaptive_page_size() {
+        let page = |size: usize| -> Vec<Record<EncryptedData>> {
+            (0..10)
+                .map(|_| {
+                    let mut record = test_record();
+                    record.data.data = "a".repeat(size);
+                    record
+                })
+                .collect()
+        };
+
+        assert_eq!(sync::page_size(&[]), 100);
+        assert_eq!(sync::page_size(&page(1024)), 512);
+
+        // clamped, so neither tiny nor huge records make for silly pages
+        assert_eq!(sync::page_size(&page(1)), 1000);
+        assert_eq!(sync::page_size(&page(1024 * 1024)), 10);
+    }
+
+    #[test]
+    fn sync_lock() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-sync-{}.lock",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+
+        let lock = sync::SyncLock::acquire_at(path.clone()).unwrap();
+        assert!(sync::SyncLock::acquire_at(path.clone()).is_none());
+
+        drop(lock);
+        let lock = sync::SyncLock::acquire_at(path.clone()).unwrap();
+
+        // one left behind by a sync that died is taken over
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600))
+            .unwrap();
+        std::mem::forget(lock);
+
+        let lock = sync::SyncLock::acquire_at(path.clone()).unwrap();
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    // Two hosts sharing a channel each send their own records, and get the other's
+    #[tokio::test]
+    async fn sync_with_shared_remote() {
+        use super::super::blob::{BlobRemote, Directory};
+
+        let dir = std::env::temp_dir().join(format!(
+            "atuin-channel-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+
+        let ours = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let theirs = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+
+        let our_record = test_record();
+        let their_record = test_record();
+        ours.push(&our_record).await.unwrap();
+        theirs.push(&their_record).await.unwrap();
+
+        let remote = BlobRemote::new(Box::new(Directory::new(&dir)));
+
+        let (uploaded, downloaded) = sync::sync_with(&ours, &remote).await.unwrap();
+        assert_eq!((uploaded, downloaded), (1, vec![]));
+
+        let (uploaded, downloaded) = sync::sync_with(&theirs, &remote).await.unwrap();
+        assert_eq!((uploaded, downloaded), (1, vec![our_record.id]));
+
+        let (uploaded, downloaded) = sync::sync_with(&ours, &remote).await.unwrap();
+        assert_eq!((uploaded, downloaded), (0, vec![their_record.id]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,85 @@
+//! Human readable labels for shell sessions, which are otherwise only known by their uuid.
+//!
+//! Labels live in the kv store, so they sync along with everything else.
+
+use std::collections::BTreeMap;
+
+use atuin_common::record::HostId;
+use eyre::Result;
+
+use crate::{kv::KvStore, record::store::Store};
+
+const LABEL_NAMESPACE: &str = "atuin.session";
+
+/// Label a session. An empty label removes it.
+pub async fn set_label(
+    store: &(impl Store + Send + Sync),
+    encryption_key: &[u8; 32],
+    host_id: HostId,
+    session: &str,
+    label: &str,
+) -> Result<()> {
+    KvStore::new()
+        .set(
+            store,
+            encryption_key,
+            host_id,
+            LABEL_NAMESPACE,
+            session,
+            label.trim(),
+        )
+        .await
+}
+
+/// Every labelled session, keyed by session id
+pub async fn labels(
+    store: &impl Store,
+    encryption_key: &[u8; 32],
+) -> Result<BTreeMap<String, String>> {
+    let labels = KvStore::new()
+        .list(store, encryption_key, Some(LABEL_NAMESPACE))
+        .await?;
+
+    // the kv store has no deletes, so a removed label is stored as an empty one
+    Ok(labels
+        .into_iter()
+        .filter(|kv| !kv.value.is_empty())
+        .map(|kv| (kv.key, kv.value))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
+    use rand::rngs::OsRng;
+
+    use crate::record::sqlite_store::SqliteStore;
+    use crate::settings::test_local_timeout;
+
+    use super::{labels, set_label};
+
+    #[tokio::test]
+    async fn label_sessions() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+        set_label(&store, &key, host_id, "one", "deploy")
+            .await
+            .unwrap();
+        set_label(&store, &key, host_id, "two", "debugging")
+            .await
+            .unwrap();
+        set_label(&store, &key, host_id, "one", " release ")
+            .await
+            .unwrap();
+        set_label(&store, &key, host_id, "two", "").await.unwrap();
+
+        let labels = labels(&store, &key).await.unwrap();
+
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels.get("one").map(String::as_str), Some("release"));
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap, convert::TryFrom, fmt, io::prelude::*, path::PathBuf, str::FromStr,
+    sync::OnceLock,
 };
 
 use atuin_common::record::HostId;
@@ -10,25 +11,31 @@ use config::{
 use eyre::{bail, eyre, Context, Error, Result};
 use fs_err::{create_dir_all, File};
 use humantime::parse_duration;
-use regex::RegexSet;
+use rand::Rng;
+use regex::{Regex, RegexSet};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use serde_with::DeserializeFromStr;
 use time::{
     format_description::{well_known::Rfc3339, FormatItem},
     macros::format_description,
-    OffsetDateTime, UtcOffset,
+    OffsetDateTime, Time, UtcOffset,
 };
 use uuid::Uuid;
 
+use crate::network::Network;
+
 pub const HISTORY_PAGE_SIZE: i64 = 100;
 pub const LAST_SYNC_FILENAME: &str = "last_sync_time";
-pub const LAST_VERSION_CHECK_FILENAME: &str = "last_version_check_time";
-pub const LATEST_VERSION_FILENAME: &str = "latest_version";
+pub const UPDATE_CHECK_FILENAME: &str = "update_check.json";
 pub const HOST_ID_FILENAME: &str = "host_id";
 static EXAMPLE_CONFIG: &str = include_str!("../config.toml");
 
+// How often Settings::watch checks config.toml for changes
+const SETTINGS_WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
 mod dotfiles;
+pub mod file;
 
 #[derive(Clone, Debug, Deserialize, Copy, ValueEnum, PartialEq, Serialize)]
 pub enum SearchMode {
@@ -83,6 +90,26 @@ pub enum FilterMode {
 
     #[serde(rename = "workspace")]
     Workspace = 4,
+
+    /// History from the same container, or from the host when not in one
+    #[serde(rename = "namespace")]
+    Namespace = 5,
+
+    /// Commands that have been starred
+    #[serde(rename = "starred")]
+    Starred = 6,
+
+    /// History shared to the channels this host subscribes to
+    #[serde(rename = "channel")]
+    Channel = 7,
+
+    /// History from this tmux, zellij or wezterm pane
+    #[serde(rename = "pane")]
+    Pane = 8,
+
+    /// History from containers of this one's image, or from any container when not in one
+    #[serde(rename = "container")]
+    Container = 9,
 }
 
 impl FilterMode {
@@ -93,17 +120,26 @@ impl FilterMode {
             FilterMode::Session => "SESSION",
             FilterMode::Directory => "DIRECTORY",
             FilterMode::Workspace => "WORKSPACE",
+            FilterMode::Namespace => "NAMESPACE",
+            FilterMode::Starred => "STARRED",
+            FilterMode::Channel => "CHANNEL",
+            FilterMode::Pane => "PANE",
+            FilterMode::Container => "CONTAINER",
         }
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Copy, Serialize)]
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq, Serialize)]
 pub enum ExitMode {
     #[serde(rename = "return-original")]
     ReturnOriginal,
 
     #[serde(rename = "return-query")]
     ReturnQuery,
+
+    /// Put the selected command in the shell's buffer, without running it
+    #[serde(rename = "return-selected")]
+    ReturnSelected,
 }
 
 // FIXME: Can use upstream Dialect enum if https://github.com/stevedonovan/chrono-english/pull/16 is merged
@@ -175,6 +211,86 @@ impl FromStr for Timezone {
     }
 }
 
+/// A daily window of local time, eg "08:00-20:00". If the end is before the start, the window
+/// wraps past midnight.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, DeserializeFromStr, Serialize)]
+pub struct SyncWindow {
+    pub start: Time,
+    pub end: Time,
+}
+
+static WINDOW_TIME_FMT: &[FormatItem<'_>] = format_description!("[hour padding:none]:[minute]");
+
+impl SyncWindow {
+    pub fn contains(&self, time: Time) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for SyncWindow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((start, end)) = s.split_once('-') else {
+            bail!(r#""{s}" is not a valid sync window, expected eg "08:00-20:00""#)
+        };
+
+        let start = Time::parse(start.trim(), WINDOW_TIME_FMT)
+            .wrap_err_with(|| format!(r#"invalid start time in sync window "{s}""#))?;
+        let end = Time::parse(end.trim(), WINDOW_TIME_FMT)
+            .wrap_err_with(|| format!(r#"invalid end time in sync window "{s}""#))?;
+
+        Ok(Self { start, end })
+    }
+}
+
+/// Which releases to look out for. `true` and `false` are still read as stable and off, as they
+/// were before there was a beta channel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, DeserializeFromStr, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateCheck {
+    Off,
+    Stable,
+    /// Betas and other pre-releases, as well as stable releases
+    Beta,
+}
+
+impl UpdateCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateCheck::Off => "off",
+            UpdateCheck::Stable => "stable",
+            UpdateCheck::Beta => "beta",
+        }
+    }
+}
+
+impl FromStr for UpdateCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "off" | "false" => Ok(Self::Off),
+            "stable" | "true" => Ok(Self::Stable),
+            "beta" => Ok(Self::Beta),
+            _ => bail!("invalid update_check {s:?}, expected off, stable or beta"),
+        }
+    }
+}
+
+/// The last update check, so that it's only made once per `update_check_frequency`
+#[derive(Debug, Deserialize, Serialize)]
+struct UpdateCheckCache {
+    #[serde(with = "time::serde::rfc3339")]
+    checked: OffsetDateTime,
+    channel: UpdateCheck,
+    latest: String,
+}
+
 #[derive(Clone, Debug, Deserialize, Copy, Serialize)]
 pub enum Style {
     #[serde(rename = "auto")]
@@ -325,12 +441,97 @@ impl Default for Stats {
 #[derive(Clone, Debug, Deserialize, Default, Serialize)]
 pub struct Sync {
     pub records: bool,
+
+    /// Only auto sync within this window of local time
+    pub hours: Option<SyncWindow>,
+
+    /// Don't auto sync over metered connections, where we can detect them
+    pub skip_metered: bool,
+
+    /// Log how long each request to the sync server takes
+    #[serde(default)]
+    pub log_timings: bool,
+
+    /// Minimum time between auto syncs on a named network. Overrides sync_frequency.
+    #[serde(default)]
+    pub network_frequency: HashMap<String, String>,
+
+    /// History fields to leave out of synced records. They're still kept in the local history
+    /// database, but not the local record store, so rebuilding history from it loses them.
+    #[serde(default)]
+    pub strip: Vec<SyncField>,
+
+    /// Host IDs whose records aren't downloaded
+    #[serde(default)]
+    pub exclude_hosts: Vec<String>,
+
+    /// Record tags that aren't downloaded from other hosts
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+
+    /// Compress large records before encrypting them. Versions of atuin from before compression
+    /// can't read them.
+    #[serde(default)]
+    pub compress: bool,
+}
+
+/// A history field that can be kept off the sync server
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq, Serialize)]
+pub enum SyncField {
+    #[serde(rename = "cwd")]
+    Cwd,
+
+    #[serde(rename = "hostname")]
+    Hostname,
+
+    #[serde(rename = "session")]
+    Session,
+}
+
+#[derive(Clone, Debug, Deserialize, Default, Serialize)]
+pub struct Retention {
+    /// Prune history older than this, eg "1y" or "6months"
+    pub max_age: Option<String>,
+
+    /// Prune all but this many of the most recent history entries
+    pub max_count: Option<usize>,
+
+    /// Prune whenever a command finishes, rather than only with `atuin history prune`
+    pub prune_on_save: bool,
+
+    /// Export pruned history to a file in this directory before deleting it
+    pub archive_dir: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Default, Serialize)]
 pub struct Keys {
     pub scroll_exits: bool,
     pub prefix: String,
+
+    /// Bind ctrl-r to atuin when the shell starts
+    pub ctrl_r: bool,
+
+    /// Bind the up arrow to atuin when the shell starts
+    pub up_arrow: bool,
+
+    /// What each way out of the search does, where it's different to `exit_mode`
+    #[serde(default)]
+    pub exit: ExitKeys,
+}
+
+#[derive(Clone, Debug, Deserialize, Default, Serialize)]
+pub struct ExitKeys {
+    /// Esc, or ctrl-[
+    pub esc: Option<ExitMode>,
+
+    /// The left arrow, at the start of the query
+    pub left: Option<ExitMode>,
+
+    /// Scrolling past the newest entry, with `scroll_exits`
+    pub scroll: Option<ExitMode>,
+
+    /// Ctrl-c or ctrl-g, which always return the original buffer unless set
+    pub ctrl_c: Option<ExitMode>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -363,6 +564,9 @@ pub struct Daemon {
     /// The path to the unix socket used by the daemon
     pub socket_path: String,
 
+    /// The path to the unix socket shell hooks send commands to, without starting a process
+    pub shell_socket_path: String,
+
     /// Use a socket passed via systemd's socket activation protocol, instead of the path
     pub systemd_socket: bool,
 
@@ -370,12 +574,98 @@ pub struct Daemon {
     pub tcp_port: u64,
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Age {
+    /// The identity file to decrypt the key with, as given to `age -i`
+    pub identity: String,
+
+    /// Who to encrypt the key to, as given to `age -r`. Usually the public half of `identity`.
+    pub recipient: String,
+
+    /// Keep the unlocked key in the session keyring until logout, so it's only unlocked once.
+    /// Linux only.
+    pub cache: bool,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Search {
     /// The list of enabled filter modes, in order of priority.
     pub filters: Vec<FilterMode>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Export {
+    /// The format used when exporting results from the interactive search
+    pub format: ExportFormat,
+
+    /// The directory exported results are written to. Defaults to the current directory
+    pub directory: String,
+}
+
+/// A channel history is shared to, with a team. Everyone subscribed uses the same backend and
+/// key, and can search everything shared to it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Channel {
+    pub name: String,
+
+    /// Where the channel's records are kept, as with sync_backend: s3://, dav://, davs://, or
+    /// file://
+    pub backend: String,
+
+    /// The channel's key, which everyone in it shares. In the data dir by default.
+    #[serde(default)]
+    pub key_path: Option<String>,
+
+    /// History tagged with any of these is shared to the channel when it syncs
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// History fields left out of what's shared
+    #[serde(default)]
+    pub strip: Vec<SyncField>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Backup {
+    /// Take a backup when a command finishes, if the last is older than `frequency`
+    pub auto: bool,
+
+    /// How often automatic backups are taken, eg "1d"
+    pub frequency: String,
+
+    /// How many backups to keep, deleting the oldest past this. 0 keeps them all
+    pub keep: usize,
+
+    /// The directory backups are written to
+    pub directory: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Import {
+    /// How many entries `atuin import` saves in each transaction
+    pub batch_size: usize,
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self {
+            auto: false,
+            frequency: "1d".to_string(),
+            keep: 7,
+            directory: atuin_common::utils::data_dir()
+                .join("backups")
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+}
+
+impl Default for Import {
+    fn default() -> Self {
+        Self { batch_size: 1000 }
+    }
+}
+
 impl Default for Preview {
     fn default() -> Self {
         Self {
@@ -400,12 +690,22 @@ impl Default for Daemon {
             enabled: false,
             sync_frequency: 300,
             socket_path: "".to_string(),
+            shell_socket_path: "".to_string(),
             systemd_socket: false,
             tcp_port: 8889,
         }
     }
 }
 
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Json,
+            directory: ".".to_string(),
+        }
+    }
+}
+
 impl Default for Search {
     fn default() -> Self {
         Self {
@@ -415,11 +715,54 @@ impl Default for Search {
                 FilterMode::Session,
                 FilterMode::Workspace,
                 FilterMode::Directory,
+                FilterMode::Channel,
             ],
         }
     }
 }
 
+/// What to do with history that looks like it contains a secret
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq, Serialize)]
+pub enum SecretsAction {
+    /// Don't save it at all
+    #[serde(rename = "skip")]
+    Skip,
+
+    /// Save it, with the secret replaced
+    #[serde(rename = "redact")]
+    Redact,
+}
+
+/// Where the sync encryption key is kept
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq, Serialize)]
+pub enum KeyBackend {
+    /// A plain file, at key_path
+    #[serde(rename = "file")]
+    File,
+
+    /// The OS keychain: the macOS Keychain, Windows Credential Manager, or the Secret Service
+    /// (libsecret) on Linux
+    #[serde(rename = "keychain")]
+    Keychain,
+
+    /// A file at key_path, encrypted with age. Unlocking it may need a passphrase, or a touch
+    /// of a hardware key with a plugin such as age-plugin-yubikey.
+    #[serde(rename = "age")]
+    Age,
+}
+
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+pub enum ExportFormat {
+    #[serde(rename = "json")]
+    Json,
+
+    #[serde(rename = "markdown")]
+    Markdown,
+
+    #[serde(rename = "csv")]
+    Csv,
+}
+
 // The preview height strategy also takes max_preview_height into account.
 #[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq, ValueEnum, Serialize)]
 pub enum PreviewStrategy {
@@ -436,18 +779,106 @@ pub enum PreviewStrategy {
     Fixed,
 }
 
+pub const PROJECT_CONFIG_FILENAME: &str = ".atuin.toml";
+
+/// What an `.atuin.toml` in the current directory, or at the root of its git repo, can set. The
+/// file comes with whatever was cloned, so it's only how search behaves, and it can only add to
+/// what's kept out of history. It's applied over config.toml.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectSettings {
+    pub filter_mode: Option<FilterMode>,
+    pub search_mode: Option<SearchMode>,
+    pub inline_height: Option<u16>,
+
+    /// Kept out of history as well as the configured `history_filter`
+    #[serde(default)]
+    pub history_filter: Vec<String>,
+}
+
+impl ProjectSettings {
+    pub const KEYS: [&'static str; 4] = [
+        "filter_mode",
+        "search_mode",
+        "inline_height",
+        "history_filter",
+    ];
+
+    /// The `.atuin.toml` that applies in `dir`, if there is one
+    pub fn path(dir: &str) -> Option<PathBuf> {
+        let here = PathBuf::from(dir).join(PROJECT_CONFIG_FILENAME);
+
+        if here.is_file() {
+            return Some(here);
+        }
+
+        atuin_common::utils::in_git_repo(dir)
+            .map(|root| root.join(PROJECT_CONFIG_FILENAME))
+            .filter(|path| path.is_file())
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let config = Config::builder()
+            .add_source(ConfigFile::from(path).format(FileFormat::Toml))
+            .build()?;
+
+        let keys = config
+            .clone()
+            .try_deserialize::<HashMap<String, config::Value>>()?;
+
+        for key in keys.keys() {
+            if !Self::KEYS.contains(&key.as_str()) {
+                warn!("{path:?} can't set {key}, only {}", Self::KEYS.join(", "));
+            }
+        }
+
+        config
+            .try_deserialize()
+            .map_err(|e| eyre!("failed to deserialize {path:?}: {e}"))
+    }
+
+    /// Override `settings` with these
+    pub fn apply(self, settings: &mut Settings) -> Result<()> {
+        if !self.history_filter.is_empty() {
+            let patterns = settings.history_filter.patterns().iter();
+            settings.history_filter = RegexSet::new(patterns.chain(&self.history_filter))?;
+        }
+
+        if self.filter_mode.is_some() {
+            settings.filter_mode = self.filter_mode;
+        }
+
+        settings.search_mode = self.search_mode.unwrap_or(settings.search_mode);
+        settings.inline_height = self.inline_height.unwrap_or(settings.inline_height);
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub dialect: Dialect,
     pub timezone: Timezone,
     pub style: Style,
     pub auto_sync: bool,
-    pub update_check: bool,
+    pub update_check: UpdateCheck,
+    /// How long an update check is good for, before asking again
+    pub update_check_frequency: String,
+    /// Never touch the network: no sync, and no update checks. `--offline` turns this on for
+    /// one command.
+    pub offline: bool,
+    /// Whether `atuin self-update` may replace the binary. Packages turn this off, so that the
+    /// package manager stays in charge of updates.
+    pub self_update: bool,
     pub sync_address: String,
+    /// Sync records with this blob target, like s3://bucket/prefix, rather than the sync server
+    pub sync_backend: Option<String>,
     pub sync_frequency: String,
     pub db_path: String,
+    /// The auth token for a libSQL db_path, such as a Turso database's
+    pub db_auth_token: Option<String>,
     pub record_store_path: String,
     pub key_path: String,
+    pub key_backend: KeyBackend,
     pub session_path: String,
     pub search_mode: SearchMode,
     pub filter_mode: Option<FilterMode>,
@@ -469,6 +900,8 @@ pub struct Settings {
     pub word_chars: String,
     pub scroll_context_lines: usize,
     pub history_format: String,
+    /// Named templates, to give `--format` or `history_format` by name
+    pub history_formats: HashMap<String, String>,
     pub prefers_reduced_motion: bool,
     pub store_failed: bool,
 
@@ -478,7 +911,16 @@ pub struct Settings {
     #[serde(with = "serde_regex", default = "RegexSet::empty", skip_serializing)]
     pub cwd_filter: RegexSet,
 
+    /// Environment variables to record with each command
+    #[serde(default)]
+    pub history_env: Vec<String>,
+
     pub secrets_filter: bool,
+    pub secrets_action: SecretsAction,
+
+    #[serde(with = "serde_regex", default, skip_serializing)]
+    pub secrets_patterns: Vec<Regex>,
+
     pub workspaces: bool,
     pub ctrl_n_shortcuts: bool,
 
@@ -487,6 +929,8 @@ pub struct Settings {
     pub local_timeout: f64,
     pub enter_accept: bool,
     pub smart_sort: bool,
+    /// Have the shell integration mark the prompt, command and output with OSC 133
+    pub prompt_marks: bool,
 
     #[serde(default)]
     pub stats: Stats,
@@ -494,6 +938,9 @@ pub struct Settings {
     #[serde(default)]
     pub sync: Sync,
 
+    #[serde(default)]
+    pub history: Retention,
+
     #[serde(default)]
     pub keys: Keys,
 
@@ -506,11 +953,27 @@ pub struct Settings {
     #[serde(default)]
     pub daemon: Daemon,
 
+    #[serde(default)]
+    pub age: Age,
+
     #[serde(default)]
     pub search: Search,
 
     #[serde(default)]
     pub theme: Theme,
+
+    #[serde(default)]
+    pub export: Export,
+
+    #[serde(default)]
+    pub import: Import,
+
+    #[serde(default)]
+    pub backup: Backup,
+
+    /// Team channels to share history to, and search
+    #[serde(default)]
+    pub channels: Vec<Channel>,
 }
 
 impl Settings {
@@ -573,18 +1036,15 @@ impl Settings {
         Settings::save_current_time(LAST_SYNC_FILENAME)
     }
 
-    pub fn save_version_check_time() -> Result<()> {
-        Settings::save_current_time(LAST_VERSION_CHECK_FILENAME)
+    /// Record a sync that started at `time`, so the next one picks up from there
+    pub fn save_sync_time_at(time: OffsetDateTime) -> Result<()> {
+        Settings::save_to_data_dir(LAST_SYNC_FILENAME, time.format(&Rfc3339)?.as_str())
     }
 
     pub fn last_sync() -> Result<OffsetDateTime> {
         Settings::load_time_from_file(LAST_SYNC_FILENAME)
     }
 
-    pub fn last_version_check() -> Result<OffsetDateTime> {
-        Settings::load_time_from_file(LAST_VERSION_CHECK_FILENAME)
-    }
-
     pub fn host_id() -> Option<HostId> {
         let id = Settings::read_from_data_dir(HOST_ID_FILENAME);
 
@@ -603,23 +1063,131 @@ impl Settings {
     }
 
     pub fn should_sync(&self) -> Result<bool> {
-        if !self.auto_sync || !PathBuf::from(self.session_path.as_str()).exists() {
+        if !self.auto_sync || !self.can_sync() {
+            return Ok(false);
+        }
+
+        // This runs as every command ends, and finding the network can mean asking the OS. A
+        // sync that isn't due at any frequency isn't due on this network either.
+        if !self.any_sync_due()? {
+            return Ok(false);
+        }
+
+        let network = self.sync_network();
+
+        if !self.sync_scheduled(network.as_ref()) {
             return Ok(false);
         }
 
-        if self.sync_frequency == "0" {
+        let frequency = self
+            .network_sync_frequency(network.as_ref())
+            .unwrap_or(&self.sync_frequency);
+
+        self.sync_due(frequency)
+    }
+
+    fn any_sync_due(&self) -> Result<bool> {
+        let frequencies =
+            std::iter::once(&self.sync_frequency).chain(self.sync.network_frequency.values());
+
+        for frequency in frequencies {
+            if self.sync_due(frequency)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Whether at least `frequency`, and this host's [jitter](Self::sync_jitter), has passed
+    /// since the last sync
+    pub fn sync_due(&self, frequency: &str) -> Result<bool> {
+        if frequency == "0" {
             return Ok(true);
         }
 
-        match parse_duration(self.sync_frequency.as_str()) {
+        match parse_duration(frequency) {
             Ok(d) => {
-                let d = time::Duration::try_from(d).unwrap();
+                let d = time::Duration::try_from(d + Self::sync_jitter(d)).unwrap();
                 Ok(OffsetDateTime::now_utc() - Settings::last_sync()? >= d)
             }
             Err(e) => Err(eyre!("failed to check sync: {}", e)),
         }
     }
 
+    /// How much later than every `period` to sync. Laptops that wake at the same time would
+    /// otherwise all sync at once, so it's up to a tenth of the period, chosen at random once per
+    /// process.
+    pub fn sync_jitter(period: std::time::Duration) -> std::time::Duration {
+        static JITTER: OnceLock<f64> = OnceLock::new();
+
+        period.mul_f64(*JITTER.get_or_init(|| rand::thread_rng().gen_range(0.0..0.1)))
+    }
+
+    /// History from before this point is past the configured `history.max_age`
+    pub fn retention_cutoff(&self) -> Result<Option<OffsetDateTime>> {
+        let Some(max_age) = &self.history.max_age else {
+            return Ok(None);
+        };
+
+        let max_age = parse_duration(max_age)
+            .map_err(|e| eyre!("invalid history.max_age {max_age:?}: {e}"))?;
+        let max_age = time::Duration::try_from(max_age)?;
+
+        Ok(Some(OffsetDateTime::now_utc() - max_age))
+    }
+
+    /// The sync frequency configured for the network we're on, if any
+    pub fn network_sync_frequency(&self, network: Option<&Network>) -> Option<&String> {
+        network
+            .and_then(|n| n.name.as_ref())
+            .and_then(|name| self.sync.network_frequency.get(name))
+    }
+
+    /// The network we're currently on, if the sync schedule needs to know about it
+    pub fn sync_network(&self) -> Option<Network> {
+        if self.sync.skip_metered || !self.sync.network_frequency.is_empty() {
+            Network::current()
+        } else {
+            None
+        }
+    }
+
+    /// Whether the sync schedule allows a sync right now. This doesn't consider how long it has
+    /// been since the last sync.
+    pub fn sync_scheduled(&self, network: Option<&Network>) -> bool {
+        if let Some(hours) = self.sync.hours {
+            let now = OffsetDateTime::now_utc().to_offset(self.timezone.0).time();
+
+            if !hours.contains(now) {
+                debug!("outside of sync hours {:?}, not syncing", hours);
+                return false;
+            }
+        }
+
+        if self.sync.skip_metered && network.is_some_and(|n| n.metered) {
+            debug!("on a metered network, not syncing");
+            return false;
+        }
+
+        true
+    }
+
+    /// Whether there's anywhere to sync to: a sync server we're logged in to, or a sync backend.
+    /// Never while offline.
+    pub fn can_sync(&self) -> bool {
+        !self.offline && (self.logged_in() || (self.sync_backend.is_some() && self.sync.records))
+    }
+
+    /// For commands that can't do anything without the network
+    pub fn ensure_online(&self) -> Result<()> {
+        if self.offline {
+            bail!("atuin is offline, so can't reach the network. Drop --offline, or set offline = false in your config");
+        }
+
+        Ok(())
+    }
+
     pub fn logged_in(&self) -> bool {
         let session_path = self.session_path.as_str();
 
@@ -635,15 +1203,8 @@ impl Settings {
         Ok(fs_err::read_to_string(session_path)?)
     }
 
-    #[cfg(feature = "check-update")]
-    fn needs_update_check(&self) -> Result<bool> {
-        let last_check = Settings::last_version_check()?;
-        let diff = OffsetDateTime::now_utc() - last_check;
-
-        // Check a max of once per hour
-        Ok(diff.whole_hours() >= 1)
-    }
-
+    /// The latest release on the update channel, from the last check if it's recent enough.
+    /// Otherwise, and whenever it can't be found, this version.
     #[cfg(feature = "check-update")]
     async fn latest_version(&self) -> Result<Version> {
         // Default to the current version, and if that doesn't parse, a version so high it's unlikely to ever
@@ -651,34 +1212,41 @@ impl Settings {
         let current =
             Version::parse(env!("CARGO_PKG_VERSION")).unwrap_or(Version::new(100000, 0, 0));
 
-        if !self.needs_update_check()? {
-            // Worst case, we don't want Atuin to fail to start because something funky is going on with
-            // version checking.
-            let version = tokio::task::spawn_blocking(|| {
-                Settings::read_from_data_dir(LATEST_VERSION_FILENAME)
-            })
-            .await
-            .expect("file task panicked");
+        let channel = self.update_check;
+        let ttl = parse_duration(&self.update_check_frequency)
+            .map_err(|e| eyre!("invalid update_check_frequency: {e}"))?;
 
-            let version = match version {
-                Some(v) => Version::parse(&v).unwrap_or(current),
-                None => current,
-            };
+        // Worst case, we don't want Atuin to fail to start because something funky is going on
+        // with version checking.
+        let cached = tokio::task::spawn_blocking(|| {
+            Settings::read_from_data_dir(UPDATE_CHECK_FILENAME)
+                .and_then(|cache| serde_json::from_str::<UpdateCheckCache>(&cache).ok())
+        })
+        .await
+        .expect("file task panicked");
 
-            return Ok(version);
+        if let Some(cached) = cached.filter(|cached| cached.channel == channel) {
+            if OffsetDateTime::now_utc() - cached.checked < ttl {
+                return Ok(Version::parse(&cached.latest).unwrap_or(current));
+            }
         }
 
         #[cfg(feature = "sync")]
-        let latest = crate::api_client::latest_version().await.unwrap_or(current);
+        let latest = crate::api_client::latest_version(channel)
+            .await
+            .unwrap_or(current);
 
         #[cfg(not(feature = "sync"))]
         let latest = current;
 
-        let latest_encoded = latest.to_string();
+        let cache = UpdateCheckCache {
+            checked: OffsetDateTime::now_utc(),
+            channel,
+            latest: latest.to_string(),
+        };
+        let cache = serde_json::to_string(&cache)?;
         tokio::task::spawn_blocking(move || {
-            Settings::save_version_check_time()?;
-            Settings::save_to_data_dir(LATEST_VERSION_FILENAME, &latest_encoded)?;
-            Ok::<(), eyre::Report>(())
+            Settings::save_to_data_dir(UPDATE_CHECK_FILENAME, &cache)
         })
         .await
         .expect("file task panicked")?;
@@ -689,7 +1257,7 @@ impl Settings {
     // Return Some(latest version) if an update is needed. Otherwise, none.
     #[cfg(feature = "check-update")]
     pub async fn needs_update(&self) -> Option<Version> {
-        if !self.update_check {
+        if self.update_check == UpdateCheck::Off || self.offline {
             return None;
         }
 
@@ -711,6 +1279,16 @@ impl Settings {
         None
     }
 
+    /// The template to list history with: `format` if it was given, or else `history_format`.
+    /// Either can be the name of one of the `history_formats` instead.
+    pub fn history_template<'a>(&'a self, format: Option<&'a str>) -> &'a str {
+        let format = format.unwrap_or(&self.history_format);
+
+        self.history_formats
+            .get(format)
+            .map_or(format, String::as_str)
+    }
+
     pub fn default_filter_mode(&self) -> FilterMode {
         self.filter_mode
             .filter(|x| self.search.filters.contains(x))
@@ -724,25 +1302,55 @@ impl Settings {
     }
 
     pub fn builder() -> Result<ConfigBuilder<DefaultState>> {
+        Ok(Self::defaults()?.add_source(
+            Environment::with_prefix("atuin")
+                .prefix_separator("_")
+                .separator("__"),
+        ))
+    }
+
+    /// Every setting's default, before config.toml or the environment
+    pub fn defaults() -> Result<ConfigBuilder<DefaultState>> {
         let data_dir = atuin_common::utils::data_dir();
         let db_path = data_dir.join("history.db");
         let record_store_path = data_dir.join("records.db");
-        let socket_path = atuin_common::utils::runtime_dir().join("atuin.sock");
+        // the runtime dir may be shared by every profile, so each names its sockets
+        let socket_name = atuin_common::utils::profile().map_or_else(
+            || String::from("atuin"),
+            |profile| format!("atuin-{profile}"),
+        );
+        let runtime_dir = atuin_common::utils::runtime_dir();
+        let socket_path = runtime_dir.join(format!("{socket_name}.sock"));
+        let shell_socket_path = runtime_dir.join(format!("{socket_name}-shell.sock"));
 
         let key_path = data_dir.join("key");
         let session_path = data_dir.join("session");
 
         Ok(Config::builder()
             .set_default("history_format", "{time}\t{command}\t{duration}")?
+            .set_default("history_formats", HashMap::<String, String>::new())?
             .set_default("db_path", db_path.to_str())?
+            .set_default("db_auth_token", None::<String>)?
             .set_default("record_store_path", record_store_path.to_str())?
             .set_default("key_path", key_path.to_str())?
+            .set_default("key_backend", "file")?
             .set_default("session_path", session_path.to_str())?
             .set_default("dialect", "us")?
             .set_default("timezone", "local")?
             .set_default("auto_sync", true)?
-            .set_default("update_check", cfg!(feature = "check-update"))?
+            .set_default(
+                "update_check",
+                if cfg!(feature = "check-update") {
+                    "stable"
+                } else {
+                    "off"
+                },
+            )?
+            .set_default("update_check_frequency", "1h")?
+            .set_default("offline", false)?
+            .set_default("self_update", true)?
             .set_default("sync_address", "https://api.atuin.sh")?
+            .set_default("sync_backend", None::<String>)?
             .set_default("sync_frequency", "10m")?
             .set_default("search_mode", "fuzzy")?
             .set_default("filter_mode", None::<String>)?
@@ -766,6 +1374,7 @@ impl Settings {
             .set_default("workspaces", false)?
             .set_default("ctrl_n_shortcuts", false)?
             .set_default("secrets_filter", true)?
+            .set_default("secrets_action", "skip")?
             .set_default("network_connect_timeout", 5)?
             .set_default("network_timeout", 30)?
             .set_default("local_timeout", 2.0)?
@@ -776,22 +1385,47 @@ impl Settings {
             // New users will get the new default, that is more similar to what they are used to.
             .set_default("enter_accept", false)?
             .set_default("sync.records", true)?
+            .set_default("sync.skip_metered", false)?
+            .set_default("sync.log_timings", false)?
+            .set_default("sync.compress", false)?
+            .set_default("history.prune_on_save", false)?
             .set_default("keys.scroll_exits", true)?
             .set_default("keys.prefix", "a")?
+            .set_default("keys.ctrl_r", true)?
+            .set_default("keys.up_arrow", true)?
             .set_default("keymap_mode", "emacs")?
             .set_default("keymap_mode_shell", "auto")?
             .set_default("keymap_cursor", HashMap::<String, String>::new())?
             .set_default("smart_sort", false)?
             .set_default("store_failed", true)?
+            .set_default("prompt_marks", false)?
+            .set_default("age.identity", "")?
+            .set_default("age.recipient", "")?
+            .set_default("age.cache", true)?
             .set_default("daemon.sync_frequency", 300)?
             .set_default("daemon.enabled", false)?
             .set_default("daemon.socket_path", socket_path.to_str())?
+            .set_default("daemon.shell_socket_path", shell_socket_path.to_str())?
             .set_default("daemon.systemd_socket", false)?
             .set_default("daemon.tcp_port", 8889)?
             .set_default(
                 "search.filters",
-                vec!["global", "host", "session", "workspace", "directory"],
+                vec![
+                    "global",
+                    "host",
+                    "session",
+                    "workspace",
+                    "directory",
+                    "channel",
+                ],
             )?
+            .set_default("export.format", "json")?
+            .set_default("export.directory", ".")?
+            .set_default("import.batch_size", 1000)?
+            .set_default("backup.auto", false)?
+            .set_default("backup.frequency", "1d")?
+            .set_default("backup.keep", 7)?
+            .set_default("backup.directory", data_dir.join("backups").to_str())?
             .set_default("theme.name", "default")?
             .set_default("theme.debug", None::<bool>)?
             .set_default(
@@ -800,12 +1434,19 @@ impl Settings {
                     .ok()
                     .map(|_| config::Value::new(None, config::ValueKind::Boolean(true)))
                     .unwrap_or_else(|| config::Value::new(None, config::ValueKind::Boolean(false))),
-            )?
-            .add_source(
-                Environment::with_prefix("atuin")
-                    .prefix_separator("_")
-                    .separator("__"),
-            ))
+            )?)
+    }
+
+    /// Where config.toml is, which is `$ATUIN_CONFIG_DIR` if that's set
+    pub fn config_path() -> PathBuf {
+        let mut config_file = if let Ok(p) = std::env::var("ATUIN_CONFIG_DIR") {
+            PathBuf::from(p)
+        } else {
+            atuin_common::utils::config_dir()
+        };
+
+        config_file.push("config.toml");
+        config_file
     }
 
     pub fn new() -> Result<Self> {
@@ -817,15 +1458,7 @@ impl Settings {
 
         create_dir_all(&data_dir).wrap_err_with(|| format!("could not create dir {data_dir:?}"))?;
 
-        let mut config_file = if let Ok(p) = std::env::var("ATUIN_CONFIG_DIR") {
-            PathBuf::from(p)
-        } else {
-            let mut config_file = PathBuf::new();
-            config_file.push(config_dir);
-            config_file
-        };
-
-        config_file.push("config.toml");
+        let config_file = Self::config_path();
 
         let mut config_builder = Self::builder()?;
 
@@ -860,9 +1493,108 @@ impl Settings {
         let session_path = shellexpand::full(&session_path)?;
         settings.session_path = session_path.to_string();
 
+        let backup_dir = settings.backup.directory;
+        let backup_dir = shellexpand::full(&backup_dir)?;
+        settings.backup.directory = backup_dir.to_string();
+
+        if let Some(archive_dir) = settings.history.archive_dir {
+            let archive_dir = shellexpand::full(&archive_dir)?;
+            settings.history.archive_dir = Some(archive_dir.to_string());
+        }
+
+        for channel in &mut settings.channels {
+            if let Some(key_path) = &channel.key_path {
+                channel.key_path = Some(shellexpand::full(key_path)?.to_string());
+            }
+        }
+
+        // a broken .atuin.toml shouldn't stop atuin working anywhere in the project
+        if let Some(path) = ProjectSettings::path(&atuin_common::utils::get_current_dir()) {
+            if let Err(e) = ProjectSettings::load(&path).and_then(|p| p.apply(&mut settings)) {
+                warn!("ignoring {path:?}: {e}");
+            }
+        }
+
         Ok(settings)
     }
 
+    /// These settings, then the settings again each time config.toml changes, for processes that
+    /// run for a while, like the search UI and the daemon. If changed settings can't be loaded
+    /// they're logged and skipped. Must be called from within a tokio runtime, and stops watching
+    /// once every receiver has been dropped.
+    pub fn watch(&self) -> tokio::sync::watch::Receiver<Settings> {
+        let (tx, rx) = tokio::sync::watch::channel(self.clone());
+
+        // the file is polled, as it's cheap to check and doesn't need a watcher per platform
+        tokio::spawn(async move {
+            let path = Self::config_path();
+            let modified = |path: &PathBuf| fs_err::metadata(path).and_then(|m| m.modified()).ok();
+
+            let mut last = modified(&path);
+            let mut interval = tokio::time::interval(SETTINGS_WATCH_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    () = tx.closed() => break,
+                    _ = interval.tick() => {}
+                }
+
+                let now = modified(&path);
+                if now == last {
+                    continue;
+                }
+
+                last = now;
+
+                match tokio::task::spawn_blocking(Self::new).await {
+                    Ok(Ok(settings)) => {
+                        debug!("reloaded {path:?}");
+                        tx.send_replace(settings);
+                    }
+                    Ok(Err(e)) => warn!("not reloading {path:?}: {e}"),
+                    Err(e) => warn!("not reloading {path:?}: {e}"),
+                }
+            }
+        });
+
+        rx
+    }
+
+    /// Take the settings a running search can change to, its look, keys and search settings, from
+    /// `reloaded`. The search, filter and keymap modes may have been set on the command line, so
+    /// they only change if config.toml changed them since `previous` was loaded.
+    pub fn reload_interactive(&mut self, previous: &Settings, reloaded: &Settings) {
+        self.style = reloaded.style;
+        self.invert = reloaded.invert;
+        self.show_preview = reloaded.show_preview;
+        self.max_preview_height = reloaded.max_preview_height;
+        self.show_help = reloaded.show_help;
+        self.show_tabs = reloaded.show_tabs;
+        self.exit_mode = reloaded.exit_mode;
+        self.keymap_cursor.clone_from(&reloaded.keymap_cursor);
+        self.word_jump_mode = reloaded.word_jump_mode;
+        self.word_chars.clone_from(&reloaded.word_chars);
+        self.scroll_context_lines = reloaded.scroll_context_lines;
+        self.enter_accept = reloaded.enter_accept;
+        self.smart_sort = reloaded.smart_sort;
+        self.keys = reloaded.keys.clone();
+        self.preview = reloaded.preview.clone();
+        self.search = reloaded.search.clone();
+        self.theme = reloaded.theme.clone();
+
+        if previous.search_mode != reloaded.search_mode {
+            self.search_mode = reloaded.search_mode;
+        }
+
+        if previous.filter_mode != reloaded.filter_mode {
+            self.filter_mode = reloaded.filter_mode;
+        }
+
+        if previous.keymap_mode != reloaded.keymap_mode {
+            self.keymap_mode = reloaded.keymap_mode;
+        }
+    }
+
     pub fn example_config() -> &'static str {
         EXAMPLE_CONFIG
     }
@@ -897,7 +1629,11 @@ mod tests {
 
     use eyre::Result;
 
-    use super::Timezone;
+    use time::macros::time;
+
+    use super::{
+        KeymapMode, ProjectSettings, SearchMode, Settings, SyncWindow, Timezone, UpdateCheck,
+    };
 
     #[test]
     fn can_parse_offset_timezone_spec() -> Result<()> {
@@ -928,4 +1664,129 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn can_parse_sync_window() -> Result<()> {
+        let day = SyncWindow::from_str("08:00-20:00")?;
+        assert_eq!((day.start, day.end), (time!(8:00), time!(20:00)));
+        assert!(day.contains(time!(8:00)));
+        assert!(day.contains(time!(19:59)));
+        assert!(!day.contains(time!(20:00)));
+        assert!(!day.contains(time!(3:00)));
+
+        // wraps past midnight
+        let night = SyncWindow::from_str("22:30 - 6:00")?;
+        assert!(night.contains(time!(23:00)));
+        assert!(night.contains(time!(1:00)));
+        assert!(!night.contains(time!(12:00)));
+
+        assert!(SyncWindow::from_str("08:00").is_err());
+        assert!(SyncWindow::from_str("8am-8pm").is_err());
+        assert!(SyncWindow::from_str("08:00-25:00").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_parse_update_check() -> Result<()> {
+        assert_eq!(UpdateCheck::from_str("beta")?, UpdateCheck::Beta);
+        assert_eq!(UpdateCheck::from_str("Stable")?, UpdateCheck::Stable);
+
+        // from when update_check was a bool
+        assert_eq!(UpdateCheck::from_str("true")?, UpdateCheck::Stable);
+        assert_eq!(UpdateCheck::from_str("false")?, UpdateCheck::Off);
+
+        assert!(UpdateCheck::from_str("nightly").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_template() {
+        let mut settings = Settings::utc();
+        settings.history_format = "{time}\t{command}".to_string();
+        settings
+            .history_formats
+            .insert("short".to_string(), "{dir} {command}".to_string());
+
+        assert_eq!(settings.history_template(None), "{time}\t{command}");
+        assert_eq!(settings.history_template(Some("short")), "{dir} {command}");
+        assert_eq!(settings.history_template(Some("{exit}")), "{exit}");
+
+        settings.history_format = "short".to_string();
+        assert_eq!(settings.history_template(None), "{dir} {command}");
+    }
+
+    #[test]
+    fn project_settings() -> Result<()> {
+        let repo = std::env::temp_dir().join(format!(
+            "atuin-project-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        let subdir = repo.join("src");
+        fs_err::create_dir_all(repo.join(".git"))?;
+        fs_err::create_dir_all(&subdir)?;
+
+        assert!(ProjectSettings::path(subdir.to_str().unwrap()).is_none());
+
+        // found at the root of the repo, from anywhere in it
+        let path = repo.join(".atuin.toml");
+        fs_err::write(
+            &path,
+            "search_mode = \"prefix\"\nhistory_filter = [\"^vault\"]\ndb_path = \"/tmp/x\"\n",
+        )?;
+        assert_eq!(
+            ProjectSettings::path(subdir.to_str().unwrap()),
+            Some(path.clone())
+        );
+
+        let mut settings = Settings {
+            history_filter: regex::RegexSet::new(["^psql"])?,
+            ..Settings::utc()
+        };
+        let db_path = settings.db_path.clone();
+        ProjectSettings::load(&path)?.apply(&mut settings)?;
+
+        assert_eq!(settings.search_mode, SearchMode::Prefix);
+        assert_eq!(settings.history_filter.patterns(), ["^psql", "^vault"]);
+        assert_eq!(settings.db_path, db_path);
+
+        fs_err::remove_dir_all(&repo)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_interactive() {
+        // --search-mode fuzzy, with config.toml saying prefix
+        let loaded = Settings {
+            search_mode: SearchMode::Prefix,
+            ..Settings::utc()
+        };
+        let mut settings = Settings {
+            search_mode: SearchMode::Fuzzy,
+            ..loaded.clone()
+        };
+
+        let reloaded = Settings {
+            invert: !loaded.invert,
+            keymap_mode: KeymapMode::VimInsert,
+            ..loaded.clone()
+        };
+        settings.reload_interactive(&loaded, &reloaded);
+
+        assert_eq!(settings.invert, reloaded.invert);
+        assert_eq!(settings.keymap_mode, KeymapMode::VimInsert);
+        assert_eq!(settings.search_mode, SearchMode::Fuzzy);
+
+        // until config.toml changes it
+        let loaded = reloaded;
+        let reloaded = Settings {
+            search_mode: SearchMode::FullText,
+            ..loaded.clone()
+        };
+        settings.reload_interactive(&loaded, &reloaded);
+
+        assert_eq!(settings.search_mode, SearchMode::FullText);
+    }
 }
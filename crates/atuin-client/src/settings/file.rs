@@ -0,0 +1,340 @@
+//! Reading and editing config.toml, checked against the settings atuin knows about. Editing keeps
+//! the file's comments and layout.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use config::{File as ConfigFile, FileFormat};
+use eyre::{bail, eyre, Result};
+use regex::Regex;
+use serde_json::Value;
+use toml_edit::{DocumentMut, Item, Table};
+
+use super::{ProjectSettings, Settings};
+
+/// Every setting, with its default, by its dotted key (such as `daemon.enabled`)
+pub fn defaults() -> Result<BTreeMap<String, Value>> {
+    let defaults: Settings = Settings::defaults()?
+        .build()?
+        .try_deserialize()
+        .map_err(|e| eyre!("failed to deserialize: {e}"))?;
+
+    Ok(flatten(to_value(&defaults)?))
+}
+
+/// Settings as JSON, including the regex filters that aren't otherwise serialized
+pub fn to_value(settings: &Settings) -> Result<Value> {
+    let mut value = serde_json::to_value(settings)?;
+
+    if let Value::Object(map) = &mut value {
+        let secrets: Vec<&str> = settings
+            .secrets_patterns
+            .iter()
+            .map(Regex::as_str)
+            .collect();
+
+        map.insert(
+            "history_filter".into(),
+            settings.history_filter.patterns().into(),
+        );
+        map.insert("cwd_filter".into(), settings.cwd_filter.patterns().into());
+        map.insert("secrets_patterns".into(), secrets.into());
+    }
+
+    Ok(value)
+}
+
+/// Settings by their dotted keys. Tables of settings are flattened, but maps (which are empty by
+/// default) and lists are values of their own.
+pub fn flatten(value: Value) -> BTreeMap<String, Value> {
+    fn walk(prefix: &str, value: Value, out: &mut BTreeMap<String, Value>) {
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                for (key, value) in map {
+                    let key = if prefix.is_empty() {
+                        key
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+
+                    walk(&key, value, out);
+                }
+            }
+            value => {
+                out.insert(prefix.to_string(), value);
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    walk("", value, &mut out);
+    out
+}
+
+/// Whether atuin has a setting called `key`. Keys within maps, such as
+/// `sync.network_frequency.Home`, are known if the map is.
+pub fn known(defaults: &BTreeMap<String, Value>, key: &str) -> bool {
+    if defaults.contains_key(key) {
+        return true;
+    }
+
+    let mut parent = key;
+    while let Some((p, _)) = parent.rsplit_once('.') {
+        if matches!(defaults.get(p), Some(Value::Object(_))) {
+            return true;
+        }
+
+        parent = p;
+    }
+
+    false
+}
+
+/// Where a setting's value comes from, with the first that's set taking precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The project's `.atuin.toml`
+    Project(PathBuf),
+    File,
+    /// The environment variable that sets it
+    Env(String),
+    Default,
+}
+
+/// The environment variable that sets `key`, such as `ATUIN_DAEMON__ENABLED`
+pub fn env_var(key: &str) -> String {
+    format!("ATUIN_{}", key.replace('.', "__").to_uppercase())
+}
+
+pub struct SettingsFile {
+    pub path: PathBuf,
+    doc: DocumentMut,
+
+    /// The settings a project's `.atuin.toml` overrides, if there is one here
+    project: Option<(PathBuf, Vec<String>)>,
+}
+
+impl SettingsFile {
+    /// The config file, which is empty if there isn't one yet
+    pub fn load() -> Result<Self> {
+        let path = Settings::config_path();
+
+        let doc = if path.exists() {
+            fs_err::read_to_string(&path)?
+                .parse()
+                .map_err(|e| eyre!("{path:?} isn't valid TOML: {e}"))?
+        } else {
+            DocumentMut::new()
+        };
+
+        let project = ProjectSettings::path(&atuin_common::utils::get_current_dir()).map(|path| {
+            let keys = fs_err::read_to_string(&path)
+                .ok()
+                .and_then(|s| s.parse::<DocumentMut>().ok())
+                .map(|doc| doc.iter().map(|(key, _)| key.to_string()).collect())
+                .unwrap_or_default();
+
+            (path, keys)
+        });
+
+        Ok(Self { path, doc, project })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs_err::write(&self.path, self.doc.to_string())?;
+
+        Ok(())
+    }
+
+    /// The value `key` is set to, if the file sets it
+    pub fn get(&self, key: &str) -> Option<&Item> {
+        key.split('.')
+            .try_fold(self.doc.as_item(), |item, part| {
+                item.as_table_like()?.get(part)
+            })
+            .filter(|item| !item.is_none())
+    }
+
+    /// Every setting in the file, by its dotted key
+    pub fn keys(&self) -> Vec<String> {
+        fn walk(prefix: &str, item: &Item, out: &mut Vec<String>) {
+            match item.as_table_like() {
+                Some(table) => {
+                    for (key, item) in table.iter() {
+                        let key = if prefix.is_empty() {
+                            key.to_string()
+                        } else {
+                            format!("{prefix}.{key}")
+                        };
+
+                        walk(&key, item, out);
+                    }
+                }
+                None => out.push(prefix.to_string()),
+            }
+        }
+
+        let mut out = Vec::new();
+        walk("", self.doc.as_item(), &mut out);
+        out
+    }
+
+    /// Set `key` to `value`, which is read as the same type as the setting's default. Fails,
+    /// leaving the file as it was, if atuin couldn't load the result.
+    pub fn set(
+        &mut self,
+        defaults: &BTreeMap<String, Value>,
+        key: &str,
+        value: &str,
+    ) -> Result<()> {
+        let value = typed(defaults.get(key), value);
+        let before = self.doc.clone();
+
+        let (parents, name) = match key.rsplit_once('.') {
+            Some((parents, name)) => (parents.split('.').collect(), name),
+            None => (Vec::new(), key),
+        };
+
+        let mut table = self.doc.as_table_mut();
+        for part in parents {
+            // implicit, so a table that only holds tables doesn't get a header of its own
+            let item = table.entry(part).or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            });
+
+            let Some(next) = item.as_table_mut() else {
+                self.doc = before;
+                bail!("{key} is inside {part}, which isn't a table");
+            };
+
+            table = next;
+        }
+
+        table[name] = toml_edit::value(value);
+
+        if let Err(e) = self.validate() {
+            self.doc = before;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// The settings in this file, over the defaults. The environment isn't considered.
+    pub fn validate(&self) -> Result<Settings> {
+        Settings::defaults()?
+            .add_source(ConfigFile::from_str(
+                &self.doc.to_string(),
+                FileFormat::Toml,
+            ))
+            .build()?
+            .try_deserialize()
+            .map_err(|e| eyre!("{e}"))
+    }
+
+    /// The project's `.atuin.toml`, if there is one here, and the settings it has
+    pub fn project(&self) -> Option<&(PathBuf, Vec<String>)> {
+        self.project.as_ref()
+    }
+
+    /// Where `key` is set, if anywhere. As settings are loaded, a project's `.atuin.toml` wins
+    /// over config.toml, which wins over the environment.
+    pub fn source(&self, key: &str) -> Source {
+        let var = env_var(key);
+        let project = self.project.as_ref().filter(|(_, keys)| {
+            ProjectSettings::KEYS.contains(&key) && keys.iter().any(|k| k == key)
+        });
+
+        if let Some((path, _)) = project {
+            Source::Project(path.clone())
+        } else if self.get(key).is_some() {
+            Source::File
+        } else if std::env::var_os(&var).is_some() {
+            Source::Env(var)
+        } else {
+            Source::Default
+        }
+    }
+}
+
+// Settings that are strings, like `sync_frequency = "10m"`, are kept as strings whatever they
+// look like. Anything else is read as TOML, falling back to a string.
+fn typed(default: Option<&Value>, value: &str) -> toml_edit::Value {
+    if matches!(default, Some(Value::String(_))) {
+        return value.into();
+    }
+
+    value.parse().unwrap_or_else(|_| value.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{defaults, known, SettingsFile};
+
+    fn file(contents: &str) -> SettingsFile {
+        SettingsFile {
+            path: "config.toml".into(),
+            doc: contents.parse().unwrap(),
+            project: None,
+        }
+    }
+
+    #[test]
+    fn known_keys() {
+        let defaults = defaults().unwrap();
+
+        assert!(known(&defaults, "auto_sync"));
+        assert!(known(&defaults, "daemon.enabled"));
+        assert!(known(&defaults, "search.filters"));
+        assert!(known(&defaults, "sync.network_frequency.Home"));
+        assert!(known(&defaults, "history_filter"));
+        assert!(known(&defaults, "secrets_patterns"));
+
+        assert!(!known(&defaults, "auto_snyc"));
+        assert!(!known(&defaults, "daemon.enabeld"));
+    }
+
+    #[test]
+    fn set() {
+        let defaults = defaults().unwrap();
+        let mut file = file("# sync often\nsync_frequency = \"5m\"\n\n[daemon]\nenabled = false\n");
+
+        file.set(&defaults, "daemon.enabled", "true").unwrap();
+        file.set(&defaults, "sync_frequency", "1h").unwrap();
+        file.set(&defaults, "history_env", "[\"AWS_PROFILE\"]")
+            .unwrap();
+        file.set(&defaults, "sync.records", "false").unwrap();
+        file.set(&defaults, "keys.exit.esc", "return-selected")
+            .unwrap();
+
+        assert_eq!(
+            file.doc.to_string(),
+            "# sync often\nsync_frequency = \"1h\"\nhistory_env = [\"AWS_PROFILE\"]\n\n[daemon]\nenabled = true\n\n[sync]\nrecords = false\n\n[keys.exit]\nesc = \"return-selected\"\n"
+        );
+        assert_eq!(
+            file.keys(),
+            [
+                "sync_frequency",
+                "daemon.enabled",
+                "history_env",
+                "sync.records",
+                "keys.exit.esc"
+            ]
+        );
+
+        let settings = file.validate().unwrap();
+        assert!(settings.daemon.enabled);
+        assert_eq!(settings.history_env, ["AWS_PROFILE"]);
+    }
+
+    #[test]
+    fn set_wrong_type() {
+        let defaults = defaults().unwrap();
+        let mut file = file("auto_sync = true\n");
+
+        assert!(file.set(&defaults, "auto_sync", "sometimes").is_err());
+        assert!(file.set(&defaults, "auto_sync.often", "true").is_err());
+        assert_eq!(file.doc.to_string(), "auto_sync = true\n");
+    }
+}
@@ -6,15 +6,26 @@ use eyre::Result;
 
 use atuin_common::api::AddHistoryRequest;
 use crypto_secretbox::Key;
-use time::OffsetDateTime;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use crate::{
     api_client,
     database::Database,
     encryption::{decrypt, encrypt, load_key},
-    settings::Settings,
+    history::HistoryId,
+    settings::{Settings, SyncField},
 };
 
+const DOWNLOAD_CURSOR_FILENAME: &str = "sync_download_cursor";
+const UPLOAD_CURSOR_FILENAME: &str = "sync_upload_cursor";
+
+/// How far through a sync we are, reported after every page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncProgress {
+    Upload { done: i64, total: i64 },
+    Download { done: i64, total: i64 },
+}
+
 pub fn hash_str(string: &str) -> String {
     use sha2::{Digest, Sha256};
     let mut hasher = Sha256::new();
@@ -31,6 +42,37 @@ pub fn hash_str(string: &str) -> String {
 // with year, then find the week, then the day, then the hour, then download it
 // all! The current naive approach will do for now.
 
+// Where an interrupted sync got to, saved after every page so the next sync can carry on from
+// there. One timestamp per line.
+fn read_cursor(filename: &str) -> Option<Vec<OffsetDateTime>> {
+    let path = atuin_common::utils::data_dir().join(filename);
+    let value = fs_err::read_to_string(path).ok()?;
+
+    value
+        .lines()
+        .map(|line| OffsetDateTime::parse(line, &Rfc3339).ok())
+        .collect()
+}
+
+fn save_cursor(filename: &str, cursor: &[OffsetDateTime]) -> Result<()> {
+    let value = cursor
+        .iter()
+        .map(|t| t.format(&Rfc3339))
+        .collect::<Result<Vec<_>, _>>()?
+        .join("\n");
+
+    fs_err::write(atuin_common::utils::data_dir().join(filename), value)?;
+
+    Ok(())
+}
+
+fn clear_cursor(filename: &str) -> Result<()> {
+    match fs_err::remove_file(atuin_common::utils::data_dir().join(filename)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
 // Check if remote has things we don't, and if so, download them.
 // Returns (num downloaded, total local)
 async fn sync_download(
@@ -38,6 +80,7 @@ async fn sync_download(
     force: bool,
     client: &api_client::Client<'_>,
     db: &impl Database,
+    on_progress: &impl Fn(SyncProgress),
 ) -> Result<(i64, i64)> {
     debug!("starting sync download");
 
@@ -51,14 +94,15 @@ async fn sync_download(
     let initial_local = db.history_count(true).await?;
     let mut local_count = initial_local;
 
-    let mut last_sync = if force {
-        OffsetDateTime::UNIX_EPOCH
-    } else {
-        Settings::last_sync()?
+    let (mut last_sync, mut last_timestamp) = match read_cursor(DOWNLOAD_CURSOR_FILENAME) {
+        Some(cursor) if !force && cursor.len() == 2 => {
+            debug!("resuming sync download from {:?}", cursor[1]);
+            (cursor[0], cursor[1])
+        }
+        _ if force => (OffsetDateTime::UNIX_EPOCH, OffsetDateTime::UNIX_EPOCH),
+        _ => (Settings::last_sync()?, OffsetDateTime::UNIX_EPOCH),
     };
 
-    let mut last_timestamp = OffsetDateTime::UNIX_EPOCH;
-
     let host = if force { Some(String::from("")) } else { None };
 
     while remote_count > local_count {
@@ -86,6 +130,11 @@ async fn sync_download(
 
         local_count = db.history_count(true).await?;
 
+        on_progress(SyncProgress::Download {
+            done: local_count - initial_local,
+            total: remote_count - initial_local,
+        });
+
         if history.len() < remote_status.page_size.try_into().unwrap() {
             break;
         }
@@ -104,36 +153,43 @@ async fn sync_download(
         } else {
             last_timestamp = page_last;
         }
-    }
 
-    for i in remote_status.deleted {
-        // we will update the stored history to have this data
-        // pretty much everything can be nullified
-        if let Some(h) = db.load(i.as_str()).await? {
-            db.delete(h).await?;
-        } else {
-            info!(
-                "could not delete history with id {}, not found locally",
-                i.as_str()
-            );
-        }
+        save_cursor(DOWNLOAD_CURSOR_FILENAME, &[last_sync, last_timestamp])?;
     }
 
+    clear_cursor(DOWNLOAD_CURSOR_FILENAME)?;
+
+    // history we don't have yet is still marked as deleted, so it can't be downloaded later
+    let deleted: Vec<HistoryId> = remote_status.deleted.into_iter().map(HistoryId).collect();
+    db.delete_bulk(&deleted).await?;
+
     Ok((local_count - initial_local, local_count))
 }
 
 // Check if we have things remote doesn't, and if so, upload them
 async fn sync_upload(
     key: &Key,
+    strip: &[SyncField],
     _force: bool,
     client: &api_client::Client<'_>,
     db: &impl Database,
+    on_progress: &impl Fn(SyncProgress),
 ) -> Result<()> {
     debug!("starting sync upload");
 
     let remote_status = client.status().await?;
     let remote_deleted: HashSet<String> = HashSet::from_iter(remote_status.deleted.clone());
 
+    // Apply the remote's deletions first. Anything it has deleted stays deleted here, and isn't
+    // uploaded again.
+    let deleted: Vec<HistoryId> = remote_status
+        .deleted
+        .iter()
+        .cloned()
+        .map(HistoryId)
+        .collect();
+    db.delete_bulk(&deleted).await?;
+
     let initial_remote_count = client.count().await?;
     let mut remote_count = initial_remote_count;
 
@@ -141,8 +197,11 @@ async fn sync_upload(
 
     debug!("remote has {}, we have {}", remote_count, local_count);
 
-    // first just try the most recent set
-    let mut cursor = OffsetDateTime::now_utc();
+    // first just try the most recent set, unless an earlier sync was interrupted. Anything newer
+    // than where that got to is picked up by the next sync.
+    let mut cursor = read_cursor(UPLOAD_CURSOR_FILENAME)
+        .and_then(|cursor| cursor.first().copied())
+        .unwrap_or_else(OffsetDateTime::now_utc);
 
     while local_count > remote_count {
         let last = db.before(cursor, remote_status.page_size).await?;
@@ -152,7 +211,17 @@ async fn sync_upload(
             break;
         }
 
+        let page_last = last
+            .last()
+            .expect("could not get last element of page")
+            .timestamp;
+
         for i in last {
+            if remote_deleted.contains(&i.id.0) {
+                continue;
+            }
+
+            let i = i.stripped(strip);
             let data = encrypt(&i, key)?;
             let data = serde_json::to_string(&data)?;
 
@@ -167,13 +236,25 @@ async fn sync_upload(
         }
 
         // anything left over outside of the 100 block size
-        client.post_history(&buffer).await?;
-        cursor = buffer.last().unwrap().timestamp;
+        if !buffer.is_empty() {
+            client.post_history(&buffer).await?;
+        }
+
+        cursor = page_last;
         remote_count = client.count().await?;
 
+        save_cursor(UPLOAD_CURSOR_FILENAME, &[cursor])?;
+
+        on_progress(SyncProgress::Upload {
+            done: remote_count - initial_remote_count,
+            total: local_count - initial_remote_count,
+        });
+
         debug!("upload cursor: {:?}", cursor);
     }
 
+    clear_cursor(UPLOAD_CURSOR_FILENAME)?;
+
     let deleted = db.deleted().await?;
 
     for i in deleted {
@@ -189,6 +270,17 @@ async fn sync_upload(
 }
 
 pub async fn sync(settings: &Settings, force: bool, db: &impl Database) -> Result<()> {
+    sync_with_progress(settings, force, db, |_| {}).await
+}
+
+pub async fn sync_with_progress(
+    settings: &Settings,
+    force: bool,
+    db: &impl Database,
+    on_progress: impl Fn(SyncProgress),
+) -> Result<()> {
+    let started = OffsetDateTime::now_utc();
+
     let client = api_client::Client::new(
         &settings.sync_address,
         settings.session_token()?.as_str(),
@@ -196,15 +288,16 @@ pub async fn sync(settings: &Settings, force: bool, db: &impl Database) -> Resul
         settings.network_timeout,
     )?;
 
-    Settings::save_sync_time()?;
-
     let key = load_key(settings)?; // encryption key
 
-    sync_upload(&key, force, &client, db).await?;
+    sync_upload(&key, &settings.sync.strip, force, &client, db, &on_progress).await?;
 
-    let download = sync_download(&key, force, &client, db).await?;
+    let download = sync_download(&key, force, &client, db, &on_progress).await?;
 
     debug!("sync downloaded {}", download.0);
 
+    // only once it's all done, so that an interrupted sync doesn't skip what it missed next time
+    Settings::save_sync_time_at(started)?;
+
     Ok(())
 }
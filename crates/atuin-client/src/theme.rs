@@ -55,6 +55,7 @@ use crossterm::style::{Color, ContentStyle};
 
 // For now, a theme is loaded as a mapping of meanings to colors, but it may be desirable to
 // expand that in the future to general styles, so we populate a Meaning->ContentStyle hashmap.
+#[derive(Clone)]
 pub struct Theme {
     pub name: String,
     pub parent: Option<String>,
@@ -0,0 +1,302 @@
+//! Replacing the running atuin with a newer release. Each release publishes a manifest of its
+//! archives and their checksums, signed with the key the release build of atuin was made with.
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use eyre::{bail, eyre, Context, Result};
+use fs_err as fs;
+use semver::Version;
+use sha2::{Digest, Sha256};
+
+/// Where releases are published, as `{RELEASES_URL}/v{version}/{artifact}`
+pub const RELEASES_URL: &str = "https://github.com/atuinsh/atuin/releases/download";
+
+/// The base64 ed25519 key releases are signed with. Only builds made by the release pipeline
+/// have it, and without it atuin can't update itself.
+pub const RELEASE_KEY: Option<&str> = option_env!("ATUIN_RELEASE_KEY");
+
+/// The target this platform's release is built for, as cargo-dist names its archives. Only the
+/// targets in `[workspace.metadata.dist]` are released.
+pub fn target() -> Option<&'static str> {
+    let musl = cfg!(target_env = "musl");
+
+    match (std::env::consts::ARCH, std::env::consts::OS, musl) {
+        ("x86_64", "linux", false) => Some("x86_64-unknown-linux-gnu"),
+        ("x86_64", "linux", true) => Some("x86_64-unknown-linux-musl"),
+        ("aarch64", "linux", false) => Some("aarch64-unknown-linux-gnu"),
+        ("aarch64", "linux", true) => Some("aarch64-unknown-linux-musl"),
+        ("x86_64", "macos", _) => Some("x86_64-apple-darwin"),
+        ("aarch64", "macos", _) => Some("aarch64-apple-darwin"),
+        _ => None,
+    }
+}
+
+/// The archive published for this platform, eg `atuin-x86_64-unknown-linux-gnu.tar.gz`
+pub fn artifact_name() -> Result<String> {
+    let Some(target) = target() else {
+        bail!(
+            "atuin doesn't publish releases for this platform. Update it the way it was installed"
+        );
+    };
+
+    Ok(format!("atuin-{target}.tar.gz"))
+}
+
+/// The list of every archive in a release, with its version and checksum, as
+/// `<version> <target> <sha256>` lines. It's what the release key signs.
+pub const MANIFEST_NAME: &str = "atuin-manifest.txt";
+
+/// Where to download the archive for this platform, the release's manifest and the manifest's
+/// signature from
+pub fn artifact_urls(version: &Version) -> Result<(String, String, String)> {
+    let release = format!("{RELEASES_URL}/v{version}");
+
+    Ok((
+        format!("{release}/{}", artifact_name()?),
+        format!("{release}/{MANIFEST_NAME}"),
+        format!("{release}/{MANIFEST_NAME}.sig"),
+    ))
+}
+
+pub fn release_key() -> Result<VerifyingKey> {
+    let Some(key) = RELEASE_KEY else {
+        bail!("this build of atuin can't verify releases, so can't update itself. Update it the way it was installed");
+    };
+
+    let key: [u8; 32] = BASE64_STANDARD
+        .decode(key.trim())?
+        .try_into()
+        .map_err(|_| eyre!("the release key isn't an ed25519 key"))?;
+
+    Ok(VerifyingKey::from_bytes(&key)?)
+}
+
+/// This platform's archive, as a signed manifest lists it
+#[derive(Debug)]
+pub struct Release {
+    pub version: Version,
+    sha256: String,
+}
+
+/// Check a release manifest against its signature, a base64 ed25519 signature of the manifest,
+/// and find this platform's archive in it. Old releases stay signed, so one that isn't newer
+/// than `current` is refused, rather than installed as a downgrade.
+pub fn verify_manifest(
+    manifest: &[u8],
+    signature: &str,
+    key: &VerifyingKey,
+    current: &Version,
+) -> Result<Release> {
+    let signature: [u8; 64] = BASE64_STANDARD
+        .decode(signature.trim())?
+        .try_into()
+        .map_err(|_| eyre!("the signature isn't an ed25519 signature"))?;
+
+    key.verify(manifest, &Signature::from_bytes(&signature))
+        .map_err(|_| eyre!("the release manifest isn't signed by the atuin release key"))?;
+
+    let target =
+        target().ok_or_else(|| eyre!("atuin doesn't publish releases for this platform"))?;
+
+    let (version, sha256) = std::str::from_utf8(manifest)?
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find_map(|fields| match fields[..] {
+            [version, t, sha256] if t == target => Some((version, sha256)),
+            _ => None,
+        })
+        .ok_or_else(|| eyre!("the release has no archive for {target}"))?;
+
+    let release = Release {
+        version: Version::parse(version)?,
+        sha256: sha256.to_string(),
+    };
+
+    if release.version <= *current {
+        bail!(
+            "the release is atuin {}, which isn't newer than {current}",
+            release.version
+        );
+    }
+
+    Ok(release)
+}
+
+/// Check a downloaded archive against the checksum its manifest lists
+pub fn verify_archive(archive: &[u8], release: &Release) -> Result<()> {
+    if !hex::encode(Sha256::digest(archive)).eq_ignore_ascii_case(&release.sha256) {
+        bail!("the download doesn't match the checksum in the release manifest");
+    }
+
+    Ok(())
+}
+
+/// Take the atuin binary out of a release archive. cargo-dist puts it in a directory named
+/// after the archive, alongside the readme and licenses.
+pub fn extract(archive: &[u8]) -> Result<Vec<u8>> {
+    let name = format!("atuin{}", std::env::consts::EXE_SUFFIX);
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        if entry.header().entry_type().is_file() && entry.path()?.file_name() == Some(name.as_ref())
+        {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary)?;
+
+            return Ok(binary);
+        }
+    }
+
+    bail!("the release archive doesn't have an atuin binary in it")
+}
+
+/// Replace the binary at `path` with `binary`. It's written next to it then renamed over it, so
+/// anything starting atuin meanwhile gets either the old binary or the new one.
+pub fn replace(path: &Path, binary: &[u8]) -> Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| eyre!("{path:?} isn't a file"))?
+        .to_string_lossy();
+    let staged = path.with_file_name(format!(".{name}.update"));
+
+    fs::write(&staged, binary).wrap_err("could not write the new binary")?;
+    fs::File::open(&staged)?.sync_all()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(path).map_or(0o755, |m| m.permissions().mode());
+        fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+    }
+
+    // windows won't replace a running binary, but will rename it out of the way
+    #[cfg(windows)]
+    fs::rename(path, old_path(path))?;
+
+    fs::rename(&staged, path).wrap_err("could not replace the running binary")?;
+
+    Ok(())
+}
+
+/// Where the binary being replaced goes, where it can't be replaced in place
+pub fn old_path(path: &Path) -> PathBuf {
+    let mut old = path.as_os_str().to_owned();
+    old.push(".old");
+    PathBuf::from(old)
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use ed25519_dalek::{Signer, SigningKey};
+    use sha2::{Digest, Sha256};
+
+    use semver::Version;
+
+    use super::{extract, replace, target, verify_archive, verify_manifest};
+
+    #[test]
+    fn verifies_manifest_and_archive() {
+        let signing = SigningKey::from_bytes(&[7; 32]);
+        let key = signing.verifying_key();
+        let current = Version::new(18, 3, 0);
+
+        let archive = b"new atuin";
+        let manifest = |version: &str| {
+            let lines: Vec<_> = ["x86_64-unknown-linux-musl", target().unwrap()]
+                .iter()
+                .map(|target| {
+                    format!(
+                        "{version} {target} {}",
+                        hex::encode(Sha256::digest(archive))
+                    )
+                })
+                .collect();
+            lines.join("\n")
+        };
+        let sign =
+            |manifest: &str| BASE64_STANDARD.encode(signing.sign(manifest.as_bytes()).to_bytes());
+
+        let newer = manifest("18.4.0");
+        let release = verify_manifest(newer.as_bytes(), &sign(&newer), &key, &current).unwrap();
+        assert_eq!(release.version, Version::new(18, 4, 0));
+
+        verify_archive(archive, &release).unwrap();
+        assert!(verify_archive(b"evil", &release).is_err());
+
+        // a manifest that's been changed since it was signed
+        let tampered = newer.replace("18.4.0", "18.5.0");
+        assert!(verify_manifest(tampered.as_bytes(), &sign(&newer), &key, &current).is_err());
+
+        // signed by someone else
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let forged = BASE64_STANDARD.encode(other.sign(newer.as_bytes()).to_bytes());
+        assert!(verify_manifest(newer.as_bytes(), &forged, &key, &current).is_err());
+
+        // an older release, though signed, isn't an update
+        for version in ["18.3.0", "18.2.0"] {
+            let older = manifest(version);
+            assert!(verify_manifest(older.as_bytes(), &sign(&older), &key, &current).is_err());
+        }
+
+        assert!(verify_manifest(newer.as_bytes(), "not base64", &key, &current).is_err());
+    }
+
+    #[test]
+    fn extracts_binary() {
+        let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+
+        let binary = format!(
+            "atuin-x86_64-unknown-linux-gnu/atuin{}",
+            std::env::consts::EXE_SUFFIX
+        );
+
+        for (path, contents) in [
+            ("atuin-x86_64-unknown-linux-gnu/README.md", &b"# atuin"[..]),
+            (binary.as_str(), &b"new atuin"[..]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+
+            tar.append_data(&mut header, path, contents).unwrap();
+        }
+
+        let archive = tar.into_inner().unwrap().finish().unwrap();
+
+        assert_eq!(extract(&archive).unwrap(), b"new atuin");
+
+        assert!(extract(b"not an archive").is_err());
+    }
+
+    #[test]
+    fn replaces_binary() {
+        let dir = std::env::temp_dir().join(format!(
+            "atuin-update-{}",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+        fs_err::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("atuin");
+        fs_err::write(&path, "old").unwrap();
+
+        replace(&path, b"new").unwrap();
+
+        assert_eq!(fs_err::read(&path).unwrap(), b"new");
+        assert!(!dir.join(".atuin.update").exists());
+
+        fs_err::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,7 @@
+use std::path::Path;
+
+use crate::history::{TMUX_PANE, WEZTERM_PANE, ZELLIJ_PANE};
+
 pub(crate) fn get_hostname() -> String {
     std::env::var("ATUIN_HOST_NAME").unwrap_or_else(|_| {
         whoami::fallible::hostname().unwrap_or_else(|_| "unknown-host".to_string())
@@ -12,3 +16,487 @@ pub(crate) fn get_username() -> String {
 pub(crate) fn get_host_user() -> String {
     format!("{}:{}", get_hostname(), get_username())
 }
+
+/// The environment commands are being run in, if it isn't the host itself. Containers that share
+/// a home directory with the host (devcontainers, toolbox, distrobox) would otherwise mix their
+/// history in with the host's, with no way to tell them apart.
+///
+/// Set `ATUIN_NAMESPACE` to choose one, or to an empty string to record nothing.
+pub(crate) fn get_namespace() -> String {
+    if let Ok(namespace) = std::env::var("ATUIN_NAMESPACE") {
+        return namespace;
+    }
+
+    detect_container(
+        |name| std::env::var(name).ok().filter(|v| !v.is_empty()),
+        |path| std::fs::read_to_string(path).ok(),
+    )
+    .unwrap_or_default()
+}
+
+/// The multiplexer pane commands are being run in, as the key it's recorded under in history's
+/// env and the pane's id, or None outside of one. tmux is checked first, as it's most often the
+/// one run inside the others.
+pub(crate) fn get_pane() -> Option<(&'static str, String)> {
+    pane(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+}
+
+fn pane(env: impl Fn(&str) -> Option<String>) -> Option<(&'static str, String)> {
+    tmux_pane(&env)
+        .map(|pane| (TMUX_PANE, pane))
+        .or_else(|| zellij_pane(&env).map(|pane| (ZELLIJ_PANE, pane)))
+        .or_else(|| wezterm_pane(&env).map(|pane| (WEZTERM_PANE, pane)))
+}
+
+// Pane ids are only unique within a tmux server, so this is the server's pid and the pane id,
+// like `1234:%3`
+fn tmux_pane(env: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    // $TMUX is the server's socket, pid, and session, separated by commas
+    let server = env("TMUX")?.split(',').nth(1)?.to_string();
+
+    Some(format!("{server}:{}", env("TMUX_PANE")?))
+}
+
+// zellij numbers panes per session, like `work:3`
+fn zellij_pane(env: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    Some(format!(
+        "{}:{}",
+        env("ZELLIJ_SESSION_NAME")?,
+        env("ZELLIJ_PANE_ID")?
+    ))
+}
+
+// wezterm numbers panes per mux server, which is told apart by its socket, like
+// `gui-sock-1234:5`
+fn wezterm_pane(env: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    let pane = env("WEZTERM_PANE")?;
+
+    let server = env("WEZTERM_UNIX_SOCKET")
+        .and_then(|socket| Some(Path::new(&socket).file_name()?.to_str()?.to_string()))
+        .unwrap_or_default();
+
+    Some(format!("{server}:{pane}"))
+}
+
+/// The nix shell commands are being run in, from `nix develop` or `nix-shell`, as the name of
+/// its derivation. None outside of one.
+pub(crate) fn get_nix_shell() -> Option<String> {
+    nix_shell(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+}
+
+fn nix_shell(env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // "pure" or "impure", in both
+    env("IN_NIX_SHELL")?;
+
+    // mkShell's own default, for a shell without a name
+    Some(env("name").unwrap_or_else(|| "nix-shell".to_string()))
+}
+
+/// The window of a tmux pane from [`get_pane`], in the same form. This asks tmux, as the
+/// window isn't in the environment, and panes can be moved between windows.
+pub(crate) fn get_tmux_window(pane: &str) -> Option<String> {
+    let (server, pane) = pane.split_once(':')?;
+
+    let output = std::process::Command::new("tmux")
+        .args(["display-message", "-p", "-t", pane, "#{window_id}"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    let window = String::from_utf8(output.stdout).ok()?;
+    let window = window.trim();
+
+    (!window.is_empty()).then(|| format!("{server}:{window}"))
+}
+
+fn detect_container(
+    env: impl Fn(&str) -> Option<String>,
+    read: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    // GitHub Codespaces
+    if let Some(name) = env("CODESPACE_NAME") {
+        return Some(format!("codespace:{name}"));
+    }
+
+    // VS Code and the devcontainer CLI
+    if env("REMOTE_CONTAINERS").is_some() || env("DEVCONTAINER").is_some() {
+        let name = env("DEVCONTAINER_NAME")
+            .or_else(|| env("HOSTNAME"))
+            .unwrap_or_else(get_hostname);
+
+        return Some(format!("devcontainer:{name}"));
+    }
+
+    // podman, and the toolbox and distrobox containers built on it, describe themselves here
+    if let Some(containerenv) = read("/run/.containerenv") {
+        let field = |key: &str| containerenv_field(&containerenv, key);
+
+        // toolbox and distrobox set the container's name, which is what you'd know it by. Without
+        // one, every container of the same image shares a namespace.
+        let name = env("CONTAINER_ID")
+            .or_else(|| field("name"))
+            .or_else(|| field("image"))
+            .or_else(|| field("id").and_then(|id| container_id(&id)))
+            .or_else(|| running_container_id(&read))
+            .unwrap_or_else(get_hostname);
+
+        return Some(format!("podman:{name}"));
+    }
+
+    // docker doesn't say which image it's running, so set $ATUIN_NAMESPACE in the image to share
+    // a namespace between its containers
+    if read("/.dockerenv").is_some() {
+        let id = running_container_id(&read).unwrap_or_else(get_hostname);
+
+        return Some(format!("docker:{id}"));
+    }
+
+    // kubernetes, containerd and others leave nothing behind but the cgroup they put us in
+    if let Some(cgroup) = read("/proc/self/cgroup") {
+        if let Some(id) = container_id(&cgroup) {
+            let runtime = if cgroup.contains("kubepods") {
+                "kubernetes"
+            } else {
+                "container"
+            };
+
+            return Some(format!("{runtime}:{id}"));
+        }
+    }
+
+    // systemd's convention for container managers
+    env("container").map(|manager| {
+        let id = running_container_id(&read).unwrap_or_else(get_hostname);
+
+        format!("{manager}:{id}")
+    })
+}
+
+/// The container commands are being run in, as its id, shortened like docker does, and its
+/// image, when the container says what that is. Both are None on the host. Unlike the
+/// namespace, the image is the same for every container made from it.
+pub(crate) fn get_container() -> (Option<String>, Option<String>) {
+    container(
+        |name| std::env::var(name).ok().filter(|v| !v.is_empty()),
+        |path| std::fs::read_to_string(path).ok(),
+    )
+}
+
+fn container(
+    env: impl Fn(&str) -> Option<String>,
+    read: impl Fn(&str) -> Option<String>,
+) -> (Option<String>, Option<String>) {
+    let containerenv = read("/run/.containerenv").unwrap_or_default();
+
+    let id = containerenv_field(&containerenv, "id")
+        .and_then(|id| container_id(&id))
+        .or_else(|| running_container_id(&read));
+
+    // only podman says, so other runtimes' images can set it themselves
+    let image = containerenv_field(&containerenv, "image").or_else(|| env("ATUIN_CONTAINER_IMAGE"));
+
+    (id, image)
+}
+
+// podman's /run/.containerenv is lines of key="value"
+fn containerenv_field(containerenv: &str, key: &str) -> Option<String> {
+    containerenv.lines().find_map(|line| {
+        line.strip_prefix(key)?
+            .strip_prefix('=')
+            .map(|v| v.trim_matches('"').to_string())
+            .filter(|v| !v.is_empty())
+    })
+}
+
+// The id of the container we're in, from its cgroup, or under cgroup v2, where that's just "/"
+// from inside, the directory the runtime mounts the hostname from
+fn running_container_id(read: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    container_id(&read("/proc/self/cgroup").unwrap_or_default()).or_else(|| {
+        read("/proc/self/mountinfo")?.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            (fields.get(4) == Some(&"/etc/hostname")).then(|| container_id(fields[3]))?
+        })
+    })
+}
+
+// Runtimes name cgroups and directories after the full id of the container, as 64 hex digits,
+// such as /docker/<id>, /kubepods/burstable/pod<uid>/<id> or docker-<id>.scope. It's shortened
+// like docker does.
+fn container_id(text: &str) -> Option<String> {
+    text.split(['/', '-', ':', '\n'])
+        .map(|part| part.strip_suffix(".scope").unwrap_or(part))
+        .find(|part| {
+            part.len() == 64
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        })
+        .map(|id| id[..12].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{container, detect_container, nix_shell, pane};
+
+    fn detect(env: &[(&str, &str)], files: &[(&str, &str)]) -> Option<String> {
+        let env: HashMap<_, _> = env.iter().copied().collect();
+        let files: HashMap<_, _> = files.iter().copied().collect();
+
+        detect_container(
+            |name| env.get(name).map(|v| (*v).to_string()),
+            |path| files.get(path).map(|v| (*v).to_string()),
+        )
+    }
+
+    #[test]
+    fn detects_containers() {
+        assert_eq!(detect(&[], &[]), None);
+
+        assert_eq!(
+            detect(&[("CODESPACE_NAME", "fluffy-waddle")], &[]),
+            Some("codespace:fluffy-waddle".to_string())
+        );
+        assert_eq!(
+            detect(
+                &[
+                    ("REMOTE_CONTAINERS", "true"),
+                    ("DEVCONTAINER_NAME", "atuin")
+                ],
+                &[("/.dockerenv", "")]
+            ),
+            Some("devcontainer:atuin".to_string())
+        );
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/run/.containerenv",
+                    "engine=\"podman-4.9.4\"\nname=\"fedora-toolbox-40\"\nimage=\"registry.fedoraproject.org/fedora-toolbox:40\"\n"
+                )]
+            ),
+            Some("podman:fedora-toolbox-40".to_string())
+        );
+        assert_eq!(
+            detect(&[("CONTAINER_ID", "arch")], &[("/run/.containerenv", "")]),
+            Some("podman:arch".to_string())
+        );
+
+        // unnamed podman containers go by their image, or failing that their id
+        let id = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/run/.containerenv",
+                    "engine=\"podman-4.9.4\"\nimage=\"docker.io/library/debian:12\"\n"
+                )]
+            ),
+            Some("podman:docker.io/library/debian:12".to_string())
+        );
+        assert_eq!(
+            detect(&[], &[("/run/.containerenv", &format!("id=\"{id}\"\n"))]),
+            Some("podman:0123456789ab".to_string())
+        );
+
+        // docker, by the id in its cgroup under v1, or the hostname it mounts under v2
+        assert_eq!(
+            detect(
+                &[],
+                &[
+                    ("/.dockerenv", ""),
+                    (
+                        "/proc/self/cgroup",
+                        &format!("12:pids:/docker/{id}\n0::/\n")
+                    )
+                ]
+            ),
+            Some("docker:0123456789ab".to_string())
+        );
+        let mountinfo = format!(
+            "570 480 0:52 / / rw,relatime - overlay overlay rw,upperdir=/var/lib/docker/overlay2/{}/diff\n\
+             585 570 259:2 /var/lib/docker/containers/{id}/hostname /etc/hostname rw,relatime - ext4 /dev/nvme0n1p2 rw\n",
+            "f".repeat(64)
+        );
+        assert_eq!(
+            detect(
+                &[],
+                &[
+                    ("/.dockerenv", ""),
+                    ("/proc/self/cgroup", "0::/\n"),
+                    ("/proc/self/mountinfo", &mountinfo)
+                ]
+            ),
+            Some("docker:0123456789ab".to_string())
+        );
+
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/proc/self/cgroup",
+                    &format!("0::/kubepods/burstable/pod7f3e/{id}\n")
+                )]
+            ),
+            Some("kubernetes:0123456789ab".to_string())
+        );
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/proc/self/cgroup",
+                    &format!("0::/system.slice/docker-{id}.scope\n")
+                )]
+            ),
+            Some("container:0123456789ab".to_string())
+        );
+        assert_eq!(
+            detect(
+                &[],
+                &[("/proc/self/cgroup", "0::/user.slice/session-2.scope\n")]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn containers() {
+        let detect = |env: &[(&str, &str)], files: &[(&str, &str)]| {
+            let env: HashMap<_, _> = env.iter().copied().collect();
+            let files: HashMap<_, _> = files.iter().copied().collect();
+
+            container(
+                |name| env.get(name).map(|v| (*v).to_string()),
+                |path| files.get(path).map(|v| (*v).to_string()),
+            )
+        };
+        let id = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+        assert_eq!(detect(&[], &[]), (None, None));
+        assert_eq!(
+            detect(&[], &[("/proc/self/cgroup", "0::/\n")]),
+            (None, None)
+        );
+
+        // cgroup v1
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/proc/self/cgroup",
+                    &format!("12:pids:/docker/{id}\n0::/\n")
+                )]
+            ),
+            (Some("0123456789ab".to_string()), None)
+        );
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/proc/self/cgroup",
+                    &format!("0::/system.slice/docker-{id}.scope\n")
+                )]
+            ),
+            (Some("0123456789ab".to_string()), None)
+        );
+
+        // cgroup v2, where the image's layers are mounted too
+        let mountinfo = format!(
+            "570 480 0:52 / / rw,relatime - overlay overlay rw,upperdir=/var/lib/docker/overlay2/{}/diff\n\
+             585 570 259:2 /var/lib/docker/containers/{id}/hostname /etc/hostname rw,relatime - ext4 /dev/nvme0n1p2 rw\n",
+            "f".repeat(64)
+        );
+        assert_eq!(
+            detect(
+                &[("ATUIN_CONTAINER_IMAGE", "debug:latest")],
+                &[
+                    ("/proc/self/cgroup", "0::/\n"),
+                    ("/proc/self/mountinfo", &mountinfo)
+                ]
+            ),
+            (
+                Some("0123456789ab".to_string()),
+                Some("debug:latest".to_string())
+            )
+        );
+
+        // podman says
+        assert_eq!(
+            detect(
+                &[],
+                &[(
+                    "/run/.containerenv",
+                    &format!("engine=\"podman-4.9.4\"\nid=\"{id}\"\nimage=\"docker.io/library/debian:12\"\n")
+                )]
+            ),
+            (
+                Some("0123456789ab".to_string()),
+                Some("docker.io/library/debian:12".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn panes() {
+        let pane = |env: &[(&str, &str)]| {
+            let env: HashMap<_, _> = env.iter().copied().collect();
+            pane(|name| env.get(name).map(|v| (*v).to_string()))
+        };
+
+        assert_eq!(pane(&[]), None);
+        assert_eq!(pane(&[("TMUX_PANE", "%3")]), None);
+        assert_eq!(
+            pane(&[
+                ("TMUX", "/tmp/tmux-1000/default,4821,0"),
+                ("TMUX_PANE", "%3")
+            ]),
+            Some(("tmux_pane", "4821:%3".to_string()))
+        );
+        assert_eq!(
+            pane(&[("ZELLIJ_SESSION_NAME", "work"), ("ZELLIJ_PANE_ID", "2")]),
+            Some(("zellij_pane", "work:2".to_string()))
+        );
+        assert_eq!(
+            pane(&[
+                ("WEZTERM_PANE", "5"),
+                (
+                    "WEZTERM_UNIX_SOCKET",
+                    "/run/user/1000/wezterm/gui-sock-1234"
+                )
+            ]),
+            Some(("wezterm_pane", "gui-sock-1234:5".to_string()))
+        );
+
+        // tmux run in wezterm
+        assert_eq!(
+            pane(&[
+                ("TMUX", "/tmp/tmux-1000/default,4821,0"),
+                ("TMUX_PANE", "%3"),
+                ("WEZTERM_PANE", "5")
+            ]),
+            Some(("tmux_pane", "4821:%3".to_string()))
+        );
+    }
+
+    #[test]
+    fn nix_shells() {
+        let shell = |env: &[(&str, &str)]| {
+            let env: HashMap<_, _> = env.iter().copied().collect();
+            nix_shell(|name| env.get(name).map(|v| (*v).to_string()))
+        };
+
+        assert_eq!(shell(&[]), None);
+        // set by more than nix
+        assert_eq!(shell(&[("name", "atuin-dev")]), None);
+        assert_eq!(
+            shell(&[("IN_NIX_SHELL", "impure"), ("name", "atuin-dev")]),
+            Some("atuin-dev".to_string())
+        );
+        assert_eq!(
+            shell(&[("IN_NIX_SHELL", "pure")]),
+            Some("nix-shell".to_string())
+        );
+    }
+}
@@ -4,9 +4,15 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use time::OffsetDateTime;
 
+use crate::record::HostId;
+
 // the usage of X- has been deprecated for quite along time, it turns out
 pub static ATUIN_HEADER_VERSION: &str = "Atuin-Version";
 pub static ATUIN_CARGO_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub static ATUIN_HEADER_HOST: &str = "Atuin-Host-Id";
+
+/// Set on a refused login when the account needs a two-factor code, and none was given
+pub static ATUIN_HEADER_TOTP: &str = "Atuin-Totp";
 
 lazy_static! {
     pub static ref ATUIN_VERSION: Version =
@@ -58,10 +64,14 @@ pub struct ChangePasswordRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChangePasswordResponse {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
+
+    /// A code from the account's authenticator app, if it has two-factor login enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -135,4 +145,97 @@ pub struct MessageResponse {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MeResponse {
     pub username: String,
+
+    /// Whether logging in needs a two-factor code
+    #[serde(default)]
+    pub totp: bool,
+}
+
+/// Every session was revoked, and this one replaces them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokeSessionsResponse {
+    pub session: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollResponse {
+    /// The shared secret, base32 encoded, for typing into an authenticator app
+    pub secret: String,
+
+    /// The same secret as an otpauth:// URL, which most authenticator apps can read as a QR code
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokeHostRequest {
+    pub host: HostId,
+
+    /// Ask the revoked host to delete its encryption key, as well as stop syncing
+    #[serde(default)]
+    pub wipe_key: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokeHostResponse {
+    /// Replaces the account's session, which the revoked host had too
+    pub session: String,
+}
+
+/// What an upload would have gone over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    /// How many history entries and records an account may store
+    Records,
+    /// How many bytes an account may store
+    Bytes,
+    /// How big one record may be
+    RecordSize,
+}
+
+/// Returned with a 413 when an upload would go over a limit. Has a reason, so older clients
+/// can read it as an [`ErrorResponse`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuotaExceededResponse {
+    pub reason: String,
+    pub kind: QuotaKind,
+    pub limit: u64,
+    /// How much is stored, with the upload. For record_size, the size of the record.
+    pub used: u64,
+}
+
+/// A user, as the admin API shows them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUserResponse {
+    pub username: String,
+    pub email: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub verified: bool,
+    /// Entries in the old history table
+    pub history: i64,
+    /// Records in the record store
+    pub records: i64,
+    /// Bytes of encrypted data stored, across both
+    pub storage: i64,
+    /// When they last uploaded anything
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_sync: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUsersResponse {
+    pub users: Vec<AdminUserResponse>,
+}
+
+/// Returned with a 410 to a host that has been revoked
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HostRevokedResponse {
+    pub reason: String,
+    pub wipe_key: bool,
 }
@@ -0,0 +1,48 @@
+//! Messages for whoever is running atuin, as opposed to the output they asked for.
+//!
+//! Progress and status go through [`status!`](crate::status) and
+//! [`detail!`](crate::detail) rather than `println!`, so that `-q` silences them for scripts and
+//! `-v` adds more for people, the same way for every command. Anything a command was run to
+//! produce - a search result, a key, the stats - should still be printed directly.
+
+use std::sync::atomic::{AtomicI8, Ordering};
+
+static VERBOSITY: AtomicI8 = AtomicI8::new(0);
+
+/// Set how much to print. Below zero is quiet, zero is the default, and each step above it is
+/// another `-v`.
+pub fn set_verbosity(level: i8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> i8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+pub fn is_quiet() -> bool {
+    verbosity() < 0
+}
+
+pub fn is_verbose() -> bool {
+    verbosity() > 0
+}
+
+/// Print a status message, unless running quietly
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print a message only when asked to be verbose
+#[macro_export]
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if $crate::output::is_verbose() {
+            println!($($arg)*);
+        }
+    };
+}
@@ -199,6 +199,98 @@ impl RecordStatus {
     }
 }
 
+/// A summary of one host's records for one tag
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestEntry {
+    pub host: HostId,
+    pub tag: String,
+    pub count: u64,
+    /// The idx and ID of the last record
+    pub idx: RecordIdx,
+    pub last: RecordId,
+}
+
+impl DigestEntry {
+    fn hash(&self) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.host.0.as_bytes());
+        hasher.update(self.tag.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.count.to_be_bytes());
+        hasher.update(self.idx.to_be_bytes());
+        hasher.update(self.last.0.as_bytes());
+
+        hasher.finalize().into()
+    }
+}
+
+/// A digest of a record store, for checking that two of them agree. If the roots of two digests
+/// match, so does every entry. If not, the entries say where they differ.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordDigest {
+    /// Sorted by host, then tag
+    pub entries: Vec<DigestEntry>,
+}
+
+impl RecordDigest {
+    pub fn new(mut entries: Vec<DigestEntry>) -> Self {
+        entries.sort_by(|a, b| (a.host, &a.tag).cmp(&(b.host, &b.tag)));
+        Self { entries }
+    }
+
+    /// A hash of every entry
+    pub fn root(&self) -> String {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+
+        for entry in &self.entries {
+            hasher.update(entry.hash());
+        }
+
+        URL_SAFE_NO_PAD.encode(hasher.finalize())
+    }
+
+    /// Only the entries for a host and tag that `other` also has
+    pub fn shared_with(&self, other: &Self) -> Self {
+        let entries = self
+            .entries
+            .iter()
+            .filter(|e| other.get(e.host, &e.tag).is_some())
+            .cloned()
+            .collect();
+
+        Self { entries }
+    }
+
+    pub fn get(&self, host: HostId, tag: &str) -> Option<&DigestEntry> {
+        self.entries
+            .binary_search_by(|e| (e.host, e.tag.as_str()).cmp(&(host, tag)))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// Where this digest and `other` disagree, for hosts and tags they both have. Those that only
+    /// one has are left to sync.
+    pub fn diverged<'a>(&'a self, other: &'a Self) -> Vec<(&'a DigestEntry, &'a DigestEntry)> {
+        let ours = self.shared_with(other);
+        let theirs = other.shared_with(self);
+
+        if ours.root() == theirs.root() {
+            return Vec::new();
+        }
+
+        self.entries
+            .iter()
+            .filter_map(|e| Some((e, other.get(e.host, &e.tag)?)))
+            .filter(|(a, b)| a != b)
+            .collect()
+    }
+}
+
 pub trait Encryption {
     fn re_encrypt(
         data: EncryptedData,
@@ -423,4 +515,43 @@ mod tests {
         assert_eq!(index1.diff(&index1).len(), 0);
         assert_eq!(index2.diff(&index2).len(), 0);
     }
+
+    #[test]
+    fn digest_divergence() {
+        use super::{DigestEntry, RecordDigest, RecordId};
+
+        let entry = |host, tag: &str, count| DigestEntry {
+            host,
+            tag: tag.to_string(),
+            count,
+            idx: count - 1,
+            last: RecordId(uuid::Uuid::nil()),
+        };
+
+        let a = HostId(crate::utils::uuid_v7());
+        let b = HostId(crate::utils::uuid_v7());
+
+        let local = RecordDigest::new(vec![entry(b, "history", 10), entry(a, "history", 5)]);
+        let remote = RecordDigest::new(vec![entry(a, "history", 5), entry(b, "history", 10)]);
+
+        assert_eq!(local.root(), remote.root());
+        assert!(local.diverged(&remote).is_empty());
+
+        // a store only one side has is left for sync to sort out
+        let remote = RecordDigest::new(vec![
+            entry(a, "history", 5),
+            entry(b, "history", 10),
+            entry(b, "kv", 3),
+        ]);
+        assert!(local.diverged(&remote).is_empty());
+
+        // but a gap in one that both have is caught
+        let mut gap = entry(b, "history", 10);
+        gap.count = 9;
+        let remote = RecordDigest::new(vec![entry(a, "history", 5), gap.clone()]);
+
+        let diverged = local.diverged(&remote);
+        assert_eq!(diverged.len(), 1);
+        assert_eq!(diverged[0].1, &gap);
+    }
 }
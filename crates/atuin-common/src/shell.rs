@@ -58,6 +58,7 @@ impl Shell {
 
         let shell = parent.name().trim().to_lowercase();
         let shell = shell.strip_prefix('-').unwrap_or(&shell);
+        let shell = shell.strip_suffix(".exe").unwrap_or(shell);
 
         Shell::from_string(shell.to_string())
     }
@@ -120,7 +121,7 @@ impl Shell {
             "xonsh" => Shell::Xonsh,
             "nu" => Shell::Nu,
             "sh" => Shell::Sh,
-            "powershell" => Shell::Powershell,
+            "powershell" | "pwsh" => Shell::Powershell,
 
             _ => Shell::Unknown,
         }
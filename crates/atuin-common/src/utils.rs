@@ -61,6 +61,30 @@ pub fn in_git_repo(path: &str) -> Option<PathBuf> {
     None
 }
 
+/// The git branch checked out in the repo containing `path`, or the short commit hash if HEAD is
+/// detached. Read straight from HEAD, so this is cheap enough to do for every command.
+pub fn git_branch(path: &str) -> Option<String> {
+    let root = in_git_repo(path)?;
+    let mut gitdir = root.join(".git");
+
+    // worktrees and submodules have a file pointing at the real git dir
+    if gitdir.is_file() {
+        let pointer = std::fs::read_to_string(&gitdir).ok()?;
+        gitdir = root.join(pointer.strip_prefix("gitdir:")?.trim());
+    }
+
+    let head = std::fs::read_to_string(gitdir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    match head.strip_prefix("ref:") {
+        Some(r) => {
+            let r = r.trim();
+            Some(r.strip_prefix("refs/heads/").unwrap_or(r).to_string())
+        }
+        None => head.get(..7).map(ToString::to_string),
+    }
+}
+
 // TODO: more reliable, more tested
 // I don't want to use ProjectDirs, it puts config in awkward places on
 // mac. Data too. Seems to be more intended for GUI apps.
@@ -77,29 +101,96 @@ pub fn home_dir() -> PathBuf {
     PathBuf::from(home)
 }
 
-pub fn config_dir() -> PathBuf {
+/// The config dir shared by every profile, where the default profile is kept
+pub fn root_config_dir() -> PathBuf {
     let config_dir =
         std::env::var("XDG_CONFIG_HOME").map_or_else(|_| home_dir().join(".config"), PathBuf::from);
     config_dir.join("atuin")
 }
 
-pub fn data_dir() -> PathBuf {
+pub fn root_data_dir() -> PathBuf {
     let data_dir = std::env::var("XDG_DATA_HOME")
         .map_or_else(|_| home_dir().join(".local").join("share"), PathBuf::from);
 
     data_dir.join("atuin")
 }
 
+/// The config dir of the profile in use
+pub fn config_dir() -> PathBuf {
+    with_profile(root_config_dir())
+}
+
+/// The data dir of the profile in use, holding its databases, key and session
+pub fn data_dir() -> PathBuf {
+    with_profile(root_data_dir())
+}
+
+/// The name of the profile that isn't one, using the root config and data dirs
+pub const DEFAULT_PROFILE: &str = "default";
+
+const PROFILE_FILENAME: &str = "profile";
+
+pub fn is_valid_profile(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The profile in use: `$ATUIN_PROFILE`, or else the one chosen with `atuin profile use`. None
+/// for the default profile.
+pub fn profile() -> Option<String> {
+    let profile = std::env::var("ATUIN_PROFILE")
+        .ok()
+        .or_else(default_profile)?;
+
+    (profile != DEFAULT_PROFILE && is_valid_profile(&profile)).then_some(profile)
+}
+
+/// The profile used when `$ATUIN_PROFILE` isn't set
+pub fn default_profile() -> Option<String> {
+    // finding the data dir from XDG_DATA_HOME alone shouldn't need a home to look in for this
+    if ["XDG_CONFIG_HOME", "HOME", "USERPROFILE"]
+        .iter()
+        .all(|var| std::env::var_os(var).is_none())
+    {
+        return None;
+    }
+
+    let profile = std::fs::read_to_string(root_config_dir().join(PROFILE_FILENAME)).ok()?;
+    let profile = profile.trim();
+
+    (!profile.is_empty()).then(|| profile.to_string())
+}
+
+pub fn set_default_profile(name: &str) -> std::io::Result<()> {
+    let path = root_config_dir().join(PROFILE_FILENAME);
+
+    if name == DEFAULT_PROFILE {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    } else {
+        std::fs::create_dir_all(root_config_dir())?;
+        std::fs::write(path, format!("{name}\n"))
+    }
+}
+
+fn with_profile(dir: PathBuf) -> PathBuf {
+    match profile() {
+        Some(profile) => dir.join("profiles").join(profile),
+        None => dir,
+    }
+}
+
 pub fn runtime_dir() -> PathBuf {
     std::env::var("XDG_RUNTIME_DIR").map_or_else(|_| data_dir(), PathBuf::from)
 }
 
 pub fn dotfiles_cache_dir() -> PathBuf {
     // In most cases, this will be  ~/.local/share/atuin/dotfiles/cache
-    let data_dir = std::env::var("XDG_DATA_HOME")
-        .map_or_else(|_| home_dir().join(".local").join("share"), PathBuf::from);
-
-    data_dir.join("atuin").join("dotfiles").join("cache")
+    data_dir().join("dotfiles").join("cache")
 }
 
 pub fn get_current_dir() -> String {
@@ -133,6 +224,16 @@ pub fn is_xonsh() -> bool {
     env::var("ATUIN_SHELL_XONSH").is_ok()
 }
 
+pub fn is_powershell() -> bool {
+    // only set on powershell
+    env::var("ATUIN_SHELL_POWERSHELL").is_ok()
+}
+
+pub fn is_elvish() -> bool {
+    // only set on elvish
+    env::var("ATUIN_SHELL_ELVISH").is_ok()
+}
+
 /// Extension trait for anything that can behave like a string to make it easy to escape control
 /// characters.
 ///
@@ -207,6 +308,7 @@ mod tests {
         test_config_dir();
         test_data_dir_xdg();
         test_data_dir();
+        test_profile_dirs();
     }
 
     fn test_config_dir_xdg() {
@@ -242,6 +344,70 @@ mod tests {
         env::remove_var("HOME");
     }
 
+    fn test_profile_dirs() {
+        env::set_var("HOME", "/home/user");
+        env::set_var("ATUIN_PROFILE", "work");
+
+        assert_eq!(
+            config_dir(),
+            PathBuf::from("/home/user/.config/atuin/profiles/work")
+        );
+        assert_eq!(
+            data_dir(),
+            PathBuf::from("/home/user/.local/share/atuin/profiles/work")
+        );
+        assert_eq!(root_config_dir(), PathBuf::from("/home/user/.config/atuin"));
+
+        env::set_var("ATUIN_PROFILE", DEFAULT_PROFILE);
+        assert_eq!(config_dir(), PathBuf::from("/home/user/.config/atuin"));
+
+        env::set_var("ATUIN_PROFILE", "../escape");
+        assert_eq!(config_dir(), PathBuf::from("/home/user/.config/atuin"));
+
+        env::remove_var("ATUIN_PROFILE");
+        env::remove_var("HOME");
+    }
+
+    #[test]
+    fn test_git_branch() {
+        let repo = env::temp_dir().join(format!("atuin-git-branch-{}", uuid_v7().as_simple()));
+        let git = repo.join(".git");
+        let cwd = repo.join("src");
+        std::fs::create_dir_all(&git).unwrap();
+        std::fs::create_dir_all(&cwd).unwrap();
+        let cwd = cwd.to_str().unwrap();
+
+        std::fs::write(git.join("HEAD"), "ref: refs/heads/feature/branch\n").unwrap();
+        assert_eq!(git_branch(cwd).as_deref(), Some("feature/branch"));
+
+        std::fs::write(
+            git.join("HEAD"),
+            "3f2a9c1d6b0e4e5f8a7b6c5d4e3f2a1b0c9d8e7f\n",
+        )
+        .unwrap();
+        assert_eq!(git_branch(cwd).as_deref(), Some("3f2a9c1"));
+
+        // a worktree
+        let worktree =
+            env::temp_dir().join(format!("atuin-git-worktree-{}", uuid_v7().as_simple()));
+        let worktree_git = git.join("worktrees").join("fix");
+        std::fs::create_dir_all(&worktree).unwrap();
+        std::fs::create_dir_all(&worktree_git).unwrap();
+        std::fs::write(
+            worktree.join(".git"),
+            format!("gitdir: {}\n", worktree_git.display()),
+        )
+        .unwrap();
+        std::fs::write(worktree_git.join("HEAD"), "ref: refs/heads/fix\n").unwrap();
+        assert_eq!(
+            git_branch(worktree.to_str().unwrap()).as_deref(),
+            Some("fix")
+        );
+
+        std::fs::remove_dir_all(repo).unwrap();
+        std::fs::remove_dir_all(worktree).unwrap();
+    }
+
     #[test]
     fn days_from_month() {
         assert_eq!(time::util::days_in_year_month(2023, Month::January), 31);
@@ -3,7 +3,7 @@ use std::{env, fs, path::PathBuf};
 use protox::prost::Message;
 
 fn main() -> std::io::Result<()> {
-    let file_descriptors = protox::compile(["history.proto"], ["./proto/"]).unwrap();
+    let file_descriptors = protox::compile(["history.proto", "query.proto"], ["./proto/"]).unwrap();
 
     let file_descriptor_path = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"))
         .join("file_descriptor_set.bin");
@@ -13,5 +13,5 @@ fn main() -> std::io::Result<()> {
         .build_server(true)
         .file_descriptor_set_path(&file_descriptor_path)
         .skip_protoc_run()
-        .compile(&["history.proto"], &["."])
+        .compile(&["history.proto", "query.proto"], &["."])
 }
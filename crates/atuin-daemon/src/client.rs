@@ -73,6 +73,9 @@ impl HistoryClient {
             command: h.command,
             cwd: h.cwd,
             hostname: h.hostname,
+            namespace: h.namespace,
+            env: h.env.into_iter().collect(),
+            branch: h.branch,
             session: h.session,
             timestamp: h.timestamp.unix_timestamp_nanos() as u64,
         };
@@ -87,8 +90,16 @@ impl HistoryClient {
         id: String,
         duration: u64,
         exit: i64,
+        stdout_bytes: Option<u64>,
+        stderr_bytes: Option<u64>,
     ) -> Result<(String, u64)> {
-        let req = EndHistoryRequest { id, duration, exit };
+        let req = EndHistoryRequest {
+            id,
+            duration,
+            exit,
+            stdout_bytes,
+            stderr_bytes,
+        };
 
         let resp = self.client.end_history(req).await?;
         let resp = resp.into_inner();
@@ -1,3 +1,4 @@
 pub mod client;
 pub mod history;
+pub mod query;
 pub mod server;
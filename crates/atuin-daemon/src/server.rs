@@ -1,43 +1,130 @@
 use eyre::WrapErr;
 
 use atuin_client::encryption;
-use atuin_client::history::store::HistoryStore;
+use atuin_client::history::{retention, store::HistoryStore};
 use atuin_client::record::sqlite_store::SqliteStore;
 use atuin_client::settings::Settings;
+use atuin_common::record::{RecordId, RecordIdx};
 use std::path::PathBuf;
 use std::sync::Arc;
 use time::OffsetDateTime;
+use tokio::sync::watch;
 use tracing::{instrument, Level};
 
-use atuin_client::database::{Database, Sqlite as HistoryDatabase};
+use atuin_client::database::Database;
 use atuin_client::history::{History, HistoryId};
 use dashmap::DashMap;
 use eyre::Result;
 use tonic::{transport::Server, Request, Response, Status};
 
 use crate::history::history_server::{History as HistorySvc, HistoryServer};
+use crate::query::query_server::QueryServer;
 
 use crate::history::{EndHistoryReply, EndHistoryRequest, StartHistoryReply, StartHistoryRequest};
 
+mod query;
+#[cfg(unix)]
+mod shell;
 mod sync;
 
-#[derive(Debug)]
+use query::QueryService;
+
+// Whichever backend the history is kept in, see atuin_client::database::open
+type HistoryDatabase = Arc<dyn Database>;
+
+#[derive(Debug, Clone)]
 pub struct HistoryService {
     // A store for WIP history
     // This is history that has not yet been completed, aka a command that's current running.
     running: Arc<DashMap<HistoryId, History>>,
     store: HistoryStore,
     history_db: HistoryDatabase,
+    settings: watch::Receiver<Settings>,
 }
 
 impl HistoryService {
-    pub fn new(store: HistoryStore, history_db: HistoryDatabase) -> Self {
+    pub fn new(
+        store: HistoryStore,
+        history_db: HistoryDatabase,
+        settings: watch::Receiver<Settings>,
+    ) -> Self {
         Self {
             running: Arc::new(DashMap::new()),
             store,
             history_db,
+            settings,
         }
     }
+
+    /// Keep history that's started running, until it's ended
+    fn start(&self, h: History) -> HistoryId {
+        // The old behaviour had us inserting half-finished history records into the database
+        // The new behaviour no longer allows that.
+        // History that's running is stored in-memory by the daemon, and only committed when
+        // complete.
+        // If anyone relied on the old behaviour, we could perhaps insert to the history db here
+        // too. I'd rather keep it pure, unless that ends up being the case.
+        let id = h.id.clone();
+        tracing::info!(id = id.to_string(), "start history");
+        self.running.insert(id.clone(), h);
+
+        id
+    }
+
+    /// Finish running history, and save it. A duration of 0 is counted from when it started.
+    async fn end(
+        &self,
+        id: HistoryId,
+        exit: i64,
+        duration: u64,
+        stdout_bytes: Option<u64>,
+        stderr_bytes: Option<u64>,
+    ) -> Result<(RecordId, RecordIdx), Status> {
+        let Some((_, mut history)) = self.running.remove(&id) else {
+            return Err(Status::not_found(format!(
+                "could not find history with id: {id}"
+            )));
+        };
+
+        history.exit = exit;
+        history.duration = match duration {
+            0 => i64::try_from((OffsetDateTime::now_utc() - history.timestamp).whole_nanoseconds())
+                .expect("failed to convert calculated duration to i64"),
+            value => i64::try_from(value).expect("failed to get i64 duration"),
+        };
+        history.stdout_bytes = stdout_bytes.and_then(|b| i64::try_from(b).ok());
+        history.stderr_bytes = stderr_bytes.and_then(|b| i64::try_from(b).ok());
+
+        // Perhaps allow the incremental build to handle this entirely.
+        self.history_db
+            .save(&history)
+            .await
+            .map_err(|e| Status::internal(format!("failed to write to db: {e:?}")))?;
+
+        tracing::info!(
+            id = id.0.to_string(),
+            duration = history.duration,
+            "end history"
+        );
+
+        let (id, idx) = self
+            .store
+            .push(history)
+            .await
+            .map_err(|e| Status::internal(format!("failed to push record to store: {e:?}")))?;
+
+        let settings = self.settings.borrow().clone();
+
+        if settings.history.prune_on_save {
+            // the command is saved either way, so don't fail the request over this
+            match retention::prune(&settings, &self.history_db, &self.store).await {
+                Ok(pruned) => tracing::debug!(pruned, "pruned expired history"),
+                Err(e) => tracing::error!("failed to prune history: {e:?}"),
+            }
+        }
+
+        Ok((id, idx))
+    }
 }
 
 #[tonic::async_trait()]
@@ -47,7 +134,6 @@ impl HistorySvc for HistoryService {
         &self,
         request: Request<StartHistoryRequest>,
     ) -> Result<Response<StartHistoryReply>, Status> {
-        let running = self.running.clone();
         let req = request.into_inner();
 
         let timestamp =
@@ -63,18 +149,13 @@ impl HistorySvc for HistoryService {
             .cwd(req.cwd)
             .session(req.session)
             .hostname(req.hostname)
+            .namespace(req.namespace)
+            .env(req.env.into_iter().collect())
+            .branch(req.branch)
             .build()
             .into();
 
-        // The old behaviour had us inserting half-finished history records into the database
-        // The new behaviour no longer allows that.
-        // History that's running is stored in-memory by the daemon, and only committed when
-        // complete.
-        // If anyone relied on the old behaviour, we could perhaps insert to the history db here
-        // too. I'd rather keep it pure, unless that ends up being the case.
-        let id = h.id.clone();
-        tracing::info!(id = id.to_string(), "start history");
-        running.insert(id.clone(), h);
+        let id = self.start(h);
 
         let reply = StartHistoryReply { id: id.to_string() };
 
@@ -86,49 +167,24 @@ impl HistorySvc for HistoryService {
         &self,
         request: Request<EndHistoryRequest>,
     ) -> Result<Response<EndHistoryReply>, Status> {
-        let running = self.running.clone();
         let req = request.into_inner();
 
-        let id = HistoryId(req.id);
+        let (id, idx) = self
+            .end(
+                HistoryId(req.id),
+                req.exit,
+                req.duration,
+                req.stdout_bytes,
+                req.stderr_bytes,
+            )
+            .await?;
+
+        let reply = EndHistoryReply {
+            id: id.0.to_string(),
+            idx,
+        };
 
-        if let Some((_, mut history)) = running.remove(&id) {
-            history.exit = req.exit;
-            history.duration = match req.duration {
-                0 => i64::try_from(
-                    (OffsetDateTime::now_utc() - history.timestamp).whole_nanoseconds(),
-                )
-                .expect("failed to convert calculated duration to i64"),
-                value => i64::try_from(value).expect("failed to get i64 duration"),
-            };
-
-            // Perhaps allow the incremental build to handle this entirely.
-            self.history_db
-                .save(&history)
-                .await
-                .map_err(|e| Status::internal(format!("failed to write to db: {e:?}")))?;
-
-            tracing::info!(
-                id = id.0.to_string(),
-                duration = history.duration,
-                "end history"
-            );
-
-            let (id, idx) =
-                self.store.push(history).await.map_err(|e| {
-                    Status::internal(format!("failed to push record to store: {e:?}"))
-                })?;
-
-            let reply = EndHistoryReply {
-                id: id.0.to_string(),
-                idx,
-            };
-
-            return Ok(Response::new(reply));
-        }
-
-        Err(Status::not_found(format!(
-            "could not find history with id: {id}"
-        )))
+        Ok(Response::new(reply))
     }
 }
 
@@ -161,7 +217,11 @@ async fn shutdown_signal() {
 }
 
 #[cfg(unix)]
-async fn start_server(settings: Settings, history: HistoryService) -> Result<()> {
+async fn start_server(
+    settings: Settings,
+    history: HistoryService,
+    query: QueryService,
+) -> Result<()> {
     use tokio::net::UnixListener;
     use tokio_stream::wrappers::UnixListenerStream;
 
@@ -210,6 +270,7 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 
     Server::builder()
         .add_service(HistoryServer::new(history))
+        .add_service(QueryServer::new(query))
         .serve_with_incoming_shutdown(
             uds_stream,
             shutdown_signal(cleanup.then_some(socket_path.into())),
@@ -220,7 +281,11 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 }
 
 #[cfg(not(unix))]
-async fn start_server(settings: Settings, history: HistoryService) -> Result<()> {
+async fn start_server(
+    settings: Settings,
+    history: HistoryService,
+    query: QueryService,
+) -> Result<()> {
     use tokio::net::TcpListener;
     use tokio_stream::wrappers::TcpListenerStream;
 
@@ -233,13 +298,12 @@ async fn start_server(settings: Settings, history: HistoryService) -> Result<()>
 
     Server::builder()
         .add_service(HistoryServer::new(history))
+        .add_service(QueryServer::new(query))
         .serve_with_incoming_shutdown(tcp_stream, shutdown_signal())
         .await?;
     Ok(())
 }
 
-// break the above down when we end up with multiple services
-
 /// Listen on a unix socket
 /// Pass the path to the socket
 pub async fn listen(
@@ -252,17 +316,36 @@ pub async fn listen(
         .into();
 
     let host_id = Settings::host_id().expect("failed to get host_id");
-    let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
+    let history_store =
+        HistoryStore::from_settings(store.clone(), host_id, encryption_key, &settings);
+
+    // the services pick up changes to config.toml as they're made
+    let watched = settings.watch();
 
-    let history = HistoryService::new(history_store.clone(), history_db.clone());
+    let history = HistoryService::new(history_store.clone(), history_db.clone(), watched.clone());
+    let query = QueryService::new(
+        history_db.clone(),
+        store.clone(),
+        encryption_key,
+        host_id,
+        watched.clone(),
+    );
 
     // start services
-    tokio::spawn(sync::worker(
-        settings.clone(),
-        store,
-        history_store,
-        history_db,
-    ));
-
-    start_server(settings, history).await
+    tokio::spawn(sync::worker(watched, store, history_store, history_db));
+
+    // the daemon is still useful through atuin without it, so this isn't fatal
+    #[cfg(unix)]
+    tokio::spawn({
+        let path = settings.daemon.shell_socket_path.clone();
+        let history = history.clone();
+
+        async move {
+            if let Err(e) = shell::listen(path, history).await {
+                tracing::error!("not listening for shells: {e:?}");
+            }
+        }
+    });
+
+    start_server(settings, history, query).await
 }
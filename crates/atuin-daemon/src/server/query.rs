@@ -0,0 +1,264 @@
+use std::ops::Range;
+
+use atuin_client::{
+    database::{shell_context, Context, Database, OptFilters},
+    history::History,
+    kv::KvStore,
+    record::sqlite_store::SqliteStore,
+    settings::{self, Settings},
+};
+use atuin_common::record::HostId;
+use atuin_history::stats::compute_from_counts;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+use tonic::{Request, Response, Status};
+use tracing::{instrument, Level};
+
+use super::HistoryDatabase;
+use crate::query::{
+    query_server::Query as QuerySvc, CommandCount, FilterMode, HistoryEntry, KvGetReply,
+    KvGetRequest, KvSetReply, KvSetRequest, ListReply, ListRequest, SearchMode, SearchReply,
+    SearchRequest, StatsReply, StatsRequest,
+};
+
+const DEFAULT_STATS_COUNT: usize = 10;
+
+#[derive(Debug)]
+pub struct QueryService {
+    history_db: HistoryDatabase,
+    store: SqliteStore,
+    encryption_key: [u8; 32],
+    host_id: HostId,
+    settings: watch::Receiver<Settings>,
+}
+
+impl QueryService {
+    pub fn new(
+        history_db: HistoryDatabase,
+        store: SqliteStore,
+        encryption_key: [u8; 32],
+        host_id: HostId,
+        settings: watch::Receiver<Settings>,
+    ) -> Self {
+        Self {
+            history_db,
+            store,
+            encryption_key,
+            host_id,
+            settings,
+        }
+    }
+
+    fn search_mode(&self, mode: SearchMode) -> settings::SearchMode {
+        match mode {
+            SearchMode::Default => self.settings.borrow().search_mode,
+            SearchMode::Prefix => settings::SearchMode::Prefix,
+            SearchMode::FullText => settings::SearchMode::FullText,
+            SearchMode::Fuzzy => settings::SearchMode::Fuzzy,
+            SearchMode::Skim => settings::SearchMode::Skim,
+        }
+    }
+
+    fn filter_mode(&self, mode: FilterMode) -> settings::FilterMode {
+        match mode {
+            FilterMode::Default => self.settings.borrow().default_filter_mode(),
+            FilterMode::Global => settings::FilterMode::Global,
+            FilterMode::Host => settings::FilterMode::Host,
+            FilterMode::Session => settings::FilterMode::Session,
+            FilterMode::Directory => settings::FilterMode::Directory,
+            FilterMode::Workspace => settings::FilterMode::Workspace,
+            FilterMode::Namespace => settings::FilterMode::Namespace,
+            FilterMode::Starred => settings::FilterMode::Starred,
+        }
+    }
+}
+
+fn context(context: Option<crate::query::Context>) -> Context {
+    let context = context.unwrap_or_default();
+    shell_context(context.session, context.cwd)
+}
+
+fn internal(e: impl std::fmt::Debug) -> Status {
+    Status::internal(format!("{e:?}"))
+}
+
+fn timestamp(nanos: i64) -> Result<OffsetDateTime, Status> {
+    OffsetDateTime::from_unix_timestamp_nanos(nanos.into())
+        .map_err(|_| Status::invalid_argument("timestamps are nanoseconds since the unix epoch"))
+}
+
+fn namespace(namespace: &str) -> &str {
+    if namespace.is_empty() {
+        "default"
+    } else {
+        namespace
+    }
+}
+
+impl From<History> for HistoryEntry {
+    fn from(h: History) -> Self {
+        Self {
+            id: h.id.0,
+            timestamp: h.timestamp.unix_timestamp_nanos() as i64,
+            command: h.command,
+            cwd: h.cwd,
+            exit: h.exit,
+            duration: h.duration,
+            session: h.session,
+            hostname: h.hostname,
+        }
+    }
+}
+
+#[tonic::async_trait()]
+impl QuerySvc for QueryService {
+    #[instrument(skip_all, level = Level::INFO)]
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchReply>, Status> {
+        let req = request.into_inner();
+        let search_mode = self.search_mode(req.search_mode());
+        let filter_mode = self.filter_mode(req.filter_mode());
+
+        let history = self
+            .history_db
+            .search(
+                search_mode,
+                filter_mode,
+                &context(req.context),
+                &req.query,
+                OptFilters {
+                    limit: (req.limit > 0).then_some(req.limit),
+                    ..OptFilters::default()
+                },
+            )
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(SearchReply {
+            history: history.into_iter().map(HistoryEntry::from).collect(),
+        }))
+    }
+
+    #[instrument(skip_all, level = Level::INFO)]
+    async fn list(&self, request: Request<ListRequest>) -> Result<Response<ListReply>, Status> {
+        let req = request.into_inner();
+        let filter_mode = self.filter_mode(req.filter_mode());
+        let limit = usize::try_from(req.limit).ok().filter(|limit| *limit > 0);
+
+        let history = self
+            .history_db
+            .list(
+                &[filter_mode],
+                &context(req.context),
+                limit,
+                req.unique,
+                false,
+            )
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(ListReply {
+            history: history.into_iter().map(HistoryEntry::from).collect(),
+        }))
+    }
+
+    #[instrument(skip_all, level = Level::INFO)]
+    async fn stats(&self, request: Request<StatsRequest>) -> Result<Response<StatsReply>, Status> {
+        let req = request.into_inner();
+
+        // the same as `atuin stats`, which can also give a start or an end
+        let range: Option<Range<OffsetDateTime>> = match (req.since, req.until) {
+            (None, None) => None,
+            (since, until) => Some(
+                since.map_or(Ok(OffsetDateTime::UNIX_EPOCH), timestamp)?
+                    ..until.map_or(Ok(OffsetDateTime::now_utc()), timestamp)?,
+            ),
+        };
+
+        let count = match usize::try_from(req.count) {
+            Ok(0) | Err(_) => DEFAULT_STATS_COUNT,
+            Ok(count) => count,
+        };
+
+        let commands = self
+            .history_db
+            .top_commands(range.clone(), None, false)
+            .await
+            .map_err(internal)?;
+        let cancelled = self
+            .history_db
+            .cancelled_count(range)
+            .await
+            .map_err(internal)?;
+
+        let commands = commands
+            .iter()
+            .map(|(command, count)| (command.as_str(), usize::try_from(*count).unwrap_or(0)));
+
+        let mut reply = StatsReply {
+            cancelled_commands: u64::try_from(cancelled).unwrap_or(0),
+            ..StatsReply::default()
+        };
+
+        let stats = compute_from_counts(&self.settings.borrow(), commands, count, 1);
+
+        if let Some(stats) = stats {
+            reply.total_commands = stats.total_commands as u64;
+            reply.unique_commands = stats.unique_commands as u64;
+            reply.top = stats
+                .top
+                .into_iter()
+                .map(|(command, count)| CommandCount {
+                    command: command.join(" | "),
+                    count: count as u64,
+                })
+                .collect();
+        }
+
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(skip_all, level = Level::INFO)]
+    async fn kv_get(&self, request: Request<KvGetRequest>) -> Result<Response<KvGetReply>, Status> {
+        let req = request.into_inner();
+
+        let record = KvStore::new()
+            .get(
+                &self.store,
+                &self.encryption_key,
+                namespace(&req.namespace),
+                &req.key,
+            )
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(KvGetReply {
+            value: record.map(|r| r.value),
+        }))
+    }
+
+    #[instrument(skip_all, level = Level::INFO)]
+    async fn kv_set(&self, request: Request<KvSetRequest>) -> Result<Response<KvSetReply>, Status> {
+        let req = request.into_inner();
+
+        if req.key.is_empty() {
+            return Err(Status::invalid_argument("a key is required"));
+        }
+
+        KvStore::new()
+            .set(
+                &self.store,
+                &self.encryption_key,
+                self.host_id,
+                namespace(&req.namespace),
+                &req.key,
+                &req.value,
+            )
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(KvSetReply {}))
+    }
+}
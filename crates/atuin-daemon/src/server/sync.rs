@@ -1,29 +1,38 @@
 use eyre::Result;
 use rand::Rng;
-use tokio::time::{self, MissedTickBehavior};
+use tokio::{
+    sync::watch,
+    time::{self, MissedTickBehavior},
+};
 
-use atuin_client::database::Sqlite as HistoryDatabase;
 use atuin_client::{
     encryption,
-    history::store::HistoryStore,
+    history::{annotation::AnnotationStore, store::HistoryStore},
+    kv::KvStore,
     record::{sqlite_store::SqliteStore, sync},
     settings::Settings,
 };
 
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
+
+use super::HistoryDatabase;
 
 pub async fn worker(
-    settings: Settings,
+    watched: watch::Receiver<Settings>,
     store: SqliteStore,
     history_store: HistoryStore,
     history_db: HistoryDatabase,
 ) -> Result<()> {
     tracing::info!("booting sync worker");
 
+    let mut settings = watched.borrow().clone();
+
     let encryption_key: [u8; 32] = encryption::load_key(&settings)?.into();
     let host_id = Settings::host_id().expect("failed to get host_id");
     let alias_store = AliasStore::new(store.clone(), host_id, encryption_key);
     let var_store = VarStore::new(store.clone(), host_id, encryption_key);
+    let snippet_store = SnippetStore::new(store.clone(), host_id, encryption_key);
+    let annotation_store = AnnotationStore::new(store.clone(), host_id, encryption_key);
 
     // Don't backoff by more than 30 mins (with a random jitter of up to 1 min)
     let max_interval: f64 = 60.0 * 30.0 + rand::thread_rng().gen_range(0.0..60.0);
@@ -38,11 +47,44 @@ pub async fn worker(
         ticker.tick().await;
         tracing::info!("sync worker tick");
 
-        if !settings.logged_in() {
-            tracing::debug!("not logged in, skipping sync tick");
+        // so a changed schedule or network frequency counts from this tick
+        settings = watched.borrow().clone();
+
+        if !settings.can_sync() {
+            tracing::debug!("not logged in and no sync backend, skipping sync tick");
+            continue;
+        }
+
+        let network = settings.sync_network();
+
+        if !settings.sync_scheduled(network.as_ref()) {
+            tracing::debug!("sync schedule does not allow syncing now, skipping sync tick");
             continue;
         }
 
+        if let Some(frequency) = settings.network_sync_frequency(network.as_ref()) {
+            match settings.sync_due(frequency) {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::debug!("synced too recently for this network, skipping sync tick");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("invalid network sync frequency: {e}");
+                    continue;
+                }
+            }
+        }
+
+        // so that daemons on hosts that woke together don't all sync at once
+        time::sleep(Settings::sync_jitter(ticker.period())).await;
+
+        // shells sync too, when the daemon isn't recording their history
+        let Some(_lock) = sync::SyncLock::acquire() else {
+            tracing::debug!("another sync is running, skipping sync tick");
+            continue;
+        };
+
         let res = sync::sync(&settings, &store).await;
 
         if let Err(e) = res {
@@ -72,9 +114,20 @@ pub async fn worker(
             history_store
                 .incremental_build(&history_db, &downloaded)
                 .await?;
+            history_store.build_starred(&history_db).await?;
+            annotation_store.build(&history_db).await?;
 
             alias_store.build().await?;
             var_store.build().await?;
+            snippet_store.build().await?;
+
+            // build_starred brought the kv index up to date, so this catches keys that arrived
+            // already expired too
+            match KvStore::new().purge_expired(&store).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::debug!(purged, "purged expired kv keys"),
+                Err(e) => tracing::error!("failed to purge expired kv keys: {e:?}"),
+            }
 
             // Reset backoff on success
             if ticker.period().as_secs() != settings.daemon.sync_frequency {
@@ -8,6 +8,7 @@ use crate::store::AliasStore;
 
 pub mod bash;
 pub mod fish;
+pub mod nu;
 pub mod xonsh;
 pub mod zsh;
 
@@ -75,6 +76,140 @@ impl Var {
     }
 }
 
+/// Which shells a snippet is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SnippetShell {
+    /// bash and zsh
+    Posix,
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl SnippetShell {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnippetShell::Posix => "posix",
+            SnippetShell::Bash => "bash",
+            SnippetShell::Zsh => "zsh",
+            SnippetShell::Fish => "fish",
+        }
+    }
+
+    /// The shell a file is for, from its extension
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "sh" => Some(SnippetShell::Posix),
+            "bash" => Some(SnippetShell::Bash),
+            "zsh" => Some(SnippetShell::Zsh),
+            "fish" => Some(SnippetShell::Fish),
+            _ => None,
+        }
+    }
+
+    /// Whether a snippet for this can run in `shell`
+    pub fn runs_in(&self, shell: SnippetShell) -> bool {
+        *self == shell || (*self == SnippetShell::Posix && shell != SnippetShell::Fish)
+    }
+}
+
+impl std::str::FromStr for SnippetShell {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "posix" | "sh" => Ok(SnippetShell::Posix),
+            "bash" => Ok(SnippetShell::Bash),
+            "zsh" => Ok(SnippetShell::Zsh),
+            "fish" => Ok(SnippetShell::Fish),
+            _ => Err(eyre!(
+                "unknown snippet shell {s:?}, expected one of posix, bash, zsh, or fish"
+            )),
+        }
+    }
+}
+
+/// A piece of shell code, like a function, run by every new shell it's for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snippet {
+    pub name: String,
+    pub shell: SnippetShell,
+    pub body: String,
+}
+
+impl Snippet {
+    /// Serialize into the given vec
+    /// This is intended to be called by the store
+    pub fn serialize(&self, output: &mut Vec<u8>) -> Result<()> {
+        encode::write_array_len(output, 3)?; // 3 fields
+
+        encode::write_str(output, self.name.as_str())?;
+        encode::write_str(output, self.shell.as_str())?;
+        encode::write_str(output, self.body.as_str())?;
+
+        Ok(())
+    }
+
+    pub fn deserialize(bytes: &mut decode::Bytes) -> Result<Self> {
+        fn error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
+            eyre!("{err:?}")
+        }
+
+        let nfields = decode::read_array_len(bytes).map_err(error_report)?;
+
+        ensure!(
+            nfields == 3,
+            "too many entries in v0 dotfiles snippet create record, got {}, expected {}",
+            nfields,
+            3
+        );
+
+        let bytes = bytes.remaining_slice();
+
+        let (name, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+        let (shell, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+        let (body, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+        ensure!(
+            bytes.is_empty(),
+            "trailing bytes in encoded dotfiles snippet record, malformed"
+        );
+
+        Ok(Snippet {
+            name: name.to_owned(),
+            shell: shell.parse()?,
+            body: body.to_owned(),
+        })
+    }
+
+    /// The snippet as config for `shell`. It's run with eval, so that one that fails to parse
+    /// doesn't stop the rest of the config from loading.
+    pub fn config(&self, shell: SnippetShell) -> String {
+        let body = if shell == SnippetShell::Fish {
+            fish_quote(&self.body)
+        } else {
+            posix_quote(&self.body)
+        };
+
+        format!("# atuin snippet: {}\neval {body}\n", self.name)
+    }
+}
+
+/// Quote a string to be one word in bash or zsh
+pub fn posix_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quote a string to be one word in fish
+pub fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Quote a string as a nushell string
+pub fn nu_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', r"\\").replace('"', r#"\""#))
+}
+
 pub fn parse_alias(line: &str) -> Option<Alias> {
     // consider the fact we might be importing a fish alias
     // 'alias' output
@@ -112,6 +247,39 @@ pub fn parse_alias(line: &str) -> Option<Alias> {
     })
 }
 
+/// Parse the aliases defined in a shell file, like ~/.aliases or ~/.zshrc. Only lines that are
+/// an alias on their own are understood, in posix (`alias foo='bar'`) or fish (`alias foo bar`)
+/// form. Anything else is skipped, including aliases with options like zsh's `alias -g`.
+pub fn parse_alias_file(contents: &str) -> Vec<Alias> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("alias")?;
+
+            // "aliased=1" is not an alias
+            if !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+
+            let rest = rest.trim_start();
+
+            if rest.starts_with('-') {
+                return None;
+            }
+
+            let name = rest.split(char::is_whitespace).next().unwrap_or_default();
+
+            let alias = if name.contains('=') {
+                parse_alias(rest)?
+            } else {
+                parse_alias(&format!("alias {rest}"))?
+            };
+
+            (!alias.name.is_empty() && !alias.value.is_empty()).then_some(alias)
+        })
+        .collect()
+}
+
 pub fn existing_aliases(shell: Option<Shell>) -> Result<Vec<Alias>, ShellError> {
     let shell = if let Some(shell) = shell {
         shell
@@ -158,7 +326,7 @@ pub async fn import_aliases(store: &AliasStore) -> Result<Vec<Alias>> {
 
 #[cfg(test)]
 mod tests {
-    use crate::shell::{parse_alias, Alias};
+    use crate::shell::{parse_alias, parse_alias_file, Alias};
 
     #[test]
     fn test_parse_simple_alias() {
@@ -234,4 +402,34 @@ k=kubectl
         assert_eq!(aliases[1].name, "k");
         assert_eq!(aliases[1].value, "kubectl");
     }
+
+    #[test]
+    fn test_parse_alias_file() {
+        let file = r#"
+# some aliases
+alias k=kubectl
+alias gs='git status'
+    alias ll="ls -la"
+alias gl git log --oneline
+alias -g G='| grep'
+export aliased=1
+aliases=1
+alias
+"#;
+
+        let alias = |name: &str, value: &str| Alias {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        assert_eq!(
+            parse_alias_file(file),
+            [
+                alias("k", "kubectl"),
+                alias("gs", "'git status'"),
+                alias("ll", "\"ls -la\""),
+                alias("gl", "git log --oneline"),
+            ]
+        );
+    }
 }
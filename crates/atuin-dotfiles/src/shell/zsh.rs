@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
-use crate::store::{var::VarStore, AliasStore};
+use crate::shell::SnippetShell;
+use crate::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 
 async fn cached_aliases(path: PathBuf, store: &AliasStore) -> String {
     match tokio::fs::read_to_string(path).await {
@@ -30,6 +31,20 @@ async fn cached_vars(path: PathBuf, store: &VarStore) -> String {
     }
 }
 
+async fn cached_snippets(path: PathBuf, store: &SnippetStore) -> String {
+    match tokio::fs::read_to_string(path).await {
+        Ok(snippets) => snippets,
+        Err(r) => {
+            // we failed to read the file for some reason, but the file does exist
+            // fallback to generating new snippets on the fly
+
+            store.config(SnippetShell::Zsh).await.unwrap_or_else(|e| {
+                format!("echo 'Atuin: failed to read and generate snippets: \n{r}\n{e}'",)
+            })
+        }
+    }
+}
+
 /// Return zsh dotfile config
 ///
 /// Do not return an error. We should not prevent the shell from starting.
@@ -66,3 +81,18 @@ pub async fn var_config(store: &VarStore) -> String {
 
     cached_vars(vars, store).await
 }
+
+pub async fn snippet_config(store: &SnippetStore) -> String {
+    // First try to read the cached config
+    let snippets = atuin_common::utils::dotfiles_cache_dir().join("snippets.zsh");
+
+    if snippets.exists() {
+        return cached_snippets(snippets, store).await;
+    }
+
+    if let Err(e) = store.build().await {
+        return format!("echo 'Atuin: failed to generate snippets: {}'", e);
+    }
+
+    cached_snippets(snippets, store).await
+}
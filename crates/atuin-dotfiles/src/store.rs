@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use atuin_client::record::sqlite_store::SqliteStore;
 // Sync aliases
@@ -15,19 +15,67 @@ use atuin_client::record::store::Store;
 use crate::shell::Alias;
 
 const CONFIG_SHELL_ALIAS_VERSION: &str = "v0";
+// v1 adds Set, which also says what the alias was on the host that set it, to spot conflicts
+const CONFIG_SHELL_ALIAS_VERSION_V1: &str = "v1";
 const CONFIG_SHELL_ALIAS_TAG: &str = "config-shell-alias";
 const CONFIG_SHELL_ALIAS_FIELD_MAX_LEN: usize = 20000; // 20kb max total len, way more than should be needed.
 
 mod alias;
+pub mod profile;
+pub mod snippet;
 pub mod var;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AliasRecord {
     Create(Alias),  // create a full record
     Delete(String), // delete by name
+
+    Set(Alias, Option<String>), // create, with the value it replaced on the host that set it
+}
+
+/// An alias that two hosts set differently, where the one that won didn't know about the other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasConflict {
+    /// The alias as it is now
+    pub alias: Alias,
+    pub host: HostId,
+
+    /// The value it overwrote, and the host that set that
+    pub overwritten: String,
+    pub overwritten_host: HostId,
+}
+
+/// A line that building the alias config again would add or remove
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigChange {
+    Added(String),
+    Removed(String),
+}
+
+/// The lines that differ between two configs, in order. Config is one alias per line, sorted by
+/// name, so the old and new versions of an alias end up next to each other.
+pub fn diff_config(old: &str, new: &str) -> Vec<ConfigChange> {
+    let old: BTreeSet<&str> = old.lines().collect();
+    let new: BTreeSet<&str> = new.lines().collect();
+
+    old.union(&new)
+        .filter_map(|line| match (old.contains(line), new.contains(line)) {
+            (true, false) => Some(ConfigChange::Removed(line.to_string())),
+            (false, true) => Some(ConfigChange::Added(line.to_string())),
+            _ => None,
+        })
+        .collect()
 }
 
 impl AliasRecord {
+    /// The record version this is serialized as
+    pub fn version(&self) -> &'static str {
+        match self {
+            AliasRecord::Create(_) | AliasRecord::Delete(_) => CONFIG_SHELL_ALIAS_VERSION,
+            AliasRecord::Set(..) => CONFIG_SHELL_ALIAS_VERSION_V1,
+        }
+    }
+
     pub fn serialize(&self) -> Result<DecryptedData> {
         use rmp::encode;
 
@@ -47,6 +95,18 @@ impl AliasRecord {
 
                 encode::write_str(&mut output, name.as_str())?;
             }
+            AliasRecord::Set(alias, replaced) => {
+                encode::write_u8(&mut output, 2)?; // set
+                encode::write_array_len(&mut output, 3)?; // 3 fields
+
+                encode::write_str(&mut output, alias.name.as_str())?;
+                encode::write_str(&mut output, alias.value.as_str())?;
+
+                match replaced {
+                    Some(replaced) => encode::write_str(&mut output, replaced.as_str())?,
+                    None => encode::write_nil(&mut output)?,
+                }
+            }
         }
 
         Ok(DecryptedData(output))
@@ -59,8 +119,10 @@ impl AliasRecord {
             eyre!("{err:?}")
         }
 
+        const NIL: u8 = 0xc0;
+
         match version {
-            CONFIG_SHELL_ALIAS_VERSION => {
+            CONFIG_SHELL_ALIAS_VERSION | CONFIG_SHELL_ALIAS_VERSION_V1 => {
                 let mut bytes = decode::Bytes::new(&data.0);
 
                 let record_type = decode::read_u8(&mut bytes).map_err(error_report)?;
@@ -111,6 +173,43 @@ impl AliasRecord {
                         Ok(AliasRecord::Delete(key.to_owned()))
                     }
 
+                    // set
+                    2 if version == CONFIG_SHELL_ALIAS_VERSION_V1 => {
+                        let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
+                        ensure!(
+                            nfields == 3,
+                            "too many entries in v1 shell alias set record"
+                        );
+
+                        let bytes = bytes.remaining_slice();
+
+                        let (key, bytes) =
+                            decode::read_str_from_slice(bytes).map_err(error_report)?;
+                        let (value, bytes) =
+                            decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+                        let (replaced, bytes) = match bytes.split_first() {
+                            Some((&NIL, rest)) => (None, rest),
+                            _ => {
+                                let (replaced, bytes) =
+                                    decode::read_str_from_slice(bytes).map_err(error_report)?;
+                                (Some(replaced.to_owned()), bytes)
+                            }
+                        };
+
+                        if !bytes.is_empty() {
+                            bail!("trailing bytes in encoded shell alias record. malformed")
+                        }
+
+                        Ok(AliasRecord::Set(
+                            Alias {
+                                name: key.to_owned(),
+                                value: value.to_owned(),
+                            },
+                            replaced,
+                        ))
+                    }
+
                     n => {
                         bail!("unknown AliasRecord type {n}")
                     }
@@ -168,6 +267,20 @@ impl AliasStore {
         Ok(config)
     }
 
+    pub async fn nu(&self) -> Result<String> {
+        let aliases = self.aliases().await?;
+
+        let mut config = String::new();
+
+        for alias in aliases {
+            let value = unquote(alias.value.as_str()).unwrap_or(alias.value.clone());
+
+            config.push_str(&format!("alias {} = {}\n", alias.name, value));
+        }
+
+        Ok(config)
+    }
+
     pub async fn build(&self) -> Result<()> {
         let dir = atuin_common::utils::dotfiles_cache_dir();
         tokio::fs::create_dir_all(dir.clone()).await?;
@@ -175,6 +288,7 @@ impl AliasStore {
         // Build for all supported shells
         let posix = self.posix().await?;
         let xonsh = self.xonsh().await?;
+        let nu = self.nu().await?;
 
         // All the same contents, maybe optimize in the future or perhaps there will be quirks
         // per-shell
@@ -183,64 +297,70 @@ impl AliasStore {
         let bash = dir.join("aliases.bash");
         let fish = dir.join("aliases.fish");
         let xsh = dir.join("aliases.xsh");
+        let nush = dir.join("aliases.nu");
 
         tokio::fs::write(zsh, &posix).await?;
         tokio::fs::write(bash, &posix).await?;
         tokio::fs::write(fish, &posix).await?;
         tokio::fs::write(xsh, &xonsh).await?;
+        tokio::fs::write(nush, &nu).await?;
 
         Ok(())
     }
 
-    pub async fn set(&self, name: &str, value: &str) -> Result<()> {
-        if name.len() + value.len() > CONFIG_SHELL_ALIAS_FIELD_MAX_LEN {
-            return Err(eyre!(
-                "alias record too large: max len {} bytes",
-                CONFIG_SHELL_ALIAS_FIELD_MAX_LEN
-            ));
-        }
+    /// How building the config would change the aliases that shells load now
+    pub async fn diff(&self) -> Result<Vec<ConfigChange>> {
+        // bash and zsh share the posix config, and every shell has the same aliases
+        let cached = atuin_common::utils::dotfiles_cache_dir().join("aliases.zsh");
 
-        let record = AliasRecord::Create(Alias {
-            name: name.to_string(),
-            value: value.to_string(),
-        });
+        let old = match tokio::fs::read_to_string(cached).await {
+            Ok(old) => old,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
 
-        let bytes = record.serialize()?;
+        Ok(diff_config(&old, &self.posix().await?))
+    }
 
-        let idx = self
-            .store
-            .last(self.host_id, CONFIG_SHELL_ALIAS_TAG)
+    pub async fn set(&self, name: &str, value: &str) -> Result<()> {
+        let replaced = self
+            .aliases()
             .await?
-            .map_or(0, |entry| entry.idx + 1);
-
-        let record = atuin_common::record::Record::builder()
-            .host(Host::new(self.host_id))
-            .version(CONFIG_SHELL_ALIAS_VERSION.to_string())
-            .tag(CONFIG_SHELL_ALIAS_TAG.to_string())
-            .idx(idx)
-            .data(bytes)
-            .build();
-
-        self.store
-            .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
-            .await?;
+            .into_iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value);
 
-        // set mutates shell config, so build again
-        self.build().await?;
+        let record = AliasRecord::Set(
+            Alias {
+                name: name.to_string(),
+                value: value.to_string(),
+            },
+            replaced,
+        );
 
-        Ok(())
+        self.push(record).await
     }
 
     pub async fn delete(&self, name: &str) -> Result<()> {
-        if name.len() > CONFIG_SHELL_ALIAS_FIELD_MAX_LEN {
+        self.push(AliasRecord::Delete(name.to_string())).await
+    }
+
+    async fn push(&self, record: AliasRecord) -> Result<()> {
+        let len = match &record {
+            AliasRecord::Create(alias) | AliasRecord::Set(alias, _) => {
+                alias.name.len() + alias.value.len()
+            }
+            AliasRecord::Delete(name) => name.len(),
+        };
+
+        if len > CONFIG_SHELL_ALIAS_FIELD_MAX_LEN {
             return Err(eyre!(
                 "alias record too large: max len {} bytes",
                 CONFIG_SHELL_ALIAS_FIELD_MAX_LEN
             ));
         }
 
-        let record = AliasRecord::Delete(name.to_string());
-
+        let version = record.version();
         let bytes = record.serialize()?;
 
         let idx = self
@@ -251,7 +371,7 @@ impl AliasStore {
 
         let record = atuin_common::record::Record::builder()
             .host(Host::new(self.host_id))
-            .version(CONFIG_SHELL_ALIAS_VERSION.to_string())
+            .version(version.to_string())
             .tag(CONFIG_SHELL_ALIAS_TAG.to_string())
             .idx(idx)
             .data(bytes)
@@ -261,39 +381,91 @@ impl AliasStore {
             .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
             .await?;
 
-        // delete mutates shell config, so build again
+        // this mutates shell config, so build again
         self.build().await?;
 
         Ok(())
     }
 
     pub async fn aliases(&self) -> Result<Vec<Alias>> {
-        let mut build = BTreeMap::new();
+        let (aliases, _) = self.replay().await?;
+
+        Ok(aliases.into_values().map(|(alias, _)| alias).collect())
+    }
+
+    /// Aliases whose latest value overwrote a different one set on another host, without the
+    /// host that set it having seen that value. Setting the alias again settles it.
+    pub async fn conflicts(&self) -> Result<Vec<AliasConflict>> {
+        let (_, conflicts) = self.replay().await?;
+
+        Ok(conflicts.into_values().collect())
+    }
+
+    // Replay every alias record, keeping the host that set each alias
+    #[allow(clippy::type_complexity)]
+    async fn replay(
+        &self,
+    ) -> Result<(
+        BTreeMap<String, (Alias, HostId)>,
+        BTreeMap<String, AliasConflict>,
+    )> {
+        let mut build: BTreeMap<String, (Alias, HostId)> = BTreeMap::new();
+        let mut conflicts = BTreeMap::new();
 
         // this is sorted, oldest to newest
         let tagged = self.store.all_tagged(CONFIG_SHELL_ALIAS_TAG).await?;
 
         for record in tagged {
             let version = record.version.clone();
+            let host = record.host.id;
 
             let decrypted = match version.as_str() {
-                CONFIG_SHELL_ALIAS_VERSION => record.decrypt::<PASETO_V4>(&self.encryption_key)?,
+                CONFIG_SHELL_ALIAS_VERSION | CONFIG_SHELL_ALIAS_VERSION_V1 => {
+                    record.decrypt::<PASETO_V4>(&self.encryption_key)?
+                }
                 version => bail!("unknown version {version:?}"),
             };
 
             let ar = AliasRecord::deserialize(&decrypted.data, version.as_str())?;
 
             match ar {
+                // v0 records don't say what they replaced, so can't be checked
                 AliasRecord::Create(a) => {
-                    build.insert(a.name.clone(), a);
+                    conflicts.remove(&a.name);
+                    build.insert(a.name.clone(), (a, host));
+                }
+                AliasRecord::Set(a, replaced) => {
+                    match build.get(&a.name) {
+                        Some((current, current_host))
+                            if *current_host != host
+                                && current.value != a.value
+                                && replaced.as_ref() != Some(&current.value) =>
+                        {
+                            conflicts.insert(
+                                a.name.clone(),
+                                AliasConflict {
+                                    alias: a.clone(),
+                                    host,
+                                    overwritten: current.value.clone(),
+                                    overwritten_host: *current_host,
+                                },
+                            );
+                        }
+                        _ => {
+                            conflicts.remove(&a.name);
+                        }
+                    }
+
+                    build.insert(a.name.clone(), (a, host));
                 }
                 AliasRecord::Delete(d) => {
+                    conflicts.remove(&d);
                     build.remove(&d);
                 }
             }
         }
 
-        Ok(build.into_values().collect())
+        Ok((build, conflicts))
     }
 }
 
@@ -311,11 +483,14 @@ pub(crate) fn test_local_timeout() -> f64 {
 mod tests {
     use rand::rngs::OsRng;
 
-    use atuin_client::record::sqlite_store::SqliteStore;
+    use atuin_client::record::{sqlite_store::SqliteStore, store::Store};
 
     use crate::shell::Alias;
 
-    use super::{test_local_timeout, AliasRecord, AliasStore, CONFIG_SHELL_ALIAS_VERSION};
+    use super::{
+        diff_config, test_local_timeout, AliasRecord, AliasStore, ConfigChange,
+        CONFIG_SHELL_ALIAS_VERSION, CONFIG_SHELL_ALIAS_VERSION_V1,
+    };
     use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
 
     #[test]
@@ -390,6 +565,122 @@ mod tests {
 alias k='kubectl'
 alias kgap='kubectl get pods --all-namespaces'
 "
-        )
+        );
+
+        let build = alias.nu().await.expect("failed to build aliases");
+
+        assert_eq!(
+            build,
+            "alias gp = git push
+alias k = kubectl
+alias kgap = kubectl get pods --all-namespaces
+"
+        );
+    }
+
+    #[test]
+    fn encode_decode_set() {
+        let alias = Alias {
+            name: "k".to_owned(),
+            value: "kubectl".to_owned(),
+        };
+
+        for replaced in [None, Some("kubecolor".to_owned())] {
+            let record = AliasRecord::Set(alias.clone(), replaced);
+            assert_eq!(record.version(), CONFIG_SHELL_ALIAS_VERSION_V1);
+
+            let encoded = record.serialize().unwrap();
+            let decoded =
+                AliasRecord::deserialize(&encoded, CONFIG_SHELL_ALIAS_VERSION_V1).unwrap();
+
+            assert_eq!(decoded, record);
+            assert!(AliasRecord::deserialize(&encoded, CONFIG_SHELL_ALIAS_VERSION).is_err());
+        }
+    }
+
+    // Copy the alias records one store has that the other doesn't, like a sync would
+    async fn sync(from: &AliasStore, to: &AliasStore) {
+        let status = to.store.status().await.unwrap();
+        let records: Vec<_> = from
+            .store
+            .all_tagged(super::CONFIG_SHELL_ALIAS_TAG)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|r| {
+                status
+                    .get(r.host.id, r.tag.clone())
+                    .map_or(true, |idx| r.idx > idx)
+            })
+            .collect();
+
+        to.store.push_batch(records.iter()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn conflicts() {
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+
+        let mut stores = Vec::new();
+
+        for _ in 0..2 {
+            let store = SqliteStore::new(":memory:", test_local_timeout())
+                .await
+                .unwrap();
+            let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+            stores.push(AliasStore::new(store, host_id, key));
+        }
+
+        let (desktop, laptop) = (&stores[0], &stores[1]);
+
+        // both set the same alias before syncing
+        desktop.set("k", "kubectl").await.unwrap();
+        laptop.set("k", "kubecolor").await.unwrap();
+
+        sync(desktop, laptop).await;
+        sync(laptop, desktop).await;
+
+        for store in [desktop, laptop] {
+            let conflicts = store.conflicts().await.unwrap();
+
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].alias.value, "kubecolor");
+            assert_eq!(conflicts[0].host, laptop.host_id);
+            assert_eq!(conflicts[0].overwritten, "kubectl");
+            assert_eq!(conflicts[0].overwritten_host, desktop.host_id);
+        }
+
+        // an overwrite from a host that had seen the old value is not a conflict
+        desktop.set("gs", "git status").await.unwrap();
+        sync(desktop, laptop).await;
+        laptop.set("gs", "git status -sb").await.unwrap();
+
+        // and setting it again settles it
+        desktop.set("k", "kubecolor").await.unwrap();
+
+        sync(desktop, laptop).await;
+        sync(laptop, desktop).await;
+
+        for store in [desktop, laptop] {
+            assert!(store.conflicts().await.unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let old = "alias gs='git status'\nalias k='kubectl'\n";
+        let new = "alias gp='git push'\nalias gs='git status'\nalias k='kubecolor'\n";
+
+        assert_eq!(
+            diff_config(old, new),
+            [
+                ConfigChange::Added("alias gp='git push'".to_string()),
+                ConfigChange::Added("alias k='kubecolor'".to_string()),
+                ConfigChange::Removed("alias k='kubectl'".to_string()),
+            ]
+        );
+
+        assert!(diff_config(new, new).is_empty());
     }
 }
@@ -0,0 +1,578 @@
+/// Store for directory profiles: vars and aliases that apply while the shell is in a directory,
+/// or anywhere below it. The shell hooks ask for the profile script each time the directory
+/// changes, which undoes what the last profile did and applies the new one.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use atuin_client::record::sqlite_store::SqliteStore;
+use atuin_common::record::{DecryptedData, Host, HostId};
+use eyre::{bail, ensure, eyre, Result};
+
+use atuin_client::record::encryption::PASETO_V4;
+use atuin_client::record::store::Store;
+
+use crate::shell::{fish_quote, posix_quote};
+
+const DOTFILES_PROFILE_VERSION: &str = "v0";
+const DOTFILES_PROFILE_TAG: &str = "dotfiles-profile";
+const DOTFILES_PROFILE_LEN: usize = 20000; // 20kb max total len, way more than should be needed.
+
+// Exported by the profile script, holding the value a var had before a profile replaced it
+const SAVED_PREFIX: &str = "__ATUIN_PROFILE_SAVED_";
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Profile {
+    /// The directory it applies in. Paths in the home directory start with ~, so that they
+    /// match on every machine.
+    pub dir: String,
+    pub vars: BTreeMap<String, String>,
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Profile {
+    fn serialize(&self, output: &mut Vec<u8>) -> Result<()> {
+        use rmp::encode;
+
+        encode::write_array_len(output, 3)?; // 3 fields
+        encode::write_str(output, &self.dir)?;
+
+        for map in [&self.vars, &self.aliases] {
+            encode::write_map_len(output, map.len() as u32)?;
+
+            for (name, value) in map {
+                encode::write_str(output, name)?;
+                encode::write_str(output, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self> {
+        use rmp::decode;
+
+        fn error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
+            eyre!("{err:?}")
+        }
+
+        let mut bytes = decode::Bytes::new(bytes);
+        let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
+        ensure!(
+            nfields == 3,
+            "too many entries in v0 dotfiles profile create record"
+        );
+
+        let (dir, mut bytes) =
+            decode::read_str_from_slice(bytes.remaining_slice()).map_err(error_report)?;
+
+        let mut maps = [BTreeMap::new(), BTreeMap::new()];
+
+        for map in &mut maps {
+            let mut rest = decode::Bytes::new(bytes);
+            let len = decode::read_map_len(&mut rest).map_err(error_report)?;
+            bytes = rest.remaining_slice();
+
+            for _ in 0..len {
+                let (name, rest) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+                let (value, rest) = decode::read_str_from_slice(rest).map_err(error_report)?;
+                bytes = rest;
+
+                map.insert(name.to_owned(), value.to_owned());
+            }
+        }
+
+        if !bytes.is_empty() {
+            bail!("trailing bytes in encoded dotfiles profile record. malformed")
+        }
+
+        let [vars, aliases] = maps;
+
+        Ok(Profile {
+            dir: dir.to_owned(),
+            vars,
+            aliases,
+        })
+    }
+
+    /// Whether it applies in `cwd`
+    pub fn applies_in(&self, cwd: &Path, home: &Path) -> bool {
+        cwd.starts_with(expand_dir(&self.dir, home))
+    }
+}
+
+/// A directory as it's kept in a profile, with the home directory as ~
+pub fn profile_dir(dir: &Path, home: &Path) -> String {
+    match dir.strip_prefix(home) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => dir.display().to_string(),
+    }
+}
+
+fn expand_dir(dir: &str, home: &Path) -> PathBuf {
+    match dir.strip_prefix('~') {
+        Some(rest) => home.join(rest.trim_start_matches('/')),
+        None => PathBuf::from(dir),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileRecord {
+    Create(Profile), // create or replace a whole profile
+    Delete(String),  // delete by directory
+}
+
+impl ProfileRecord {
+    pub fn serialize(&self) -> Result<DecryptedData> {
+        use rmp::encode;
+
+        let mut output = vec![];
+
+        match self {
+            ProfileRecord::Create(profile) => {
+                encode::write_u8(&mut output, 0)?; // create
+
+                profile.serialize(&mut output)?;
+            }
+            ProfileRecord::Delete(dir) => {
+                encode::write_u8(&mut output, 1)?; // delete
+                encode::write_array_len(&mut output, 1)?; // 1 field
+
+                encode::write_str(&mut output, dir.as_str())?;
+            }
+        }
+
+        Ok(DecryptedData(output))
+    }
+
+    pub fn deserialize(data: &DecryptedData, version: &str) -> Result<Self> {
+        use rmp::decode;
+
+        fn error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
+            eyre!("{err:?}")
+        }
+
+        match version {
+            DOTFILES_PROFILE_VERSION => {
+                let mut bytes = decode::Bytes::new(&data.0);
+
+                let record_type = decode::read_u8(&mut bytes).map_err(error_report)?;
+
+                match record_type {
+                    // create
+                    0 => Ok(ProfileRecord::Create(Profile::deserialize(
+                        bytes.remaining_slice(),
+                    )?)),
+
+                    // delete
+                    1 => {
+                        let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
+                        ensure!(
+                            nfields == 1,
+                            "too many entries in v0 dotfiles profile delete record"
+                        );
+
+                        let (dir, bytes) = decode::read_str_from_slice(bytes.remaining_slice())
+                            .map_err(error_report)?;
+
+                        if !bytes.is_empty() {
+                            bail!("trailing bytes in encoded dotfiles profile record. malformed")
+                        }
+
+                        Ok(ProfileRecord::Delete(dir.to_owned()))
+                    }
+
+                    n => {
+                        bail!("unknown Dotfiles profile record type {n}")
+                    }
+                }
+            }
+            _ => {
+                bail!("unknown version {version:?}")
+            }
+        }
+    }
+}
+
+/// The shells profile scripts can be written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileShell {
+    Posix,
+    Fish,
+}
+
+/// What the last profile script applied, which the shell hands back to the next one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Applied {
+    pub vars: Vec<String>,
+    pub aliases: Vec<String>,
+}
+
+/// The script that takes the shell from the profiles it has applied to those for `cwd`.
+/// Profiles for a directory further down override those above them.
+///
+/// Vars a profile replaces are saved in the environment, and put back when the shell leaves.
+/// Aliases it replaces go back to `restore_alias`, or are removed.
+pub fn profile_script(
+    shell: ProfileShell,
+    profiles: &[Profile],
+    cwd: &Path,
+    home: &Path,
+    applied: &Applied,
+    env: impl Fn(&str) -> Option<String>,
+    restore_alias: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut matched: Vec<&Profile> = profiles
+        .iter()
+        .filter(|profile| profile.applies_in(cwd, home))
+        .collect();
+    matched.sort_by_key(|profile| expand_dir(&profile.dir, home).components().count());
+
+    let mut vars = BTreeMap::new();
+    let mut aliases = BTreeMap::new();
+
+    for profile in matched {
+        vars.extend(profile.vars.iter());
+        aliases.extend(profile.aliases.iter());
+    }
+
+    let quote = match shell {
+        ProfileShell::Posix => posix_quote,
+        ProfileShell::Fish => fish_quote,
+    };
+
+    let mut script = String::new();
+    let mut line = |posix: String, fish: String| {
+        script.push_str(match shell {
+            ProfileShell::Posix => &posix,
+            ProfileShell::Fish => &fish,
+        });
+        script.push('\n');
+    };
+
+    // undo the last profile
+    for name in &applied.vars {
+        let saved = format!("{SAVED_PREFIX}{name}");
+
+        match env(&saved) {
+            Some(value) => {
+                let value = quote(&value);
+                line(
+                    format!("export {name}={value}; unset {saved}"),
+                    format!("set -gx {name} {value}; set -e {saved}"),
+                );
+            }
+            None => line(format!("unset {name}"), format!("set -e {name}")),
+        }
+    }
+
+    for name in &applied.aliases {
+        match restore_alias(name) {
+            Some(value) => {
+                let value = quote(&value);
+                line(
+                    format!("alias {name}={value}"),
+                    format!("alias {name} {value}"),
+                );
+            }
+            None => line(
+                format!("unalias {name} 2>/dev/null"),
+                format!("functions -e {name}"),
+            ),
+        }
+    }
+
+    // and apply the new one
+    for (name, value) in &vars {
+        // the last profile's value isn't worth saving, but anything else is
+        if !applied.vars.contains(name) {
+            if let Some(old) = env(name) {
+                let old = quote(&old);
+                line(
+                    format!("export {SAVED_PREFIX}{name}={old}"),
+                    format!("set -gx {SAVED_PREFIX}{name} {old}"),
+                );
+            }
+        }
+
+        let value = quote(value);
+        line(
+            format!("export {name}={value}"),
+            format!("set -gx {name} {value}"),
+        );
+    }
+
+    for (name, value) in &aliases {
+        let value = quote(value);
+        line(
+            format!("alias {name}={value}"),
+            format!("alias {name} {value}"),
+        );
+    }
+
+    let names = |names: Vec<&String>| {
+        quote(
+            &names
+                .into_iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    };
+    let vars = names(vars.into_keys().collect());
+    let aliases = names(aliases.into_keys().collect());
+
+    line(
+        format!("__atuin_profile_vars={vars}; __atuin_profile_aliases={aliases}"),
+        format!("set -g __atuin_profile_vars {vars}; set -g __atuin_profile_aliases {aliases}"),
+    );
+
+    script
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileStore {
+    pub store: SqliteStore,
+    pub host_id: HostId,
+    pub encryption_key: [u8; 32],
+}
+
+impl ProfileStore {
+    pub fn new(store: SqliteStore, host_id: HostId, encryption_key: [u8; 32]) -> ProfileStore {
+        ProfileStore {
+            store,
+            host_id,
+            encryption_key,
+        }
+    }
+
+    /// The profile for a directory, as it's kept (see [`profile_dir`])
+    pub async fn get(&self, dir: &str) -> Result<Option<Profile>> {
+        Ok(self.profiles().await?.into_iter().find(|p| p.dir == dir))
+    }
+
+    /// Create or replace a profile
+    pub async fn set(&self, profile: Profile) -> Result<()> {
+        let len = profile.dir.len()
+            + profile
+                .vars
+                .iter()
+                .chain(profile.aliases.iter())
+                .map(|(name, value)| name.len() + value.len())
+                .sum::<usize>();
+
+        if len > DOTFILES_PROFILE_LEN {
+            return Err(eyre!(
+                "profile record too large: max len {} bytes",
+                DOTFILES_PROFILE_LEN
+            ));
+        }
+
+        self.push(ProfileRecord::Create(profile)).await
+    }
+
+    pub async fn delete(&self, dir: &str) -> Result<()> {
+        self.push(ProfileRecord::Delete(dir.to_string())).await
+    }
+
+    async fn push(&self, record: ProfileRecord) -> Result<()> {
+        let bytes = record.serialize()?;
+
+        let idx = self
+            .store
+            .last(self.host_id, DOTFILES_PROFILE_TAG)
+            .await?
+            .map_or(0, |entry| entry.idx + 1);
+
+        let record = atuin_common::record::Record::builder()
+            .host(Host::new(self.host_id))
+            .version(DOTFILES_PROFILE_VERSION.to_string())
+            .tag(DOTFILES_PROFILE_TAG.to_string())
+            .idx(idx)
+            .data(bytes)
+            .build();
+
+        self.store
+            .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn profiles(&self) -> Result<Vec<Profile>> {
+        let mut build = BTreeMap::new();
+
+        // this is sorted, oldest to newest
+        let tagged = self.store.all_tagged(DOTFILES_PROFILE_TAG).await?;
+
+        for record in tagged {
+            let version = record.version.clone();
+
+            let decrypted = match version.as_str() {
+                DOTFILES_PROFILE_VERSION => record.decrypt::<PASETO_V4>(&self.encryption_key)?,
+                version => bail!("unknown version {version:?}"),
+            };
+
+            match ProfileRecord::deserialize(&decrypted.data, version.as_str())? {
+                ProfileRecord::Create(profile) => {
+                    build.insert(profile.dir.clone(), profile);
+                }
+                ProfileRecord::Delete(dir) => {
+                    build.remove(&dir);
+                }
+            }
+        }
+
+        Ok(build.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use rand::rngs::OsRng;
+
+    use atuin_client::record::sqlite_store::SqliteStore;
+
+    use crate::store::test_local_timeout;
+
+    use super::{
+        profile_dir, profile_script, Applied, Profile, ProfileRecord, ProfileShell, ProfileStore,
+        DOTFILES_PROFILE_VERSION,
+    };
+    use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
+
+    fn profile(dir: &str, vars: &[(&str, &str)], aliases: &[(&str, &str)]) -> Profile {
+        let map = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        Profile {
+            dir: dir.to_string(),
+            vars: map(vars),
+            aliases: map(aliases),
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let record = ProfileRecord::Create(profile(
+            "~/work",
+            &[("AWS_PROFILE", "work"), ("KUBECONFIG", "~/.kube/work")],
+            &[("k", "kubectl --context work")],
+        ));
+
+        let encoded = record.serialize().unwrap();
+        let decoded = ProfileRecord::deserialize(&encoded, DOTFILES_PROFILE_VERSION).unwrap();
+
+        assert_eq!(decoded, record);
+    }
+
+    #[test]
+    fn dirs() {
+        let home = Path::new("/home/ellie");
+
+        assert_eq!(profile_dir(Path::new("/home/ellie/work"), home), "~/work");
+        assert_eq!(profile_dir(home, home), "~");
+        assert_eq!(profile_dir(Path::new("/srv/app"), home), "/srv/app");
+
+        let work = profile("~/work", &[], &[]);
+        assert!(work.applies_in(Path::new("/home/ellie/work"), home));
+        assert!(work.applies_in(Path::new("/home/ellie/work/foo"), home));
+        assert!(!work.applies_in(Path::new("/home/ellie/workshop"), home));
+        assert!(!work.applies_in(home, home));
+    }
+
+    #[test]
+    fn script() {
+        let home = Path::new("/home/ellie");
+        let profiles = [
+            profile("~/work", &[("AWS_PROFILE", "work")], &[("k", "kubectl")]),
+            profile("~/work/foo", &[("AWS_PROFILE", "foo's")], &[]),
+        ];
+
+        // entering, with AWS_PROFILE already set
+        let env = |name: &str| (name == "AWS_PROFILE").then(|| "personal".to_string());
+        let script = profile_script(
+            ProfileShell::Posix,
+            &profiles,
+            Path::new("/home/ellie/work/foo/src"),
+            home,
+            &Applied::default(),
+            env,
+            |_| None,
+        );
+
+        assert_eq!(
+            script,
+            "export __ATUIN_PROFILE_SAVED_AWS_PROFILE='personal'
+export AWS_PROFILE='foo'\\''s'
+alias k='kubectl'
+__atuin_profile_vars='AWS_PROFILE'; __atuin_profile_aliases='k'
+"
+        );
+
+        // and leaving
+        let applied = Applied {
+            vars: vec!["AWS_PROFILE".to_string()],
+            aliases: vec!["k".to_string()],
+        };
+        let env = |name: &str| match name {
+            "AWS_PROFILE" => Some("foo's".to_string()),
+            "__ATUIN_PROFILE_SAVED_AWS_PROFILE" => Some("personal".to_string()),
+            _ => None,
+        };
+        let script = profile_script(
+            ProfileShell::Fish,
+            &profiles,
+            home,
+            home,
+            &applied,
+            env,
+            |_| None,
+        );
+
+        assert_eq!(
+            script,
+            "set -gx AWS_PROFILE 'personal'; set -e __ATUIN_PROFILE_SAVED_AWS_PROFILE
+functions -e k
+set -g __atuin_profile_vars ''; set -g __atuin_profile_aliases ''
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn build_profiles() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+        let profiles = ProfileStore::new(store, host_id, key);
+
+        profiles
+            .set(profile("~/work", &[("AWS_PROFILE", "work")], &[]))
+            .await
+            .unwrap();
+        profiles
+            .set(profile("~/play", &[("AWS_PROFILE", "play")], &[]))
+            .await
+            .unwrap();
+        profiles
+            .set(profile("~/work", &[("AWS_PROFILE", "work2")], &[]))
+            .await
+            .unwrap();
+        profiles.delete("~/play").await.unwrap();
+
+        assert_eq!(
+            profiles.profiles().await.unwrap(),
+            [profile("~/work", &[("AWS_PROFILE", "work2")], &[])]
+        );
+    }
+}
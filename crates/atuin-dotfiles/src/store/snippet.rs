@@ -0,0 +1,327 @@
+/// Store for shell snippets: functions, and other bits of shell config too big for an alias
+use std::collections::BTreeMap;
+
+use atuin_client::record::sqlite_store::SqliteStore;
+use atuin_common::record::{DecryptedData, Host, HostId};
+use eyre::{bail, ensure, eyre, Result};
+
+use atuin_client::record::encryption::PASETO_V4;
+use atuin_client::record::store::Store;
+
+use crate::shell::{Snippet, SnippetShell};
+
+const DOTFILES_SNIPPET_VERSION: &str = "v0";
+const DOTFILES_SNIPPET_TAG: &str = "dotfiles-snippet";
+const DOTFILES_SNIPPET_LEN: usize = 64 * 1024; // 64kb max total len, plenty for a few functions
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnippetRecord {
+    Create(Snippet), // create a full record
+    Delete(String),  // delete by name
+}
+
+impl SnippetRecord {
+    pub fn serialize(&self) -> Result<DecryptedData> {
+        use rmp::encode;
+
+        let mut output = vec![];
+
+        match self {
+            SnippetRecord::Create(snippet) => {
+                encode::write_u8(&mut output, 0)?; // create
+
+                snippet.serialize(&mut output)?;
+            }
+            SnippetRecord::Delete(name) => {
+                encode::write_u8(&mut output, 1)?; // delete
+                encode::write_array_len(&mut output, 1)?; // 1 field
+
+                encode::write_str(&mut output, name.as_str())?;
+            }
+        }
+
+        Ok(DecryptedData(output))
+    }
+
+    pub fn deserialize(data: &DecryptedData, version: &str) -> Result<Self> {
+        use rmp::decode;
+
+        fn error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
+            eyre!("{err:?}")
+        }
+
+        match version {
+            DOTFILES_SNIPPET_VERSION => {
+                let mut bytes = decode::Bytes::new(&data.0);
+
+                let record_type = decode::read_u8(&mut bytes).map_err(error_report)?;
+
+                match record_type {
+                    // create
+                    0 => {
+                        let snippet = Snippet::deserialize(&mut bytes)?;
+                        Ok(SnippetRecord::Create(snippet))
+                    }
+
+                    // delete
+                    1 => {
+                        let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
+                        ensure!(
+                            nfields == 1,
+                            "too many entries in v0 dotfiles snippet delete record"
+                        );
+
+                        let bytes = bytes.remaining_slice();
+
+                        let (name, bytes) =
+                            decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+                        if !bytes.is_empty() {
+                            bail!("trailing bytes in encoded dotfiles snippet record. malformed")
+                        }
+
+                        Ok(SnippetRecord::Delete(name.to_owned()))
+                    }
+
+                    n => {
+                        bail!("unknown Dotfiles snippet record type {n}")
+                    }
+                }
+            }
+            _ => {
+                bail!("unknown version {version:?}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SnippetStore {
+    pub store: SqliteStore,
+    pub host_id: HostId,
+    pub encryption_key: [u8; 32],
+}
+
+impl SnippetStore {
+    pub fn new(store: SqliteStore, host_id: HostId, encryption_key: [u8; 32]) -> SnippetStore {
+        SnippetStore {
+            store,
+            host_id,
+            encryption_key,
+        }
+    }
+
+    /// Config for one shell, with every snippet that runs in it
+    pub async fn config(&self, shell: SnippetShell) -> Result<String> {
+        let snippets = self.snippets().await?;
+
+        Ok(snippets
+            .iter()
+            .filter(|snippet| snippet.shell.runs_in(shell))
+            .map(|snippet| snippet.config(shell))
+            .collect())
+    }
+
+    pub async fn build(&self) -> Result<()> {
+        let dir = atuin_common::utils::dotfiles_cache_dir();
+        tokio::fs::create_dir_all(dir.clone()).await?;
+
+        // Unlike aliases and vars, snippets are written for a particular shell, so each gets its
+        // own. There are none for xonsh, as it isn't a shell snippets can be written for.
+        let bash = self.config(SnippetShell::Bash).await?;
+        let zsh = self.config(SnippetShell::Zsh).await?;
+        let fish = self.config(SnippetShell::Fish).await?;
+
+        tokio::fs::write(dir.join("snippets.bash"), &bash).await?;
+        tokio::fs::write(dir.join("snippets.zsh"), &zsh).await?;
+        tokio::fs::write(dir.join("snippets.fish"), &fish).await?;
+
+        Ok(())
+    }
+
+    pub async fn set(&self, name: &str, shell: SnippetShell, body: &str) -> Result<()> {
+        ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')),
+            "snippet names can only have letters, numbers, '-', '_' and '.'"
+        );
+
+        let record = SnippetRecord::Create(Snippet {
+            name: name.to_string(),
+            shell,
+            body: body.to_string(),
+        });
+
+        self.push(record).await
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<()> {
+        self.push(SnippetRecord::Delete(name.to_string())).await
+    }
+
+    async fn push(&self, record: SnippetRecord) -> Result<()> {
+        let len = match &record {
+            SnippetRecord::Create(snippet) => snippet.name.len() + snippet.body.len(),
+            SnippetRecord::Delete(name) => name.len(),
+        };
+
+        if len > DOTFILES_SNIPPET_LEN {
+            return Err(eyre!(
+                "snippet record too large: max len {} bytes",
+                DOTFILES_SNIPPET_LEN
+            ));
+        }
+
+        let bytes = record.serialize()?;
+
+        let idx = self
+            .store
+            .last(self.host_id, DOTFILES_SNIPPET_TAG)
+            .await?
+            .map_or(0, |entry| entry.idx + 1);
+
+        let record = atuin_common::record::Record::builder()
+            .host(Host::new(self.host_id))
+            .version(DOTFILES_SNIPPET_VERSION.to_string())
+            .tag(DOTFILES_SNIPPET_TAG.to_string())
+            .idx(idx)
+            .data(bytes)
+            .build();
+
+        self.store
+            .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
+            .await?;
+
+        // this mutates shell config, so build again
+        self.build().await?;
+
+        Ok(())
+    }
+
+    pub async fn snippets(&self) -> Result<Vec<Snippet>> {
+        let mut build = BTreeMap::new();
+
+        // this is sorted, oldest to newest
+        let tagged = self.store.all_tagged(DOTFILES_SNIPPET_TAG).await?;
+
+        for record in tagged {
+            let version = record.version.clone();
+
+            let decrypted = match version.as_str() {
+                DOTFILES_SNIPPET_VERSION => record.decrypt::<PASETO_V4>(&self.encryption_key)?,
+                version => bail!("unknown version {version:?}"),
+            };
+
+            match SnippetRecord::deserialize(&decrypted.data, version.as_str())? {
+                SnippetRecord::Create(snippet) => {
+                    build.insert(snippet.name.clone(), snippet);
+                }
+                SnippetRecord::Delete(name) => {
+                    build.remove(&name);
+                }
+            }
+        }
+
+        Ok(build.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use atuin_client::record::sqlite_store::SqliteStore;
+
+    use crate::{
+        shell::{Snippet, SnippetShell},
+        store::test_local_timeout,
+    };
+
+    use super::{SnippetRecord, SnippetStore, DOTFILES_SNIPPET_VERSION};
+    use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
+
+    #[test]
+    fn encode_decode() {
+        let record = SnippetRecord::Create(Snippet {
+            name: "hi".to_owned(),
+            shell: SnippetShell::Fish,
+            body: "echo hi".to_owned(),
+        });
+
+        let encoded = record.serialize().unwrap();
+        let decoded = SnippetRecord::deserialize(&encoded, DOTFILES_SNIPPET_VERSION).unwrap();
+
+        assert_eq!(decoded, record);
+    }
+
+    #[tokio::test]
+    async fn build_snippets() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+        let host_id = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+
+        let snippets = SnippetStore::new(store, host_id, key);
+
+        snippets
+            .set(
+                "deploy",
+                SnippetShell::Posix,
+                "deploy() {\n  echo 'deploying' \"$1\"\n}",
+            )
+            .await
+            .unwrap();
+        snippets
+            .set(
+                "greet",
+                SnippetShell::Fish,
+                "function greet\n  echo 'hi \\o/'\nend",
+            )
+            .await
+            .unwrap();
+        snippets
+            .set("prompt", SnippetShell::Zsh, "setopt prompt_subst")
+            .await
+            .unwrap();
+
+        assert!(snippets
+            .set("bad name", SnippetShell::Zsh, "true")
+            .await
+            .is_err());
+
+        assert_eq!(
+            snippets.config(SnippetShell::Bash).await.unwrap(),
+            "# atuin snippet: deploy
+eval 'deploy() {
+  echo '\\''deploying'\\'' \"$1\"
+}'
+"
+        );
+
+        assert_eq!(
+            snippets.config(SnippetShell::Fish).await.unwrap(),
+            "# atuin snippet: greet
+eval 'function greet
+  echo \\'hi \\\\o/\\'
+end'
+"
+        );
+
+        let zsh = snippets.config(SnippetShell::Zsh).await.unwrap();
+        assert!(zsh.contains("snippet: deploy") && zsh.contains("snippet: prompt"));
+
+        snippets.delete("prompt").await.unwrap();
+
+        let names: Vec<_> = snippets
+            .snippets()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["deploy", "greet"]);
+    }
+}
@@ -7,13 +7,17 @@ use std::collections::BTreeMap;
 use atuin_client::record::sqlite_store::SqliteStore;
 use atuin_common::record::{DecryptedData, Host, HostId};
 use eyre::{bail, ensure, eyre, Result};
+use uuid::Uuid;
 
 use atuin_client::record::encryption::PASETO_V4;
 use atuin_client::record::store::Store;
 
-use crate::shell::Var;
+use crate::shell::{nu_quote, Var};
 
 const DOTFILES_VAR_VERSION: &str = "v0";
+// v1 adds records that only apply to one host. Only those are written as v1, so clients that only
+// know v0 can still read everything else.
+const DOTFILES_VAR_VERSION_V1: &str = "v1";
 const DOTFILES_VAR_TAG: &str = "dotfiles-var";
 const DOTFILES_VAR_LEN: usize = 20000; // 20kb max total len, way more than should be needed.
 
@@ -21,9 +25,20 @@ const DOTFILES_VAR_LEN: usize = 20000; // 20kb max total len, way more than shou
 pub enum VarRecord {
     Create(Var),    // create a full record
     Delete(String), // delete by name
+
+    CreateForHost(HostId, Var), // set on one host, overriding the value set for every host
+    DeleteForHost(HostId, String), // delete one host's override
 }
 
 impl VarRecord {
+    /// The record version this is serialized as
+    pub fn version(&self) -> &'static str {
+        match self {
+            VarRecord::Create(_) | VarRecord::Delete(_) => DOTFILES_VAR_VERSION,
+            VarRecord::CreateForHost(..) | VarRecord::DeleteForHost(..) => DOTFILES_VAR_VERSION_V1,
+        }
+    }
+
     pub fn serialize(&self) -> Result<DecryptedData> {
         use rmp::encode;
 
@@ -41,6 +56,19 @@ impl VarRecord {
 
                 encode::write_str(&mut output, env.as_str())?;
             }
+            VarRecord::CreateForHost(host, env) => {
+                encode::write_u8(&mut output, 2)?; // create for host
+                encode::write_str(&mut output, &host.0.as_hyphenated().to_string())?;
+
+                env.serialize(&mut output)?;
+            }
+            VarRecord::DeleteForHost(host, env) => {
+                encode::write_u8(&mut output, 3)?; // delete for host
+                encode::write_array_len(&mut output, 2)?; // 2 fields
+
+                encode::write_str(&mut output, &host.0.as_hyphenated().to_string())?;
+                encode::write_str(&mut output, env.as_str())?;
+            }
         }
 
         Ok(DecryptedData(output))
@@ -53,8 +81,14 @@ impl VarRecord {
             eyre!("{err:?}")
         }
 
+        fn read_host(bytes: &[u8]) -> Result<(HostId, &[u8])> {
+            let (host, bytes) = decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+            Ok((HostId(Uuid::parse_str(host)?), bytes))
+        }
+
         match version {
-            DOTFILES_VAR_VERSION => {
+            DOTFILES_VAR_VERSION | DOTFILES_VAR_VERSION_V1 => {
                 let mut bytes = decode::Bytes::new(&data.0);
 
                 let record_type = decode::read_u8(&mut bytes).map_err(error_report)?;
@@ -86,6 +120,33 @@ impl VarRecord {
                         Ok(VarRecord::Delete(key.to_owned()))
                     }
 
+                    // create for host
+                    2 if version == DOTFILES_VAR_VERSION_V1 => {
+                        let (host, bytes) = read_host(bytes.remaining_slice())?;
+                        let env = Var::deserialize(&mut decode::Bytes::new(bytes))?;
+
+                        Ok(VarRecord::CreateForHost(host, env))
+                    }
+
+                    // delete for host
+                    3 if version == DOTFILES_VAR_VERSION_V1 => {
+                        let nfields = decode::read_array_len(&mut bytes).map_err(error_report)?;
+                        ensure!(
+                            nfields == 2,
+                            "too many entries in v1 dotfiles var host delete record"
+                        );
+
+                        let (host, bytes) = read_host(bytes.remaining_slice())?;
+                        let (key, bytes) =
+                            decode::read_str_from_slice(bytes).map_err(error_report)?;
+
+                        if !bytes.is_empty() {
+                            bail!("trailing bytes in encoded dotfiles var record. malformed")
+                        }
+
+                        Ok(VarRecord::DeleteForHost(host, key.to_owned()))
+                    }
+
                     n => {
                         bail!("unknown Dotfiles var record type {n}")
                     }
@@ -133,7 +194,25 @@ impl VarStore {
         let mut config = String::new();
 
         for env in env {
-            config.push_str(&format!("set -gx {} {}\n", env.name, env.value));
+            if env.export {
+                config.push_str(&format!("set -gx {} {}\n", env.name, env.value));
+            } else {
+                config.push_str(&format!("set -g {} {}\n", env.name, env.value));
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub async fn nu(&self) -> Result<String> {
+        let env = self.vars().await?;
+
+        let mut config = String::new();
+
+        // nushell has no shell vars that outlive the file setting them, so every var goes in
+        // $env
+        for env in env {
+            config.push_str(&format!("$env.{} = {}\n", env.name, nu_quote(&env.value)));
         }
 
         Ok(config)
@@ -163,6 +242,7 @@ impl VarStore {
         let posix = self.posix().await?;
         let xonsh = self.xonsh().await?;
         let fsh = self.fish().await?;
+        let nu = self.nu().await?;
 
         // All the same contents, maybe optimize in the future or perhaps there will be quirks
         // per-shell
@@ -171,65 +251,65 @@ impl VarStore {
         let bash = dir.join("vars.bash");
         let fish = dir.join("vars.fish");
         let xsh = dir.join("vars.xsh");
+        let nush = dir.join("vars.nu");
 
         tokio::fs::write(zsh, &posix).await?;
         tokio::fs::write(bash, &posix).await?;
         tokio::fs::write(fish, &fsh).await?;
         tokio::fs::write(xsh, &xonsh).await?;
+        tokio::fs::write(nush, &nu).await?;
 
         Ok(())
     }
 
     pub async fn set(&self, name: &str, value: &str, export: bool) -> Result<()> {
-        if name.len() + value.len() > DOTFILES_VAR_LEN {
-            return Err(eyre!(
-                "var record too large: max len {} bytes",
-                DOTFILES_VAR_LEN
-            ));
-        }
-
-        let record = VarRecord::Create(Var {
+        let var = Var {
             name: name.to_string(),
             value: value.to_string(),
             export,
-        });
-
-        let bytes = record.serialize()?;
+        };
 
-        let idx = self
-            .store
-            .last(self.host_id, DOTFILES_VAR_TAG)
-            .await?
-            .map_or(0, |entry| entry.idx + 1);
+        self.push(VarRecord::Create(var)).await
+    }
 
-        let record = atuin_common::record::Record::builder()
-            .host(Host::new(self.host_id))
-            .version(DOTFILES_VAR_VERSION.to_string())
-            .tag(DOTFILES_VAR_TAG.to_string())
-            .idx(idx)
-            .data(bytes)
-            .build();
+    /// Set a var on this host only. It overrides the value set for every host, until the
+    /// override is deleted.
+    pub async fn set_for_host(&self, name: &str, value: &str, export: bool) -> Result<()> {
+        let var = Var {
+            name: name.to_string(),
+            value: value.to_string(),
+            export,
+        };
 
-        self.store
-            .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
-            .await?;
+        self.push(VarRecord::CreateForHost(self.host_id, var)).await
+    }
 
-        // set mutates shell config, so build again
-        self.build().await?;
+    pub async fn delete(&self, name: &str) -> Result<()> {
+        self.push(VarRecord::Delete(name.to_string())).await
+    }
 
-        Ok(())
+    /// Delete this host's override, so it goes back to the value set for every host
+    pub async fn delete_for_host(&self, name: &str) -> Result<()> {
+        self.push(VarRecord::DeleteForHost(self.host_id, name.to_string()))
+            .await
     }
 
-    pub async fn delete(&self, name: &str) -> Result<()> {
-        if name.len() > DOTFILES_VAR_LEN {
+    async fn push(&self, record: VarRecord) -> Result<()> {
+        let len = match &record {
+            VarRecord::Create(var) | VarRecord::CreateForHost(_, var) => {
+                var.name.len() + var.value.len()
+            }
+            VarRecord::Delete(name) | VarRecord::DeleteForHost(_, name) => name.len(),
+        };
+
+        if len > DOTFILES_VAR_LEN {
             return Err(eyre!(
                 "var record too large: max len {} bytes",
-                DOTFILES_VAR_LEN,
+                DOTFILES_VAR_LEN
             ));
         }
 
-        let record = VarRecord::Delete(name.to_string());
-
+        let version = record.version();
         let bytes = record.serialize()?;
 
         let idx = self
@@ -240,7 +320,7 @@ impl VarStore {
 
         let record = atuin_common::record::Record::builder()
             .host(Host::new(self.host_id))
-            .version(DOTFILES_VAR_VERSION.to_string())
+            .version(version.to_string())
             .tag(DOTFILES_VAR_TAG.to_string())
             .idx(idx)
             .data(bytes)
@@ -250,14 +330,39 @@ impl VarStore {
             .push(&record.encrypt::<PASETO_V4>(&self.encryption_key))
             .await?;
 
-        // delete mutates shell config, so build again
+        // this mutates shell config, so build again
         self.build().await?;
 
         Ok(())
     }
 
+    /// The vars for this host: those set for every host, with this host's overrides in place
     pub async fn vars(&self) -> Result<Vec<Var>> {
+        let (mut vars, overrides) = self.replay().await?;
+
+        vars.extend(overrides);
+
+        Ok(vars.into_values().collect())
+    }
+
+    /// Only the vars set for every host, without this host's overrides
+    pub async fn shared(&self) -> Result<Vec<Var>> {
+        let (vars, _) = self.replay().await?;
+
+        Ok(vars.into_values().collect())
+    }
+
+    /// Only the vars this host overrides
+    pub async fn overrides(&self) -> Result<Vec<Var>> {
+        let (_, overrides) = self.replay().await?;
+
+        Ok(overrides.into_values().collect())
+    }
+
+    // Replay every var record, into those set for every host and this host's overrides
+    async fn replay(&self) -> Result<(BTreeMap<String, Var>, BTreeMap<String, Var>)> {
         let mut build = BTreeMap::new();
+        let mut overrides = BTreeMap::new();
 
         // this is sorted, oldest to newest
         let tagged = self.store.all_tagged(DOTFILES_VAR_TAG).await?;
@@ -266,7 +371,9 @@ impl VarStore {
             let version = record.version.clone();
 
             let decrypted = match version.as_str() {
-                DOTFILES_VAR_VERSION => record.decrypt::<PASETO_V4>(&self.encryption_key)?,
+                DOTFILES_VAR_VERSION | DOTFILES_VAR_VERSION_V1 => {
+                    record.decrypt::<PASETO_V4>(&self.encryption_key)?
+                }
                 version => bail!("unknown version {version:?}"),
             };
 
@@ -279,10 +386,18 @@ impl VarStore {
                 VarRecord::Delete(d) => {
                     build.remove(&d);
                 }
+                VarRecord::CreateForHost(host, a) if host == self.host_id => {
+                    overrides.insert(a.name.clone(), a);
+                }
+                VarRecord::DeleteForHost(host, d) if host == self.host_id => {
+                    overrides.remove(&d);
+                }
+                // another host's override
+                VarRecord::CreateForHost(..) | VarRecord::DeleteForHost(..) => {}
             }
         }
 
-        Ok(build.into_values().collect())
+        Ok((build, overrides))
     }
 }
 
@@ -294,7 +409,7 @@ mod tests {
 
     use crate::{shell::Var, store::test_local_timeout};
 
-    use super::{VarRecord, VarStore, DOTFILES_VAR_VERSION};
+    use super::{VarRecord, VarStore, DOTFILES_VAR_VERSION, DOTFILES_VAR_VERSION_V1};
     use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
 
     #[test]
@@ -353,5 +468,88 @@ mod tests {
                 export: true,
             }
         );
+
+        env.set("GREETING", r#"say "hi""#, true).await.unwrap();
+
+        assert_eq!(
+            env.nu().await.unwrap(),
+            r#"$env.BEEP = "boop"
+$env.GREETING = "say \"hi\""
+$env.HOMEBREW_NO_AUTO_UPDATE = "1"
+"#
+        );
+    }
+
+    #[test]
+    fn encode_decode_host() {
+        let host = atuin_common::record::HostId(atuin_common::utils::uuid_v7());
+        let records = [
+            VarRecord::CreateForHost(
+                host,
+                Var {
+                    name: "AWS_PROFILE".to_owned(),
+                    value: "work".to_owned(),
+                    export: true,
+                },
+            ),
+            VarRecord::DeleteForHost(host, "AWS_PROFILE".to_owned()),
+        ];
+
+        for record in records {
+            assert_eq!(record.version(), DOTFILES_VAR_VERSION_V1);
+
+            let encoded = record.serialize().unwrap();
+            let decoded = VarRecord::deserialize(&encoded, DOTFILES_VAR_VERSION_V1).unwrap();
+
+            assert_eq!(decoded, record);
+            assert!(VarRecord::deserialize(&encoded, DOTFILES_VAR_VERSION).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn host_overrides() {
+        let store = SqliteStore::new(":memory:", test_local_timeout())
+            .await
+            .unwrap();
+        let key: [u8; 32] = XSalsa20Poly1305::generate_key(&mut OsRng).into();
+
+        let desktop = VarStore::new(
+            store.clone(),
+            atuin_common::record::HostId(atuin_common::utils::uuid_v7()),
+            key,
+        );
+        let laptop = VarStore::new(
+            store,
+            atuin_common::record::HostId(atuin_common::utils::uuid_v7()),
+            key,
+        );
+
+        let profile = |value: &str| Var {
+            name: String::from("AWS_PROFILE"),
+            value: String::from(value),
+            export: true,
+        };
+
+        desktop.set("AWS_PROFILE", "work", true).await.unwrap();
+        laptop
+            .set_for_host("AWS_PROFILE", "personal", true)
+            .await
+            .unwrap();
+
+        // the override only applies on the host that set it
+        assert_eq!(desktop.vars().await.unwrap(), [profile("work")]);
+        assert_eq!(laptop.vars().await.unwrap(), [profile("personal")]);
+        assert_eq!(laptop.shared().await.unwrap(), [profile("work")]);
+        assert!(desktop.overrides().await.unwrap().is_empty());
+
+        // and outlives changes to the shared value
+        desktop.set("AWS_PROFILE", "prod", true).await.unwrap();
+        assert_eq!(laptop.vars().await.unwrap(), [profile("personal")]);
+
+        laptop.delete_for_host("AWS_PROFILE").await.unwrap();
+        assert_eq!(laptop.vars().await.unwrap(), [profile("prod")]);
+
+        desktop.delete("AWS_PROFILE").await.unwrap();
+        assert!(laptop.vars().await.unwrap().is_empty());
     }
 }
@@ -0,0 +1,123 @@
+/// A long command that's typed often enough to be worth an alias
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub name: String,
+    pub command: String,
+    pub runs: i64,
+}
+
+impl Suggestion {
+    /// Roughly how many characters the alias would have saved, had it always been used
+    pub fn saved(&self) -> i64 {
+        let per_run = self.command.chars().count() - self.name.chars().count();
+        self.runs * i64::try_from(per_run).unwrap_or(i64::MAX)
+    }
+}
+
+/// Suggest aliases for commands run at least `min_runs` times, and at least `min_length`
+/// characters long, the biggest savings first.
+///
+/// Names are the initials of the command's words, so `git commit --amend` becomes `gca`. A name
+/// that `taken` rejects, such as an existing alias or a program on the PATH, gets a number after
+/// it instead.
+pub fn suggest<'a>(
+    commands_run: impl IntoIterator<Item = (&'a str, i64)>,
+    min_runs: i64,
+    min_length: usize,
+    taken: impl Fn(&str) -> bool,
+) -> Vec<Suggestion> {
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+
+    for (command, runs) in commands_run {
+        let command = command.trim();
+
+        // multi-line commands make for confusing aliases
+        if runs < min_runs || command.chars().count() < min_length || command.contains('\n') {
+            continue;
+        }
+
+        let initials = initials(command);
+        if initials.len() < 2 {
+            continue;
+        }
+
+        let name = (1..)
+            .map(|n| match n {
+                1 => initials.clone(),
+                n => format!("{initials}{n}"),
+            })
+            .find(|name| !taken(name) && suggestions.iter().all(|s| &s.name != name))
+            .expect("ran out of alias names");
+
+        suggestions.push(Suggestion {
+            name,
+            command: command.to_string(),
+            runs,
+        });
+    }
+
+    suggestions.sort_by_key(|s| std::cmp::Reverse(s.saved()));
+    suggestions
+}
+
+// The first letter or digit of each word, skipping the dashes in front of flags
+fn initials(command: &str) -> String {
+    command
+        .split_whitespace()
+        .filter_map(|word| {
+            word.trim_start_matches('-')
+                .chars()
+                .next()
+                .filter(char::is_ascii_alphanumeric)
+        })
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{initials, suggest, Suggestion};
+
+    #[test]
+    fn test_initials() {
+        assert_eq!(initials("git commit --amend --no-edit"), "gcan");
+        assert_eq!(initials("docker compose up -d"), "dcud");
+        assert_eq!(initials("ls | grep Foo"), "lgf");
+        assert_eq!(initials("  ./run.sh  "), "");
+    }
+
+    #[test]
+    fn test_suggest() {
+        let commands = [
+            ("git commit --amend --no-edit", 20),
+            ("ls", 500),
+            ("git status --short", 3),
+            ("docker compose up -d", 40),
+            ("echo one\necho two and three", 40),
+            ("git commit --all --no-verify", 10),
+        ];
+
+        let suggestions = suggest(commands, 5, 10, |name| name == "dcud");
+
+        assert_eq!(
+            suggestions,
+            [
+                Suggestion {
+                    name: "dcud2".to_string(),
+                    command: "docker compose up -d".to_string(),
+                    runs: 40,
+                },
+                Suggestion {
+                    name: "gcan".to_string(),
+                    command: "git commit --amend --no-edit".to_string(),
+                    runs: 20,
+                },
+                Suggestion {
+                    name: "gcan2".to_string(),
+                    command: "git commit --all --no-verify".to_string(),
+                    runs: 10,
+                },
+            ]
+        );
+    }
+}
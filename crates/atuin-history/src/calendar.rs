@@ -0,0 +1,228 @@
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use time::{macros::format_description, Date, Duration};
+
+use atuin_client::theme::{Meaning, Theme};
+
+/// How many weeks the calendar shows, enough to always cover a year
+pub const WEEKS: usize = 53;
+
+/// How each level of activity is drawn, from no commands up to the busiest days
+pub const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+/// Labels for the rows, which are days of the week from Monday
+pub const DAY_LABELS: [&str; 7] = ["Mon", "", "Wed", "", "Fri", "", ""];
+
+/// Commands run each day, laid out like a contribution graph: a column for each week, and a row
+/// for each day of the week.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Calendar {
+    /// The Monday of the first week
+    pub start: Date,
+    /// Counts for each day of each week, Monday first. Days after the end are `None`.
+    pub weeks: Vec<[Option<u64>; 7]>,
+    /// The most commands run on any one day
+    pub max: u64,
+}
+
+/// The Monday the calendar starts on, for one that ends on `end`
+pub fn start(end: Date) -> Date {
+    let weekday = end.weekday().number_days_from_monday();
+    end - Duration::days(i64::from(weekday)) - Duration::weeks(WEEKS as i64 - 1)
+}
+
+impl Calendar {
+    /// Lay out counts of commands for each day, as ("YYYY-MM-DD", count), over the weeks up to
+    /// and including `end`. Days that aren't listed had no commands.
+    pub fn new(days: &[(String, i64)], end: Date) -> Self {
+        let format = format_description!("[year]-[month]-[day]");
+
+        let weekday = usize::from(end.weekday().number_days_from_monday());
+        let start = start(end);
+
+        let mut weeks = vec![[Some(0); 7]; WEEKS];
+        for day in &mut weeks[WEEKS - 1][weekday + 1..] {
+            *day = None;
+        }
+
+        for (day, count) in days {
+            let Ok(day) = Date::parse(day, &format) else {
+                continue;
+            };
+
+            if day < start || day > end {
+                continue;
+            }
+
+            let index = usize::try_from((day - start).whole_days()).unwrap_or(0);
+            weeks[index / 7][index % 7] = Some(u64::try_from(*count).unwrap_or(0));
+        }
+
+        let max = weeks.iter().flatten().flatten().copied().max().unwrap_or(0);
+
+        Self { start, weeks, max }
+    }
+
+    /// How busy a day was, from 0 for no commands to 4 for the busiest days
+    pub fn level(&self, count: u64) -> usize {
+        if count == 0 || self.max == 0 {
+            return 0;
+        }
+
+        usize::try_from((count * 4).div_ceil(self.max).clamp(1, 4)).unwrap_or(4)
+    }
+
+    /// How many commands were run in the whole calendar
+    pub fn total(&self) -> u64 {
+        self.weeks.iter().flatten().flatten().sum()
+    }
+
+    /// How many days had any commands run on them
+    pub fn active_days(&self) -> usize {
+        self.weeks
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|count| **count > 0)
+            .count()
+    }
+
+    /// The day with the most commands, and how many were run. The earliest wins a tie.
+    pub fn busiest(&self) -> Option<(Date, u64)> {
+        let index = self
+            .weeks
+            .iter()
+            .flatten()
+            .position(|count| *count == Some(self.max))
+            .filter(|_| self.max > 0)?;
+
+        Some((self.start + Duration::days(index as i64), self.max))
+    }
+
+    /// A line of month names, each above the first week that starts in that month. It's as
+    /// wide as the calendar, a character for each week.
+    pub fn month_labels(&self) -> String {
+        let mut labels = vec![' '; self.weeks.len()];
+        let mut previous = None;
+
+        for week in 0..self.weeks.len() {
+            let month = (self.start + Duration::weeks(week as i64)).month();
+
+            if previous.is_some_and(|previous| previous != month) {
+                // a month that starts in the last couple of weeks doesn't fit its name
+                let name = &format!("{month}")[..3];
+                if week + name.len() <= labels.len() {
+                    for (i, c) in name.chars().enumerate() {
+                        labels[week + i] = c;
+                    }
+                }
+            }
+
+            previous = Some(month);
+        }
+
+        labels.into_iter().collect()
+    }
+}
+
+/// Print the calendar to the terminal, with the busiest days in the theme's info colour
+pub fn pretty_print(calendar: &Calendar, theme: &Theme) {
+    let muted = SetForegroundColor(
+        theme
+            .as_style(Meaning::Muted)
+            .foreground_color
+            .unwrap_or(Color::Grey),
+    );
+    let active = SetForegroundColor(theme.get_info().foreground_color.unwrap_or(Color::Green));
+
+    println!("    {}", calendar.month_labels());
+
+    for (weekday, label) in DAY_LABELS.iter().enumerate() {
+        print!("{label:4}");
+
+        for week in &calendar.weeks {
+            match week[weekday] {
+                None => print!(" "),
+                Some(0) => print!("{muted}{}{ResetColor}", SHADES[0]),
+                Some(count) => print!("{active}{}{ResetColor}", SHADES[calendar.level(count)]),
+            }
+        }
+
+        println!();
+    }
+
+    println!();
+    println!(
+        "{} commands on {} days",
+        calendar.total(),
+        calendar.active_days()
+    );
+
+    if let Some((day, count)) = calendar.busiest() {
+        println!("Busiest day was {day}, with {count} commands");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::{Calendar, WEEKS};
+
+    #[test]
+    fn lays_out_weeks_from_monday() {
+        // a Wednesday
+        let end = date!(2024 - 03 - 06);
+        let days = [
+            ("2023-01-01".to_string(), 100),
+            ("2024-03-04".to_string(), 4),
+            ("2024-03-06".to_string(), 1),
+            ("2024-02-26".to_string(), 2),
+        ];
+
+        let calendar = Calendar::new(&days, end);
+
+        assert_eq!(calendar.start, date!(2023 - 03 - 06));
+        assert_eq!(calendar.weeks.len(), WEEKS);
+        assert_eq!(
+            calendar.weeks[WEEKS - 1],
+            [Some(4), Some(0), Some(1), None, None, None, None]
+        );
+        assert_eq!(calendar.weeks[WEEKS - 2][0], Some(2));
+
+        // the first day is too long ago to be shown
+        assert_eq!(calendar.max, 4);
+        assert_eq!(calendar.total(), 7);
+        assert_eq!(calendar.active_days(), 3);
+        assert_eq!(calendar.busiest(), Some((date!(2024 - 03 - 04), 4)));
+    }
+
+    #[test]
+    fn levels() {
+        let calendar = Calendar::new(&[("2024-03-04".to_string(), 8)], date!(2024 - 03 - 04));
+
+        assert_eq!(calendar.level(0), 0);
+        assert_eq!(calendar.level(1), 1);
+        assert_eq!(calendar.level(4), 2);
+        assert_eq!(calendar.level(5), 3);
+        assert_eq!(calendar.level(8), 4);
+    }
+
+    #[test]
+    fn empty() {
+        let calendar = Calendar::new(&[], date!(2024 - 03 - 04));
+
+        assert_eq!(calendar.level(0), 0);
+        assert_eq!(calendar.busiest(), None);
+        assert_eq!(calendar.total(), 0);
+    }
+
+    #[test]
+    fn month_labels() {
+        let calendar = Calendar::new(&[], date!(2024 - 03 - 06));
+        let labels = calendar.month_labels();
+
+        assert_eq!(labels.chars().count(), WEEKS);
+        // the first week starts in March 2023, and April's first Monday is four weeks on
+        assert!(labels.starts_with("    Apr"));
+    }
+}
@@ -1,2 +1,6 @@
+pub mod aliases;
+pub mod calendar;
+pub mod reproducibility;
+pub mod search;
 pub mod sort;
 pub mod stats;
@@ -0,0 +1,323 @@
+use std::fmt;
+
+/// Something about a command that makes it hard to rerun elsewhere, or later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// An absolute path into somebody's home directory
+    UserPath(String),
+
+    /// A download piped straight into a shell, so what runs can change under you
+    PipeToShell,
+
+    /// A package installed without pinning its version
+    Unpinned { tool: &'static str, package: String },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::UserPath(path) => write!(f, "uses the user specific path {path}"),
+            Issue::PipeToShell => write!(f, "pipes a download into a shell"),
+            Issue::Unpinned { tool, package } => {
+                write!(
+                    f,
+                    "installs {package} with {tool}, without pinning a version"
+                )
+            }
+        }
+    }
+}
+
+const SHELLS: &[&str] = &[
+    "sh", "bash", "zsh", "dash", "ksh", "fish", "python", "python3",
+];
+
+const DOWNLOADERS: &[&str] = &["curl", "wget"];
+
+/// Everything in `command` that would make it hard to reproduce
+pub fn lint(command: &str) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = user_paths(command)
+        .into_iter()
+        .map(Issue::UserPath)
+        .collect();
+
+    if pipes_to_shell(command) {
+        issues.push(Issue::PipeToShell);
+    }
+
+    for words in simple_commands(command) {
+        issues.extend(unpinned(&words));
+    }
+
+    issues.dedup();
+    issues
+}
+
+// Home directories, up to and including the user's name. Relative paths, and `~`, are fine.
+fn user_paths(command: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+
+    for home in ["/home/", "/Users/"] {
+        for (i, _) in command.match_indices(home) {
+            // part of a longer path, such as /mnt/home/, is not a home directory
+            let preceded = command[..i]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '/' || c == '.' || c == '_');
+
+            let name: String = command[i + home.len()..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+                .collect();
+
+            if preceded || name.is_empty() {
+                continue;
+            }
+
+            let path = format!("{home}{name}");
+
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    paths
+}
+
+fn pipes_to_shell(command: &str) -> bool {
+    // curl https://example.com/install.sh | sh
+    let segments: Vec<Vec<&str>> = command.split('|').map(words).collect();
+
+    let piped = segments.windows(2).any(|pair| {
+        pair[0].iter().any(|w| DOWNLOADERS.contains(w))
+            && program(&pair[1]).is_some_and(|p| SHELLS.contains(&p))
+    });
+
+    // sh -c "$(curl https://example.com/install.sh)", or bash <(curl ...)
+    let substituted = simple_commands(command).iter().any(|words| {
+        program(words).is_some_and(|p| SHELLS.contains(&p))
+            && DOWNLOADERS
+                .iter()
+                .any(|d| command.contains(&format!("$({d}")) || command.contains(&format!("<({d}")))
+    });
+
+    piped || substituted
+}
+
+// Split on the operators that separate commands, and trim quotes from each word
+fn simple_commands(command: &str) -> Vec<Vec<&str>> {
+    command
+        .split(['|', ';', '&', '\n'])
+        .map(words)
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+fn words(command: &str) -> Vec<&str> {
+    command
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c| c == '"' || c == '\''))
+        .collect()
+}
+
+// Where the program being run is, skipping over sudo and environment variables
+fn program_start(words: &[&str]) -> Option<usize> {
+    words
+        .iter()
+        .position(|w| *w != "sudo" && *w != "env" && !w.contains('='))
+}
+
+fn program<'a>(words: &[&'a str]) -> Option<&'a str> {
+    program_start(words).map(|i| words[i])
+}
+
+fn unpinned(words: &[&str]) -> Vec<Issue> {
+    let Some(start) = program_start(words) else {
+        return Vec::new();
+    };
+    let mut words = &words[start..];
+
+    // python -m pip install ...
+    if words.len() > 2 && words[0].starts_with("python") && words[1] == "-m" {
+        words = &words[2..];
+    }
+
+    let (tool, args) = match words {
+        ["pip" | "pip3", "install", args @ ..] => ("pip", args),
+        ["pipx", "install", args @ ..] => ("pipx", args),
+        ["npm", "install" | "i" | "add", args @ ..] => ("npm", args),
+        ["pnpm", "install" | "i" | "add", args @ ..] => ("pnpm", args),
+        ["yarn", "add", args @ ..] => ("yarn", args),
+        ["cargo", "install", args @ ..] => ("cargo", args),
+        ["go", "install", args @ ..] => ("go", args),
+        ["gem", "install", args @ ..] => ("gem", args),
+        ["docker", "pull", args @ ..] => ("docker", args),
+        _ => return Vec::new(),
+    };
+
+    // Flags that pin everything installed by the command, and flags whose value is a file or
+    // source rather than a package
+    let (pins, takes_value): (&[&str], &[&str]) = match tool {
+        "pip" | "pipx" => (
+            &[],
+            &[
+                "-r",
+                "--requirement",
+                "-c",
+                "--constraint",
+                "-e",
+                "--editable",
+                "-i",
+                "--index-url",
+                "--extra-index-url",
+                "-t",
+                "--target",
+            ],
+        ),
+        "cargo" => (
+            &["--version", "--vers", "--tag", "--rev", "--path"],
+            &[
+                "--git",
+                "--branch",
+                "--root",
+                "--target",
+                "--features",
+                "-F",
+                "-j",
+            ],
+        ),
+        "gem" => (
+            &["-v", "--version"],
+            &["-i", "--install-dir", "-s", "--source"],
+        ),
+        "docker" => (&[], &["--platform"]),
+        _ => (&[], &["--registry", "--prefix"]),
+    };
+
+    if args.iter().any(|a| {
+        pins.iter()
+            .any(|p| a == p || a.starts_with(&format!("{p}=")))
+    }) {
+        return Vec::new();
+    }
+
+    let mut packages = Vec::new();
+    let mut skip = false;
+
+    for arg in args {
+        if std::mem::take(&mut skip) {
+            continue;
+        }
+
+        if arg.starts_with('-') {
+            skip = takes_value.contains(arg);
+            continue;
+        }
+
+        // local paths and archives aren't fetched by version
+        if arg.starts_with('.') || arg.starts_with('/') || arg.starts_with('~') {
+            continue;
+        }
+
+        let pinned = match tool {
+            "pip" | "pipx" => arg.contains("==") || arg.contains("://"),
+            "npm" | "pnpm" | "yarn" => {
+                // scoped packages start with an @ of their own
+                let name = arg.strip_prefix('@').unwrap_or(arg);
+                name.contains('@') && !name.ends_with("@latest")
+            }
+            "cargo" => arg.contains('@'),
+            "go" => arg.contains('@') && !arg.ends_with("@latest"),
+            "docker" => {
+                // a tag is after the last :, unless that : is part of a registry host's port
+                let image = arg.rsplit('/').next().unwrap_or(arg);
+                arg.contains('@') || (image.contains(':') && !image.ends_with(":latest"))
+            }
+            _ => false,
+        };
+
+        if !pinned {
+            packages.push(Issue::Unpinned {
+                tool,
+                package: (*arg).to_owned(),
+            });
+        }
+    }
+
+    packages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, Issue};
+
+    fn unpinned(tool: &'static str, package: &str) -> Issue {
+        Issue::Unpinned {
+            tool,
+            package: package.to_owned(),
+        }
+    }
+
+    #[test]
+    fn user_paths() {
+        assert_eq!(
+            lint("cp /home/ellie/.vimrc /Users/ellie/"),
+            vec![
+                Issue::UserPath("/home/ellie".to_owned()),
+                Issue::UserPath("/Users/ellie".to_owned())
+            ]
+        );
+
+        assert!(lint("cp ~/.vimrc ./dotfiles").is_empty());
+        assert!(lint("ls /mnt/home/backup").is_empty());
+    }
+
+    #[test]
+    fn pipe_to_shell() {
+        assert_eq!(
+            lint("curl -sSf https://sh.rustup.rs | sh -s -- -y"),
+            vec![Issue::PipeToShell]
+        );
+        assert_eq!(
+            lint("sudo wget -qO- https://example.com/install.sh | sudo bash"),
+            vec![Issue::PipeToShell]
+        );
+        assert_eq!(
+            lint(r#"sh -c "$(curl -fsSL https://example.com/install.sh)""#),
+            vec![Issue::PipeToShell]
+        );
+
+        assert!(lint("curl https://example.com/data.json | jq .").is_empty());
+    }
+
+    #[test]
+    fn version_pins() {
+        assert_eq!(
+            lint("pip install requests flask==3.0.0 -r requirements.txt"),
+            vec![unpinned("pip", "requests")]
+        );
+        assert_eq!(
+            lint("python3 -m pip install --upgrade pip"),
+            vec![unpinned("pip", "pip")]
+        );
+        assert_eq!(
+            lint("npm install -g @angular/cli typescript@5.4.5 && npm i"),
+            vec![unpinned("npm", "@angular/cli")]
+        );
+        assert_eq!(
+            lint("go install golang.org/x/tools/gopls@latest"),
+            vec![unpinned("go", "golang.org/x/tools/gopls@latest")]
+        );
+        assert_eq!(
+            lint("docker pull localhost:5000/postgres"),
+            vec![unpinned("docker", "localhost:5000/postgres")]
+        );
+
+        assert!(lint("cargo install ripgrep --version 14.1.0").is_empty());
+        assert!(lint("cargo install --path .").is_empty());
+        assert!(lint("gem install rails -v 7.1.3").is_empty());
+        assert!(lint("docker pull postgres:16").is_empty());
+        assert!(lint("pip install -e .").is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use std::ops::Range;
+
+use async_trait::async_trait;
+use atuin_client::{
+    database::{Context, Database},
+    history::History,
+    settings::{FilterMode, SearchMode},
+};
+use eyre::Result;
+
+pub mod db;
+pub mod skim;
+
+/// The default engine for each search mode
+pub fn engine(search_mode: SearchMode) -> Box<dyn SearchEngine> {
+    match search_mode {
+        SearchMode::Prefix => Box::new(db::Prefix) as Box<_>,
+        SearchMode::FullText => Box::new(db::FullText) as Box<_>,
+        SearchMode::Fuzzy => Box::new(db::Fuzzy) as Box<_>,
+        SearchMode::Skim => Box::new(skim::Search::new()) as Box<_>,
+    }
+}
+
+/// What the user is searching for, and where they are searching from
+#[derive(Clone, Copy)]
+pub struct SearchQuery<'a> {
+    pub input: &'a str,
+    pub filter_mode: FilterMode,
+    pub context: &'a Context,
+}
+
+#[async_trait]
+pub trait SearchEngine: Send + Sync + 'static {
+    /// Find history matching a non-empty query
+    async fn full_query(
+        &mut self,
+        query: &SearchQuery<'_>,
+        db: &mut dyn Database,
+    ) -> Result<Vec<History>>;
+
+    /// Find history matching the query. An empty query lists the most recent history.
+    async fn query(
+        &mut self,
+        query: &SearchQuery<'_>,
+        db: &mut dyn Database,
+    ) -> Result<Vec<History>> {
+        if query.input.is_empty() {
+            Ok(db
+                .list(&[query.filter_mode], query.context, Some(200), true, false)
+                .await?
+                .into_iter()
+                .collect::<Vec<_>>())
+        } else {
+            self.full_query(query, db).await
+        }
+    }
+
+    /// Order results by relevance to the query. By default they are left in the order the
+    /// engine found them.
+    fn rank(&self, _query: &SearchQuery<'_>, results: Vec<History>) -> Vec<History> {
+        results
+    }
+
+    /// The byte ranges of `command` matched by `input`, for highlighting
+    fn highlight(&self, input: &str, command: &str) -> Vec<Range<usize>>;
+}
+
+// Sort ranges, and join any that touch or overlap
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
@@ -0,0 +1,223 @@
+use std::{borrow::Cow, ops::Range};
+
+use async_trait::async_trait;
+use atuin_client::{
+    database::Database, database::OptFilters, history::History, settings::SearchMode,
+};
+use eyre::Result;
+
+use super::{merge_ranges, SearchEngine, SearchQuery};
+
+/// Match commands starting with the query
+pub struct Prefix;
+
+/// Match commands containing every term of the query
+pub struct FullText;
+
+/// Match commands containing the characters of every term of the query, in order
+pub struct Fuzzy;
+
+async fn search(
+    mode: SearchMode,
+    query: &SearchQuery<'_>,
+    db: &mut dyn Database,
+) -> Result<Vec<History>> {
+    Ok(db
+        .search(
+            mode,
+            query.filter_mode,
+            query.context,
+            query.input,
+            OptFilters {
+                limit: Some(200),
+                ..Default::default()
+            },
+        )
+        .await
+        // ignore errors as it may be caused by incomplete regex
+        .map_or(Vec::new(), |r| r.into_iter().collect()))
+}
+
+#[async_trait]
+impl SearchEngine for Prefix {
+    async fn full_query(
+        &mut self,
+        query: &SearchQuery<'_>,
+        db: &mut dyn Database,
+    ) -> Result<Vec<History>> {
+        search(SearchMode::Prefix, query, db).await
+    }
+
+    fn highlight(&self, input: &str, command: &str) -> Vec<Range<usize>> {
+        // LIKE is case insensitive, and '*' is a wildcard. Only the literal text up until the
+        // first wildcard is highlighted.
+        let prefix = input.split('*').next().unwrap_or_default();
+        let mut ranges = Vec::new();
+
+        if !prefix.is_empty()
+            && command.len() >= prefix.len()
+            && command.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+        {
+            ranges.push(0..prefix.len());
+        }
+
+        ranges
+    }
+}
+
+#[async_trait]
+impl SearchEngine for FullText {
+    async fn full_query(
+        &mut self,
+        query: &SearchQuery<'_>,
+        db: &mut dyn Database,
+    ) -> Result<Vec<History>> {
+        search(SearchMode::FullText, query, db).await
+    }
+
+    fn highlight(&self, input: &str, command: &str) -> Vec<Range<usize>> {
+        highlight_terms(input, command, false)
+    }
+}
+
+#[async_trait]
+impl SearchEngine for Fuzzy {
+    async fn full_query(
+        &mut self,
+        query: &SearchQuery<'_>,
+        db: &mut dyn Database,
+    ) -> Result<Vec<History>> {
+        search(SearchMode::Fuzzy, query, db).await
+    }
+
+    fn highlight(&self, input: &str, command: &str) -> Vec<Range<usize>> {
+        highlight_terms(input, command, true)
+    }
+}
+
+// Follows the query syntax of `Database::search`. Terms are matched smart case (case sensitive
+// only if they contain an uppercase character). Inverse terms and regexes don't highlight
+// anything.
+fn highlight_terms(input: &str, command: &str, fuzzy: bool) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut in_regex = false;
+
+    for term in input.split_whitespace() {
+        // regexes may contain spaces, so skip every part of one
+        if in_regex {
+            in_regex = !term.ends_with('/');
+            continue;
+        }
+
+        if let Some(regex) = term.strip_prefix("r/") {
+            in_regex = !regex.ends_with('/');
+            continue;
+        }
+
+        if term == "|" || term.starts_with('!') {
+            continue;
+        }
+
+        // ascii lowercasing keeps byte offsets the same, and matches what LIKE ignores
+        let haystack = if term.contains(char::is_uppercase) {
+            Cow::Borrowed(command)
+        } else {
+            Cow::Owned(command.to_ascii_lowercase())
+        };
+
+        if let Some(term) = term.strip_prefix('^') {
+            if !term.is_empty() && haystack.starts_with(term) {
+                ranges.push(0..term.len());
+            }
+        } else if let Some(term) = term.strip_suffix('$') {
+            if !term.is_empty() && haystack.ends_with(term) {
+                ranges.push(haystack.len() - term.len()..haystack.len());
+            }
+        } else if let Some(term) = term.strip_prefix('\'') {
+            ranges.extend(substrings(term, &haystack));
+        } else if fuzzy {
+            ranges.extend(subsequence(term, &haystack));
+        } else {
+            ranges.extend(substrings(term, &haystack));
+        }
+    }
+
+    merge_ranges(ranges)
+}
+
+// Every occurrence of each literal part of a wildcard term
+fn substrings(term: &str, haystack: &str) -> Vec<Range<usize>> {
+    term.split('*')
+        .filter(|part| !part.is_empty())
+        .flat_map(|part| {
+            haystack
+                .match_indices(part)
+                .map(|(i, m)| i..i + m.len())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// The leftmost match of the term's characters, in order. Nothing if they don't all match.
+fn subsequence(term: &str, haystack: &str) -> Vec<Range<usize>> {
+    let mut needle = term.chars().filter(|&c| c != '*').peekable();
+    let mut ranges = Vec::new();
+
+    for (i, c) in haystack.char_indices() {
+        match needle.peek() {
+            Some(&n) if n == c => {
+                needle.next();
+                ranges.push(i..i + c.len_utf8());
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    if needle.peek().is_none() {
+        ranges
+    } else {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FullText, Fuzzy, Prefix};
+    use crate::search::SearchEngine;
+
+    #[test]
+    fn highlight_prefix() {
+        assert_eq!(Prefix.highlight("GIT st", "git status"), vec![0..6]);
+        assert_eq!(Prefix.highlight("git*us", "git status"), vec![0..3]);
+        assert!(Prefix.highlight("status", "git status").is_empty());
+    }
+
+    #[test]
+    fn highlight_fulltext() {
+        assert_eq!(
+            FullText.highlight("git !push st", "git status git stash"),
+            vec![0..3, 4..6, 11..14, 15..17]
+        );
+
+        // smart case
+        assert!(FullText.highlight("Status", "git status").is_empty());
+        assert_eq!(FullText.highlight("status", "git Status"), vec![4..10]);
+
+        assert_eq!(
+            FullText.highlight("^git tus$", "git status"),
+            vec![0..3, 7..10]
+        );
+        assert!(FullText.highlight("r/git st.*/", "git status").is_empty());
+    }
+
+    #[test]
+    fn highlight_fuzzy() {
+        assert_eq!(
+            Fuzzy.highlight("gtst", "git status"),
+            vec![0..1, 2..3, 4..6]
+        );
+        assert_eq!(Fuzzy.highlight("'stat", "git status"), vec![4..8]);
+        assert!(Fuzzy.highlight("gitz", "git status").is_empty());
+    }
+}
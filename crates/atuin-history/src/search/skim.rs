@@ -1,20 +1,32 @@
-use std::path::Path;
+use std::{collections::HashSet, ops::Range, path::Path};
 
 use async_trait::async_trait;
-use atuin_client::{database::Database, history::History, settings::FilterMode};
+use atuin_client::{
+    database::{host_matches, take_query_filters, Database, OptFilters},
+    history::History,
+    settings::FilterMode,
+};
 use eyre::Result;
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use itertools::Itertools;
 use time::OffsetDateTime;
 use tokio::task::yield_now;
 
-use super::{SearchEngine, SearchState};
+use super::{merge_ranges, SearchEngine, SearchQuery};
 
+/// Fuzzy match with skim's algorithm, over all history held in memory. Results are ranked as they
+/// are found, by match score, frequency, recency and distance from the current directory.
 pub struct Search {
     all_history: Vec<(History, i32)>,
     engine: SkimMatcherV2,
 }
 
+impl Default for Search {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Search {
     pub fn new() -> Self {
         Search {
@@ -28,45 +40,104 @@ impl Search {
 impl SearchEngine for Search {
     async fn full_query(
         &mut self,
-        state: &SearchState,
+        query: &SearchQuery<'_>,
         db: &mut dyn Database,
     ) -> Result<Vec<History>> {
         if self.all_history.is_empty() {
             self.all_history = db.all_with_count().await.unwrap();
         }
 
-        Ok(fuzzy_search(&self.engine, state, &self.all_history).await)
+        let mut filters = OptFilters::default();
+        let input = take_query_filters(query.input, &mut filters);
+
+        // stars and tags can change during a search, so they aren't kept with the rest of history,
+        // and nor is which pane or container each run of a command was in
+        let listed = match query.filter_mode {
+            FilterMode::Starred => db.starred().await?,
+            FilterMode::Pane | FilterMode::Container => {
+                db.list(&[query.filter_mode], query.context, None, true, false)
+                    .await?
+            }
+            _ => Vec::new(),
+        };
+        let listed = listed.into_iter().map(|h| h.command).collect();
+
+        // history is held a command at a time, so a tag on any run of a command matches it
+        let mut tagged: Option<HashSet<String>> = None;
+        for tag in &filters.tags {
+            let commands: HashSet<String> = db
+                .tagged(tag)
+                .await?
+                .into_iter()
+                .map(|h| h.command)
+                .collect();
+
+            tagged = Some(match tagged {
+                Some(t) => t.intersection(&commands).cloned().collect(),
+                None => commands,
+            });
+        }
+
+        Ok(fuzzy_search(
+            &self.engine,
+            query,
+            &input,
+            &filters,
+            &self.all_history,
+            &listed,
+            tagged.as_ref(),
+        )
+        .await)
+    }
+
+    fn highlight(&self, input: &str, command: &str) -> Vec<Range<usize>> {
+        let Some((_, indices)) = self.engine.fuzzy_indices(command, input) else {
+            return Vec::new();
+        };
+
+        // skim gives us char indices, but we want bytes
+        let ranges = command
+            .char_indices()
+            .enumerate()
+            .filter(|(i, _)| indices.contains(i))
+            .map(|(_, (byte, c))| byte..byte + c.len_utf8())
+            .collect();
+
+        merge_ranges(ranges)
     }
 }
 
 async fn fuzzy_search(
     engine: &SkimMatcherV2,
-    state: &SearchState,
+    state: &SearchQuery<'_>,
+    query: &str,
+    filters: &OptFilters,
     all_history: &[(History, i32)],
+    listed: &HashSet<String>,
+    tagged: Option<&HashSet<String>>,
 ) -> Vec<History> {
     let mut set = Vec::with_capacity(200);
     let mut ranks = Vec::with_capacity(200);
-    let query = state.input.as_str();
     let now = OffsetDateTime::now_utc();
 
     for (i, (history, count)) in all_history.iter().enumerate() {
         if i % 256 == 0 {
             yield_now().await;
         }
-        let context = &state.context;
+        let context = state.context;
         let git_root = context
             .git_root
             .as_ref()
             .and_then(|git_root| git_root.to_str())
             .unwrap_or(&context.cwd);
         match state.filter_mode {
-            FilterMode::Global => {}
+            FilterMode::Global | FilterMode::Channel => {}
             // we aggregate host by ',' separating them
             FilterMode::Host
                 if history
                     .hostname
                     .split(',')
-                    .contains(&context.hostname.as_str()) => {}
+                    .any(|hostname| host_matches(&context.hostname, hostname)) => {}
             // we aggregate session by concattenating them.
             // sessions are 32 byte simple uuid formats
             FilterMode::Session
@@ -78,8 +149,31 @@ async fn fuzzy_search(
             // we aggregate directory by ':' separating them
             FilterMode::Directory if history.cwd.split(':').contains(&context.cwd.as_str()) => {}
             FilterMode::Workspace if history.cwd.split(':').contains(&git_root) => {}
+            FilterMode::Namespace
+                if history
+                    .namespace
+                    .split(',')
+                    .contains(&context.namespace.as_str()) => {}
+            FilterMode::Starred | FilterMode::Pane | FilterMode::Container
+                if listed.contains(&history.command) => {}
             _ => continue,
         }
+        if tagged.is_some_and(|tagged| !tagged.contains(&history.command)) {
+            continue;
+        }
+        // we aggregate branch by ',' separating them
+        if let Some(branch) = &filters.branch {
+            if !history.branch.split(',').contains(&branch.as_str()) {
+                continue;
+            }
+        }
+        if !filters
+            .env
+            .iter()
+            .all(|(name, value)| history.env.get(name) == Some(value))
+        {
+            continue;
+        }
         #[allow(clippy::cast_lossless, clippy::cast_precision_loss)]
         if let Some((score, indices)) = engine.fuzzy_indices(&history.command, query) {
             let begin = indices.first().copied().unwrap_or_default();
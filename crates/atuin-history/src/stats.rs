@@ -10,6 +10,9 @@ use atuin_client::{history::History, settings::Settings, theme::Meaning, theme::
 pub struct Stats {
     pub total_commands: usize,
     pub unique_commands: usize,
+    /// Commands typed, then cancelled at the prompt rather than run
+    #[serde(default)]
+    pub cancelled_commands: usize,
     pub top: Vec<(Vec<String>, usize)>,
 }
 
@@ -182,6 +185,10 @@ pub fn pretty_print(stats: Stats, ngram_size: usize, theme: &Theme) {
     }
     println!("Total commands:   {}", stats.total_commands);
     println!("Unique commands:  {}", stats.unique_commands);
+
+    if stats.cancelled_commands > 0 {
+        println!("Cancelled:        {}", stats.cancelled_commands);
+    }
 }
 
 pub fn compute(
@@ -190,23 +197,41 @@ pub fn compute(
     count: usize,
     ngram_size: usize,
 ) -> Option<Stats> {
-    let mut commands = HashSet::<&str>::with_capacity(history.len());
+    compute_from_counts(
+        settings,
+        history.iter().map(|h| (h.command.as_str(), 1)),
+        count,
+        ngram_size,
+    )
+}
+
+/// Compute stats from commands and the number of times each was run, such as the output of
+/// `Database::top_commands`. This avoids loading every history entry to count them.
+pub fn compute_from_counts<'a>(
+    settings: &Settings,
+    commands_run: impl IntoIterator<Item = (&'a str, usize)>,
+    count: usize,
+    ngram_size: usize,
+) -> Option<Stats> {
+    let commands_run = commands_run.into_iter();
+    let (size, _) = commands_run.size_hint();
+    let mut commands = HashSet::<&str>::with_capacity(size);
     let mut total_unignored = 0;
-    let mut prefixes = HashMap::<Vec<&str>, usize>::with_capacity(history.len());
+    let mut prefixes = HashMap::<Vec<&str>, usize>::with_capacity(size);
 
-    for i in history {
+    for (command, runs) in commands_run {
         // just in case it somehow has a leading tab or space or something (legacy atuin didn't ignore space prefixes)
-        let command = i.command.trim();
+        let command = command.trim();
         let prefix = interesting_command(settings, command);
 
         if settings.stats.ignored_commands.iter().any(|c| c == prefix) {
             continue;
         }
 
-        total_unignored += 1;
+        total_unignored += runs;
         commands.insert(command);
 
-        split_at_pipe(i.command.trim())
+        split_at_pipe(command)
             .iter()
             .map(|l| {
                 let command = l.trim();
@@ -218,7 +243,7 @@ pub fn compute(
             .for_each(|w| {
                 *prefixes
                     .entry(w.iter().map(|c| interesting_command(settings, c)).collect())
-                    .or_default() += 1;
+                    .or_default() += runs;
             });
     }
 
@@ -235,6 +260,7 @@ pub fn compute(
     Some(Stats {
         unique_commands: unique,
         total_commands: total_unignored,
+        cancelled_commands: 0,
         top: top
             .into_iter()
             .map(|t| (t.0.into_iter().map(|s| s.to_string()).collect(), t.1))
@@ -248,7 +274,7 @@ mod tests {
     use atuin_client::settings::Settings;
     use time::OffsetDateTime;
 
-    use super::compute;
+    use super::{compute, compute_from_counts};
     use super::{interesting_command, split_at_pipe};
 
     #[test]
@@ -274,6 +300,40 @@ mod tests {
         assert_eq!(stats.unique_commands, 1);
     }
 
+    #[test]
+    fn counted_commands() {
+        let settings = Settings::utc();
+
+        let history: Vec<History> = ["git status", "git push", "git status", "ls | wc -l"]
+            .into_iter()
+            .map(|c| {
+                History::import()
+                    .timestamp(OffsetDateTime::now_utc())
+                    .command(c)
+                    .build()
+                    .into()
+            })
+            .collect();
+
+        let stats = compute(&settings, &history, 10, 1).expect("failed to compute stats");
+        let counted = compute_from_counts(
+            &settings,
+            [("git status", 2), ("git push", 1), ("ls | wc -l", 1)],
+            10,
+            1,
+        )
+        .expect("failed to compute stats");
+
+        assert_eq!(counted.total_commands, stats.total_commands);
+        assert_eq!(counted.unique_commands, stats.unique_commands);
+
+        let mut top = stats.top;
+        let mut counted_top = counted.top;
+        top.sort();
+        counted_top.sort();
+        assert_eq!(counted_top, top);
+    }
+
     #[test]
     fn interesting_commands() {
         let settings = Settings::utc();
@@ -11,10 +11,12 @@ use std::{
 
 use self::{
     calendar::{TimePeriod, TimePeriodInfo},
-    models::{History, NewHistory, NewSession, NewUser, Session, User},
+    models::{
+        History, NewHistory, NewSession, NewUser, RevokedHost, Session, User, UserStats, UserTotp,
+    },
 };
 use async_trait::async_trait;
-use atuin_common::record::{EncryptedData, HostId, Record, RecordIdx, RecordStatus};
+use atuin_common::record::{EncryptedData, HostId, Record, RecordDigest, RecordIdx, RecordStatus};
 use serde::{de::DeserializeOwned, Serialize};
 use time::{Date, Duration, Month, OffsetDateTime, Time, UtcOffset};
 use tracing::instrument;
@@ -49,6 +51,8 @@ pub trait Database: Sized + Clone + Send + Sync + 'static {
     async fn get_session(&self, token: &str) -> DbResult<Session>;
     async fn get_session_user(&self, token: &str) -> DbResult<User>;
     async fn add_session(&self, session: &NewSession) -> DbResult<()>;
+    /// Log the user out everywhere, leaving only the new session
+    async fn replace_sessions(&self, session: &NewSession) -> DbResult<()>;
 
     async fn get_user(&self, username: &str) -> DbResult<User>;
     async fn get_user_session(&self, u: &User) -> DbResult<Session>;
@@ -60,6 +64,18 @@ pub trait Database: Sized + Clone + Send + Sync + 'static {
 
     async fn update_user_password(&self, u: &User) -> DbResult<()>;
 
+    async fn user_totp(&self, u: &User) -> DbResult<Option<UserTotp>>;
+    /// Start enrolling in two-factor login with a new secret, replacing any unconfirmed one
+    async fn set_user_totp(&self, u: &User, secret: &str) -> DbResult<()>;
+    async fn enable_user_totp(&self, u: &User) -> DbResult<()>;
+    /// Accept a code for this time step, unless one for it or a later step already has been
+    async fn use_totp_step(&self, u: &User, step: i64) -> DbResult<bool>;
+    async fn delete_user_totp(&self, u: &User) -> DbResult<()>;
+
+    /// Every user and how much they store, for admins
+    async fn list_users(&self) -> DbResult<Vec<UserStats>>;
+    async fn user_stats(&self, u: &User) -> DbResult<UserStats>;
+
     async fn total_history(&self) -> DbResult<i64>;
     async fn count_history(&self, user: &User) -> DbResult<i64>;
     async fn count_history_cached(&self, user: &User) -> DbResult<i64>;
@@ -69,6 +85,9 @@ pub trait Database: Sized + Clone + Send + Sync + 'static {
     async fn deleted_history(&self, user: &User) -> DbResult<Vec<String>>;
     async fn delete_store(&self, user: &User) -> DbResult<()>;
 
+    async fn revoke_host(&self, user: &User, host: HostId, wipe_key: bool) -> DbResult<()>;
+    async fn revoked_host(&self, user: &User, host: HostId) -> DbResult<Option<RevokedHost>>;
+
     async fn add_records(&self, user: &User, record: &[Record<EncryptedData>]) -> DbResult<()>;
     async fn next_records(
         &self,
@@ -82,6 +101,9 @@ pub trait Database: Sized + Clone + Send + Sync + 'static {
     // Return the tail record ID for each store, so (HostID, Tag, TailRecordID)
     async fn status(&self, user: &User) -> DbResult<RecordStatus>;
 
+    /// The count and last record of each store, for clients to check they agree with us
+    async fn digest(&self, user: &User) -> DbResult<RecordDigest>;
+
     async fn count_history_range(&self, user: &User, range: Range<OffsetDateTime>)
         -> DbResult<i64>;
 
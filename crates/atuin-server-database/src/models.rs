@@ -1,3 +1,4 @@
+use atuin_common::record::HostId;
 use time::OffsetDateTime;
 
 pub struct History {
@@ -27,6 +28,7 @@ pub struct NewHistory {
     pub data: String,
 }
 
+#[derive(Clone)]
 pub struct User {
     pub id: i64,
     pub username: String,
@@ -51,3 +53,43 @@ pub struct NewSession {
     pub user_id: i64,
     pub token: String,
 }
+
+pub struct UserTotp {
+    pub user_id: i64,
+
+    /// The shared secret, base32 encoded
+    pub secret: String,
+
+    /// When enrolment was confirmed with a code. Until then, logging in doesn't need one.
+    pub enabled: Option<OffsetDateTime>,
+
+    /// The last time step a code was accepted for, so that no code is used twice
+    pub last_step: i64,
+}
+
+pub struct RevokedHost {
+    pub user_id: i64,
+    pub host: HostId,
+
+    /// Whether the host should delete its key, as well as stop syncing
+    pub wipe_key: bool,
+    pub revoked_at: OffsetDateTime,
+}
+
+/// How much a user stores, for admins
+pub struct UserStats {
+    pub id: i64,
+    pub username: String,
+    pub email: String,
+    pub created_at: OffsetDateTime,
+    pub verified: Option<OffsetDateTime>,
+
+    /// Entries in the old history table
+    pub history: i64,
+    /// Records in the record store
+    pub records: i64,
+    /// Bytes of encrypted data, across both
+    pub storage: i64,
+    /// When they last uploaded anything
+    pub last_sync: Option<OffsetDateTime>,
+}
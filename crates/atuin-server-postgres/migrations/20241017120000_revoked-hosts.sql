@@ -0,0 +1,11 @@
+-- Hosts that may no longer sync. A stolen or lost machine can be cut off, without having to
+-- rotate the account's key or password on every other device.
+create table revoked_hosts(
+  id bigserial primary key,
+  user_id bigint not null references users(id),
+  host uuid not null,
+  wipe_key boolean not null default false,
+  revoked_at timestamp with time zone not null default (current_timestamp at time zone 'utc'),
+
+  unique(user_id, host)
+);
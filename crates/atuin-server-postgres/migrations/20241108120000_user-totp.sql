@@ -0,0 +1,7 @@
+-- Two-factor login. The secret has to be kept as it is, to check codes against.
+create table user_totp(
+  user_id bigint primary key references users(id),
+  secret text not null,
+  enabled_at timestamp with time zone,
+  last_step bigint not null default 0
+);
@@ -3,9 +3,13 @@ use std::fmt::Debug;
 use std::ops::Range;
 
 use async_trait::async_trait;
-use atuin_common::record::{EncryptedData, HostId, Record, RecordIdx, RecordStatus};
+use atuin_common::record::{
+    DigestEntry, EncryptedData, HostId, Record, RecordDigest, RecordId, RecordIdx, RecordStatus,
+};
 use atuin_common::utils::crypto_random_string;
-use atuin_server_database::models::{History, NewHistory, NewSession, NewUser, Session, User};
+use atuin_server_database::models::{
+    History, NewHistory, NewSession, NewUser, RevokedHost, Session, User, UserStats, UserTotp,
+};
 use atuin_server_database::{Database, DbError, DbResult};
 use futures_util::TryStreamExt;
 use metrics::counter;
@@ -16,10 +20,28 @@ use sqlx::Row;
 use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
 use tracing::{instrument, trace};
 use uuid::Uuid;
-use wrappers::{DbHistory, DbRecord, DbSession, DbUser};
+use wrappers::{DbHistory, DbRecord, DbSession, DbUser, DbUserStats};
 
 mod wrappers;
 
+/// Users and how much they store. Both tables have an index on user_id.
+const USER_STATS_QUERY: &str = "select
+        users.id, users.username, users.email, users.created_at, users.verified_at,
+        coalesce(history.count, 0) as history,
+        coalesce(store.count, 0) as records,
+        coalesce(history.bytes, 0) + coalesce(store.bytes, 0) as storage,
+        greatest(history.last, store.last) as last_sync
+    from users
+    left join lateral (
+        select count(1) as count, sum(length(data))::bigint as bytes, max(created_at) as last
+        from history where history.user_id = users.id
+    ) history on true
+    left join lateral (
+        select count(1) as count, sum(length(data) + length(cek))::bigint as bytes,
+            max(created_at) as last
+        from store where store.user_id = users.id
+    ) store on true";
+
 const MIN_PG_VERSION: u32 = 14;
 
 #[derive(Clone)]
@@ -268,6 +290,45 @@ impl Database for Postgres {
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn revoke_host(&self, user: &User, host: HostId, wipe_key: bool) -> DbResult<()> {
+        // revoking again only ever escalates to wiping the key
+        sqlx::query(
+            "insert into revoked_hosts (user_id, host, wipe_key) values ($1, $2, $3)
+            on conflict (user_id, host) do update
+            set wipe_key = revoked_hosts.wipe_key or excluded.wipe_key",
+        )
+        .bind(user.id)
+        .bind(host.0)
+        .bind(wipe_key)
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn revoked_host(&self, user: &User, host: HostId) -> DbResult<Option<RevokedHost>> {
+        let res: Option<(bool, OffsetDateTime)> = sqlx::query_as(
+            "select wipe_key, revoked_at from revoked_hosts
+            where user_id = $1
+            and host = $2",
+        )
+        .bind(user.id)
+        .bind(host.0)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.map(|(wipe_key, revoked_at)| RevokedHost {
+            user_id: user.id,
+            host,
+            wipe_key,
+            revoked_at,
+        }))
+    }
+
     async fn delete_history(&self, user: &User, id: String) -> DbResult<()> {
         sqlx::query(
             "update history
@@ -421,6 +482,18 @@ impl Database for Postgres {
             .await
             .map_err(fix_error)?;
 
+        sqlx::query("delete from revoked_hosts where user_id = $1")
+            .bind(u.id)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        sqlx::query("delete from user_totp where user_id = $1")
+            .bind(u.id)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
         sqlx::query("delete from total_history_count_user where user_id = $1")
             .bind(u.id)
             .execute(&self.pool)
@@ -436,6 +509,25 @@ impl Database for Postgres {
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn list_users(&self) -> DbResult<Vec<UserStats>> {
+        sqlx::query_as(&format!("{USER_STATS_QUERY} order by users.id"))
+            .fetch_all(&self.pool)
+            .await
+            .map_err(fix_error)
+            .map(|users| users.into_iter().map(|DbUserStats(u)| u).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn user_stats(&self, user: &User) -> DbResult<UserStats> {
+        sqlx::query_as(&format!("{USER_STATS_QUERY} where users.id = $1"))
+            .bind(user.id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(fix_error)
+            .map(|DbUserStats(u)| u)
+    }
+
     #[instrument(skip_all)]
     async fn update_user_password(&self, user: &User) -> DbResult<()> {
         sqlx::query(
@@ -452,6 +544,85 @@ impl Database for Postgres {
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn user_totp(&self, user: &User) -> DbResult<Option<UserTotp>> {
+        let res: Option<(String, Option<OffsetDateTime>, i64)> = sqlx::query_as(
+            "select secret, enabled_at, last_step from user_totp
+            where user_id = $1",
+        )
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.map(|(secret, enabled, last_step)| UserTotp {
+            user_id: user.id,
+            secret,
+            enabled,
+            last_step,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn set_user_totp(&self, user: &User, secret: &str) -> DbResult<()> {
+        sqlx::query(
+            "insert into user_totp (user_id, secret) values ($1, $2)
+            on conflict (user_id) do update
+            set secret = excluded.secret, enabled_at = null, last_step = 0",
+        )
+        .bind(user.id)
+        .bind(secret)
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn enable_user_totp(&self, user: &User) -> DbResult<()> {
+        sqlx::query(
+            "update user_totp
+            set enabled_at = $2
+            where user_id = $1",
+        )
+        .bind(user.id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn use_totp_step(&self, user: &User, step: i64) -> DbResult<bool> {
+        let res = sqlx::query(
+            "update user_totp
+            set last_step = $2
+            where user_id = $1
+            and last_step < $2",
+        )
+        .bind(user.id)
+        .bind(step)
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_user_totp(&self, user: &User) -> DbResult<()> {
+        sqlx::query("delete from user_totp where user_id = $1")
+            .bind(user.id)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     async fn add_user(&self, user: &NewUser) -> DbResult<i64> {
         let email: &str = &user.email;
@@ -492,6 +663,28 @@ impl Database for Postgres {
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn replace_sessions(&self, session: &NewSession) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.map_err(fix_error)?;
+
+        sqlx::query("delete from sessions where user_id = $1")
+            .bind(session.user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+
+        sqlx::query("insert into sessions (user_id, token) values ($1, $2)")
+            .bind(session.user_id)
+            .bind(&session.token)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+
+        tx.commit().await.map_err(fix_error)?;
+
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     async fn get_user_session(&self, u: &User) -> DbResult<Session> {
         sqlx::query_as("select id, user_id, token from sessions where user_id = $1")
@@ -683,6 +876,34 @@ impl Database for Postgres {
 
         Ok(status)
     }
+
+    #[instrument(skip_all)]
+    async fn digest(&self, user: &User) -> DbResult<RecordDigest> {
+        let res: Vec<(Uuid, String, i64, i64, Uuid)> = sqlx::query_as(
+            "select distinct on (host, tag)
+                host, tag, count(1) over (partition by host, tag), idx, client_id
+            from store
+            where user_id = $1
+            order by host, tag, idx desc",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        let entries = res
+            .into_iter()
+            .map(|(host, tag, count, idx, last)| DigestEntry {
+                host: HostId(host),
+                tag,
+                count: count as u64,
+                idx: idx as u64,
+                last: RecordId(last),
+            })
+            .collect();
+
+        Ok(RecordDigest::new(entries))
+    }
 }
 
 fn into_utc(x: OffsetDateTime) -> PrimitiveDateTime {
@@ -1,6 +1,6 @@
 use ::sqlx::{FromRow, Result};
 use atuin_common::record::{EncryptedData, Host, Record};
-use atuin_server_database::models::{History, Session, User};
+use atuin_server_database::models::{History, Session, User, UserStats};
 use sqlx::{postgres::PgRow, Row};
 use time::PrimitiveDateTime;
 
@@ -8,6 +8,7 @@ pub struct DbUser(pub User);
 pub struct DbSession(pub Session);
 pub struct DbHistory(pub History);
 pub struct DbRecord(pub Record<EncryptedData>);
+pub struct DbUserStats(pub UserStats);
 
 impl<'a> FromRow<'a, PgRow> for DbUser {
     fn from_row(row: &'a PgRow) -> Result<Self> {
@@ -21,6 +22,26 @@ impl<'a> FromRow<'a, PgRow> for DbUser {
     }
 }
 
+impl<'a> FromRow<'a, PgRow> for DbUserStats {
+    fn from_row(row: &'a PgRow) -> Result<Self> {
+        Ok(Self(UserStats {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            email: row.try_get("email")?,
+            created_at: row
+                .try_get::<PrimitiveDateTime, _>("created_at")?
+                .assume_utc(),
+            verified: row.try_get("verified_at")?,
+            history: row.try_get("history")?,
+            records: row.try_get("records")?,
+            storage: row.try_get("storage")?,
+            last_sync: row
+                .try_get::<Option<PrimitiveDateTime>, _>("last_sync")?
+                .map(PrimitiveDateTime::assume_utc),
+        }))
+    }
+}
+
 impl<'a> ::sqlx::FromRow<'a, PgRow> for DbSession {
     fn from_row(row: &'a PgRow) -> ::sqlx::Result<Self> {
         Ok(Self(Session {
@@ -0,0 +1,66 @@
+-- Everything the server stores, for servers small enough to not need postgres. Times are unix
+-- nanoseconds, so that they sort and compare as numbers.
+
+create table users (
+	id integer primary key autoincrement,
+	username text not null unique collate nocase,
+	email text not null unique collate nocase,
+	password text not null,
+	created_at integer not null,
+	verified_at integer
+);
+
+create table sessions (
+	id integer primary key autoincrement,
+	user_id integer not null,
+	token text not null unique
+);
+
+create index sessions_user_id on sessions(user_id);
+
+create table user_verification_token (
+	id integer primary key autoincrement,
+	user_id integer not null unique,
+	token text not null,
+	valid_until integer not null
+);
+
+-- the history table from before the record store, for clients that still sync with it
+create table history (
+	id integer primary key autoincrement,
+	client_id text not null unique,
+	user_id integer not null,
+	hostname text not null,
+	timestamp integer not null,
+	data text not null,
+	created_at integer not null,
+	deleted_at integer
+);
+
+create index history_user_timestamp on history(user_id, timestamp);
+
+create table store (
+	id blob primary key,
+	client_id blob not null,
+	host blob not null,
+	idx integer not null,
+	timestamp integer not null,
+	version text not null,
+	tag text not null,
+	data text not null,
+	cek text not null,
+	user_id integer not null,
+	created_at integer not null
+);
+
+create unique index store_record on store(user_id, host, tag, idx);
+
+create table revoked_hosts (
+	id integer primary key autoincrement,
+	user_id integer not null,
+	host blob not null,
+	wipe_key boolean not null default false,
+	revoked_at integer not null,
+
+	unique(user_id, host)
+);
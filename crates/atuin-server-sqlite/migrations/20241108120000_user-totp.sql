@@ -0,0 +1,7 @@
+-- Two-factor login. The secret has to be kept as it is, to check codes against.
+create table user_totp (
+	user_id integer primary key,
+	secret text not null,
+	enabled_at integer,
+	last_step integer not null default 0
+);
@@ -0,0 +1,897 @@
+//! The server's storage in a single SQLite file, for servers that don't need postgres
+
+use std::fmt::Debug;
+use std::ops::Range;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use atuin_common::record::{
+    DigestEntry, EncryptedData, HostId, Record, RecordDigest, RecordId, RecordIdx, RecordStatus,
+};
+use atuin_common::utils::crypto_random_string;
+use atuin_server_database::models::{
+    History, NewHistory, NewSession, NewUser, RevokedHost, Session, User, UserStats, UserTotp,
+};
+use atuin_server_database::{Database, DbError, DbResult};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use time::OffsetDateTime;
+use tracing::{instrument, trace};
+use uuid::Uuid;
+use wrappers::{from_nanos, DbHistory, DbRecord, DbSession, DbUser, DbUserStats};
+
+mod wrappers;
+
+/// How long a verification token is good for
+const TOKEN_VALID_MINUTES: i64 = 15;
+
+/// Users and how much they store
+const USER_STATS_QUERY: &str = "select
+        users.id, users.username, users.email, users.created_at, users.verified_at,
+        (select count(1) from history where history.user_id = users.id) as history,
+        (select count(1) from store where store.user_id = users.id) as records,
+        coalesce((select sum(length(data)) from history where history.user_id = users.id), 0)
+            + coalesce(
+                (select sum(length(data) + length(cek)) from store where store.user_id = users.id),
+                0
+            ) as storage,
+        nullif(max(
+            coalesce((select max(created_at) from history where history.user_id = users.id), 0),
+            coalesce((select max(created_at) from store where store.user_id = users.id), 0)
+        ), 0) as last_sync
+    from users";
+
+#[derive(Clone)]
+pub struct Sqlite {
+    pool: sqlx::Pool<sqlx::sqlite::Sqlite>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SqliteSettings {
+    /// eg sqlite:///var/lib/atuin/atuin.db
+    pub db_uri: String,
+}
+
+/// Whether a db_uri is for this backend, rather than postgres
+pub fn is_sqlite_uri(uri: &str) -> bool {
+    uri.starts_with("sqlite:")
+}
+
+fn fix_error(error: sqlx::Error) -> DbError {
+    match error {
+        sqlx::Error::RowNotFound => DbError::NotFound,
+        error => DbError::Other(error.into()),
+    }
+}
+
+fn nanos(time: OffsetDateTime) -> i64 {
+    // i64 nanoseconds last until 2262
+    time.unix_timestamp_nanos() as i64
+}
+
+#[async_trait]
+impl Database for Sqlite {
+    type Settings = SqliteSettings;
+    async fn new(settings: &SqliteSettings) -> DbResult<Self> {
+        let opts = SqliteConnectOptions::from_str(&settings.db_uri)
+            .map_err(fix_error)?
+            .journal_mode(SqliteJournalMode::Wal)
+            .create_if_missing(true);
+
+        // every connection to an in-memory database gets its own, so only ever make one
+        let in_memory = settings.db_uri.contains(":memory:");
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(if in_memory { 1 } else { 10 })
+            .connect_with(opts)
+            .await
+            .map_err(fix_error)?;
+
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .map_err(|error| DbError::Other(error.into()))?;
+
+        Ok(Self { pool })
+    }
+
+    #[instrument(skip_all)]
+    async fn get_session(&self, token: &str) -> DbResult<Session> {
+        sqlx::query_as("select id, user_id, token from sessions where token = ?1")
+            .bind(token)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(fix_error)
+            .map(|DbSession(session)| session)
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user(&self, username: &str) -> DbResult<User> {
+        sqlx::query_as(
+            "select id, username, email, password, verified_at from users where username = ?1",
+        )
+        .bind(username)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(fix_error)
+        .map(|DbUser(user)| user)
+    }
+
+    #[instrument(skip_all)]
+    async fn user_verified(&self, id: i64) -> DbResult<bool> {
+        let res: (bool,) =
+            sqlx::query_as("select verified_at is not null from users where id = ?1")
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(fix_error)?;
+
+        Ok(res.0)
+    }
+
+    #[instrument(skip_all)]
+    async fn verify_user(&self, id: i64) -> DbResult<()> {
+        sqlx::query("update users set verified_at = ?2 where id = ?1")
+            .bind(id)
+            .bind(nanos(OffsetDateTime::now_utc()))
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    /// A valid verification token for the user, replacing any that has expired
+    #[instrument(skip_all)]
+    async fn user_verification_token(&self, id: i64) -> DbResult<String> {
+        let now = OffsetDateTime::now_utc();
+
+        let token: Option<(String, i64)> = sqlx::query_as(
+            "select token, valid_until from user_verification_token where user_id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        if let Some((token, valid_until)) = token {
+            trace!("Token for user {id} valid until {valid_until}");
+
+            if from_nanos(valid_until).map_err(fix_error)? > now {
+                return Ok(token);
+            }
+        }
+
+        let token = crypto_random_string::<24>();
+
+        sqlx::query(
+            "insert into user_verification_token (user_id, token, valid_until) values (?1, ?2, ?3)
+            on conflict (user_id) do update set token = excluded.token, valid_until = excluded.valid_until",
+        )
+        .bind(id)
+        .bind(&token)
+        .bind(nanos(now + time::Duration::minutes(TOKEN_VALID_MINUTES)))
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(token)
+    }
+
+    #[instrument(skip_all)]
+    async fn get_session_user(&self, token: &str) -> DbResult<User> {
+        sqlx::query_as(
+            "select users.id, users.username, users.email, users.password, users.verified_at
+            from users
+            inner join sessions
+            on users.id = sessions.user_id
+            and sessions.token = ?1",
+        )
+        .bind(token)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(fix_error)
+        .map(|DbUser(user)| user)
+    }
+
+    #[instrument(skip_all)]
+    async fn count_history(&self, user: &User) -> DbResult<i64> {
+        let res: (i64,) = sqlx::query_as("select count(1) from history where user_id = ?1")
+            .bind(user.id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(res.0)
+    }
+
+    #[instrument(skip_all)]
+    async fn total_history(&self) -> DbResult<i64> {
+        let res: (i64,) = sqlx::query_as("select count(1) from history")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(res.0)
+    }
+
+    /// Counting is cheap enough at the sizes SQLite is for, so there's no cache
+    #[instrument(skip_all)]
+    async fn count_history_cached(&self, user: &User) -> DbResult<i64> {
+        self.count_history(user).await
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_store(&self, user: &User) -> DbResult<()> {
+        sqlx::query("delete from store where user_id = ?1")
+            .bind(user.id)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke_host(&self, user: &User, host: HostId, wipe_key: bool) -> DbResult<()> {
+        // revoking again only ever escalates to wiping the key
+        sqlx::query(
+            "insert into revoked_hosts (user_id, host, wipe_key, revoked_at) values (?1, ?2, ?3, ?4)
+            on conflict (user_id, host) do update
+            set wipe_key = revoked_hosts.wipe_key or excluded.wipe_key",
+        )
+        .bind(user.id)
+        .bind(host.0)
+        .bind(wipe_key)
+        .bind(nanos(OffsetDateTime::now_utc()))
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn revoked_host(&self, user: &User, host: HostId) -> DbResult<Option<RevokedHost>> {
+        let res: Option<(bool, i64)> = sqlx::query_as(
+            "select wipe_key, revoked_at from revoked_hosts
+            where user_id = ?1
+            and host = ?2",
+        )
+        .bind(user.id)
+        .bind(host.0)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        let Some((wipe_key, revoked_at)) = res else {
+            return Ok(None);
+        };
+
+        Ok(Some(RevokedHost {
+            user_id: user.id,
+            host,
+            wipe_key,
+            revoked_at: from_nanos(revoked_at).map_err(fix_error)?,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_history(&self, user: &User, id: String) -> DbResult<()> {
+        sqlx::query(
+            "update history
+            set deleted_at = ?3
+            where user_id = ?1
+            and client_id = ?2
+            and deleted_at is null", // don't just keep setting it
+        )
+        .bind(user.id)
+        .bind(id)
+        .bind(nanos(OffsetDateTime::now_utc()))
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn deleted_history(&self, user: &User) -> DbResult<Vec<String>> {
+        let res: Vec<(String,)> = sqlx::query_as(
+            "select client_id from history
+            where user_id = ?1
+            and deleted_at is not null",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.into_iter().map(|(id,)| id).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn count_history_range(
+        &self,
+        user: &User,
+        range: Range<OffsetDateTime>,
+    ) -> DbResult<i64> {
+        let res: (i64,) = sqlx::query_as(
+            "select count(1) from history
+            where user_id = ?1
+            and timestamp >= ?2
+            and timestamp < ?3",
+        )
+        .bind(user.id)
+        .bind(nanos(range.start))
+        .bind(nanos(range.end))
+        .fetch_one(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.0)
+    }
+
+    #[instrument(skip_all)]
+    async fn list_history(
+        &self,
+        user: &User,
+        created_after: OffsetDateTime,
+        since: OffsetDateTime,
+        host: &str,
+        page_size: i64,
+    ) -> DbResult<Vec<History>> {
+        let res: Vec<DbHistory> = sqlx::query_as(
+            "select id, client_id, user_id, hostname, timestamp, data, created_at from history
+            where user_id = ?1
+            and hostname != ?2
+            and created_at >= ?3
+            and timestamp >= ?4
+            order by timestamp asc
+            limit ?5",
+        )
+        .bind(user.id)
+        .bind(host)
+        .bind(nanos(created_after))
+        .bind(nanos(since))
+        .bind(page_size)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.into_iter().map(|DbHistory(h)| h).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn add_history(&self, history: &[NewHistory]) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.map_err(fix_error)?;
+        let now = nanos(OffsetDateTime::now_utc());
+
+        for i in history {
+            sqlx::query(
+                "insert into history
+                    (client_id, user_id, hostname, timestamp, data, created_at)
+                values (?1, ?2, ?3, ?4, ?5, ?6)
+                on conflict do nothing",
+            )
+            .bind(&i.client_id)
+            .bind(i.user_id)
+            .bind(&i.hostname)
+            .bind(nanos(i.timestamp))
+            .bind(&i.data)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+        }
+
+        tx.commit().await.map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_user(&self, u: &User) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.map_err(fix_error)?;
+
+        for table in [
+            "sessions",
+            "history",
+            "store",
+            "user_verification_token",
+            "revoked_hosts",
+            "user_totp",
+        ] {
+            sqlx::query(&format!("delete from {table} where user_id = ?1"))
+                .bind(u.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(fix_error)?;
+        }
+
+        sqlx::query("delete from users where id = ?1")
+            .bind(u.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+
+        tx.commit().await.map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn list_users(&self) -> DbResult<Vec<UserStats>> {
+        sqlx::query_as(&format!("{USER_STATS_QUERY} order by users.id"))
+            .fetch_all(&self.pool)
+            .await
+            .map_err(fix_error)
+            .map(|users| users.into_iter().map(|DbUserStats(u)| u).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn user_stats(&self, user: &User) -> DbResult<UserStats> {
+        sqlx::query_as(&format!("{USER_STATS_QUERY} where users.id = ?1"))
+            .bind(user.id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(fix_error)
+            .map(|DbUserStats(u)| u)
+    }
+
+    #[instrument(skip_all)]
+    async fn update_user_password(&self, user: &User) -> DbResult<()> {
+        sqlx::query("update users set password = ?1 where id = ?2")
+            .bind(&user.password)
+            .bind(user.id)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn user_totp(&self, user: &User) -> DbResult<Option<UserTotp>> {
+        let res: Option<(String, Option<i64>, i64)> = sqlx::query_as(
+            "select secret, enabled_at, last_step from user_totp
+            where user_id = ?1",
+        )
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        let Some((secret, enabled, last_step)) = res else {
+            return Ok(None);
+        };
+
+        Ok(Some(UserTotp {
+            user_id: user.id,
+            secret,
+            enabled: enabled.map(from_nanos).transpose().map_err(fix_error)?,
+            last_step,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn set_user_totp(&self, user: &User, secret: &str) -> DbResult<()> {
+        sqlx::query(
+            "insert into user_totp (user_id, secret) values (?1, ?2)
+            on conflict (user_id) do update
+            set secret = excluded.secret, enabled_at = null, last_step = 0",
+        )
+        .bind(user.id)
+        .bind(secret)
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn enable_user_totp(&self, user: &User) -> DbResult<()> {
+        sqlx::query(
+            "update user_totp
+            set enabled_at = ?2
+            where user_id = ?1",
+        )
+        .bind(user.id)
+        .bind(nanos(OffsetDateTime::now_utc()))
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn use_totp_step(&self, user: &User, step: i64) -> DbResult<bool> {
+        let res = sqlx::query(
+            "update user_totp
+            set last_step = ?2
+            where user_id = ?1
+            and last_step < ?2",
+        )
+        .bind(user.id)
+        .bind(step)
+        .execute(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_user_totp(&self, user: &User) -> DbResult<()> {
+        sqlx::query("delete from user_totp where user_id = ?1")
+            .bind(user.id)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn add_user(&self, user: &NewUser) -> DbResult<i64> {
+        let res: (i64,) = sqlx::query_as(
+            "insert into users
+                (username, email, password, created_at)
+            values (?1, ?2, ?3, ?4)
+            returning id",
+        )
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(&user.password)
+        .bind(nanos(OffsetDateTime::now_utc()))
+        .fetch_one(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(res.0)
+    }
+
+    #[instrument(skip_all)]
+    async fn add_session(&self, session: &NewSession) -> DbResult<()> {
+        sqlx::query("insert into sessions (user_id, token) values (?1, ?2)")
+            .bind(session.user_id)
+            .bind(&session.token)
+            .execute(&self.pool)
+            .await
+            .map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn replace_sessions(&self, session: &NewSession) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.map_err(fix_error)?;
+
+        sqlx::query("delete from sessions where user_id = ?1")
+            .bind(session.user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+
+        sqlx::query("insert into sessions (user_id, token) values (?1, ?2)")
+            .bind(session.user_id)
+            .bind(&session.token)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+
+        tx.commit().await.map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn get_user_session(&self, u: &User) -> DbResult<Session> {
+        sqlx::query_as("select id, user_id, token from sessions where user_id = ?1")
+            .bind(u.id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(fix_error)
+            .map(|DbSession(session)| session)
+    }
+
+    #[instrument(skip_all)]
+    async fn oldest_history(&self, user: &User) -> DbResult<History> {
+        sqlx::query_as(
+            "select id, client_id, user_id, hostname, timestamp, data, created_at from history
+            where user_id = ?1
+            order by timestamp asc
+            limit 1",
+        )
+        .bind(user.id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(fix_error)
+        .map(|DbHistory(h)| h)
+    }
+
+    #[instrument(skip_all)]
+    async fn add_records(&self, user: &User, records: &[Record<EncryptedData>]) -> DbResult<()> {
+        let mut tx = self.pool.begin().await.map_err(fix_error)?;
+        let now = nanos(OffsetDateTime::now_utc());
+
+        for i in records {
+            let id = atuin_common::utils::uuid_v7();
+
+            sqlx::query(
+                "insert into store
+                    (id, client_id, host, idx, timestamp, version, tag, data, cek, user_id, created_at)
+                values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                on conflict do nothing",
+            )
+            .bind(id)
+            .bind(i.id)
+            .bind(i.host.id)
+            .bind(i.idx as i64)
+            .bind(i.timestamp as i64)
+            .bind(&i.version)
+            .bind(&i.tag)
+            .bind(&i.data.data)
+            .bind(&i.data.content_encryption_key)
+            .bind(user.id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(fix_error)?;
+        }
+
+        tx.commit().await.map_err(fix_error)?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn next_records(
+        &self,
+        user: &User,
+        host: HostId,
+        tag: String,
+        start: Option<RecordIdx>,
+        count: u64,
+    ) -> DbResult<Vec<Record<EncryptedData>>> {
+        tracing::debug!("{:?} - {:?} - {:?}", host, tag, start);
+        let start = start.unwrap_or(0);
+
+        let records: Vec<DbRecord> = sqlx::query_as(
+            "select client_id, host, idx, timestamp, version, tag, data, cek from store
+            where user_id = ?1
+            and tag = ?2
+            and host = ?3
+            and idx >= ?4
+            order by idx asc
+            limit ?5",
+        )
+        .bind(user.id)
+        .bind(tag)
+        .bind(host)
+        .bind(start as i64)
+        .bind(count as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        Ok(records.into_iter().map(Into::into).collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn status(&self, user: &User) -> DbResult<RecordStatus> {
+        let res: Vec<(Uuid, String, i64)> = sqlx::query_as(
+            "select host, tag, max(idx) from store where user_id = ?1 group by host, tag",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        let mut status = RecordStatus::new();
+
+        for (host, tag, idx) in res {
+            status.set_raw(HostId(host), tag, idx as u64);
+        }
+
+        Ok(status)
+    }
+
+    #[instrument(skip_all)]
+    async fn digest(&self, user: &User) -> DbResult<RecordDigest> {
+        let res: Vec<(Uuid, String, i64, i64, Uuid)> = sqlx::query_as(
+            "select host, tag, count, idx, client_id from (
+                select host, tag, idx, client_id,
+                    count(1) over (partition by host, tag) as count,
+                    row_number() over (partition by host, tag order by idx desc) as n
+                from store
+                where user_id = ?1
+            )
+            where n = 1
+            order by host, tag",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(fix_error)?;
+
+        let entries = res
+            .into_iter()
+            .map(|(host, tag, count, idx, last)| DigestEntry {
+                host: HostId(host),
+                tag,
+                count: count as u64,
+                idx: idx as u64,
+                last: RecordId(last),
+            })
+            .collect();
+
+        Ok(RecordDigest::new(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_common::record::{EncryptedData, Host, HostId, Record, RecordId};
+    use atuin_common::utils::uuid_v7;
+    use atuin_server_database::{
+        models::{NewHistory, NewSession, NewUser},
+        Database,
+    };
+    use time::OffsetDateTime;
+
+    use super::{Sqlite, SqliteSettings};
+
+    async fn db() -> Sqlite {
+        Sqlite::new(&SqliteSettings {
+            db_uri: "sqlite::memory:".to_string(),
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn users_and_sessions() {
+        let db = db().await;
+
+        let id = db
+            .add_user(&NewUser {
+                username: "ellie".to_string(),
+                email: "ellie@example.com".to_string(),
+                password: "hashed".to_string(),
+            })
+            .await
+            .unwrap();
+        db.add_session(&NewSession {
+            user_id: id,
+            token: "token".to_string(),
+        })
+        .await
+        .unwrap();
+
+        let user = db.get_session_user("token").await.unwrap();
+        assert_eq!(user.username, "ellie");
+        assert!(!db.user_verified(id).await.unwrap());
+
+        db.verify_user(id).await.unwrap();
+        assert!(db.user_verified(id).await.unwrap());
+
+        let token = db.user_verification_token(id).await.unwrap();
+        assert_eq!(db.user_verification_token(id).await.unwrap(), token);
+
+        db.replace_sessions(&NewSession {
+            user_id: id,
+            token: "rotated".to_string(),
+        })
+        .await
+        .unwrap();
+        assert!(db.get_session("token").await.is_err());
+        assert_eq!(db.get_user_session(&user).await.unwrap().token, "rotated");
+
+        db.delete_user(&user).await.unwrap();
+        assert!(db.get_user("ellie").await.is_err());
+        assert!(db.get_session("rotated").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn revoked_hosts() {
+        let db = db().await;
+
+        let id = db
+            .add_user(&NewUser {
+                username: "ellie".to_string(),
+                email: "ellie@example.com".to_string(),
+                password: "hashed".to_string(),
+            })
+            .await
+            .unwrap();
+        let user = db.get_user("ellie").await.unwrap();
+        let host = HostId(uuid_v7());
+
+        assert!(db.revoked_host(&user, host).await.unwrap().is_none());
+
+        db.revoke_host(&user, host, false).await.unwrap();
+        db.revoke_host(&user, host, true).await.unwrap();
+
+        // the latest revocation wins
+        let revoked = db.revoked_host(&user, host).await.unwrap().unwrap();
+        assert_eq!(revoked.user_id, id);
+        assert!(revoked.wipe_key);
+    }
+
+    #[tokio::test]
+    async fn history_and_records() {
+        let db = db().await;
+
+        let id = db
+            .add_user(&NewUser {
+                username: "ellie".to_string(),
+                email: "ellie@example.com".to_string(),
+                password: "hashed".to_string(),
+            })
+            .await
+            .unwrap();
+        let user = db.get_user("ellie").await.unwrap();
+
+        let now = OffsetDateTime::now_utc();
+        db.add_history(&[NewHistory {
+            client_id: "one".to_string(),
+            user_id: id,
+            hostname: "laptop".to_string(),
+            timestamp: now,
+            data: "encrypted".to_string(),
+        }])
+        .await
+        .unwrap();
+
+        assert_eq!(db.count_history(&user).await.unwrap(), 1);
+        let listed = db
+            .list_history(&user, now - time::Duration::hours(1), now, "desktop", 100)
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].client_id, "one");
+
+        db.delete_history(&user, "one".to_string()).await.unwrap();
+        assert_eq!(db.deleted_history(&user).await.unwrap(), ["one"]);
+
+        let host = HostId(uuid_v7());
+        let first = Record::builder()
+            .host(Host::new(host))
+            .version("v0".to_string())
+            .tag("kv".to_string())
+            .idx(0)
+            .data(EncryptedData {
+                data: "encrypted".to_string(),
+                content_encryption_key: "key".to_string(),
+            })
+            .build();
+        let second = Record {
+            id: RecordId(uuid_v7()),
+            idx: 1,
+            ..first.clone()
+        };
+
+        db.add_records(&user, &[first.clone(), second.clone()])
+            .await
+            .unwrap();
+        // uploading again changes nothing
+        db.add_records(&user, &[second.clone()]).await.unwrap();
+
+        let next = db
+            .next_records(&user, host, "kv".to_string(), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(next.len(), 2);
+        assert_eq!(
+            db.status(&user).await.unwrap().get(host, "kv".to_string()),
+            Some(1)
+        );
+
+        let stats = db.user_stats(&user).await.unwrap();
+        assert_eq!((stats.history, stats.records), (1, 2));
+        assert!(stats.last_sync.is_some());
+    }
+}
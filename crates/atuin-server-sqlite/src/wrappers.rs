@@ -0,0 +1,107 @@
+use ::sqlx::{FromRow, Result};
+use atuin_common::record::{EncryptedData, Host, Record};
+use atuin_server_database::models::{History, Session, User, UserStats};
+use sqlx::{sqlite::SqliteRow, Row};
+use time::OffsetDateTime;
+
+pub struct DbUser(pub User);
+pub struct DbSession(pub Session);
+pub struct DbHistory(pub History);
+pub struct DbRecord(pub Record<EncryptedData>);
+pub struct DbUserStats(pub UserStats);
+
+/// Times are stored as unix nanoseconds
+pub fn from_nanos(nanos: i64) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(nanos.into())
+        .map_err(|e| sqlx::Error::Decode(e.into()))
+}
+
+fn get_time(row: &SqliteRow, column: &str) -> Result<OffsetDateTime> {
+    from_nanos(row.try_get(column)?)
+}
+
+fn get_optional_time(row: &SqliteRow, column: &str) -> Result<Option<OffsetDateTime>> {
+    row.try_get::<Option<i64>, _>(column)?
+        .map(from_nanos)
+        .transpose()
+}
+
+impl<'a> FromRow<'a, SqliteRow> for DbUser {
+    fn from_row(row: &'a SqliteRow) -> Result<Self> {
+        Ok(Self(User {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            email: row.try_get("email")?,
+            password: row.try_get("password")?,
+            verified: get_optional_time(row, "verified_at")?,
+        }))
+    }
+}
+
+impl<'a> FromRow<'a, SqliteRow> for DbUserStats {
+    fn from_row(row: &'a SqliteRow) -> Result<Self> {
+        Ok(Self(UserStats {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            email: row.try_get("email")?,
+            created_at: get_time(row, "created_at")?,
+            verified: get_optional_time(row, "verified_at")?,
+            history: row.try_get("history")?,
+            records: row.try_get("records")?,
+            storage: row.try_get("storage")?,
+            last_sync: get_optional_time(row, "last_sync")?,
+        }))
+    }
+}
+
+impl<'a> FromRow<'a, SqliteRow> for DbSession {
+    fn from_row(row: &'a SqliteRow) -> Result<Self> {
+        Ok(Self(Session {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            token: row.try_get("token")?,
+        }))
+    }
+}
+
+impl<'a> FromRow<'a, SqliteRow> for DbHistory {
+    fn from_row(row: &'a SqliteRow) -> Result<Self> {
+        Ok(Self(History {
+            id: row.try_get("id")?,
+            client_id: row.try_get("client_id")?,
+            user_id: row.try_get("user_id")?,
+            hostname: row.try_get("hostname")?,
+            timestamp: get_time(row, "timestamp")?,
+            data: row.try_get("data")?,
+            created_at: get_time(row, "created_at")?,
+        }))
+    }
+}
+
+impl<'a> FromRow<'a, SqliteRow> for DbRecord {
+    fn from_row(row: &'a SqliteRow) -> Result<Self> {
+        let timestamp: i64 = row.try_get("timestamp")?;
+        let idx: i64 = row.try_get("idx")?;
+
+        let data = EncryptedData {
+            data: row.try_get("data")?,
+            content_encryption_key: row.try_get("cek")?,
+        };
+
+        Ok(Self(Record {
+            id: row.try_get("client_id")?,
+            host: Host::new(row.try_get("host")?),
+            idx: idx as u64,
+            timestamp: timestamp as u64,
+            version: row.try_get("version")?,
+            tag: row.try_get("tag")?,
+            data,
+        }))
+    }
+}
+
+impl From<DbRecord> for Record<EncryptedData> {
+    fn from(other: DbRecord) -> Record<EncryptedData> {
+        Record { ..other.0 }
+    }
+}
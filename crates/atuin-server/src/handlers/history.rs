@@ -3,6 +3,7 @@ use std::{collections::HashMap, convert::TryFrom};
 use axum::{
     extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use metrics::counter;
@@ -11,6 +12,8 @@ use tracing::{debug, error, instrument};
 
 use super::{ErrorResponse, ErrorResponseStatus, RespExt};
 use crate::{
+    metrics::sync_size,
+    quota::check_quota,
     router::{AppState, UserAuth},
     utils::client_version_min,
 };
@@ -96,6 +99,12 @@ pub async fn list<DB: Database>(
     );
 
     counter!("atuin_history_returned", history.len() as u64);
+    sync_size(
+        "download",
+        "history",
+        history.len(),
+        history.iter().map(String::len).sum(),
+    );
 
     Ok(Json(SyncHistoryResponse { history }))
 }
@@ -127,8 +136,10 @@ pub async fn add<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
     Json(req): Json<Vec<AddHistoryRequest>>,
-) -> Result<(), ErrorResponseStatus<'static>> {
-    let State(AppState { database, settings }) = state;
+) -> Result<(), Response> {
+    let State(AppState {
+        database, settings, ..
+    }) = state;
 
     debug!("request to add {} history items", req.len());
     counter!("atuin_history_uploaded", req.len() as u64);
@@ -163,11 +174,17 @@ pub async fn add<DB: Database>(
         keep
     });
 
+    let bytes = history.iter().map(|h| h.data.len()).sum();
+    sync_size("upload", "history", history.len(), bytes);
+
+    check_quota(&database, &settings, &user, history.len(), bytes).await?;
+
     if let Err(e) = database.add_history(&history).await {
         error!("failed to add history: {}", e);
 
         return Err(ErrorResponse::reply("failed to add history")
-            .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+            .into_response());
     };
 
     Ok(())
@@ -9,6 +9,7 @@ use argon2::{
 use axum::{
     extract::{Path, State},
     http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
 use metrics::counter;
@@ -18,6 +19,7 @@ use postmark::{reqwest::PostmarkClient, Query};
 use rand::rngs::OsRng;
 use tracing::{debug, error, info, instrument};
 
+use super::v0::totp::check_code;
 use super::{ErrorResponse, ErrorResponseStatus, RespExt};
 use crate::router::{AppState, UserAuth};
 use atuin_server_database::{
@@ -178,6 +180,32 @@ pub async fn delete<DB: Database>(
     Ok(Json(DeleteUserResponse {}))
 }
 
+/// Log out everywhere. Every machine shares the one session, so the caller gets a new one and
+/// the rest have to log in again.
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn revoke_sessions<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+) -> Result<Json<RevokeSessionsResponse>, ErrorResponseStatus<'static>> {
+    let token = crypto_random_string::<24>();
+    let session = NewSession {
+        user_id: user.id,
+        token: (&token).into(),
+    };
+
+    if let Err(e) = state.database.replace_sessions(&session).await {
+        error!("failed to revoke sessions: {}", e);
+
+        return Err(ErrorResponse::reply("failed to revoke sessions")
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    counter!("atuin_sessions_revoked", 1);
+    info!(user = user.username, "revoked all sessions");
+
+    Ok(Json(RevokeSessionsResponse { session: token }))
+}
+
 #[instrument(skip_all, fields(user.id = user.id))]
 pub async fn send_verification<DB: Database>(
     UserAuth(user): UserAuth,
@@ -315,18 +343,21 @@ pub async fn change_password<DB: Database>(
 pub async fn login<DB: Database>(
     state: State<AppState<DB>>,
     login: Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, ErrorResponseStatus<'static>> {
+) -> Result<Json<LoginResponse>, Response> {
     let db = &state.0.database;
     let user = match db.get_user(login.username.borrow()).await {
         Ok(u) => u,
         Err(DbError::NotFound) => {
-            return Err(ErrorResponse::reply("user not found").with_status(StatusCode::NOT_FOUND));
+            return Err(ErrorResponse::reply("user not found")
+                .with_status(StatusCode::NOT_FOUND)
+                .into_response());
         }
         Err(DbError::Other(e)) => {
             error!("failed to get user {}: {}", login.username.clone(), e);
 
             return Err(ErrorResponse::reply("database error")
-                .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response());
         }
     };
 
@@ -334,12 +365,15 @@ pub async fn login<DB: Database>(
         Ok(u) => u,
         Err(DbError::NotFound) => {
             debug!("user session not found for user id={}", user.id);
-            return Err(ErrorResponse::reply("user not found").with_status(StatusCode::NOT_FOUND));
+            return Err(ErrorResponse::reply("user not found")
+                .with_status(StatusCode::NOT_FOUND)
+                .into_response());
         }
         Err(DbError::Other(err)) => {
             error!("database error for user {}: {}", login.username, err);
             return Err(ErrorResponse::reply("database error")
-                .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response());
         }
     };
 
@@ -347,9 +381,41 @@ pub async fn login<DB: Database>(
 
     if !verified {
         debug!(user = user.username, "login failed");
-        return Err(
-            ErrorResponse::reply("password is not correct").with_status(StatusCode::UNAUTHORIZED)
-        );
+        return Err(ErrorResponse::reply("password is not correct")
+            .with_status(StatusCode::UNAUTHORIZED)
+            .into_response());
+    }
+
+    match db.user_totp(&user).await {
+        Ok(Some(totp)) if totp.enabled.is_some() => {
+            let Some(code) = &login.totp else {
+                debug!(user = user.username, "login needs a two-factor code");
+
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    [(ATUIN_HEADER_TOTP, "required")],
+                    Json(ErrorResponse::reply(
+                        "this account needs a two-factor code to log in",
+                    )),
+                )
+                    .into_response());
+            };
+
+            check_code(db, &user, &totp, code)
+                .await
+                .map_err(IntoResponse::into_response)?;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!(
+                "failed to query two-factor login for {}: {e:?}",
+                user.username
+            );
+
+            return Err(ErrorResponse::reply("database error")
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response());
+        }
     }
 
     debug!(user = user.username, "login success");
@@ -0,0 +1,101 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use metrics::counter;
+use tracing::{error, instrument};
+
+use crate::{
+    handlers::{ErrorResponse, ErrorResponseStatus, RespExt},
+    router::{AdminAuth, AppState},
+};
+use atuin_server_database::{models::UserStats, Database, DbError};
+
+use atuin_common::api::{AdminUserResponse, AdminUsersResponse, DeleteUserResponse};
+
+fn user_response(stats: UserStats) -> AdminUserResponse {
+    AdminUserResponse {
+        username: stats.username,
+        email: stats.email,
+        created_at: stats.created_at,
+        verified: stats.verified.is_some(),
+        history: stats.history,
+        records: stats.records,
+        storage: stats.storage,
+        last_sync: stats.last_sync,
+    }
+}
+
+fn db_error(e: DbError, what: &'static str) -> ErrorResponseStatus<'static> {
+    match e {
+        DbError::NotFound => {
+            ErrorResponse::reply("user not found").with_status(StatusCode::NOT_FOUND)
+        }
+        DbError::Other(e) => {
+            error!("failed to {what}: {e:?}");
+            ErrorResponse::reply("database error").with_status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn list<DB: Database>(
+    _: AdminAuth,
+    state: State<AppState<DB>>,
+) -> Result<Json<AdminUsersResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    let users = database
+        .list_users()
+        .await
+        .map_err(|e| db_error(e, "list users"))?;
+
+    Ok(Json(AdminUsersResponse {
+        users: users.into_iter().map(user_response).collect(),
+    }))
+}
+
+#[instrument(skip_all, fields(user.username = username.as_str()))]
+pub async fn get<DB: Database>(
+    _: AdminAuth,
+    Path(username): Path<String>,
+    state: State<AppState<DB>>,
+) -> Result<Json<AdminUserResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    let user = database
+        .get_user(&username)
+        .await
+        .map_err(|e| db_error(e, "get user"))?;
+    let stats = database
+        .user_stats(&user)
+        .await
+        .map_err(|e| db_error(e, "get user stats"))?;
+
+    Ok(Json(user_response(stats)))
+}
+
+/// Delete an account, and everything it stored
+#[instrument(skip_all, fields(user.username = username.as_str()))]
+pub async fn delete<DB: Database>(
+    _: AdminAuth,
+    Path(username): Path<String>,
+    state: State<AppState<DB>>,
+) -> Result<Json<DeleteUserResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    let user = database
+        .get_user(&username)
+        .await
+        .map_err(|e| db_error(e, "get user"))?;
+    database
+        .delete_user(&user)
+        .await
+        .map_err(|e| db_error(e, "delete user"))?;
+
+    counter!("atuin_users_deleted", 1);
+    tracing::info!(user = user.username, "admin deleted user");
+
+    Ok(Json(DeleteUserResponse {}))
+}
@@ -0,0 +1,56 @@
+use axum::{extract::State, http::StatusCode, Json};
+use metrics::counter;
+use tracing::{error, instrument};
+
+use crate::{
+    handlers::{ErrorResponse, ErrorResponseStatus, RespExt},
+    router::{AppState, UserAuth},
+};
+use atuin_server_database::{models::NewSession, Database};
+
+use atuin_common::{
+    api::{RevokeHostRequest, RevokeHostResponse},
+    utils::crypto_random_string,
+};
+
+/// Stop a host syncing. Every host shares the account's session, so it's replaced too, and the
+/// caller gets the new one. The rest have to log in again.
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn revoke<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+    Json(req): Json<RevokeHostRequest>,
+) -> Result<Json<RevokeHostResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    if let Err(e) = database.revoke_host(&user, req.host, req.wipe_key).await {
+        error!("failed to revoke host: {e:?}");
+
+        return Err(ErrorResponse::reply("failed to revoke host")
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    let token = crypto_random_string::<24>();
+    let session = NewSession {
+        user_id: user.id,
+        token: (&token).into(),
+    };
+
+    if let Err(e) = database.replace_sessions(&session).await {
+        error!("failed to revoke sessions: {e:?}");
+
+        return Err(ErrorResponse::reply("failed to revoke sessions")
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    counter!("atuin_host_revoked", 1);
+
+    tracing::info!(
+        user = user.username,
+        host = ?req.host,
+        wipe_key = req.wipe_key,
+        "revoked host"
+    );
+
+    Ok(Json(RevokeHostResponse { session: token }))
+}
@@ -1,16 +1,29 @@
-use axum::Json;
-use tracing::instrument;
+use axum::{extract::State, http::StatusCode, Json};
+use tracing::{error, instrument};
 
-use crate::handlers::ErrorResponseStatus;
-use crate::router::UserAuth;
+use crate::handlers::{ErrorResponse, ErrorResponseStatus, RespExt};
+use crate::router::{AppState, UserAuth};
+use atuin_server_database::Database;
 
 use atuin_common::api::*;
 
 #[instrument(skip_all, fields(user.id = user.id))]
-pub async fn get(
+pub async fn get<DB: Database>(
     UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
 ) -> Result<Json<MeResponse>, ErrorResponseStatus<'static>> {
+    let totp = match state.database.user_totp(&user).await {
+        Ok(totp) => totp.is_some_and(|totp| totp.enabled.is_some()),
+        Err(e) => {
+            error!("failed to query two-factor login: {e:?}");
+
+            return Err(ErrorResponse::reply("failed to query two-factor login")
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+
     Ok(Json(MeResponse {
         username: user.username,
+        totp,
     }))
 }
@@ -1,3 +1,6 @@
+pub(crate) mod admin;
+pub(crate) mod host;
 pub(crate) mod me;
 pub(crate) mod record;
 pub(crate) mod store;
+pub(crate) mod totp;
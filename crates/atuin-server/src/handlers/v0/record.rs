@@ -1,23 +1,35 @@
-use axum::{extract::Query, extract::State, http::StatusCode, Json};
+use std::collections::HashSet;
+
+use axum::{
+    extract::Query,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use metrics::counter;
 use serde::Deserialize;
 use tracing::{error, instrument};
 
 use crate::{
     handlers::{ErrorResponse, ErrorResponseStatus, RespExt},
+    metrics::sync_size,
+    quota::{check_quota, check_record_size},
     router::{AppState, UserAuth},
 };
 use atuin_server_database::Database;
 
-use atuin_common::record::{EncryptedData, HostId, Record, RecordIdx, RecordStatus};
+use atuin_common::record::{EncryptedData, HostId, Record, RecordDigest, RecordIdx, RecordStatus};
 
 #[instrument(skip_all, fields(user.id = user.id))]
 pub async fn post<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
     Json(records): Json<Vec<Record<EncryptedData>>>,
-) -> Result<(), ErrorResponseStatus<'static>> {
-    let State(AppState { database, settings }) = state;
+) -> Result<(), Response> {
+    let State(AppState {
+        database, settings, ..
+    }) = state;
 
     tracing::debug!(
         count = records.len(),
@@ -27,24 +39,53 @@ pub async fn post<DB: Database>(
 
     counter!("atuin_record_uploaded", records.len() as u64);
 
-    let keep = records
-        .iter()
-        .all(|r| r.data.data.len() <= settings.max_record_size || settings.max_record_size == 0);
-
-    if !keep {
-        counter!("atuin_record_too_large", 1);
+    for record in &records {
+        if let Err(e) = check_record_size(&settings, record.data.data.len()) {
+            counter!("atuin_record_too_large", 1);
+            return Err(e.into_response());
+        }
+    }
 
-        return Err(
-            ErrorResponse::reply("could not add records; record too large")
-                .with_status(StatusCode::BAD_REQUEST),
-        );
+    let bytes = records
+        .iter()
+        .map(|r| r.data.data.len() + r.data.content_encryption_key.len())
+        .sum();
+    sync_size("upload", "records", records.len(), bytes);
+
+    check_quota(&database, &settings, &user, records.len(), bytes).await?;
+
+    // Records carry the host that wrote them, so uploads from a revoked host are refused even
+    // if they arrive by way of another client
+    let hosts: HashSet<HostId> = records.iter().map(|r| r.host.id).collect();
+
+    for host in hosts {
+        match database.revoked_host(&user, host).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                counter!("atuin_record_revoked_host", 1);
+
+                return Err(
+                    ErrorResponse::reply("could not add records; host has been revoked")
+                        .with_status(StatusCode::FORBIDDEN)
+                        .into_response(),
+                );
+            }
+            Err(e) => {
+                error!("failed to check revoked hosts: {}", e);
+
+                return Err(ErrorResponse::reply("failed to add record")
+                    .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .into_response());
+            }
+        }
     }
 
     if let Err(e) = database.add_records(&user, &records).await {
         error!("failed to add record: {}", e);
 
         return Err(ErrorResponse::reply("failed to add record")
-            .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+            .into_response());
     };
 
     Ok(())
@@ -55,10 +96,7 @@ pub async fn index<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
 ) -> Result<Json<RecordStatus>, ErrorResponseStatus<'static>> {
-    let State(AppState {
-        database,
-        settings: _,
-    }) = state;
+    let State(AppState { database, .. }) = state;
 
     let record_index = match database.status(&user).await {
         Ok(index) => index,
@@ -75,6 +113,28 @@ pub async fn index<DB: Database>(
     Ok(Json(record_index))
 }
 
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn digest<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+) -> Result<Json<RecordDigest>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    let digest = match database.digest(&user).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            error!("failed to get record digest: {}", e);
+
+            return Err(ErrorResponse::reply("failed to calculate record digest")
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    };
+
+    tracing::debug!(user = user.username, "record digest request");
+
+    Ok(Json(digest))
+}
+
 #[derive(Deserialize)]
 pub struct NextParams {
     host: HostId,
@@ -89,10 +149,7 @@ pub async fn next<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
 ) -> Result<Json<Vec<Record<EncryptedData>>>, ErrorResponseStatus<'static>> {
-    let State(AppState {
-        database,
-        settings: _,
-    }) = state;
+    let State(AppState { database, .. }) = state;
     let params = params.0;
 
     let records = match database
@@ -110,5 +167,11 @@ pub async fn next<DB: Database>(
 
     counter!("atuin_record_downloaded", records.len() as u64);
 
+    let bytes = records
+        .iter()
+        .map(|r| r.data.data.len() + r.data.content_encryption_key.len())
+        .sum();
+    sync_size("download", "records", records.len(), bytes);
+
     Ok(Json(records))
 }
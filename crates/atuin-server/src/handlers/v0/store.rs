@@ -18,10 +18,7 @@ pub async fn delete<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
 ) -> Result<(), ErrorResponseStatus<'static>> {
-    let State(AppState {
-        database,
-        settings: _,
-    }) = state;
+    let State(AppState { database, .. }) = state;
 
     if let Err(e) = database.delete_store(&user).await {
         counter!("atuin_store_delete_failed", 1);
@@ -0,0 +1,155 @@
+use axum::{extract::State, http::StatusCode, Json};
+use metrics::counter;
+use time::OffsetDateTime;
+use tracing::{error, instrument};
+
+use crate::{
+    handlers::{ErrorResponse, ErrorResponseStatus, RespExt},
+    router::{AppState, UserAuth},
+    totp,
+};
+use atuin_server_database::{
+    models::{User, UserTotp},
+    Database,
+};
+
+use atuin_common::api::{MessageResponse, TotpCodeRequest, TotpEnrollResponse};
+
+fn database_error(e: impl std::fmt::Debug) -> ErrorResponseStatus<'static> {
+    error!("failed to query two-factor login: {e:?}");
+
+    ErrorResponse::reply("failed to query two-factor login")
+        .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Check a code from the user's authenticator app. Each code is only accepted once.
+pub(crate) async fn check_code<DB: Database>(
+    database: &DB,
+    user: &User,
+    totp: &UserTotp,
+    code: &str,
+) -> Result<(), ErrorResponseStatus<'static>> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    let Some(step) = totp::verify(&totp.secret, code, now) else {
+        counter!("atuin_totp_failed", 1);
+
+        return Err(ErrorResponse::reply("two-factor code is not correct")
+            .with_status(StatusCode::UNAUTHORIZED));
+    };
+
+    match database.use_totp_step(user, step).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            counter!("atuin_totp_failed", 1);
+
+            Err(
+                ErrorResponse::reply("two-factor code has already been used")
+                    .with_status(StatusCode::UNAUTHORIZED),
+            )
+        }
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// Start enrolling in two-factor login. It isn't needed to log in until a code confirms it.
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn enroll<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+) -> Result<Json<TotpEnrollResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    match database.user_totp(&user).await {
+        Ok(Some(UserTotp {
+            enabled: Some(_), ..
+        })) => {
+            return Err(ErrorResponse::reply(
+                "two-factor login is already enabled; disable it first",
+            )
+            .with_status(StatusCode::CONFLICT));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(database_error(e)),
+    }
+
+    let secret = totp::new_secret();
+
+    database
+        .set_user_totp(&user, &secret)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(TotpEnrollResponse {
+        url: totp::url(&secret, &user.username),
+        secret,
+    }))
+}
+
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn enable<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<MessageResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    let totp = match database.user_totp(&user).await {
+        Ok(Some(totp)) if totp.enabled.is_none() => totp,
+        Ok(Some(_)) => {
+            return Err(ErrorResponse::reply("two-factor login is already enabled")
+                .with_status(StatusCode::CONFLICT));
+        }
+        Ok(None) => {
+            return Err(
+                ErrorResponse::reply("two-factor login has not been enrolled in")
+                    .with_status(StatusCode::BAD_REQUEST),
+            );
+        }
+        Err(e) => return Err(database_error(e)),
+    };
+
+    check_code(&database, &user, &totp, &req.code).await?;
+
+    database
+        .enable_user_totp(&user)
+        .await
+        .map_err(database_error)?;
+
+    counter!("atuin_totp_enabled", 1);
+    tracing::info!(user = user.username, "enabled two-factor login");
+
+    Ok(Json(MessageResponse {
+        message: String::from("two-factor login enabled"),
+    }))
+}
+
+/// Turn off two-factor login, which takes a code if it's enabled
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn disable<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<MessageResponse>, ErrorResponseStatus<'static>> {
+    let State(AppState { database, .. }) = state;
+
+    match database.user_totp(&user).await {
+        Ok(Some(totp)) if totp.enabled.is_some() => {
+            check_code(&database, &user, &totp, &req.code).await?;
+        }
+        Ok(_) => {}
+        Err(e) => return Err(database_error(e)),
+    }
+
+    database
+        .delete_user_totp(&user)
+        .await
+        .map_err(database_error)?;
+
+    counter!("atuin_totp_disabled", 1);
+    tracing::info!(user = user.username, "disabled two-factor login");
+
+    Ok(Json(MessageResponse {
+        message: String::from("two-factor login disabled"),
+    }))
+}
@@ -11,7 +11,10 @@ use eyre::{eyre, Context, Result};
 
 mod handlers;
 mod metrics;
+mod quota;
+mod rate_limit;
 mod router;
+mod totp;
 mod utils;
 
 pub use settings::example_config;
@@ -70,9 +73,12 @@ pub async fn launch_with_tcp_listener<Db: Database>(
 ) -> Result<()> {
     let r = make_router::<Db>(settings).await?;
 
-    serve(listener, r.into_make_service())
-        .with_graceful_shutdown(shutdown)
-        .await?;
+    serve(
+        listener,
+        r.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await?;
 
     Ok(())
 }
@@ -102,7 +108,7 @@ async fn launch_with_tls<Db: Database>(
 
     let server = axum_server::bind_rustls(addr, rustls_config)
         .handle(handle.clone())
-        .serve(r.into_make_service());
+        .serve(r.into_make_service_with_connect_info::<SocketAddr>());
 
     tokio::select! {
         _ = server => {}
@@ -138,9 +144,12 @@ pub async fn launch_metrics_server(host: String, port: u16) -> Result<()> {
 async fn make_router<Db: Database>(
     settings: Settings<<Db as Database>::Settings>,
 ) -> Result<Router, eyre::Error> {
-    let db = Db::new(&settings.db_settings)
+    let db = metrics::TimedDatabase::<Db>::new(&settings.db_settings)
         .await
         .wrap_err_with(|| format!("failed to connect to db: {:?}", settings.db_settings))?;
-    let r = router::router(db, settings);
+    let rate_limit = rate_limit::RateLimiter::new(settings.rate_limit.clone())
+        .await
+        .wrap_err("failed to set up rate limiting")?;
+    let r = router::router(db, settings, rate_limit);
     Ok(r)
 }
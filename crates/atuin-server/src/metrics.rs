@@ -1,23 +1,63 @@
-use std::time::Instant;
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::Range,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
 
+use async_trait::async_trait;
+use atuin_common::record::{EncryptedData, HostId, Record, RecordDigest, RecordIdx, RecordStatus};
+use atuin_server_database::{
+    calendar::{TimePeriod, TimePeriodInfo},
+    models::{
+        History, NewHistory, NewSession, NewUser, RevokedHost, Session, User, UserStats, UserTotp,
+    },
+    Database, DbResult,
+};
 use axum::{
     extract::{MatchedPath, Request},
     middleware::Next,
     response::IntoResponse,
 };
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use time::{OffsetDateTime, UtcOffset};
+
+/// How often the active user gauges are worked out again
+const ACTIVE_USERS_EVERY: Duration = Duration::from_secs(60);
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
 
 pub fn setup_metrics_recorder() -> PrometheusHandle {
     const EXPONENTIAL_SECONDS: &[f64] = &[
         0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
     ];
 
+    // clients upload and download up to a few hundred entries at a time
+    const SYNC_RECORDS: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0];
+
+    const SYNC_BYTES: &[f64] = &[
+        256.0,
+        1024.0,
+        4096.0,
+        16384.0,
+        65536.0,
+        262_144.0,
+        1_048_576.0,
+        4_194_304.0,
+    ];
+
     PrometheusBuilder::new()
         .set_buckets_for_metric(
-            Matcher::Full("http_requests_duration_seconds".to_string()),
+            Matcher::Suffix("_duration_seconds".to_string()),
             EXPONENTIAL_SECONDS,
         )
         .unwrap()
+        .set_buckets_for_metric(Matcher::Suffix("_sync_records".to_string()), SYNC_RECORDS)
+        .unwrap()
+        .set_buckets_for_metric(Matcher::Suffix("_sync_bytes".to_string()), SYNC_BYTES)
+        .unwrap()
         .install_recorder()
         .unwrap()
 }
@@ -54,3 +94,262 @@ pub async fn track_metrics(req: Request, next: Next) -> impl IntoResponse {
 
     response
 }
+
+/// The size of one upload or download, in entries and bytes. `kind` is history or records.
+pub fn sync_size(direction: &'static str, kind: &'static str, records: usize, bytes: usize) {
+    #[allow(clippy::cast_precision_loss)]
+    let (records, bytes) = (records as f64, bytes as f64);
+
+    metrics::histogram!("atuin_sync_records", records, "direction" => direction, "kind" => kind);
+    metrics::histogram!("atuin_sync_bytes", bytes, "direction" => direction, "kind" => kind);
+}
+
+#[derive(Default)]
+struct ActiveUsers {
+    seen: HashMap<i64, Instant>,
+    reported: Option<Instant>,
+}
+
+static ACTIVE_USERS: LazyLock<Mutex<ActiveUsers>> = LazyLock::new(Mutex::default);
+
+/// Note that a user made an authenticated request, for the active user gauges. They only count
+/// users seen by this server process, so restarting it starts them again from zero.
+pub fn user_seen(id: i64) {
+    let now = Instant::now();
+    let mut active = ACTIVE_USERS.lock().expect("active users poisoned");
+
+    active.seen.insert(id, now);
+
+    if active
+        .reported
+        .is_some_and(|reported| now.duration_since(reported) < ACTIVE_USERS_EVERY)
+    {
+        return;
+    }
+
+    active
+        .seen
+        .retain(|_, seen| now.duration_since(*seen) < DAY);
+    active.reported = Some(now);
+
+    let hour = active
+        .seen
+        .values()
+        .filter(|seen| now.duration_since(**seen) < HOUR)
+        .count();
+
+    #[allow(clippy::cast_precision_loss)]
+    let (hour, day) = (hour as f64, active.seen.len() as f64);
+
+    metrics::gauge!("atuin_active_users", hour, "window" => "1h");
+    metrics::gauge!("atuin_active_users", day, "window" => "24h");
+}
+
+async fn timed<T>(query: &'static str, fut: impl Future<Output = DbResult<T>>) -> DbResult<T> {
+    let start = Instant::now();
+    let res = fut.await;
+
+    let latency = start.elapsed().as_secs_f64();
+    let status = if res.is_ok() { "ok" } else { "error" };
+
+    metrics::histogram!("atuin_db_query_duration_seconds", latency, "query" => query, "status" => status);
+
+    res
+}
+
+/// Wraps a database, timing every query it makes
+#[derive(Clone)]
+pub struct TimedDatabase<DB>(DB);
+
+#[async_trait]
+impl<DB: Database> Database for TimedDatabase<DB> {
+    type Settings = DB::Settings;
+
+    async fn new(settings: &Self::Settings) -> DbResult<Self> {
+        Ok(Self(DB::new(settings).await?))
+    }
+
+    async fn get_session(&self, token: &str) -> DbResult<Session> {
+        timed("get_session", self.0.get_session(token)).await
+    }
+
+    async fn get_session_user(&self, token: &str) -> DbResult<User> {
+        timed("get_session_user", self.0.get_session_user(token)).await
+    }
+
+    async fn add_session(&self, session: &NewSession) -> DbResult<()> {
+        timed("add_session", self.0.add_session(session)).await
+    }
+
+    async fn replace_sessions(&self, session: &NewSession) -> DbResult<()> {
+        timed("replace_sessions", self.0.replace_sessions(session)).await
+    }
+
+    async fn get_user(&self, username: &str) -> DbResult<User> {
+        timed("get_user", self.0.get_user(username)).await
+    }
+
+    async fn get_user_session(&self, u: &User) -> DbResult<Session> {
+        timed("get_user_session", self.0.get_user_session(u)).await
+    }
+
+    async fn add_user(&self, user: &NewUser) -> DbResult<i64> {
+        timed("add_user", self.0.add_user(user)).await
+    }
+
+    async fn user_verified(&self, id: i64) -> DbResult<bool> {
+        timed("user_verified", self.0.user_verified(id)).await
+    }
+
+    async fn verify_user(&self, id: i64) -> DbResult<()> {
+        timed("verify_user", self.0.verify_user(id)).await
+    }
+
+    async fn user_verification_token(&self, id: i64) -> DbResult<String> {
+        timed(
+            "user_verification_token",
+            self.0.user_verification_token(id),
+        )
+        .await
+    }
+
+    async fn update_user_password(&self, u: &User) -> DbResult<()> {
+        timed("update_user_password", self.0.update_user_password(u)).await
+    }
+
+    async fn user_totp(&self, u: &User) -> DbResult<Option<UserTotp>> {
+        timed("user_totp", self.0.user_totp(u)).await
+    }
+
+    async fn set_user_totp(&self, u: &User, secret: &str) -> DbResult<()> {
+        timed("set_user_totp", self.0.set_user_totp(u, secret)).await
+    }
+
+    async fn enable_user_totp(&self, u: &User) -> DbResult<()> {
+        timed("enable_user_totp", self.0.enable_user_totp(u)).await
+    }
+
+    async fn use_totp_step(&self, u: &User, step: i64) -> DbResult<bool> {
+        timed("use_totp_step", self.0.use_totp_step(u, step)).await
+    }
+
+    async fn delete_user_totp(&self, u: &User) -> DbResult<()> {
+        timed("delete_user_totp", self.0.delete_user_totp(u)).await
+    }
+
+    async fn list_users(&self) -> DbResult<Vec<UserStats>> {
+        timed("list_users", self.0.list_users()).await
+    }
+
+    async fn user_stats(&self, u: &User) -> DbResult<UserStats> {
+        timed("user_stats", self.0.user_stats(u)).await
+    }
+
+    async fn total_history(&self) -> DbResult<i64> {
+        timed("total_history", self.0.total_history()).await
+    }
+
+    async fn count_history(&self, user: &User) -> DbResult<i64> {
+        timed("count_history", self.0.count_history(user)).await
+    }
+
+    async fn count_history_cached(&self, user: &User) -> DbResult<i64> {
+        timed("count_history_cached", self.0.count_history_cached(user)).await
+    }
+
+    async fn delete_user(&self, u: &User) -> DbResult<()> {
+        timed("delete_user", self.0.delete_user(u)).await
+    }
+
+    async fn delete_history(&self, user: &User, id: String) -> DbResult<()> {
+        timed("delete_history", self.0.delete_history(user, id)).await
+    }
+
+    async fn deleted_history(&self, user: &User) -> DbResult<Vec<String>> {
+        timed("deleted_history", self.0.deleted_history(user)).await
+    }
+
+    async fn delete_store(&self, user: &User) -> DbResult<()> {
+        timed("delete_store", self.0.delete_store(user)).await
+    }
+
+    async fn revoke_host(&self, user: &User, host: HostId, wipe_key: bool) -> DbResult<()> {
+        timed("revoke_host", self.0.revoke_host(user, host, wipe_key)).await
+    }
+
+    async fn revoked_host(&self, user: &User, host: HostId) -> DbResult<Option<RevokedHost>> {
+        timed("revoked_host", self.0.revoked_host(user, host)).await
+    }
+
+    async fn add_records(&self, user: &User, record: &[Record<EncryptedData>]) -> DbResult<()> {
+        timed("add_records", self.0.add_records(user, record)).await
+    }
+
+    async fn next_records(
+        &self,
+        user: &User,
+        host: HostId,
+        tag: String,
+        start: Option<RecordIdx>,
+        count: u64,
+    ) -> DbResult<Vec<Record<EncryptedData>>> {
+        timed(
+            "next_records",
+            self.0.next_records(user, host, tag, start, count),
+        )
+        .await
+    }
+
+    async fn status(&self, user: &User) -> DbResult<RecordStatus> {
+        timed("status", self.0.status(user)).await
+    }
+
+    async fn digest(&self, user: &User) -> DbResult<RecordDigest> {
+        timed("digest", self.0.digest(user)).await
+    }
+
+    async fn count_history_range(
+        &self,
+        user: &User,
+        range: Range<OffsetDateTime>,
+    ) -> DbResult<i64> {
+        timed(
+            "count_history_range",
+            self.0.count_history_range(user, range),
+        )
+        .await
+    }
+
+    async fn list_history(
+        &self,
+        user: &User,
+        created_after: OffsetDateTime,
+        since: OffsetDateTime,
+        host: &str,
+        page_size: i64,
+    ) -> DbResult<Vec<History>> {
+        timed(
+            "list_history",
+            self.0
+                .list_history(user, created_after, since, host, page_size),
+        )
+        .await
+    }
+
+    async fn add_history(&self, history: &[NewHistory]) -> DbResult<()> {
+        timed("add_history", self.0.add_history(history)).await
+    }
+
+    async fn oldest_history(&self, user: &User) -> DbResult<History> {
+        timed("oldest_history", self.0.oldest_history(user)).await
+    }
+
+    async fn calendar(
+        &self,
+        user: &User,
+        period: TimePeriod,
+        tz: UtcOffset,
+    ) -> DbResult<HashMap<u64, TimePeriodInfo>> {
+        timed("calendar", self.0.calendar(user, period, tz)).await
+    }
+}
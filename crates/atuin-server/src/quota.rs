@@ -0,0 +1,107 @@
+//! Limits on how much each account may store, checked before an upload is written
+
+use atuin_common::api::{ErrorResponse, QuotaExceededResponse, QuotaKind};
+use atuin_server_database::{models::User, Database};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use metrics::counter;
+use tracing::error;
+
+use crate::{handlers::RespExt, settings::Settings};
+
+pub struct QuotaExceeded(pub QuotaExceededResponse);
+
+impl QuotaExceeded {
+    pub fn new(kind: QuotaKind, limit: u64, used: u64) -> Self {
+        let reason = match kind {
+            QuotaKind::Records => format!(
+                "the account may store {limit} entries, and this upload would make it {used}"
+            ),
+            QuotaKind::Bytes => {
+                format!("the account may store {limit} bytes, and this upload would make it {used}")
+            }
+            QuotaKind::RecordSize => {
+                format!("records may be up to {limit} bytes, and this one is {used}")
+            }
+        };
+
+        Self(QuotaExceededResponse {
+            reason,
+            kind,
+            limit,
+            used,
+        })
+    }
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let kind = match self.0.kind {
+            QuotaKind::Records => "records",
+            QuotaKind::Bytes => "bytes",
+            QuotaKind::RecordSize => "record_size",
+        };
+        counter!("atuin_quota_exceeded", 1, "kind" => kind);
+
+        (StatusCode::PAYLOAD_TOO_LARGE, Json(self.0)).into_response()
+    }
+}
+
+/// Refuse a record bigger than `max_record_size`, unless that's 0
+pub fn check_record_size<DbSettings>(
+    settings: &Settings<DbSettings>,
+    size: usize,
+) -> Result<(), QuotaExceeded> {
+    let limit = settings.max_record_size;
+
+    if limit == 0 || size <= limit {
+        return Ok(());
+    }
+
+    Err(QuotaExceeded::new(
+        QuotaKind::RecordSize,
+        limit as u64,
+        size as u64,
+    ))
+}
+
+/// Refuse an upload of `count` entries and `bytes` bytes that would take the account over
+/// `max_records` or `max_storage`. Either being 0 doesn't limit it.
+pub async fn check_quota<DB: Database>(
+    database: &DB,
+    settings: &Settings<DB::Settings>,
+    user: &User,
+    count: usize,
+    bytes: usize,
+) -> Result<(), Response> {
+    if settings.max_records == 0 && settings.max_storage == 0 {
+        return Ok(());
+    }
+
+    let stats = database.user_stats(user).await.map_err(|e| {
+        error!("failed to get user stats: {e}");
+
+        ErrorResponse::reply("failed to check quota")
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+            .into_response()
+    })?;
+
+    let records = u64::try_from(stats.history + stats.records).unwrap_or(0) + count as u64;
+    if settings.max_records != 0 && records > settings.max_records {
+        return Err(
+            QuotaExceeded::new(QuotaKind::Records, settings.max_records, records).into_response(),
+        );
+    }
+
+    let storage = u64::try_from(stats.storage).unwrap_or(0) + bytes as u64;
+    if settings.max_storage != 0 && storage > settings.max_storage {
+        return Err(
+            QuotaExceeded::new(QuotaKind::Bytes, settings.max_storage, storage).into_response(),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,382 @@
+//! Rate limits for the endpoints worth flooding: registering, logging in and uploading history.
+//! Each client gets a token bucket per IP and per account, kept in memory or in redis.
+
+use std::{
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use atuin_common::api::{ErrorResponse, LoginRequest};
+use atuin_server_database::Database;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{self, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use eyre::Result;
+use lru::LruCache;
+use tracing::debug;
+
+use crate::{
+    handlers::RespExt,
+    router::{authenticate, AppState, AuthenticatedUser},
+    settings::{Limit, RateLimit},
+};
+
+/// The most buckets kept in memory. Past this, the least recently used is dropped to make room.
+const MAX_MEMORY_BUCKETS: NonZeroUsize = match NonZeroUsize::new(100_000) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+/// Login requests are tiny, so anything bigger isn't one
+const MAX_LOGIN_BODY: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Scope {
+    Register,
+    Login,
+    History,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Register => "register",
+            Self::Login => "login",
+            Self::History => "history",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    period: Duration,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(capacity: u32, period: Duration, now: Instant) -> Self {
+        Self {
+            capacity: f64::from(capacity),
+            period,
+            tokens: f64::from(capacity),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens =
+            (self.tokens + elapsed * self.capacity / self.period.as_secs_f64()).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Take a token, or say how long until there's one
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - self.tokens) * self.period.as_secs_f64() / self.capacity;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+/// Take a request from the bucket in memory for `key`, making it if it's new
+fn take(
+    buckets: &mut LruCache<String, Bucket>,
+    key: String,
+    capacity: u32,
+    period: Duration,
+    now: Instant,
+) -> Result<(), Duration> {
+    buckets
+        .get_or_insert_mut(key, || Bucket::full(capacity, period, now))
+        .take(now)
+}
+
+enum Backend {
+    Memory(Mutex<LruCache<String, Bucket>>),
+    #[cfg(feature = "redis")]
+    Redis(redis::aio::MultiplexedConnection),
+}
+
+/// The same bucket as [`Bucket`], in redis. Times are milliseconds, and it returns how many to
+/// wait, or 0 if it took a token.
+#[cfg(feature = "redis")]
+const REDIS_BUCKET: &str = r"
+local capacity = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * capacity / period)
+
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * period / capacity)
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], period)
+
+return wait
+";
+
+pub struct RateLimiter {
+    settings: RateLimit,
+    backend: Backend,
+}
+
+impl RateLimiter {
+    pub async fn new(settings: RateLimit) -> Result<Self> {
+        let backend = match &settings.redis_url {
+            #[cfg(feature = "redis")]
+            Some(url) if settings.enable => {
+                let client = redis::Client::open(url.as_str())?;
+                Backend::Redis(client.get_multiplexed_async_connection().await?)
+            }
+
+            #[cfg(not(feature = "redis"))]
+            Some(_) if settings.enable => {
+                eyre::bail!("rate_limit.redis_url is set, but this server was built without redis")
+            }
+
+            _ => Backend::Memory(Mutex::new(LruCache::new(MAX_MEMORY_BUCKETS))),
+        };
+
+        Ok(Self { settings, backend })
+    }
+
+    fn limit(&self, scope: Scope) -> Limit {
+        match scope {
+            Scope::Register => self.settings.register,
+            Scope::Login => self.settings.login,
+            Scope::History => self.settings.history,
+        }
+    }
+
+    /// Take a request from the bucket for `key`, or say how long until it may make another
+    async fn check(&self, scope: Scope, key: &str, capacity: u32) -> Result<(), Duration> {
+        if !self.settings.enable || capacity == 0 {
+            return Ok(());
+        }
+
+        let period = Duration::from_secs(self.limit(scope).period.max(1));
+        let key = format!("atuin:rate_limit:{}:{key}", scope.as_str());
+
+        match &self.backend {
+            Backend::Memory(buckets) => {
+                let mut buckets = buckets.lock().expect("rate limit buckets poisoned");
+
+                take(&mut buckets, key, capacity, period, Instant::now())
+            }
+
+            #[cfg(feature = "redis")]
+            Backend::Redis(connection) => {
+                let now = time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+
+                let wait: redis::RedisResult<u64> = redis::Script::new(REDIS_BUCKET)
+                    .key(key)
+                    .arg(capacity)
+                    .arg(period.as_millis().to_string())
+                    .arg(now.to_string())
+                    .invoke_async(&mut connection.clone())
+                    .await;
+
+                match wait {
+                    Ok(0) => Ok(()),
+                    Ok(wait) => Err(Duration::from_millis(wait)),
+                    Err(e) => {
+                        // better to let everyone through than to lock everyone out
+                        tracing::error!(error = ?e, "could not check rate limit");
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    async fn check_ip(&self, scope: Scope, ip: &str) -> Result<(), Duration> {
+        self.check(scope, &format!("ip:{ip}"), self.limit(scope).per_ip)
+            .await
+    }
+
+    async fn check_account(&self, scope: Scope, username: &str) -> Result<(), Duration> {
+        self.check(
+            scope,
+            &format!("account:{username}"),
+            self.limit(scope).per_account,
+        )
+        .await
+    }
+
+    /// Where the request came from, from the proxy's header if there is one
+    fn client_ip(&self, req: &Request) -> Option<String> {
+        if let Some(header) = &self.settings.client_ip_header {
+            return req
+                .headers()
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .map(|ip| ip.trim().to_string());
+        }
+
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    }
+}
+
+fn too_many_requests(scope: Scope, wait: Duration) -> Response {
+    metrics::increment_counter!("atuin_rate_limited_total", "scope" => scope.as_str());
+
+    let wait = wait.as_secs().max(1);
+
+    (
+        http::StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, wait.to_string())],
+        Json(ErrorResponse::reply(&format!(
+            "too many requests, try again in {wait}s"
+        ))),
+    )
+        .into_response()
+}
+
+/// Middleware limiting requests per IP, and per account where the request says which
+pub async fn limit<DB: Database>(
+    State((state, scope)): State<(AppState<DB>, Scope)>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let limiter = &state.rate_limit;
+
+    if !limiter.settings.enable {
+        return next.run(req).await;
+    }
+
+    if let Some(ip) = limiter.client_ip(&req) {
+        if let Err(wait) = limiter.check_ip(scope, &ip).await {
+            debug!(ip, scope = scope.as_str(), "rate limited");
+            return too_many_requests(scope, wait);
+        }
+    }
+
+    let username = match scope {
+        // there's no account yet
+        Scope::Register => None,
+
+        Scope::Login => {
+            let (parts, body) = req.into_parts();
+            let Ok(body) = to_bytes(body, MAX_LOGIN_BODY).await else {
+                return ErrorResponse::reply("invalid login request")
+                    .with_status(http::StatusCode::BAD_REQUEST)
+                    .into_response();
+            };
+
+            let username = serde_json::from_slice::<LoginRequest>(&body)
+                .ok()
+                .map(|login| login.username);
+
+            req = Request::from_parts(parts, Body::from(body));
+            username
+        }
+
+        // authenticated once here, and handed on to the handler
+        Scope::History => {
+            let (mut parts, body) = req.into_parts();
+            let user = match authenticate(&parts, &state).await {
+                Ok(user) => user,
+                Err(e) => return e,
+            };
+
+            let username = user.username.clone();
+            parts.extensions.insert(AuthenticatedUser(user));
+
+            req = Request::from_parts(parts, body);
+            Some(username)
+        }
+    };
+
+    if let Some(username) = username {
+        if let Err(wait) = limiter.check_account(scope, &username).await {
+            debug!(user = username, scope = scope.as_str(), "rate limited");
+            return too_many_requests(scope, wait);
+        }
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        time::{Duration, Instant},
+    };
+
+    use lru::LruCache;
+
+    use super::{take, Bucket};
+
+    #[test]
+    fn bucket_refills_over_the_period() {
+        let start = Instant::now();
+        let mut bucket = Bucket::full(3, Duration::from_secs(60), start);
+
+        for _ in 0..3 {
+            bucket.take(start).unwrap();
+        }
+
+        // a request comes back every 20 seconds
+        let wait = bucket.take(start).unwrap_err();
+        assert_eq!(wait.as_secs(), 20);
+
+        let later = start + Duration::from_secs(20);
+        bucket.take(later).unwrap();
+        assert!(bucket.take(later).is_err());
+
+        // and it never holds more than it started with
+        let much_later = start + Duration::from_secs(3600);
+        bucket.refill(much_later);
+        assert!(bucket.tokens >= bucket.capacity);
+        for _ in 0..3 {
+            bucket.take(much_later).unwrap();
+        }
+        assert!(bucket.take(much_later).is_err());
+    }
+
+    #[test]
+    fn buckets_are_capped() {
+        let now = Instant::now();
+        let period = Duration::from_secs(60);
+        let mut buckets = LruCache::new(NonZeroUsize::new(2).unwrap());
+
+        take(&mut buckets, "a".into(), 1, period, now).unwrap();
+        take(&mut buckets, "b".into(), 1, period, now).unwrap();
+        assert!(take(&mut buckets, "a".into(), 1, period, now).is_err());
+
+        // a new client pushes out the one seen least recently, and no more are kept
+        take(&mut buckets, "c".into(), 1, period, now).unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert!(!buckets.contains("b"));
+        assert!(take(&mut buckets, "a".into(), 1, period, now).is_err());
+    }
+}
@@ -1,79 +1,208 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use atuin_common::api::{ErrorResponse, ATUIN_CARGO_VERSION, ATUIN_HEADER_VERSION};
+use atuin_common::{
+    api::{
+        ErrorResponse, HostRevokedResponse, ATUIN_CARGO_VERSION, ATUIN_HEADER_HOST,
+        ATUIN_HEADER_VERSION,
+    },
+    record::HostId,
+};
 use axum::{
     extract::{FromRequestParts, Request},
     http::{self, request::Parts},
     middleware::Next,
     response::{IntoResponse, Response},
     routing::{delete, get, patch, post},
-    Router,
+    Json, Router,
 };
 use eyre::Result;
 use tower::ServiceBuilder;
-use tower_http::trace::TraceLayer;
+use tower_http::{
+    compression::CompressionLayer, decompression::RequestDecompressionLayer, trace::TraceLayer,
+};
+use uuid::Uuid;
 
 use super::handlers;
 use crate::{
     handlers::{ErrorResponseStatus, RespExt},
     metrics,
+    rate_limit::{self, RateLimiter, Scope},
     settings::Settings,
 };
-use atuin_server_database::{models::User, Database, DbError};
+use atuin_server_database::{
+    models::{RevokedHost, User},
+    Database, DbError,
+};
 
 pub struct UserAuth(pub User);
 
+/// A user a middleware has already authenticated, for [`UserAuth`] to take rather than looking
+/// them up again
+#[derive(Clone)]
+pub struct AuthenticatedUser(pub User);
+
 #[async_trait]
 impl<DB: Send + Sync> FromRequestParts<AppState<DB>> for UserAuth
 where
     DB: Database,
 {
-    type Rejection = ErrorResponseStatus<'static>;
+    type Rejection = Response;
 
     async fn from_request_parts(
         req: &mut Parts,
         state: &AppState<DB>,
     ) -> Result<Self, Self::Rejection> {
-        let auth_header = req
-            .headers
-            .get(http::header::AUTHORIZATION)
-            .ok_or_else(|| {
-                ErrorResponse::reply("missing authorization header")
-                    .with_status(http::StatusCode::BAD_REQUEST)
-            })?;
-        let auth_header = auth_header.to_str().map_err(|_| {
-            ErrorResponse::reply("invalid authorization header encoding")
+        let user = match req.extensions.remove::<AuthenticatedUser>() {
+            Some(AuthenticatedUser(user)) => user,
+            None => authenticate(req, state).await?,
+        };
+
+        Ok(UserAuth(user))
+    }
+}
+
+/// Find the user a request's session belongs to, and check the host it came from hasn't been
+/// revoked
+pub async fn authenticate<DB: Database>(
+    req: &Parts,
+    state: &AppState<DB>,
+) -> Result<User, Response> {
+    let token = session_token(req).map_err(IntoResponse::into_response)?;
+
+    // Clients identify the host they sync from, so that a lost or stolen machine can be cut
+    // off. Older clients don't, and the session is what authenticates them either way.
+    let host = match req.headers.get(ATUIN_HEADER_HOST) {
+        None => None,
+        Some(header) => Some(
+            header
+                .to_str()
+                .ok()
+                .and_then(|h| Uuid::parse_str(h).ok())
+                .map(HostId)
+                .ok_or_else(|| {
+                    ErrorResponse::reply("invalid host id header")
+                        .with_status(http::StatusCode::BAD_REQUEST)
+                        .into_response()
+                })?,
+        ),
+    };
+
+    // every bad token gets the same answer, so it says nothing about the hosts of any account
+    let user = match state.database.get_session_user(token).await {
+        Ok(user) => user,
+        Err(DbError::NotFound) => {
+            return Err(ErrorResponse::reply("session not found")
+                .with_status(http::StatusCode::UNAUTHORIZED)
+                .into_response());
+        }
+        Err(e) => return Err(query_failed(e, "could not query user session")),
+    };
+
+    // revoking a host logs the account out everywhere, so a revoked host only gets this far
+    // once it's logged in again, and is told then whether to delete its key
+    if let Some(host) = host {
+        match state.database.revoked_host(&user, host).await {
+            Ok(None) => {}
+            Ok(Some(revoked)) => return Err(host_revoked(&revoked)),
+            Err(e) => return Err(query_failed(e, "could not query revoked hosts")),
+        }
+    }
+
+    metrics::user_seen(user.id);
+
+    Ok(user)
+}
+
+fn session_token(req: &Parts) -> Result<&str, ErrorResponseStatus<'static>> {
+    let auth_header = req
+        .headers
+        .get(http::header::AUTHORIZATION)
+        .ok_or_else(|| {
+            ErrorResponse::reply("missing authorization header")
                 .with_status(http::StatusCode::BAD_REQUEST)
         })?;
-        let (typ, token) = auth_header.split_once(' ').ok_or_else(|| {
+    let auth_header = auth_header.to_str().map_err(|_| {
+        ErrorResponse::reply("invalid authorization header encoding")
+            .with_status(http::StatusCode::BAD_REQUEST)
+    })?;
+    let (typ, token) = auth_header.split_once(' ').ok_or_else(|| {
+        ErrorResponse::reply("invalid authorization header encoding")
+            .with_status(http::StatusCode::BAD_REQUEST)
+    })?;
+
+    if typ != "Token" {
+        return Err(
             ErrorResponse::reply("invalid authorization header encoding")
-                .with_status(http::StatusCode::BAD_REQUEST)
-        })?;
+                .with_status(http::StatusCode::BAD_REQUEST),
+        );
+    }
+
+    Ok(token)
+}
+
+fn host_revoked(revoked: &RevokedHost) -> Response {
+    tracing::info!(user_id = revoked.user_id, host = ?revoked.host, "rejected revoked host");
+
+    (
+        http::StatusCode::GONE,
+        Json(HostRevokedResponse {
+            reason: "this host has been revoked, and may no longer sync".into(),
+            wipe_key: revoked.wipe_key,
+        }),
+    )
+        .into_response()
+}
+
+fn query_failed(e: DbError, reason: &'static str) -> Response {
+    tracing::error!(error = ?e, "{reason}");
+
+    ErrorResponse::reply(reason)
+        .with_status(http::StatusCode::INTERNAL_SERVER_ERROR)
+        .into_response()
+}
+
+/// Requests made with the server's `admin_token`. Without one, there's no admin API.
+pub struct AdminAuth;
 
-        if typ != "Token" {
+#[async_trait]
+impl<DB: Send + Sync> FromRequestParts<AppState<DB>> for AdminAuth
+where
+    DB: Database,
+{
+    type Rejection = ErrorResponseStatus<'static>;
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &AppState<DB>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(admin_token) = state.settings.admin_token.as_deref() else {
             return Err(
-                ErrorResponse::reply("invalid authorization header encoding")
-                    .with_status(http::StatusCode::BAD_REQUEST),
+                ErrorResponse::reply("404 not found").with_status(http::StatusCode::NOT_FOUND)
             );
-        }
+        };
 
-        let user = state
-            .database
-            .get_session_user(token)
-            .await
-            .map_err(|e| match e {
-                DbError::NotFound => ErrorResponse::reply("session not found")
-                    .with_status(http::StatusCode::FORBIDDEN),
-                DbError::Other(e) => {
-                    tracing::error!(error = ?e, "could not query user session");
-                    ErrorResponse::reply("could not query user session")
-                        .with_status(http::StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            })?;
+        let token = req
+            .headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Token "));
 
-        Ok(UserAuth(user))
+        match token {
+            Some(token) if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) => {
+                Ok(AdminAuth)
+            }
+            _ => Err(ErrorResponse::reply("invalid admin token")
+                .with_status(http::StatusCode::FORBIDDEN)),
+        }
     }
 }
 
+/// Compare without leaking how much of the token was right through how long it took
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
 async fn teapot() -> impl IntoResponse {
     // This used to return 418: 🫖
     // Much as it was fun, it wasn't as useful or informative as it should be
@@ -102,26 +231,61 @@ async fn semver(request: Request, next: Next) -> Response {
     response
 }
 
+/// Let clients know they can compress what they send. Responses are compressed for any client
+/// that asks.
+async fn accept_encoding(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        http::header::ACCEPT_ENCODING,
+        http::HeaderValue::from_static("zstd"),
+    );
+
+    response
+}
+
 #[derive(Clone)]
 pub struct AppState<DB: Database> {
     pub database: DB,
     pub settings: Settings<DB::Settings>,
+    pub rate_limit: Arc<RateLimiter>,
 }
 
-pub fn router<DB: Database>(database: DB, settings: Settings<DB::Settings>) -> Router {
+pub fn router<DB: Database>(
+    database: DB,
+    settings: Settings<DB::Settings>,
+    rate_limit: RateLimiter,
+) -> Router {
+    let state = AppState {
+        database,
+        settings,
+        rate_limit: Arc::new(rate_limit),
+    };
+    let limit = |scope| {
+        axum::middleware::from_fn_with_state((state.clone(), scope), rate_limit::limit::<DB>)
+    };
+
     let routes = Router::new()
         .route("/", get(handlers::index))
         .route("/sync/count", get(handlers::history::count))
         .route("/sync/history", get(handlers::history::list))
         .route("/sync/calendar/:focus", get(handlers::history::calendar))
         .route("/sync/status", get(handlers::status::status))
-        .route("/history", post(handlers::history::add))
+        .route(
+            "/history",
+            post(handlers::history::add).route_layer(limit(Scope::History)),
+        )
         .route("/history", delete(handlers::history::delete))
         .route("/user/:username", get(handlers::user::get))
         .route("/account", delete(handlers::user::delete))
         .route("/account/password", patch(handlers::user::change_password))
-        .route("/register", post(handlers::user::register))
-        .route("/login", post(handlers::user::login))
+        .route(
+            "/register",
+            post(handlers::user::register).route_layer(limit(Scope::Register)),
+        )
+        .route(
+            "/login",
+            post(handlers::user::login).route_layer(limit(Scope::Login)),
+        )
         .route("/record", post(handlers::record::post::<DB>))
         .route("/record", get(handlers::record::index::<DB>))
         .route("/record/next", get(handlers::record::next))
@@ -131,24 +295,49 @@ pub fn router<DB: Database>(database: DB, settings: Settings<DB::Settings>) -> R
             "/api/v0/account/send-verification",
             post(handlers::user::send_verification),
         )
-        .route("/api/v0/record", post(handlers::v0::record::post))
+        .route(
+            "/api/v0/account/sessions/revoke",
+            post(handlers::user::revoke_sessions),
+        )
+        .route(
+            "/api/v0/account/totp",
+            post(handlers::v0::totp::enroll).delete(handlers::v0::totp::disable),
+        )
+        .route(
+            "/api/v0/account/totp/enable",
+            post(handlers::v0::totp::enable),
+        )
+        .route(
+            "/api/v0/record",
+            post(handlers::v0::record::post).route_layer(limit(Scope::History)),
+        )
         .route("/api/v0/record", get(handlers::v0::record::index))
         .route("/api/v0/record/next", get(handlers::v0::record::next))
-        .route("/api/v0/store", delete(handlers::v0::store::delete));
+        .route("/api/v0/record/digest", get(handlers::v0::record::digest))
+        .route("/api/v0/store", delete(handlers::v0::store::delete))
+        .route("/api/v0/host/revoke", post(handlers::v0::host::revoke))
+        .route("/api/v0/admin/users", get(handlers::v0::admin::list))
+        .route(
+            "/api/v0/admin/users/:username",
+            get(handlers::v0::admin::get).delete(handlers::v0::admin::delete),
+        );
 
-    let path = settings.path.as_str();
+    let path = state.settings.path.as_str();
     if path.is_empty() {
         routes
     } else {
         Router::new().nest(path, routes)
     }
     .fallback(teapot)
-    .with_state(AppState { database, settings })
+    .with_state(state)
     .layer(
         ServiceBuilder::new()
             .layer(axum::middleware::from_fn(clacks_overhead))
             .layer(TraceLayer::new_for_http())
             .layer(axum::middleware::from_fn(metrics::track_metrics))
-            .layer(axum::middleware::from_fn(semver)),
+            .layer(axum::middleware::from_fn(semver))
+            .layer(axum::middleware::from_fn(accept_encoding))
+            .layer(RequestDecompressionLayer::new())
+            .layer(CompressionLayer::new()),
     )
 }
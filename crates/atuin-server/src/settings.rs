@@ -52,6 +52,61 @@ impl Default for Metrics {
     }
 }
 
+/// How many requests a client may make to a group of endpoints. Each is a bucket holding that
+/// many requests, refilling evenly over the period. 0 doesn't limit.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Limit {
+    pub per_ip: u32,
+    pub per_account: u32,
+    /// In seconds
+    pub period: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RateLimit {
+    #[serde(alias = "enabled")]
+    pub enable: bool,
+
+    /// Keep the buckets in redis, so that several servers share them. In memory otherwise.
+    pub redis_url: Option<String>,
+
+    /// The header a reverse proxy puts the client's address in, such as X-Forwarded-For. The
+    /// last address in it is used, as that's the one the proxy added. Only set this behind a
+    /// proxy, or clients can claim to be anyone.
+    pub client_ip_header: Option<String>,
+
+    /// Per account is per username
+    pub register: Limit,
+    pub login: Limit,
+    /// Uploading history and records
+    pub history: Limit,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            redis_url: None,
+            client_ip_header: None,
+            register: Limit {
+                per_ip: 10,
+                per_account: 0,
+                period: 3600,
+            },
+            login: Limit {
+                per_ip: 60,
+                per_account: 10,
+                period: 900,
+            },
+            history: Limit {
+                per_ip: 0,
+                per_account: 600,
+                period: 60,
+            },
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Settings<DbSettings> {
     pub host: String,
@@ -60,12 +115,17 @@ pub struct Settings<DbSettings> {
     pub open_registration: bool,
     pub max_history_length: usize,
     pub max_record_size: usize,
+    /// How many history entries and records each account may store. 0 doesn't limit it.
+    pub max_records: u64,
+    /// How many bytes each account may store. 0 doesn't limit it.
+    pub max_storage: u64,
     pub page_size: i64,
     pub register_webhook_url: Option<String>,
     pub register_webhook_username: String,
     pub metrics: Metrics,
     pub tls: Tls,
     pub mail: Mail,
+    pub rate_limit: RateLimit,
 
     /// Advertise a version that is not what we are _actually_ running
     /// Many clients compare their version with api.atuin.sh, and if they differ, notify the user
@@ -74,6 +134,10 @@ pub struct Settings<DbSettings> {
     /// notifying users when the server runs something that is not a stable release.
     pub fake_version: Option<String>,
 
+    /// Lets requests with `Authorization: Token <admin_token>` list and delete users, through
+    /// /api/v0/admin. There's no admin API without it.
+    pub admin_token: Option<String>,
+
     #[serde(flatten)]
     pub db_settings: DbSettings,
 }
@@ -84,13 +148,15 @@ impl<DbSettings: DeserializeOwned> Settings<DbSettings> {
             PathBuf::from(p)
         } else {
             let mut config_file = PathBuf::new();
-            let config_dir = atuin_common::utils::config_dir();
+            let config_dir = atuin_common::utils::root_config_dir();
             config_file.push(config_dir);
             config_file
         };
 
         config_file.push("server.toml");
 
+        let limits = RateLimit::default();
+
         // create the config file if it does not exist
         let mut config_builder = Config::builder()
             .set_default("host", "127.0.0.1")?
@@ -98,6 +164,8 @@ impl<DbSettings: DeserializeOwned> Settings<DbSettings> {
             .set_default("open_registration", false)?
             .set_default("max_history_length", 8192)?
             .set_default("max_record_size", 1024 * 1024 * 1024)? // pretty chonky
+            .set_default("max_records", 0)?
+            .set_default("max_storage", 0)?
             .set_default("path", "")?
             .set_default("register_webhook_username", "")?
             .set_default("page_size", 1100)?
@@ -108,6 +176,19 @@ impl<DbSettings: DeserializeOwned> Settings<DbSettings> {
             .set_default("tls.enable", false)?
             .set_default("tls.cert_path", "")?
             .set_default("tls.pkey_path", "")?
+            .set_default("rate_limit.enable", false)?
+            .set_default("rate_limit.register.per_ip", limits.register.per_ip)?
+            .set_default(
+                "rate_limit.register.per_account",
+                limits.register.per_account,
+            )?
+            .set_default("rate_limit.register.period", limits.register.period)?
+            .set_default("rate_limit.login.per_ip", limits.login.per_ip)?
+            .set_default("rate_limit.login.per_account", limits.login.per_account)?
+            .set_default("rate_limit.login.period", limits.login.period)?
+            .set_default("rate_limit.history.per_ip", limits.history.per_ip)?
+            .set_default("rate_limit.history.per_account", limits.history.per_account)?
+            .set_default("rate_limit.history.period", limits.history.period)?
             .add_source(
                 Environment::with_prefix("atuin")
                     .prefix_separator("_")
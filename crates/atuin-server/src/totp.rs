@@ -0,0 +1,154 @@
+//! Time-based one time passwords (RFC 6238), for two-factor login. Codes are six digits, a new
+//! one every 30 seconds, which is what every authenticator app expects.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+const STEP: i64 = 30;
+const DIGITS: u32 = 6;
+
+/// Codes a step either side of now are accepted too, for clocks that are a little out
+const SKEW: i64 = 1;
+
+const SECRET_BYTES: usize = 20;
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn new_secret() -> String {
+    let mut secret = [0; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    base32_encode(&secret)
+}
+
+/// The secret as an otpauth:// URL. Usernames are only letters, numbers and hyphens, so need no
+/// escaping.
+pub fn url(secret: &str, username: &str) -> String {
+    format!(
+        "otpauth://totp/Atuin:{username}?secret={secret}&issuer=Atuin&digits={DIGITS}&period={STEP}"
+    )
+}
+
+/// The time step a unix timestamp falls in
+pub fn step(unix: i64) -> i64 {
+    unix.div_euclid(STEP)
+}
+
+/// Check a code against the secret, returning the time step it was for
+pub fn verify(secret: &str, code: &str, unix: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let code: u32 = code.parse().ok()?;
+    let secret = base32_decode(secret)?;
+    let now = step(unix);
+
+    (now - SKEW..=now + SKEW).find(|&step| generate(&secret, step) == code)
+}
+
+fn generate(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation, from RFC 4226
+    let offset = usize::from(hash[hash.len() - 1] & 0xf);
+    let bin = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    bin % 10u32.pow(DIGITS)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+
+    for &byte in data {
+        buffer = (buffer << 8) | u16::from(byte);
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[usize::from((buffer >> bits) & 0x1f)] as char);
+        }
+    }
+
+    if bits > 0 {
+        out.push(BASE32[usize::from((buffer << (5 - bits)) & 0x1f)] as char);
+    }
+
+    out
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+
+    for c in data.bytes().filter(|&c| c != b'=') {
+        let value = BASE32.iter().position(|&b| b == c.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32_decode, base32_encode, generate, new_secret, step, verify};
+
+    // the SHA-1 test vectors from RFC 6238, truncated to six digits
+    #[test]
+    fn generates_rfc_codes() {
+        let secret = b"12345678901234567890";
+
+        for (time, code) in [
+            (59, 287_082),
+            (1_111_111_109, 81_804),
+            (1_111_111_111, 50_471),
+            (1_234_567_890, 5_924),
+            (2_000_000_000, 279_037),
+        ] {
+            assert_eq!(generate(secret, step(time)), code);
+        }
+    }
+
+    #[test]
+    fn verifies_codes_near_now() {
+        let secret = new_secret();
+        let raw = base32_decode(&secret).unwrap();
+        let now = 1_700_000_000;
+
+        let code = format!("{:06}", generate(&raw, step(now) - 1));
+        assert_eq!(verify(&secret, &code, now), Some(step(now) - 1));
+
+        let code = format!("{:06}", generate(&raw, step(now) - 2));
+        assert_eq!(verify(&secret, &code, now), None);
+
+        assert_eq!(verify(&secret, "12345", now), None);
+        assert_eq!(verify(&secret, "abcdef", now), None);
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+
+        let secret = new_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(base32_encode(&base32_decode(&secret).unwrap()), secret);
+    }
+}
@@ -3,9 +3,8 @@ use std::path::PathBuf;
 use clap::Subcommand;
 use eyre::{Result, WrapErr};
 
-use atuin_client::{
-    database::Sqlite, record::sqlite_store::SqliteStore, settings::Settings, theme,
-};
+use atuin_client::{database, record::sqlite_store::SqliteStore, settings::Settings, theme};
+use atuin_common::output;
 use tracing_subscriber::{filter::EnvFilter, fmt, prelude::*};
 
 #[cfg(feature = "sync")]
@@ -14,21 +13,42 @@ mod sync;
 #[cfg(feature = "sync")]
 mod account;
 
+#[cfg(feature = "sync")]
+mod channel;
+
+#[cfg(feature = "sync")]
+mod share;
+
 #[cfg(feature = "daemon")]
 mod daemon;
 
+mod backup;
+mod config;
 mod default_config;
 mod doctor;
 mod dotfiles;
+mod format;
 mod history;
 mod import;
+mod incognito;
 mod info;
 mod init;
 mod kv;
+mod popup;
+mod profile;
+mod remote;
+mod report;
 mod search;
+#[cfg(feature = "self-update")]
+mod self_update;
+mod session;
 mod stats;
 mod store;
+mod suggest;
+mod tmux_popup;
 
+// only ever built once, from the command line
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 #[command(infer_subcommands = true)]
 pub enum Cmd {
@@ -46,6 +66,10 @@ pub enum Cmd {
     /// Interactive history search
     Search(search::Cmd),
 
+    /// Report on patterns in your history
+    #[command(subcommand)]
+    Report(report::Cmd),
+
     #[cfg(feature = "sync")]
     #[command(flatten)]
     Sync(sync::Cmd),
@@ -54,14 +78,46 @@ pub enum Cmd {
     #[cfg(feature = "sync")]
     Account(account::Cmd),
 
+    /// Share a history entry with a team channel, to search from everyone's "channel" filter
+    #[cfg(feature = "sync")]
+    Share(share::Cmd),
+
+    /// Manage the team channels history is shared to
+    #[cfg(feature = "sync")]
+    #[command(subcommand)]
+    Channel(channel::Cmd),
+
+    /// Suggest improvements based on your history
+    #[command(subcommand)]
+    Suggest(suggest::Cmd),
+
     /// Get or set small key-value pairs
     #[command(subcommand)]
     Kv(kv::Cmd),
 
+    /// Name shell sessions
+    #[command(subcommand)]
+    Session(session::Cmd),
+
+    /// Start a shell whose history isn't saved
+    Incognito(incognito::Cmd),
+
+    /// Search another machine's history over SSH, without syncing it
+    Remote(remote::Cmd),
+
+    // these are described, with how to bind them, by their own docs
+    TmuxPopup(tmux_popup::Cmd),
+    WeztermPopup(popup::Wezterm),
+    ZellijPopup(popup::Zellij),
+
     /// Manage the atuin data store
     #[command(subcommand)]
     Store(store::Cmd),
 
+    /// Back up and restore the local databases and key
+    #[command(subcommand)]
+    Backup(backup::Cmd),
+
     /// Manage your dotfiles with Atuin
     #[command(subcommand)]
     Dotfiles(dotfiles::Cmd),
@@ -86,10 +142,30 @@ pub enum Cmd {
     /// Print the default atuin configuration (config.toml)
     #[command()]
     DefaultConfig,
+
+    /// Read and edit settings in config.toml
+    #[command(subcommand)]
+    Config(config::Cmd),
+
+    /// Switch between profiles, each with its own config, key, sync account and history
+    #[command(subcommand)]
+    Profile(profile::Cmd),
+
+    /// Update atuin to the latest release
+    #[cfg(feature = "self-update")]
+    SelfUpdate(self_update::Cmd),
 }
 
 impl Cmd {
     pub fn run(self) -> Result<()> {
+        // config.toml may be what's stopping settings from loading, so it's edited without them.
+        // Profiles don't need them either
+        match self {
+            Self::Config(config) => return config.run(),
+            Self::Profile(profile) => return profile.run(),
+            _ => {}
+        }
+
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()
@@ -109,8 +185,25 @@ impl Cmd {
         mut settings: Settings,
         mut theme_manager: theme::ThemeManager,
     ) -> Result<()> {
-        let filter =
-            EnvFilter::from_env("ATUIN_LOG").add_directive("sqlx_sqlite::regexp=off".parse()?);
+        // ATUIN_LOG takes precedence, but otherwise -v and -vv turn up atuin's own logging
+        let filter = if std::env::var_os("ATUIN_LOG").is_some() {
+            EnvFilter::from_env("ATUIN_LOG")
+        } else {
+            match output::verbosity() {
+                ..=0 => EnvFilter::new("error"),
+                1 => EnvFilter::new("error,atuin=info,atuin_client=info,atuin_common=info"),
+                _ => EnvFilter::new("error,atuin=debug,atuin_client=debug,atuin_common=debug"),
+            }
+        }
+        .add_directive("sqlx_sqlite::regexp=off".parse()?);
+
+        #[cfg(feature = "sync")]
+        let filter = if settings.sync.log_timings {
+            let target = atuin_client::api_client::TIMING_TARGET;
+            filter.add_directive(format!("{target}=info").parse()?)
+        } else {
+            filter
+        };
 
         tracing_subscriber::registry()
             .with(fmt::layer())
@@ -125,22 +218,44 @@ impl Cmd {
         match self {
             Self::History(history) => return history.run(&settings).await,
             Self::Init(init) => return init.run(&settings).await,
+            Self::Incognito(incognito) => return incognito.run(),
+            Self::TmuxPopup(popup) => return popup.run(),
+            Self::WeztermPopup(popup) => return popup.run(),
+            Self::ZellijPopup(popup) => return popup.run(),
+            // a restore replaces the databases, so they mustn't be open
+            Self::Backup(backup) => return backup.run(&settings).await,
+            #[cfg(feature = "self-update")]
+            Self::SelfUpdate(self_update) => return self_update.run(&settings).await,
             _ => {}
         }
 
-        let db_path = PathBuf::from(settings.db_path.as_str());
+        // autosuggestions run on every keypress, so only open what they need
+        if let Self::Search(search) = &self {
+            if let Some(prefix) = search.prefix_suggest() {
+                let db = database::open_session(&settings).await?;
+                return search::prefix_suggest(&db, prefix).await;
+            }
+        }
+
         let record_store_path = PathBuf::from(settings.record_store_path.as_str());
 
-        let db = Sqlite::new(db_path, settings.local_timeout).await?;
+        // incognito shells search everything, but keep their own history out of it
+        let db = if matches!(self, Self::Search(_)) {
+            database::open_session(&settings).await?
+        } else {
+            database::open(&settings).await?
+        };
         let sqlite_store = SqliteStore::new(record_store_path, settings.local_timeout).await?;
 
         let theme_name = settings.theme.name.clone();
         let theme = theme_manager.load_theme(theme_name.as_str(), settings.theme.max_depth);
 
         match self {
-            Self::Import(import) => import.run(&db).await,
+            Self::Import(import) => import.run(&db, &settings).await,
             Self::Stats(stats) => stats.run(&db, &settings, theme).await,
             Self::Search(search) => search.run(db, &mut settings, sqlite_store, theme).await,
+            Self::Report(report) => report.run(&db).await,
+            Self::Suggest(suggest) => suggest.run(&settings, &db, sqlite_store).await,
 
             #[cfg(feature = "sync")]
             Self::Sync(sync) => sync.run(settings, &db, sqlite_store).await,
@@ -148,8 +263,18 @@ impl Cmd {
             #[cfg(feature = "sync")]
             Self::Account(account) => account.run(settings, sqlite_store).await,
 
+            #[cfg(feature = "sync")]
+            Self::Share(share) => share.run(&settings, &db).await,
+
+            #[cfg(feature = "sync")]
+            Self::Channel(channel) => channel.run(&settings, &db).await,
+
             Self::Kv(kv) => kv.run(&settings, &sqlite_store).await,
 
+            Self::Session(session) => session.run(&settings, &sqlite_store).await,
+
+            Self::Remote(remote) => remote.run(&settings, theme).await,
+
             Self::Store(store) => store.run(&settings, &db, sqlite_store).await,
 
             Self::Dotfiles(dotfiles) => dotfiles.run(&settings, sqlite_store).await,
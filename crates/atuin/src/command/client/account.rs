@@ -9,6 +9,9 @@ pub mod delete;
 pub mod login;
 pub mod logout;
 pub mod register;
+pub mod revoke_host;
+pub mod sessions;
+pub mod two_factor;
 pub mod verify;
 
 #[derive(Args, Debug)]
@@ -36,10 +39,25 @@ pub enum Commands {
 
     /// Verify your account
     Verify(verify::Cmd),
+
+    /// Stop a host from syncing, such as a lost or stolen machine
+    RevokeHost(revoke_host::Cmd),
+
+    /// Manage the sessions your machines are logged in with
+    #[command(subcommand)]
+    Sessions(sessions::Cmd),
+
+    /// Manage two-factor login, with codes from an authenticator app
+    #[command(name = "2fa", subcommand)]
+    TwoFactor(two_factor::Cmd),
 }
 
 impl Cmd {
     pub async fn run(self, settings: Settings, store: SqliteStore) -> Result<()> {
+        if !matches!(self.command, Commands::Logout) {
+            settings.ensure_online()?;
+        }
+
         match self.command {
             Commands::Login(l) => l.run(&settings, &store).await,
             Commands::Register(r) => r.run(&settings).await,
@@ -47,6 +65,9 @@ impl Cmd {
             Commands::Delete => delete::run(&settings).await,
             Commands::ChangePassword(c) => c.run(&settings).await,
             Commands::Verify(c) => c.run(&settings).await,
+            Commands::RevokeHost(c) => c.run(&settings).await,
+            Commands::Sessions(c) => c.run(&settings).await,
+            Commands::TwoFactor(c) => c.run(&settings).await,
         }
     }
 }
@@ -52,6 +52,9 @@ pub async fn run(
         .await?;
 
     println!("Account password successfully changed!");
+    println!(
+        "Other machines stay logged in. Run `atuin account sessions revoke --all` to log them out"
+    );
 
     Ok(())
 }
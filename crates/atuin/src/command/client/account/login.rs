@@ -1,4 +1,4 @@
-use std::{io, path::PathBuf};
+use std::io;
 
 use clap::Parser;
 use eyre::{bail, Context, Result};
@@ -6,7 +6,7 @@ use tokio::{fs::File, io::AsyncWriteExt};
 
 use atuin_client::{
     api_client,
-    encryption::{decode_key, encode_key, load_key, new_key, Key},
+    encryption::{decode_key, encode_key, key_exists, load_key, new_key, read_key, save_key, Key},
     record::sqlite_store::SqliteStore,
     record::store::Store,
     settings::Settings,
@@ -25,6 +25,11 @@ pub struct Cmd {
     /// The encryption key for your account
     #[clap(long, short)]
     pub key: Option<String>,
+
+    /// A code from your authenticator app, if your account has two-factor login enabled. You're
+    /// asked for one if it's needed and not given.
+    #[clap(long)]
+    pub totp: Option<String>,
 }
 
 fn get_input() -> Result<String> {
@@ -52,9 +57,6 @@ impl Cmd {
         let username = or_user_input(&self.username, "username");
         let password = self.password.clone().unwrap_or_else(read_user_password);
 
-        let key_path = settings.key_path.as_str();
-        let key_path = PathBuf::from(key_path);
-
         let key = or_user_input(&self.key, "encryption key [blank to use existing key file]");
 
         // if provided, the key may be EITHER base64, or a bip mnemonic
@@ -62,54 +64,29 @@ impl Cmd {
         let key = if key.is_empty() {
             key
         } else {
-            // try parse the key as a mnemonic...
-            match bip39::Mnemonic::from_phrase(&key, bip39::Language::English) {
-                Ok(mnemonic) => encode_key(Key::from_slice(mnemonic.entropy()))?,
-                Err(err) => {
-                    if let Some(err) = err.downcast_ref::<bip39::ErrorKind>() {
-                        match err {
-                            // assume they copied in the base64 key
-                            bip39::ErrorKind::InvalidWord => key,
-                            bip39::ErrorKind::InvalidChecksum => {
-                                bail!("key mnemonic was not valid")
-                            }
-                            bip39::ErrorKind::InvalidKeysize(_)
-                            | bip39::ErrorKind::InvalidWordLength(_)
-                            | bip39::ErrorKind::InvalidEntropyLength(_, _) => {
-                                bail!("key was not the correct length")
-                            }
-                        }
-                    } else {
-                        // unknown error. assume they copied the base64 key
-                        key
-                    }
-                }
-            }
+            normalize_key(key)?
         };
 
         // I've simplified this a little, but it could really do with a refactor
         // Annoyingly, it's also very important to get it correct
         if key.is_empty() {
-            if key_path.exists() {
-                let bytes = fs_err::read_to_string(key_path)
-                    .context("existing key file couldn't be read")?;
+            if let Some(bytes) = read_key(settings).context("existing key couldn't be read")? {
                 if decode_key(bytes).is_err() {
-                    bail!("the key in existing key file was invalid");
+                    bail!("the existing key was invalid");
                 }
             } else {
                 println!("No key file exists, creating a new");
                 let _key = new_key(settings)?;
             }
-        } else if !key_path.exists() {
+        } else if !key_exists(settings)? {
             if decode_key(key.clone()).is_err() {
                 bail!("the specified key was invalid");
             }
 
-            let mut file = File::create(key_path).await?;
-            file.write_all(key.as_bytes()).await?;
+            save_key(settings, &key)?;
         } else {
-            // we now know that the user has logged in specifying a key, AND that the key path
-            // exists
+            // we now know that the user has logged in specifying a key, AND that there's one
+            // saved already
 
             // 1. check if the saved key and the provided key match. if so, nothing to do.
             // 2. if not, re-encrypt the local history and overwrite the key
@@ -126,16 +103,24 @@ impl Cmd {
                 store.re_encrypt(&current_key, &new_key).await?;
 
                 println!("Writing new key");
-                let mut file = File::create(key_path).await?;
-                file.write_all(encoded.as_bytes()).await?;
+                save_key(settings, &encoded)?;
             }
         }
 
-        let session = api_client::login(
-            settings.sync_address.as_str(),
-            LoginRequest { username, password },
-        )
-        .await?;
+        let mut request = LoginRequest {
+            username,
+            password,
+            totp: self.totp.clone(),
+        };
+
+        let session = match api_client::login(settings.sync_address.as_str(), request.clone()).await
+        {
+            Err(e) if e.is::<api_client::TotpRequired>() && request.totp.is_none() => {
+                request.totp = Some(read_user_input("two-factor code"));
+                api_client::login(settings.sync_address.as_str(), request).await?
+            }
+            res => res?,
+        };
 
         let session_path = settings.session_path.as_str();
         let mut file = File::create(session_path).await?;
@@ -147,6 +132,33 @@ impl Cmd {
     }
 }
 
+/// A key may be given as EITHER base64, or a bip mnemonic. Normalize it to base64.
+pub fn normalize_key(key: String) -> Result<String> {
+    // try parse the key as a mnemonic...
+    match bip39::Mnemonic::from_phrase(&key, bip39::Language::English) {
+        Ok(mnemonic) => encode_key(Key::from_slice(mnemonic.entropy())),
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<bip39::ErrorKind>() {
+                match err {
+                    // assume they copied in the base64 key
+                    bip39::ErrorKind::InvalidWord => Ok(key),
+                    bip39::ErrorKind::InvalidChecksum => {
+                        bail!("key mnemonic was not valid")
+                    }
+                    bip39::ErrorKind::InvalidKeysize(_)
+                    | bip39::ErrorKind::InvalidWordLength(_)
+                    | bip39::ErrorKind::InvalidEntropyLength(_, _) => {
+                        bail!("key was not the correct length")
+                    }
+                }
+            } else {
+                // unknown error. assume they copied the base64 key
+                Ok(key)
+            }
+        }
+    }
+}
+
 pub(super) fn or_user_input(value: &'_ Option<String>, name: &'static str) -> String {
     value.clone().unwrap_or_else(|| read_user_input(name))
 }
@@ -156,7 +168,7 @@ pub(super) fn read_user_password() -> String {
     password.expect("Failed to read from input")
 }
 
-fn read_user_input(name: &'static str) -> String {
+pub(super) fn read_user_input(name: &'static str) -> String {
     eprint!("Please enter {name}: ");
     get_input().expect("Failed to read from input")
 }
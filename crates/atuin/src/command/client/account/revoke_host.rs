@@ -0,0 +1,46 @@
+use clap::Parser;
+use eyre::Result;
+use tokio::{fs::File, io::AsyncWriteExt};
+use uuid::Uuid;
+
+use atuin_client::{api_client, settings::Settings};
+use atuin_common::record::HostId;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// The ID of the host to revoke, as shown by `atuin store status`
+    pub host: Uuid,
+
+    /// Also ask the host to delete its encryption key the next time it contacts the server
+    #[clap(long)]
+    pub wipe_key: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let client = api_client::Client::new(
+            &settings.sync_address,
+            settings.session_token()?.as_str(),
+            settings.network_connect_timeout,
+            settings.network_timeout,
+        )?;
+
+        let revoked = client.revoke_host(HostId(self.host), self.wipe_key).await?;
+
+        // the revoked host shared this machine's session, so it's been replaced
+        let mut file = File::create(settings.session_path.as_str()).await?;
+        file.write_all(revoked.session.as_bytes()).await?;
+
+        println!(
+            "Host {} is revoked, and will stop syncing the next time it contacts the server",
+            self.host.as_hyphenated()
+        );
+        println!("Every other machine is logged out, and has to log in again to sync");
+
+        if self.wipe_key {
+            println!("It will also delete its copy of your encryption key");
+        }
+
+        Ok(())
+    }
+}
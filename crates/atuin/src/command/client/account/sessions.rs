@@ -0,0 +1,43 @@
+use clap::Subcommand;
+use eyre::{bail, Result};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+use atuin_client::{api_client, settings::Settings};
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// Log out everywhere. This machine stays logged in, but every other one has to log in
+    /// again.
+    Revoke {
+        /// Revoke every session. All your machines share one, so this is the only way to revoke
+        /// any
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let Self::Revoke { all } = self;
+
+        if !all {
+            bail!("all your machines share one session, so it can only be revoked with --all");
+        }
+
+        let client = api_client::Client::new(
+            &settings.sync_address,
+            settings.session_token()?.as_str(),
+            settings.network_connect_timeout,
+            settings.network_timeout,
+        )?;
+
+        let session = client.revoke_sessions().await?;
+
+        let mut file = File::create(settings.session_path.as_str()).await?;
+        file.write_all(session.session.as_bytes()).await?;
+
+        println!("Every other machine is logged out, and has to log in again to sync");
+
+        Ok(())
+    }
+}
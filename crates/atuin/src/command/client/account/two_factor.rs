@@ -0,0 +1,77 @@
+use clap::Subcommand;
+use eyre::Result;
+
+use atuin_client::{api_client, settings::Settings};
+
+use super::login::or_user_input;
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// Whether logging in needs a two-factor code
+    Status,
+
+    /// Turn on two-factor login. This prints a secret for your authenticator app, then asks for
+    /// a code from it to confirm.
+    Enable {
+        /// The code from your authenticator app, rather than being asked for it
+        #[arg(long)]
+        code: Option<String>,
+    },
+
+    /// Turn off two-factor login
+    Disable {
+        /// A code from your authenticator app, rather than being asked for it
+        #[arg(long)]
+        code: Option<String>,
+    },
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let client = api_client::Client::new(
+            &settings.sync_address,
+            settings.session_token()?.as_str(),
+            settings.network_connect_timeout,
+            settings.network_timeout,
+        )?;
+
+        match self {
+            Self::Status => {
+                if client.me().await?.totp {
+                    println!("Two-factor login is enabled");
+                } else {
+                    println!("Two-factor login is not enabled");
+                }
+            }
+
+            Self::Enable { code } => {
+                let enrolment = client.totp_enroll().await?;
+
+                println!("Add this account to your authenticator app, with the secret");
+                println!("\n    {}\n", enrolment.secret);
+                println!("or, if it can read a link, with");
+                println!("\n    {}\n", enrolment.url);
+
+                client
+                    .totp_enable(or_user_input(&code, "a code from the app to confirm"))
+                    .await?;
+
+                println!("Two-factor login is enabled. Logging in will ask for a code from now on");
+            }
+
+            Self::Disable { code } => {
+                let code = if client.me().await?.totp {
+                    or_user_input(&code, "a code from your authenticator app")
+                } else {
+                    String::new()
+                };
+
+                client.totp_disable(code).await?;
+
+                println!("Two-factor login is disabled");
+            }
+        }
+
+        Ok(())
+    }
+}
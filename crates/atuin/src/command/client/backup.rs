@@ -0,0 +1,57 @@
+use clap::Subcommand;
+use eyre::Result;
+
+use atuin_client::{backup, settings::Settings};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Back up the history and record databases, and the key
+    Now,
+
+    /// List backups, oldest first
+    List,
+
+    /// Replace the databases and key with a backup. What's there now is backed up first.
+    ///
+    /// Close your other shells first, or they may keep writing to the replaced databases.
+    Restore {
+        /// The name of the backup, as shown by `atuin backup list`, or "latest"
+        snapshot: String,
+    },
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        match self {
+            Self::Now => {
+                let snapshot = backup::create(settings).await?;
+                println!("Backed up to {}", snapshot.path.display());
+            }
+
+            Self::List => {
+                for snapshot in backup::list(settings)? {
+                    println!(
+                        "{}\t{}",
+                        snapshot.name,
+                        snapshot.created.to_offset(settings.timezone.0)
+                    );
+                }
+            }
+
+            Self::Restore { snapshot } => {
+                let snapshot = backup::find(settings, &snapshot)?;
+                let current = backup::restore(settings, &snapshot).await?;
+
+                println!("Restored {}", snapshot.name);
+                println!(
+                    "What was there before is in {}, to undo run `atuin backup restore {}`",
+                    current.path.display(),
+                    current.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use clap::Subcommand;
+use eyre::Result;
+
+use atuin_client::{
+    channel::{self, ChannelStore},
+    database::Database,
+    encryption::encode_key,
+    settings::Settings,
+};
+use atuin_common::status;
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// List the channels in config.toml, and whether each has a key yet
+    List,
+
+    /// Print a channel's key, to give to teammates joining it
+    Key {
+        name: String,
+
+        /// Make a key for a new channel
+        #[arg(long, conflicts_with = "import")]
+        new: bool,
+
+        /// Save a key a teammate gave you
+        #[arg(long)]
+        import: Option<String>,
+    },
+
+    /// Share tagged history, and send and fetch everything shared, for every channel or just
+    /// this one. `atuin sync` does this too.
+    Sync { name: Option<String> },
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings, db: &impl Database) -> Result<()> {
+        match self {
+            Self::List => {
+                if settings.channels.is_empty() {
+                    println!("No channels. Add one with [[channels]] in config.toml");
+                }
+
+                for config in &settings.channels {
+                    let key = if channel::key_path(config).exists() {
+                        ""
+                    } else {
+                        " (no key)"
+                    };
+
+                    println!("{}\t{}{key}", config.name, config.backend);
+                }
+
+                Ok(())
+            }
+
+            Self::Key { name, new, import } => {
+                let config = channel::find(settings, &name)?;
+
+                if new {
+                    let key = channel::new_key(config)?;
+                    println!("{key}");
+                } else if let Some(key) = import {
+                    channel::save_key(config, &key)?;
+                    println!("Saved the key for {name}");
+                } else {
+                    println!("{}", encode_key(&channel::load_key(config)?)?);
+                }
+
+                Ok(())
+            }
+
+            Self::Sync { name } => {
+                if let Some(name) = name {
+                    channel::find(settings, &name)?;
+                    sync(settings, db, Some(&name)).await
+                } else {
+                    sync(settings, db, None).await
+                }
+            }
+        }
+    }
+}
+
+/// Sync every channel, or just the one named. A channel that fails to sync doesn't stop the
+/// others, and only fails the whole sync if it was the one asked for.
+pub async fn sync(settings: &Settings, db: &impl Database, only: Option<&str>) -> Result<()> {
+    let Some(channels_db) = channel::open_db(settings).await? else {
+        return Ok(());
+    };
+
+    for config in &settings.channels {
+        if only.is_some_and(|name| name != config.name) {
+            continue;
+        }
+
+        let res = async {
+            let store = ChannelStore::open(settings, config).await?;
+            let shared = store.share_tagged(db, &channels_db).await?;
+            let (uploaded, downloaded) = store.sync(&channels_db).await?;
+
+            Ok::<_, eyre::Report>((shared, uploaded, downloaded))
+        }
+        .await;
+
+        match res {
+            Ok((shared, uploaded, downloaded)) => {
+                if shared > 0 {
+                    status!("Shared {shared} tagged commands to {}", config.name);
+                }
+
+                status!("{uploaded}/{downloaded} up/down to channel {}", config.name);
+            }
+            Err(e) if only.is_some() => return Err(e),
+            Err(e) => status!("Could not sync channel {}: {e}", config.name),
+        }
+    }
+
+    Ok(())
+}
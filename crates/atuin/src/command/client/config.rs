@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use clap::Subcommand;
+use eyre::{bail, Result};
+use serde_json::Value;
+
+use atuin_client::settings::{
+    file::{self, SettingsFile, Source},
+    ProjectSettings, Settings,
+};
+use atuin_common::status;
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Print the value a setting has, wherever it's set (eg `atuin config get sync_frequency`)
+    Get { key: String },
+
+    /// Set a setting in config.toml (eg `atuin config set daemon.enabled true`)
+    Set { key: String, value: String },
+
+    /// List the settings in config.toml
+    #[command(alias = "ls")]
+    List,
+
+    /// Explain every setting: its value, and whether that's from the project's .atuin.toml,
+    /// config.toml, the environment or the default
+    Doctor,
+}
+
+impl Cmd {
+    // Settings aren't loaded first, so that a config.toml atuin can't load can still be fixed
+    pub fn run(self) -> Result<()> {
+        let defaults = file::defaults()?;
+
+        match self {
+            Self::Get { key } => get(&defaults, &key),
+            Self::Set { key, value } => set(&defaults, &key, &value),
+            Self::List => list(&defaults),
+            Self::Doctor => doctor(&defaults),
+        }
+    }
+}
+
+fn get(defaults: &BTreeMap<String, Value>, key: &str) -> Result<()> {
+    if !file::known(defaults, key) {
+        bail!("there's no setting called {key}");
+    }
+
+    let settings = file::to_value(&Settings::new()?)?;
+
+    // a key in a map, such as sync.network_frequency.Home, may not be set at all
+    let Some(value) = settings.pointer(&format!("/{}", key.replace('.', "/"))) else {
+        bail!("{key} isn't set");
+    };
+
+    println!("{}", display(value));
+
+    Ok(())
+}
+
+fn set(defaults: &BTreeMap<String, Value>, key: &str, value: &str) -> Result<()> {
+    let mut config = SettingsFile::load()?;
+
+    if !file::known(defaults, key) {
+        eprintln!("warning: there's no setting called {key}, so atuin will ignore it");
+    }
+
+    config.set(defaults, key, value)?;
+    config.save()?;
+
+    status!("Set {key} in {}", config.path.display());
+
+    Ok(())
+}
+
+fn list(defaults: &BTreeMap<String, Value>) -> Result<()> {
+    let config = SettingsFile::load()?;
+
+    for key in config.keys() {
+        let value = config
+            .get(&key)
+            .map(ToString::to_string)
+            .unwrap_or_default();
+
+        println!("{key} = {}", value.trim());
+    }
+
+    check(&config, defaults);
+
+    Ok(())
+}
+
+fn doctor(defaults: &BTreeMap<String, Value>) -> Result<()> {
+    let config = SettingsFile::load()?;
+
+    if !check(&config, defaults) {
+        bail!("fix config.toml, then run this again");
+    }
+
+    let settings = file::flatten(file::to_value(&Settings::new()?)?);
+    let width = settings.keys().map(String::len).max().unwrap_or_default();
+
+    for (key, value) in &settings {
+        let source = match config.source(key) {
+            Source::Project(path) => path.display().to_string(),
+            Source::File => "config.toml".to_string(),
+            Source::Env(var) => var,
+            Source::Default => "default".to_string(),
+        };
+
+        println!("{key:width$}  {}  ({source})", display(value));
+    }
+
+    Ok(())
+}
+
+// Warn about anything in the file that atuin won't use. Returns whether atuin can load it.
+fn check(config: &SettingsFile, defaults: &BTreeMap<String, Value>) -> bool {
+    for key in config.keys() {
+        if !file::known(defaults, &key) {
+            eprintln!("warning: there's no setting called {key}, so atuin ignores it");
+        }
+    }
+
+    // atuin carries on without a broken .atuin.toml, so it's only ever a warning
+    if let Some((path, keys)) = config.project() {
+        for key in keys {
+            if !ProjectSettings::KEYS.contains(&key.as_str()) {
+                eprintln!(
+                    "warning: {} can't set {key}, so atuin ignores it",
+                    path.display()
+                );
+            }
+        }
+
+        if let Err(e) = ProjectSettings::load(path).and_then(|p| p.apply(&mut Settings::utc())) {
+            eprintln!("warning: atuin ignores {}: {e}", path.display());
+        }
+    }
+
+    if let Err(e) = config.validate() {
+        eprintln!("error: {} can't be loaded: {e}", config.path.display());
+        return false;
+    }
+
+    true
+}
+
+// Strings as they are, for scripts, and anything else as JSON
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "(unset)".to_string(),
+        value => value.to_string(),
+    }
+}
@@ -1,9 +1,15 @@
 use eyre::Result;
 
-use atuin_client::{database::Sqlite, record::sqlite_store::SqliteStore, settings::Settings};
+use std::sync::Arc;
+
+use atuin_client::{database::Database, record::sqlite_store::SqliteStore, settings::Settings};
 use atuin_daemon::server::listen;
 
-pub async fn run(settings: Settings, store: SqliteStore, history_db: Sqlite) -> Result<()> {
+pub async fn run(
+    settings: Settings,
+    store: SqliteStore,
+    history_db: Arc<dyn Database>,
+) -> Result<()> {
     listen(settings, store, history_db).await?;
 
     Ok(())
@@ -4,6 +4,7 @@ use std::{env, path::PathBuf, str::FromStr};
 use atuin_client::database::Sqlite;
 use atuin_client::settings::Settings;
 use atuin_common::shell::{shell_name, Shell};
+use atuin_common::status;
 use colored::Colorize;
 use eyre::Result;
 use serde::{Deserialize, Serialize};
@@ -315,6 +316,7 @@ fn checks(info: &DoctorDump) {
                 //
     let zfs_error = "[Filesystem] ZFS is known to have some issues with SQLite. Atuin uses SQLite heavily. If you are having poor performance, there are some workarounds here: https://github.com/atuinsh/atuin/issues/952".bold().red();
     let bash_plugin_error = "[Shell] If you are using Bash, Atuin requires that either bash-preexec or ble.sh be installed. An older ble.sh may not be detected. so ignore this if you have it set up! Read more here: https://docs.atuin.sh/guide/installation/#bash".bold().red();
+    let bash_fallback_warning = "[Shell] Atuin is using its fallback for old versions of Bash, as neither bash-preexec nor ble.sh is loaded. Commands are only recorded once they finish, durations are rounded to the second, and commands left out of the Bash history are not recorded. Read more here: https://docs.atuin.sh/guide/installation/#bash".bold().yellow();
     let blesh_integration_error = "[Shell] Atuin and ble.sh seem to be loaded in the session, but the integration does not seem to be working. Please check the setup in .bashrc.".bold().red();
 
     // ZFS: https://github.com/atuinsh/atuin/issues/952
@@ -324,7 +326,14 @@ fn checks(info: &DoctorDump) {
 
     // Shell
     if info.shell.name == "bash" {
-        if !info
+        if info
+            .shell
+            .preexec
+            .as_ref()
+            .is_some_and(|val| val == "prompt-command")
+        {
+            println!("{bash_fallback_warning}");
+        } else if !info
             .shell
             .plugins
             .iter()
@@ -343,15 +352,15 @@ fn checks(info: &DoctorDump) {
 }
 
 pub async fn run(settings: &Settings) -> Result<()> {
-    println!("{}", "Atuin Doctor".bold());
-    println!("Checking for diagnostics");
+    status!("{}", "Atuin Doctor".bold());
+    status!("Checking for diagnostics");
     let dump = DoctorDump::new(settings).await;
 
     checks(&dump);
 
     let dump = serde_json::to_string_pretty(&dump)?;
 
-    println!("\nPlease include the output below with any bug reports or issues\n");
+    status!("\nPlease include the output below with any bug reports or issues\n");
     println!("{dump}");
 
     Ok(())
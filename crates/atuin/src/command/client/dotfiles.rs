@@ -4,6 +4,8 @@ use eyre::Result;
 use atuin_client::{record::sqlite_store::SqliteStore, settings::Settings};
 
 mod alias;
+mod profile;
+mod snippet;
 mod var;
 
 #[derive(Subcommand, Debug)]
@@ -16,6 +18,14 @@ pub enum Cmd {
     /// Manage shell and environment variables with Atuin
     #[command(subcommand)]
     Var(var::Cmd),
+
+    /// Manage shell snippets, like functions, with Atuin
+    #[command(subcommand)]
+    Snippet(snippet::Cmd),
+
+    /// Manage vars and aliases that apply in a directory, and everywhere below it
+    #[command(subcommand)]
+    Profile(profile::Cmd),
 }
 
 impl Cmd {
@@ -23,6 +33,8 @@ impl Cmd {
         match self {
             Self::Alias(cmd) => cmd.run(settings, store).await,
             Self::Var(cmd) => cmd.run(settings, store).await,
+            Self::Snippet(cmd) => cmd.run(settings, store).await,
+            Self::Profile(cmd) => cmd.run(settings, store).await,
         }
     }
 }
@@ -1,9 +1,23 @@
+use std::path::PathBuf;
+
 use clap::Subcommand;
 use eyre::{eyre, Context, Result};
 
 use atuin_client::{encryption, record::sqlite_store::SqliteStore, settings::Settings};
 
-use atuin_dotfiles::{shell::Alias, store::AliasStore};
+use atuin_dotfiles::{
+    shell::{parse_alias_file, Alias},
+    store::{AliasStore, ConfigChange},
+};
+
+// Where aliases are usually defined, checked by import when it's not given any files
+const ALIAS_FILES: &[&str] = &[
+    ".aliases",
+    ".bash_aliases",
+    ".bashrc",
+    ".zshrc",
+    ".config/fish/config.fish",
+];
 
 #[derive(Subcommand, Debug)]
 #[command(infer_subcommands = true)]
@@ -19,14 +33,28 @@ pub enum Cmd {
 
     /// Delete all aliases
     Clear,
-    // There are too many edge cases to parse at the moment. Disable for now.
-    // Import,
+
+    /// Import aliases from shell files, like ~/.aliases or ~/.zshrc. With no files, every usual
+    /// one that exists is read.
+    Import {
+        files: Vec<PathBuf>,
+
+        /// Replace aliases that are already set to something else
+        #[arg(long)]
+        overwrite: bool,
+    },
+
+    /// Show how the alias config that shells load would change if it were built now
+    Diff,
+
+    /// List aliases that two hosts set differently, where one overwrote the other without
+    /// having seen it
+    Conflicts,
 }
 
 impl Cmd {
     async fn set(&self, store: &AliasStore, name: String, value: String) -> Result<()> {
-        let illegal_char = regex::Regex::new("[ \t\n&();<>|\\\"'`$/]").unwrap();
-        if illegal_char.is_match(name.as_str()) {
+        if !valid_name(&name) {
             return Err(eyre!("Illegal character in alias name"));
         }
 
@@ -78,17 +106,106 @@ impl Cmd {
         Ok(())
     }
 
-    /*
-    async fn import(&self, store: &AliasStore) -> Result<()> {
-        let aliases = atuin_dotfiles::shell::import_aliases(store).await?;
+    async fn import(&self, store: &AliasStore, files: &[PathBuf], overwrite: bool) -> Result<()> {
+        let files = if files.is_empty() {
+            let home = atuin_common::utils::home_dir();
 
-        for i in aliases {
-            println!("Importing {}={}", i.name, i.value);
+            ALIAS_FILES
+                .iter()
+                .map(|file| home.join(file))
+                .filter(|file| file.exists())
+                .collect()
+        } else {
+            files.to_vec()
+        };
+
+        if files.is_empty() {
+            println!("No alias files found. Pass the files to import from.");
+            return Ok(());
+        }
+
+        let mut aliases = store.aliases().await?;
+
+        for file in files {
+            let contents = fs_err::read_to_string(&file)?;
+
+            for alias in parse_alias_file(&contents) {
+                if !valid_name(&alias.name) {
+                    eprintln!("Skipping '{}': illegal character in name.", alias.name);
+                    continue;
+                }
+
+                match aliases.iter_mut().find(|a| a.name == alias.name) {
+                    Some(existing) if existing.value == alias.value => continue,
+                    Some(existing) if !overwrite => {
+                        eprintln!(
+                            "Skipping '{}={}': already set to '{}'. Use --overwrite to replace it.",
+                            alias.name, alias.value, existing.value
+                        );
+                        continue;
+                    }
+                    Some(existing) => existing.value.clone_from(&alias.value),
+                    None => aliases.push(alias.clone()),
+                }
+
+                println!(
+                    "Importing '{}={}' from {}.",
+                    alias.name,
+                    alias.value,
+                    file.display()
+                );
+                store.set(&alias.name, &alias.value).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn diff(&self, store: &AliasStore) -> Result<()> {
+        let changes = store.diff().await?;
+
+        if changes.is_empty() {
+            println!("The alias config is up to date.");
+        }
+
+        for change in changes {
+            match change {
+                ConfigChange::Removed(line) => println!("- {line}"),
+                ConfigChange::Added(line) => println!("+ {line}"),
+            }
         }
 
         Ok(())
     }
-    */
+
+    async fn conflicts(&self, store: &AliasStore) -> Result<()> {
+        let conflicts = store.conflicts().await?;
+
+        if conflicts.is_empty() {
+            println!("No conflicting aliases.");
+            return Ok(());
+        }
+
+        let host_id = Settings::host_id().expect("failed to get host_id");
+        let host = |id| if id == host_id { " (this host)" } else { "" };
+
+        for conflict in conflicts {
+            println!(
+                "{}: '{}' from {}{} overwrote '{}' from {}{}",
+                conflict.alias.name,
+                conflict.alias.value,
+                conflict.host.0.as_simple(),
+                host(conflict.host),
+                conflict.overwritten,
+                conflict.overwritten_host.0.as_simple(),
+                host(conflict.overwritten_host),
+            );
+        }
+
+        println!("\nSet an alias again to settle it.");
+
+        Ok(())
+    }
 
     pub async fn run(&self, settings: &Settings, store: SqliteStore) -> Result<()> {
         if !settings.dotfiles.enabled {
@@ -109,6 +226,15 @@ impl Cmd {
             Self::Delete { name } => self.delete(&alias_store, name.clone()).await,
             Self::List => self.list(&alias_store).await,
             Self::Clear => self.clear(&alias_store).await,
+            Self::Import { files, overwrite } => self.import(&alias_store, files, *overwrite).await,
+            Self::Diff => self.diff(&alias_store).await,
+            Self::Conflicts => self.conflicts(&alias_store).await,
         }
     }
 }
+
+fn valid_name(name: &str) -> bool {
+    let illegal_char = regex::Regex::new("[ \t\n&();<>|\\\"'`$/]").unwrap();
+
+    !illegal_char.is_match(name)
+}
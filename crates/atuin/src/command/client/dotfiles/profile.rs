@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Subcommand, ValueEnum};
+use eyre::{bail, Context, Result};
+
+use atuin_client::{encryption, record::sqlite_store::SqliteStore, settings::Settings};
+
+use atuin_dotfiles::store::{
+    profile::{profile_dir, profile_script, Applied, Profile, ProfileShell, ProfileStore},
+    AliasStore,
+};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Set a var in a directory's profile (eg `atuin dotfiles profile set ~/work AWS_PROFILE work`)
+    Set {
+        dir: PathBuf,
+        name: String,
+        value: String,
+
+        /// Set an alias rather than a var
+        #[arg(long, short)]
+        alias: bool,
+    },
+
+    /// Remove a var from a directory's profile
+    Unset {
+        dir: PathBuf,
+        name: String,
+
+        /// Remove an alias rather than a var
+        #[arg(long, short)]
+        alias: bool,
+    },
+
+    /// Delete a directory's profile
+    Delete { dir: PathBuf },
+
+    /// List all profiles
+    List,
+
+    /// Print the script that applies the profiles for the current directory. The shell hooks
+    /// run this when the directory changes.
+    #[command(hide = true)]
+    Hook {
+        shell: HookShell,
+
+        /// The vars the last profile script applied
+        #[arg(long, default_value = "")]
+        vars: String,
+
+        /// The aliases the last profile script applied
+        #[arg(long, default_value = "")]
+        aliases: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum HookShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+// The directory as a profile keeps it, so that ~/work on one machine is ~/work on every other
+fn dir_key(dir: &Path) -> Result<String> {
+    let dir = if dir.is_absolute() {
+        dir.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(dir)
+    };
+
+    Ok(profile_dir(&dir, &atuin_common::utils::home_dir()))
+}
+
+impl Cmd {
+    async fn set(
+        &self,
+        store: &ProfileStore,
+        dir: &Path,
+        name: &str,
+        value: &str,
+        alias: bool,
+    ) -> Result<()> {
+        let dir = dir_key(dir)?;
+
+        let mut profile = store.get(&dir).await?.unwrap_or_else(|| Profile {
+            dir: dir.clone(),
+            ..Profile::default()
+        });
+
+        let (map, kind) = if alias {
+            (&mut profile.aliases, "alias")
+        } else {
+            (&mut profile.vars, "var")
+        };
+
+        match map.insert(name.to_string(), value.to_string()) {
+            Some(old) => {
+                println!("Overwriting {kind} '{name}={old}' with '{name}={value}' in {dir}.");
+            }
+            None => println!("Setting {kind} '{name}={value}' in {dir}."),
+        }
+
+        store.set(profile).await
+    }
+
+    async fn unset(&self, store: &ProfileStore, dir: &Path, name: &str, alias: bool) -> Result<()> {
+        let dir = dir_key(dir)?;
+
+        let Some(mut profile) = store.get(&dir).await? else {
+            bail!("there is no profile for {dir}");
+        };
+
+        let map = if alias {
+            &mut profile.aliases
+        } else {
+            &mut profile.vars
+        };
+
+        if map.remove(name).is_none() {
+            bail!("the profile for {dir} does not set '{name}'");
+        }
+
+        println!("Removing '{name}' from {dir}.");
+
+        store.set(profile).await
+    }
+
+    async fn delete(&self, store: &ProfileStore, dir: &Path) -> Result<()> {
+        let dir = dir_key(dir)?;
+
+        if store.get(&dir).await?.is_none() {
+            bail!("there is no profile for {dir}");
+        }
+
+        println!("Deleting the profile for {dir}.");
+
+        store.delete(&dir).await
+    }
+
+    async fn list(&self, store: &ProfileStore) -> Result<()> {
+        for profile in store.profiles().await? {
+            println!("{}", profile.dir);
+
+            for (name, value) in &profile.vars {
+                println!("  export {name}={value}");
+            }
+
+            for (name, value) in &profile.aliases {
+                println!("  alias {name}={value}");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn hook(
+        &self,
+        store: &ProfileStore,
+        aliases: &AliasStore,
+        shell: HookShell,
+        applied: Applied,
+    ) -> Result<()> {
+        let profiles = store.profiles().await?;
+
+        // aliases a profile replaced go back to their synced value, if they have one
+        let synced = if applied.aliases.is_empty() {
+            Vec::new()
+        } else {
+            aliases.aliases().await?
+        };
+
+        let shell = match shell {
+            HookShell::Bash | HookShell::Zsh => ProfileShell::Posix,
+            HookShell::Fish => ProfileShell::Fish,
+        };
+
+        let script = profile_script(
+            shell,
+            &profiles,
+            &std::env::current_dir()?,
+            &atuin_common::utils::home_dir(),
+            &applied,
+            |name| std::env::var(name).ok(),
+            |name| {
+                synced
+                    .iter()
+                    .find(|alias| alias.name == name)
+                    .map(|alias| alias.value.clone())
+            },
+        );
+
+        print!("{script}");
+
+        Ok(())
+    }
+
+    pub async fn run(&self, settings: &Settings, store: SqliteStore) -> Result<()> {
+        if !settings.dotfiles.enabled {
+            eprintln!("Dotfiles are not enabled. Add\n\n[dotfiles]\nenabled = true\n\nto your configuration file to enable them.\n");
+            eprintln!("The default configuration file is located at ~/.config/atuin/config.toml.");
+            return Ok(());
+        }
+
+        let encryption_key: [u8; 32] = encryption::load_key(settings)
+            .context("could not load encryption key")?
+            .into();
+        let host_id = Settings::host_id().expect("failed to get host_id");
+
+        let profile_store = ProfileStore::new(store.clone(), host_id, encryption_key);
+
+        match self {
+            Self::Set {
+                dir,
+                name,
+                value,
+                alias,
+            } => self.set(&profile_store, dir, name, value, *alias).await,
+            Self::Unset { dir, name, alias } => self.unset(&profile_store, dir, name, *alias).await,
+            Self::Delete { dir } => self.delete(&profile_store, dir).await,
+            Self::List => self.list(&profile_store).await,
+            Self::Hook {
+                shell,
+                vars,
+                aliases,
+            } => {
+                let applied = Applied {
+                    vars: vars.split_whitespace().map(String::from).collect(),
+                    aliases: aliases.split_whitespace().map(String::from).collect(),
+                };
+                let alias_store = AliasStore::new(store, host_id, encryption_key);
+
+                // this runs on every cd, so a broken profile shouldn't print errors each time
+                if let Err(e) = self
+                    .hook(&profile_store, &alias_store, *shell, applied)
+                    .await
+                {
+                    log::debug!("failed to apply directory profiles: {e:?}");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use eyre::{bail, eyre, Context, Result};
+
+use atuin_client::{encryption, record::sqlite_store::SqliteStore, settings::Settings};
+
+use atuin_dotfiles::{shell::SnippetShell, store::snippet::SnippetStore};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Add a snippet from a file, or replace one with the same name (eg atuin dotfiles snippet
+    /// add deploy.sh)
+    Add {
+        file: PathBuf,
+
+        /// The name to give it. Defaults to the file's name, without its extension.
+        #[arg(long, short)]
+        name: Option<String>,
+
+        /// Which shells it's for: posix (bash and zsh), bash, zsh, or fish. Defaults to the
+        /// one its extension says.
+        #[arg(long, short)]
+        shell: Option<SnippetShell>,
+    },
+
+    /// Print a snippet
+    Show { name: String },
+
+    /// Delete a snippet
+    Delete { name: String },
+
+    /// List all snippets
+    List,
+}
+
+impl Cmd {
+    async fn add(
+        &self,
+        store: &SnippetStore,
+        file: &PathBuf,
+        name: Option<&str>,
+        shell: Option<SnippetShell>,
+    ) -> Result<()> {
+        let body = fs_err::read_to_string(file)?;
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| eyre!("cannot name a snippet after {file:?}, pass --name"))?,
+        };
+
+        let shell = match shell {
+            Some(shell) => shell,
+            None => file
+                .extension()
+                .and_then(|ext| SnippetShell::from_extension(&ext.to_string_lossy()))
+                .ok_or_else(|| eyre!("cannot tell which shell {file:?} is for, pass --shell"))?,
+        };
+
+        let exists = store.snippets().await?.iter().any(|s| s.name == name);
+
+        store.set(&name, shell, &body).await?;
+
+        if exists {
+            println!("Replaced snippet '{name}' ({}).", shell.as_str());
+        } else {
+            println!("Added snippet '{name}' ({}).", shell.as_str());
+        }
+
+        Ok(())
+    }
+
+    async fn show(&self, store: &SnippetStore, name: &str) -> Result<()> {
+        let Some(snippet) = store.snippets().await?.into_iter().find(|s| s.name == name) else {
+            bail!("no snippet named '{name}'");
+        };
+
+        print!("{}", snippet.body);
+
+        if !snippet.body.ends_with('\n') {
+            println!();
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, store: &SnippetStore, name: &str) -> Result<()> {
+        if store.snippets().await?.iter().any(|s| s.name == name) {
+            println!("Deleting snippet '{name}'.");
+            store.delete(name).await?;
+        } else {
+            eprintln!("Cannot delete '{name}': Snippet not set.");
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, store: &SnippetStore) -> Result<()> {
+        for snippet in store.snippets().await? {
+            println!("{} ({})", snippet.name, snippet.shell.as_str());
+        }
+
+        Ok(())
+    }
+
+    pub async fn run(&self, settings: &Settings, store: SqliteStore) -> Result<()> {
+        if !settings.dotfiles.enabled {
+            eprintln!("Dotfiles are not enabled. Add\n\n[dotfiles]\nenabled = true\n\nto your configuration file to enable them.\n");
+            eprintln!("The default configuration file is located at ~/.config/atuin/config.toml.");
+            return Ok(());
+        }
+
+        let encryption_key: [u8; 32] = encryption::load_key(settings)
+            .context("could not load encryption key")?
+            .into();
+        let host_id = Settings::host_id().expect("failed to get host_id");
+
+        let snippet_store = SnippetStore::new(store, host_id, encryption_key);
+
+        match self {
+            Self::Add { file, name, shell } => {
+                self.add(&snippet_store, file, name.as_deref(), *shell)
+                    .await
+            }
+            Self::Show { name } => self.show(&snippet_store, name).await,
+            Self::Delete { name } => self.delete(&snippet_store, name).await,
+            Self::List => self.list(&snippet_store).await,
+        }
+    }
+}
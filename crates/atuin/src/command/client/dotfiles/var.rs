@@ -8,62 +8,111 @@ use atuin_dotfiles::{shell::Var, store::var::VarStore};
 #[derive(Subcommand, Debug)]
 #[command(infer_subcommands = true)]
 pub enum Cmd {
-    /// Set a variable
+    /// Set a variable. Variables are exported unless --no-export is given.
     Set {
         name: String,
         value: String,
 
+        /// Export the variable to child processes. This is the default.
+        #[clap(long, short, action, conflicts_with = "no_export")]
+        export: bool,
+
         #[clap(long, short, action)]
         no_export: bool,
+
+        /// Only set it on this host, overriding the value set for every host
+        #[clap(long, action)]
+        host: bool,
     },
 
     /// Delete a variable
-    Delete { name: String },
+    #[command(alias = "unset")]
+    Delete {
+        name: String,
+
+        /// Only delete this host's override, going back to the value set for every host
+        #[clap(long, action)]
+        host: bool,
+    },
 
     /// List all variables
     List,
 }
 
 impl Cmd {
-    async fn set(&self, store: VarStore, name: String, value: String, export: bool) -> Result<()> {
-        let vars = store.vars().await?;
+    async fn set(
+        &self,
+        store: VarStore,
+        name: String,
+        value: String,
+        export: bool,
+        host: bool,
+    ) -> Result<()> {
+        let vars = if host {
+            store.overrides().await?
+        } else {
+            store.shared().await?
+        };
         let found: Vec<Var> = vars.into_iter().filter(|a| a.name == name).collect();
         let show_export = if export { "export " } else { "" };
+        let on_host = if host { " on this host" } else { "" };
 
         if found.is_empty() {
-            println!("Setting '{show_export}{name}={value}'.");
+            println!("Setting '{show_export}{name}={value}'{on_host}.");
         } else {
             println!(
-                "Overwriting alias '{show_export}{name}={}' with '{name}={value}'.",
+                "Overwriting var '{show_export}{name}={}' with '{name}={value}'{on_host}.",
                 found[0].value
             );
         }
 
-        store.set(&name, &value, export).await?;
+        if host {
+            store.set_for_host(&name, &value, export).await?;
+        } else {
+            store.set(&name, &value, export).await?;
+        }
 
         Ok(())
     }
 
     async fn list(&self, store: VarStore) -> Result<()> {
         let vars = store.vars().await?;
+        let overrides = store.overrides().await?;
 
-        for i in vars.iter().filter(|v| !v.export) {
-            println!("{}={}", i.name, i.value);
-        }
+        let line = |var: &Var| {
+            let export = if var.export { "export " } else { "" };
+            let host = if overrides.contains(var) {
+                "  # this host"
+            } else {
+                ""
+            };
 
-        for i in vars.iter().filter(|v| v.export) {
-            println!("export {}={}", i.name, i.value);
-        }
+            println!("{export}{}={}{host}", var.name, var.value);
+        };
+
+        vars.iter().filter(|v| !v.export).for_each(line);
+        vars.iter().filter(|v| v.export).for_each(line);
 
         Ok(())
     }
 
-    async fn delete(&self, store: VarStore, name: String) -> Result<()> {
-        let mut vars = store.vars().await?.into_iter();
+    async fn delete(&self, store: VarStore, name: String, host: bool) -> Result<()> {
+        let mut vars = if host {
+            store.overrides().await?.into_iter()
+        } else {
+            store.shared().await?.into_iter()
+        };
 
         if let Some(var) = vars.find(|var| var.name == name) {
-            println!("Deleting '{name}={}'.", var.value);
-            store.delete(&name).await?;
+            if host {
+                println!("Deleting this host's '{name}={}'.", var.value);
+                store.delete_for_host(&name).await?;
+            } else {
+                println!("Deleting '{name}={}'.", var.value);
+                store.delete(&name).await?;
+            }
+        } else if host {
+            eprintln!("Cannot delete '{name}': this host does not override it.");
         } else {
             eprintln!("Cannot delete '{name}': Var not set.");
         };
@@ -90,11 +139,13 @@ impl Cmd {
                 name,
                 value,
                 no_export,
+                host,
+                ..
             } => {
-                self.set(var_store, name.clone(), value.clone(), !no_export)
+                self.set(var_store, name.clone(), value.clone(), !no_export, *host)
                     .await
             }
-            Self::Delete { name } => self.delete(var_store, name.clone()).await,
+            Self::Delete { name, host } => self.delete(var_store, name.clone(), *host).await,
             Self::List => self.list(var_store).await,
         }
     }
@@ -0,0 +1,76 @@
+//! `--format json` and `--format ndjson`, for the commands whose output other tools read. What's
+//! printed only ever gains fields, so scripts and dashboards can rely on it.
+
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use eyre::Result;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// For people to read
+    #[default]
+    #[value(alias = "table")]
+    Text,
+
+    /// Pretty printed JSON
+    Json,
+
+    /// A line of JSON for each item
+    Ndjson,
+}
+
+impl Format {
+    /// `json` or `ndjson`, given to a `--format` that's otherwise a template
+    pub fn from_template(template: Option<&str>) -> Option<Self> {
+        match template? {
+            "json" => Some(Self::Json),
+            "ndjson" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+
+    pub fn is_text(self) -> bool {
+        self == Self::Text
+    }
+
+    /// Status messages go to stdout too, so JSON is printed on its own, with only errors
+    pub fn quiet(self) {
+        if !self.is_text() {
+            atuin_common::output::set_verbosity(-1);
+        }
+    }
+
+    /// Print one value, which for ndjson is on one line
+    pub fn print(self, value: &impl Serialize) -> Result<()> {
+        let json = match self {
+            Self::Ndjson => serde_json::to_string(value)?,
+            Self::Text | Self::Json => serde_json::to_string_pretty(value)?,
+        };
+
+        write(&json)
+    }
+
+    /// Print a list, as an array for json, or a line for each item for ndjson
+    pub fn print_all<T: Serialize>(self, items: impl IntoIterator<Item = T>) -> Result<()> {
+        match self {
+            Self::Ndjson => {
+                for item in items {
+                    write(&serde_json::to_string(&item)?)?;
+                }
+
+                Ok(())
+            }
+            Self::Text | Self::Json => self.print(&items.into_iter().collect::<Vec<_>>()),
+        }
+    }
+}
+
+fn write(json: &str) -> Result<()> {
+    match writeln!(io::stdout().lock(), "{json}") {
+        // whatever was reading, like `head`, has what it wanted
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        res => Ok(res?),
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     fmt::{self, Display},
     io::{self, IsTerminal, Write},
     path::PathBuf,
@@ -7,13 +8,18 @@ use std::{
 
 use atuin_common::utils::{self, Escapable as _};
 use clap::Subcommand;
-use eyre::{Context, Result};
+use eyre::{bail, Context, Result};
 use runtime_format::{FormatKey, FormatKeyError, ParseSegment, ParsedFmt};
+use serde::Serialize;
 
 use atuin_client::{
-    database::{current_context, Database, Sqlite},
-    encryption,
-    history::{store::HistoryStore, History},
+    backup,
+    database::{self, current_context, Database},
+    encryption::{self, HostKey},
+    history::{
+        annotation::AnnotationStore, retention, store::HistoryStore, History, HistoryId,
+        CANCELLED_EXIT,
+    },
     record::sqlite_store::SqliteStore,
     settings::{
         FilterMode::{Directory, Global, Session},
@@ -25,9 +31,9 @@ use atuin_client::{
 use atuin_client::{record, sync};
 
 use log::{debug, warn};
-use time::{macros::format_description, OffsetDateTime};
+use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime};
 
-use super::search::format_duration_into;
+use super::{format::Format, search::format_duration_into};
 
 #[derive(Subcommand, Debug)]
 #[command(infer_subcommands = true)]
@@ -37,6 +43,12 @@ pub enum Cmd {
         command: Vec<String>,
     },
 
+    /// Records a command that was typed, then cancelled at the prompt rather than run
+    #[command(hide = true)]
+    Cancel {
+        command: Vec<String>,
+    },
+
     /// Finishes a new command in the history (adds time, exit code)
     End {
         id: String,
@@ -44,6 +56,12 @@ pub enum Cmd {
         exit: i64,
         #[arg(long, short)]
         duration: Option<u64>,
+        /// How many bytes the command wrote to stdout, for shell integrations that can measure it
+        #[arg(long)]
+        stdout_bytes: Option<u64>,
+        /// How many bytes the command wrote to stderr, for shell integrations that can measure it
+        #[arg(long)]
+        stderr_bytes: Option<u64>,
     },
 
     /// List all items in history
@@ -80,8 +98,10 @@ pub enum Cmd {
         #[arg(long, visible_alias = "tz")]
         timezone: Option<Timezone>,
 
-        /// Available variables: {command}, {directory}, {duration}, {user}, {host}, {exit} and {time}.
+        /// Available variables: {command}, {directory} (or {dir}), {duration}, {user}, {host}, {exit},
+        /// {id}, {session}, {namespace}, {branch}, {time} and {relativetime}.
         /// Example: --format "{time} - [{duration}] - {directory}$\t{command}"
+        /// Or the name of a template in the config, or "json" or "ndjson" for other tools to read
         #[arg(long, short)]
         format: Option<String>,
     },
@@ -103,20 +123,66 @@ pub enum Cmd {
         #[arg(long, visible_alias = "tz")]
         timezone: Option<Timezone>,
 
-        /// Available variables: {command}, {directory}, {duration}, {user}, {host} and {time}.
+        /// Available variables: {command}, {directory} (or {dir}), {duration}, {user}, {host}, {exit},
+        /// {id}, {session}, {namespace}, {branch}, {time} and {relativetime}.
         /// Example: --format "{time} - [{duration}] - {directory}$\t{command}"
+        /// Or the name of a template in the config, or "json" or "ndjson" for other tools to read
         #[arg(long, short)]
         format: Option<String>,
     },
 
     InitStore,
 
-    /// Delete history entries matching the configured exclusion filters
+    /// Delete history entries matching the configured exclusion filters, or past the retention
+    /// policy set in the [history] config section
     Prune {
         /// List matching history lines without performing the actual deletion.
         #[arg(short = 'n', long)]
         dry_run: bool,
     },
+
+    /// Star a history entry, so that it shows in the "starred" filter mode. Stars sync between
+    /// machines
+    Star {
+        /// The entry's id, as shown with `--format "{id}"`
+        id: String,
+
+        /// Remove the star instead
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Tag a history entry, to find it again with `tag:NAME` in a search. Tags sync between
+    /// machines
+    Tag {
+        /// The entry's id, as shown with `--format "{id}"`
+        id: String,
+
+        #[arg(required = true)]
+        tags: Vec<String>,
+
+        /// Remove the tags instead
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Attach a note to a history entry, shown in the inspector. Notes sync between machines
+    Note {
+        /// The entry's id, as shown with `--format "{id}"`
+        id: String,
+
+        /// The note. Leave it empty to remove the note
+        #[arg(default_value = "")]
+        note: String,
+    },
+
+    /// Collapse exact duplicates - the same command, run in the same directory during the same
+    /// session - down to their latest run, then compact the database
+    Dedup {
+        /// List the duplicates without deleting them
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -147,6 +213,22 @@ pub fn print_list(
     reverse: bool,
     tz: Timezone,
 ) {
+    if let Some(output) = Format::from_template(format) {
+        let iterator: Box<dyn Iterator<Item = &History>> = if reverse {
+            Box::new(h.iter().rev())
+        } else {
+            Box::new(h.iter())
+        };
+
+        let entries = iterator.map(|history| JsonHistory::new(history, tz));
+        if let Err(err) = output.print_all(entries) {
+            eprintln!("ERROR: History output failed with the following error: {err}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
     let w = std::io::stdout();
     let mut w = w.lock();
 
@@ -208,6 +290,53 @@ fn check_for_write_errors(write: Result<(), io::Error>) {
     }
 }
 
+/// An entry as `--format json` prints it
+#[derive(Serialize)]
+struct JsonHistory<'a> {
+    id: &'a str,
+    /// RFC 3339, in the timezone asked for
+    timestamp: String,
+    /// In nanoseconds, or -1 while the command is still running
+    duration: i64,
+    exit: i64,
+    command: &'a str,
+    directory: &'a str,
+    session: &'a str,
+    host: &'a str,
+    user: &'a str,
+    namespace: &'a str,
+    branch: &'a str,
+    env: &'a BTreeMap<String, String>,
+}
+
+impl<'a> JsonHistory<'a> {
+    fn new(history: &'a History, tz: Timezone) -> Self {
+        let (host, user) = history
+            .hostname
+            .split_once(':')
+            .unwrap_or((&history.hostname, ""));
+
+        Self {
+            id: &history.id.0,
+            timestamp: history
+                .timestamp
+                .to_offset(tz.0)
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            duration: history.duration,
+            exit: history.exit,
+            command: history.command.trim(),
+            directory: history.cwd.trim(),
+            session: &history.session,
+            host,
+            user,
+            namespace: &history.namespace,
+            branch: &history.branch,
+            env: &history.env,
+        }
+    }
+}
+
 /// Type wrapper around `History` with formatting settings.
 #[derive(Clone, Copy, Debug)]
 struct FmtHistory<'a> {
@@ -243,7 +372,11 @@ impl FormatKey for FmtHistory<'_> {
                 CmdFormat::Literal => f.write_str(self.history.command.trim()),
                 CmdFormat::Escaped => f.write_str(&self.history.command.trim().escape_control()),
             }?,
-            "directory" => f.write_str(self.history.cwd.trim())?,
+            "id" => f.write_str(&self.history.id.0)?,
+            "directory" | "dir" => f.write_str(self.history.cwd.trim())?,
+            "session" => f.write_str(&self.history.session)?,
+            "namespace" => f.write_str(&self.history.namespace)?,
+            "branch" => f.write_str(&self.history.branch)?,
             "exit" => f.write_str(&self.history.exit.to_string())?,
             "duration" => {
                 let dur = Duration::from_nanos(std::cmp::max(self.history.duration, 0) as u64);
@@ -292,16 +425,37 @@ fn parse_fmt(format: &str) -> ParsedFmt {
 }
 
 impl Cmd {
-    #[allow(clippy::too_many_lines, clippy::cast_possible_truncation)]
-    async fn handle_start(
+    async fn handle_start(settings: &Settings, command: &[String]) -> Result<()> {
+        let command = command.join(" ");
+
+        // It's better for atuin to silently fail here and attempt to
+        // store whatever is ran, than to throw an error to the terminal
+        let cwd = utils::get_current_dir();
+
+        let Some(h) = History::started(settings, command, cwd) else {
+            return Ok(());
+        };
+
+        // print the ID
+        // we use this as the key for calling end
+        println!("{}", h.id);
+        database::save_start(settings, &h).await?;
+
+        Ok(())
+    }
+
+    async fn handle_cancel(
         db: &impl Database,
+        history_store: HistoryStore,
         settings: &Settings,
         command: &[String],
     ) -> Result<()> {
         let command = command.join(" ");
 
-        // It's better for atuin to silently fail here and attempt to
-        // store whatever is ran, than to throw an error to the terminal
+        if command.trim().is_empty() {
+            return Ok(());
+        }
+
         let cwd = utils::get_current_dir();
 
         let h: History = History::capture()
@@ -315,10 +469,13 @@ impl Cmd {
             return Ok(());
         }
 
-        // print the ID
-        // we use this as the key for calling end
-        println!("{}", h.id);
+        // it never ran, so it's finished as soon as it starts
+        let mut h = h.redacted(settings).with_env(settings);
+        h.exit = CANCELLED_EXIT;
+        h.duration = 0;
+
         db.save(&h).await?;
+        history_store.push(h).await?;
 
         Ok(())
     }
@@ -331,16 +488,9 @@ impl Cmd {
         // store whatever is ran, than to throw an error to the terminal
         let cwd = utils::get_current_dir();
 
-        let h: History = History::capture()
-            .timestamp(OffsetDateTime::now_utc())
-            .command(command)
-            .cwd(cwd)
-            .build()
-            .into();
-
-        if !h.should_save(settings) {
+        let Some(h) = History::started(settings, command, cwd) else {
             return Ok(());
-        }
+        };
 
         let resp = atuin_daemon::client::HistoryClient::new(
             #[cfg(not(unix))]
@@ -360,6 +510,7 @@ impl Cmd {
     }
 
     #[allow(unused_variables)]
+    #[allow(clippy::too_many_arguments)]
     async fn handle_end(
         db: &impl Database,
         store: SqliteStore,
@@ -368,6 +519,8 @@ impl Cmd {
         id: &str,
         exit: i64,
         duration: Option<u64>,
+        stdout_bytes: Option<u64>,
+        stderr_bytes: Option<u64>,
     ) -> Result<()> {
         if id.trim() == "" {
             return Ok(());
@@ -389,7 +542,7 @@ impl Cmd {
             debug!("history has non-zero exit code, and store_failed is false");
 
             // the history has already been inserted half complete. remove it
-            db.delete(h).await?;
+            db.delete(h.id).await?;
 
             return Ok(());
         }
@@ -400,21 +553,60 @@ impl Cmd {
             None => i64::try_from((OffsetDateTime::now_utc() - h.timestamp).whole_nanoseconds())
                 .context("command took over 292 years")?,
         };
+        h.stdout_bytes = stdout_bytes.map(i64::try_from).transpose()?;
+        h.stderr_bytes = stderr_bytes.map(i64::try_from).transpose()?;
 
         db.update(&h).await?;
         history_store.push(h).await?;
 
+        if settings.history.prune_on_save {
+            let pruned = retention::prune(settings, db, &history_store).await?;
+            debug!("pruned {pruned} expired history entries");
+        }
+
+        // a failed backup shouldn't fail the command, it's tried again after the next one
+        if settings.backup.auto && backup::due(settings).unwrap_or(false) {
+            if let Err(e) = backup::create(settings).await {
+                debug!("automatic backup failed: {e:?}");
+            }
+        }
+
         if settings.should_sync()? {
             #[cfg(feature = "sync")]
             {
-                if settings.sync.records {
-                    let (_, downloaded) = record::sync::sync(settings, &store).await?;
-                    Settings::save_sync_time()?;
-
-                    crate::sync::build(settings, &store, db, Some(&downloaded)).await?;
+                // Other shells may be finishing commands at the same time. Only one syncs, and
+                // the rest find it isn't due any more once it has.
+                let lock = record::sync::SyncLock::acquire();
+
+                if lock.is_none() {
+                    debug!("another sync is running, not syncing");
+                } else if !settings.should_sync()? {
+                    debug!("synced while taking the lock, not syncing");
+                } else if !record::sync::SyncState::load().retry_due() {
+                    // History is already saved locally, and will go up with the next sync that
+                    // reaches the server. Until then, back off rather than fail every command.
+                    debug!("backing off after failed syncs, not syncing");
+                } else if settings.sync.records {
+                    match record::sync::sync(settings, &store).await {
+                        Ok((_, downloaded)) => {
+                            Settings::save_sync_time()?;
+
+                            crate::sync::build(settings, &store, db, Some(&downloaded)).await?;
+                        }
+                        Err(e @ record::sync::SyncError::RemoteRequestError { .. }) => {
+                            debug!("sync failed, will retry later: {e}");
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
                 } else {
                     debug!("running periodic background sync");
-                    sync::sync(settings, false, db).await?;
+
+                    if let Err(e) = sync::sync(settings, false, db).await {
+                        debug!("sync failed, will retry later: {e:?}");
+                        record::sync::SyncState::failed();
+                    } else {
+                        record::sync::SyncState::succeeded();
+                    }
                 }
             }
             #[cfg(not(feature = "sync"))]
@@ -433,6 +625,8 @@ impl Cmd {
         id: &str,
         exit: i64,
         duration: Option<u64>,
+        stdout_bytes: Option<u64>,
+        stderr_bytes: Option<u64>,
     ) -> Result<()> {
         let resp = atuin_daemon::client::HistoryClient::new(
             #[cfg(not(unix))]
@@ -441,7 +635,13 @@ impl Cmd {
             settings.daemon.socket_path.clone(),
         )
         .await?
-        .end_history(id.to_string(), duration.unwrap_or(0), exit)
+        .end_history(
+            id.to_string(),
+            duration.unwrap_or(0),
+            exit,
+            stdout_bytes,
+            stderr_bytes,
+        )
         .await?;
 
         Ok(())
@@ -476,10 +676,7 @@ impl Cmd {
         print_list(
             &history,
             mode,
-            match format {
-                None => Some(settings.history_format.as_str()),
-                _ => format.as_deref(),
-            },
+            Some(settings.history_template(format.as_deref())),
             print0,
             reverse,
             tz,
@@ -491,20 +688,27 @@ impl Cmd {
     async fn handle_prune(
         db: &impl Database,
         settings: &Settings,
-        store: SqliteStore,
+        history_store: &HistoryStore,
         context: atuin_client::database::Context,
         dry_run: bool,
     ) -> Result<()> {
         // Grab all executed commands and filter them using History::should_save.
         // We could iterate or paginate here if memory usage becomes an issue.
-        let matches: Vec<History> = db
+        let filtered: Vec<History> = db
             .list(&[Global], &context, None, false, false)
             .await?
             .into_iter()
             .filter(|h| !h.should_save(settings))
             .collect();
 
-        match matches.len() {
+        // Then anything past the retention policy. These are archived first, if configured to.
+        let expired: Vec<History> = retention::expired(settings, db)
+            .await?
+            .into_iter()
+            .filter(|h| !filtered.iter().any(|f| f.id == h.id))
+            .collect();
+
+        match filtered.len() + expired.len() {
             0 => {
                 println!("No entries to prune.");
                 return Ok(());
@@ -514,70 +718,209 @@ impl Cmd {
         }
 
         if dry_run {
+            let mut matches = filtered;
+            matches.extend(expired);
+            matches.sort_by_key(|h| h.timestamp);
+
             print_list(
                 &matches,
                 ListMode::Human,
-                Some(settings.history_format.as_str()),
+                Some(settings.history_template(None)),
                 false,
                 false,
                 settings.timezone,
             );
         } else {
-            let encryption_key: [u8; 32] = encryption::load_key(settings)
-                .context("could not load encryption key")?
-                .into();
-            let host_id = Settings::host_id().expect("failed to get host_id");
-            let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
-
-            for entry in matches {
-                eprintln!("deleting {}", entry.id);
-                if settings.sync.records {
-                    let (id, _) = history_store.delete(entry.id.clone()).await?;
-                    history_store.incremental_build(db, &[id]).await?;
-                } else {
-                    db.delete(entry.clone()).await?;
-                }
+            if let Some(path) = retention::archive(settings, &expired)? {
+                println!("Archived {} entries to {}", expired.len(), path.display());
             }
+
+            Self::delete_entries(db, settings, history_store, filtered).await?;
+            Self::delete_entries(db, settings, history_store, expired).await?;
+        }
+        Ok(())
+    }
+
+    // Tags and notes are read back to search with, so they need the master key
+    fn master_key(settings: &Settings) -> Result<[u8; 32]> {
+        Ok(encryption::load_key(settings)
+            .context("could not load encryption key")?
+            .into())
+    }
+
+    async fn existing_id(db: &impl Database, id: String) -> Result<HistoryId> {
+        if db.load(&id).await?.is_none() {
+            bail!("there is no history with the id {id}");
+        }
+
+        Ok(id.into())
+    }
+
+    async fn handle_dedup(
+        db: &impl Database,
+        settings: &Settings,
+        history_store: &HistoryStore,
+        dry_run: bool,
+    ) -> Result<()> {
+        let dupes = db.duplicates().await?;
+
+        match dupes.len() {
+            0 => println!("No duplicate entries found."),
+            1 => println!("Found 1 duplicate entry."),
+            n => println!("Found {n} duplicate entries."),
         }
+
+        if dry_run {
+            print_list(
+                &dupes,
+                ListMode::Human,
+                Some(settings.history_template(None)),
+                false,
+                false,
+                settings.timezone,
+            );
+
+            return Ok(());
+        }
+
+        Self::delete_entries(db, settings, history_store, dupes).await?;
+
+        // Deleted rows stay behind as tombstones, but without their command, so there is still
+        // space to give back
+        println!("Compacting database...");
+        db.vacuum().await?;
+
         Ok(())
     }
 
+    async fn delete_entries(
+        db: &impl Database,
+        settings: &Settings,
+        history_store: &HistoryStore,
+        entries: Vec<History>,
+    ) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        for entry in &entries {
+            eprintln!("deleting {}", entry.id);
+        }
+
+        if !settings.sync.records {
+            let ids: Vec<_> = entries.into_iter().map(|h| h.id).collect();
+            db.delete_bulk(&ids).await?;
+            return Ok(());
+        }
+
+        let mut ids = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            let (id, _) = history_store.delete(entry.id).await?;
+            ids.push(id);
+        }
+
+        history_store.incremental_build(db, &ids).await
+    }
+
+    #[allow(clippy::too_many_lines)]
     pub async fn run(self, settings: &Settings) -> Result<()> {
         let context = current_context();
 
         #[cfg(feature = "daemon")]
-        // Skip initializing any databases for start/end, if the daemon is enabled
-        if settings.daemon.enabled {
+        // Skip initializing any databases for start/end, if the daemon is enabled. Incognito
+        // shells don't use it, as the daemon would keep their history.
+        if settings.daemon.enabled && !database::incognito() {
             match self {
                 Self::Start { command } => {
                     return Self::handle_daemon_start(settings, &command).await
                 }
 
-                Self::End { id, exit, duration } => {
-                    return Self::handle_daemon_end(settings, &id, exit, duration).await
+                Self::End {
+                    id,
+                    exit,
+                    duration,
+                    stdout_bytes,
+                    stderr_bytes,
+                } => {
+                    return Self::handle_daemon_end(
+                        settings,
+                        &id,
+                        exit,
+                        duration,
+                        stdout_bytes,
+                        stderr_bytes,
+                    )
+                    .await
                 }
 
                 _ => {}
             }
         }
 
-        let db_path = PathBuf::from(settings.db_path.as_str());
+        // Start runs before every command, so it saves the history and nothing else. Syncing
+        // and the record store wait until the command ends. Settings are still loaded in full,
+        // as the filters, secrets and history_env it checks can come from anywhere they can;
+        // zsh with the daemon running doesn't start atuin at all.
+        if let Self::Start { command } = &self {
+            return Self::handle_start(settings, command).await;
+        }
+
+        // an incognito shell's history was only ever in memory, so there's nothing to end.
+        // Cancelled commands would go straight to the database, so aren't kept either.
+        if database::incognito() && matches!(self, Self::End { .. } | Self::Cancel { .. }) {
+            return Ok(());
+        }
+
+        // the hooks run for every command, out of sight, so they can't be the ones to ask
+        if matches!(self, Self::End { .. } | Self::Cancel { .. })
+            && encryption::unlocks_every_run(settings)
+        {
+            bail!(
+                "the age key backend can't keep the key unlocked here, so it would be asked for \
+                 after every command. Use it on Linux with age.cache, or use another key_backend"
+            );
+        }
+
         let record_store_path = PathBuf::from(settings.record_store_path.as_str());
 
-        let db = Sqlite::new(db_path, settings.local_timeout).await?;
+        let db = database::open(settings).await?;
         let store = SqliteStore::new(record_store_path, settings.local_timeout).await?;
 
-        let encryption_key: [u8; 32] = encryption::load_key(settings)
-            .context("could not load encryption key")?
-            .into();
-
         let host_id = Settings::host_id().expect("failed to get host_id");
-        let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
+        let history_store =
+            match encryption::load_host_key(settings).context("could not load encryption key")? {
+                HostKey::Master(key) => {
+                    HistoryStore::from_settings(store.clone(), host_id, key, settings)
+                }
+                HostKey::Public(key) => HistoryStore::write_only(store.clone(), host_id, key)
+                    .with_strip(&settings.sync.strip),
+            };
 
         match self {
-            Self::Start { command } => Self::handle_start(&db, settings, &command).await,
-            Self::End { id, exit, duration } => {
-                Self::handle_end(&db, store, history_store, settings, &id, exit, duration).await
+            Self::Start { .. } => unreachable!("started above"),
+            Self::Cancel { command } => {
+                Self::handle_cancel(&db, history_store, settings, &command).await
+            }
+            Self::End {
+                id,
+                exit,
+                duration,
+                stdout_bytes,
+                stderr_bytes,
+            } => {
+                Self::handle_end(
+                    &db,
+                    store,
+                    history_store,
+                    settings,
+                    &id,
+                    exit,
+                    duration,
+                    stdout_bytes,
+                    stderr_bytes,
+                )
+                .await
             }
             Self::List {
                 session,
@@ -609,10 +952,7 @@ impl Cmd {
                 print_list(
                     last,
                     ListMode::from_flags(human, cmd_only),
-                    match format {
-                        None => Some(settings.history_format.as_str()),
-                        _ => format.as_deref(),
-                    },
+                    Some(settings.history_template(format.as_deref())),
                     false,
                     true,
                     tz,
@@ -624,8 +964,86 @@ impl Cmd {
             Self::InitStore => history_store.init_store(&db).await,
 
             Self::Prune { dry_run } => {
-                Self::handle_prune(&db, settings, store, context, dry_run).await
+                Self::handle_prune(&db, settings, &history_store, context, dry_run).await
+            }
+
+            Self::Dedup { dry_run } => {
+                Self::handle_dedup(&db, settings, &history_store, dry_run).await
+            }
+
+            Self::Star { id, remove } => {
+                let id = Self::existing_id(&db, id).await?;
+
+                history_store.star(&db, id, !remove).await
+            }
+
+            Self::Tag { id, tags, remove } => {
+                let id = Self::existing_id(&db, id).await?;
+                let annotations = AnnotationStore::new(store, host_id, Self::master_key(settings)?);
+
+                for tag in tags {
+                    if remove {
+                        annotations.untag(&db, id.clone(), &tag).await?;
+                    } else {
+                        annotations.tag(&db, id.clone(), &tag).await?;
+                    }
+                }
+
+                Ok(())
+            }
+
+            Self::Note { id, note } => {
+                let id = Self::existing_id(&db, id).await?;
+
+                AnnotationStore::new(store, host_id, Self::master_key(settings)?)
+                    .note(&db, id, &note)
+                    .await
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use atuin_client::{history::History, settings::Timezone};
+    use time::macros::datetime;
+
+    use super::JsonHistory;
+
+    #[test]
+    fn json_history() {
+        let mut history: History = History::import()
+            .timestamp(datetime!(2024-05-01 09:30 UTC))
+            .command("cargo build ")
+            .cwd("/src/atuin")
+            .exit(0)
+            .duration(1_500_000_000)
+            .session("s1")
+            .hostname("laptop:ellie")
+            .build()
+            .into();
+        // otherwise it's wherever the tests run, like a container
+        history.namespace = "work".to_string();
+
+        let json = serde_json::to_value(JsonHistory::new(&history, Timezone(time::UtcOffset::UTC)))
+            .unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "id": history.id.0,
+                "timestamp": "2024-05-01T09:30:00Z",
+                "duration": 1_500_000_000,
+                "exit": 0,
+                "command": "cargo build",
+                "directory": "/src/atuin",
+                "session": "s1",
+                "host": "laptop",
+                "user": "ellie",
+                "namespace": "work",
+                "branch": "",
+                "env": {},
+            })
+        );
+    }
+}
@@ -2,17 +2,20 @@ use std::env;
 
 use async_trait::async_trait;
 use clap::Parser;
-use eyre::Result;
+use eyre::{bail, Result};
 use indicatif::ProgressBar;
 
 use atuin_client::{
     database::Database,
     history::History,
     import::{
-        bash::Bash, fish::Fish, nu::Nu, nu_histdb::NuHistDb, replxx::Replxx, resh::Resh,
-        xonsh::Xonsh, xonsh_sqlite::XonshSqlite, zsh::Zsh, zsh_histdb::ZshHistDb, Importer, Loader,
+        bash::Bash, fish::Fish, json::Json, nu::Nu, nu_histdb::NuHistDb, powershell::Powershell,
+        replxx::Replxx, resh::Resh, xonsh::Xonsh, xonsh_sqlite::XonshSqlite, zsh::Zsh,
+        zsh_histdb::ZshHistDb, Importer, Loader,
     },
+    settings::Settings,
 };
+use atuin_common::{detail, output, status};
 
 #[derive(Parser, Debug)]
 #[command(infer_subcommands = true)]
@@ -33,26 +36,44 @@ pub enum Cmd {
     /// Import history from the fish history file
     Fish,
     /// Import history from the nu history file
+    #[command(alias = "nushell")]
     Nu,
     /// Import history from the nu history file
     NuHistDb,
+    /// Import history from the powershell history file
+    #[command(alias = "pwsh")]
+    Powershell,
     /// Import history from xonsh json files
     Xonsh,
     /// Import history from xonsh sqlite db
     XonshSqlite,
+    /// Import history from newline-delimited json on stdin
+    ///
+    /// Each line is an object with these fields, of which only command and timestamp are
+    /// required:
+    ///
+    ///   command    the command line, as a string
+    ///   timestamp  when it ran, in seconds since the unix epoch or as an RFC 3339 string
+    ///   duration   how long it ran, in nanoseconds
+    ///   exit       its exit code
+    ///   cwd        the directory it ran in
+    ///   session    any string, entries that share one are imported into the same session
+    ///   hostname   the machine it ran on, as host:user
+    ///
+    /// Nothing is imported if any line is invalid.
+    #[command(verbatim_doc_comment)]
+    Json,
 }
 
-const BATCH_SIZE: usize = 100;
-
 impl Cmd {
-    pub async fn run<DB: Database>(&self, db: &DB) -> Result<()> {
-        println!("        Atuin         ");
-        println!("======================");
-        println!("          \u{1f30d}          ");
-        println!("       \u{1f418}\u{1f418}\u{1f418}\u{1f418}       ");
-        println!("          \u{1f422}          ");
-        println!("======================");
-        println!("Importing history...");
+    pub async fn run<DB: Database>(&self, db: &DB, settings: &Settings) -> Result<()> {
+        status!("        Atuin         ");
+        status!("======================");
+        status!("          \u{1f30d}          ");
+        status!("       \u{1f418}\u{1f418}\u{1f418}\u{1f418}       ");
+        status!("          \u{1f422}          ");
+        status!("======================");
+        status!("Importing history...");
 
         match self {
             Self::Auto => {
@@ -67,55 +88,60 @@ impl Cmd {
                 let shell = env::var("SHELL").unwrap_or_else(|_| String::from("NO_SHELL"));
 
                 if xonsh_histfile.to_lowercase().ends_with(".json") {
-                    println!("Detected Xonsh",);
-                    import::<Xonsh, DB>(db).await
+                    status!("Detected Xonsh",);
+                    import::<Xonsh, DB>(db, settings).await
                 } else if xonsh_histfile.to_lowercase().ends_with(".sqlite") {
-                    println!("Detected Xonsh (SQLite backend)");
-                    import::<XonshSqlite, DB>(db).await
+                    status!("Detected Xonsh (SQLite backend)");
+                    import::<XonshSqlite, DB>(db, settings).await
                 } else if shell.ends_with("/zsh") {
                     if ZshHistDb::histpath().is_ok() {
-                        println!(
+                        status!(
                             "Detected Zsh-HistDb, using :{}",
                             ZshHistDb::histpath().unwrap().to_str().unwrap()
                         );
-                        import::<ZshHistDb, DB>(db).await
+                        import::<ZshHistDb, DB>(db, settings).await
                     } else {
-                        println!("Detected ZSH");
-                        import::<Zsh, DB>(db).await
+                        status!("Detected ZSH");
+                        import::<Zsh, DB>(db, settings).await
                     }
                 } else if shell.ends_with("/fish") {
-                    println!("Detected Fish");
-                    import::<Fish, DB>(db).await
+                    status!("Detected Fish");
+                    import::<Fish, DB>(db, settings).await
                 } else if shell.ends_with("/bash") {
-                    println!("Detected Bash");
-                    import::<Bash, DB>(db).await
+                    status!("Detected Bash");
+                    import::<Bash, DB>(db, settings).await
                 } else if shell.ends_with("/nu") {
                     if NuHistDb::histpath().is_ok() {
-                        println!(
+                        status!(
                             "Detected Nu-HistDb, using :{}",
                             NuHistDb::histpath().unwrap().to_str().unwrap()
                         );
-                        import::<NuHistDb, DB>(db).await
+                        import::<NuHistDb, DB>(db, settings).await
                     } else {
-                        println!("Detected Nushell");
-                        import::<Nu, DB>(db).await
+                        status!("Detected Nushell");
+                        import::<Nu, DB>(db, settings).await
                     }
+                } else if shell.ends_with("/pwsh") {
+                    status!("Detected PowerShell");
+                    import::<Powershell, DB>(db, settings).await
                 } else {
                     println!("cannot import {shell} history");
                     Ok(())
                 }
             }
 
-            Self::Zsh => import::<Zsh, DB>(db).await,
-            Self::ZshHistDb => import::<ZshHistDb, DB>(db).await,
-            Self::Bash => import::<Bash, DB>(db).await,
-            Self::Replxx => import::<Replxx, DB>(db).await,
-            Self::Resh => import::<Resh, DB>(db).await,
-            Self::Fish => import::<Fish, DB>(db).await,
-            Self::Nu => import::<Nu, DB>(db).await,
-            Self::NuHistDb => import::<NuHistDb, DB>(db).await,
-            Self::Xonsh => import::<Xonsh, DB>(db).await,
-            Self::XonshSqlite => import::<XonshSqlite, DB>(db).await,
+            Self::Zsh => import::<Zsh, DB>(db, settings).await,
+            Self::ZshHistDb => import::<ZshHistDb, DB>(db, settings).await,
+            Self::Bash => import::<Bash, DB>(db, settings).await,
+            Self::Replxx => import::<Replxx, DB>(db, settings).await,
+            Self::Resh => import::<Resh, DB>(db, settings).await,
+            Self::Fish => import::<Fish, DB>(db, settings).await,
+            Self::Nu => import::<Nu, DB>(db, settings).await,
+            Self::NuHistDb => import::<NuHistDb, DB>(db, settings).await,
+            Self::Powershell => import::<Powershell, DB>(db, settings).await,
+            Self::Xonsh => import::<Xonsh, DB>(db, settings).await,
+            Self::XonshSqlite => import::<XonshSqlite, DB>(db, settings).await,
+            Self::Json => import::<Json, DB>(db, settings).await,
         }
     }
 }
@@ -124,23 +150,59 @@ pub struct HistoryImporter<'db, DB: Database> {
     pb: ProgressBar,
     buf: Vec<History>,
     db: &'db DB,
+    settings: &'db Settings,
+    imported: u64,
+    existing: u64,
+    skipped: usize,
+    failed: usize,
 }
 
 impl<'db, DB: Database> HistoryImporter<'db, DB> {
-    fn new(db: &'db DB, len: usize) -> Self {
+    fn new(db: &'db DB, settings: &'db Settings, len: usize) -> Self {
         Self {
-            pb: ProgressBar::new(len as u64),
-            buf: Vec::with_capacity(BATCH_SIZE),
+            pb: if output::is_quiet() {
+                ProgressBar::hidden()
+            } else {
+                ProgressBar::new(len as u64)
+            },
+            buf: Vec::with_capacity(settings.import.batch_size.max(1)),
             db,
+            settings,
+            imported: 0,
+            existing: 0,
+            skipped: 0,
+            failed: 0,
+        }
+    }
+
+    /// Save the buffered entries in one transaction. A batch that fails to save is counted, and
+    /// the import carries on with the next one.
+    async fn save(&mut self) {
+        match self.db.save_bulk(&self.buf).await {
+            Ok(saved) => {
+                self.imported += saved;
+                self.existing += self.buf.len() as u64 - saved;
+                self.pb.suspend(|| {
+                    detail!("Saved {saved} new entries of {}", self.buf.len());
+                });
+            }
+            Err(e) => {
+                self.failed += self.buf.len();
+                self.pb.suspend(|| {
+                    detail!("Failed to save {} entries: {e}", self.buf.len());
+                });
+            }
         }
+
+        self.buf.clear();
     }
 
-    async fn flush(self) -> Result<()> {
+    async fn flush(mut self) -> Self {
         if !self.buf.is_empty() {
-            self.db.save_bulk(&self.buf).await?;
+            self.save().await;
         }
         self.pb.finish();
-        Ok(())
+        self
     }
 }
 
@@ -148,24 +210,52 @@ impl<'db, DB: Database> HistoryImporter<'db, DB> {
 impl<'db, DB: Database> Loader for HistoryImporter<'db, DB> {
     async fn push(&mut self, hist: History) -> Result<()> {
         self.pb.inc(1);
-        self.buf.push(hist);
-        if self.buf.len() == self.buf.capacity() {
-            self.db.save_bulk(&self.buf).await?;
-            self.buf.clear();
+
+        // imported history goes through the same filters as history recorded by the shell
+        if !hist.should_save(self.settings) {
+            self.skipped += 1;
+            return Ok(());
+        }
+
+        self.buf.push(hist.redacted(self.settings));
+        if self.buf.len() >= self.settings.import.batch_size.max(1) {
+            self.save().await;
         }
         Ok(())
     }
 }
 
-async fn import<I: Importer + Send, DB: Database>(db: &DB) -> Result<()> {
-    println!("Importing history from {}", I::NAME);
+async fn import<I: Importer + Send, DB: Database>(db: &DB, settings: &Settings) -> Result<()> {
+    status!("Importing history from {}", I::NAME);
 
     let mut importer = I::new().await?;
     let len = importer.entries().await.unwrap();
-    let mut loader = HistoryImporter::new(db, len);
+    detail!("Found {len} entries");
+    let mut loader = HistoryImporter::new(db, settings, len);
     importer.load(&mut loader).await?;
-    loader.flush().await?;
 
-    println!("Import complete!");
+    let loader = loader.flush().await;
+
+    status!("Imported {} new entries", loader.imported);
+    if loader.existing > 0 {
+        status!(
+            "Skipped {} entries that were already imported",
+            loader.existing
+        );
+    }
+    if loader.skipped > 0 {
+        status!(
+            "Skipped {} entries matching your history filters",
+            loader.skipped
+        );
+    }
+    if loader.failed > 0 {
+        bail!(
+            "{} entries failed to save, run the import again to retry them",
+            loader.failed
+        );
+    }
+
+    status!("Import complete!");
     Ok(())
 }
@@ -0,0 +1,43 @@
+use std::{env, process::Command};
+
+use clap::Parser;
+use eyre::{bail, Result, WrapErr};
+
+use atuin_client::database;
+use atuin_common::status;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// The shell to start. Defaults to $SHELL
+    #[arg(long)]
+    shell: Option<String>,
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        if database::incognito() {
+            bail!("this shell is already incognito");
+        }
+
+        let shell = self
+            .shell
+            .or_else(|| env::var("SHELL").ok())
+            .unwrap_or_else(|| "sh".to_string());
+
+        status!("History from this shell won't be saved. Exit it to leave incognito.");
+
+        // the shell's own session hooks pick this up, and keep its history in memory
+        let exit = Command::new(&shell)
+            .env("ATUIN_INCOGNITO", "1")
+            .status()
+            .wrap_err_with(|| format!("could not start {shell}"))?;
+
+        status!("Left incognito.");
+
+        if !exit.success() {
+            std::process::exit(exit.code().unwrap_or(1));
+        }
+
+        Ok(())
+    }
+}
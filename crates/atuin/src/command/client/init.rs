@@ -1,12 +1,17 @@
 use std::path::PathBuf;
 
-use atuin_client::{encryption, record::sqlite_store::SqliteStore, settings::Settings};
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_client::{
+    encryption, history::History, record::sqlite_store::SqliteStore, settings::Settings,
+};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 use clap::{Parser, ValueEnum};
 use eyre::{Result, WrapErr};
 
 mod bash;
+mod elvish;
 mod fish;
+mod nu;
+mod powershell;
 mod xonsh;
 mod zsh;
 
@@ -32,57 +37,18 @@ pub enum Shell {
     /// Fish setup
     Fish,
     /// Nu setup
+    #[value(alias = "nushell")]
     Nu,
     /// Xonsh setup
     Xonsh,
+    /// Powershell setup
+    #[value(alias = "pwsh")]
+    Powershell,
+    /// Elvish setup
+    Elvish,
 }
 
 impl Cmd {
-    fn init_nu(&self) {
-        let full = include_str!("../../shell/atuin.nu");
-        println!("{full}");
-
-        if std::env::var("ATUIN_NOBIND").is_err() {
-            const BIND_CTRL_R: &str = r"$env.config = (
-    $env.config | upsert keybindings (
-        $env.config.keybindings
-        | append {
-            name: atuin
-            modifier: control
-            keycode: char_r
-            mode: [emacs, vi_normal, vi_insert]
-            event: { send: executehostcommand cmd: (_atuin_search_cmd) }
-        }
-    )
-)";
-            const BIND_UP_ARROW: &str = r"
-$env.config = (
-    $env.config | upsert keybindings (
-        $env.config.keybindings
-        | append {
-            name: atuin
-            modifier: none
-            keycode: up
-            mode: [emacs, vi_normal, vi_insert]
-            event: {
-                until: [
-                    {send: menuup}
-                    {send: executehostcommand cmd: (_atuin_search_cmd '--shell-up-key-binding') }
-                ]
-            }
-        }
-    )
-)
-";
-            if !self.disable_ctrl_r {
-                println!("{BIND_CTRL_R}");
-            }
-            if !self.disable_up_arrow {
-                println!("{BIND_UP_ARROW}");
-            }
-        }
-    }
-
     fn static_init(&self) {
         match self.shell {
             Shell::Zsh => {
@@ -95,11 +61,17 @@ $env.config = (
                 fish::init_static(self.disable_up_arrow, self.disable_ctrl_r);
             }
             Shell::Nu => {
-                self.init_nu();
+                nu::init_static(self.disable_up_arrow, self.disable_ctrl_r);
             }
             Shell::Xonsh => {
                 xonsh::init_static(self.disable_up_arrow, self.disable_ctrl_r);
             }
+            Shell::Powershell => {
+                powershell::init_static(self.disable_up_arrow, self.disable_ctrl_r);
+            }
+            Shell::Elvish => {
+                elvish::init_static(self.disable_up_arrow, self.disable_ctrl_r);
+            }
         };
     }
 
@@ -114,12 +86,14 @@ $env.config = (
 
         let alias_store = AliasStore::new(sqlite_store.clone(), host_id, encryption_key);
         let var_store = VarStore::new(sqlite_store.clone(), host_id, encryption_key);
+        let snippet_store = SnippetStore::new(sqlite_store.clone(), host_id, encryption_key);
 
         match self.shell {
             Shell::Zsh => {
                 zsh::init(
                     alias_store,
                     var_store,
+                    snippet_store,
                     self.disable_up_arrow,
                     self.disable_ctrl_r,
                 )
@@ -129,6 +103,7 @@ $env.config = (
                 bash::init(
                     alias_store,
                     var_store,
+                    snippet_store,
                     self.disable_up_arrow,
                     self.disable_ctrl_r,
                 )
@@ -136,6 +111,16 @@ $env.config = (
             }
             Shell::Fish => {
                 fish::init(
+                    alias_store,
+                    var_store,
+                    snippet_store,
+                    self.disable_up_arrow,
+                    self.disable_ctrl_r,
+                )
+                .await?;
+            }
+            Shell::Nu => {
+                nu::init(
                     alias_store,
                     var_store,
                     self.disable_up_arrow,
@@ -143,7 +128,6 @@ $env.config = (
                 )
                 .await?;
             }
-            Shell::Nu => self.init_nu(),
             Shell::Xonsh => {
                 xonsh::init(
                     alias_store,
@@ -153,18 +137,51 @@ $env.config = (
                 )
                 .await?;
             }
+            // aliases and vars aren't written for powershell or elvish yet
+            Shell::Powershell => {
+                powershell::init_static(self.disable_up_arrow, self.disable_ctrl_r);
+            }
+            Shell::Elvish => {
+                elvish::init_static(self.disable_up_arrow, self.disable_ctrl_r);
+            }
         }
 
         Ok(())
     }
 
-    pub async fn run(self, settings: &Settings) -> Result<()> {
+    pub async fn run(mut self, settings: &Settings) -> Result<()> {
+        // the config is read each time a shell starts, so bindings can change without touching
+        // rc files. Either it or the flags can turn one off.
+        self.disable_ctrl_r |= !settings.keys.ctrl_r;
+        self.disable_up_arrow |= !settings.keys.up_arrow;
+
         if settings.dotfiles.enabled {
             self.dotfiles_init(settings).await?;
         } else {
             self.static_init();
         }
 
+        // zsh can talk to the daemon without starting a process. The daemon can't see the
+        // shell's environment, so a shell that records any of it (history_env, a namespace,
+        // a pane, a nix shell or a container) still needs atuin.
+        if matches!(self.shell, Shell::Zsh)
+            && cfg!(unix)
+            && settings.daemon.enabled
+            && !History::records_env(settings)
+        {
+            let path = settings.daemon.shell_socket_path.replace('\'', r"'\''");
+            println!("__atuin_daemon_socket='{path}'");
+        }
+
+        // read by the hooks as they run, so the terminal's own integration can be loaded later
+        if settings.prompt_marks {
+            match self.shell {
+                Shell::Zsh | Shell::Bash => println!("__atuin_prompt_marks=1"),
+                Shell::Fish => println!("set -g __atuin_prompt_marks 1"),
+                _ => {}
+            }
+        }
+
         Ok(())
     }
 }
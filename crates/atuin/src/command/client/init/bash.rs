@@ -1,6 +1,15 @@
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 use eyre::Result;
 
+// Applies directory profiles before each prompt where the directory has changed. Bash has no
+// hook for cd itself.
+const PROFILE_HOOK: &str = r#"__atuin_profile() {
+    [[ $PWD == "${__atuin_profile_pwd-}" ]] && return
+    __atuin_profile_pwd=$PWD
+    eval "$(atuin dotfiles profile hook bash --vars "${__atuin_profile_vars-}" --aliases "${__atuin_profile_aliases-}")"
+}
+precmd_functions+=(__atuin_profile)"#;
+
 pub fn init_static(disable_up_arrow: bool, disable_ctrl_r: bool) {
     let base = include_str!("../../../shell/atuin.bash");
 
@@ -18,6 +27,7 @@ pub fn init_static(disable_up_arrow: bool, disable_ctrl_r: bool) {
 pub async fn init(
     aliases: AliasStore,
     vars: VarStore,
+    snippets: SnippetStore,
     disable_up_arrow: bool,
     disable_ctrl_r: bool,
 ) -> Result<()> {
@@ -25,9 +35,12 @@ pub async fn init(
 
     let aliases = atuin_dotfiles::shell::bash::alias_config(&aliases).await;
     let vars = atuin_dotfiles::shell::bash::var_config(&vars).await;
+    let snippets = atuin_dotfiles::shell::bash::snippet_config(&snippets).await;
 
     println!("{aliases}");
     println!("{vars}");
+    println!("{snippets}");
+    println!("{PROFILE_HOOK}");
 
     Ok(())
 }
@@ -0,0 +1,13 @@
+pub fn init_static(disable_up_arrow: bool, disable_ctrl_r: bool) {
+    let base = include_str!("../../../shell/atuin.elv");
+
+    let (bind_ctrl_r, bind_up_arrow) = if std::env::var("ATUIN_NOBIND").is_ok() {
+        (false, false)
+    } else {
+        (!disable_ctrl_r, !disable_up_arrow)
+    };
+
+    println!("var __atuin_bind_ctrl_r = ${bind_ctrl_r}");
+    println!("var __atuin_bind_up_arrow = ${bind_up_arrow}");
+    println!("{base}");
+}
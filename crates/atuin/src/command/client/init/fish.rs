@@ -1,6 +1,13 @@
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 use eyre::Result;
 
+// Applies directory profiles whenever the directory changes, and once for the directory the
+// shell starts in
+const PROFILE_HOOK: &str = r#"function _atuin_profile --on-variable PWD
+    atuin dotfiles profile hook fish --vars "$__atuin_profile_vars" --aliases "$__atuin_profile_aliases" | source
+end
+_atuin_profile"#;
+
 pub fn init_static(disable_up_arrow: bool, disable_ctrl_r: bool) {
     let base = include_str!("../../../shell/atuin.fish");
 
@@ -37,6 +44,7 @@ bind -M insert \e\[A _atuin_bind_up";
 pub async fn init(
     aliases: AliasStore,
     vars: VarStore,
+    snippets: SnippetStore,
     disable_up_arrow: bool,
     disable_ctrl_r: bool,
 ) -> Result<()> {
@@ -44,9 +52,12 @@ pub async fn init(
 
     let aliases = atuin_dotfiles::shell::fish::alias_config(&aliases).await;
     let vars = atuin_dotfiles::shell::fish::var_config(&vars).await;
+    let snippets = atuin_dotfiles::shell::fish::snippet_config(&snippets).await;
 
     println!("{aliases}");
     println!("{vars}");
+    println!("{snippets}");
+    println!("{PROFILE_HOOK}");
 
     Ok(())
 }
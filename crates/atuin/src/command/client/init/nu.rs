@@ -0,0 +1,71 @@
+use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use eyre::Result;
+
+const BIND_CTRL_R: &str = r"$env.config = (
+    $env.config | upsert keybindings (
+        $env.config.keybindings
+        | append {
+            name: atuin
+            modifier: control
+            keycode: char_r
+            mode: [emacs, vi_normal, vi_insert]
+            event: { send: executehostcommand cmd: (_atuin_search_cmd) }
+        }
+    )
+)";
+
+const BIND_UP_ARROW: &str = r"
+$env.config = (
+    $env.config | upsert keybindings (
+        $env.config.keybindings
+        | append {
+            name: atuin
+            modifier: none
+            keycode: up
+            mode: [emacs, vi_normal, vi_insert]
+            event: {
+                until: [
+                    {send: menuup}
+                    {send: executehostcommand cmd: (_atuin_search_cmd '--shell-up-key-binding') }
+                ]
+            }
+        }
+    )
+)
+";
+
+pub fn init_static(disable_up_arrow: bool, disable_ctrl_r: bool) {
+    let base = include_str!("../../../shell/atuin.nu");
+
+    let (bind_ctrl_r, bind_up_arrow) = if std::env::var("ATUIN_NOBIND").is_ok() {
+        (false, false)
+    } else {
+        (!disable_ctrl_r, !disable_up_arrow)
+    };
+
+    println!("{base}");
+
+    if bind_ctrl_r {
+        println!("{BIND_CTRL_R}");
+    }
+    if bind_up_arrow {
+        println!("{BIND_UP_ARROW}");
+    }
+}
+
+pub async fn init(
+    aliases: AliasStore,
+    vars: VarStore,
+    disable_up_arrow: bool,
+    disable_ctrl_r: bool,
+) -> Result<()> {
+    init_static(disable_up_arrow, disable_ctrl_r);
+
+    let aliases = atuin_dotfiles::shell::nu::alias_config(&aliases).await;
+    let vars = atuin_dotfiles::shell::nu::var_config(&vars).await;
+
+    println!("{aliases}");
+    println!("{vars}");
+
+    Ok(())
+}
@@ -1,6 +1,14 @@
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 use eyre::Result;
 
+// Applies directory profiles whenever the directory changes, and once for the directory the
+// shell starts in
+const PROFILE_HOOK: &str = r#"_atuin_profile() {
+    eval "$(atuin dotfiles profile hook zsh --vars "${__atuin_profile_vars-}" --aliases "${__atuin_profile_aliases-}")"
+}
+add-zsh-hook chpwd _atuin_profile
+_atuin_profile"#;
+
 pub fn init_static(disable_up_arrow: bool, disable_ctrl_r: bool) {
     let base = include_str!("../../../shell/atuin.zsh");
 
@@ -31,6 +39,7 @@ bindkey -M vicmd 'k' atuin-up-search-vicmd";
 pub async fn init(
     aliases: AliasStore,
     vars: VarStore,
+    snippets: SnippetStore,
     disable_up_arrow: bool,
     disable_ctrl_r: bool,
 ) -> Result<()> {
@@ -38,9 +47,12 @@ pub async fn init(
 
     let aliases = atuin_dotfiles::shell::zsh::alias_config(&aliases).await;
     let vars = atuin_dotfiles::shell::zsh::var_config(&vars).await;
+    let snippets = atuin_dotfiles::shell::zsh::snippet_config(&snippets).await;
 
     println!("{aliases}");
     println!("{vars}");
+    println!("{snippets}");
+    println!("{PROFILE_HOOK}");
 
     Ok(())
 }
@@ -1,39 +1,91 @@
 use clap::Subcommand;
-use eyre::{Context, Result};
+use eyre::{bail, Context, Result};
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
 
-use atuin_client::{encryption, kv::KvStore, record::store::Store, settings::Settings};
+use atuin_client::{
+    encryption,
+    kv::{KvRecord, KvStore},
+    record::store::Store,
+    settings::Settings,
+};
+
+use super::format::Format;
 
 #[derive(Subcommand, Debug)]
 #[command(infer_subcommands = true)]
 pub enum Cmd {
-    // atuin kv set foo bar bar
+    /// Set a key (eg atuin kv set --namespace deploy region eu-west-1)
     Set {
-        #[arg(long, short)]
         key: String,
 
+        value: String,
+
         #[arg(long, short, default_value = "default")]
         namespace: String,
 
-        value: String,
+        /// Expire the key after this long (eg 1h, 30m, 2days)
+        #[arg(long, value_parser = humantime::parse_duration)]
+        ttl: Option<std::time::Duration>,
     },
 
-    // atuin kv get foo => bar baz
+    /// Print the value of a key
     Get {
         key: String,
 
         #[arg(long, short, default_value = "default")]
         namespace: String,
+
+        /// Print the value, or json or ndjson for other tools to read
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
     },
 
-    List {
+    /// Delete a key. The deletion syncs to your other machines.
+    #[command(alias = "rm")]
+    Delete {
+        key: String,
+
         #[arg(long, short, default_value = "default")]
         namespace: String,
+    },
+
+    /// List the keys in a namespace, or every key as namespace.key
+    #[command(alias = "ls")]
+    List {
+        namespace: Option<String>,
+
+        /// Print values too
+        #[arg(long)]
+        values: bool,
 
-        #[arg(long, short)]
-        all_namespaces: bool,
+        /// Print the keys, or json or ndjson (which always have values) for other tools to read
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
     },
 }
 
+/// A key as `--format json` prints it
+#[derive(Serialize)]
+struct JsonKv<'a> {
+    namespace: &'a str,
+    key: &'a str,
+    value: &'a str,
+    /// RFC 3339, if the key was set with a ttl
+    expires: Option<String>,
+}
+
+impl<'a> From<&'a KvRecord> for JsonKv<'a> {
+    fn from(kv: &'a KvRecord) -> Self {
+        Self {
+            namespace: &kv.namespace,
+            key: &kv.key,
+            value: &kv.value,
+            expires: kv.expires.and_then(|t| t.format(&Rfc3339).ok()),
+        }
+    }
+}
+
 impl Cmd {
     pub async fn run(&self, settings: &Settings, store: &(impl Store + Send + Sync)) -> Result<()> {
         let kv_store = KvStore::new();
@@ -49,43 +101,81 @@ impl Cmd {
                 key,
                 value,
                 namespace,
+                ttl: None,
             } => {
                 kv_store
                     .set(store, &encryption_key, host_id, namespace, key, value)
                     .await
             }
 
-            Self::Get { key, namespace } => {
+            Self::Set {
+                key,
+                value,
+                namespace,
+                ttl: Some(ttl),
+            } => {
+                let ttl = time::Duration::try_from(*ttl).context("ttl is too long")?;
+
+                kv_store
+                    .set_with_ttl(store, &encryption_key, host_id, namespace, key, value, ttl)
+                    .await
+            }
+
+            Self::Get {
+                key,
+                namespace,
+                format,
+            } => {
                 let val = kv_store.get(store, &encryption_key, namespace, key).await?;
 
-                if let Some(kv) = val {
-                    println!("{}", kv.value);
+                match (val, format) {
+                    (Some(kv), Format::Text) => println!("{}", kv.value),
+                    (None, Format::Text) => {}
+                    // null when there's no such key
+                    (val, format) => format.print(&val.as_ref().map(JsonKv::from))?,
                 }
 
                 Ok(())
             }
 
+            Self::Delete { key, namespace } => {
+                if kv_store
+                    .get(store, &encryption_key, namespace, key)
+                    .await?
+                    .is_none()
+                {
+                    bail!("no key {key} in namespace {namespace}");
+                }
+
+                kv_store
+                    .delete(store, &encryption_key, host_id, namespace, key)
+                    .await
+            }
+
             Self::List {
                 namespace,
-                all_namespaces,
+                values,
+                format,
             } => {
-                // TODO: don't rebuild this every time lol
-                let map = kv_store.build_kv(store, &encryption_key).await?;
-
-                // slower, but sorting is probably useful
-                if *all_namespaces {
-                    for (ns, kv) in &map {
-                        for k in kv.keys() {
-                            println!("{ns}.{k}");
-                        }
-                    }
-                } else {
-                    let ns = map.get(namespace);
+                let entries = kv_store
+                    .list(store, &encryption_key, namespace.as_deref())
+                    .await?;
+
+                if !format.is_text() {
+                    return format.print_all(entries.iter().map(JsonKv::from));
+                }
 
-                    if let Some(ns) = ns {
-                        for k in ns.keys() {
-                            println!("{k}");
-                        }
+                for kv in entries {
+                    let name = if namespace.is_some() {
+                        kv.key
+                    } else {
+                        format!("{}.{}", kv.namespace, kv.key)
+                    };
+
+                    if *values {
+                        println!("{name}\t{}", kv.value);
+                    } else {
+                        println!("{name}");
                     }
                 }
 
@@ -0,0 +1,153 @@
+//! Search in a pane of its own in wezterm or zellij, for the command picked to go to the pane
+//! it was opened from. Unlike `atuin tmux-popup`, these run in the new pane, as neither
+//! multiplexer waits for a pane it opens.
+
+use std::{
+    env,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use eyre::{bail, Result, WrapErr};
+
+/// Pick a command with `atuin search`, run in this pane as if from the pane `var` names, for
+/// the pane filter mode. None leaves the pane filter mode out, rather than searching this one.
+/// Returns nothing if the search was cancelled.
+fn pick(search_args: &[String], var: &str, pane: Option<&str>) -> Result<String> {
+    let mut search = Command::new(env::current_exe()?);
+    search
+        .args(["search", "-i", "--print0"])
+        .args(search_args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    match pane {
+        Some(pane) => search.env(var, pane),
+        None => search.env_remove(var),
+    };
+
+    let output = search.output().wrap_err("could not run atuin search")?;
+
+    // it exits with 1 when nothing was picked
+    match output.status.code() {
+        Some(0 | 1) => {}
+        _ => bail!("atuin search failed"),
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\0')
+        .to_string())
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .wrap_err_with(|| format!("could not run {program}"))?;
+
+    if !status.success() {
+        bail!("{program} {} failed", args.join(" "));
+    }
+
+    Ok(())
+}
+
+/// Search in a wezterm pane, and paste the command picked into the pane it was opened from
+///
+/// Bind it in wezterm.lua with
+///
+///     { key = 'r', mods = 'CTRL|ALT', action = wezterm.action_callback(function(window, pane)
+///       window:perform_action(wezterm.action.SplitPane {
+///         direction = 'Down',
+///         size = { Percent = 40 },
+///         command = { args = { 'atuin', 'wezterm-popup', '--pane', tostring(pane:pane_id()) } },
+///       }, pane)
+///     end) },
+#[derive(Parser, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct Wezterm {
+    /// The pane to paste into, and search the history of with the pane filter mode
+    #[arg(long)]
+    pane: String,
+
+    /// Options for `atuin search`, such as `--filter-mode pane`
+    #[arg(last = true)]
+    search_args: Vec<String>,
+}
+
+impl Wezterm {
+    pub fn run(self) -> Result<()> {
+        let selected = pick(&self.search_args, "WEZTERM_PANE", Some(&self.pane))?;
+
+        if !selected.is_empty() {
+            // send-text pastes, so a command over several lines isn't run a line at a time
+            run(
+                "wezterm",
+                &["cli", "send-text", "--pane-id", &self.pane, "--", &selected],
+            )?;
+        }
+
+        run(
+            "wezterm",
+            &["cli", "activate-pane", "--pane-id", &self.pane],
+        )
+    }
+}
+
+/// Search in a zellij floating pane, and type the command picked into the pane under it
+///
+/// Bind it in zellij's config.kdl with
+///
+///     bind "Ctrl r" {
+///         Run "atuin" "zellij-popup" { floating true; close_on_exit true; }
+///         SwitchToMode "Normal"
+///     }
+///
+/// Plugins can pass the pane they opened it from with --pane, and read the command picked with
+/// --stdout.
+#[derive(Parser, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct Zellij {
+    /// The pane it was opened from, to search the history of with the pane filter mode. Without
+    /// it, the pane filter mode is skipped
+    #[arg(long)]
+    pane: Option<String>,
+
+    /// Print the command picked, rather than typing it into the pane under this one
+    #[arg(long)]
+    stdout: bool,
+
+    /// Options for `atuin search`, such as `--filter-mode pane`
+    #[arg(last = true)]
+    search_args: Vec<String>,
+}
+
+impl Zellij {
+    pub fn run(self) -> Result<()> {
+        let selected = pick(&self.search_args, "ZELLIJ_PANE_ID", self.pane.as_deref())?;
+
+        if self.stdout {
+            print!("{selected}");
+            return Ok(());
+        }
+
+        if selected.is_empty() {
+            return Ok(());
+        }
+
+        // hiding this pane focuses the one under it. The command is written as a bracketed
+        // paste, so a command over several lines isn't run a line at a time
+        run("zellij", &["action", "toggle-floating-panes"])?;
+
+        let bytes: Vec<String> = ["\x1b[200~", &selected, "\x1b[201~"]
+            .concat()
+            .bytes()
+            .map(|b| b.to_string())
+            .collect();
+
+        let mut args = vec!["action", "write"];
+        args.extend(bytes.iter().map(String::as_str));
+
+        run("zellij", &args)
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeSet;
+
+use clap::Subcommand;
+use eyre::{bail, Result};
+
+use atuin_common::utils::{
+    is_valid_profile, profile, root_config_dir, root_data_dir, set_default_profile, DEFAULT_PROFILE,
+};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Print the profile in use
+    Current,
+
+    /// List every profile that has a config or data dir
+    List,
+
+    /// Use a profile by default, in every shell. `default` goes back to the default profile.
+    /// `$ATUIN_PROFILE` and --profile still take precedence.
+    Use {
+        /// The profile. Made the first time it's used
+        name: String,
+    },
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        let current = profile().unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+
+        match self {
+            Self::Current => println!("{current}"),
+
+            Self::List => {
+                let mut profiles = BTreeSet::from([DEFAULT_PROFILE.to_string()]);
+
+                for dir in [root_config_dir(), root_data_dir()] {
+                    let Ok(entries) = fs_err::read_dir(dir.join("profiles")) else {
+                        continue;
+                    };
+
+                    profiles.extend(
+                        entries
+                            .filter_map(Result::ok)
+                            .filter(|entry| entry.path().is_dir())
+                            .filter_map(|entry| entry.file_name().into_string().ok())
+                            .filter(|name| is_valid_profile(name)),
+                    );
+                }
+
+                for name in profiles {
+                    let marker = if name == current { "*" } else { " " };
+                    println!("{marker} {name}");
+                }
+            }
+
+            Self::Use { name } => {
+                if !is_valid_profile(&name) {
+                    bail!("profile names may only have letters, numbers, - and _");
+                }
+
+                set_default_profile(&name)?;
+
+                if name == DEFAULT_PROFILE {
+                    println!("Back to the default profile");
+                } else {
+                    println!("Using the {name} profile by default");
+                }
+
+                if std::env::var("ATUIN_PROFILE").is_ok_and(|env| env != name) {
+                    println!("This shell has $ATUIN_PROFILE set, which still takes precedence");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    io::{stdout, IsTerminal as _},
+    process::Stdio,
+};
+
+use clap::Parser;
+use eyre::{bail, Context, Result};
+use serde::Deserialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::process::Command;
+
+use atuin_client::{
+    database::{Database, Sqlite},
+    history::History,
+    settings::{FilterMode, Settings},
+    theme::Theme,
+};
+
+use super::search::interactive::{self, Screen};
+
+/// Search another machine's history, fetched over SSH. It's only kept in memory while
+/// searching, and never saved or synced here.
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// The host to search, as given to ssh: `host`, `user@host`, or an alias from ~/.ssh/config
+    host: String,
+
+    /// Where atuin is on the other machine, if it's not on the PATH ssh gives commands, such as
+    /// `~/.atuin/bin/atuin`. It's run by the remote shell.
+    #[arg(long, default_value = "atuin")]
+    remote_atuin: String,
+
+    /// Options for ssh, such as `-p 2222`
+    #[arg(long, allow_hyphen_values = true)]
+    ssh_args: Option<String>,
+
+    /// Start the search with this query
+    query: Vec<String>,
+}
+
+/// An entry as the other machine's `atuin history list --format ndjson` prints it
+#[derive(Deserialize)]
+struct RemoteHistory {
+    id: String,
+    timestamp: String,
+    duration: i64,
+    exit: i64,
+    command: String,
+    directory: String,
+    session: String,
+    host: String,
+    #[serde(default)]
+    user: String,
+    #[serde(default)]
+    namespace: String,
+    #[serde(default)]
+    branch: String,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+impl TryFrom<RemoteHistory> for History {
+    type Error = eyre::Report;
+
+    fn try_from(h: RemoteHistory) -> Result<Self> {
+        let hostname = if h.user.is_empty() {
+            h.host
+        } else {
+            format!("{}:{}", h.host, h.user)
+        };
+
+        Ok(History::from_db()
+            .id(h.id)
+            .timestamp(OffsetDateTime::parse(&h.timestamp, &Rfc3339)?)
+            .command(h.command)
+            .cwd(h.directory)
+            .exit(h.exit)
+            .duration(h.duration)
+            .session(h.session)
+            .hostname(hostname)
+            .namespace(h.namespace)
+            .stdout_bytes(None)
+            .stderr_bytes(None)
+            .env(h.env)
+            .branch(h.branch)
+            .deleted_at(None)
+            .build()
+            .into())
+    }
+}
+
+fn parse(output: &[u8]) -> Result<Vec<History>> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let h: RemoteHistory = serde_json::from_str(line)
+                .context("unexpected output from the remote atuin. Is it up to date?")?;
+
+            h.try_into()
+        })
+        .collect()
+}
+
+impl Cmd {
+    async fn fetch(&self) -> Result<Vec<History>> {
+        let mut ssh = Command::new("ssh");
+
+        if let Some(args) = &self.ssh_args {
+            ssh.args(args.split_whitespace());
+        }
+
+        let output = ssh
+            .args(["-C", "--", &self.host])
+            .arg(format!(
+                "{} history list --format ndjson",
+                self.remote_atuin
+            ))
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .context("could not run ssh. Is it installed?")?;
+
+        if !output.status.success() {
+            bail!(
+                "could not list history on {}. If atuin isn't on the PATH there, give --remote-atuin",
+                self.host
+            );
+        }
+
+        parse(&output.stdout)
+    }
+
+    pub async fn run(self, settings: &Settings, theme: &Theme) -> Result<()> {
+        let history = self.fetch().await?;
+        eprintln!("Fetched {} commands from {}", history.len(), self.host);
+
+        let db = Sqlite::new("sqlite::memory:", settings.local_timeout).await?;
+        db.save_bulk(&history).await?;
+
+        // the other machine's sessions and directories aren't ours, and channels are only
+        // searched locally
+        let mut settings = settings.clone();
+        settings.filter_mode = Some(FilterMode::Global);
+        settings.channels.clear();
+
+        // drawn out of the way when the selection is being captured
+        let screen = if stdout().is_terminal() {
+            Screen::Stdout
+        } else {
+            Screen::Stderr
+        };
+
+        let selected = interactive::history(
+            &self.query,
+            &settings,
+            db,
+            None,
+            theme,
+            None,
+            BTreeMap::new(),
+            screen,
+        )
+        .await?;
+
+        if !selected.is_empty() {
+            println!("{selected}");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parse_ndjson() {
+        let output = br#"{"id":"0192","timestamp":"2024-11-08T12:00:00Z","duration":1000,"exit":0,"command":"ls","directory":"/srv","session":"s","host":"web1","user":"deploy","namespace":"","branch":"","env":{}}
+
+{"id":"0193","timestamp":"2024-11-08T12:01:00+01:00","duration":-1,"exit":1,"command":"make","directory":"/srv","session":"s","host":"web1","user":""}
+"#;
+
+        let history = parse(output).unwrap();
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].hostname, "web1:deploy");
+        assert_eq!(history[0].command, "ls");
+        assert_eq!(history[1].hostname, "web1");
+        assert_eq!(history[1].exit, 1);
+
+        assert!(parse(b"Error: not atuin").is_err());
+    }
+}
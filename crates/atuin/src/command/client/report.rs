@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use eyre::Result;
+
+use atuin_client::{
+    database::{current_context, Database},
+    settings::FilterMode,
+};
+use atuin_common::utils;
+use atuin_history::reproducibility::lint;
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Flag commands that would be hard to rerun elsewhere, such as ones using paths in your
+    /// home directory, piping downloads into a shell, or installing packages without pinning a
+    /// version. Useful when turning history into a script.
+    Reproducibility {
+        /// Report on history run in this directory and below. Defaults to the current git
+        /// repository, or the current directory if not in one.
+        dir: Option<PathBuf>,
+    },
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &impl Database) -> Result<()> {
+        match self {
+            Self::Reproducibility { dir } => reproducibility(db, dir.as_ref()).await,
+        }
+    }
+}
+
+async fn reproducibility(db: &impl Database, dir: Option<&PathBuf>) -> Result<()> {
+    let mut context = current_context();
+
+    // The workspace filter matches everything below the git root, or the cwd if there isn't one
+    if let Some(dir) = dir {
+        let dir = if dir.is_absolute() {
+            dir.clone()
+        } else {
+            PathBuf::from(utils::get_current_dir()).join(dir)
+        };
+
+        context.cwd = dir.to_string_lossy().trim_end_matches('/').to_string();
+        context.git_root = None;
+    }
+
+    let mut history = db
+        .list(&[FilterMode::Workspace], &context, None, true, false)
+        .await?;
+    history.reverse();
+
+    let mut flagged = 0;
+
+    for h in &history {
+        let issues = lint(&h.command);
+
+        if issues.is_empty() {
+            continue;
+        }
+
+        flagged += 1;
+
+        println!("{}", h.command.trim());
+        for issue in issues {
+            println!("  - {issue}");
+        }
+        println!();
+    }
+
+    let place = context
+        .git_root
+        .map_or(context.cwd, |root| root.to_string_lossy().to_string());
+
+    println!(
+        "{flagged} of {} commands run in {place} may be hard to reproduce",
+        history.len()
+    );
+
+    Ok(())
+}
@@ -1,29 +1,36 @@
-use std::io::{stderr, IsTerminal as _};
+use std::{
+    collections::BTreeMap,
+    io::{stderr, IsTerminal as _},
+};
 
 use atuin_common::utils::{self, Escapable as _};
 use clap::Parser;
 use eyre::Result;
 
 use atuin_client::{
+    channel,
     database::Database,
     database::{current_context, OptFilters},
     encryption,
     history::{store::HistoryStore, History},
     record::sqlite_store::SqliteStore,
+    session,
     settings::{FilterMode, KeymapMode, SearchMode, Settings, Timezone},
     theme::Theme,
 };
 
 use super::history::ListMode;
+use interactive::Screen;
 
 mod cursor;
 mod duration;
 mod engines;
 mod history_list;
 mod inspector;
-mod interactive;
+pub(super) mod interactive;
+mod recovery;
 
-pub use duration::format_duration_into;
+pub use duration::{format_duration, format_duration_into};
 
 #[allow(clippy::struct_excessive_bools, clippy::struct_field_names)]
 #[derive(Parser, Debug)]
@@ -36,6 +43,28 @@ pub struct Cmd {
     #[arg(long = "exclude-cwd")]
     exclude_cwd: Option<String>,
 
+    /// Filter search result by the container it was run in, such as "devcontainer:atuin". Use
+    /// an empty string for history from the host
+    #[arg(long)]
+    namespace: Option<String>,
+
+    /// Filter search result by an environment variable it was run with, as NAME=VALUE. The same
+    /// as `env:NAME=VALUE` in the query. Only the variables listed in `history_env` are recorded,
+    /// along with `nix_shell`, the derivation of a `nix develop` or `nix-shell` environment. Can
+    /// be given more than once
+    #[arg(long, value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Filter search result by the git branch it was run on. The same as `branch:NAME` in the
+    /// query
+    #[arg(long)]
+    branch: Option<String>,
+
+    /// Filter search result by a tag, added with `atuin history tag`. The same as `tag:NAME` in
+    /// the query. Can be given more than once
+    #[arg(long)]
+    tag: Vec<String>,
+
     /// Filter search result by exit code
     #[arg(long, short)]
     exit: Option<i64>,
@@ -64,10 +93,24 @@ pub struct Cmd {
     #[arg(long, short)]
     interactive: bool,
 
+    /// Print the selected command to stdout, and draw the interface full screen on stderr, to pick
+    /// a command from a script or another tool. Exits with 1 if nothing was selected
+    #[arg(long, requires = "interactive")]
+    stdout: bool,
+
+    /// Like --stdout, but end the command with a NUL rather than a newline
+    #[arg(long, requires = "interactive")]
+    print0: bool,
+
     /// Allow overriding filter mode over config
     #[arg(long = "filter-mode")]
     filter_mode: Option<FilterMode>,
 
+    /// Show history from another host, as "host:user", or just "host" for any user on it.
+    /// Implies --filter-mode host
+    #[arg(long = "filter-host")]
+    filter_host: Option<String>,
+
     /// Allow overriding search mode over config
     #[arg(long = "search-mode")]
     search_mode: Option<SearchMode>,
@@ -110,18 +153,35 @@ pub struct Cmd {
     #[arg(long, visible_alias = "tz")]
     timezone: Option<Timezone>,
 
-    /// Available variables: {command}, {directory}, {duration}, {user}, {host}, {time}, {exit} and
-    /// {relativetime}.
+    /// Available variables: {command}, {directory} (or {dir}), {duration}, {user}, {host}, {time},
+    /// {exit}, {id}, {session}, {namespace}, {branch} and {relativetime}.
     /// Example: --format "{time} - [{duration}] - {directory}$\t{command}"
+    /// Or the name of a template in the config, or "json" or "ndjson" for other tools to read
     #[arg(long, short)]
     format: Option<String>,
 
-    /// Set the maximum number of lines Atuin's interface should take up.
+    /// Set the maximum number of lines Atuin's interface should take up, below the prompt. 0
+    /// goes full screen.
     #[arg(long = "inline-height")]
     inline_height: Option<u16>,
+
+    /// Print the one command that best completes this, for shell autosuggestion plugins.
+    /// Commands run often, recently and in the current directory win. Exits 1 with no
+    /// suggestion
+    #[arg(
+        long,
+        value_name = "BUFFER",
+        conflicts_with_all = ["interactive", "delete", "delete_it_all"]
+    )]
+    prefix_suggest: Option<String>,
 }
 
 impl Cmd {
+    /// The shell's buffer, if this search is for an autosuggestion
+    pub fn prefix_suggest(&self) -> Option<&str> {
+        self.prefix_suggest.as_deref()
+    }
+
     // clippy: please write this instead
     // clippy: now it has too many lines
     // me: I'll do it later OKAY
@@ -178,6 +238,9 @@ impl Cmd {
         if self.filter_mode.is_some() {
             settings.filter_mode = self.filter_mode;
         }
+        if self.filter_host.is_some() {
+            settings.filter_mode = Some(FilterMode::Host);
+        }
         if self.inline_height.is_some() {
             settings.inline_height = self.inline_height.unwrap();
         }
@@ -195,11 +258,43 @@ impl Cmd {
         let encryption_key: [u8; 32] = encryption::load_key(settings)?.into();
 
         let host_id = Settings::host_id().expect("failed to get host_id");
-        let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
+        let history_store =
+            HistoryStore::from_settings(store.clone(), host_id, encryption_key, settings);
 
         if self.interactive {
-            let item = interactive::history(&query, settings, db, &history_store, theme).await?;
-            if stderr().is_terminal() {
+            // labels are a nicety, not worth failing the search over
+            let session_labels = session::labels(&store, &encryption_key)
+                .await
+                .unwrap_or_else(|e| {
+                    log::debug!("failed to load session labels: {e}");
+                    BTreeMap::new()
+                });
+
+            let print = self.stdout || self.print0;
+            let item = interactive::history(
+                &query,
+                settings,
+                db,
+                Some(&history_store),
+                theme,
+                self.filter_host,
+                session_labels,
+                if print {
+                    Screen::Stderr
+                } else {
+                    Screen::Stdout
+                },
+            )
+            .await?;
+
+            if print {
+                if item.is_empty() {
+                    std::process::exit(1)
+                }
+
+                let end = if self.print0 { '\0' } else { '\n' };
+                print!("{item}{end}");
+            } else if stderr().is_terminal() {
                 eprintln!("{}", item.escape_control());
             } else {
                 eprintln!("{item}");
@@ -210,6 +305,10 @@ impl Cmd {
                 exclude_exit: self.exclude_exit,
                 cwd: self.cwd,
                 exclude_cwd: self.exclude_cwd,
+                namespace: self.namespace,
+                env: self.env,
+                branch: self.branch,
+                tags: self.tag,
                 before: self.before,
                 after: self.after,
                 limit: self.limit,
@@ -217,8 +316,25 @@ impl Cmd {
                 reverse: self.reverse,
             };
 
-            let mut entries =
-                run_non_interactive(settings, opt_filter.clone(), &query, &db).await?;
+            // channel history is in a database of its own, and isn't ours to delete
+            let channels = if settings.default_filter_mode() == FilterMode::Channel {
+                if self.delete || self.delete_it_all {
+                    eyre::bail!("shared channel history can't be deleted from here. Take it out with `atuin share <id> --channel <name> --remove`");
+                }
+
+                channel::open_db(settings).await?
+            } else {
+                None
+            };
+
+            let mut entries = run_non_interactive(
+                settings,
+                opt_filter.clone(),
+                self.filter_host.as_deref(),
+                &query,
+                channels.as_ref().map_or(&db as &dyn Database, |c| c),
+            )
+            .await?;
 
             if entries.is_empty() {
                 std::process::exit(1)
@@ -237,18 +353,21 @@ impl Cmd {
                             let (id, _) = history_store.delete(entry.id.clone()).await?;
                             history_store.incremental_build(&db, &[id]).await?;
                         } else {
-                            db.delete(entry.clone()).await?;
+                            db.delete(entry.id.clone()).await?;
                         }
                     }
 
-                    entries =
-                        run_non_interactive(settings, opt_filter.clone(), &query, &db).await?;
+                    entries = run_non_interactive(
+                        settings,
+                        opt_filter.clone(),
+                        self.filter_host.as_deref(),
+                        &query,
+                        &db,
+                    )
+                    .await?;
                 }
             } else {
-                let format = match self.format {
-                    None => Some(settings.history_format.as_str()),
-                    _ => self.format.as_deref(),
-                };
+                let format = Some(settings.history_template(self.format.as_deref()));
                 let tz = self.timezone.unwrap_or(settings.timezone);
 
                 super::history::print_list(
@@ -265,14 +384,36 @@ impl Cmd {
     }
 }
 
+/// Print the suggestion for the shell's buffer
+pub async fn prefix_suggest(db: &impl Database, prefix: &str) -> Result<()> {
+    let context = current_context();
+
+    match db.prefix_suggestion(prefix, &context).await? {
+        Some(command) => println!("{command}"),
+        None => std::process::exit(1),
+    }
+
+    Ok(())
+}
+
+fn parse_env(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() && !name.contains('"') => {
+            Ok((name.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected NAME=VALUE, got {arg:?}")),
+    }
+}
+
 // This is supposed to more-or-less mirror the command line version, so ofc
 // it is going to have a lot of args
 #[allow(clippy::too_many_arguments, clippy::cast_possible_truncation)]
 async fn run_non_interactive(
     settings: &Settings,
     filter_options: OptFilters,
+    filter_host: Option<&str>,
     query: &[String],
-    db: &impl Database,
+    db: &dyn Database,
 ) -> Result<Vec<History>> {
     let dir = if filter_options.cwd.as_deref() == Some(".") {
         Some(utils::get_current_dir())
@@ -280,7 +421,11 @@ async fn run_non_interactive(
         filter_options.cwd
     };
 
-    let context = current_context();
+    let mut context = current_context();
+
+    if let Some(host) = filter_host {
+        context.hostname = host.to_string();
+    }
 
     let opt_filter = OptFilters {
         cwd: dir.clone(),
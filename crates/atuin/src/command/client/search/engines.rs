@@ -1,23 +1,11 @@
-use async_trait::async_trait;
 use atuin_client::{
-    database::{Context, Database},
-    history::History,
-    settings::{FilterMode, SearchMode, Settings},
+    database::Context,
+    settings::{FilterMode, Settings},
 };
-use eyre::Result;
+use atuin_history::search::SearchQuery;
 
 use super::cursor::Cursor;
 
-pub mod db;
-pub mod skim;
-
-pub fn engine(search_mode: SearchMode) -> Box<dyn SearchEngine> {
-    match search_mode {
-        SearchMode::Skim => Box::new(skim::Search::new()) as Box<_>,
-        mode => Box::new(db::Search(mode)) as Box<_>,
-    }
-}
-
 pub struct SearchState {
     pub input: Cursor,
     pub filter_mode: FilterMode,
@@ -25,6 +13,14 @@ pub struct SearchState {
 }
 
 impl SearchState {
+    pub(crate) fn query(&self) -> SearchQuery<'_> {
+        SearchQuery {
+            input: self.input.as_str(),
+            filter_mode: self.filter_mode,
+            context: &self.context,
+        }
+    }
+
     pub(crate) fn rotate_filter_mode(&mut self, settings: &Settings, offset: isize) {
         let mut i = settings
             .search
@@ -45,28 +41,9 @@ impl SearchState {
     fn filter_mode_available(&self, mode: FilterMode, settings: &Settings) -> bool {
         match mode {
             FilterMode::Workspace => settings.workspaces && self.context.git_root.is_some(),
+            FilterMode::Channel => !settings.channels.is_empty(),
+            FilterMode::Pane => self.context.pane.is_some(),
             _ => true,
         }
     }
 }
-
-#[async_trait]
-pub trait SearchEngine: Send + Sync + 'static {
-    async fn full_query(
-        &mut self,
-        state: &SearchState,
-        db: &mut dyn Database,
-    ) -> Result<Vec<History>>;
-
-    async fn query(&mut self, state: &SearchState, db: &mut dyn Database) -> Result<Vec<History>> {
-        if state.input.as_str().is_empty() {
-            Ok(db
-                .list(&[state.filter_mode], &state.context, Some(200), true, false)
-                .await?
-                .into_iter()
-                .collect::<Vec<_>>())
-        } else {
-            self.full_query(state, db).await
-        }
-    }
-}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{ops::Range, time::Duration};
 
 use atuin_client::{
     history::History,
@@ -22,6 +22,8 @@ pub struct HistoryList<'a> {
     /// Apply an alternative highlighting to the selected row
     alternate_highlight: bool,
     now: &'a dyn Fn() -> OffsetDateTime,
+    /// The byte ranges of a command matched by the search, from the search engine
+    highlight: &'a dyn Fn(&str) -> Vec<Range<usize>>,
     indicator: &'a str,
     theme: &'a Theme,
 }
@@ -75,6 +77,7 @@ impl<'a> StatefulWidget for HistoryList<'a> {
             inverted: self.inverted,
             alternate_highlight: self.alternate_highlight,
             now: &self.now,
+            highlight: &self.highlight,
             indicator: self.indicator,
             theme: self.theme,
         };
@@ -98,6 +101,7 @@ impl<'a> HistoryList<'a> {
         inverted: bool,
         alternate_highlight: bool,
         now: &'a dyn Fn() -> OffsetDateTime,
+        highlight: &'a dyn Fn(&str) -> Vec<Range<usize>>,
         indicator: &'a str,
         theme: &'a Theme,
     ) -> Self {
@@ -107,6 +111,7 @@ impl<'a> HistoryList<'a> {
             inverted,
             alternate_highlight,
             now,
+            highlight,
             indicator,
             theme,
         }
@@ -141,6 +146,7 @@ struct DrawState<'a> {
     inverted: bool,
     alternate_highlight: bool,
     now: &'a dyn Fn() -> OffsetDateTime,
+    highlight: &'a dyn Fn(&str) -> Vec<Range<usize>>,
     indicator: &'a str,
     theme: &'a Theme,
 }
@@ -169,7 +175,9 @@ impl DrawState<'_> {
     }
 
     fn duration(&mut self, h: &History) {
-        let status = self.theme.as_style(if h.success() {
+        let status = self.theme.as_style(if h.cancelled() {
+            Meaning::Muted
+        } else if h.success() {
             Meaning::AlertInfo
         } else {
             Meaning::AlertError
@@ -209,14 +217,36 @@ impl DrawState<'_> {
             style.attributes.set(crossterm::style::Attribute::Bold);
         }
 
-        for section in h.command.escape_control().split_ascii_whitespace() {
-            self.draw(" ", style.into());
+        let command = h.command.escape_control();
+        let matched = (self.highlight)(&command);
+        let is_match = |at: usize| matched.iter().any(|r| r.contains(&at));
+        let style: Style = style.into();
+
+        for section in command.split_ascii_whitespace() {
+            self.draw(" ", style);
             if self.x > self.list_area.width {
                 // Avoid attempting to draw a command section beyond the width
                 // of the list
                 return;
             }
-            self.draw(section, style.into());
+
+            // draw the section in runs, underlining the parts the search matched
+            let start = section.as_ptr() as usize - command.as_ptr() as usize;
+            let ends = section.char_indices().skip(1).map(|(i, _)| i);
+            let mut run = 0;
+            for i in ends.chain([section.len()]) {
+                if i < section.len() && is_match(start + i) == is_match(start + run) {
+                    continue;
+                }
+
+                let part_style = if is_match(start + run) {
+                    style.add_modifier(Modifier::UNDERLINED)
+                } else {
+                    style
+                };
+                self.draw(&section[run..i], part_style);
+                run = i;
+            }
         }
     }
 
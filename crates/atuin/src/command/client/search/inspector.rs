@@ -1,15 +1,17 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 use time::macros::format_description;
 
 use atuin_client::{
     history::{History, HistoryStats},
     settings::Settings,
 };
+use atuin_history::calendar::{Calendar, DAY_LABELS, SHADES};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::{
     layout::Rect,
     prelude::{Constraint, Direction, Layout},
     style::Style,
+    text::{Line, Span},
     widgets::{Bar, BarChart, BarGroup, Block, Borders, Padding, Paragraph, Row, Table},
     Frame,
 };
@@ -28,6 +30,29 @@ fn u64_or_zero(num: i64) -> u64 {
     }
 }
 
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: Option<i64>) -> String {
+    let Some(bytes) = bytes.map(u64_or_zero) else {
+        return "?".to_string();
+    };
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in ["KiB", "MiB", "GiB", "TiB"] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    format!("{size:.1} {unit}")
+}
+
 pub fn draw_commands(
     f: &mut Frame<'_>,
     parent: Rect,
@@ -90,24 +115,75 @@ pub fn draw_stats_table(
     parent: Rect,
     history: &History,
     stats: &HistoryStats,
+    session_labels: &BTreeMap<String, String>,
     theme: &Theme,
 ) {
     let duration = Duration::from_nanos(u64_or_zero(history.duration));
     let avg_duration = Duration::from_nanos(stats.average_duration);
 
-    let rows = [
+    let mut rows = vec![
         Row::new(vec!["Time".to_string(), history.timestamp.to_string()]),
         Row::new(vec!["Duration".to_string(), format_duration(duration)]),
         Row::new(vec![
             "Avg duration".to_string(),
             format_duration(avg_duration),
         ]),
-        Row::new(vec!["Exit".to_string(), history.exit.to_string()]),
+        Row::new(vec![
+            "Exit".to_string(),
+            if history.cancelled() {
+                "cancelled".to_string()
+            } else {
+                history.exit.to_string()
+            },
+        ]),
         Row::new(vec!["Directory".to_string(), history.cwd.to_string()]),
-        Row::new(vec!["Session".to_string(), history.session.to_string()]),
+        Row::new(vec!["Branch".to_string(), history.branch.to_string()]),
+        Row::new(vec![
+            "Session".to_string(),
+            session_labels.get(&history.session).map_or_else(
+                || history.session.clone(),
+                |label| format!("{label} ({})", history.session),
+            ),
+        ]),
         Row::new(vec!["Total runs".to_string(), stats.total.to_string()]),
+        Row::new(vec!["Success rate".to_string(), success_rate(&stats.exits)]),
     ];
 
+    if let Some(trend) = duration_trend(&sort_duration_over_time(&stats.duration_over_time)) {
+        rows.push(Row::new(vec!["Duration trend".to_string(), trend]));
+    }
+
+    if !stats.tags.is_empty() {
+        rows.push(Row::new(vec!["Tags".to_string(), stats.tags.join(", ")]));
+    }
+
+    if let Some(note) = &stats.note {
+        rows.push(Row::new(vec!["Note".to_string(), note.clone()]));
+    }
+
+    // only some shell integrations can tell us this
+    if history.stdout_bytes.is_some() || history.stderr_bytes.is_some() {
+        rows.push(Row::new(vec![
+            "Output".to_string(),
+            format!(
+                "{} stdout, {} stderr",
+                format_bytes(history.stdout_bytes),
+                format_bytes(history.stderr_bytes)
+            ),
+        ]));
+    }
+
+    if !history.env.is_empty() {
+        let env = history
+            .env
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        rows.push(Row::new(vec!["Environment".to_string(), env]));
+    }
+
     let widths = [Constraint::Ratio(1, 5), Constraint::Ratio(4, 5)];
 
     let table = Table::new(rows, widths).column_spacing(1).block(
@@ -121,6 +197,42 @@ pub fn draw_stats_table(
     f.render_widget(table, parent);
 }
 
+/// How many runs with a known exit code succeeded
+#[allow(clippy::cast_precision_loss)]
+fn success_rate(exits: &[(i64, i64)]) -> String {
+    let (succeeded, known) = exits.iter().filter(|(exit, _)| *exit >= 0).fold(
+        (0, 0),
+        |(succeeded, known), (exit, count)| {
+            (
+                succeeded + if *exit == 0 { *count } else { 0 },
+                known + count,
+            )
+        },
+    );
+
+    if known == 0 {
+        return "?".to_string();
+    }
+
+    let rate = 100.0 * succeeded as f64 / known as f64;
+    format!("{rate:.0}% ({succeeded} of {known})")
+}
+
+/// The average duration of the latest month, against the month before it
+#[allow(clippy::cast_precision_loss)]
+fn duration_trend(durations: &[(String, i64)]) -> Option<String> {
+    let [.., (previous_month, previous), (_, latest)] = durations else {
+        return None;
+    };
+
+    if *previous <= 0 {
+        return None;
+    }
+
+    let change = 100.0 * (latest - previous) as f64 / *previous as f64;
+    Some(format!("{change:+.0}% since {previous_month}"))
+}
+
 fn num_to_day(num: &str) -> String {
     match num {
         "0" => "Sunday".to_string(),
@@ -135,32 +247,145 @@ fn num_to_day(num: &str) -> String {
 }
 
 fn sort_duration_over_time(durations: &[(String, i64)]) -> Vec<(String, i64)> {
+    sort_by_month(
+        durations
+            .iter()
+            .map(|(month, duration)| (month.as_str(), *duration)),
+    )
+}
+
+fn sort_success_over_time(runs: &[(String, i64, i64)]) -> Vec<(String, (i64, i64))> {
+    sort_by_month(
+        runs.iter()
+            .map(|(month, succeeded, failed)| (month.as_str(), (*succeeded, *failed))),
+    )
+}
+
+/// Sort values keyed by sqlite's "01-mm-yyyy" month strings, and relabel them as "mm/yy"
+fn sort_by_month<'a, T>(values: impl Iterator<Item = (&'a str, T)>) -> Vec<(String, T)> {
     let format = format_description!("[day]-[month]-[year]");
     let output = format_description!("[month]/[year repr:last_two]");
 
-    let mut durations: Vec<(time::Date, i64)> = durations
-        .iter()
-        .map(|d| {
+    let mut values: Vec<(time::Date, T)> = values
+        .map(|(month, value)| {
             (
-                time::Date::parse(d.0.as_str(), &format).expect("invalid date string from sqlite"),
-                d.1,
+                time::Date::parse(month, &format).expect("invalid date string from sqlite"),
+                value,
             )
         })
         .collect();
 
-    durations.sort_by(|a, b| a.0.cmp(&b.0));
+    values.sort_by(|a, b| a.0.cmp(&b.0));
 
-    durations
-        .iter()
-        .map(|(date, duration)| {
+    values
+        .into_iter()
+        .map(|(date, value)| {
             (
                 date.format(output).expect("failed to format sqlite date"),
-                *duration,
+                value,
             )
         })
         .collect()
 }
 
+/// One group a month, of runs that succeeded then runs that failed
+fn success_chart<'a>(stats: &HistoryStats, theme: &Theme) -> BarChart<'a> {
+    sort_success_over_time(&stats.success_over_time)
+        .into_iter()
+        .map(|(month, (succeeded, failed))| {
+            BarGroup::default().label(month.into()).bars(&[
+                Bar::default()
+                    .value(u64_or_zero(succeeded))
+                    .style(theme.as_style(Meaning::AlertInfo)),
+                Bar::default()
+                    .value(u64_or_zero(failed))
+                    .style(theme.as_style(Meaning::AlertError)),
+            ])
+        })
+        .fold(
+            BarChart::default()
+                .block(
+                    Block::default()
+                        .title("Succeeded and failed over time")
+                        .style(theme.as_style(Meaning::Base))
+                        .borders(Borders::ALL),
+                )
+                .bar_width(2)
+                .bar_gap(0)
+                .group_gap(1)
+                .value_style(Style::default())
+                .label_style(Style::default()),
+            BarChart::data,
+        )
+}
+
+fn hour_chart<'a>(stats: &HistoryStats, theme: &Theme) -> BarChart<'a> {
+    let hour_of_day: Vec<Bar> = stats
+        .hour_of_day
+        .iter()
+        .map(|(hour, count)| {
+            Bar::default()
+                .label(format!("{hour:02}").into())
+                .value(u64_or_zero(*count))
+        })
+        .collect();
+
+    BarChart::default()
+        .block(
+            Block::default()
+                .title("Runs per hour")
+                .style(theme.as_style(Meaning::Base))
+                .borders(Borders::ALL),
+        )
+        .bar_width(2)
+        .bar_gap(1)
+        .bar_style(Style::default())
+        .value_style(Style::default())
+        .label_style(Style::default())
+        .data(BarGroup::default().bars(&hour_of_day))
+}
+
+/// Runs of the command each day, as a contribution graph of as many recent weeks as fit
+fn calendar_widget<'a>(stats: &HistoryStats, width: u16, theme: &Theme) -> Paragraph<'a> {
+    let today = time::OffsetDateTime::now_local()
+        .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+        .date();
+    let calendar = Calendar::new(&stats.calendar, today);
+
+    // the borders, and the day labels with a space after them
+    let fits = usize::from(width.saturating_sub(2 + 4));
+    let skip = calendar.weeks.len().saturating_sub(fits);
+
+    let months: String = calendar.month_labels().chars().skip(skip).collect();
+    let mut lines = vec![Line::from(format!("    {months}"))];
+
+    lines.extend(DAY_LABELS.iter().enumerate().map(|(weekday, label)| {
+        let mut spans = vec![Span::raw(format!("{label:4}"))];
+
+        spans.extend(
+            calendar.weeks[skip..]
+                .iter()
+                .map(|week| match week[weekday] {
+                    None => Span::raw(" "),
+                    Some(0) => Span::styled(SHADES[0].to_string(), theme.as_style(Meaning::Muted)),
+                    Some(count) => Span::styled(
+                        SHADES[calendar.level(count)].to_string(),
+                        theme.as_style(Meaning::AlertInfo),
+                    ),
+                }),
+        );
+
+        Line::from(spans)
+    }));
+
+    Paragraph::new(lines).block(
+        Block::default()
+            .title(format!("Runs in the last year ({})", calendar.total()))
+            .style(theme.as_style(Meaning::Base))
+            .borders(Borders::ALL),
+    )
+}
+
 fn draw_stats_charts(f: &mut Frame<'_>, parent: Rect, stats: &HistoryStats, theme: &Theme) {
     let exits: Vec<Bar> = stats
         .exits
@@ -236,18 +461,37 @@ fn draw_stats_charts(f: &mut Frame<'_>, parent: Rect, stats: &HistoryStats, them
         .label_style(Style::default())
         .data(BarGroup::default().bars(&duration_over_time));
 
+    let success_over_time = success_chart(stats, theme);
+    let hour_of_day = hour_chart(stats, theme);
+
+    let calendar = calendar_widget(stats, parent.width, theme);
+
+    // the calendar needs a line for months and each day of the week, inside its borders
     let layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Ratio(1, 3),
             Constraint::Ratio(1, 3),
-            Constraint::Ratio(1, 3),
+            Constraint::Fill(1),
+            Constraint::Length(10),
         ])
         .split(parent);
 
-    f.render_widget(exits, layout[0]);
-    f.render_widget(day_of_week, layout[1]);
+    let halves = |area| {
+        Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)])
+            .split(area)
+    };
+    let exit_layout = halves(layout[0]);
+    let day_layout = halves(layout[1]);
+
+    f.render_widget(exits, exit_layout[0]);
+    f.render_widget(success_over_time, exit_layout[1]);
+    f.render_widget(day_of_week, day_layout[0]);
+    f.render_widget(hour_of_day, day_layout[1]);
     f.render_widget(duration_over_time, layout[2]);
+    f.render_widget(calendar, layout[3]);
 }
 
 pub fn draw(
@@ -255,6 +499,7 @@ pub fn draw(
     chunk: Rect,
     history: &History,
     stats: &HistoryStats,
+    session_labels: &BTreeMap<String, String>,
     theme: &Theme,
 ) {
     let vert_layout = Layout::default()
@@ -268,7 +513,7 @@ pub fn draw(
         .split(vert_layout[1]);
 
     draw_commands(f, vert_layout[0], history, stats, theme);
-    draw_stats_table(f, stats_layout[0], history, stats, theme);
+    draw_stats_table(f, stats_layout[0], history, stats, session_labels, theme);
     draw_stats_charts(f, stats_layout[1], stats, theme);
 }
 
@@ -287,3 +532,41 @@ pub fn input(
         _ => InputAction::Continue,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{duration_trend, format_bytes, success_rate};
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(None), "?");
+        assert_eq!(format_bytes(Some(0)), "0 B");
+        assert_eq!(format_bytes(Some(1023)), "1023 B");
+        assert_eq!(format_bytes(Some(1536)), "1.5 KiB");
+        assert_eq!(format_bytes(Some(5 * 1024 * 1024)), "5.0 MiB");
+    }
+
+    #[test]
+    fn test_success_rate() {
+        assert_eq!(success_rate(&[]), "?");
+        assert_eq!(success_rate(&[(-1, 4)]), "?");
+        assert_eq!(success_rate(&[(0, 3), (1, 1), (-1, 10)]), "75% (3 of 4)");
+    }
+
+    #[test]
+    fn test_duration_trend() {
+        assert_eq!(duration_trend(&[("09/26".to_string(), 100)]), None);
+        assert_eq!(
+            duration_trend(&[
+                ("08/26".to_string(), 50),
+                ("09/26".to_string(), 100),
+                ("10/26".to_string(), 150),
+            ]),
+            Some("+50% since 09/26".to_string())
+        );
+        assert_eq!(
+            duration_trend(&[("09/26".to_string(), 100), ("10/26".to_string(), 75)]),
+            Some("-25% since 09/26".to_string())
+        );
+    }
+}
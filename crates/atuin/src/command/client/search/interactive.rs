@@ -1,5 +1,9 @@
 use std::{
-    io::{stdout, Write},
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{stdout, BufWriter, Write},
+    ops::Range,
+    path::PathBuf,
     time::Duration,
 };
 
@@ -20,21 +24,25 @@ use time::OffsetDateTime;
 use unicode_width::UnicodeWidthStr;
 
 use atuin_client::{
-    database::{current_context, Database},
+    channel,
+    database::{self, current_context, Database, Sqlite},
+    export,
     history::{store::HistoryStore, History, HistoryStats},
     settings::{
         CursorStyle, ExitMode, FilterMode, KeymapMode, PreviewStrategy, SearchMode, Settings,
     },
 };
+use atuin_history::search::{self, SearchEngine};
 
 use super::{
     cursor::Cursor,
-    engines::{SearchEngine, SearchState},
+    engines::SearchState,
     history_list::{HistoryList, ListState, PREFIX_LENGTH},
+    recovery::{Autosave, Snapshot},
 };
 
-use crate::command::client::theme::{Meaning, Theme};
-use crate::{command::client::search::engines, VERSION};
+use crate::command::client::theme::{Meaning, Theme, ThemeManager};
+use crate::VERSION;
 
 use ratatui::{
     backend::CrosstermBackend,
@@ -52,6 +60,8 @@ pub enum InputAction {
     Accept(usize),
     Copy(usize),
     Delete(usize),
+    Star(usize),
+    Export,
     ReturnOriginal,
     ReturnQuery,
     Continue,
@@ -71,12 +81,32 @@ pub struct State {
     prefix: bool,
     current_cursor: Option<CursorStyle>,
     tab_index: usize,
+    notice: Option<String>,
+    /// Hosts that can be picked for the host filter, this one first
+    hosts: Vec<String>,
+    /// A search that was interrupted last time, offered back until the next key press
+    recovered: Option<Snapshot>,
+    /// Labels given to sessions with `atuin session name`, by session id
+    session_labels: BTreeMap<String, String>,
+    screen: Screen,
 
     search: SearchState,
     engine: Box<dyn SearchEngine>,
     now: Box<dyn Fn() -> OffsetDateTime + Send>,
 }
 
+/// Channel history is searched in a database of its own
+fn search_db<'a>(
+    db: &'a mut dyn Database,
+    channels: &'a mut Option<Sqlite>,
+    filter_mode: FilterMode,
+) -> &'a mut dyn Database {
+    match (filter_mode, channels) {
+        (FilterMode::Channel, Some(channels)) => channels,
+        _ => db,
+    }
+}
+
 #[derive(Clone, Copy)]
 struct StyleState {
     compact: bool,
@@ -90,7 +120,9 @@ impl State {
         db: &mut dyn Database,
         smart_sort: bool,
     ) -> Result<Vec<History>> {
-        let results = self.engine.query(&self.search, db).await?;
+        let query = self.search.query();
+        let results = self.engine.query(&query, db).await?;
+        let results = self.engine.rank(&query, results);
 
         self.results_state.select(0);
         self.results_len = results.len();
@@ -115,6 +147,7 @@ impl State {
         W: Write,
     {
         execute!(w, EnableMouseCapture)?;
+        self.notice = None;
         let r = match input {
             Event::Key(k) => self.handle_key_input(settings, k),
             Event::Mouse(m) => self.handle_mouse_input(*m),
@@ -168,7 +201,7 @@ impl State {
         if cursor_style != self.current_cursor {
             if let Some(style) = cursor_style {
                 self.current_cursor = cursor_style;
-                let _ = execute!(stdout(), Self::cast_cursor_style(style));
+                let _ = execute!(self.screen.writer(), Self::cast_cursor_style(style));
             }
         }
     }
@@ -191,10 +224,61 @@ impl State {
         }
     }
 
-    fn handle_key_exit(settings: &Settings) -> InputAction {
-        match settings.exit_mode {
+    // Follow the modes config.toml changed to while searching
+    fn reload(&mut self, previous: &Settings, settings: &Settings) {
+        if previous.search_mode != settings.search_mode {
+            self.search_mode = settings.search_mode;
+            self.engine = search::engine(self.search_mode);
+        }
+
+        let filter_mode = settings.default_filter_mode();
+        if previous.default_filter_mode() != filter_mode
+            && (filter_mode != FilterMode::Workspace || self.search.context.git_root.is_some())
+        {
+            self.search.filter_mode = filter_mode;
+        }
+
+        if previous.keymap_mode != settings.keymap_mode {
+            self.keymap_mode = match settings.keymap_mode {
+                KeymapMode::Auto => KeymapMode::Emacs,
+                value => value,
+            };
+        }
+
+        // the cursor shapes may have changed too
+        self.initialize_keymap_cursor(settings);
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.search.input = Cursor::from(snapshot.query);
+        self.search.input.end();
+        self.search.filter_mode = snapshot.filter_mode;
+        self.search_mode = snapshot.search_mode;
+        self.engine = search::engine(self.search_mode);
+    }
+
+    // Filter by the next known host, wrapping back around to this one
+    fn cycle_host(&mut self) {
+        if self.hosts.is_empty() {
+            return;
+        }
+
+        let next = self
+            .hosts
+            .iter()
+            .position(|h| *h == self.search.context.hostname)
+            .map_or(0, |i| (i + 1) % self.hosts.len());
+
+        self.search.context.hostname = self.hosts[next].clone();
+        self.search.filter_mode = FilterMode::Host;
+    }
+
+    fn handle_key_exit(&self, mode: ExitMode) -> InputAction {
+        match mode {
             ExitMode::ReturnOriginal => InputAction::ReturnOriginal,
             ExitMode::ReturnQuery => InputAction::ReturnQuery,
+            // accepted without setting self.accept, so the shell doesn't run it
+            ExitMode::ReturnSelected => InputAction::Accept(self.results_state.selected()),
         }
     }
 
@@ -203,8 +287,16 @@ impl State {
             return InputAction::Continue;
         }
 
+        if let Some(snapshot) = self.recovered.take() {
+            if input.code == KeyCode::Char('y') && input.modifiers.is_empty() {
+                self.restore(snapshot);
+                return InputAction::Continue;
+            }
+        }
+
         let ctrl = input.modifiers.contains(KeyModifiers::CONTROL);
         let esc_allow_exit = !(self.tab_index == 0 && self.keymap_mode == KeymapMode::VimInsert);
+        let exit = &settings.keys.exit;
         let cursor_at_end_of_line =
             self.search.input.position() == UnicodeWidthStr::width(self.search.input.as_str());
         let cursor_at_start_of_line = self.search.input.position() == 0;
@@ -220,14 +312,22 @@ impl State {
 
         // core input handling, common for all tabs
         let common: Option<InputAction> = match input.code {
-            KeyCode::Char('c' | 'g') if ctrl => Some(InputAction::ReturnOriginal),
-            KeyCode::Esc if esc_allow_exit => Some(Self::handle_key_exit(settings)),
-            KeyCode::Char('[') if ctrl && esc_allow_exit => Some(Self::handle_key_exit(settings)),
+            KeyCode::Char('c' | 'g') if ctrl => {
+                Some(self.handle_key_exit(exit.ctrl_c.unwrap_or(ExitMode::ReturnOriginal)))
+            }
+            KeyCode::Esc if esc_allow_exit => {
+                Some(self.handle_key_exit(exit.esc.unwrap_or(settings.exit_mode)))
+            }
+            KeyCode::Char('[') if ctrl && esc_allow_exit => {
+                Some(self.handle_key_exit(exit.esc.unwrap_or(settings.exit_mode)))
+            }
             KeyCode::Tab => Some(InputAction::Accept(self.results_state.selected())),
             KeyCode::Right if cursor_at_end_of_line => {
                 Some(InputAction::Accept(self.results_state.selected()))
             }
-            KeyCode::Left if cursor_at_start_of_line => Some(Self::handle_key_exit(settings)),
+            KeyCode::Left if cursor_at_start_of_line => {
+                Some(self.handle_key_exit(exit.left.unwrap_or(settings.exit_mode)))
+            }
             KeyCode::Char('o') if ctrl => {
                 self.tab_index = (self.tab_index + 1) % TAB_TITLES.len();
                 Some(InputAction::Continue)
@@ -263,7 +363,8 @@ impl State {
     ) -> InputAction {
         if is_down {
             if settings.keys.scroll_exits && enable_exit && self.results_state.selected() == 0 {
-                return Self::handle_key_exit(settings);
+                return self
+                    .handle_key_exit(settings.keys.exit.scroll.unwrap_or(settings.exit_mode));
             }
             self.scroll_down(1);
         } else {
@@ -308,6 +409,19 @@ impl State {
                 KeyCode::Char('d') => {
                     return InputAction::Delete(self.results_state.selected());
                 }
+                KeyCode::Char('e') => {
+                    self.prefix = false;
+                    return InputAction::Export;
+                }
+                KeyCode::Char('s') => {
+                    self.prefix = false;
+                    return InputAction::Star(self.results_state.selected());
+                }
+                KeyCode::Char('h') => {
+                    self.cycle_host();
+                    self.prefix = false;
+                    return InputAction::Continue;
+                }
                 KeyCode::Char('a') => {
                     self.search.input.start();
                     //  This prevents pressing ctrl-a twice while still in prefix mode
@@ -468,7 +582,7 @@ impl State {
             KeyCode::Char('s') if ctrl => {
                 self.switched_search_mode = true;
                 self.search_mode = self.search_mode.next(settings);
-                self.engine = engines::engine(self.search_mode);
+                self.engine = search::engine(self.search_mode);
             }
             KeyCode::Down => {
                 return self.handle_search_down(settings, true);
@@ -706,11 +820,14 @@ impl State {
 
         match self.tab_index {
             0 => {
+                let highlight =
+                    |command: &str| self.engine.highlight(self.search.input.as_str(), command);
                 let results_list = Self::build_results_list(
                     style,
                     results,
                     self.keymap_mode,
                     &self.now,
+                    &highlight,
                     indicator.as_str(),
                     theme,
                 );
@@ -736,6 +853,7 @@ impl State {
                         results_list_chunk,
                         &results[self.results_state.selected()],
                         &stats.expect("Drawing inspector, but no stats"),
+                        &self.session_labels,
                         theme,
                     );
                 }
@@ -839,12 +957,14 @@ impl State {
     }
 
     fn build_stats(&self, theme: &Theme) -> Paragraph {
-        let stats = Paragraph::new(Text::from(Span::raw(format!(
-            "history count: {}",
-            self.history_count,
-        ))))
-        .style(theme.as_style(Meaning::Annotation))
-        .alignment(Alignment::Right);
+        let text = self
+            .notice
+            .clone()
+            .unwrap_or_else(|| format!("history count: {}", self.history_count));
+
+        let stats = Paragraph::new(Text::from(Span::raw(text)))
+            .style(theme.as_style(Meaning::Annotation))
+            .alignment(Alignment::Right);
         stats
     }
 
@@ -853,6 +973,7 @@ impl State {
         results: &'a [History],
         keymap_mode: KeymapMode,
         now: &'a dyn Fn() -> OffsetDateTime,
+        highlight: &'a dyn Fn(&str) -> Vec<Range<usize>>,
         indicator: &'a str,
         theme: &'a Theme,
     ) -> HistoryList<'a> {
@@ -861,6 +982,7 @@ impl State {
             style.invert,
             keymap_mode == KeymapMode::VimNormal,
             now,
+            highlight,
             indicator,
             theme,
         );
@@ -886,8 +1008,29 @@ impl State {
     fn build_input(&self, style: StyleState) -> Paragraph {
         /// Max width of the UI box showing current mode
         const MAX_WIDTH: usize = 14;
+        // cut a name down to fit
+        fn fit(name: &str) -> &str {
+            let end = name
+                .char_indices()
+                .map(|(i, c)| i + c.len_utf8())
+                .take_while(|&end| end <= MAX_WIDTH)
+                .last()
+                .unwrap_or_default();
+            &name[..end]
+        }
+
+        let hostname = self.search.context.hostname.as_str();
+        let session_label = self.session_labels.get(&self.search.context.session);
         let (pref, mode) = if self.switched_search_mode {
             (" SRCH:", self.search_mode.as_str())
+        } else if self.search.filter_mode == FilterMode::Host
+            && self.hosts.first().map(String::as_str) != Some(hostname)
+        {
+            // name the other host we're looking at
+            ("", fit(hostname))
+        } else if let (FilterMode::Session, Some(label)) = (self.search.filter_mode, session_label)
+        {
+            ("", fit(label))
         } else {
             ("", self.search.filter_mode.as_str())
         };
@@ -953,15 +1096,32 @@ impl State {
     }
 }
 
+/// Where the interface is drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Screen {
+    Stdout,
+    /// Leaving stdout for the selection, when it's printed there
+    Stderr,
+}
+
+impl Screen {
+    fn writer(self) -> Box<dyn Write + Send> {
+        match self {
+            Self::Stdout => Box::new(stdout()),
+            Self::Stderr => Box::new(std::io::stderr()),
+        }
+    }
+}
+
 struct Stdout {
-    stdout: std::io::Stdout,
+    stdout: Box<dyn Write + Send>,
     inline_mode: bool,
 }
 
 impl Stdout {
-    pub fn new(inline_mode: bool) -> std::io::Result<Self> {
+    pub fn new(inline_mode: bool, screen: Screen) -> std::io::Result<Self> {
         terminal::enable_raw_mode()?;
-        let mut stdout = stdout();
+        let mut stdout = screen.writer();
 
         if !inline_mode {
             execute!(stdout, terminal::EnterAlternateScreen)?;
@@ -1022,20 +1182,31 @@ impl Write for Stdout {
 // this is a big blob of horrible! clean it up!
 // for now, it works. But it'd be great if it were more easily readable, and
 // modular. I'd like to add some more stats and stuff at some point
-#[allow(clippy::cast_possible_truncation, clippy::too_many_lines)]
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::too_many_lines,
+    clippy::too_many_arguments
+)]
 pub async fn history(
     query: &[String],
     settings: &Settings,
     mut db: impl Database,
-    history_store: &HistoryStore,
+    history_store: Option<&HistoryStore>,
     theme: &Theme,
+    filter_host: Option<String>,
+    session_labels: BTreeMap<String, String>,
+    screen: Screen,
 ) -> Result<String> {
-    let stdout = Stdout::new(settings.inline_height > 0)?;
+    // an inline viewport finds where it is by asking the terminal over stdout, so drawing
+    // anywhere else is always full screen
+    let inline = settings.inline_height > 0 && screen == Screen::Stdout;
+
+    let stdout = Stdout::new(inline, screen)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::with_options(
         backend,
         TerminalOptions {
-            viewport: if settings.inline_height > 0 {
+            viewport: if inline {
                 Viewport::Inline(settings.inline_height)
             } else {
                 Viewport::Fullscreen
@@ -1051,7 +1222,19 @@ pub async fn history(
     let update_needed = tokio::spawn(async move { settings2.needs_update().await }).fuse();
     tokio::pin!(update_needed);
 
-    let context = current_context();
+    let mut context = current_context();
+
+    let mut hosts = vec![context.hostname.clone()];
+    hosts.extend(
+        db.hosts()
+            .await?
+            .into_iter()
+            .filter(|h| *h != context.hostname),
+    );
+
+    if let Some(host) = filter_host {
+        context.hostname = host;
+    }
 
     let history_count = db.history_count(false).await?;
     let search_mode = if settings.shell_up_key_binding {
@@ -1078,7 +1261,7 @@ pub async fn history(
                 .unwrap_or(FilterMode::Global),
             context,
         },
-        engine: engines::engine(search_mode),
+        engine: search::engine(search_mode),
         results_len: 0,
         accept: false,
         keymap_mode: match settings.keymap_mode {
@@ -1093,20 +1276,51 @@ pub async fn history(
             Box::new(OffsetDateTime::now_utc)
         },
         prefix: false,
+        notice: None,
+        hosts,
+        recovered: None,
+        session_labels,
+        screen,
     };
 
+    // If the last search was cut short, offer it back rather than losing it
+    let mut autosave = Autosave::new();
+    app.recovered = autosave
+        .recover()
+        .filter(|s| s.query != app.search.input.as_str());
+    if let Some(snapshot) = &app.recovered {
+        app.notice = Some(format!(
+            "restore interrupted search \"{}\"? y/N",
+            snapshot.query.escape_control()
+        ));
+    }
+
     app.initialize_keymap_cursor(settings);
 
-    let mut results = app.query_results(&mut db, settings.smart_sort).await?;
+    // config.toml can be edited while searching, and the search follows along
+    let mut watcher = settings.watch();
+    let mut loaded = watcher.borrow().clone();
+    let mut settings = settings.clone();
+    let mut theme = Cow::Borrowed(theme);
+
+    let mut channels = channel::open_db(&settings).await?;
+
+    let mut results = app
+        .query_results(
+            search_db(&mut db, &mut channels, app.search.filter_mode),
+            settings.smart_sort,
+        )
+        .await?;
 
     let mut stats: Option<HistoryStats> = None;
     let accept;
     let result = 'render: loop {
-        terminal.draw(|f| app.draw(f, &results, stats.clone(), settings, theme))?;
+        terminal.draw(|f| app.draw(f, &results, stats.clone(), &settings, &theme))?;
 
         let initial_input = app.search.input.as_str().to_owned();
         let initial_filter_mode = app.search.filter_mode;
         let initial_search_mode = app.search_mode;
+        let initial_host = app.search.context.hostname.clone();
 
         let event_ready = tokio::task::spawn_blocking(|| event::poll(Duration::from_millis(250)));
 
@@ -1114,8 +1328,20 @@ pub async fn history(
             event_ready = event_ready => {
                 if event_ready?? {
                     loop {
-                        match app.handle_input(settings, &event::read()?, &mut std::io::stdout())? {
+                        match app.handle_input(&settings, &event::read()?, &mut screen.writer())? {
                             InputAction::Continue => {},
+                            // incognito searches can't write to history, only read it
+                            InputAction::Delete(_) | InputAction::Star(_) if database::incognito() => {
+                                app.notice = Some("history is read only while incognito".to_string());
+                            },
+                            // shared history is only taken back by whoever shared it
+                            InputAction::Delete(_) | InputAction::Star(_) if app.search.filter_mode == FilterMode::Channel => {
+                                app.notice = Some("channel history is read only".to_string());
+                            },
+                            // without a store, this isn't our history, such as another host's
+                            InputAction::Delete(_) | InputAction::Star(_) if history_store.is_none() => {
+                                app.notice = Some("this history is read only".to_string());
+                            },
                             InputAction::Delete(index) => {
                                 app.results_len -= 1;
                                 let selected = app.results_state.selected();
@@ -1124,19 +1350,53 @@ pub async fn history(
                                 }
 
                                 let entry = results.remove(index);
+                                let history_store = history_store.expect("read only history is skipped above");
 
                                 if settings.sync.records {
                                     let (id, _) = history_store.delete(entry.id).await?;
                                     history_store.incremental_build(&db, &[id]).await?;
                                 } else {
-                                    db.delete(entry.clone()).await?;
+                                    db.delete(entry.id.clone()).await?;
                                 }
 
                                 app.tab_index  = 0;
                             },
+                            InputAction::Star(index) => {
+                                if let Some(entry) = results.get(index) {
+                                    let history_store = history_store.expect("read only history is skipped above");
+
+                                    // the star may be on another run of the same command
+                                    let starred: Vec<History> = db
+                                        .starred()
+                                        .await?
+                                        .into_iter()
+                                        .filter(|h| h.command == entry.command)
+                                        .collect();
+
+                                    if starred.is_empty() {
+                                        history_store.star(&db, entry.id.clone(), true).await?;
+                                        app.notice = Some("starred".to_string());
+                                    } else {
+                                        for h in starred {
+                                            history_store.star(&db, h.id, false).await?;
+                                        }
+                                        app.notice = Some("unstarred".to_string());
+                                    }
+
+                                    if app.search.filter_mode == FilterMode::Starred {
+                                        results = app.query_results(&mut db, settings.smart_sort).await?;
+                                    }
+                                }
+                            },
+                            InputAction::Export => {
+                                app.notice = Some(match export_results(&results, &settings) {
+                                    Ok(path) => format!("exported {} to {}", results.len(), path.display()),
+                                    Err(e) => format!("export failed: {e}"),
+                                });
+                            },
                             InputAction::Redraw => {
                                 terminal.clear()?;
-                                terminal.draw(|f| app.draw(f, &results, stats.clone(), settings, theme))?;
+                                terminal.draw(|f| app.draw(f, &results, stats.clone(), &settings, &theme))?;
                             },
                             r => {
                                 accept = app.accept;
@@ -1152,36 +1412,78 @@ pub async fn history(
             update_needed = &mut update_needed => {
                 app.update_needed = update_needed?;
             }
+            Ok(()) = watcher.changed() => {
+                let reloaded = watcher.borrow_and_update().clone();
+                let previous = settings.clone();
+
+                settings.reload_interactive(&loaded, &reloaded);
+                app.reload(&previous, &settings);
+
+                theme = Cow::Owned(
+                    ThemeManager::new(settings.theme.debug, None)
+                        .load_theme(&settings.theme.name, settings.theme.max_depth)
+                        .clone(),
+                );
+                loaded = reloaded;
+            }
         }
 
         if initial_input != app.search.input.as_str()
             || initial_filter_mode != app.search.filter_mode
             || initial_search_mode != app.search_mode
+            || initial_host != app.search.context.hostname
         {
-            results = app.query_results(&mut db, settings.smart_sort).await?;
+            results = app
+                .query_results(
+                    search_db(&mut db, &mut channels, app.search.filter_mode),
+                    settings.smart_sort,
+                )
+                .await?;
+        }
+
+        // don't overwrite the interrupted search before they've decided about it
+        if app.recovered.is_none() {
+            autosave.save(
+                app.search.input.as_str(),
+                app.search.filter_mode,
+                app.search_mode,
+            );
         }
 
         stats = if app.tab_index == 0 {
             None
         } else if !results.is_empty() {
             let selected = results[app.results_state.selected()].clone();
-            Some(db.stats(&selected).await?)
+            Some(
+                search_db(&mut db, &mut channels, app.search.filter_mode)
+                    .stats(&selected)
+                    .await?,
+            )
         } else {
             None
         };
     };
 
-    app.finalize_keymap_cursor(settings);
+    autosave.clear();
+
+    app.finalize_keymap_cursor(&settings);
 
-    if settings.inline_height > 0 {
+    if inline {
         terminal.clear()?;
     }
 
     match result {
         InputAction::Accept(index) if index < results.len() => {
             let mut command = results.swap_remove(index).command;
+            // only a shell's integration knows to run it, not whatever reads it from stdout
             if accept
-                && (utils::is_zsh() || utils::is_fish() || utils::is_bash() || utils::is_xonsh())
+                && screen == Screen::Stdout
+                && (utils::is_zsh()
+                    || utils::is_fish()
+                    || utils::is_bash()
+                    || utils::is_xonsh()
+                    || utils::is_powershell()
+                    || utils::is_elvish())
             {
                 command = String::from("__atuin_accept__:") + &command;
             }
@@ -1201,12 +1503,32 @@ pub async fn history(
             // * out of bounds -> usually implies no selected entry so we return the input
             Ok(app.search.input.into_inner())
         }
-        InputAction::Continue | InputAction::Redraw | InputAction::Delete(_) => {
+        InputAction::Continue
+        | InputAction::Redraw
+        | InputAction::Delete(_)
+        | InputAction::Star(_)
+        | InputAction::Export => {
             unreachable!("should have been handled!")
         }
     }
 }
 
+// Write the results currently on screen to a new file in the configured export directory
+fn export_results(results: &[History], settings: &Settings) -> Result<PathBuf> {
+    let format = settings.export.format;
+    let path = PathBuf::from(&settings.export.directory).join(format!(
+        "atuin-export-{}.{}",
+        OffsetDateTime::now_utc().unix_timestamp(),
+        format.extension()
+    ));
+
+    let mut file = BufWriter::new(fs_err::File::create(&path)?);
+    export::export(&mut file, format, results)?;
+    file.flush()?;
+
+    Ok(path)
+}
+
 // cli-clipboard only works on Windows, Mac, and Linux.
 
 #[cfg(all(
@@ -1228,17 +1550,22 @@ fn set_clipboard(_s: String) {}
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use atuin_client::database::Context;
     use atuin_client::history::History;
     use atuin_client::settings::{
-        FilterMode, KeymapMode, Preview, PreviewStrategy, SearchMode, Settings,
+        ExitMode, FilterMode, KeymapMode, Preview, PreviewStrategy, SearchMode, Settings,
     };
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
     use time::OffsetDateTime;
 
-    use crate::command::client::search::engines::{self, SearchState};
+    use atuin_history::search;
+
+    use crate::command::client::search::engines::SearchState;
     use crate::command::client::search::history_list::ListState;
 
-    use super::State;
+    use super::{InputAction, Screen, State};
 
     #[test]
     fn calc_preview_height_test() {
@@ -1393,10 +1720,8 @@ mod tests {
         assert_eq!(settings_preview_fixed, 15 + border_space);
     }
 
-    // Test when there's no results, scrolling up or down doesn't underflow
-    #[test]
-    fn state_scroll_up_underflow() {
-        let mut state = State {
+    fn state() -> State {
+        State {
             history_count: 0,
             update_needed: None,
             results_state: ListState::default(),
@@ -1408,6 +1733,11 @@ mod tests {
             prefix: false,
             current_cursor: None,
             tab_index: 0,
+            notice: None,
+            hosts: Vec::new(),
+            recovered: None,
+            session_labels: BTreeMap::new(),
+            screen: Screen::Stdout,
             search: SearchState {
                 input: String::new().into(),
                 filter_mode: FilterMode::Directory,
@@ -1415,15 +1745,58 @@ mod tests {
                     session: String::new(),
                     cwd: String::new(),
                     hostname: String::new(),
+                    namespace: String::new(),
                     host_id: String::new(),
                     git_root: None,
+                    pane: None,
+                    container: None,
                 },
             },
-            engine: engines::engine(SearchMode::Fuzzy),
+            engine: search::engine(SearchMode::Fuzzy),
             now: Box::new(OffsetDateTime::now_utc),
-        };
+        }
+    }
+
+    // Test when there's no results, scrolling up or down doesn't underflow
+    #[test]
+    fn state_scroll_up_underflow() {
+        let mut state = state();
 
         state.scroll_up(1);
         state.scroll_down(1);
     }
+
+    #[test]
+    fn exit_keys() {
+        let mut state = state();
+        state.results_len = 3;
+        state.results_state.select(2);
+
+        let mut settings = Settings {
+            exit_mode: ExitMode::ReturnQuery,
+            ..Settings::utc()
+        };
+        settings.keys.exit.esc = Some(ExitMode::ReturnSelected);
+
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        assert!(matches!(
+            state.handle_key_input(&settings, &key(KeyCode::Esc)),
+            InputAction::Accept(2)
+        ));
+        assert!(!state.accept);
+
+        // the cursor is at the start of the empty query
+        assert!(matches!(
+            state.handle_key_input(&settings, &key(KeyCode::Left)),
+            InputAction::ReturnQuery
+        ));
+        assert!(matches!(
+            state.handle_key_input(
+                &settings,
+                &KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)
+            ),
+            InputAction::ReturnOriginal
+        ));
+    }
 }
@@ -0,0 +1,152 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use atuin_client::settings::{FilterMode, SearchMode};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+const FILENAME: &str = "search_state.json";
+
+// Often enough to lose little to a dropped connection, rarely enough that typing doesn't hit
+// the disk on every key
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+// A search interrupted any longer ago than this isn't worth offering back
+const MAX_AGE: i64 = 24 * 60 * 60;
+
+/// The parts of an interactive search worth getting back if it's interrupted, by a terminal
+/// crashing or an SSH connection dropping
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub query: String,
+    pub filter_mode: FilterMode,
+    pub search_mode: SearchMode,
+    /// Unix timestamp
+    pub saved_at: i64,
+}
+
+/// Saves the search as it changes, throttled, and removes the save once the search ends
+/// normally. Anything left over was interrupted.
+pub struct Autosave {
+    path: PathBuf,
+    saved: Option<(String, FilterMode, SearchMode)>,
+    last_write: Option<Instant>,
+}
+
+impl Autosave {
+    pub fn new() -> Self {
+        Self::at(atuin_common::utils::data_dir().join(FILENAME))
+    }
+
+    fn at(path: PathBuf) -> Self {
+        Self {
+            path,
+            saved: None,
+            last_write: None,
+        }
+    }
+
+    /// The search that was interrupted last time, if there was one recently
+    pub fn recover(&self) -> Option<Snapshot> {
+        let snapshot = fs_err::read_to_string(&self.path).ok()?;
+
+        match serde_json::from_str::<Snapshot>(&snapshot) {
+            Ok(s) if OffsetDateTime::now_utc().unix_timestamp() - s.saved_at < MAX_AGE => Some(s),
+            _ => {
+                self.clear();
+                None
+            }
+        }
+    }
+
+    pub fn save(&mut self, query: &str, filter_mode: FilterMode, search_mode: SearchMode) {
+        if self
+            .saved
+            .as_ref()
+            .is_some_and(|s| (s.0.as_str(), s.1, s.2) == (query, filter_mode, search_mode))
+        {
+            return;
+        }
+
+        if self
+            .last_write
+            .is_some_and(|last| last.elapsed() < SAVE_INTERVAL)
+        {
+            return;
+        }
+
+        // an empty search has nothing worth recovering
+        let res = if query.is_empty() {
+            fs_err::remove_file(&self.path).or_else(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            })
+        } else {
+            let snapshot = Snapshot {
+                query: query.to_string(),
+                filter_mode,
+                search_mode,
+                saved_at: OffsetDateTime::now_utc().unix_timestamp(),
+            };
+
+            write(&self.path, &snapshot)
+        };
+
+        // this is best effort, there's no sense interrupting a search over it
+        if let Err(e) = res {
+            log::debug!("failed to save search state: {e}");
+        }
+
+        self.saved = Some((query.to_string(), filter_mode, search_mode));
+        self.last_write = Some(Instant::now());
+    }
+
+    /// The search ended normally, so there's nothing to recover
+    pub fn clear(&self) {
+        let _ = fs_err::remove_file(&self.path);
+    }
+}
+
+// Write to a temporary file first, so an interruption can't leave a half written one behind
+fn write(path: &PathBuf, snapshot: &Snapshot) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+
+    fs_err::write(&tmp, serde_json::to_vec(snapshot)?)?;
+    fs_err::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use atuin_client::settings::{FilterMode, SearchMode};
+
+    use super::Autosave;
+
+    #[test]
+    fn recovers_interrupted_search() {
+        let path = std::env::temp_dir().join(format!(
+            "atuin-search-state-{}.json",
+            atuin_common::utils::uuid_v7().as_simple()
+        ));
+
+        let mut autosave = Autosave::at(path.clone());
+        assert_eq!(autosave.recover(), None);
+
+        autosave.save("git reb", FilterMode::Directory, SearchMode::Fuzzy);
+
+        // throttled, so this doesn't replace it yet
+        autosave.save("git rebase", FilterMode::Directory, SearchMode::Fuzzy);
+
+        let snapshot = Autosave::at(path.clone()).recover().unwrap();
+        assert_eq!(snapshot.query, "git reb");
+        assert_eq!(snapshot.filter_mode, FilterMode::Directory);
+        assert_eq!(snapshot.search_mode, SearchMode::Fuzzy);
+
+        autosave.clear();
+        assert_eq!(Autosave::at(path).recover(), None);
+    }
+}
@@ -0,0 +1,76 @@
+use clap::Parser;
+use eyre::{bail, Result, WrapErr};
+use semver::Version;
+
+use atuin_client::{
+    api_client,
+    settings::{Settings, UpdateCheck},
+    update,
+};
+use atuin_common::{detail, status};
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Only say whether there's a newer release
+    #[arg(long)]
+    check: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        if !settings.self_update {
+            bail!("self_update is off in your config, as atuin was installed by a package manager. Update it with that instead");
+        }
+
+        settings.ensure_online()?;
+
+        // asking to update is asking to check, even with update checks off
+        let channel = match settings.update_check {
+            UpdateCheck::Off => UpdateCheck::Stable,
+            channel => channel,
+        };
+
+        let current = Version::parse(env!("CARGO_PKG_VERSION"))?;
+        let latest = api_client::latest_version(channel)
+            .await
+            .wrap_err("could not check for a new release")?;
+
+        if latest <= current {
+            status!("atuin {current} is the latest release");
+            return Ok(());
+        }
+
+        status!("atuin {latest} is out, this is {current}");
+
+        if self.check {
+            return Ok(());
+        }
+
+        // checked before downloading anything, as a build without the key never can
+        let key = update::release_key()?;
+        let path = std::env::current_exe()?;
+
+        let (archive, manifest, signature) = update::artifact_urls(&latest)?;
+
+        let manifest = api_client::download(&manifest).await?;
+        let signature = api_client::download(&signature).await?;
+        let release = update::verify_manifest(
+            &manifest,
+            &String::from_utf8_lossy(&signature),
+            &key,
+            &current,
+        )?;
+
+        detail!("Downloading {archive}");
+
+        let archive = api_client::download(&archive).await?;
+        update::verify_archive(&archive, &release)?;
+
+        let binary = update::extract(&archive)?;
+        update::replace(&path, &binary)?;
+
+        status!("Updated {} to atuin {}", path.display(), release.version);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::env;
+
+use clap::Subcommand;
+use eyre::{eyre, Context, Result};
+
+use atuin_client::{encryption, record::store::Store, session, settings::Settings};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Label the current shell session, or print its label
+    Name {
+        /// A name for the session, shown in place of its id when searching
+        label: Option<String>,
+
+        /// Remove the session's label
+        #[arg(long, conflicts_with = "label")]
+        clear: bool,
+    },
+
+    /// List labelled sessions
+    List,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: &Settings, store: &(impl Store + Send + Sync)) -> Result<()> {
+        let encryption_key: [u8; 32] = encryption::load_key(settings)
+            .context("could not load encryption key")?
+            .into();
+
+        match self {
+            Self::Name { label, clear } => {
+                let session = env::var("ATUIN_SESSION").map_err(|_| {
+                    eyre!("$ATUIN_SESSION is not set. Check that you have correctly set up your shell")
+                })?;
+
+                if let Some(label) = label {
+                    let host_id = Settings::host_id().expect("failed to get host_id");
+                    session::set_label(store, &encryption_key, host_id, &session, label).await
+                } else if *clear {
+                    let host_id = Settings::host_id().expect("failed to get host_id");
+                    session::set_label(store, &encryption_key, host_id, &session, "").await
+                } else {
+                    let labels = session::labels(store, &encryption_key).await?;
+
+                    if let Some(label) = labels.get(&session) {
+                        println!("{label}");
+                    }
+
+                    Ok(())
+                }
+            }
+
+            Self::List => {
+                for (session, label) in session::labels(store, &encryption_key).await? {
+                    println!("{session}\t{label}");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use clap::Parser;
+use eyre::{bail, Result};
+
+use atuin_client::{
+    channel::{self, ChannelStore},
+    database::Database,
+    history::HistoryId,
+    settings::Settings,
+};
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// The entry's id, as shown with `--format "{id}"`
+    id: String,
+
+    /// The channel to share it to, from `[[channels]]` in config.toml
+    #[arg(long, short)]
+    channel: String,
+
+    /// Take it back out of the channel instead
+    #[arg(long)]
+    remove: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings, db: &impl Database) -> Result<()> {
+        let config = channel::find(settings, &self.channel)?;
+        let store = ChannelStore::open(settings, config).await?;
+        let channels_db = channel::open_db(settings)
+            .await?
+            .expect("a channel is configured");
+
+        if self.remove {
+            store.unshare(&channels_db, HistoryId(self.id)).await?;
+            println!("Removed from {}", self.channel);
+
+            return Ok(());
+        }
+
+        let Some(history) = db.load(&self.id).await? else {
+            bail!("there is no history with the id {}", self.id);
+        };
+
+        if store.share(&channels_db, history).await? {
+            println!(
+                "Shared to {}. It's sent with the next `atuin sync`",
+                self.channel
+            );
+        } else {
+            println!("Already shared to {}", self.channel);
+        }
+
+        Ok(())
+    }
+}
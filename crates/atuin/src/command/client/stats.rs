@@ -1,15 +1,32 @@
+use std::ops::Range;
+
 use clap::Parser;
 use eyre::Result;
 use interim::parse_date_string;
+use serde::Serialize;
 use time::{Duration, OffsetDateTime, Time};
 
 use atuin_client::{
-    database::{current_context, Database},
+    database::{Database, GroupStats, StatsGroup},
     settings::Settings,
     theme::Theme,
 };
+use atuin_common::{detail, status};
+
+use atuin_history::{
+    calendar::{self, Calendar},
+    stats::{compute_from_counts, pretty_print, Stats},
+};
 
-use atuin_history::stats::{compute, pretty_print};
+use super::{format::Format, search::format_duration};
+
+/// A day of `--calendar`, as json
+#[derive(Serialize)]
+struct CalendarDay<'a> {
+    /// YYYY-MM-DD
+    day: &'a str,
+    commands: i64,
+}
 
 #[derive(Parser, Debug)]
 #[command(infer_subcommands = true)]
@@ -24,11 +41,33 @@ pub struct Cmd {
     /// The number of consecutive commands to consider
     #[arg(long, short, default_value = "1")]
     ngram_size: usize,
+
+    /// Only count history from this time on, eg "2024-01-01" or "last monday"
+    #[arg(long, conflicts_with = "period")]
+    since: Option<String>,
+
+    /// Only count history from before this time
+    #[arg(long, conflicts_with = "period")]
+    until: Option<String>,
+
+    /// Count history for each day, week, directory, host or exit code, rather than listing the
+    /// top commands
+    #[arg(long, value_enum)]
+    group_by: Option<StatsGroup>,
+
+    /// Show how many commands were run each day of the last year, as a calendar
+    #[arg(long, conflicts_with_all = ["period", "since", "until", "group_by"])]
+    calendar: bool,
+
+    /// Print a table, or json or ndjson for other tools to read
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
 }
 
 impl Cmd {
     pub async fn run(&self, db: &impl Database, settings: &Settings, theme: &Theme) -> Result<()> {
-        let context = current_context();
+        self.format.quiet();
+
         let words = if self.period.is_empty() {
             String::from("all")
         } else {
@@ -36,38 +75,147 @@ impl Cmd {
         };
 
         let now = OffsetDateTime::now_utc().to_offset(settings.timezone.0);
+
+        if self.calendar {
+            let start = calendar::start(now.date())
+                .midnight()
+                .assume_offset(now.offset());
+            let weeks = start..now + Duration::SECOND;
+            let days = db
+                .daily_counts(Some(weeks), None, settings.timezone.0)
+                .await?;
+
+            match self.format {
+                Format::Text => calendar::pretty_print(&Calendar::new(&days, now.date()), theme),
+                format => format.print_all(days.iter().map(|(day, commands)| CalendarDay {
+                    day,
+                    commands: *commands,
+                }))?,
+            }
+
+            return Ok(());
+        }
+
+        let range = if self.since.is_some() || self.until.is_some() {
+            let parse = |words: &str| parse_date_string(words, now, settings.dialect.into());
+
+            let start = self.since.as_deref().map(parse).transpose()?;
+            let end = self.until.as_deref().map(parse).transpose()?;
+
+            Some(start.unwrap_or(OffsetDateTime::UNIX_EPOCH)..end.unwrap_or(now + Duration::SECOND))
+        } else {
+            Self::period_range(&words, now, settings)?
+        };
+
+        match &range {
+            Some(range) => detail!("Stats from {} to {}", range.start, range.end),
+            None => detail!("Stats for all history"),
+        }
+
+        if let Some(group) = self.group_by {
+            let groups = db.grouped_stats(range, group, settings.timezone.0).await?;
+
+            match self.format {
+                Format::Text if groups.is_empty() => status!("No history found for {words}"),
+                Format::Text => print_groups(&groups),
+                format => format.print_all(&groups)?,
+            }
+
+            return Ok(());
+        }
+
+        // Count each distinct command in the database, rather than loading all of history
+        let commands = db.top_commands(range.clone(), None, false).await?;
+        let cancelled = db.cancelled_count(range).await?;
+        let commands = commands
+            .iter()
+            .map(|(command, count)| (command.as_str(), usize::try_from(*count).unwrap_or(0)));
+
+        let stats = compute_from_counts(settings, commands, self.count, self.ngram_size);
+        let cancelled = usize::try_from(cancelled).unwrap_or(0);
+
+        match (stats, self.format) {
+            (Some(mut stats), Format::Text) => {
+                stats.cancelled_commands = cancelled;
+                pretty_print(stats, self.ngram_size, theme);
+            }
+            (None, Format::Text) => status!("No history found for {words}"),
+            (stats, format) => {
+                let mut stats = stats.unwrap_or(Stats {
+                    total_commands: 0,
+                    unique_commands: 0,
+                    cancelled_commands: 0,
+                    top: Vec::new(),
+                });
+                stats.cancelled_commands = cancelled;
+                format.print(&stats)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn period_range(
+        words: &str,
+        now: OffsetDateTime,
+        settings: &Settings,
+    ) -> Result<Option<Range<OffsetDateTime>>> {
         let last_night = now.replace_time(Time::MIDNIGHT);
 
-        let history = if words.as_str() == "all" {
-            db.list(&[], &context, None, false, false).await?
+        let range = if words == "all" {
+            None
         } else if words.trim() == "today" {
             let start = last_night;
             let end = start + Duration::days(1);
-            db.range(start, end).await?
+            Some(start..end)
         } else if words.trim() == "month" {
             let end = last_night;
             let start = end - Duration::days(31);
-            db.range(start, end).await?
+            Some(start..end)
         } else if words.trim() == "week" {
             let end = last_night;
             let start = end - Duration::days(7);
-            db.range(start, end).await?
+            Some(start..end)
         } else if words.trim() == "year" {
             let end = last_night;
             let start = end - Duration::days(365);
-            db.range(start, end).await?
+            Some(start..end)
         } else {
-            let start = parse_date_string(&words, now, settings.dialect.into())?;
+            let start = parse_date_string(words, now, settings.dialect.into())?;
             let end = start + Duration::days(1);
-            db.range(start, end).await?
+            Some(start..end)
         };
 
-        let stats = compute(settings, &history, self.count, self.ngram_size);
+        Ok(range)
+    }
+}
+
+fn print_groups(groups: &[GroupStats]) {
+    let width = groups
+        .iter()
+        .map(|g| g.group.len())
+        .chain(std::iter::once("GROUP".len()))
+        .max()
+        .unwrap_or(0);
 
-        if let Some(stats) = stats {
-            pretty_print(stats, self.ngram_size, theme);
-        }
+    println!(
+        "{:width$}  {:>8}  {:>8}  {:>8}  {:>12}",
+        "GROUP", "COMMANDS", "UNIQUE", "FAILED", "AVG DURATION"
+    );
 
-        Ok(())
+    for g in groups {
+        let duration = g.average_duration.map_or_else(
+            || "-".to_string(),
+            |d| {
+                format_duration(std::time::Duration::from_nanos(
+                    u64::try_from(d).unwrap_or(0),
+                ))
+            },
+        );
+
+        println!(
+            "{:width$}  {:>8}  {:>8}  {:>8}  {:>12}",
+            g.group, g.commands, g.unique_commands, g.failed, duration
+        );
     }
 }
@@ -14,6 +14,8 @@ mod push;
 #[cfg(feature = "sync")]
 mod pull;
 
+mod export;
+mod import;
 mod purge;
 mod rebuild;
 mod rekey;
@@ -37,6 +39,13 @@ pub enum Cmd {
     /// Verify that all records in the store can be decrypted with the current key
     Verify(verify::Verify),
 
+    /// Export the record store to an archive, still encrypted, for backup or moving to another
+    /// machine
+    Export(export::Export),
+
+    /// Import records from an archive made by `atuin store export`
+    Import(import::Import),
+
     /// Push all records to the remote sync server (one way sync)
     #[cfg(feature = "sync")]
     Push(push::Push),
@@ -59,6 +68,8 @@ impl Cmd {
             Self::Rekey(rekey) => rekey.run(settings, store).await,
             Self::Verify(verify) => verify.run(settings, store).await,
             Self::Purge(purge) => purge.run(settings, store).await,
+            Self::Export(export) => export.run(store).await,
+            Self::Import(import) => import.run(settings, store, database).await,
 
             #[cfg(feature = "sync")]
             Self::Push(push) => push.run(settings, store).await,
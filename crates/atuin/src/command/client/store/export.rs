@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+
+use atuin_client::{
+    record::{archive, sqlite_store::SqliteStore},
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Export {
+    /// Where to write the archive. Its records stay encrypted, so importing it needs the same key.
+    pub file: PathBuf,
+}
+
+impl Export {
+    pub async fn run(&self, store: SqliteStore) -> Result<()> {
+        let host_id = Settings::host_id().expect("failed to get host_id");
+
+        let file = fs_err::File::create(&self.file)?;
+        let header = archive::export(&store, host_id, std::io::BufWriter::new(file))
+            .await
+            .context("could not write the archive")?;
+
+        println!(
+            "Exported {} records from {} stores to {}",
+            header.count(),
+            header.digest.entries.len(),
+            self.file.display()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{Context, Result};
+
+use atuin_client::{
+    database::Database,
+    record::{archive, sqlite_store::SqliteStore},
+    settings::Settings,
+};
+
+#[derive(Args, Debug)]
+pub struct Import {
+    /// An archive made by `atuin store export`
+    pub file: PathBuf,
+}
+
+impl Import {
+    pub async fn run(
+        &self,
+        settings: &Settings,
+        store: SqliteStore,
+        database: &dyn Database,
+    ) -> Result<()> {
+        let file = fs_err::File::open(&self.file)?;
+        let imported = archive::import(&store, std::io::BufReader::new(file))
+            .await
+            .context("could not import the archive")?;
+
+        println!(
+            "Imported {} new records, of {} in the archive",
+            imported.records.len(),
+            imported.read
+        );
+
+        if imported.read < imported.header.count() {
+            println!(
+                "The archive should have {} records, it may have been cut short",
+                imported.header.count()
+            );
+        }
+
+        crate::sync::build(settings, &store, database, Some(&imported.records)).await?;
+
+        Ok(())
+    }
+}
@@ -28,6 +28,8 @@ impl Pull {
         store: SqliteStore,
         db: &dyn Database,
     ) -> Result<()> {
+        settings.ensure_online()?;
+
         if self.force {
             println!("Forcing local overwrite!");
             println!("Clearing local store");
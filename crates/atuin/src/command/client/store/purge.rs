@@ -3,6 +3,7 @@ use eyre::Result;
 
 use atuin_client::{
     encryption::load_key,
+    kv::KvStore,
     record::{sqlite_store::SqliteStore, store::Store},
     settings::Settings,
 };
@@ -21,6 +22,9 @@ impl Purge {
             Err(e) => println!("Failed to purge local store: {e:?}"),
         }
 
+        let expired = KvStore::new().purge_expired(&store).await?;
+        println!("Purged {expired} expired kv keys");
+
         Ok(())
     }
 }
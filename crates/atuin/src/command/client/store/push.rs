@@ -29,6 +29,8 @@ pub struct Push {
 
 impl Push {
     pub async fn run(&self, settings: &Settings, store: SqliteStore) -> Result<()> {
+        settings.ensure_online()?;
+
         let host_id = Settings::host_id().expect("failed to get host_id");
 
         if self.force {
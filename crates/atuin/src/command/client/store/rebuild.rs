@@ -1,4 +1,4 @@
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 use clap::Args;
 use eyre::{bail, Result};
 
@@ -48,7 +48,7 @@ impl Rebuild {
         let encryption_key: [u8; 32] = encryption::load_key(settings)?.into();
 
         let host_id = Settings::host_id().expect("failed to get host_id");
-        let history_store = HistoryStore::new(store, host_id, encryption_key);
+        let history_store = HistoryStore::from_settings(store, host_id, encryption_key, settings);
 
         history_store.build(database).await?;
 
@@ -62,9 +62,11 @@ impl Rebuild {
 
         let alias_store = AliasStore::new(store.clone(), host_id, encryption_key);
         let var_store = VarStore::new(store.clone(), host_id, encryption_key);
+        let snippet_store = SnippetStore::new(store.clone(), host_id, encryption_key);
 
         alias_store.build().await?;
         var_store.build().await?;
+        snippet_store.build().await?;
 
         Ok(())
     }
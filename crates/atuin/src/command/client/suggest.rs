@@ -0,0 +1,159 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::Subcommand;
+use eyre::{Context, Result};
+
+use atuin_client::{
+    database::Database, encryption, record::sqlite_store::SqliteStore, settings::Settings,
+};
+use atuin_dotfiles::store::AliasStore;
+use atuin_history::aliases::{suggest, Suggestion};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// Suggest aliases for long commands you type often
+    Aliases {
+        /// Only suggest commands run at least this many times
+        #[arg(long, default_value_t = 10)]
+        min_runs: i64,
+
+        /// Only suggest commands at least this many characters long
+        #[arg(long, default_value_t = 15)]
+        min_length: usize,
+
+        /// How many suggestions to show
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+
+        /// Ask about each suggestion, and add the ones accepted to your synced aliases
+        #[arg(long)]
+        accept: bool,
+    },
+}
+
+impl Cmd {
+    pub async fn run(
+        self,
+        settings: &Settings,
+        db: &impl Database,
+        store: SqliteStore,
+    ) -> Result<()> {
+        match self {
+            Self::Aliases {
+                min_runs,
+                min_length,
+                limit,
+                accept,
+            } => aliases(settings, db, store, min_runs, min_length, limit, accept).await,
+        }
+    }
+}
+
+async fn aliases(
+    settings: &Settings,
+    db: &impl Database,
+    store: SqliteStore,
+    min_runs: i64,
+    min_length: usize,
+    limit: usize,
+    accept: bool,
+) -> Result<()> {
+    if accept && !settings.dotfiles.enabled {
+        eprintln!("Dotfiles are not enabled. Add\n\n[dotfiles]\nenabled = true\n\nto your configuration file to add aliases with Atuin.");
+        return Ok(());
+    }
+
+    // without dotfiles there are no synced aliases to clash with, only programs
+    let aliases = if settings.dotfiles.enabled {
+        Some(alias_store(settings, store)?)
+    } else {
+        None
+    };
+    let existing = match &aliases {
+        Some(store) => store.aliases().await?,
+        None => Vec::new(),
+    };
+
+    let commands = db.top_commands(None, None, false).await?;
+    let commands = commands
+        .iter()
+        .map(|(command, runs)| (command.as_str(), *runs))
+        .filter(|(command, _)| existing.iter().all(|a| a.value != *command));
+
+    let mut suggestions = suggest(commands, min_runs, min_length, |name| {
+        existing.iter().any(|a| a.name == name) || on_path(name)
+    });
+    suggestions.truncate(limit);
+
+    if suggestions.is_empty() {
+        println!("No long commands are run often enough to suggest an alias for.");
+        return Ok(());
+    }
+
+    let Some(aliases) = aliases.filter(|_| accept) else {
+        print_suggestions(&suggestions);
+        println!("\nRun again with --accept to choose which to add.");
+        return Ok(());
+    };
+
+    for suggestion in suggestions {
+        let answer = read_input(&format!(
+            "Alias '{}={}'? Run {} times. [y/N/q]",
+            suggestion.name, suggestion.command, suggestion.runs
+        ))?;
+
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => {
+                aliases.set(&suggestion.name, &suggestion.command).await?;
+                println!("Aliasing '{}={}'.", suggestion.name, suggestion.command);
+            }
+            "q" | "quit" => break,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn print_suggestions(suggestions: &[Suggestion]) {
+    let width = suggestions
+        .iter()
+        .map(|s| s.name.len())
+        .max()
+        .unwrap_or_default();
+
+    for suggestion in suggestions {
+        println!(
+            "{:width$}  {:>5} runs  {}",
+            suggestion.name, suggestion.runs, suggestion.command
+        );
+    }
+}
+
+fn alias_store(settings: &Settings, store: SqliteStore) -> Result<AliasStore> {
+    let encryption_key: [u8; 32] = encryption::load_key(settings)
+        .context("could not load encryption key")?
+        .into();
+    let host_id = Settings::host_id().expect("failed to get host_id");
+
+    Ok(AliasStore::new(store, host_id, encryption_key))
+}
+
+// An alias named after a program would hide it
+fn on_path(name: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| Path::new(&dir).join(name).is_file())
+    })
+}
+
+fn read_input(prompt: &str) -> Result<String> {
+    print!("{prompt} ");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    Ok(input.trim().to_string())
+}
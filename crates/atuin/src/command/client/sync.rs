@@ -3,15 +3,21 @@ use eyre::{Result, WrapErr};
 
 use atuin_client::{
     database::Database,
-    encryption,
+    encryption::{self, HostKey},
     history::store::HistoryStore,
     record::{sqlite_store::SqliteStore, store::Store, sync},
     settings::Settings,
+    sync::SyncProgress,
 };
+use atuin_common::status;
+use indicatif::{ProgressBar, ProgressStyle};
 
+mod recover;
+mod rotate;
 mod status;
+mod verify;
 
-use crate::command::client::account;
+use crate::command::client::{account, channel, format::Format};
 
 #[derive(Subcommand, Debug)]
 #[command(infer_subcommands = true)]
@@ -21,6 +27,10 @@ pub enum Cmd {
         /// Force re-download everything
         #[arg(long, short)]
         force: bool,
+
+        /// Show a progress bar while syncing history. The record store always shows one.
+        #[arg(long)]
+        progress: bool,
     },
 
     /// Login to the configured server
@@ -33,14 +43,50 @@ pub enum Cmd {
     Register(account::register::Cmd),
 
     /// Print the encryption key for transfer to another machine
+    #[command(args_conflicts_with_subcommands = true)]
     Key {
+        #[command(subcommand)]
+        cmd: Option<KeyCmd>,
+
         /// Switch to base64 output of the key
         #[arg(long)]
         base64: bool,
+
+        /// Print the public key, which can encrypt records but not read them
+        #[arg(long, conflicts_with = "base64")]
+        public: bool,
     },
 
     /// Display the sync status
-    Status,
+    Status {
+        /// Print the status, or json or ndjson for other tools to read
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+
+    /// Check that the local store and the server agree, and sync everything again where they
+    /// don't
+    Verify,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCmd {
+    /// Replace the encryption key with a new one, re-encrypting the local store and the records on
+    /// the server to match
+    Rotate {
+        /// Leave records encrypted with the old key, rather than re-encrypting them. The old key
+        /// is kept, so this host can still read them.
+        #[arg(long)]
+        keep_records: bool,
+    },
+
+    /// Keep only the public key on this host, from `atuin key --public` on one that has the
+    /// encryption key. History recorded here is sealed to it and synced up, but this host can't
+    /// read any history back, its own included.
+    WriteOnly {
+        /// The public key, starting `k4.public.`
+        public_key: String,
+    },
 }
 
 impl Cmd {
@@ -50,23 +96,69 @@ impl Cmd {
         db: &impl Database,
         store: SqliteStore,
     ) -> Result<()> {
+        // logging out, the key and the status don't need the server
+        if !matches!(
+            self,
+            Self::Logout
+                | Self::Key {
+                    cmd: None | Some(KeyCmd::WriteOnly { .. }),
+                    ..
+                }
+                | Self::Status { .. }
+        ) {
+            settings.ensure_online()?;
+        }
+
         match self {
-            Self::Sync { force } => run(&settings, force, db, store).await,
+            Self::Sync { force, progress } => run(&settings, force, progress, db, store).await,
             Self::Login(l) => l.run(&settings, &store).await,
             Self::Logout => account::logout::run(&settings),
             Self::Register(r) => r.run(&settings).await,
-            Self::Status => status::run(&settings, db).await,
-            Self::Key { base64 } => {
+            Self::Status { format } => status::run(&settings, db, &store, format).await,
+            Self::Verify => verify::run(&settings, db, &store).await,
+            Self::Key {
+                cmd: Some(KeyCmd::Rotate { keep_records }),
+                ..
+            } => rotate::run(&settings, &store, keep_records).await,
+            Self::Key {
+                cmd: Some(KeyCmd::WriteOnly { public_key }),
+                ..
+            } => {
+                encryption::save_public_key(&settings, &public_key)?;
+                status!("This host now only has the public key. It will write history, but can't read it");
+                Ok(())
+            }
+            Self::Key {
+                cmd: None,
+                base64,
+                public,
+            } => {
                 use atuin_client::encryption::{encode_key, load_key};
+
+                if public {
+                    if encryption::is_write_only(&settings)? {
+                        // it's saved just as it's printed
+                        let key = encryption::read_key(&settings)?.unwrap_or_default();
+                        status!("{}", key.trim());
+                    } else {
+                        let key: [u8; 32] = load_key(&settings)
+                            .wrap_err("could not load encryption key")?
+                            .into();
+                        status!("{}", atuin_client::record::encryption::public_key(&key));
+                    }
+
+                    return Ok(());
+                }
+
                 let key = load_key(&settings).wrap_err("could not load encryption key")?;
 
                 if base64 {
                     let encode = encode_key(&key).wrap_err("could not encode encryption key")?;
-                    println!("{encode}");
+                    status!("{encode}");
                 } else {
                     let mnemonic = bip39::Mnemonic::from_entropy(&key, bip39::Language::English)
                         .map_err(|_| eyre::eyre!("invalid key"))?;
-                    println!("{mnemonic}");
+                    status!("{mnemonic}");
                 }
                 Ok(())
             }
@@ -77,51 +169,73 @@ impl Cmd {
 async fn run(
     settings: &Settings,
     force: bool,
+    progress: bool,
     db: &impl Database,
     store: SqliteStore,
 ) -> Result<()> {
-    if settings.sync.records {
-        let encryption_key: [u8; 32] = encryption::load_key(settings)
-            .context("could not load encryption key")?
-            .into();
+    if settings.sync_backend.is_some() && !settings.sync.records {
+        eyre::bail!("sync_backend only syncs the record store. Set sync.records = true to use it");
+    }
 
+    if settings.sync.records {
         let host_id = Settings::host_id().expect("failed to get host_id");
-        let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
+        let history_store =
+            match encryption::load_host_key(settings).context("could not load encryption key")? {
+                HostKey::Master(key) => {
+                    HistoryStore::from_settings(store.clone(), host_id, key, settings)
+                }
+                HostKey::Public(key) => HistoryStore::write_only(store.clone(), host_id, key)
+                    .with_strip(&settings.sync.strip),
+            };
 
         let (uploaded, downloaded) = sync::sync(settings, &store).await?;
 
-        crate::sync::build(settings, &store, db, Some(&downloaded)).await?;
+        recover::build(settings, &store, db, &downloaded).await?;
 
-        println!("{uploaded}/{} up/down to record store", downloaded.len());
+        status!("{uploaded}/{} up/down to record store", downloaded.len());
 
         let history_length = db.history_count(true).await?;
         let store_history_length = store.len_tag("history").await?;
 
+        // a host with only the public key can't read the store to see which history is missing
         #[allow(clippy::cast_sign_loss)]
-        if history_length as u64 > store_history_length {
-            println!(
+        if history_store.public_key.is_none() && history_length as u64 > store_history_length {
+            status!(
                 "{history_length} in history index, but {store_history_length} in history store"
             );
-            println!("Running automatic history store init...");
+            status!("Running automatic history store init...");
 
             // Internally we use the global filter mode, so this context is ignored.
             // don't recurse or loop here.
             history_store.init_store(db).await?;
 
-            println!("Re-running sync due to new records locally");
+            status!("Re-running sync due to new records locally");
 
             // we'll want to run sync once more, as there will now be stuff to upload
             let (uploaded, downloaded) = sync::sync(settings, &store).await?;
 
-            crate::sync::build(settings, &store, db, Some(&downloaded)).await?;
+            recover::build(settings, &store, db, &downloaded).await?;
 
-            println!("{uploaded}/{} up/down to record store", downloaded.len());
+            status!("{uploaded}/{} up/down to record store", downloaded.len());
         }
+
+        let diverged = sync::SyncState::load().diverged;
+
+        if !diverged.is_empty() {
+            status!(
+                "The server disagrees with this machine about {} stores. Run `atuin sync verify` to fix this.",
+                diverged.len()
+            );
+        }
+
+        channel::sync(settings, db, None).await?;
+    } else if progress && !atuin_common::output::is_quiet() {
+        legacy_sync_with_progress(settings, force, db).await?;
     } else {
         atuin_client::sync::sync(settings, force, db).await?;
     }
 
-    println!(
+    status!(
         "Sync complete! {} items in history database, force: {}",
         db.history_count(true).await?,
         force
@@ -129,3 +243,32 @@ async fn run(
 
     Ok(())
 }
+
+async fn legacy_sync_with_progress(
+    settings: &Settings,
+    force: bool,
+    db: &impl Database,
+) -> Result<()> {
+    let pb = ProgressBar::new(0);
+    pb.set_style(
+        ProgressStyle::with_template("{msg} [{wide_bar:.cyan/blue}] {human_pos}/{human_len}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+
+    let res = atuin_client::sync::sync_with_progress(settings, force, db, |p| {
+        let (msg, done, total) = match p {
+            SyncProgress::Upload { done, total } => ("Uploading", done, total),
+            SyncProgress::Download { done, total } => ("Downloading", done, total),
+        };
+
+        pb.set_message(msg);
+        pb.set_length(total.max(0).unsigned_abs());
+        pb.set_position(done.max(0).unsigned_abs());
+    })
+    .await;
+
+    pb.finish_and_clear();
+
+    res
+}
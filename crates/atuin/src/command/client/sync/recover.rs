@@ -0,0 +1,149 @@
+use std::io::{self, IsTerminal, Write};
+
+use eyre::{Context, Result};
+
+use atuin_client::{
+    database::Database,
+    encryption::{decode_key, load_key, save_key},
+    record::{
+        encryption::{key_id, KeyMismatch},
+        sqlite_store::SqliteStore,
+        store::Store,
+    },
+    settings::Settings,
+};
+use atuin_common::record::RecordId;
+
+use crate::command::client::account::login::normalize_key;
+
+enum Choice {
+    /// Switch to this key, as base64
+    Key(String),
+    Skip,
+    Abort,
+}
+
+/// Rebuild after a sync. If some of the downloaded records were encrypted with a different key,
+/// and there's someone at the terminal to ask, help them fix it rather than failing the sync.
+pub async fn build(
+    settings: &Settings,
+    store: &SqliteStore,
+    db: &impl Database,
+    downloaded: &[RecordId],
+) -> Result<()> {
+    loop {
+        let Err(err) = crate::sync::build(settings, store, db, Some(downloaded)).await else {
+            return Ok(());
+        };
+
+        let Some(mismatch) = err.downcast_ref::<KeyMismatch>().cloned() else {
+            return Err(err);
+        };
+
+        if !io::stdin().is_terminal() {
+            return Err(err.wrap_err(
+                "records were encrypted with a different key. Run `atuin sync` in a terminal to fix this",
+            ));
+        }
+
+        match prompt(&mismatch)? {
+            Choice::Key(key) => switch_key(settings, store, key).await?,
+            Choice::Skip => skip(settings, store).await?,
+            Choice::Abort => return Err(err),
+        }
+
+        // and try again. If there's a third key in the mix, we'll be back.
+    }
+}
+
+fn prompt(mismatch: &KeyMismatch) -> Result<Choice> {
+    println!();
+    println!("Some records are encrypted with a different key to the one on this machine.");
+    println!("  this machine's key: {}", mismatch.current);
+    println!("  the records' key:   {}", mismatch.expected);
+    println!();
+    println!("This usually means this machine was set up with a new key, rather than the one");
+    println!("your other machines use. Run `atuin key` on one of them to find it.");
+
+    loop {
+        println!();
+        println!("  1) Enter the key");
+        println!("  2) Import the key from a file");
+        println!("  3) Skip the records that can't be decrypted, and carry on");
+        println!("  4) Abort");
+
+        let key = match read_input("Choose [1-4]")?.as_str() {
+            "1" => read_input("Key (mnemonic or base64)")?,
+            "2" => {
+                let path = read_input("Path to key file")?;
+
+                match fs_err::read_to_string(path) {
+                    Ok(key) => key.trim().to_string(),
+                    Err(e) => {
+                        println!("Couldn't read the key file: {e}");
+                        continue;
+                    }
+                }
+            }
+            "3" => return Ok(Choice::Skip),
+            "4" | "q" => return Ok(Choice::Abort),
+            _ => continue,
+        };
+
+        let (decoded, encoded) =
+            match normalize_key(key).and_then(|k| Ok((decode_key(k.clone())?, k))) {
+                Ok(key) => key,
+                Err(e) => {
+                    println!("That key isn't valid: {e}");
+                    continue;
+                }
+            };
+
+        let id = key_id(&decoded.into());
+
+        if id == mismatch.expected {
+            return Ok(Choice::Key(encoded));
+        }
+
+        println!("That's not the records' key either, it's {id}");
+    }
+}
+
+fn read_input(prompt: &str) -> Result<String> {
+    print!("{prompt}: ");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    Ok(input.trim().to_string())
+}
+
+// Use the key from now on, re-encrypting anything we have locally to match
+async fn switch_key(settings: &Settings, store: &SqliteStore, key: String) -> Result<()> {
+    let current_key: [u8; 32] = load_key(settings)?.into();
+    let new_key: [u8; 32] = decode_key(key.clone())?.into();
+
+    println!("Re-encrypting local store with the new key");
+    store
+        .re_encrypt(&current_key, &new_key)
+        .await
+        .context("could not re-encrypt the local store")?;
+
+    println!("Writing new key");
+    save_key(settings, &key)?;
+
+    Ok(())
+}
+
+// Drop anything we can't decrypt from the local store. It's still on the server, so it'll come
+// back on the next sync, and so will this question.
+async fn skip(settings: &Settings, store: &SqliteStore) -> Result<()> {
+    let key: [u8; 32] = load_key(settings)?.into();
+
+    store.purge(&key).await?;
+
+    println!("Skipped the records that couldn't be decrypted. They're still on the server.");
+
+    Ok(())
+}
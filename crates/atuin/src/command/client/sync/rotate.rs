@@ -0,0 +1,133 @@
+use eyre::{bail, Context, Result};
+
+use atuin_client::{
+    api_client::Client,
+    encryption::{archive_key, generate_encoded_key, load_key, save_key},
+    record::{sqlite_store::SqliteStore, store::Store, sync},
+    settings::Settings,
+};
+use atuin_common::status;
+
+// Replace the key with a fresh one. Every record keeps its content, but has its content key
+// re-wrapped with the new key, locally and then on the server. With `keep_records`, records are
+// left as they are, and read with the old key from the keyring instead.
+pub async fn run(settings: &Settings, store: &SqliteStore, keep_records: bool) -> Result<()> {
+    let old_key = load_key(settings).context("could not load encryption key")?;
+    let (new_key, encoded) = generate_encoded_key()?;
+
+    let old: [u8; 32] = old_key.into();
+    let new: [u8; 32] = new_key.into();
+
+    // anything left encrypted with the old key, here or on other hosts, stays readable
+    archive_key(settings, &old_key).context("could not keep the old key")?;
+
+    if keep_records {
+        save_key(settings, &encoded).context("could not save the new key")?;
+    } else {
+        re_encrypt(settings, store, &old, &new, &encoded).await?;
+    }
+
+    let mnemonic = bip39::Mnemonic::from_entropy(&new, bip39::Language::English)
+        .map_err(|_| eyre::eyre!("invalid key"))?;
+
+    status!("Key rotated. Log in on your other machines with the new key:");
+    println!("{mnemonic}");
+
+    Ok(())
+}
+
+async fn re_encrypt(
+    settings: &Settings,
+    store: &SqliteStore,
+    old: &[u8; 32],
+    new: &[u8; 32],
+    encoded: &str,
+) -> Result<()> {
+    let client = if settings.logged_in() {
+        let client = Client::new(
+            &settings.sync_address,
+            settings.session_token()?.as_str(),
+            settings.network_connect_timeout,
+            settings.network_timeout * 10,
+        )?;
+
+        pull(settings, store, &client).await?;
+
+        Some(client)
+    } else {
+        None
+    };
+
+    // anything we can't decrypt can't be re-encrypted either, so would be lost
+    store.verify(old).await.context(
+        "the local store has records the current key can't decrypt. Run `atuin sync` to recover them first",
+    )?;
+
+    status!("Re-encrypting the local store with a new key");
+    store
+        .re_encrypt(old, new)
+        .await
+        .context("could not re-encrypt the local store")?;
+
+    // the store is only readable with the new key now, so if it can't be saved put the store
+    // back how it was
+    if let Err(e) = save_key(settings, encoded) {
+        store
+            .re_encrypt(new, old)
+            .await
+            .context("could not save the new key, or restore the local store")?;
+
+        return Err(e).context("could not save the new key. Nothing has been changed");
+    }
+
+    if let Some(client) = client {
+        status!("Replacing the records on the server");
+
+        upload(store, &client).await.context(
+            "the new key is saved, but the server still has the old records. Run `atuin sync`, then `atuin store push --force` to retry",
+        )?;
+    }
+
+    Ok(())
+}
+
+// The server's records are replaced with the local store's, so it needs every one of them first.
+// That includes the hosts and tags sync leaves out, which are only kept to be put back.
+async fn pull(settings: &Settings, store: &SqliteStore, client: &Client<'_>) -> Result<()> {
+    let (diff, _) = sync::diff(settings, store).await?;
+
+    if !diff.is_empty() {
+        bail!("the local store is out of sync with the server. Run `atuin sync` first");
+    }
+
+    status!("Downloading every record from the server");
+    sync::sync_with(store, client).await?;
+
+    ensure_synced(store, client).await
+}
+
+async fn ensure_synced(store: &SqliteStore, client: &Client<'_>) -> Result<()> {
+    let local = store.status().await?;
+    let remote = client.record_status().await?;
+
+    if !local.diff(&remote).is_empty() {
+        bail!("the server has records the local store doesn't. Run `atuin sync`, and try again");
+    }
+
+    Ok(())
+}
+
+// The server only ever adds records, so the old ones have to go before the re-encrypted ones can
+// take their place
+async fn upload(store: &SqliteStore, client: &Client<'_>) -> Result<()> {
+    // another host may have uploaded since we pulled, and its records would go with the old ones
+    ensure_synced(store, client).await?;
+
+    client.delete_store().await?;
+
+    let (uploaded, _) = sync::sync_with(store, client).await?;
+
+    status!("Uploaded {uploaded} records");
+
+    Ok(())
+}
@@ -1,19 +1,89 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 use crate::{SHA, VERSION};
-use atuin_client::{api_client, database::Database, settings::Settings};
+use atuin_client::{
+    api_client,
+    database::Database,
+    record::{sqlite_store::SqliteStore, store::Store, sync::SyncState},
+    settings::Settings,
+};
 use colored::Colorize;
 use eyre::Result;
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
 
-pub async fn run(settings: &Settings, db: &impl Database) -> Result<()> {
+use crate::command::client::format::Format;
+
+/// The sync status as `--format json` prints it. Times are RFC 3339.
+#[derive(Serialize)]
+struct Status {
+    version: &'static str,
+    logged_in: bool,
+    auto_sync: bool,
+    sync_frequency: String,
+    last_sync: Option<String>,
+
+    /// Records waiting to upload, by tag. Only with `sync.records`.
+    pending: BTreeMap<String, Pending>,
+    /// Hosts and tags the server disagreed with after the last sync, as host/tag
+    diverged: Vec<String>,
+    /// Syncs in a row that couldn't reach the server
+    failures: u32,
+    retry_after: Option<String>,
+
+    /// Only without `sync.records`
+    history_count: Option<i64>,
+    deleted_count: Option<i64>,
+
+    address: String,
+    username: Option<String>,
+    /// Why the server couldn't be reached
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Pending {
+    count: u64,
+    last_sync: Option<String>,
+}
+
+pub async fn run(
+    settings: &Settings,
+    db: &impl Database,
+    store: &SqliteStore,
+    format: Format,
+) -> Result<()> {
     let session_path = settings.session_path.as_str();
+    let logged_in = PathBuf::from(session_path).exists();
 
-    if !PathBuf::from(session_path).exists() {
+    if !logged_in && format.is_text() {
         println!("You are not logged in to a sync server - cannot show sync status");
 
         return Ok(());
     }
 
+    let tz = settings.timezone.0;
+    let mut status = Status {
+        version: VERSION,
+        logged_in,
+        auto_sync: settings.auto_sync,
+        sync_frequency: settings.sync_frequency.clone(),
+        last_sync: None,
+        pending: BTreeMap::new(),
+        diverged: Vec::new(),
+        failures: 0,
+        retry_after: None,
+        history_count: None,
+        deleted_count: None,
+        address: settings.sync_address.clone(),
+        username: None,
+        error: None,
+    };
+
+    if !logged_in {
+        return format.print(&status);
+    }
+
     let client = api_client::Client::new(
         &settings.sync_address,
         settings.session_token()?.as_str(),
@@ -21,31 +91,124 @@ pub async fn run(settings: &Settings, db: &impl Database) -> Result<()> {
         settings.network_timeout,
     )?;
 
-    let status = client.status().await?;
-    let last_sync = Settings::last_sync()?;
+    // still worth showing what's waiting to upload if the server can't be reached
+    if settings.offline {
+        status.error = Some("offline".to_string());
+    } else {
+        match client.status().await {
+            Ok(remote) => status.username = Some(remote.username),
+            Err(e) => status.error = Some(e.to_string()),
+        }
+    }
+
+    status.last_sync = Some(time(Settings::last_sync()?, tz, format));
+
+    if settings.sync.records {
+        let state = SyncState::load();
+        let host_id = Settings::host_id().expect("failed to get host_id");
+
+        for (tag, count) in state.pending(&store.status().await?, host_id) {
+            let last_sync = state.tags.get(&tag).map(|t| time(t.last_sync, tz, format));
+            status.pending.insert(tag, Pending { count, last_sync });
+        }
+
+        status.failures = state.failures;
+        status.retry_after = state
+            .retry_after
+            .filter(|_| state.failures > 0)
+            .map(|t| time(t, tz, format));
+        status.diverged = state.diverged;
+    } else {
+        let local_count = db.history_count(false).await?;
+
+        status.history_count = Some(local_count);
+        status.deleted_count = Some(db.history_count(true).await? - local_count);
+    }
 
+    if format.is_text() {
+        print(settings, &status);
+        Ok(())
+    } else {
+        format.print(&status)
+    }
+}
+
+// RFC 3339 for other tools, and as it's always been shown for people
+fn time(time: OffsetDateTime, tz: UtcOffset, format: Format) -> String {
+    let time = time.to_offset(tz);
+
+    if format.is_text() {
+        time.to_string()
+    } else {
+        time.format(&Rfc3339).unwrap_or_default()
+    }
+}
+
+fn print(settings: &Settings, status: &Status) {
     println!("Atuin v{VERSION} - Build rev {SHA}\n");
 
     println!("{}", "[Local]".green());
 
-    if settings.auto_sync {
-        println!("Sync frequency: {}", settings.sync_frequency);
-        println!("Last sync: {}", last_sync.to_offset(settings.timezone.0));
+    if status.auto_sync {
+        println!("Sync frequency: {}", status.sync_frequency);
+        println!(
+            "Last sync: {}",
+            status.last_sync.as_deref().unwrap_or("never")
+        );
     }
 
-    if !settings.sync.records {
-        let local_count = db.history_count(false).await?;
-        let deleted_count = db.history_count(true).await? - local_count;
-
-        println!("History count: {local_count}");
-        println!("Deleted history count: {deleted_count}\n");
+    if settings.sync.records {
+        print_pending(status);
+    } else {
+        println!(
+            "History count: {}",
+            status.history_count.unwrap_or_default()
+        );
+        println!(
+            "Deleted history count: {}\n",
+            status.deleted_count.unwrap_or_default()
+        );
     }
 
-    if settings.auto_sync {
+    if status.auto_sync {
         println!("{}", "[Remote]".green());
-        println!("Address: {}", settings.sync_address);
-        println!("Username: {}", status.username);
+        println!("Address: {}", status.address);
+
+        match (&status.username, &status.error) {
+            (Some(username), _) => println!("Username: {username}"),
+            (None, Some(e)) => println!("Unreachable: {e}"),
+            (None, None) => {}
+        }
+    }
+}
+
+fn print_pending(status: &Status) {
+    println!(
+        "Pending upload: {}",
+        status.pending.values().map(|p| p.count).sum::<u64>()
+    );
+
+    for (tag, pending) in &status.pending {
+        println!(
+            "  {tag}: {} pending, last synced {}",
+            pending.count,
+            pending.last_sync.as_deref().unwrap_or("never")
+        );
+    }
+
+    if !status.diverged.is_empty() {
+        println!(
+            "Out of step with the server, run `atuin sync verify`: {}",
+            status.diverged.join(", ")
+        );
+    }
+
+    if let Some(retry_after) = &status.retry_after {
+        println!(
+            "Failed syncs in a row: {}, next retry after {retry_after}",
+            status.failures
+        );
     }
 
-    Ok(())
+    println!();
 }
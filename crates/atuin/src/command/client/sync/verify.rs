@@ -0,0 +1,49 @@
+use eyre::{bail, Result};
+
+use atuin_client::{
+    database::Database,
+    record::{sqlite_store::SqliteStore, sync},
+    settings::Settings,
+};
+use atuin_common::status;
+
+// Compare digests with the server, and where they differ send every record both ways. Each side
+// ignores what it already has, so only the gaps are filled.
+pub async fn run(settings: &Settings, db: &impl Database, store: &SqliteStore) -> Result<()> {
+    let diverged = sync::diverged(settings, store).await?;
+
+    if diverged.is_empty() {
+        status!("This machine and the server agree");
+        sync::check_digest(settings, store).await?;
+
+        return Ok(());
+    }
+
+    for (local, remote) in &diverged {
+        status!(
+            "{}/{}: {} records here, {} on the server",
+            local.host.0.as_simple(),
+            local.tag,
+            local.count,
+            remote.count
+        );
+    }
+
+    let (uploaded, downloaded) = sync::reconcile(settings, store, &diverged).await?;
+    super::recover::build(settings, store, db, &downloaded).await?;
+
+    status!("{uploaded}/{} up/down to record store", downloaded.len());
+
+    let remaining = sync::check_digest(settings, store).await?;
+
+    if !remaining.is_empty() {
+        bail!(
+            "this machine and the server still disagree about {} stores. Records may be missing from both, or differ between them",
+            remaining.len()
+        );
+    }
+
+    status!("This machine and the server agree");
+
+    Ok(())
+}
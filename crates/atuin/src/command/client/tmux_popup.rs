@@ -0,0 +1,109 @@
+use std::{env, process::Command};
+
+use clap::Parser;
+use eyre::{bail, eyre, Result, WrapErr};
+use fs_err as fs;
+
+use atuin_common::utils::uuid_v7;
+use atuin_dotfiles::shell::posix_quote;
+
+/// Search in a tmux popup, and paste the command picked into the pane it was opened from
+///
+/// Bind it in tmux.conf with
+///
+///     bind-key C-r run-shell -b 'atuin tmux-popup --pane "#{pane_id}"'
+#[derive(Parser, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct Cmd {
+    /// The pane to paste into, and search the history of with the pane filter mode. Defaults to
+    /// `$TMUX_PANE`
+    #[arg(long)]
+    pane: Option<String>,
+
+    /// The popup's width, in cells or as a percentage of the window
+    #[arg(long, default_value = "80%")]
+    width: String,
+
+    /// The popup's height, in cells or as a percentage of the window
+    #[arg(long, default_value = "60%")]
+    height: String,
+
+    /// Options for `atuin search`, such as `--filter-mode pane`
+    #[arg(last = true)]
+    search_args: Vec<String>,
+}
+
+fn tmux(args: &[&str]) -> Result<String> {
+    let output = Command::new("tmux")
+        .args(args)
+        .output()
+        .wrap_err("could not run tmux. Is it installed?")?;
+
+    if !output.status.success() {
+        bail!(
+            "tmux {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        let pane = self
+            .pane
+            .or_else(|| env::var("TMUX_PANE").ok())
+            .ok_or_else(|| eyre!("not in tmux. Give the pane to paste into with --pane"))?;
+
+        let cwd = tmux(&["display-message", "-p", "-t", &pane, "#{pane_current_path}"])?;
+        let exe = env::current_exe()?;
+        let out = env::temp_dir().join(format!("atuin-tmux-popup-{}", uuid_v7().as_simple()));
+
+        let mut search = vec![
+            posix_quote(&exe.to_string_lossy()),
+            "search".to_string(),
+            "-i".to_string(),
+            "--print0".to_string(),
+        ];
+        search.extend(self.search_args.iter().map(|arg| posix_quote(arg)));
+        search.push(format!(">{}", posix_quote(&out.to_string_lossy())));
+
+        // the popup's shell isn't the pane's, so it gets a session of its own. The pane is passed
+        // on for the pane filter mode
+        let res = tmux(&[
+            "display-popup",
+            "-E",
+            "-t",
+            &pane,
+            "-d",
+            &cwd,
+            "-w",
+            &self.width,
+            "-h",
+            &self.height,
+            "-e",
+            &format!("TMUX_PANE={pane}"),
+            "-e",
+            &format!("ATUIN_SESSION={}", uuid_v7().as_simple()),
+            &search.join(" "),
+        ]);
+
+        // nothing is written if the search was cancelled
+        let selected = fs::read_to_string(&out).unwrap_or_default();
+        let _ = fs::remove_file(&out);
+        res?;
+
+        let selected = selected.trim_end_matches('\0');
+        if selected.is_empty() {
+            return Ok(());
+        }
+
+        // pasted rather than typed, so a command over several lines isn't run a line at a time
+        tmux(&["set-buffer", "-b", "atuin", "--", selected])?;
+        tmux(&["paste-buffer", "-p", "-d", "-b", "atuin", "-t", &pane])?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+use std::{fmt::Write as _, path::PathBuf};
+
+use clap::{Arg, ArgAction, Command, CommandFactory, Parser, ValueEnum};
+use eyre::Result;
+use fs_err as fs;
+
+use crate::VERSION;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DocFormat {
+    /// Roff, for man(1)
+    Man,
+    Markdown,
+}
+
+impl DocFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Man => "1",
+            Self::Markdown => "md",
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Cmd {
+    /// Generate man pages, or a markdown reference
+    format: DocFormat,
+
+    /// Write a page for each command into this directory, rather than all of them to stdout
+    #[arg(long, short)]
+    out_dir: Option<PathBuf>,
+}
+
+impl Cmd {
+    pub fn run(self) -> Result<()> {
+        let mut cli = crate::Atuin::command();
+        cli.build();
+
+        for page in pages(&cli) {
+            let doc = match self.format {
+                DocFormat::Man => man(&page),
+                DocFormat::Markdown => markdown(&page, self.out_dir.is_some()),
+            };
+
+            match &self.out_dir {
+                Some(dir) => {
+                    let name = format!("{}.{}", page.path.join("-"), self.format.extension());
+                    fs::write(dir.join(name), doc)?;
+                }
+                None => print!("{doc}"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A command to document, with the names of the commands it's under
+struct Page<'a> {
+    path: Vec<&'a str>,
+    command: &'a Command,
+}
+
+impl<'a> Page<'a> {
+    fn subcommands(&self) -> impl Iterator<Item = &'a Command> {
+        self.command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+    }
+
+    fn about(&self) -> String {
+        self.command
+            .get_about()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    fn description(&self) -> String {
+        self.command
+            .get_long_about()
+            .or_else(|| self.command.get_about())
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    fn usage(&self) -> String {
+        // built commands know the names of the commands they're under
+        let usage = self.command.clone().render_usage().to_string();
+        usage.strip_prefix("Usage: ").unwrap_or(&usage).to_string()
+    }
+
+    fn args(&self) -> impl Iterator<Item = &Arg> {
+        self.command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+    }
+}
+
+/// Every command, depth first, starting with atuin itself
+fn pages(cli: &Command) -> Vec<Page<'_>> {
+    fn walk<'a>(page: Page<'a>, pages: &mut Vec<Page<'a>>) {
+        let children: Vec<_> = page
+            .subcommands()
+            .map(|sub| {
+                let mut path = page.path.clone();
+                path.push(sub.get_name());
+                Page { path, command: sub }
+            })
+            .collect();
+
+        pages.push(page);
+
+        for child in children {
+            walk(child, pages);
+        }
+    }
+
+    let mut pages = Vec::new();
+    walk(
+        Page {
+            path: vec![cli.get_name()],
+            command: cli,
+        },
+        &mut pages,
+    );
+
+    pages
+}
+
+/// How an argument is written, like `-c, --count <COUNT>` or `<QUERY>...`
+fn arg_spec(arg: &Arg) -> String {
+    let value = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(|| arg.get_id().as_str().to_uppercase(), ToString::to_string);
+    let many = arg.get_num_args().is_some_and(|n| n.max_values() > 1);
+    let value = format!("<{value}>{}", if many { "..." } else { "" });
+
+    if arg.is_positional() {
+        return value;
+    }
+
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("-{short}"));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("--{long}"));
+    }
+
+    let mut spec = names.join(", ");
+    if arg.get_action().takes_values() {
+        spec.push(' ');
+        spec.push_str(&value);
+    }
+
+    spec
+}
+
+/// The help for an argument, with its default and the values it takes
+fn arg_help(arg: &Arg) -> String {
+    let mut help = arg
+        .get_long_help()
+        .or_else(|| arg.get_help())
+        .map(ToString::to_string)
+        .unwrap_or_default();
+
+    // flags default to false, which isn't worth saying
+    let takes_values = arg.get_action().takes_values()
+        && !matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse);
+
+    let possible: Vec<_> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if takes_values && !possible.is_empty() {
+        let _ = write!(help, " [possible values: {}]", possible.join(", "));
+    }
+
+    let default: Vec<_> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy())
+        .collect();
+    if takes_values && !default.is_empty() {
+        let _ = write!(help, " [default: {}]", default.join(","));
+    }
+
+    help.trim().to_string()
+}
+
+/// Escape text for roff, so it can't be read as a request or an escape
+fn roff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{line}")
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn man(page: &Page<'_>) -> String {
+    let name = page.path.join("-");
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        ".TH {} 1 \"\" \"atuin {VERSION}\" \"Atuin Manual\"",
+        roff(&name.to_uppercase())
+    );
+
+    out.push_str(".SH NAME\n");
+    let _ = writeln!(out, "{} \\- {}", roff(&name), roff(&page.about()));
+
+    out.push_str(".SH SYNOPSIS\n");
+    let _ = writeln!(out, "\\fB{}\\fR", roff(&page.usage()));
+
+    let description = page.description();
+    if !description.is_empty() {
+        out.push_str(".SH DESCRIPTION\n");
+        // blank lines would end the section
+        let _ = writeln!(out, "{}", roff(&description).replace("\n\n", "\n.PP\n"));
+    }
+
+    let mut args = page.args().peekable();
+    if args.peek().is_some() {
+        out.push_str(".SH OPTIONS\n");
+
+        for arg in args {
+            let _ = writeln!(
+                out,
+                ".TP\n\\fB{}\\fR\n{}",
+                roff(&arg_spec(arg)),
+                roff(&arg_help(arg)).replace("\n\n", "\n.IP\n")
+            );
+        }
+    }
+
+    let mut subcommands = page.subcommands().peekable();
+    if subcommands.peek().is_some() {
+        out.push_str(".SH COMMANDS\n");
+
+        for sub in subcommands {
+            let about = sub.get_about().map(ToString::to_string).unwrap_or_default();
+            let _ = writeln!(
+                out,
+                ".TP\n\\fB{}\\-{}\\fR(1)\n{}",
+                roff(&name),
+                roff(sub.get_name()),
+                roff(&about)
+            );
+        }
+    }
+
+    out.push_str(".SH VERSION\n");
+    let _ = writeln!(out, "v{}", roff(VERSION));
+
+    out
+}
+
+/// Links to subcommands go to their own files if each command has one, or else to their
+/// headings further down
+fn markdown(page: &Page<'_>, separate: bool) -> String {
+    let title = page.path.join(" ");
+    let mut out = String::new();
+
+    let _ = writeln!(out, "{} {title}\n", "#".repeat(page.path.len().min(6)));
+
+    let description = page.description();
+    if !description.is_empty() {
+        let _ = writeln!(out, "{description}\n");
+    }
+
+    let _ = writeln!(out, "```\n{}\n```\n", page.usage());
+
+    let mut args = page.args().peekable();
+    if args.peek().is_some() {
+        out.push_str("**Options**\n\n");
+
+        for arg in args {
+            let help = arg_help(arg).replace('\n', " ");
+            let _ = writeln!(out, "- `{}`: {help}", arg_spec(arg));
+        }
+
+        out.push('\n');
+    }
+
+    let mut subcommands = page.subcommands().peekable();
+    if subcommands.peek().is_some() {
+        out.push_str("**Commands**\n\n");
+
+        for sub in subcommands {
+            let about = sub.get_about().map(ToString::to_string).unwrap_or_default();
+            let name = format!("{}-{}", page.path.join("-"), sub.get_name());
+            let link = if separate {
+                format!("{name}.md")
+            } else {
+                format!("#{name}")
+            };
+            let _ = writeln!(out, "- [`{title} {}`]({link}): {about}", sub.get_name());
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction, Command};
+
+    use super::{arg_help, arg_spec, man, pages, roff, Page};
+
+    fn cli() -> Command {
+        let mut cli = Command::new("atuin").subcommand(
+            Command::new("history")
+                .about("Manipulate shell history")
+                .subcommand(
+                    Command::new("list")
+                        .about("List all items in history")
+                        .arg(
+                            Arg::new("format")
+                                .short('f')
+                                .long("format")
+                                .help("How to print each entry")
+                                .default_value("{time}"),
+                        )
+                        .arg(
+                            Arg::new("cwd")
+                                .long("cwd")
+                                .help("Only in this directory")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        );
+        cli.build();
+        cli
+    }
+
+    #[test]
+    fn walks_every_command() {
+        let cli = cli();
+        let paths: Vec<_> = pages(&cli).iter().map(|page| page.path.join("-")).collect();
+
+        assert_eq!(paths, ["atuin", "atuin-history", "atuin-history-list"]);
+    }
+
+    #[test]
+    fn args() {
+        let cli = cli();
+        let list = cli
+            .find_subcommand("history")
+            .and_then(|history| history.find_subcommand("list"))
+            .unwrap();
+
+        let format = list.get_arguments().find(|a| a.get_id() == "format");
+        let cwd = list.get_arguments().find(|a| a.get_id() == "cwd");
+
+        assert_eq!(arg_spec(format.unwrap()), "-f, --format <FORMAT>");
+        assert_eq!(
+            arg_help(format.unwrap()),
+            "How to print each entry [default: {time}]"
+        );
+        assert_eq!(arg_spec(cwd.unwrap()), "--cwd");
+        assert_eq!(arg_help(cwd.unwrap()), "Only in this directory");
+    }
+
+    #[test]
+    fn man_page() {
+        let cli = cli();
+        let history = cli.find_subcommand("history").unwrap();
+        let page = Page {
+            path: vec!["atuin", "history"],
+            command: history,
+        };
+
+        let man = man(&page);
+        assert!(man.starts_with(".TH ATUIN\\-HISTORY 1"));
+        assert!(man.contains("atuin\\-history \\- Manipulate shell history\n"));
+        assert!(man.contains("\\fBatuin history [COMMAND]\\fR"));
+        assert!(man.contains(".TP\n\\fBatuin\\-history\\-list\\fR(1)\nList all items in history"));
+    }
+
+    #[test]
+    fn escapes_roff() {
+        assert_eq!(roff(".hidden\n'quoted"), "\\&.hidden\n\\&'quoted");
+        assert_eq!(roff("a\\b --c"), "a\\eb \\-\\-c");
+    }
+}
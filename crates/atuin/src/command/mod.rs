@@ -13,7 +13,10 @@ mod server;
 mod contributors;
 
 mod gen_completions;
+mod gen_docs;
 
+// only ever built once, from the command line
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 #[command(infer_subcommands = true)]
 pub enum AtuinCmd {
@@ -33,6 +36,9 @@ pub enum AtuinCmd {
 
     /// Generate shell completions
     GenCompletions(gen_completions::Cmd),
+
+    /// Generate man pages or a markdown reference for every command
+    GenDocs(gen_docs::Cmd),
 }
 
 impl AtuinCmd {
@@ -60,6 +66,7 @@ impl AtuinCmd {
                 Ok(())
             }
             Self::GenCompletions(gen_completions) => gen_completions.run(),
+            Self::GenDocs(gen_docs) => gen_docs.run(),
         }
     }
 }
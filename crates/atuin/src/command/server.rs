@@ -1,12 +1,17 @@
 use std::net::SocketAddr;
 
 use atuin_server_postgres::Postgres;
+use atuin_server_sqlite::{is_sqlite_uri, Sqlite};
+use serde::Deserialize;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 use clap::Parser;
 use eyre::{Context, Result};
 
 use atuin_server::{example_config, launch, launch_metrics_server, Settings};
+use atuin_server_database::Database;
+
+mod users;
 
 #[derive(Parser, Debug)]
 #[clap(infer_subcommands = true)]
@@ -24,6 +29,10 @@ pub enum Cmd {
 
     /// Print server example configuration
     DefaultConfig,
+
+    /// List, inspect and delete the server's users
+    #[command(subcommand)]
+    Users(users::Cmd),
 }
 
 impl Cmd {
@@ -36,6 +45,20 @@ impl Cmd {
 
         tracing::trace!(command = ?self, "server command");
 
+        // a sqlite: db_uri picks sqlite, and anything else postgres
+        let uri = Settings::<DbUri>::new()
+            .wrap_err("could not load server settings")?
+            .db_settings
+            .db_uri;
+
+        if is_sqlite_uri(&uri) {
+            self.run_with::<Sqlite>().await
+        } else {
+            self.run_with::<Postgres>().await
+        }
+    }
+
+    async fn run_with<Db: Database>(self) -> Result<()> {
         match self {
             Self::Start { host, port } => {
                 let settings = Settings::new().wrap_err("could not load server settings")?;
@@ -50,12 +73,27 @@ impl Cmd {
                     ));
                 }
 
-                launch::<Postgres>(settings, addr).await
+                launch::<Db>(settings, addr).await
             }
             Self::DefaultConfig => {
                 println!("{}", example_config());
                 Ok(())
             }
+            Self::Users(users) => {
+                let settings: Settings<Db::Settings> =
+                    Settings::new().wrap_err("could not load server settings")?;
+                let db = Db::new(&settings.db_settings)
+                    .await
+                    .wrap_err("could not connect to the database")?;
+
+                users.run(&db).await
+            }
         }
     }
 }
+
+/// Just enough of the settings to pick a database
+#[derive(Deserialize)]
+struct DbUri {
+    db_uri: String,
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+
+use clap::Subcommand;
+use eyre::{bail, Result};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use atuin_server_database::{models::UserStats, Database};
+
+#[derive(Subcommand, Debug)]
+#[command(infer_subcommands = true)]
+pub enum Cmd {
+    /// List every user, with how much they store and when they last synced
+    #[command(alias = "ls")]
+    List,
+
+    /// Show how much a user stores, or the whole server without a username
+    Stats { username: Option<String> },
+
+    /// Delete a user, and everything they have synced
+    #[command(alias = "rm")]
+    Delete {
+        username: String,
+
+        /// Don't ask first
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+impl Cmd {
+    pub async fn run(self, db: &impl Database) -> Result<()> {
+        match self {
+            Self::List => {
+                let users = db.list_users().await?;
+
+                println!(
+                    "{:<32} {:>10} {:>10} {:>10}  LAST SYNC",
+                    "USERNAME", "HISTORY", "RECORDS", "STORAGE"
+                );
+                for user in users {
+                    println!(
+                        "{:<32} {:>10} {:>10} {:>10}  {}",
+                        user.username,
+                        user.history,
+                        user.records,
+                        format_bytes(user.storage),
+                        format_time(user.last_sync)
+                    );
+                }
+            }
+
+            Self::Stats {
+                username: Some(username),
+            } => {
+                let user = db.get_user(&username).await?;
+                let stats = db.user_stats(&user).await?;
+
+                print_stats(&stats);
+            }
+
+            Self::Stats { username: None } => {
+                let users = db.list_users().await?;
+
+                let total = |f: fn(&UserStats) -> i64| users.iter().map(f).sum::<i64>();
+                let active = users
+                    .iter()
+                    .filter(|u| u.last_sync.is_some_and(|t| is_recent(t, 30)))
+                    .count();
+
+                println!("Users: {}", users.len());
+                println!("Synced in the last 30 days: {active}");
+                println!("History: {}", total(|u| u.history));
+                println!("Records: {}", total(|u| u.records));
+                println!("Storage: {}", format_bytes(total(|u| u.storage)));
+            }
+
+            Self::Delete { username, yes } => {
+                let user = db.get_user(&username).await?;
+                let stats = db.user_stats(&user).await?;
+
+                if !yes {
+                    print_stats(&stats);
+
+                    print!("\nDelete {username} and everything they have synced? [y/N]: ");
+                    io::stdout().flush()?;
+
+                    let mut input = String::new();
+                    io::stdin().read_line(&mut input)?;
+
+                    if !input.trim().eq_ignore_ascii_case("y") {
+                        bail!("not deleting {username}");
+                    }
+                }
+
+                db.delete_user(&user).await?;
+                println!("Deleted {username}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn print_stats(stats: &UserStats) {
+    println!("Username: {}", stats.username);
+    println!("Email: {}", stats.email);
+    println!("Created: {}", format_time(Some(stats.created_at)));
+    println!("Verified: {}", stats.verified.is_some());
+    println!("History: {}", stats.history);
+    println!("Records: {}", stats.records);
+    println!("Storage: {}", format_bytes(stats.storage));
+    println!("Last sync: {}", format_time(stats.last_sync));
+}
+
+fn is_recent(time: OffsetDateTime, days: i64) -> bool {
+    OffsetDateTime::now_utc() - time < time::Duration::days(days)
+}
+
+fn format_time(time: Option<OffsetDateTime>) -> String {
+    time.and_then(|t| t.format(&Rfc3339).ok())
+        .unwrap_or_else(|| "never".to_string())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: i64) -> String {
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in ["KiB", "MiB", "GiB", "TiB"] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    format!("{size:.1} {unit}")
+}
@@ -1,14 +1,14 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 #![allow(clippy::use_self, clippy::missing_const_for_fn)] // not 100% reliable
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use eyre::Result;
 
 use command::AtuinCmd;
 
 mod command;
 
-#[cfg(feature = "sync")]
+#[cfg(feature = "client")]
 mod sync;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -32,13 +32,54 @@ static HELP_TEMPLATE: &str = "\
     help_template(HELP_TEMPLATE),
 )]
 struct Atuin {
+    /// Print more detail about what's happening. Repeat for debug logs
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only print errors, and the output that was asked for
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Don't touch the network: no sync, and no update checks
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Use a profile, with its own config, key, sync account and history. The same as setting
+    /// `$ATUIN_PROFILE`
+    #[arg(long, global = true, value_parser = parse_profile)]
+    profile: Option<String>,
+
     #[command(subcommand)]
-    atuin: AtuinCmd,
+    command: AtuinCmd,
 }
 
 impl Atuin {
     fn run(self) -> Result<()> {
-        self.atuin.run()
+        let level = if self.quiet {
+            -1
+        } else {
+            i8::try_from(self.verbose).unwrap_or(i8::MAX)
+        };
+        atuin_common::output::set_verbosity(level);
+
+        // through the environment, so settings and anything atuin starts see it too
+        if self.offline {
+            std::env::set_var("ATUIN_OFFLINE", "true");
+        }
+
+        if let Some(profile) = &self.profile {
+            std::env::set_var("ATUIN_PROFILE", profile);
+        }
+
+        self.command.run()
+    }
+}
+
+fn parse_profile(name: &str) -> Result<String, &'static str> {
+    if atuin_common::utils::is_valid_profile(name) {
+        Ok(name.to_string())
+    } else {
+        Err("profile names may only have letters, numbers, - and _")
     }
 }
 
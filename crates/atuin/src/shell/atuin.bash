@@ -30,6 +30,17 @@ __atuin_update_preexec_backend() {
     fi
 }
 
+# OSC 133 marks the prompt, command and output for terminals with semantic zones, when
+# prompt_marks is on in config.toml. They're left to the terminal's own shell integration when
+# it's loaded, so none are written twice. Fails when nothing was written.
+__atuin_mark() {
+    [[ ${__atuin_prompt_marks-} ]] || return 1
+    [[ ! ${KITTY_SHELL_INTEGRATION-}${ITERM_SHELL_INTEGRATION_INSTALLED-}${VSCODE_SHELL_INTEGRATION-} ]] || return 1
+    ! declare -F __wezterm_semantic_precmd >/dev/null || return 1
+
+    printf '\e]133;%s\a' "$1"
+}
+
 __atuin_preexec() {
     # Workaround for old versions of bash-preexec
     if [[ ! ${BLE_ATTACHED-} ]]; then
@@ -50,6 +61,11 @@ __atuin_preexec() {
     # attaching state can dynamically change.
     __atuin_update_preexec_backend
 
+    # not when __atuin_precmd catches up on a command, as its output has already been written
+    if [[ ! ${__atuin_mark_skip-} ]] && __atuin_mark C; then
+        __atuin_marked_command=1
+    fi
+
     local id
     id=$(atuin history start -- "$1")
     export ATUIN_HISTORY_ID=$id
@@ -59,6 +75,15 @@ __atuin_preexec() {
 __atuin_precmd() {
     local EXIT=$? __atuin_precmd_time=${EPOCHREALTIME-}
 
+    # a command's output only ends if it started, and not at the first prompt
+    if [[ ${__atuin_marked_command-} ]]; then
+        __atuin_mark "D;$EXIT"
+        unset -v __atuin_marked_command
+    fi
+    if __atuin_mark A && [[ $PS1 != *'\e]133;B'* ]]; then
+        PS1+='\[\e]133;B\a\]'
+    fi
+
     [[ ! $ATUIN_HISTORY_ID ]] && return
 
     # If the previous preexec hook failed, we manually call __atuin_preexec
@@ -69,6 +94,7 @@ __atuin_precmd() {
             export LC_ALL=C HISTTIMEFORMAT=''
             builtin history 1 | sed '1 s/^ *[0-9][0-9]*[* ] //'
         )
+        local __atuin_mark_skip=1
         __atuin_preexec "$previous_command"
     fi
 
@@ -300,8 +326,57 @@ __atuin_initialize_blesh() {
 }
 __atuin_initialize_blesh
 BLE_ONLOAD+=(__atuin_initialize_blesh)
-precmd_functions+=(__atuin_precmd)
-preexec_functions+=(__atuin_preexec)
+
+# The fallback for bash < 4 without bash-preexec or ble.sh, such as the bash
+# 3.2 that macOS ships.  Without a preexec hook, we can only see a command
+# after it has finished, so we read it back from the history list from
+# PROMPT_COMMAND and record its start and end at once.  The duration is
+# measured from the history timestamp at a resolution of one second, the
+# directory is the one the command finished in, and commands that are not
+# added to the history (see HISTCONTROL and HISTIGNORE) are not recorded.
+__atuin_prompt_command() {
+    local EXIT=$? entry histnum timestamp now
+
+    # The entry is printed as "<number>[* ] <seconds> <command>"
+    entry=$(
+        export LC_ALL=C HISTTIMEFORMAT='%s '
+        builtin history 1
+    )
+    entry=${entry#"${entry%%[0-9]*}"}
+    histnum=${entry%%[!0-9]*}
+    entry=${entry#"$histnum"[* ] }
+    timestamp=${entry%% *}
+    entry=${entry#"$timestamp" }
+
+    # Nothing new was added to the history since the last prompt.  At the
+    # first prompt, the last entry comes from the history file.
+    if [[ ${__atuin_prompt_histnum-} && $histnum && $histnum != "$__atuin_prompt_histnum" ]]; then
+        local id duration=""
+        id=$(atuin history start -- "$entry")
+        now=$(date +%s)
+        if [[ $timestamp && $timestamp != *[!0-9]* && $now && $now != *[!0-9]* ]] && ((now >= timestamp)); then
+            duration=$(((now - timestamp) * 1000000000))
+        fi
+        (ATUIN_LOG=error atuin history end --exit "$EXIT" ${duration:+"--duration=$duration"} -- "$id" &) >/dev/null 2>&1
+    fi
+    __atuin_prompt_histnum=$histnum
+
+    # Nothing else runs precmd_functions without bash-preexec
+    local __atuin_hook
+    for __atuin_hook in "${precmd_functions[@]}"; do
+        __atuin_set_ret_value "$EXIT"
+        "$__atuin_hook"
+    done
+    __atuin_set_ret_value "$EXIT"
+}
+
+if ((BASH_VERSINFO[0] < 4)) && [[ ! ${BLE_VERSION-} && ! ${bash_preexec_imported-} && ! ${__bp_imported-} ]]; then
+    ATUIN_PREEXEC_BACKEND=$SHLVL:prompt-command
+    PROMPT_COMMAND="__atuin_prompt_command${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+else
+    precmd_functions+=(__atuin_precmd)
+    preexec_functions+=(__atuin_preexec)
+fi
 
 # shellcheck disable=SC2154
 if [[ $__atuin_bind_ctrl_r == true ]]; then
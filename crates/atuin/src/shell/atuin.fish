@@ -1,7 +1,34 @@
 set -gx ATUIN_SESSION (atuin uuid)
 set --erase ATUIN_HISTORY_ID
 
+# OSC 133 marks the prompt, command and output for terminals with semantic zones, when
+# prompt_marks is on in config.toml. fish 4 writes them itself, and they're left to the
+# terminal's own shell integration when it's loaded, so none are written twice. Fails when
+# nothing was written.
+function _atuin_mark
+    test -n "$__atuin_prompt_marks"; or return 1
+    string match -qr '^[0-3]\.' -- $version; or return 1
+    test -z "$KITTY_SHELL_INTEGRATION$ITERM_SHELL_INTEGRATION_INSTALLED$VSCODE_SHELL_INTEGRATION"; or return 1
+
+    printf '\e]133;%s\a' $argv[1]
+end
+
+function _atuin_mark_prompt --on-event fish_prompt
+    _atuin_mark A; or return
+
+    # the end of the prompt is marked by wrapping it, once there is one
+    if functions -q fish_prompt; and not functions -q _atuin_fish_prompt
+        functions -c fish_prompt _atuin_fish_prompt
+        function fish_prompt
+            _atuin_fish_prompt
+            _atuin_mark B
+        end
+    end
+end
+
 function _atuin_preexec --on-event fish_preexec
+    _atuin_mark C; and set -g __atuin_marked_command 1
+
     if not test -n "$fish_private_mode"
         set -g ATUIN_HISTORY_ID (atuin history start -- "$argv[1]")
     end
@@ -10,6 +37,11 @@ end
 function _atuin_postexec --on-event fish_postexec
     set -l s $status
 
+    if set -q __atuin_marked_command
+        _atuin_mark "D;$s"
+        set --erase __atuin_marked_command
+    end
+
     if test -n "$ATUIN_HISTORY_ID"
         ATUIN_LOG=error atuin history end --exit $s -- $ATUIN_HISTORY_ID &>/dev/null &
         disown
@@ -0,0 +1,131 @@
+# Source this in your PowerShell profile ($PROFILE):
+#
+#     atuin init powershell | Out-String | Invoke-Expression
+#
+# Requires PSReadLine, which PowerShell loads by default in interactive sessions.
+
+# Include guard
+if (-not $global:__atuin_initialized -and (Get-Module PSReadLine)) {
+$global:__atuin_initialized = $true
+
+$env:ATUIN_SESSION = (atuin uuid | Out-String).Trim()
+$env:ATUIN_HISTORY_ID = $null
+
+$global:__atuin_previous_prompt = $function:prompt
+$global:__atuin_previous_add_to_history = (Get-PSReadLineOption).AddToHistoryHandler
+
+# PSReadLine asks this whether to keep each line as the user accepts it, just before it runs,
+# which makes it the one place to start recording a command
+Set-PSReadLineOption -AddToHistoryHandler {
+    param([string]$line)
+
+    $keep = if ($global:__atuin_previous_add_to_history) {
+        & $global:__atuin_previous_add_to_history $line
+    } else {
+        $true
+    }
+
+    $skip = ($keep -is [bool] -and -not $keep) -or ("$keep" -eq 'SkipAdding')
+
+    if (-not $skip -and $line.Trim()) {
+        # the command about to run shouldn't see our exit code
+        $native_exit = $global:LASTEXITCODE
+        $env:ATUIN_HISTORY_ID = (atuin history start -- $line | Out-String).Trim()
+        $global:LASTEXITCODE = $native_exit
+    }
+
+    $keep
+}
+
+function global:prompt {
+    # save these first, as anything run here replaces them
+    $succeeded = $global:?
+    $native_exit = $global:LASTEXITCODE
+
+    if ($env:ATUIN_HISTORY_ID) {
+        $exit = if ($succeeded) { 0 } elseif ($native_exit) { $native_exit } else { 1 }
+
+        $log = $env:ATUIN_LOG
+        $env:ATUIN_LOG = 'error'
+        atuin history end --exit $exit -- $env:ATUIN_HISTORY_ID | Out-Null
+        $env:ATUIN_LOG = $log
+
+        $env:ATUIN_HISTORY_ID = $null
+        $global:LASTEXITCODE = $native_exit
+    }
+
+    & $global:__atuin_previous_prompt
+}
+
+function global:__atuin_search {
+    param([string[]]$ExtraArgs = @())
+
+    $line = $null
+    $cursor = $null
+    [Microsoft.PowerShell.PSConsoleReadLine]::GetBufferState([ref]$line, [ref]$cursor)
+
+    $saved = @{
+        ATUIN_LOG = $env:ATUIN_LOG
+        ATUIN_QUERY = $env:ATUIN_QUERY
+        ATUIN_SHELL_POWERSHELL = $env:ATUIN_SHELL_POWERSHELL
+    }
+    $env:ATUIN_LOG = 'error'
+    $env:ATUIN_QUERY = $line
+    $env:ATUIN_SHELL_POWERSHELL = 't'
+
+    # atuin draws on stdout and prints what was picked on stderr, so only stderr is redirected
+    $result_file = New-TemporaryFile
+
+    try {
+        Start-Process -Wait -NoNewWindow -FilePath atuin `
+            -ArgumentList (@('search', '--interactive') + $ExtraArgs) `
+            -RedirectStandardError $result_file
+        $result = (Get-Content -Raw -Encoding UTF8 $result_file | Out-String).Trim()
+    } finally {
+        Remove-Item $result_file -ErrorAction Ignore
+        foreach ($name in $saved.Keys) {
+            Set-Item "env:$name" $saved[$name]
+        }
+    }
+
+    [Microsoft.PowerShell.PSConsoleReadLine]::InvokePrompt()
+
+    # nothing was picked
+    if (-not $result) {
+        return
+    }
+
+    $accept = $result.StartsWith('__atuin_accept__:')
+    if ($accept) {
+        $result = $result.Substring('__atuin_accept__:'.Length)
+    }
+
+    [Microsoft.PowerShell.PSConsoleReadLine]::Replace(0, $line.Length, $result)
+
+    if ($accept) {
+        [Microsoft.PowerShell.PSConsoleReadLine]::AcceptLine()
+    }
+}
+
+if ($__atuin_bind_ctrl_r) {
+    Set-PSReadLineKeyHandler -Chord 'Ctrl+r' -BriefDescription 'atuin search' -ScriptBlock {
+        __atuin_search
+    }
+}
+
+if ($__atuin_bind_up_arrow) {
+    Set-PSReadLineKeyHandler -Chord 'UpArrow' -BriefDescription 'atuin search' -ScriptBlock {
+        $line = $null
+        $cursor = $null
+        [Microsoft.PowerShell.PSConsoleReadLine]::GetBufferState([ref]$line, [ref]$cursor)
+
+        # in a multi-line command, up still moves between its lines
+        if ($line.Contains("`n")) {
+            [Microsoft.PowerShell.PSConsoleReadLine]::PreviousLine()
+        } else {
+            __atuin_search '--shell-up-key-binding'
+        }
+    }
+}
+
+} # (include guard) end of main content
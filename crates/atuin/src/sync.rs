@@ -1,8 +1,10 @@
-use atuin_dotfiles::store::{var::VarStore, AliasStore};
+use atuin_dotfiles::store::{snippet::SnippetStore, var::VarStore, AliasStore};
 use eyre::{Context, Result};
 
 use atuin_client::{
-    database::Database, history::store::HistoryStore, record::sqlite_store::SqliteStore,
+    database::Database,
+    history::{annotation::AnnotationStore, store::HistoryStore},
+    record::sqlite_store::SqliteStore,
     settings::Settings,
 };
 use atuin_common::record::RecordId;
@@ -19,6 +21,11 @@ pub async fn build(
     db: &dyn Database,
     downloaded: Option<&[RecordId]>,
 ) -> Result<()> {
+    // there's nothing a host with only the public key can read to build from
+    if atuin_client::encryption::is_write_only(settings)? {
+        return Ok(());
+    }
+
     let encryption_key: [u8; 32] = atuin_client::encryption::load_key(settings)
         .context("could not load encryption key")?
         .into();
@@ -27,14 +34,20 @@ pub async fn build(
 
     let downloaded = downloaded.unwrap_or(&[]);
 
-    let history_store = HistoryStore::new(store.clone(), host_id, encryption_key);
+    let history_store =
+        HistoryStore::from_settings(store.clone(), host_id, encryption_key, settings);
     let alias_store = AliasStore::new(store.clone(), host_id, encryption_key);
     let var_store = VarStore::new(store.clone(), host_id, encryption_key);
+    let snippet_store = SnippetStore::new(store.clone(), host_id, encryption_key);
+    let annotation_store = AnnotationStore::new(store.clone(), host_id, encryption_key);
 
     history_store.incremental_build(db, downloaded).await?;
+    history_store.build_starred(db).await?;
+    annotation_store.build(db).await?;
 
     alias_store.build().await?;
     var_store.build().await?;
+    snippet_store.build().await?;
 
     Ok(())
 }
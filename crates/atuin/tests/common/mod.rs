@@ -32,6 +32,8 @@ pub async fn start_server(path: &str) -> (String, oneshot::Sender<()>, JoinHandl
         open_registration: true,
         max_history_length: 8192,
         max_record_size: 1024 * 1024 * 1024,
+        max_records: 0,
+        max_storage: 0,
         page_size: 1100,
         register_webhook_url: None,
         register_webhook_username: String::new(),
@@ -39,7 +41,9 @@ pub async fn start_server(path: &str) -> (String, oneshot::Sender<()>, JoinHandl
         metrics: atuin_server::settings::Metrics::default(),
         tls: atuin_server::settings::Tls::default(),
         mail: atuin_server::settings::Mail::default(),
+        rate_limit: atuin_server::settings::RateLimit::default(),
         fake_version: None,
+        admin_token: None,
     };
 
     let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
@@ -86,7 +90,11 @@ pub async fn login(address: &str, username: String, password: String) -> api_cli
     // registration works
     let login_respose = api_client::login(
         address,
-        atuin_common::api::LoginRequest { username, password },
+        atuin_common::api::LoginRequest {
+            username,
+            password,
+            totp: None,
+        },
     )
     .await
     .unwrap();
@@ -1,4 +1,9 @@
-use atuin_common::{api::AddHistoryRequest, utils::uuid_v7};
+use atuin_client::api_client::{self, HostRevoked};
+use atuin_common::{
+    api::AddHistoryRequest,
+    record::{EncryptedData, Host, HostId, Record},
+    utils::uuid_v7,
+};
 use time::OffsetDateTime;
 
 mod common;
@@ -43,3 +48,120 @@ async fn sync() {
     shutdown.send(()).unwrap();
     server.await.unwrap();
 }
+
+#[tokio::test]
+async fn revoked_host() {
+    let path = format!("/{}", uuid_v7().as_simple());
+    let (address, shutdown, server) = common::start_server(&path).await;
+
+    let client = common::register(&address).await;
+    let host = HostId(uuid_v7());
+
+    let record = Record::builder()
+        .host(Host::new(host))
+        .version("v0".into())
+        .tag("history".into())
+        .data(EncryptedData {
+            data: "data".into(),
+            content_encryption_key: "cek".into(),
+        })
+        .idx(0)
+        .build();
+
+    client.post_records(&[record.clone()]).await.unwrap();
+    let revoked = client.revoke_host(host, true).await.unwrap();
+
+    // records written by a revoked host are refused, whoever uploads them
+    let renewed = api_client::Client::new(&address, &revoked.session, 5, 30).unwrap();
+    assert!(renewed.post_records(&[record]).await.is_err());
+
+    // the revoked host's session is gone, and it's turned away like any other bad session
+    let client = client.with_host_id(host);
+    let err = client.record_status().await.unwrap_err();
+    assert!(err.downcast_ref::<HostRevoked>().is_none());
+
+    // logged in again, it's told to stop, and to delete its key
+    let renewed = renewed.with_host_id(host);
+    let err = renewed.record_status().await.unwrap_err();
+    let revoked = err.downcast_ref::<HostRevoked>().unwrap();
+    assert!(revoked.wipe_key);
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn compressed_records() {
+    let path = format!("/{}", uuid_v7().as_simple());
+    let (address, shutdown, server) = common::start_server(&path).await;
+
+    let client = common::register(&address).await;
+    let host = HostId(uuid_v7());
+
+    // the server says it takes compressed requests, so uploads after this are compressed
+    client.record_status().await.unwrap();
+
+    let records: Vec<_> = (0..50)
+        .map(|idx| {
+            Record::builder()
+                .host(Host::new(host))
+                .version("v0".into())
+                .tag("history".into())
+                .data(EncryptedData {
+                    data: uuid_v7().as_simple().to_string().repeat(100),
+                    content_encryption_key: "cek".into(),
+                })
+                .idx(idx)
+                .build()
+        })
+        .collect();
+
+    client.post_records(&records).await.unwrap();
+
+    let downloaded = client
+        .next_records(host, "history".into(), 0, 100)
+        .await
+        .unwrap();
+
+    assert_eq!(downloaded, records);
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn record_digest() {
+    let path = format!("/{}", uuid_v7().as_simple());
+    let (address, shutdown, server) = common::start_server(&path).await;
+
+    let client = common::register(&address).await;
+    let host = HostId(uuid_v7());
+
+    let records: Vec<_> = (0..5)
+        .map(|idx| {
+            Record::builder()
+                .host(Host::new(host))
+                .version("v0".into())
+                .tag("history".into())
+                .data(EncryptedData {
+                    data: "data".into(),
+                    content_encryption_key: "cek".into(),
+                })
+                .idx(idx)
+                .build()
+        })
+        .collect();
+
+    client.post_records(&records).await.unwrap();
+
+    let digest = client.record_digest().await.unwrap();
+    let entry = digest.get(host, "history").unwrap();
+
+    assert_eq!(digest.entries.len(), 1);
+    assert_eq!(entry.count, 5);
+    assert_eq!(entry.idx, 4);
+    assert_eq!(entry.last, records[4].id);
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap();
+}